@@ -41,6 +41,11 @@ pub fn initialize() -> anyhow::Result<()> {
         );
     }))
     .buck_error_context_anyhow("Error initializing soft errors")?;
+    buck2_core::error::initialize_escalation_handler(Box::new(imp::write_soft_error_escalation));
+    #[cfg(not(client_only))]
+    buck2_core::error::initialize_soft_error_observer(Box::new(
+        buck2_server::soft_error_tally::record_soft_error,
+    ));
     Ok(())
 }
 
@@ -176,28 +181,48 @@ mod imp {
             }),
         );
 
-        // If the soft error was fired in a context with an ambient dispatcher, then we only send
-        // it there, but some contexts don't have one, and in that case, we notify all running
-        // commands.
-        match buck2_events::dispatch::get_dispatcher_opt() {
-            Some(dispatcher) => {
-                dispatcher.instant_event(event.clone());
-            }
-            None => {
-                #[cfg(client_only)]
-                let warn = true;
-                #[cfg(not(client_only))]
-                let warn = !buck2_server::active_commands::broadcast_instant_event(&event)
-                    && !options.quiet;
-                if warn {
-                    tracing::warn!("Warning \"{}\": {:#}", category, err);
-                }
-            }
+        let dispatched = dispatch_instant_event(&event);
+        #[cfg(client_only)]
+        let warn = !dispatched;
+        #[cfg(not(client_only))]
+        let warn = !dispatched && !options.quiet;
+        if warn {
+            tracing::warn!("Warning \"{}\": {:#}", category, err);
         }
 
         write_to_scribe(fb, event);
     }
 
+    pub(crate) fn write_soft_error_escalation(
+        category: &str,
+        policy: &str,
+        err: &buck2_error::Error,
+    ) {
+        dispatch_instant_event(&buck2_data::SoftErrorEscalated {
+            category: category.to_owned(),
+            policy: policy.to_owned(),
+            message: format!("{:#}", err),
+        });
+    }
+
+    /// If the soft error was fired in a context with an ambient dispatcher, then we only send
+    /// it there, but some contexts don't have one, and in that case, we notify all running
+    /// commands. Returns whether any command received the event.
+    fn dispatch_instant_event<E: Into<buck2_data::instant_event::Data> + Clone>(event: &E) -> bool {
+        if let Some(dispatcher) = buck2_events::dispatch::get_dispatcher_opt() {
+            dispatcher.instant_event(event.clone());
+            return true;
+        }
+        #[cfg(not(client_only))]
+        {
+            buck2_server::active_commands::broadcast_instant_event(event)
+        }
+        #[cfg(client_only)]
+        {
+            false
+        }
+    }
+
     fn panic_payload(
         location: Option<Location>,
         message: String,
@@ -297,6 +297,7 @@ impl Action for DownloadFileAction {
                                 checksum: self.inner.checksum.dupe(),
                                 metadata,
                                 owner: ctx.target().owner().dupe(),
+                                re_use_case: ctx.re_use_case(),
                             },
                             ctx.cancellation_context(),
                         )
@@ -329,6 +330,7 @@ impl Action for DownloadFileAction {
                         &rel_path,
                         url,
                         &self.inner.checksum,
+                        self.inner.size_bytes,
                         self.inner.is_executable,
                     )
                     .await?;
@@ -11,6 +11,7 @@ use std::borrow::Cow;
 use std::io::Write;
 
 use buck2_build_api::actions::artifact::get_artifact_fs::GetArtifactFs;
+use buck2_build_api::audit_dep_files::AUDIT_DEP_FILE_COVERAGE;
 use buck2_build_api::audit_dep_files::AUDIT_DEP_FILES;
 use buck2_core::category::Category;
 use buck2_core::deferred::base_deferred_key::BaseDeferredKey;
@@ -22,9 +23,12 @@ use buck2_error::buck2_error;
 use buck2_execute::digest_config::HasDigestConfig;
 use buck2_execute::materialize::materializer::HasMaterializer;
 use dice::DiceTransaction;
+use dupe::Dupe;
 
 use crate::actions::impls::run::dep_files::DepFilesKey;
 use crate::actions::impls::run::dep_files::StoredFingerprints;
+use crate::actions::impls::run::dep_files::dep_files_keys_for_owner;
+use crate::actions::impls::run::dep_files::get_dep_file_lookups;
 use crate::actions::impls::run::dep_files::get_dep_files;
 use crate::actions::impls::run::dep_files::read_dep_files;
 
@@ -32,6 +36,7 @@ pub(crate) fn init_audit_dep_files() {
     AUDIT_DEP_FILES.init(|ctx, label, category, identifier, stdout| {
         Box::pin(audit_dep_files(ctx, label, category, identifier, stdout))
     });
+    AUDIT_DEP_FILE_COVERAGE.init(audit_dep_file_coverage);
 }
 
 async fn audit_dep_files(
@@ -70,7 +75,7 @@ async fn audit_dep_files(
                 "Fingerprints were stored as digests! You probably need to use BUCK2_KEEP_DEP_FILE_DIRECTORIES=true"
             ));
         }
-        StoredFingerprints::Dirs(dirs) => dirs,
+        StoredFingerprints::Dirs { filtered, .. } => filtered,
     };
 
     for path in dirs.untagged.ordered_walk_leaves().paths() {
@@ -85,3 +90,55 @@ async fn audit_dep_files(
 
     Ok(())
 }
+
+fn audit_dep_file_coverage(
+    label: ConfiguredTargetLabel,
+    stdout: &mut (dyn Write + Send),
+) -> buck2_error::Result<()> {
+    let keys = dep_files_keys_for_owner(&BaseDeferredKey::TargetLabel(label.dupe()));
+    if keys.is_empty() {
+        return Err(buck2_error!(
+            buck2_error::ErrorTag::Input,
+            "No actions of `{}` with dep files have run since the daemon started",
+            label
+        ));
+    }
+
+    for key in keys {
+        let lookups = get_dep_file_lookups(&key);
+        writeln!(
+            stdout,
+            "{} {}",
+            key.category(),
+            key.identifier().unwrap_or("<no identifier>")
+        )?;
+        writeln!(
+            stdout,
+            "  dep file hits: {}, misses: {}",
+            lookups.hits, lookups.misses
+        )?;
+
+        match get_dep_files(&key).and_then(|state| state.coverage()) {
+            Some(coverage) => {
+                for dep_file in coverage {
+                    writeln!(
+                        stdout,
+                        "  {}: {} declared, {} pruned",
+                        dep_file.label,
+                        dep_file.declared,
+                        dep_file.pruned.len()
+                    )?;
+                    for path in dep_file.pruned {
+                        writeln!(stdout, "    pruned\t{}", path)?;
+                    }
+                }
+            }
+            None => writeln!(
+                stdout,
+                "  inputs: not recorded, use BUCK2_KEEP_DEP_FILE_DIRECTORIES=true to record them"
+            )?,
+        }
+    }
+
+    Ok(())
+}
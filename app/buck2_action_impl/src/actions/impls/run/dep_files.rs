@@ -36,9 +36,12 @@ use buck2_core::category::Category;
 use buck2_core::deferred::base_deferred_key::BaseDeferredKey;
 use buck2_core::fs::artifact_path_resolver::ArtifactFs;
 use buck2_core::fs::fs_util;
+use buck2_core::fs::paths::forward_rel_path::ForwardRelativePathBuf;
 use buck2_core::fs::paths::forward_rel_path::ForwardRelativePathNormalizer;
 use buck2_core::fs::project_rel_path::ProjectRelativePathBuf;
 use buck2_core::soft_error;
+use buck2_directory::directory::directory::Directory;
+use buck2_directory::directory::directory_iterator::DirectoryIterator;
 use buck2_directory::directory::directory_selector::DirectorySelector;
 use buck2_directory::directory::fingerprinted_directory::FingerprintedDirectory;
 use buck2_error::BuckErrorContext;
@@ -76,6 +79,11 @@ use tracing::instrument;
 #[allocative::root]
 static DEP_FILES: Lazy<DashMap<DepFilesKey, Arc<DepFileState>>> = Lazy::new(DashMap::new);
 
+/// How often the local dep file of each action was a hit, for `buck2 audit dep-file-coverage`.
+/// Unlike `DEP_FILES`, entries survive misses.
+#[allocative::root]
+static DEP_FILE_LOOKUPS: Lazy<DashMap<DepFilesKey, DepFileLookups>> = Lazy::new(DashMap::new);
+
 /// When this is set, we retain directories after fingerprinting, so that we can output them later
 /// for debugging via `buck2 audit dep-files`.
 fn keep_directories() -> buck2_error::Result<bool> {
@@ -87,6 +95,7 @@ fn keep_directories() -> buck2_error::Result<bool> {
 fn flush_dep_files() {
     tracing::info!("Flushing all {} dep files", DEP_FILES.len());
     DEP_FILES.clear();
+    DEP_FILE_LOOKUPS.clear();
 }
 
 /// Flush all dep files that were not produced locally.
@@ -114,8 +123,50 @@ pub(crate) fn get_dep_files(key: &DepFilesKey) -> Option<Arc<DepFileState>> {
     DEP_FILES.get(key).map(|s| s.dupe())
 }
 
+/// Keys of the actions of `owner` that have dep file state or were looked up, sorted.
+pub(crate) fn dep_files_keys_for_owner(owner: &BaseDeferredKey) -> Vec<DepFilesKey> {
+    let mut keys: Vec<DepFilesKey> = DEP_FILES
+        .iter()
+        .map(|e| e.key().clone())
+        .chain(DEP_FILE_LOOKUPS.iter().map(|e| e.key().clone()))
+        .filter(|key| key.owner == *owner)
+        .collect();
+    keys.sort_by(|a, b| (&a.category, &a.identifier).cmp(&(&b.category, &b.identifier)));
+    keys.dedup();
+    keys
+}
+
+#[derive(Default, Clone, Copy, Allocative)]
+pub(crate) struct DepFileLookups {
+    pub(crate) hits: u64,
+    pub(crate) misses: u64,
+}
+
+impl DepFileLookups {
+    fn record(&mut self, hit: bool) {
+        if hit {
+            self.hits += 1;
+        } else {
+            self.misses += 1;
+        }
+    }
+}
+
+pub(crate) fn get_dep_file_lookups(key: &DepFilesKey) -> DepFileLookups {
+    DEP_FILE_LOOKUPS.get(key).map(|l| *l).unwrap_or_default()
+}
+
+/// Record the outcome of checking the local dep file of an action. Lookups that an action cache
+/// hit made unnecessary aren't recorded.
+fn record_dep_file_lookup(key: &DepFilesKey, hit: bool) {
+    match DEP_FILE_LOOKUPS.get_mut(key) {
+        Some(mut lookups) => lookups.record(hit),
+        None => DEP_FILE_LOOKUPS.entry(key.clone()).or_default().record(hit),
+    }
+}
+
 /// A key used to associate a RunAction with a possible previous dep file.
-#[derive(Eq, PartialEq, Hash, Display, Allocative)]
+#[derive(Clone, Eq, PartialEq, Hash, Display, Allocative)]
 #[display(
     "{} {} {}",
     owner,
@@ -148,6 +199,14 @@ impl DepFilesKey {
             identifier: target.identifier().map(|t| t.to_owned()),
         }
     }
+
+    pub(crate) fn category(&self) -> &Category {
+        &self.category
+    }
+
+    pub(crate) fn identifier(&self) -> Option<&str> {
+        self.identifier.as_deref()
+    }
 }
 
 /// The input signatures for a DepFileState. We compute those lazily, so we either have the input
@@ -190,21 +249,65 @@ pub(crate) enum StoredFingerprints {
     Digests(PartitionedInputs<TrackedFileDigest>),
 
     /// Store digests + dirs. We allow this via BUCK2_KEEP_DEP_FILE_DIRECTORIES because it gives
-    /// more debuggability.
-    Dirs(PartitionedInputs<ActionImmutableDirectory>),
+    /// more debuggability. The declared inputs are kept too, to tell what the dep files pruned.
+    Dirs {
+        filtered: PartitionedInputs<ActionImmutableDirectory>,
+        declared: PartitionedInputs<ActionImmutableDirectory>,
+    },
 }
 
 impl PartialEq<PartitionedInputs<ActionImmutableDirectory>> for StoredFingerprints {
     fn eq(&self, other: &PartitionedInputs<ActionImmutableDirectory>) -> bool {
         let fingerprints = match self {
             Self::Digests(fingerprints) => Cow::Borrowed(fingerprints),
-            Self::Dirs(dirs) => Cow::Owned(dirs.as_fingerprints()),
+            Self::Dirs { filtered, .. } => Cow::Owned(filtered.as_fingerprints()),
         };
 
         *fingerprints == other.as_fingerprints()
     }
 }
 
+/// How the inputs tagged with the label of a dep file compare to those the dep file kept.
+#[derive(Debug, PartialEq)]
+pub(crate) struct DepFileCoverage {
+    pub(crate) label: Arc<str>,
+    pub(crate) declared: usize,
+    /// Declared inputs not in the dep file, in order.
+    pub(crate) pruned: Vec<ForwardRelativePathBuf>,
+}
+
+impl StoredFingerprints {
+    /// The coverage of each dep file, or `None` if only digests were stored.
+    pub(crate) fn coverage(&self) -> Option<Vec<DepFileCoverage>> {
+        let (filtered, declared) = match self {
+            Self::Digests(..) => return None,
+            Self::Dirs { filtered, declared } => (filtered, declared),
+        };
+
+        let coverage = declared
+            .tagged
+            .iter()
+            .map(|(label, declared)| {
+                let kept: HashSet<ForwardRelativePathBuf> = match filtered.tagged.get(label) {
+                    Some(filtered) => filtered.ordered_walk_leaves().paths().collect(),
+                    None => HashSet::new(),
+                };
+                let declared: Vec<ForwardRelativePathBuf> =
+                    declared.ordered_walk_leaves().paths().collect();
+                DepFileCoverage {
+                    label: label.dupe(),
+                    declared: declared.len(),
+                    pruned: declared
+                        .into_iter()
+                        .filter(|path| !kept.contains(path))
+                        .collect(),
+                }
+            })
+            .collect();
+        Some(coverage)
+    }
+}
+
 /// The state that resulted from the previous evaluation of a command that produced dep files. This
 /// contains everything we need to determine whether re-evaluation is necessary (and if it isn't,
 /// to return the previous value).
@@ -235,6 +338,14 @@ impl DepFileState {
         &self.declared_dep_files
     }
 
+    /// The coverage of each dep file, if the fingerprints were computed and kept directories.
+    pub(crate) fn coverage(&self) -> Option<Vec<DepFileCoverage>> {
+        match &*self.input_signatures.lock() {
+            DepFileStateInputSignatures::Computed(fingerprints) => fingerprints.coverage(),
+            DepFileStateInputSignatures::Deferred(..) => None,
+        }
+    }
+
     /// Compute the signature for this DepFileState, having provided the dep files from
     /// read_dep_files.
     pub(crate) fn locked_compute_fingerprints<'a>(
@@ -370,7 +481,7 @@ impl CommonDigests {
     ) -> Vec<DepFileInputs> {
         let filtered_input_fingerprints = match filtered_input_fingerprints {
             StoredFingerprints::Digests(digests) => Cow::Borrowed(digests),
-            StoredFingerprints::Dirs(dirs) => Cow::Owned(dirs.as_fingerprints()),
+            StoredFingerprints::Dirs { filtered, .. } => Cow::Owned(filtered.as_fingerprints()),
         };
         declared_dep_files
             .tagged
@@ -470,6 +581,10 @@ impl DepFileBundle {
             buck2_data::MatchDepFilesEnd {},
         )
         .await?;
+        // Otherwise the full check records the outcome, if the action cache doesn't hit.
+        if outputs.is_some() || !check_filtered_inputs {
+            record_dep_file_lookup(&self.dep_files_key, outputs.is_some());
+        }
         let outputs = outputs.map(|o| {
             (
                 o,
@@ -505,6 +620,7 @@ impl DepFileBundle {
             buck2_data::MatchDepFilesEnd {},
         )
         .await?;
+        record_dep_file_lookup(&self.dep_files_key, matching_result.is_some());
 
         let matching_result = matching_result.map(|o| {
             (
@@ -947,11 +1063,13 @@ fn compute_fingerprints(
     digest_config: DigestConfig,
     keep_directories: bool,
 ) -> StoredFingerprints {
-    let filtered_directories = directories.filter(dep_files).fingerprint(digest_config);
     if keep_directories {
-        StoredFingerprints::Dirs(filtered_directories)
+        let declared = directories.clone().fingerprint(digest_config);
+        let filtered = directories.filter(dep_files).fingerprint(digest_config);
+        StoredFingerprints::Dirs { filtered, declared }
     } else {
-        StoredFingerprints::Digests(filtered_directories.as_fingerprints())
+        let filtered = directories.filter(dep_files).fingerprint(digest_config);
+        StoredFingerprints::Digests(filtered.as_fingerprints())
     }
 }
 
@@ -1422,8 +1540,12 @@ mod tests {
 
     use buck2_artifact::actions::key::ActionIndex;
     use buck2_artifact::artifact::artifact_type::testing::BuildArtifactTestingExt;
+    use buck2_common::file_ops::FileMetadata;
     use buck2_core::configuration::data::ConfigurationData;
+    use buck2_core::fs::paths::forward_rel_path::ForwardRelativePath;
+    use buck2_core::fs::project_rel_path::ProjectRelativePath;
     use buck2_core::target::configured_target_label::ConfiguredTargetLabel;
+    use buck2_execute::directory::insert_file;
 
     use super::*;
 
@@ -1542,4 +1664,54 @@ mod tests {
         assert!(!decl2.declares_same_dep_files(&decl3));
         assert!(!decl3.declares_same_dep_files(&decl4));
     }
+
+    #[test]
+    fn test_dep_file_coverage() -> buck2_error::Result<()> {
+        let digest_config = DigestConfig::testing_default();
+        let directory = |paths: &[&str]| {
+            let mut builder = ActionDirectoryBuilder::empty();
+            for path in paths {
+                insert_file(
+                    &mut builder,
+                    ProjectRelativePath::new(path)?,
+                    FileMetadata::empty(digest_config.cas_digest_config()),
+                )?;
+            }
+            buck2_error::Ok(builder)
+        };
+
+        // A run action declaring three headers, whose dep file reports that only one was used.
+        let declared = PartitionedInputs {
+            untagged: directory(&["src/main.c"])?,
+            tagged: OrderedMap::from_iter([(
+                Arc::from("headers"),
+                directory(&["inc/a.h", "inc/b.h", "inc/c.h"])?,
+            )]),
+        };
+        let mut selector = DirectorySelector::empty();
+        selector.select(ForwardRelativePath::new("inc/a.h")?);
+        let dep_files = ConcreteDepFiles {
+            contents: HashMap::from_iter([(Arc::from("headers"), selector)]),
+        };
+
+        let fingerprints =
+            compute_fingerprints(declared.clone(), dep_files.clone(), digest_config, true);
+        assert_eq!(
+            fingerprints.coverage(),
+            Some(vec![DepFileCoverage {
+                label: Arc::from("headers"),
+                declared: 3,
+                pruned: vec![
+                    ForwardRelativePathBuf::unchecked_new("inc/b.h".to_owned()),
+                    ForwardRelativePathBuf::unchecked_new("inc/c.h".to_owned()),
+                ],
+            }])
+        );
+
+        // Without the directories there is nothing to compare.
+        let fingerprints = compute_fingerprints(declared, dep_files, digest_config, false);
+        assert_eq!(fingerprints.coverage(), None);
+
+        Ok(())
+    }
 }
@@ -78,6 +78,13 @@ use crate::dynamic::dynamic_actions_callable::P_ACTIONS;
 use crate::dynamic::params::FrozenDynamicLambdaParams;
 use crate::dynamic::resolved_dynamic_value::StarlarkResolvedDynamicValue;
 
+#[derive(Debug, buck2_error::Error)]
+#[buck2(tag = DiceCancelled)]
+enum DynamicLambdaError {
+    #[error("Dynamic lambda was cancelled")]
+    Cancelled,
+}
+
 pub enum DynamicLambdaArgs<'v> {
     OldPositional {
         ctx: Value<'v>,
@@ -156,6 +163,26 @@ pub fn invoke_dynamic_output_lambda<'v>(
     Ok(provider_collection)
 }
 
+/// Like `invoke_dynamic_output_lambda`, but stops with a cancellation error as soon as
+/// `is_cancelled` returns true, so that cancelling a build doesn't wait for long-running lambdas
+/// to finish.
+pub fn invoke_cancellable_dynamic_output_lambda<'v, 'a, 'e: 'a>(
+    eval: &mut Evaluator<'v, 'a, 'e>,
+    lambda: Value<'v>,
+    args: DynamicLambdaArgs<'v>,
+    is_cancelled: impl Fn() -> bool + Clone + 'a,
+) -> buck2_error::Result<ProviderCollection<'v>> {
+    eval.set_check_cancelled(Box::new(is_cancelled.clone()));
+    invoke_dynamic_output_lambda(eval, lambda, args).map_err(|e| {
+        // Starlark reports cancellation as an evaluation error like any other.
+        if is_cancelled() {
+            DynamicLambdaError::Cancelled.into()
+        } else {
+            e
+        }
+    })
+}
+
 async fn execute_lambda(
     lambda: OwnedRefFrozenRef<'_, FrozenDynamicLambdaParams>,
     dice: &mut DiceComputations<'_>,
@@ -255,10 +282,11 @@ async fn execute_lambda(
                         }
                     };
 
-                    let providers: ProviderCollection = invoke_dynamic_output_lambda(
+                    let providers: ProviderCollection = invoke_cancellable_dynamic_output_lambda(
                         &mut eval,
                         dynamic_lambda_ctx_data.lambda.lambda(),
                         args,
+                        move || liveness.is_cancellation_requested(),
                     )?;
                     let providers = eval.heap().alloc(providers);
                     let providers = ValueTypedComplex::<ProviderCollection>::new(providers)
@@ -736,3 +764,48 @@ pub fn dynamic_lambda_ctx_data<'v>(
         registry,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use buck2_error::ErrorTag;
+    use starlark::environment::Globals;
+    use starlark::syntax::AstModule;
+    use starlark::syntax::Dialect;
+
+    use super::*;
+
+    #[test]
+    fn test_cancel_mid_evaluation() -> buck2_error::Result<()> {
+        let checks = Cell::new(0);
+        let env = Module::new();
+        let mut eval = Evaluator::new(&env);
+        let ast = AstModule::parse(
+            "dynamic.bzl",
+            "def f(ctx, artifact_values, outputs):\n    for _ in range(1000000000):\n        pass\n"
+                .to_owned(),
+            &Dialect::Standard,
+        )?;
+        eval.eval_module(ast, &Globals::standard())?;
+        let lambda = env.get("f").unwrap();
+
+        let args = DynamicLambdaArgs::OldPositional {
+            ctx: Value::new_none(),
+            artifact_values: ValueOfUnchecked::new(Value::new_none()),
+            outputs: ValueOfUnchecked::new(Value::new_none()),
+        };
+        // Cancelled after the lambda started running.
+        let is_cancelled = || {
+            checks.set(checks.get() + 1);
+            checks.get() > 10
+        };
+        let e = invoke_cancellable_dynamic_output_lambda(&mut eval, lambda, args, is_cancelled)
+            .err()
+            .unwrap();
+        assert!(e.has_tag(ErrorTag::DiceCancelled), "error: {:?}", e);
+        // The lambda stopped on the first check after cancellation, plus the one of the error.
+        assert_eq!(12, checks.get());
+        Ok(())
+    }
+}
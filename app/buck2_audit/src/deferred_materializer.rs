@@ -7,11 +7,22 @@
  * of this source tree.
  */
 
+pub mod debug_rpc;
+
 use async_trait::async_trait;
 use buck2_client_ctx::common::CommonCommandOptions;
 use buck2_client_ctx::common::target_cfg::TargetCfgUnusedOptions;
 
 use crate::AuditSubcommand;
+use crate::deferred_materializer::debug_rpc::FlushAccessTimesRequest;
+use crate::deferred_materializer::debug_rpc::FsckRequest;
+use crate::deferred_materializer::debug_rpc::GetRefreshLogRequest;
+use crate::deferred_materializer::debug_rpc::ListRequest;
+use crate::deferred_materializer::debug_rpc::ListSubscriptionsRequest;
+use crate::deferred_materializer::debug_rpc::PendingRequest;
+use crate::deferred_materializer::debug_rpc::RefreshRequest;
+use crate::deferred_materializer::debug_rpc::StatsRequest;
+use crate::deferred_materializer::debug_rpc::TestIterRequest;
 
 #[derive(Debug, clap::Parser, serde::Serialize, serde::Deserialize)]
 #[clap(
@@ -20,8 +31,15 @@ use crate::AuditSubcommand;
 )]
 pub struct DeferredMaterializerCommand {
     #[clap(subcommand)]
+    #[serde(with = "debug_rpc::subcommand_envelope")]
     pub subcommand: DeferredMaterializerSubcommand,
 
+    /// Print the response as JSON, in an envelope naming the version of its schema. See
+    /// `debug_rpc`. Streamed responses are printed as one envelope per line.
+    #[clap(long, global = true)]
+    #[serde(default)]
+    pub json: bool,
+
     /// Command doesn't need these flags, but they are used in mode files, so we need to keep them.
     #[clap(flatten)]
     pub _target_cfg: TargetCfgUnusedOptions,
@@ -30,23 +48,21 @@ pub struct DeferredMaterializerCommand {
     pub common_opts: CommonCommandOptions,
 }
 
-#[derive(Debug, clap::Subcommand, serde::Serialize, serde::Deserialize)]
+/// Serialized as the `debug_rpc::DebugRpcEnvelope` of its request.
+#[derive(Debug, Clone, PartialEq, clap::Subcommand)]
 pub enum DeferredMaterializerSubcommand {
-    List,
-    ListSubscriptions,
-    Fsck,
-    Refresh {
-        /// Minimum TTL to require for actions.
-        #[clap()]
-        min_ttl: i64,
-    },
+    List(ListRequest),
+    ListSubscriptions(ListSubscriptionsRequest),
+    Fsck(FsckRequest),
+    Refresh(RefreshRequest),
     /// Get the log for TTL refreshes.
-    GetRefreshLog,
-    TestIter {
-        #[clap(long, default_value = "1")]
-        count: usize,
-    },
-    FlushAccessTimes,
+    GetRefreshLog(GetRefreshLogRequest),
+    TestIter(TestIterRequest),
+    FlushAccessTimes(FlushAccessTimesRequest),
+    /// Show the local copies and writes waiting to start, per command.
+    Pending(PendingRequest),
+    /// Measure the memory used by the materializer's state. This walks all of it.
+    Stats(StatsRequest),
 }
 
 #[async_trait]
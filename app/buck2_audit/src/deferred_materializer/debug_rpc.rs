@@ -0,0 +1,679 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Versioned wire format of the `audit deferred-materializer` debug RPCs.
+//!
+//! Requests go from the client to the daemon as a `DebugRpcEnvelope`, naming the RPC and the
+//! version of its schema. With `--json`, responses come back the same way. Minor versions only
+//! add fields, with defaults: decoders ignore fields they don't know about, so payloads of any
+//! minor version of a major version can be decoded by the code of any other. A payload of another
+//! major version is rejected with an error naming both versions, rather than misread.
+
+use std::fmt;
+use std::time::Duration;
+
+use buck2_error::BuckErrorContext;
+use buck2_error::ErrorTag;
+use buck2_error::buck2_error;
+use serde::Deserialize;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use crate::deferred_materializer::DeferredMaterializerSubcommand;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DebugRpcVersion {
+    pub major: u32,
+    pub minor: u32,
+}
+
+impl DebugRpcVersion {
+    pub const fn new(major: u32, minor: u32) -> Self {
+        Self { major, minor }
+    }
+}
+
+impl fmt::Display for DebugRpcVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+/// A request or response of the RPC `rpc`, following version `version` of its schema.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DebugRpcEnvelope {
+    pub rpc: String,
+    pub version: DebugRpcVersion,
+    pub payload: serde_json::Value,
+}
+
+/// A request/response pair.
+pub trait DebugRpc: 'static {
+    /// The name of the subcommand running the RPC.
+    const NAME: &'static str;
+    /// Bump the minor version when adding a field with a default to the request or response, and
+    /// the major version for any other change.
+    const VERSION: DebugRpcVersion;
+    type Request: Serialize + DeserializeOwned;
+    type Response: Serialize + DeserializeOwned;
+}
+
+fn encode<R: DebugRpc>(payload: &impl Serialize) -> buck2_error::Result<DebugRpcEnvelope> {
+    Ok(DebugRpcEnvelope {
+        rpc: R::NAME.to_owned(),
+        version: R::VERSION,
+        payload: serde_json::to_value(payload)?,
+    })
+}
+
+fn decode<R: DebugRpc, P: DeserializeOwned>(envelope: DebugRpcEnvelope) -> buck2_error::Result<P> {
+    if envelope.rpc != R::NAME {
+        return Err(buck2_error!(
+            ErrorTag::Input,
+            "Expected a payload of debug RPC `{}`, got `{}`",
+            R::NAME,
+            envelope.rpc
+        ));
+    }
+    if envelope.version.major != R::VERSION.major {
+        return Err(buck2_error!(
+            ErrorTag::Input,
+            "Debug RPC `{}` payload has version {}, which is incompatible with version {} \
+            supported here. The client and the daemon are likely built from different revisions",
+            R::NAME,
+            envelope.version,
+            R::VERSION
+        ));
+    }
+    serde_json::from_value(envelope.payload).with_buck_error_context(|| {
+        format!(
+            "Invalid payload for debug RPC `{}` version {}",
+            R::NAME,
+            envelope.version
+        )
+    })
+}
+
+pub fn encode_response<R: DebugRpc>(response: &R::Response) -> buck2_error::Result<String> {
+    Ok(serde_json::to_string(&encode::<R>(response)?)?)
+}
+
+pub fn decode_response<R: DebugRpc>(text: &str) -> buck2_error::Result<R::Response> {
+    let envelope = serde_json::from_str(text).buck_error_context("Invalid debug RPC envelope")?;
+    decode::<R, R::Response>(envelope)
+}
+
+/// Decodes a request of one RPC into the subcommand running it.
+pub struct DebugRpcCodec {
+    pub name: &'static str,
+    pub version: DebugRpcVersion,
+    decode_request: fn(DebugRpcEnvelope) -> buck2_error::Result<DeferredMaterializerSubcommand>,
+}
+
+impl DebugRpcCodec {
+    const fn of<R: DebugRpc>() -> Self
+    where
+        R::Request: Into<DeferredMaterializerSubcommand>,
+    {
+        Self {
+            name: R::NAME,
+            version: R::VERSION,
+            decode_request: decode_request::<R>,
+        }
+    }
+}
+
+fn decode_request<R: DebugRpc>(
+    envelope: DebugRpcEnvelope,
+) -> buck2_error::Result<DeferredMaterializerSubcommand>
+where
+    R::Request: Into<DeferredMaterializerSubcommand>,
+{
+    decode::<R, R::Request>(envelope).map(Into::into)
+}
+
+/// All the debug RPCs, by name.
+pub static DEBUG_RPCS: &[DebugRpcCodec] = &[
+    DebugRpcCodec::of::<ListRpc>(),
+    DebugRpcCodec::of::<ListSubscriptionsRpc>(),
+    DebugRpcCodec::of::<FsckRpc>(),
+    DebugRpcCodec::of::<RefreshRpc>(),
+    DebugRpcCodec::of::<GetRefreshLogRpc>(),
+    DebugRpcCodec::of::<TestIterRpc>(),
+    DebugRpcCodec::of::<FlushAccessTimesRpc>(),
+    DebugRpcCodec::of::<PendingRpc>(),
+    DebugRpcCodec::of::<StatsRpc>(),
+];
+
+impl DeferredMaterializerSubcommand {
+    fn to_envelope(&self) -> buck2_error::Result<DebugRpcEnvelope> {
+        match self {
+            DeferredMaterializerSubcommand::List(request) => encode::<ListRpc>(request),
+            DeferredMaterializerSubcommand::ListSubscriptions(request) => {
+                encode::<ListSubscriptionsRpc>(request)
+            }
+            DeferredMaterializerSubcommand::Fsck(request) => encode::<FsckRpc>(request),
+            DeferredMaterializerSubcommand::Refresh(request) => encode::<RefreshRpc>(request),
+            DeferredMaterializerSubcommand::GetRefreshLog(request) => {
+                encode::<GetRefreshLogRpc>(request)
+            }
+            DeferredMaterializerSubcommand::TestIter(request) => encode::<TestIterRpc>(request),
+            DeferredMaterializerSubcommand::FlushAccessTimes(request) => {
+                encode::<FlushAccessTimesRpc>(request)
+            }
+            DeferredMaterializerSubcommand::Pending(request) => encode::<PendingRpc>(request),
+            DeferredMaterializerSubcommand::Stats(request) => encode::<StatsRpc>(request),
+        }
+    }
+
+    fn from_envelope(envelope: DebugRpcEnvelope) -> buck2_error::Result<Self> {
+        let codec = DEBUG_RPCS
+            .iter()
+            .find(|codec| codec.name == envelope.rpc)
+            .ok_or_else(|| {
+                buck2_error!(
+                    ErrorTag::Input,
+                    "Unknown debug RPC `{}` (version {}). The client and the daemon are likely \
+                    built from different revisions",
+                    envelope.rpc,
+                    envelope.version
+                )
+            })?;
+        (codec.decode_request)(envelope)
+    }
+}
+
+/// Serializes a `DeferredMaterializerSubcommand` as the envelope of its request, for
+/// `#[serde(with)]`.
+pub(crate) mod subcommand_envelope {
+    use serde::Deserialize;
+    use serde::Deserializer;
+    use serde::Serialize;
+    use serde::Serializer;
+
+    use super::DebugRpcEnvelope;
+    use crate::deferred_materializer::DeferredMaterializerSubcommand;
+
+    pub(crate) fn serialize<S: Serializer>(
+        subcommand: &DeferredMaterializerSubcommand,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        subcommand
+            .to_envelope()
+            .map_err(|e| serde::ser::Error::custom(format!("{:#}", e)))?
+            .serialize(serializer)
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<DeferredMaterializerSubcommand, D::Error> {
+        DeferredMaterializerSubcommand::from_envelope(DebugRpcEnvelope::deserialize(deserializer)?)
+            .map_err(|e| serde::de::Error::custom(format!("{:#}", e)))
+    }
+}
+
+macro_rules! debug_rpc {
+    ($rpc:ident, $name:literal, $version:expr, $variant:ident($request:ty) -> $response:ty) => {
+        pub struct $rpc;
+
+        impl DebugRpc for $rpc {
+            const NAME: &'static str = $name;
+            const VERSION: DebugRpcVersion = $version;
+            type Request = $request;
+            type Response = $response;
+        }
+
+        impl From<$request> for DeferredMaterializerSubcommand {
+            fn from(request: $request) -> Self {
+                DeferredMaterializerSubcommand::$variant(request)
+            }
+        }
+    };
+}
+
+// Streamed, one response per artifact.
+debug_rpc!(ListRpc, "list", DebugRpcVersion::new(2, 0), List(ListRequest) -> ListedArtifact);
+debug_rpc!(
+    ListSubscriptionsRpc,
+    "list-subscriptions",
+    DebugRpcVersion::new(1, 0),
+    ListSubscriptions(ListSubscriptionsRequest) -> ListSubscriptionsResponse
+);
+debug_rpc!(FsckRpc, "fsck", DebugRpcVersion::new(1, 0), Fsck(FsckRequest) -> FsckResponse);
+debug_rpc!(
+    RefreshRpc,
+    "refresh",
+    DebugRpcVersion::new(1, 0),
+    Refresh(RefreshRequest) -> RefreshResponse
+);
+debug_rpc!(
+    GetRefreshLogRpc,
+    "get-refresh-log",
+    DebugRpcVersion::new(1, 1),
+    GetRefreshLog(GetRefreshLogRequest) -> GetRefreshLogResponse
+);
+debug_rpc!(
+    TestIterRpc,
+    "test-iter",
+    DebugRpcVersion::new(1, 0),
+    TestIter(TestIterRequest) -> TestIterResponse
+);
+debug_rpc!(
+    FlushAccessTimesRpc,
+    "flush-access-times",
+    DebugRpcVersion::new(1, 0),
+    FlushAccessTimes(FlushAccessTimesRequest) -> FlushAccessTimesResponse
+);
+debug_rpc!(
+    PendingRpc,
+    "pending",
+    DebugRpcVersion::new(1, 0),
+    Pending(PendingRequest) -> PendingResponse
+);
+debug_rpc!(StatsRpc, "stats", DebugRpcVersion::new(1, 0), Stats(StatsRequest) -> StatsResponse);
+
+#[derive(Debug, Clone, PartialEq, clap::Args, Serialize, Deserialize)]
+pub struct ListRequest {}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ListedArtifact {
+    pub path: String,
+    /// Description of the stage of the artifact, e.g. `declared: local copy`.
+    pub state: String,
+    pub deps: Vec<ListedDep>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ListedDep {
+    pub path: String,
+    pub kind: String,
+}
+
+impl fmt::Display for ListedArtifact {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}\t{}", self.path, self.state)?;
+        writeln!(f, "  deps: {}", self.deps.len())?;
+        for dep in &self.deps {
+            writeln!(f, "    {} {}", dep.path, dep.kind)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, clap::Args, Serialize, Deserialize)]
+pub struct ListSubscriptionsRequest {}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ListSubscriptionsResponse {
+    pub paths: Vec<String>,
+}
+
+impl fmt::Display for ListSubscriptionsResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for path in &self.paths {
+            writeln!(f, "{}", path)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, clap::Args, Serialize, Deserialize)]
+pub struct FsckRequest {}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FsckResponse {
+    pub errors: Vec<FsckError>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FsckError {
+    pub path: String,
+    pub error: String,
+}
+
+impl fmt::Display for FsckResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for error in &self.errors {
+            writeln!(f, "{}\t{}", error.path, error.error)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, clap::Args, Serialize, Deserialize)]
+pub struct RefreshRequest {
+    /// Minimum TTL to require for actions.
+    #[clap()]
+    pub min_ttl: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RefreshResponse {}
+
+impl fmt::Display for RefreshResponse {
+    fn fmt(&self, _f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, clap::Args, Serialize, Deserialize)]
+pub struct GetRefreshLogRequest {
+    /// Only show the last N refreshes.
+    #[clap(long, value_name = "N")]
+    #[serde(default)]
+    pub last: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GetRefreshLogResponse {
+    /// Oldest first.
+    pub entries: Vec<RefreshLogEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RefreshLogEntry {
+    /// RFC 3339 timestamp.
+    pub at: String,
+    pub outcome: RefreshOutcome,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RefreshOutcome {
+    Skipped,
+    Ok,
+    Error(String),
+}
+
+impl fmt::Display for GetRefreshLogResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for entry in &self.entries {
+            match &entry.outcome {
+                RefreshOutcome::Skipped => writeln!(f, "{}\tSKIP", entry.at)?,
+                RefreshOutcome::Ok => writeln!(f, "{}\tOK", entry.at)?,
+                RefreshOutcome::Error(e) => writeln!(f, "{}\tERR\t{}", entry.at, e)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, clap::Args, Serialize, Deserialize)]
+pub struct TestIterRequest {
+    #[clap(long, default_value = "1")]
+    pub count: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TestIterResponse {
+    pub count: usize,
+    pub iter: Duration,
+    pub iter_with_paths: Duration,
+}
+
+impl fmt::Display for TestIterResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Elapsed for iter() ({} times): {:?}",
+            self.count, self.iter
+        )?;
+        writeln!(
+            f,
+            "Elapsed for iter().with_paths() ({} times): {:?}",
+            self.count, self.iter_with_paths
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, clap::Args, Serialize, Deserialize)]
+pub struct FlushAccessTimesRequest {}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FlushAccessTimesResponse {
+    pub message: String,
+}
+
+impl fmt::Display for FlushAccessTimesResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.message)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, clap::Args, Serialize, Deserialize)]
+pub struct PendingRequest {}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PendingResponse {
+    /// In the order the commands get their next turn.
+    pub commands: Vec<PendingCommand>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PendingCommand {
+    pub trace_id: String,
+    /// Materializations waiting on the concurrency limit.
+    pub pending: usize,
+}
+
+impl fmt::Display for PendingResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for command in &self.commands {
+            writeln!(f, "{}\t{}", command.trace_id, command.pending)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, clap::Args, Serialize, Deserialize)]
+pub struct StatsRequest {}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StatsResponse {
+    /// Estimated memory used by the artifact tree.
+    pub artifact_tree_bytes: u64,
+}
+
+impl fmt::Display for StatsResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "artifact_tree_bytes\t{}", self.artifact_tree_bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Version 1.0 of an RPC, which version 1.1 adds `added` to.
+    struct OldRpc;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct OldResponse {
+        kept: String,
+    }
+
+    impl DebugRpc for OldRpc {
+        const NAME: &'static str = "compat";
+        const VERSION: DebugRpcVersion = DebugRpcVersion::new(1, 0);
+        type Request = ListRequest;
+        type Response = OldResponse;
+    }
+
+    struct NewRpc;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct NewResponse {
+        kept: String,
+        #[serde(default)]
+        added: u64,
+    }
+
+    impl DebugRpc for NewRpc {
+        const NAME: &'static str = "compat";
+        const VERSION: DebugRpcVersion = DebugRpcVersion::new(1, 1);
+        type Request = ListRequest;
+        type Response = NewResponse;
+    }
+
+    /// Version 2.0, which changed the type of `kept`.
+    struct BrokenRpc;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct BrokenResponse {
+        kept: u64,
+    }
+
+    impl DebugRpc for BrokenRpc {
+        const NAME: &'static str = "compat";
+        const VERSION: DebugRpcVersion = DebugRpcVersion::new(2, 0);
+        type Request = ListRequest;
+        type Response = BrokenResponse;
+    }
+
+    #[test]
+    fn test_minor_versions_are_compatible() -> buck2_error::Result<()> {
+        let old = encode_response::<OldRpc>(&OldResponse {
+            kept: "x".to_owned(),
+        })?;
+        assert_eq!(
+            NewResponse {
+                kept: "x".to_owned(),
+                added: 0,
+            },
+            decode_response::<NewRpc>(&old)?
+        );
+
+        let new = encode_response::<NewRpc>(&NewResponse {
+            kept: "y".to_owned(),
+            added: 7,
+        })?;
+        assert_eq!(
+            OldResponse {
+                kept: "y".to_owned(),
+            },
+            decode_response::<OldRpc>(&new)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_major_versions_are_incompatible() -> buck2_error::Result<()> {
+        let old = encode_response::<OldRpc>(&OldResponse {
+            kept: "x".to_owned(),
+        })?;
+        let e = format!("{:#}", decode_response::<BrokenRpc>(&old).unwrap_err());
+        assert!(
+            e.contains("`compat` payload has version 1.0, which is incompatible with version 2.0"),
+            "{}",
+            e
+        );
+
+        let broken = encode_response::<BrokenRpc>(&BrokenResponse { kept: 1 })?;
+        let e = format!("{:#}", decode_response::<NewRpc>(&broken).unwrap_err());
+        assert!(
+            e.contains("`compat` payload has version 2.0, which is incompatible with version 1.1"),
+            "{}",
+            e
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_wrong_rpc() -> buck2_error::Result<()> {
+        let list = encode_response::<ListRpc>(&ListedArtifact {
+            path: "foo".to_owned(),
+            state: "materialized".to_owned(),
+            deps: Vec::new(),
+        })?;
+        let e = format!("{:#}", decode_response::<FsckRpc>(&list).unwrap_err());
+        assert!(
+            e.contains("Expected a payload of debug RPC `fsck`, got `list`"),
+            "{}",
+            e
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_registry_names_match_rpcs() {
+        let mut names: Vec<&str> = DEBUG_RPCS.iter().map(|codec| codec.name).collect();
+        names.sort();
+        names.dedup();
+        assert_eq!(DEBUG_RPCS.len(), names.len());
+    }
+
+    #[test]
+    fn test_subcommand_round_trip() -> buck2_error::Result<()> {
+        #[derive(Serialize, Deserialize)]
+        struct Wrapper {
+            #[serde(with = "subcommand_envelope")]
+            subcommand: DeferredMaterializerSubcommand,
+        }
+
+        let request = serde_json::to_value(Wrapper {
+            subcommand: DeferredMaterializerSubcommand::TestIter(TestIterRequest { count: 3 }),
+        })?;
+        assert_eq!(
+            serde_json::json!({
+                "subcommand": {
+                    "rpc": "test-iter",
+                    "version": {"major": 1, "minor": 0},
+                    "payload": {"count": 3},
+                },
+            }),
+            request
+        );
+        let decoded: Wrapper = serde_json::from_value(request)?;
+        assert_eq!(
+            DeferredMaterializerSubcommand::TestIter(TestIterRequest { count: 3 }),
+            decoded.subcommand
+        );
+
+        // A newer client sending fields this daemon doesn't know about.
+        let decoded: Wrapper = serde_json::from_value(serde_json::json!({
+            "subcommand": {
+                "rpc": "refresh",
+                "version": {"major": 1, "minor": 4},
+                "payload": {"min_ttl": 60, "methods": ["cas"]},
+            },
+        }))?;
+        assert_eq!(
+            DeferredMaterializerSubcommand::Refresh(RefreshRequest { min_ttl: 60 }),
+            decoded.subcommand
+        );
+
+        for (subcommand, expected) in [
+            (
+                serde_json::json!({
+                    "rpc": "what-blocks",
+                    "version": {"major": 1, "minor": 0},
+                    "payload": {},
+                }),
+                "Unknown debug RPC `what-blocks` (version 1.0)",
+            ),
+            (
+                serde_json::json!({
+                    "rpc": "fsck",
+                    "version": {"major": 2, "minor": 0},
+                    "payload": {},
+                }),
+                "`fsck` payload has version 2.0, which is incompatible with version 1.0",
+            ),
+        ] {
+            let e = serde_json::from_value::<Wrapper>(serde_json::json!({
+                "subcommand": subcommand,
+            }))
+            .err()
+            .unwrap()
+            .to_string();
+            assert!(e.contains(expected), "{}", e);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,37 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use async_trait::async_trait;
+use buck2_client_ctx::common::CommonCommandOptions;
+use buck2_client_ctx::common::target_cfg::TargetCfgOptions;
+
+use crate::AuditSubcommand;
+
+#[derive(Debug, clap::Parser, serde::Serialize, serde::Deserialize)]
+#[clap(
+    name = "audit-dep-file-coverage",
+    about = "prints dep file hits and misses of a target's actions, and the inputs they pruned"
+)]
+pub struct AuditDepFileCoverageCommand {
+    #[clap(help = "Target to query dep file coverage for")]
+    pub pattern: String,
+
+    #[clap(flatten)]
+    pub target_cfg: TargetCfgOptions,
+
+    #[clap(flatten)]
+    pub common_opts: CommonCommandOptions,
+}
+
+#[async_trait]
+impl AuditSubcommand for AuditDepFileCoverageCommand {
+    fn common_opts(&self) -> &CommonCommandOptions {
+        &self.common_opts
+    }
+}
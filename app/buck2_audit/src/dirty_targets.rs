@@ -0,0 +1,52 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use async_trait::async_trait;
+use buck2_client_ctx::common::CommonCommandOptions;
+use buck2_client_ctx::common::target_cfg::TargetCfgWithUniverseOptions;
+
+use crate::AuditSubcommand;
+
+#[derive(Debug, clap::Parser, serde::Serialize, serde::Deserialize)]
+#[clap(
+    name = "audit-dirty-targets",
+    about = "Report which targets the next build would reconfigure or re-analyze, without doing that work"
+)]
+pub struct AuditDirtyTargetsCommand {
+    #[clap(
+        name = "TARGET_PATTERNS",
+        help = "Top-level target patterns to check",
+        required = true
+    )]
+    pub patterns: Vec<String>,
+
+    #[clap(long, help = "Output in JSON format")]
+    pub json: bool,
+
+    #[clap(
+        long,
+        value_name = "N",
+        default_value = "20",
+        help = "Number of dirty targets to list by name"
+    )]
+    pub sample: usize,
+
+    #[clap(flatten)]
+    pub target_cfg: TargetCfgWithUniverseOptions,
+
+    #[clap(flatten)]
+    pub common_opts: CommonCommandOptions,
+}
+
+#[async_trait]
+impl AuditSubcommand for AuditDirtyTargetsCommand {
+    fn common_opts(&self) -> &CommonCommandOptions {
+        &self.common_opts
+    }
+}
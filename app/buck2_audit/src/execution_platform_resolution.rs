@@ -22,6 +22,10 @@ pub struct AuditExecutionPlatformResolutionCommand {
     #[clap(name = "TARGET_PATTERNS", help = "Patterns to analyze")]
     pub patterns: Vec<String>,
 
+    /// List every candidate execution platform for each target, with what rejected it
+    #[clap(long)]
+    pub explain: bool,
+
     #[clap(flatten)]
     pub target_cfg: TargetCfgWithUniverseOptions,
 
@@ -31,7 +31,9 @@ use crate::cell::AuditCellCommand;
 use crate::config::AuditConfigCommand;
 use crate::configurations::AuditConfigurationsCommand;
 use crate::deferred_materializer::DeferredMaterializerCommand;
+use crate::dep_file_coverage::AuditDepFileCoverageCommand;
 use crate::dep_files::AuditDepFilesCommand;
+use crate::dirty_targets::AuditDirtyTargetsCommand;
 use crate::execution_platform_resolution::AuditExecutionPlatformResolutionCommand;
 use crate::includes::AuditIncludesCommand;
 use crate::output::command::AuditOutputCommand;
@@ -50,7 +52,9 @@ pub mod classpath;
 pub mod config;
 pub mod configurations;
 pub mod deferred_materializer;
+pub mod dep_file_coverage;
 pub mod dep_files;
+pub mod dirty_targets;
 pub mod execution_platform_resolution;
 pub mod includes;
 pub mod output;
@@ -79,10 +83,12 @@ pub enum AuditCommand {
     #[clap(subcommand)]
     Starlark(StarlarkCommand),
     DepFiles(AuditDepFilesCommand),
+    DepFileCoverage(AuditDepFileCoverageCommand),
     DeferredMaterializer(DeferredMaterializerCommand),
     Output(AuditOutputCommand),
     Parse(AuditParseCommand),
     PackageValues(PackageValuesCommand),
+    DirtyTargets(AuditDirtyTargetsCommand),
     #[clap(subcommand, hide = true)]
     Perf(AuditPerfCommand),
 }
@@ -115,11 +121,13 @@ impl AuditCommand {
             AuditCommand::ExecutionPlatformResolution(cmd) => cmd,
             AuditCommand::Starlark(cmd) => cmd,
             AuditCommand::DepFiles(cmd) => cmd,
+            AuditCommand::DepFileCoverage(cmd) => cmd,
             AuditCommand::DeferredMaterializer(cmd) => cmd,
             AuditCommand::Visibility(cmd) => cmd,
             AuditCommand::Output(cmd) => cmd,
             AuditCommand::Parse(cmd) => cmd,
             AuditCommand::PackageValues(cmd) => cmd,
+            AuditCommand::DirtyTargets(cmd) => cmd,
             AuditCommand::Perf(cmd) => cmd,
         }
     }
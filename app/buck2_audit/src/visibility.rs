@@ -22,6 +22,11 @@ pub struct AuditVisibilityCommand {
     #[clap(name = "TARGET_PATTERNS", help = "Target pattern(s) to analyze.")]
     pub patterns: Vec<String>,
 
+    /// Instead of verifying the visibility of transitive deps, explain whether each of the
+    /// specified targets is visible to this target, and which pattern allows or denies it.
+    #[clap(long, value_name = "TARGET")]
+    pub explain_to: Option<String>,
+
     /// Command doesn't need these flags, but they are used in mode files, so we need to keep them.
     #[clap(flatten)]
     _target_cfg: TargetCfgUnusedOptions,
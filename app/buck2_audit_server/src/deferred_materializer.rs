@@ -7,11 +7,40 @@
  * of this source tree.
  */
 
+use std::fmt::Display;
 use std::io::Write;
 
 use async_trait::async_trait;
 use buck2_audit::deferred_materializer::DeferredMaterializerCommand;
 use buck2_audit::deferred_materializer::DeferredMaterializerSubcommand;
+use buck2_audit::deferred_materializer::debug_rpc::DebugRpc;
+use buck2_audit::deferred_materializer::debug_rpc::FlushAccessTimesResponse;
+use buck2_audit::deferred_materializer::debug_rpc::FlushAccessTimesRpc;
+use buck2_audit::deferred_materializer::debug_rpc::FsckError;
+use buck2_audit::deferred_materializer::debug_rpc::FsckResponse;
+use buck2_audit::deferred_materializer::debug_rpc::FsckRpc;
+use buck2_audit::deferred_materializer::debug_rpc::GetRefreshLogRequest;
+use buck2_audit::deferred_materializer::debug_rpc::GetRefreshLogResponse;
+use buck2_audit::deferred_materializer::debug_rpc::GetRefreshLogRpc;
+use buck2_audit::deferred_materializer::debug_rpc::ListRpc;
+use buck2_audit::deferred_materializer::debug_rpc::ListSubscriptionsResponse;
+use buck2_audit::deferred_materializer::debug_rpc::ListSubscriptionsRpc;
+use buck2_audit::deferred_materializer::debug_rpc::ListedArtifact;
+use buck2_audit::deferred_materializer::debug_rpc::ListedDep;
+use buck2_audit::deferred_materializer::debug_rpc::PendingCommand;
+use buck2_audit::deferred_materializer::debug_rpc::PendingResponse;
+use buck2_audit::deferred_materializer::debug_rpc::PendingRpc;
+use buck2_audit::deferred_materializer::debug_rpc::RefreshLogEntry;
+use buck2_audit::deferred_materializer::debug_rpc::RefreshOutcome;
+use buck2_audit::deferred_materializer::debug_rpc::RefreshRequest;
+use buck2_audit::deferred_materializer::debug_rpc::RefreshResponse;
+use buck2_audit::deferred_materializer::debug_rpc::RefreshRpc;
+use buck2_audit::deferred_materializer::debug_rpc::StatsResponse;
+use buck2_audit::deferred_materializer::debug_rpc::StatsRpc;
+use buck2_audit::deferred_materializer::debug_rpc::TestIterRequest;
+use buck2_audit::deferred_materializer::debug_rpc::TestIterResponse;
+use buck2_audit::deferred_materializer::debug_rpc::TestIterRpc;
+use buck2_audit::deferred_materializer::debug_rpc::encode_response;
 use buck2_cli_proto::ClientContext;
 use buck2_error::BuckErrorContext;
 use buck2_execute::materialize::materializer::DeferredMaterializerIterItem;
@@ -21,6 +50,23 @@ use futures::stream::StreamExt;
 
 use crate::ServerAuditSubcommand;
 
+/// Writes `response` as a `DebugRpcEnvelope` with `--json`, and as text otherwise.
+fn write_response<R: DebugRpc>(
+    json: bool,
+    stdout: &mut impl Write,
+    response: &R::Response,
+) -> buck2_error::Result<()>
+where
+    R::Response: Display,
+{
+    if json {
+        writeln!(stdout, "{}", encode_response::<R>(response)?)?;
+    } else {
+        write!(stdout, "{}", response)?;
+    }
+    Ok(())
+}
+
 #[async_trait]
 impl ServerAuditSubcommand for DeferredMaterializerCommand {
     async fn server_execute(
@@ -36,78 +82,153 @@ impl ServerAuditSubcommand for DeferredMaterializerCommand {
             .as_deferred_materializer_extension()
             .buck_error_context("Deferred materializer is not in use")?;
 
-        match self.subcommand {
-            DeferredMaterializerSubcommand::List => {
+        match &self.subcommand {
+            DeferredMaterializerSubcommand::List(_) => {
                 let mut stream = deferred_materializer
                     .iterate()
                     .buck_error_context("Failed to start iterating")?;
 
+                // There can be a lot of artifacts, so they're written as they come.
                 while let Some(DeferredMaterializerIterItem {
                     artifact_path,
                     artifact_display,
                     deps,
                 }) = stream.next().await
                 {
-                    writeln!(stdout, "{artifact_path}\t{artifact_display}")?;
-                    writeln!(stdout, "  deps: {}", deps.len())?;
-                    for (dep_path, dep_kind) in deps {
-                        writeln!(stdout, "    {dep_path} {dep_kind}")?;
-                    }
+                    let artifact = ListedArtifact {
+                        path: artifact_path.to_string(),
+                        state: artifact_display.to_string(),
+                        deps: deps
+                            .into_iter()
+                            .map(|(dep_path, dep_kind)| ListedDep {
+                                path: dep_path.to_string(),
+                                kind: dep_kind.to_owned(),
+                            })
+                            .collect(),
+                    };
+                    write_response::<ListRpc>(self.json, &mut stdout, &artifact)?;
                 }
             }
-            DeferredMaterializerSubcommand::ListSubscriptions => {
-                let mut stream = deferred_materializer
+            DeferredMaterializerSubcommand::ListSubscriptions(_) => {
+                let stream = deferred_materializer
                     .list_subscriptions()
                     .buck_error_context("Failed to start listing subscriptions")?;
 
-                while let Some(path) = stream.next().await {
-                    writeln!(stdout, "{}", path)?;
-                }
+                let paths = stream.map(|path| path.to_string()).collect().await;
+                write_response::<ListSubscriptionsRpc>(
+                    self.json,
+                    &mut stdout,
+                    &ListSubscriptionsResponse { paths },
+                )?;
             }
-            DeferredMaterializerSubcommand::Fsck => {
-                let mut stream = deferred_materializer
+            DeferredMaterializerSubcommand::Fsck(_) => {
+                let stream = deferred_materializer
                     .fsck()
                     .buck_error_context("Failed to start iterating")?;
 
-                let mut n = 0;
-
-                while let Some((path, error)) = stream.next().await {
-                    n += 1;
-                    writeln!(stdout, "{}\t{:#}", path, error)?;
-                }
+                let errors: Vec<_> = stream
+                    .map(|(path, error)| FsckError {
+                        path: path.to_string(),
+                        error: format!("{:#}", error),
+                    })
+                    .collect()
+                    .await;
+                let n = errors.len();
+                write_response::<FsckRpc>(self.json, &mut stdout, &FsckResponse { errors })?;
 
                 let mut stderr = server_ctx.stderr()?;
                 writeln!(&mut stderr, "total errors: {}", n)?;
             }
-            DeferredMaterializerSubcommand::Refresh { min_ttl } => {
+            DeferredMaterializerSubcommand::Refresh(RefreshRequest { min_ttl }) => {
                 deferred_materializer
-                    .refresh_ttls(min_ttl)
+                    .refresh_ttls(*min_ttl)
                     .await
                     .buck_error_context("Failed to refresh")?;
+
+                write_response::<RefreshRpc>(self.json, &mut stdout, &RefreshResponse {})?;
             }
-            DeferredMaterializerSubcommand::GetRefreshLog => {
-                let text = deferred_materializer
-                    .get_ttl_refresh_log()
+            DeferredMaterializerSubcommand::GetRefreshLog(GetRefreshLogRequest { last }) => {
+                let log = deferred_materializer
+                    .get_ttl_refresh_log(*last)
                     .await
                     .buck_error_context("Failed to get_ttl_refresh_log")?;
 
-                write!(stdout, "{}", text)?;
+                let entries = log
+                    .into_iter()
+                    .map(|entry| RefreshLogEntry {
+                        at: entry.at.to_rfc3339(),
+                        outcome: match entry.outcome {
+                            None => RefreshOutcome::Skipped,
+                            Some(Ok(())) => RefreshOutcome::Ok,
+                            Some(Err(e)) => RefreshOutcome::Error(e),
+                        },
+                    })
+                    .collect();
+                write_response::<GetRefreshLogRpc>(
+                    self.json,
+                    &mut stdout,
+                    &GetRefreshLogResponse { entries },
+                )?;
             }
-            DeferredMaterializerSubcommand::TestIter { count } => {
-                let text = deferred_materializer
-                    .test_iter(count)
+            DeferredMaterializerSubcommand::TestIter(TestIterRequest { count }) => {
+                let timings = deferred_materializer
+                    .test_iter(*count)
                     .await
                     .buck_error_context("Failed to test_iter")?;
 
-                write!(stdout, "{}", text)?;
+                write_response::<TestIterRpc>(
+                    self.json,
+                    &mut stdout,
+                    &TestIterResponse {
+                        count: *count,
+                        iter: timings.iter,
+                        iter_with_paths: timings.iter_with_paths,
+                    },
+                )?;
             }
-            DeferredMaterializerSubcommand::FlushAccessTimes => {
-                let text = deferred_materializer
+            DeferredMaterializerSubcommand::FlushAccessTimes(_) => {
+                let message = deferred_materializer
                     .flush_all_access_times()
                     .await
                     .buck_error_context("Failed to flush all access times")?;
 
-                write!(stdout, "{}", text)?;
+                write_response::<FlushAccessTimesRpc>(
+                    self.json,
+                    &mut stdout,
+                    &FlushAccessTimesResponse { message },
+                )?;
+            }
+            DeferredMaterializerSubcommand::Pending(_) => {
+                let commands = deferred_materializer
+                    .pending_by_trace()
+                    .await
+                    .buck_error_context("Failed to get pending materializations")?
+                    .into_iter()
+                    .map(|(trace_id, pending)| PendingCommand {
+                        trace_id: trace_id.to_string(),
+                        pending,
+                    })
+                    .collect();
+
+                write_response::<PendingRpc>(
+                    self.json,
+                    &mut stdout,
+                    &PendingResponse { commands },
+                )?;
+            }
+            DeferredMaterializerSubcommand::Stats(_) => {
+                let artifact_tree_bytes = deferred_materializer
+                    .measure_artifact_tree()
+                    .await
+                    .buck_error_context("Failed to measure the artifact tree")?;
+
+                write_response::<StatsRpc>(
+                    self.json,
+                    &mut stdout,
+                    &StatsResponse {
+                        artifact_tree_bytes,
+                    },
+                )?;
             }
         }
 
@@ -0,0 +1,464 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Estimates how much of the configured graph the next build would redo, by asking DICE which
+//! of the values it already has are still valid. Nothing is configured or analyzed.
+
+use std::collections::BTreeMap;
+use std::collections::HashSet;
+use std::io::Write;
+
+use async_trait::async_trait;
+use buck2_analysis::analysis::calculation::AnalysisKey;
+use buck2_audit::dirty_targets::AuditDirtyTargetsCommand;
+use buck2_cli_proto::ClientContext;
+use buck2_configured::nodes::ConfiguredTargetNodeKey;
+use buck2_core::configuration::compatibility::MaybeCompatible;
+use buck2_core::target::configured_target_label::ConfiguredTargetLabel;
+use buck2_server_ctx::ctx::ServerCommandContextTrait;
+use buck2_server_ctx::ctx::ServerCommandDiceContext;
+use buck2_server_ctx::partial_result_dispatcher::PartialResultDispatcher;
+use dice::DiceComputations;
+use dice::DiceKeyValidity;
+use dice::Key;
+use dupe::Dupe;
+use futures::future::join_all;
+use serde::Serialize;
+
+use crate::ServerAuditSubcommand;
+use crate::common::configured_target_labels::audit_command_configured_target_labels;
+
+const CAVEATS: &[&str] = &[
+    "Only changes the file watcher reported when this command started are taken into account.",
+    "A target is dirty when something it depends on changed. Rebuilding may find that the changed inputs produce equal values, so fewer targets may actually be redone.",
+    "The deps of a dirty target are taken from its last configured node, so deps added or removed by the change are not reflected.",
+    "Targets that were never configured in this daemon (or were evicted) are reported as not cached, and their deps are not checked.",
+    "Resolving the target patterns evaluates the build files of the requested packages.",
+];
+
+#[async_trait]
+impl ServerAuditSubcommand for AuditDirtyTargetsCommand {
+    async fn server_execute(
+        &self,
+        server_ctx: &dyn ServerCommandContextTrait,
+        mut stdout: PartialResultDispatcher<buck2_cli_proto::StdoutBytes>,
+        _client_ctx: ClientContext,
+    ) -> buck2_error::Result<()> {
+        server_ctx
+            .with_dice_ctx(|server_ctx, mut ctx| async move {
+                let targets = audit_command_configured_target_labels(
+                    &mut ctx,
+                    &self.patterns,
+                    &self.target_cfg,
+                    server_ctx,
+                )
+                .await?;
+
+                let top_level_targets = targets.len();
+                let states = target_states(&ctx, targets).await;
+                let report = DirtyTargetsReport::new(top_level_targets, &states, self.sample);
+
+                let mut stdout = stdout.as_writer();
+                if self.json {
+                    writeln!(stdout, "{}", serde_json::to_string_pretty(&report)?)?;
+                } else {
+                    report.write_human(&mut stdout)?;
+                }
+                Ok(())
+            })
+            .await
+    }
+}
+
+/// Where a key stands relative to the values DICE has cached.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum KeyState {
+    Valid,
+    /// Cached, but something it depends on has changed since.
+    Dirty,
+    /// Not cached at all.
+    Missing,
+}
+
+impl KeyState {
+    fn of<V>(validity: &DiceKeyValidity<V>) -> Self {
+        match validity {
+            DiceKeyValidity::Valid(_) => KeyState::Valid,
+            DiceKeyValidity::Dirty(_) => KeyState::Dirty,
+            DiceKeyValidity::Missing => KeyState::Missing,
+        }
+    }
+
+    fn would_recompute(self) -> bool {
+        self != KeyState::Valid
+    }
+}
+
+/// Walk the graph below `roots` using only the values DICE already has, returning every key
+/// reached. The deps of a dirty key are taken from its last known value. A missing key has no
+/// value to take deps from, so the walk stops there.
+async fn walk_cached_graph<K: Key>(
+    ctx: &DiceComputations<'_>,
+    roots: impl IntoIterator<Item = K>,
+    deps: impl Fn(&K::Value) -> Vec<K>,
+) -> Vec<(K, DiceKeyValidity<K::Value>)> {
+    let mut seen = HashSet::new();
+    let mut layer: Vec<K> = roots
+        .into_iter()
+        .filter(|k| seen.insert(k.clone()))
+        .collect();
+    let mut res = Vec::new();
+
+    while !layer.is_empty() {
+        let validities = join_all(layer.iter().map(|k| ctx.peek_validity(k))).await;
+        let mut next = Vec::new();
+        for (key, validity) in layer.into_iter().zip(validities) {
+            if let Some(value) = validity.value() {
+                next.extend(deps(value).into_iter().filter(|k| seen.insert(k.clone())));
+            }
+            res.push((key, validity));
+        }
+        layer = next;
+    }
+
+    res
+}
+
+struct TargetState {
+    label: ConfiguredTargetLabel,
+    node: KeyState,
+    /// `None` for targets whose last known configured node is not compatible, which are not
+    /// analyzed.
+    analysis: Option<KeyState>,
+}
+
+async fn target_states(
+    ctx: &DiceComputations<'_>,
+    targets: Vec<ConfiguredTargetLabel>,
+) -> Vec<TargetState> {
+    let nodes = walk_cached_graph(
+        ctx,
+        targets.into_iter().map(ConfiguredTargetNodeKey),
+        |node| match node {
+            Ok(MaybeCompatible::Compatible(node)) => node
+                .deps()
+                .map(|dep| ConfiguredTargetNodeKey(dep.label().dupe()))
+                .collect(),
+            _ => Vec::new(),
+        },
+    )
+    .await;
+
+    join_all(nodes.into_iter().map(|(key, node)| async move {
+        let analysis = match node.value() {
+            Some(Ok(MaybeCompatible::Compatible(_))) => Some(KeyState::of(
+                &ctx.peek_validity(&AnalysisKey(key.0.dupe())).await,
+            )),
+            Some(_) => None,
+            // A target that is configured from scratch is analyzed from scratch too.
+            None => Some(KeyState::Missing),
+        };
+        TargetState {
+            label: key.0,
+            node: KeyState::of(&node),
+            analysis,
+        }
+    }))
+    .await
+}
+
+#[derive(Default, Serialize)]
+struct PackageCounts {
+    reconfigure: u64,
+    reanalyze: u64,
+}
+
+#[derive(Serialize)]
+struct DirtyTarget {
+    label: String,
+    configured_node: KeyState,
+    analysis: Option<KeyState>,
+}
+
+#[derive(Serialize)]
+struct DirtyTargetsReport {
+    top_level_targets: usize,
+    /// Configured targets reached from the top-level targets through cached configured nodes.
+    checked_targets: usize,
+    /// Targets that would be reconfigured, re-analyzed or both.
+    dirty: u64,
+    reconfigure: u64,
+    reanalyze: u64,
+    /// Targets with no cached configured node, whose deps could not be checked.
+    not_cached: u64,
+    by_package: BTreeMap<String, PackageCounts>,
+    sample: Vec<DirtyTarget>,
+    caveats: &'static [&'static str],
+}
+
+impl DirtyTargetsReport {
+    fn new(top_level_targets: usize, states: &[TargetState], sample_size: usize) -> Self {
+        let mut report = DirtyTargetsReport {
+            top_level_targets,
+            checked_targets: states.len(),
+            dirty: 0,
+            reconfigure: 0,
+            reanalyze: 0,
+            not_cached: 0,
+            by_package: BTreeMap::new(),
+            sample: Vec::new(),
+            caveats: CAVEATS,
+        };
+
+        let mut dirty: Vec<&TargetState> = Vec::new();
+        for state in states {
+            let reconfigure = state.node.would_recompute();
+            let reanalyze = state.analysis.is_some_and(KeyState::would_recompute);
+            if !reconfigure && !reanalyze {
+                continue;
+            }
+            if state.node == KeyState::Missing {
+                report.not_cached += 1;
+            }
+            report.dirty += 1;
+            report.reconfigure += reconfigure as u64;
+            report.reanalyze += reanalyze as u64;
+
+            let counts = report
+                .by_package
+                .entry(state.label.pkg().to_string())
+                .or_default();
+            counts.reconfigure += reconfigure as u64;
+            counts.reanalyze += reanalyze as u64;
+            dirty.push(state);
+        }
+
+        dirty.sort_by_key(|state| state.label.to_string());
+        report.sample = dirty
+            .into_iter()
+            .take(sample_size)
+            .map(|state| DirtyTarget {
+                label: state.label.to_string(),
+                configured_node: state.node,
+                analysis: state.analysis,
+            })
+            .collect();
+
+        report
+    }
+
+    fn write_human(&self, mut out: impl Write) -> buck2_error::Result<()> {
+        writeln!(
+            out,
+            "Checked {} configured targets reachable from {} top-level targets:",
+            self.checked_targets, self.top_level_targets
+        )?;
+        writeln!(out, "  {} are dirty", self.dirty)?;
+        writeln!(out, "  {} would be reconfigured", self.reconfigure)?;
+        writeln!(out, "  {} would be re-analyzed", self.reanalyze)?;
+        writeln!(
+            out,
+            "  {} are not cached, their deps were not checked",
+            self.not_cached
+        )?;
+
+        if !self.by_package.is_empty() {
+            writeln!(out)?;
+            writeln!(out, "By package:")?;
+            for (package, counts) in &self.by_package {
+                writeln!(
+                    out,
+                    "  {}: {} reconfigured, {} re-analyzed",
+                    package, counts.reconfigure, counts.reanalyze
+                )?;
+            }
+        }
+
+        if !self.sample.is_empty() {
+            writeln!(out)?;
+            writeln!(
+                out,
+                "Dirty targets ({} of {}):",
+                self.sample.len(),
+                self.dirty
+            )?;
+            for target in &self.sample {
+                let mut what = Vec::new();
+                if target.configured_node.would_recompute() {
+                    what.push("reconfigure");
+                }
+                if target.analysis.is_some_and(KeyState::would_recompute) {
+                    what.push("re-analyze");
+                }
+                writeln!(out, "  {} ({})", target.label, what.join(", "))?;
+            }
+        }
+
+        writeln!(out)?;
+        writeln!(out, "Caveats:")?;
+        for caveat in self.caveats {
+            writeln!(out, "  - {}", caveat)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use allocative::Allocative;
+    use buck2_core::configuration::data::ConfigurationData;
+    use derive_more::Display;
+    use dice::CancellationContext;
+    use dice::DetectCycles;
+    use dice::Dice;
+    use dice::InjectedKey;
+
+    use super::*;
+
+    /// Each node depends on its own file and on the nodes listed here.
+    const GRAPH: &[(&str, &[&str])] = &[
+        ("app", &["lib", "util"]),
+        ("lib", &["base"]),
+        ("util", &["base"]),
+        ("base", &[]),
+        ("tool", &[]),
+    ];
+
+    #[derive(Clone, Dupe, Debug, Display, Eq, Hash, PartialEq, Allocative)]
+    struct File(&'static str);
+
+    impl InjectedKey for File {
+        type Value = u32;
+
+        fn equality(x: &Self::Value, y: &Self::Value) -> bool {
+            x == y
+        }
+    }
+
+    #[derive(Clone, Dupe, Debug, Display, Eq, Hash, PartialEq, Allocative)]
+    struct Node(&'static str);
+
+    #[async_trait]
+    impl Key for Node {
+        type Value = Arc<Vec<Node>>;
+
+        async fn compute(
+            &self,
+            ctx: &mut DiceComputations,
+            _cancellation: &CancellationContext,
+        ) -> Self::Value {
+            ctx.compute(&File(self.0)).await.unwrap();
+            let (_, deps) = GRAPH.iter().find(|(name, _)| *name == self.0).unwrap();
+            let deps: Vec<Node> = deps.iter().map(|dep| Node(*dep)).collect();
+            for dep in &deps {
+                ctx.compute(dep).await.unwrap();
+            }
+            Arc::new(deps)
+        }
+
+        fn equality(x: &Self::Value, y: &Self::Value) -> bool {
+            x == y
+        }
+    }
+
+    async fn walk(ctx: &DiceComputations<'_>) -> Vec<(&'static str, KeyState)> {
+        let mut res: Vec<_> =
+            walk_cached_graph(ctx, [Node("app"), Node("tool")], |deps| deps.to_vec())
+                .await
+                .into_iter()
+                .map(|(node, validity)| (node.0, KeyState::of(&validity)))
+                .collect();
+        res.sort_by_key(|(name, _)| *name);
+        res
+    }
+
+    #[tokio::test]
+    async fn test_walk_cached_graph() {
+        let dice = Dice::builder().build(DetectCycles::Enabled);
+        let mut updater = dice.updater();
+        updater
+            .changed_to(GRAPH.iter().map(|(name, _)| (File(*name), 0)))
+            .unwrap();
+        let mut ctx = updater.commit().await;
+
+        // Nothing is cached yet, so the walk can't get past the roots.
+        assert_eq!(
+            walk(&ctx).await,
+            vec![("app", KeyState::Missing), ("tool", KeyState::Missing)]
+        );
+
+        ctx.compute(&Node("app")).await.unwrap();
+        ctx.compute(&Node("tool")).await.unwrap();
+        assert!(
+            walk(&ctx)
+                .await
+                .iter()
+                .all(|(_, state)| *state == KeyState::Valid)
+        );
+
+        let mut updater = ctx.into_updater();
+        updater.changed_to([(File("lib"), 1)]).unwrap();
+        let ctx = updater.commit().await;
+
+        // Only the nodes that depend on the changed file are dirty, and the walk still reaches
+        // the deps of the dirty nodes.
+        assert_eq!(
+            walk(&ctx).await,
+            vec![
+                ("app", KeyState::Dirty),
+                ("base", KeyState::Valid),
+                ("lib", KeyState::Dirty),
+                ("tool", KeyState::Valid),
+                ("util", KeyState::Valid),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dirty_targets_report() {
+        let target = |label, node, analysis| TargetState {
+            label: ConfiguredTargetLabel::testing_parse(label, ConfigurationData::testing_new()),
+            node,
+            analysis,
+        };
+        let states = [
+            target("root//b:z", KeyState::Valid, Some(KeyState::Dirty)),
+            target("root//a:y", KeyState::Dirty, Some(KeyState::Dirty)),
+            target("root//a:x", KeyState::Valid, Some(KeyState::Valid)),
+            target("root//a:w", KeyState::Missing, Some(KeyState::Missing)),
+            target("root//c:v", KeyState::Dirty, None),
+        ];
+
+        let report = DirtyTargetsReport::new(2, &states, 2);
+        assert_eq!(report.checked_targets, 5);
+        assert_eq!(report.dirty, 4);
+        assert_eq!(report.reconfigure, 3);
+        assert_eq!(report.reanalyze, 3);
+        assert_eq!(report.not_cached, 1);
+        assert_eq!(
+            report
+                .by_package
+                .iter()
+                .map(|(package, counts)| (package.as_str(), counts.reconfigure, counts.reanalyze))
+                .collect::<Vec<_>>(),
+            vec![("root//a", 2, 2), ("root//b", 0, 1), ("root//c", 1, 0)]
+        );
+        assert_eq!(
+            report
+                .sample
+                .iter()
+                .map(|target| target.label.split(' ').next().unwrap())
+                .collect::<Vec<_>>(),
+            vec!["root//a:w", "root//a:y"]
+        );
+    }
+}
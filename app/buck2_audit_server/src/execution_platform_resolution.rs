@@ -12,6 +12,8 @@ use std::io::Write;
 use async_trait::async_trait;
 use buck2_audit::execution_platform_resolution::AuditExecutionPlatformResolutionCommand;
 use buck2_cli_proto::ClientContext;
+use buck2_core::execution_types::execution::ExecutionPlatformIncompatibleReason;
+use buck2_core::execution_types::execution::ExecutionPlatformResolution;
 use buck2_node::execution::EXECUTION_PLATFORMS_BUCKCONFIG;
 use buck2_node::execution::GetExecutionPlatforms;
 use buck2_node::nodes::configured_frontend::ConfiguredTargetNodeCalculation;
@@ -43,7 +45,8 @@ impl ServerAuditSubcommand for AuditExecutionPlatformResolutionCommand {
 
                 let mut stdout = stdout.as_writer();
 
-                match ctx.get_execution_platforms().await? {
+                let execution_platforms = ctx.get_execution_platforms().await?;
+                match &execution_platforms {
                     None => {
                         writeln!(
                             stdout,
@@ -91,13 +94,28 @@ impl ServerAuditSubcommand for AuditExecutionPlatformResolutionCommand {
                             for config_dep in configured_node.configuration_deps() {
                                 writeln!(stdout, "      {}", config_dep.label())?;
                             }
-                            for (label, reason) in resolution.skipped() {
-                                writeln!(stdout, "    Skipped {}", label)?;
-                                writeln!(IndentWriter::new("      ", &mut stdout), "{:#}", reason)?;
+                            if !self.explain {
+                                for (label, reason) in resolution.skipped() {
+                                    writeln!(stdout, "    Skipped {}", label)?;
+                                    writeln!(
+                                        IndentWriter::new("      ", &mut stdout),
+                                        "{:#}",
+                                        reason
+                                    )?;
+                                }
                             }
                         }
                         Err(e) => writeln!(stdout, "{}", e)?,
                     }
+                    if self.explain {
+                        if let Some(platforms) = &execution_platforms {
+                            write_candidates(
+                                &mut stdout,
+                                platforms.candidates().map(|c| c.id()),
+                                resolution,
+                            )?;
+                        }
+                    }
                 }
 
                 Ok(())
@@ -105,3 +123,159 @@ impl ServerAuditSubcommand for AuditExecutionPlatformResolutionCommand {
             .await?)
     }
 }
+
+/// Writes what happened to each candidate execution platform, in priority order: either it was
+/// selected, rejected (with everything that rejected it), or not checked because an earlier one
+/// was selected.
+fn write_candidates(
+    mut out: impl Write,
+    candidates: impl IntoIterator<Item = String>,
+    resolution: &ExecutionPlatformResolution,
+) -> buck2_error::Result<()> {
+    let selected = resolution.platform().ok().map(|p| p.id());
+    let mut found_selected = false;
+    writeln!(out, "  Candidates:")?;
+    for candidate in candidates {
+        if let Some((_, reason)) = resolution.skipped().iter().find(|(id, _)| *id == candidate) {
+            writeln!(out, "    {}: rejected", candidate)?;
+            write_rejection(&mut IndentWriter::new("      ", &mut out), reason)?;
+        } else if selected.as_ref() == Some(&candidate) {
+            writeln!(out, "    {}: selected", candidate)?;
+            found_selected = true;
+        } else if found_selected {
+            writeln!(out, "    {}: not checked", candidate)?;
+        }
+    }
+    if let Some(selected) = selected {
+        if !found_selected {
+            writeln!(out, "    {}: selected as fallback", selected)?;
+        }
+    }
+    Ok(())
+}
+
+/// One line per thing that rejected a platform, nested under the toolchain deps they come from.
+fn write_rejection(
+    out: &mut dyn Write,
+    reason: &ExecutionPlatformIncompatibleReason,
+) -> buck2_error::Result<()> {
+    match reason {
+        ExecutionPlatformIncompatibleReason::ConstraintNotSatisfied(constraint) => {
+            writeln!(out, "exec_compatible_with `{}` not satisfied", constraint)?;
+        }
+        ExecutionPlatformIncompatibleReason::ConstraintsNotSatisfied(constraints) => {
+            for constraint in constraints.iter() {
+                writeln!(out, "exec_compatible_with `{}` not satisfied", constraint)?;
+            }
+        }
+        ExecutionPlatformIncompatibleReason::ExecutionDependencyIncompatible(reason) => {
+            writeln!(out, "exec dep `{}` incompatible:", reason.target)?;
+            writeln!(IndentWriter::new("  ", &mut *out), "{:#}", reason)?;
+        }
+        ExecutionPlatformIncompatibleReason::ToolchainDependencyIncompatible(toolchain, reason) => {
+            writeln!(out, "toolchain dep `{}`:", toolchain)?;
+            write_rejection(&mut IndentWriter::new("  ", &mut *out), reason)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use buck2_core::configuration::compatibility::IncompatiblePlatformReason;
+    use buck2_core::configuration::compatibility::IncompatiblePlatformReasonCause;
+    use buck2_core::configuration::data::ConfigurationData;
+    use buck2_core::execution_types::execution::ExecutionPlatform;
+    use buck2_core::execution_types::executor_config::CommandExecutorConfig;
+    use buck2_core::provider::label::ProvidersLabel;
+    use buck2_core::target::label::label::TargetLabel;
+    use buck2_core::target::target_configured_target_label::TargetConfiguredTargetLabel;
+
+    use super::*;
+
+    fn constraint(label: &str) -> ProvidersLabel {
+        ProvidersLabel::default_for(TargetLabel::testing_parse(label))
+    }
+
+    #[test]
+    fn test_write_candidates() -> buck2_error::Result<()> {
+        let candidates = ["root//:p1", "root//:p2", "root//:p3", "root//:p4"];
+        let toolchain = TargetConfiguredTargetLabel::new_configure(
+            &TargetLabel::testing_parse("root//:toolchain"),
+            ConfigurationData::testing_new(),
+        );
+        let resolution = ExecutionPlatformResolution::new(
+            Some(ExecutionPlatform::platform(
+                TargetLabel::testing_parse("root//:p3"),
+                ConfigurationData::testing_new(),
+                CommandExecutorConfig::testing_local(),
+            )),
+            vec![
+                (
+                    "root//:p1".to_owned(),
+                    ExecutionPlatformIncompatibleReason::constraints_not_satisfied(vec![
+                        constraint("root//:linux"),
+                        constraint("root//:x86_64"),
+                    ]),
+                ),
+                (
+                    "root//:p2".to_owned(),
+                    ExecutionPlatformIncompatibleReason::ToolchainDependencyIncompatible(
+                        toolchain,
+                        Arc::new(ExecutionPlatformIncompatibleReason::ConstraintNotSatisfied(
+                            constraint("root//:has_compiler"),
+                        )),
+                    ),
+                ),
+            ],
+        );
+
+        let mut out = Vec::new();
+        write_candidates(&mut out, candidates.map(str::to_owned), &resolution)?;
+        assert_eq!(
+            format!(
+                "  Candidates:
+    root//:p1: rejected
+      exec_compatible_with `root//:linux` not satisfied
+      exec_compatible_with `root//:x86_64` not satisfied
+    root//:p2: rejected
+      toolchain dep `root//:toolchain ({cfg})`:
+        exec_compatible_with `root//:has_compiler` not satisfied
+    root//:p3: selected
+    root//:p4: not checked
+",
+                cfg = ConfigurationData::testing_new()
+            ),
+            String::from_utf8(out).unwrap()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_candidates_none_compatible() -> buck2_error::Result<()> {
+        let dep = Arc::new(IncompatiblePlatformReason {
+            target: TargetLabel::testing_parse("root//:tool")
+                .configure(ConfigurationData::testing_new()),
+            cause: IncompatiblePlatformReasonCause::UnsatisfiedConfig(constraint("root//:linux")),
+        });
+        let resolution = ExecutionPlatformResolution::new(
+            None,
+            vec![(
+                "root//:p1".to_owned(),
+                ExecutionPlatformIncompatibleReason::ExecutionDependencyIncompatible(dep),
+            )],
+        );
+
+        let mut out = Vec::new();
+        write_candidates(&mut out, ["root//:p1".to_owned()], &resolution)?;
+        let out = String::from_utf8(out).unwrap();
+        assert!(
+            out.contains("    root//:p1: rejected\n      exec dep `root//:tool ("),
+            "{out}"
+        );
+        assert!(out.contains("root//:linux unsatisfied"), "{out}");
+        Ok(())
+    }
+}
@@ -26,7 +26,9 @@ mod common;
 mod config;
 mod configurations;
 pub mod deferred_materializer;
+mod dep_file_coverage;
 mod dep_files;
+mod dirty_targets;
 mod execution_platform_resolution;
 mod includes;
 pub mod output;
@@ -94,11 +96,13 @@ impl AuditCommandExt for AuditCommand {
             AuditCommand::ExecutionPlatformResolution(cmd) => cmd,
             AuditCommand::Starlark(cmd) => cmd,
             AuditCommand::DepFiles(cmd) => cmd,
+            AuditCommand::DepFileCoverage(cmd) => cmd,
             AuditCommand::DeferredMaterializer(cmd) => cmd,
             AuditCommand::Visibility(cmd) => cmd,
             AuditCommand::Output(cmd) => cmd,
             AuditCommand::Parse(cmd) => cmd,
             AuditCommand::PackageValues(cmd) => cmd,
+            AuditCommand::DirtyTargets(cmd) => cmd,
             AuditCommand::Perf(cmd) => cmd,
         }
     }
@@ -7,11 +7,14 @@
  * of this source tree.
  */
 
+use std::io::Write;
+
 use async_trait::async_trait;
 use buck2_audit::visibility::AuditVisibilityCommand;
 use buck2_cli_proto::ClientContext;
 use buck2_common::pattern::parse_from_cli::parse_patterns_from_cli_args;
 use buck2_core::pattern::pattern_type::TargetPatternExtra;
+use buck2_error::BuckErrorContext;
 use buck2_node::load_patterns::MissingTargetBehavior;
 use buck2_node::load_patterns::load_patterns;
 use buck2_node::nodes::lookup::TargetNodeLookup;
@@ -68,9 +71,10 @@ async fn verify_visibility(
             match new_targets.get(dep) {
                 Some(val) => {
                     if !val.is_visible_to(target.label())? {
-                        visibility_errors.push(VisibilityError::NotVisibleTo(
+                        visibility_errors.push(VisibilityError::not_visible_to(
                             dep.dupe(),
                             target.label().dupe(),
+                            &val.explain_visibility(target.label())?,
                         ));
                     }
                 }
@@ -107,7 +111,7 @@ impl ServerAuditSubcommand for AuditVisibilityCommand {
     async fn server_execute(
         &self,
         server_ctx: &dyn ServerCommandContextTrait,
-        _stdout: PartialResultDispatcher<buck2_cli_proto::StdoutBytes>,
+        mut stdout: PartialResultDispatcher<buck2_cli_proto::StdoutBytes>,
         _client_ctx: ClientContext,
     ) -> buck2_error::Result<()> {
         Ok(server_ctx
@@ -128,7 +132,31 @@ impl ServerAuditSubcommand for AuditVisibilityCommand {
                     nodes.extend(res.values().map(|n| n.to_owned()));
                 }
 
-                verify_visibility(ctx, nodes).await?;
+                match &self.explain_to {
+                    Some(explain_to) => {
+                        let target = parse_patterns_from_cli_args::<TargetPatternExtra>(
+                            &mut ctx,
+                            &[explain_to.clone()],
+                            server_ctx.working_dir(),
+                        )
+                        .await?
+                        .into_iter()
+                        .next()
+                        .buck_error_context("Parsing patterns returned nothing")?
+                        .as_target_label(explain_to)?;
+
+                        let mut stdout = stdout.as_writer();
+                        for node in nodes.iter() {
+                            writeln!(
+                                stdout,
+                                "{}: {}",
+                                node.label(),
+                                node.explain_visibility(&target)?
+                            )?;
+                        }
+                    }
+                    None => verify_visibility(ctx, nodes).await?,
+                }
                 Ok(())
             })
             .await?)
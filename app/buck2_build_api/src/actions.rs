@@ -39,6 +39,7 @@ use buck2_core::category::CategoryRef;
 use buck2_core::content_hash::ContentBasedPathHash;
 use buck2_core::deferred::base_deferred_key::BaseDeferredKey;
 use buck2_core::execution_types::executor_config::CommandExecutorConfig;
+use buck2_core::execution_types::executor_config::RemoteExecutorUseCase;
 use buck2_core::fs::artifact_path_resolver::ArtifactFs;
 use buck2_core::fs::paths::forward_rel_path::ForwardRelativePathBuf;
 use buck2_events::dispatch::EventDispatcher;
@@ -279,6 +280,10 @@ pub trait ActionExecutionCtx: Send + Sync {
 
     fn re_platform(&self) -> &remote_execution::Platform;
 
+    /// The RE use case of the executor this action runs on, or the default one if it doesn't use
+    /// RE.
+    fn re_use_case(&self) -> RemoteExecutorUseCase;
+
     fn digest_config(&self) -> DigestConfig;
 
     /// Obtain per-command knobs for RunAction.
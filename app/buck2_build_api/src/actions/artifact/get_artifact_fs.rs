@@ -10,6 +10,7 @@
 use async_trait::async_trait;
 use buck2_common::dice::cells::HasCellResolver;
 use buck2_common::dice::data::HasIoProvider;
+use buck2_common::external_cells::get_resolved_git_commits;
 use buck2_core::fs::artifact_path_resolver::ArtifactFs;
 use dice::DiceComputations;
 use dupe::Dupe;
@@ -28,10 +29,12 @@ impl GetArtifactFs for DiceComputations<'_> {
         let buck_out_path_resolver = self.get_buck_out_path().await?;
         let project_filesystem = self.global_data().get_io_provider().project_root().dupe();
         let buck_path_resolver = self.get_cell_resolver().await?;
+        let resolved_git_commits = get_resolved_git_commits(self, &buck_path_resolver).await?;
         Ok(ArtifactFs::new(
             buck_path_resolver,
             buck_out_path_resolver,
             project_filesystem,
-        ))
+        )
+        .with_resolved_git_commits(resolved_git_commits))
     }
 }
@@ -23,6 +23,8 @@ use buck2_common::io::IoProvider;
 use buck2_common::liveliness_observer::NoopLivelinessObserver;
 use buck2_core::content_hash::ContentBasedPathHash;
 use buck2_core::execution_types::executor_config::CommandExecutorConfig;
+use buck2_core::execution_types::executor_config::Executor;
+use buck2_core::execution_types::executor_config::RemoteExecutorUseCase;
 use buck2_core::fs::artifact_path_resolver::ArtifactFs;
 use buck2_core::fs::buck_out_path::BuildArtifactPath;
 use buck2_error::BuckErrorContext;
@@ -252,6 +254,10 @@ impl HasActionExecutor for DiceComputations<'_> {
         let materializer = self.per_transaction_data().get_materializer();
         let events = self.per_transaction_data().get_dispatcher().dupe();
         let re_client = self.per_transaction_data().get_re_client();
+        let re_use_case = match &executor_config.executor {
+            Executor::RemoteEnabled(options) => options.re_use_case,
+            Executor::Local(..) => RemoteExecutorUseCase::buck2_default(),
+        };
         let run_action_knobs = self.per_transaction_data().get_run_action_knobs();
         let io_provider = self.global_data().get_io_provider();
         let http_client = self.per_transaction_data().get_http_client();
@@ -272,6 +278,7 @@ impl HasActionExecutor for DiceComputations<'_> {
             materializer,
             events,
             re_client,
+            re_use_case,
             digest_config,
             run_action_knobs,
             io_provider,
@@ -288,6 +295,7 @@ pub struct BuckActionExecutor {
     materializer: Arc<dyn Materializer>,
     events: EventDispatcher,
     re_client: UnconfiguredRemoteExecutionClient,
+    re_use_case: RemoteExecutorUseCase,
     digest_config: DigestConfig,
     run_action_knobs: RunActionKnobs,
     io_provider: Arc<dyn IoProvider>,
@@ -303,6 +311,7 @@ impl BuckActionExecutor {
         materializer: Arc<dyn Materializer>,
         events: EventDispatcher,
         re_client: UnconfiguredRemoteExecutionClient,
+        re_use_case: RemoteExecutorUseCase,
         digest_config: DigestConfig,
         run_action_knobs: RunActionKnobs,
         io_provider: Arc<dyn IoProvider>,
@@ -316,6 +325,7 @@ impl BuckActionExecutor {
             materializer,
             events,
             re_client,
+            re_use_case,
             digest_config,
             run_action_knobs,
             io_provider,
@@ -389,6 +399,10 @@ impl ActionExecutionCtx for BuckActionExecutionContext<'_> {
         self.executor.command_executor.re_platform()
     }
 
+    fn re_use_case(&self) -> RemoteExecutorUseCase {
+        self.executor.re_use_case
+    }
+
     fn digest_config(&self) -> DigestConfig {
         self.executor.digest_config
     }
@@ -758,6 +772,7 @@ mod tests {
     use buck2_core::execution_types::executor_config::CommandExecutorConfig;
     use buck2_core::execution_types::executor_config::CommandGenerationOptions;
     use buck2_core::execution_types::executor_config::PathSeparatorKind;
+    use buck2_core::execution_types::executor_config::RemoteExecutorUseCase;
     use buck2_core::fs::artifact_path_resolver::ArtifactFs;
     use buck2_core::fs::buck_out_path::BuckOutPathResolver;
     use buck2_core::fs::fs_util;
@@ -842,6 +857,7 @@ mod tests {
             Arc::new(NoDiskMaterializer),
             EventDispatcher::null(),
             UnconfiguredRemoteExecutionClient::testing_new_dummy(),
+            RemoteExecutorUseCase::buck2_default(),
             DigestConfig::testing_default(),
             Default::default(),
             Arc::new(FsIoProvider::new(
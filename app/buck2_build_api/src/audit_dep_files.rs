@@ -26,3 +26,8 @@ pub static AUDIT_DEP_FILES: LateBinding<
         &'a mut (dyn Write + Send),
     ) -> Pin<Box<dyn Future<Output = buck2_error::Result<()>> + Send + 'a>>,
 > = LateBinding::new("AUDIT_DEP_FILES");
+
+/// Implementation of `audit dep-file-coverage`.
+pub static AUDIT_DEP_FILE_COVERAGE: LateBinding<
+    fn(ConfiguredTargetLabel, &mut (dyn Write + Send)) -> buck2_error::Result<()>,
+> = LateBinding::new("AUDIT_DEP_FILE_COVERAGE");
@@ -541,6 +541,7 @@ impl<'a> BuildReportCollector<'a> {
                 .tags()
                 .into_iter()
                 .map(|tag| tag.as_str_name().to_owned())
+                .sorted()
                 .collect_vec();
 
             temp.push(ExpandedErrorInfo {
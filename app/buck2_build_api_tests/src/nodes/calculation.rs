@@ -11,8 +11,12 @@ use std::sync::Arc;
 
 use buck2_build_api::actions::execute::dice_data::set_fallback_executor_config;
 use buck2_configured::execution::ExecutionPlatformsKey;
+use buck2_configured::nodes::ConfiguredTargetNodeKey;
 use buck2_core::build_file_path::BuildFilePath;
 use buck2_core::bzl::ImportPath;
+use buck2_core::configuration::compatibility::IncompatiblePlatformReason;
+use buck2_core::configuration::compatibility::IncompatiblePlatformReasonCause;
+use buck2_core::configuration::compatibility::MaybeCompatible;
 use buck2_core::configuration::data::ConfigurationData;
 use buck2_core::execution_types::executor_config::CommandExecutorConfig;
 use buck2_core::fs::paths::file_name::FileNameBuf;
@@ -207,3 +211,83 @@ async fn test_get_node() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[tokio::test]
+async fn test_all_incompatible_exec_deps_reported() -> anyhow::Result<()> {
+    let cfg = ConfigurationData::testing_new();
+    let pkg = PackageLabel::testing_parse("cell//foo/bar");
+    let label = |name| TargetLabel::new(pkg.dupe(), TargetName::testing_new(name).as_ref());
+    let (label1, label2, label3) = (label("t1"), label("t2"), label("t3"));
+
+    let rule_type = RuleType::Starlark(Arc::new(StarlarkRuleType {
+        path: BzlOrBxlPath::Bzl(ImportPath::testing_new("cell//foo/bar:def.bzl")),
+        name: "some_rule".to_owned(),
+    }));
+    // Listed in reverse order, the reported incompatibilities are sorted.
+    let attrs1 = vec![(
+        "exec_deps",
+        Attribute::new(
+            None,
+            "",
+            AttrType::list(AttrType::exec_dep(ProviderIdSet::EMPTY)),
+        ),
+        CoercedAttr::List(ListLiteral(ArcSlice::new([
+            CoercedAttr::Dep(ProvidersLabel::default_for(label3.dupe())),
+            CoercedAttr::Dep(ProvidersLabel::default_for(label2.dupe())),
+        ]))),
+    )];
+    let node1 = TargetNode::testing_new(label1.dupe(), rule_type.dupe(), attrs1, None);
+
+    let eval_result = EvaluationResult::new(
+        Arc::new(BuildFilePath::new(
+            pkg.dupe(),
+            FileNameBuf::unchecked_new("BUCK"),
+        )),
+        Vec::new(),
+        SuperPackage::empty::<SuperPackageValuesImpl>()?,
+        TargetsMap::from_iter([node1]),
+    );
+
+    let incompatible = |label: &TargetLabel, constraint: &str| {
+        Arc::new(IncompatiblePlatformReason {
+            target: label.configure(cfg.dupe()),
+            cause: IncompatiblePlatformReasonCause::UnsatisfiedConfig(ProvidersLabel::default_for(
+                TargetLabel::testing_parse(constraint),
+            )),
+        })
+    };
+    let reason2 = incompatible(&label2, "cell//constraints:linux");
+    let reason3 = incompatible(&label3, "cell//constraints:arm64");
+
+    let mut data = UserComputationData::new();
+    set_fallback_executor_config(&mut data.data, CommandExecutorConfig::testing_local());
+    let computations = DiceBuilder::new()
+        .mock_and_return(InterpreterResultsKey(pkg), Ok(Arc::new(eval_result)))
+        .mock_and_return(ExecutionPlatformsKey, Ok(None))
+        .mock_and_return(
+            ConfiguredTargetNodeKey(label2.configure(cfg.dupe())),
+            Ok(MaybeCompatible::Incompatible(reason2.dupe())),
+        )
+        .mock_and_return(
+            ConfiguredTargetNodeKey(label3.configure(cfg.dupe())),
+            Ok(MaybeCompatible::Incompatible(reason3.dupe())),
+        )
+        .build(data)?;
+    let mut computations = computations.commit().await;
+
+    let node = computations
+        .get_internal_configured_target_node(&label1.configure(cfg.dupe()))
+        .await?;
+    let MaybeCompatible::Incompatible(reason) = node else {
+        panic!("expected `{}` to be incompatible", label1);
+    };
+    assert_eq!(
+        *reason,
+        IncompatiblePlatformReason {
+            target: label1.configure(cfg.dupe()),
+            cause: IncompatiblePlatformReasonCause::Dependencies(Arc::new([reason2, reason3])),
+        }
+    );
+
+    Ok(())
+}
@@ -36,7 +36,10 @@ fn main() -> io::Result<()> {
         .boxed("CommandProgress.progress.event")
         .boxed("CommandProgress.progress.result")
         .boxed("CommandProgress.progress.partial_result")
+        .boxed("CommandResult.original_result")
         .field_attribute("expires_at", "#[serde(with = \"serialize_timestamp\")]")
+        .field_attribute("walked_at", "#[serde(with = \"serialize_timestamp\")]")
+        .field_attribute("last_successful_sync", "#[serde(with = \"serialize_timestamp\")]")
         .extern_path(".buck.data", "::buck2_data")
         .extern_path(".buck.subscription", "::buck2_subscription_proto")
         .compile(proto_files, &includes)
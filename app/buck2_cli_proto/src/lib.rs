@@ -24,6 +24,15 @@ pub mod protobuf_util;
 
 tonic::include_proto!("buck.daemon");
 
+/// Bump this when a daemon can't serve the commands of clients built before a change, for example
+/// because the meaning of a request field changed. Clients send it in
+/// `ClientContext::client_compatibility_version`, and daemons reject commands from newer clients.
+pub const CLIENT_COMPATIBILITY_VERSION: u32 = 1;
+
+/// The oldest `CLIENT_COMPATIBILITY_VERSION` this daemon serves commands from. Raise it along with
+/// `CLIENT_COMPATIBILITY_VERSION` when older clients can't be served either.
+pub const MIN_CLIENT_COMPATIBILITY_VERSION: u32 = 1;
+
 #[derive(Debug, buck2_error::Error)]
 #[buck2(tag = Tier0)]
 enum BuckDaemonProtoError {
@@ -58,6 +67,15 @@ impl ConfigOverride {
         }
     }
 
+    /// `p` must be absolute.
+    pub fn external_file(p: &str) -> Self {
+        Self {
+            cell: None,
+            config_override: p.to_owned(),
+            config_type: crate::config_override::ConfigType::ExternalFile.into(),
+        }
+    }
+
     pub fn get_cell(&self) -> buck2_error::Result<Option<&CellRootPath>> {
         self.cell
             .as_ref()
@@ -307,11 +325,13 @@ result_convert!(AllocativeResponse);
 result_convert!(SubscriptionCommandResponse);
 result_convert!(TraceIoResponse);
 result_convert!(NewGenericResponseMessage);
+result_convert!(StreamingPingResponse);
 
 partial_result_convert!(StdoutBytes);
 partial_result_convert!(LspMessage);
 partial_result_convert!(SubscriptionResponseWrapper);
 partial_result_convert!(DapMessage);
+partial_result_convert!(PingResponse);
 
 define_request!(KillRequest);
 define_request!(StatusRequest);
@@ -331,6 +351,7 @@ define_request!(AllocativeRequest, has(context));
 define_request!(CleanStaleRequest, has(context));
 define_request!(FileStatusRequest, has(context));
 define_request!(TraceIoRequest, has(context));
+define_request!(StreamingPingRequest, has(context));
 define_request!(NewGenericRequestMessage, has(context));
 
 define_request!(InstallRequest, has(context, build_options));
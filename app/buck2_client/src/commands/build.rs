@@ -122,6 +122,22 @@ pub struct BuildCommand {
     )]
     output_path: Option<OutputDestinationArg>,
 
+    #[clap(
+        long,
+        value_name = "PATH",
+        help = "Write a JSON manifest of the default outputs (path, digest, size and executable bit) to this path. \
+            The digests come from the materializer's metadata, so all outputs must be materialized"
+    )]
+    output_manifest: Option<String>,
+
+    #[clap(
+        long,
+        value_name = "DIR",
+        help = "Copy the default outputs of all the built targets to this directory, keeping their path relative to buck-out. \
+            The copies are made by the daemon once the outputs are materialized"
+    )]
+    export_dir: Option<String>,
+
     #[clap(name = "TARGET_PATTERNS", help = "Patterns to build", value_hint = clap::ValueHint::Other)]
     patterns: Vec<String>,
 
@@ -226,9 +242,34 @@ pub fn print_build_result(
     for error in errors {
         console.print_error(&error.message)?;
     }
+    if let Some(hint) = errors.iter().find_map(cas_not_found_hint) {
+        console.print_warning(hint)?;
+    }
     Ok(())
 }
 
+/// Suggest rebuilding instead of reusing cached outputs, if `error` is about an artifact that
+/// wasn't found in the CAS.
+fn cas_not_found_hint(error: &buck2_data::ErrorReport) -> Option<&'static str> {
+    if error
+        .tags
+        .contains(&(buck2_error::ErrorTag::MaterializerCasExpired as i32))
+    {
+        Some(
+            "Hint: outputs of a cached action expired in the CAS. Re-run with `--no-remote-cache` to rebuild them.",
+        )
+    } else if error
+        .tags
+        .contains(&(buck2_error::ErrorTag::MaterializerCasNeverExisted as i32))
+    {
+        Some(
+            "Hint: the action cache returned outputs missing from the CAS. Re-run with `--no-remote-cache` to rebuild them.",
+        )
+    } else {
+        None
+    }
+}
+
 #[async_trait(?Send)]
 impl StreamingCommand for BuildCommand {
     const COMMAND_NAME: &'static str = "build";
@@ -265,6 +306,8 @@ impl StreamingCommand for BuildCommand {
                     final_artifact_uploads: self.upload_final_artifacts.to_proto() as i32,
                     target_universe: self.target_cfg.target_universe,
                     timeout: self.timeout_options.overall_timeout()?,
+                    output_manifest: self.output_manifest.clone(),
+                    export_dir: self.export_dir.clone(),
                 },
                 events_ctx,
                 ctx.console_interaction_stream(&self.common_opts.console_opts),
@@ -423,6 +466,36 @@ mod tests {
         )?)
     }
 
+    #[test]
+    fn test_cas_not_found_hint() {
+        let report = |tags: &[buck2_error::ErrorTag]| buck2_data::ErrorReport {
+            tags: tags.iter().map(|t| *t as i32).collect(),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            cas_not_found_hint(&report(&[
+                buck2_error::ErrorTag::MaterializationError,
+                buck2_error::ErrorTag::MaterializerCasExpired,
+            ])),
+            Some(
+                "Hint: outputs of a cached action expired in the CAS. Re-run with `--no-remote-cache` to rebuild them."
+            )
+        );
+        assert_eq!(
+            cas_not_found_hint(&report(&[
+                buck2_error::ErrorTag::MaterializerCasNeverExisted
+            ])),
+            Some(
+                "Hint: the action cache returned outputs missing from the CAS. Re-run with `--no-remote-cache` to rebuild them."
+            )
+        );
+        assert_eq!(
+            cas_not_found_hint(&report(&[buck2_error::ErrorTag::MaterializationError])),
+            None
+        );
+    }
+
     #[test]
     fn infos_default() -> buck2_error::Result<()> {
         let opts = parse(&[])?;
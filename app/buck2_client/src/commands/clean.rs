@@ -68,6 +68,25 @@ the specified duration, without killing the daemon",
     #[clap(long = "tracked-only", requires = "stale")]
     tracked_only: bool,
 
+    /// Only clean artifacts owned by this cell. Can be repeated.
+    #[clap(long = "cell", value_name = "CELL", requires = "stale")]
+    cells: Vec<String>,
+
+    /// Stop once this much has been cleaned, e.g. `10GiB`. The least recently used artifacts are
+    /// cleaned first.
+    #[clap(long, value_name = "SIZE", requires = "stale")]
+    max_bytes_per_run: Option<bytesize::ByteSize>,
+
+    /// Stop once this many artifacts have been cleaned. The least recently used artifacts are
+    /// cleaned first.
+    #[clap(long, value_name = "N", requires = "stale")]
+    max_files_per_run: Option<u64>,
+
+    /// Never clean artifacts under this path, relative to the project root, regardless of their
+    /// age. Can be repeated.
+    #[clap(long = "keep-path", value_name = "PATH", requires = "stale")]
+    keep_paths: Vec<String>,
+
     /// Command doesn't need these flags, but they are used in mode files, so we need to keep them.
     #[clap(flatten)]
     _target_cfg: TargetCfgUnusedOptions,
@@ -84,6 +103,10 @@ impl CleanCommand {
                 keep_since_arg,
                 dry_run: self.dry_run,
                 tracked_only: self.tracked_only,
+                cells: self.cells,
+                max_bytes_per_run: self.max_bytes_per_run.map(|size| size.as_u64()),
+                max_files_per_run: self.max_files_per_run,
+                keep_paths: self.keep_paths,
             };
             ctx.exec(cmd, matches)
         } else {
@@ -129,7 +152,7 @@ impl BuckSubcommand for InnerCleanCommand {
         .await
         .with_buck_error_context(|| "Error locking buckd lifecycle.lock")?;
 
-        kill_command_impl(&lifecycle_lock, "`buck2 clean` was invoked").await?;
+        kill_command_impl(&lifecycle_lock, "`buck2 clean` was invoked", None).await?;
 
         clean(buck_out_dir, daemon_dir, console, Some(&lifecycle_lock))
             .await
@@ -40,6 +40,10 @@ pub struct CleanStaleCommand {
     pub keep_since_arg: KeepSinceArg,
     pub dry_run: bool,
     pub tracked_only: bool,
+    pub cells: Vec<String>,
+    pub max_bytes_per_run: Option<u64>,
+    pub max_files_per_run: Option<u64>,
+    pub keep_paths: Vec<String>,
 }
 
 /// Specifies the maximum age of artifacts to keep
@@ -83,6 +87,13 @@ fn format_result_stats(stats: buck2_data::CleanStaleStats) -> String {
         stats.untracked_artifact_count,
         bytesize::to_string(stats.untracked_bytes, true),
     );
+    if stats.kept_artifact_count > 0 {
+        output += &format!(
+            "Kept {} stale or untracked paths matching --keep-path ({})\n",
+            stats.kept_artifact_count,
+            bytesize::to_string(stats.kept_bytes, true),
+        );
+    }
     if stats.cleaned_artifact_count > 0 || stats.cleaned_bytes > 0 {
         output += &format!("Cleaned {} paths\n", stats.cleaned_artifact_count,);
         output += &format!(
@@ -141,6 +152,10 @@ impl StreamingCommand for CleanStaleCommand {
                     keep_since_time: keep_since_time.timestamp(),
                     dry_run: self.dry_run,
                     tracked_only: self.tracked_only,
+                    cells: self.cells,
+                    max_bytes_per_run: self.max_bytes_per_run,
+                    max_files_per_run: self.max_files_per_run,
+                    keep_paths: self.keep_paths,
                 },
                 events_ctx,
                 ctx.console_interaction_stream(&self.common_opts.console_opts),
@@ -151,6 +166,9 @@ impl StreamingCommand for CleanStaleCommand {
         if let Some(message) = response.message {
             buck2_client_ctx::eprintln!("{}", message)?;
         }
+        for path in &response.kept_paths {
+            buck2_client_ctx::eprintln!("Kept (matches --keep-path): {}", path)?;
+        }
         if let Some(stats) = response.stats {
             buck2_client_ctx::eprintln!("{}", format_result_stats(stats))?;
         }
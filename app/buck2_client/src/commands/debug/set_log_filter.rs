@@ -47,7 +47,7 @@ impl SetLogFilterCommand {
             )
             .await?;
 
-            buckd
+            let response = buckd
                 .with_flushing()
                 .set_log_filter(
                     &mut events_ctx,
@@ -59,7 +59,31 @@ impl SetLogFilterCommand {
                 )
                 .await?;
 
-            ExitResult::success()
+            // There is no forkserver on some platforms, so only fail if nothing was updated.
+            let mut updated = false;
+            for (process, update) in [
+                ("daemon", response.daemon),
+                ("forkserver", response.forkserver),
+            ] {
+                let Some(update) = update else {
+                    continue;
+                };
+                if update.success {
+                    updated = true;
+                } else {
+                    buck2_client_ctx::eprintln!(
+                        "Failed to update the {} log filter: {}",
+                        process,
+                        update.error.as_deref().unwrap_or("unknown error")
+                    )?;
+                }
+            }
+
+            if updated || (self.no_daemon && self.no_forkserver) {
+                ExitResult::success()
+            } else {
+                ExitResult::bail("Log filter was not updated")
+            }
         })
     }
 }
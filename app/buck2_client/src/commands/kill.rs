@@ -28,6 +28,21 @@ use buck2_error::BuckErrorContext;
 /// `buck2 clean` kills the buck2 daemon and also deletes the buck2 state files.
 #[derive(Debug, clap::Parser)]
 pub struct KillCommand {
+    /// Wait for commands currently running on the daemon to finish before shutting it down.
+    /// New commands are rejected while waiting.
+    #[clap(long)]
+    wait_for_drain: bool,
+
+    /// How long to wait for running commands to finish with `--wait-for-drain` before shutting
+    /// the daemon down anyway.
+    #[clap(
+        long,
+        value_name = "DURATION",
+        default_value = "5m",
+        requires = "wait_for_drain"
+    )]
+    drain_timeout: humantime::Duration,
+
     #[clap(flatten)]
     pub(crate) event_log_opts: CommonEventLogOptions,
 }
@@ -53,6 +68,7 @@ impl BuckSubcommand for KillCommand {
         buck2_client_ctx::daemon::client::kill::kill_command_impl(
             &lifecycle_lock,
             "`buck kill` was invoked",
+            self.wait_for_drain.then(|| self.drain_timeout.into()),
         )
         .await
         .into()
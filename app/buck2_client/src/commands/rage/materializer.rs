@@ -11,6 +11,8 @@ use async_trait::async_trait;
 use buck2_audit::AuditCommand;
 use buck2_audit::deferred_materializer::DeferredMaterializerCommand;
 use buck2_audit::deferred_materializer::DeferredMaterializerSubcommand;
+use buck2_audit::deferred_materializer::debug_rpc::FsckRequest;
+use buck2_audit::deferred_materializer::debug_rpc::ListRequest;
 use buck2_cli_proto::ClientContext;
 use buck2_client_ctx::command_outcome::CommandOutcome;
 use buck2_client_ctx::daemon::client::connect::BootstrapBuckdClient;
@@ -54,12 +56,13 @@ pub async fn upload_materializer_data(
                         _target_cfg: Default::default(),
                         subcommand: match materializer_data {
                             MaterializerRageUploadData::State => {
-                                DeferredMaterializerSubcommand::List
+                                DeferredMaterializerSubcommand::List(ListRequest {})
                             }
                             MaterializerRageUploadData::Fsck => {
-                                DeferredMaterializerSubcommand::Fsck
+                                DeferredMaterializerSubcommand::Fsck(FsckRequest {})
                             }
                         },
+                        json: false,
                     },
                 ))?,
             },
@@ -124,6 +124,8 @@ impl StreamingCommand for RunCommand {
                     final_artifact_uploads: Uploads::Never as i32,
                     target_universe: self.target_cfg.target_universe,
                     timeout: None, // TODO: maybe it shouild be supported here?
+                    output_manifest: None,
+                    export_dir: None,
                 },
                 events_ctx,
                 ctx.console_interaction_stream(&self.common_opts.console_opts),
@@ -41,6 +41,24 @@ pub struct StatusCommand {
     snapshot: bool,
     #[clap(long, help = "Enable printing status for all running buckd")]
     all: bool,
+    #[clap(
+        long,
+        help = "Whether to list the commands currently running on the daemon in the output."
+    )]
+    active_commands: bool,
+    #[clap(
+        long,
+        requires = "all",
+        help = "With --all, rank the daemons by the disk space used by their buck-out instead of printing their status."
+    )]
+    disk_usage: bool,
+    #[clap(
+        long,
+        value_name = "BYTES",
+        requires = "disk_usage",
+        help = "With --disk-usage, flag the daemons whose buck-out is larger than this."
+    )]
+    disk_usage_threshold: Option<u64>,
 }
 
 impl StatusCommand {
@@ -73,17 +91,30 @@ impl StatusCommand {
                 let mut statuses = Vec::new();
                 for dir in daemon_dirs {
                     if let Ok(bootstrap_client) = establish_connection_existing(&dir).await {
-                        statuses.push(process_status(
+                        statuses.push(
                             bootstrap_client
                                 .to_connector()
                                 .with_flushing()
-                                .status(&mut events_ctx, self.snapshot)
+                                .status(&mut events_ctx, self.snapshot, self.active_commands)
                                 .await?,
-                        )?);
+                        );
                     }
                 }
 
-                buck2_client_ctx::println!("{}", serde_json::to_string_pretty(&statuses)?)?;
+                let output = if self.disk_usage {
+                    disk_usage_report(
+                        statuses.iter().map(DaemonDiskUsage::from_status).collect(),
+                        self.disk_usage_threshold,
+                    )
+                } else {
+                    serde_json::Value::Array(
+                        statuses
+                            .into_iter()
+                            .map(|status| process_status(status, self.active_commands))
+                            .collect::<buck2_error::Result<_>>()?,
+                    )
+                };
+                buck2_client_ctx::println!("{}", serde_json::to_string_pretty(&output)?)?;
             } else {
                 match connect_buckd(
                     BuckdConnectConstraints::ExistingOnly,
@@ -100,8 +131,9 @@ impl StatusCommand {
                         let json_status = process_status(
                             client
                                 .with_flushing()
-                                .status(&mut events_ctx, self.snapshot)
+                                .status(&mut events_ctx, self.snapshot, self.active_commands)
                                 .await?,
+                            self.active_commands,
                         )?;
                         buck2_client_ctx::println!(
                             "{}",
@@ -132,7 +164,10 @@ fn duration_to_string(duration: Duration) -> String {
     format_duration(duration).to_string()
 }
 
-fn process_status(status: StatusResponse) -> buck2_error::Result<serde_json::Value> {
+fn process_status(
+    status: StatusResponse,
+    include_active_commands: bool,
+) -> buck2_error::Result<serde_json::Value> {
     let timestamp = match status.start_time {
         None => "unknown".to_owned(),
         Some(timestamp) => timestamp_to_string(timestamp.seconds as u64, timestamp.nanos as u32)?,
@@ -159,6 +194,56 @@ fn process_status(status: StatusResponse) -> buck2_error::Result<serde_json::Val
         "io_provider": status.io_provider,
     });
 
+    if include_active_commands {
+        let active_commands = status
+            .active_commands
+            .into_iter()
+            .map(|command| {
+                let start_time = match command.start_time {
+                    None => "unknown".to_owned(),
+                    Some(timestamp) => {
+                        timestamp_to_string(timestamp.seconds as u64, timestamp.nanos as u32)?
+                    }
+                };
+                Ok(serde_json::json!({
+                    "trace_id": command.trace_id,
+                    "command_name": command.command_name,
+                    "argv": command.argv,
+                    "argv_summary": command.argv_summary,
+                    "start_time": start_time,
+                }))
+            })
+            .collect::<buck2_error::Result<Vec<_>>>()?;
+        value["active_command_count"] = serde_json::to_value(active_commands.len())?;
+        value["active_commands"] = serde_json::Value::Array(active_commands);
+    }
+
+    if let Some(buck_out_usage) = status.buck_out_usage {
+        value["buck_out_bytes"] = serde_json::to_value(buck_out_usage.bytes)?;
+    }
+
+    if let Some(buck_out_layout) = status.buck_out_layout {
+        value["buck_out_layout"] = serde_json::to_value(buck_out_layout)?;
+    }
+
+    if let Some(file_watcher) = status.file_watcher {
+        let last_successful_sync = match file_watcher.last_successful_sync {
+            None => None,
+            Some(timestamp) => Some(timestamp_to_string(
+                timestamp.seconds as u64,
+                timestamp.nanos as u32,
+            )?),
+        };
+        value["file_watcher"] = serde_json::json!({
+            "provider": buck2_data::FileWatcherProvider::try_from(file_watcher.provider)
+                .map_or("unknown", |p| p.as_str_name()),
+            "last_successful_sync": last_successful_sync,
+            "pending_changes": file_watcher.pending_changes,
+            "failed_syncs_since_success": file_watcher.failed_syncs_since_success,
+            "wedged": file_watcher.wedged,
+        });
+    }
+
     if let Some(valid_working_directory) = status.valid_working_directory {
         value["valid_working_directory"] = serde_json::to_value(valid_working_directory)?;
     }
@@ -170,10 +255,65 @@ fn process_status(status: StatusResponse) -> buck2_error::Result<serde_json::Val
     Ok(value)
 }
 
+/// Disk space used by one daemon's buck-out.
+#[derive(Debug)]
+struct DaemonDiskUsage {
+    project_root: String,
+    isolation_dir: String,
+    /// `None` if the daemon has not measured its buck-out yet.
+    buck_out_bytes: Option<u64>,
+}
+
+impl DaemonDiskUsage {
+    fn from_status(status: &StatusResponse) -> Self {
+        Self {
+            project_root: status.project_root.clone(),
+            isolation_dir: status.isolation_dir.clone(),
+            buck_out_bytes: status.buck_out_usage.as_ref().map(|usage| usage.bytes),
+        }
+    }
+}
+
+/// Ranks daemons from the largest buck-out to the smallest, with the daemons that have not
+/// measured theirs yet last, and sums their usage.
+fn disk_usage_report(
+    mut usages: Vec<DaemonDiskUsage>,
+    threshold_bytes: Option<u64>,
+) -> serde_json::Value {
+    usages.sort_by(|a, b| {
+        b.buck_out_bytes
+            .cmp(&a.buck_out_bytes)
+            .then_with(|| a.project_root.cmp(&b.project_root))
+            .then_with(|| a.isolation_dir.cmp(&b.isolation_dir))
+    });
+    let total_buck_out_bytes: u64 = usages.iter().filter_map(|u| u.buck_out_bytes).sum();
+    let isolation_dirs = usages
+        .into_iter()
+        .map(|usage| {
+            let exceeds_threshold = match (usage.buck_out_bytes, threshold_bytes) {
+                (Some(bytes), Some(threshold)) => bytes > threshold,
+                _ => false,
+            };
+            serde_json::json!({
+                "project_root": usage.project_root,
+                "isolation_dir": usage.isolation_dir,
+                "buck_out_bytes": usage.buck_out_bytes,
+                "exceeds_threshold": exceeds_threshold,
+            })
+        })
+        .collect::<Vec<_>>();
+    serde_json::json!({
+        "total_buck_out_bytes": total_buck_out_bytes,
+        "isolation_dirs": isolation_dirs,
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use std::time::Duration;
 
+    use crate::commands::status::DaemonDiskUsage;
+    use crate::commands::status::disk_usage_report;
     use crate::commands::status::duration_to_string;
     use crate::commands::status::timestamp_to_string;
 
@@ -193,4 +333,67 @@ mod tests {
             duration_to_string(Duration::new(3600 + 120 + 3, 123456789))
         );
     }
+
+    fn usage(isolation_dir: &str, buck_out_bytes: Option<u64>) -> DaemonDiskUsage {
+        DaemonDiskUsage {
+            project_root: "/repo".to_owned(),
+            isolation_dir: isolation_dir.to_owned(),
+            buck_out_bytes,
+        }
+    }
+
+    #[test]
+    fn test_disk_usage_report() {
+        let report = disk_usage_report(
+            vec![
+                usage("v2", Some(300)),
+                usage("opt", None),
+                usage("dev", Some(5000)),
+                usage("asan", Some(1200)),
+            ],
+            Some(1000),
+        );
+        assert_eq!(
+            report,
+            serde_json::json!({
+                "total_buck_out_bytes": 6500,
+                "isolation_dirs": [
+                    {
+                        "project_root": "/repo",
+                        "isolation_dir": "dev",
+                        "buck_out_bytes": 5000,
+                        "exceeds_threshold": true,
+                    },
+                    {
+                        "project_root": "/repo",
+                        "isolation_dir": "asan",
+                        "buck_out_bytes": 1200,
+                        "exceeds_threshold": true,
+                    },
+                    {
+                        "project_root": "/repo",
+                        "isolation_dir": "v2",
+                        "buck_out_bytes": 300,
+                        "exceeds_threshold": false,
+                    },
+                    {
+                        "project_root": "/repo",
+                        "isolation_dir": "opt",
+                        "buck_out_bytes": null,
+                        "exceeds_threshold": false,
+                    },
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn test_disk_usage_report_without_threshold() {
+        let report = disk_usage_report(vec![usage("b", Some(10)), usage("a", Some(10))], None);
+        assert_eq!(report["total_buck_out_bytes"], 20);
+        // Ties are ranked by isolation dir.
+        assert_eq!(report["isolation_dirs"][0]["isolation_dir"], "a");
+        assert_eq!(report["isolation_dirs"][1]["isolation_dir"], "b");
+        assert_eq!(report["isolation_dirs"][0]["exceeds_threshold"], false);
+    }
 }
@@ -46,6 +46,7 @@ use crate::stdin::Stdin;
 use crate::streaming::StreamingCommand;
 use crate::subscribers::recorder::get_invocation_recorder;
 use crate::subscribers::subscribers::EventSubscribers;
+use crate::version::BuckVersion;
 
 pub struct ClientCommandContext<'a> {
     init: fbinit::FacebookInit,
@@ -248,6 +249,7 @@ impl<'a> ClientCommandContext<'a> {
             unstable_typecheck: starlark_opts.unstable_typecheck,
             skip_targets_with_duplicate_names: starlark_opts.skip_targets_with_duplicate_names,
             reuse_current_config: config_opts.reuse_current_config,
+            no_config_file_cache: config_opts.no_config_file_cache,
             sanitized_argv: cmd.sanitize_argv(self.argv.clone()).argv,
             exit_when_different_state: config_opts.exit_when_different_state,
             preemptible: match config_opts.preemptible {
@@ -265,6 +267,18 @@ impl<'a> ClientCommandContext<'a> {
                 .collect(),
             target_call_stacks: starlark_opts.target_call_stacks,
             representative_config_flags: arg_matches.get_representative_config_flags_by_source(),
+            fail_on_soft_errors: config_opts.fail_on_soft_errors,
+            allowed_soft_error_categories: if config_opts.fail_on_soft_errors {
+                self.immediate_config
+                    .allowed_soft_error_categories()?
+                    .iter()
+                    .chain(&config_opts.allow_soft_error)
+                    .cloned()
+                    .collect()
+            } else {
+                Vec::new()
+            },
+            ignore_wedged_file_watcher: config_opts.ignore_wedged_file_watcher,
             ..self.empty_client_context(cmd.logging_name())?
         })
     }
@@ -294,6 +308,7 @@ impl<'a> ClientCommandContext<'a> {
             skip_targets_with_duplicate_names: false,
             trace_id: format!("{}", self.trace_id),
             reuse_current_config: false,
+            no_config_file_cache: false,
             daemon_uuid: get_possibly_nested_invocation_daemon_uuid(),
             sanitized_argv: Vec::new(),
             argfiles: Vec::new(),
@@ -307,6 +322,11 @@ impl<'a> ClientCommandContext<'a> {
                 .collect(),
             preemptible: Default::default(),
             representative_config_flags: Vec::new(),
+            client_version: BuckVersion::get_version().to_owned(),
+            client_compatibility_version: buck2_cli_proto::CLIENT_COMPATIBILITY_VERSION,
+            fail_on_soft_errors: false,
+            allowed_soft_error_categories: Vec::new(),
+            ignore_wedged_file_watcher: false,
         })
     }
 
@@ -38,6 +38,7 @@ use buck2_common::argv::ArgFilePath;
 use buck2_common::argv::ExpandedArgSource;
 use buck2_common::argv::ExpandedArgv;
 use buck2_common::argv::FlagfileArgSource;
+use buck2_core::buck2_env;
 use buck2_core::fs::paths::abs_path::AbsPath;
 use buck2_core::fs::working_dir::AbsWorkingDir;
 use dupe::Dupe;
@@ -203,6 +204,14 @@ pub struct CommonBuildConfigurationOptions {
     #[clap(long)]
     pub reuse_current_config: bool,
 
+    /// Look up buckconfig files on disk rather than trusting what previous commands found.
+    ///
+    /// Buck2 remembers which buckconfig files are missing, so that it doesn't have to check
+    /// for them again on every command. Use this if a config file that was added outside of
+    /// the project (e.g. in your home directory) is not being picked up.
+    #[clap(long)]
+    pub no_config_file_cache: bool,
+
     /// Used for exiting a concurrent command when a different state is detected.
     #[clap(long)]
     pub exit_when_different_state: bool,
@@ -216,13 +225,32 @@ pub struct CommonBuildConfigurationOptions {
     /// first completes.
     #[clap(long, ignore_case = true, value_enum)]
     pub preemptible: Option<PreemptibleWhen>,
+
+    /// Fail the command if any soft error fired while it ran, even if it otherwise succeeded.
+    ///
+    /// Categories listed in `buck2.allowed_soft_errors` in the root buckconfig, or passed with
+    /// `--allow-soft-error`, don't fail the command.
+    #[clap(long)]
+    pub fail_on_soft_errors: bool,
+
+    /// Soft error category that doesn't fail the command with `--fail-on-soft-errors`.
+    #[clap(long, value_name = "CATEGORY", requires = "fail_on_soft_errors")]
+    pub allow_soft_error: Vec<String>,
+
+    /// Run the command even if the file watcher is wedged, i.e. no sync succeeded for a while.
+    ///
+    /// File changes may not be picked up then. A successful sync clears the wedged state.
+    #[clap(long)]
+    pub ignore_wedged_file_watcher: bool,
 }
 
 impl CommonBuildConfigurationOptions {
     /// Produces a single, ordered list of config overrides. A `ConfigOverride`
     /// represents either a file, passed via `--config-file`, or a config value,
     /// passed via `-c`/`--config`. The relative order of those are important,
-    /// hence they're merged into a single list.
+    /// hence they're merged into a single list. The file named by the
+    /// `BUCK2_EXTERNAL_BUCKCONFIG` environment variable comes last, it is
+    /// read with the global buckconfigs rather than overriding anything.
     pub fn config_overrides(
         &self,
         matches: BuckArgMatches<'_>,
@@ -301,7 +329,15 @@ impl CommonBuildConfigurationOptions {
         ordered_merged_configs.extend(config_values_args);
         ordered_merged_configs.sort_by(|(lhs_index, _), (rhs_index, _)| lhs_index.cmp(rhs_index));
 
-        Ok(ordered_merged_configs.into_map(|(_, config_arg)| config_arg))
+        let mut config_overrides = ordered_merged_configs.into_map(|(_, config_arg)| config_arg);
+        if let Some(file) = buck2_env!("BUCK2_EXTERNAL_BUCKCONFIG")?.filter(|f| !f.is_empty()) {
+            let abs_path = match AbsPath::new(file) {
+                Ok(p) => p.to_owned(),
+                Err(_) => cwd.resolve(Path::new(file)),
+            };
+            config_overrides.push(ConfigOverride::external_file(&abs_path.to_string()));
+        }
+        Ok(config_overrides)
     }
 
     pub fn host_platform_override(&self) -> HostPlatformOverride {
@@ -328,8 +364,12 @@ impl CommonBuildConfigurationOptions {
             fake_arch: None,
             fake_xcode_version: None,
             reuse_current_config: false,
+            no_config_file_cache: false,
             exit_when_different_state: false,
             preemptible: Some(PreemptibleWhen::Never),
+            fail_on_soft_errors: false,
+            allow_soft_error: vec![],
+            ignore_wedged_file_watcher: false,
         };
         &DEFAULT
     }
@@ -342,8 +382,12 @@ impl CommonBuildConfigurationOptions {
             fake_arch: None,
             fake_xcode_version: None,
             reuse_current_config: true,
+            no_config_file_cache: false,
             exit_when_different_state: false,
             preemptible: Some(PreemptibleWhen::Never),
+            fail_on_soft_errors: false,
+            allow_soft_error: vec![],
+            ignore_wedged_file_watcher: false,
         };
         &OPTS
     }
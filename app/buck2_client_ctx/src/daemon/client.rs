@@ -30,6 +30,7 @@ use futures::TryStreamExt;
 use futures::future::BoxFuture;
 use futures::pin_mut;
 use futures::stream;
+use prost::Message as _;
 use tonic::Request;
 use tonic::Status;
 use tonic::codegen::InterceptedService;
@@ -193,6 +194,17 @@ pub(crate) fn tonic_status_to_error(status: tonic::Status) -> buck2_error::Error
             tags.push(ErrorTag::GrpcResponseMessageTooLarge);
         }
     }
+    if status.code() == tonic::Code::FailedPrecondition && !status.details().is_empty() {
+        // See `check_client_compatibility` in the daemon.
+        if let Ok(details) = IncompatibleClientVersion::decode(status.details()) {
+            if details.restart_daemon {
+                tags.push(ErrorTag::DaemonIncompatibleClient);
+                return buck2_error::Error::from(status).tag(tags).context(
+                    "The buck2 daemon is incompatible with this client, run `buck2 kill` and your command again to restart it",
+                );
+            }
+        }
+    }
     buck2_error::Error::from(status).tag(tags)
 }
 
@@ -282,11 +294,15 @@ impl BuckdClient {
         &mut self,
         events_ctx: &mut EventsCtx,
         snapshot: bool,
+        include_active_commands: bool,
     ) -> buck2_error::Result<StatusResponse> {
         let outcome = events_ctx
             // Safe to unwrap tailers here because they are instantiated prior to a command being called.
             .unpack_oneshot(mem::take(&mut self.tailers), {
-                self.client.status(Request::new(StatusRequest { snapshot }))
+                self.client.status(Request::new(StatusRequest {
+                    snapshot,
+                    include_active_commands,
+                }))
             })
             .await;
         // TODO(nmj): We have a number of things that wish to use status() and return an buck2_error::Result,
@@ -305,10 +321,12 @@ impl BuckdClient {
         &mut self,
         _events_ctx: &mut EventsCtx,
         req: SetLogFilterRequest,
-    ) -> buck2_error::Result<()> {
-        self.client.set_log_filter(Request::new(req)).await?;
-
-        Ok(())
+    ) -> buck2_error::Result<SetLogFilterResponse> {
+        Ok(self
+            .client
+            .set_log_filter(Request::new(req))
+            .await?
+            .into_inner())
     }
 }
 
@@ -609,7 +627,10 @@ impl FlushingBuckdClient<'_> {
     );
 
     wrap_method!(status(snapshot: bool), StatusResponse);
-    wrap_method!(set_log_filter(log_filter: SetLogFilterRequest), ());
+    wrap_method!(
+        set_log_filter(log_filter: SetLogFilterRequest),
+        SetLogFilterResponse
+    );
     stream_method!(trace_io, TraceIoRequest, TraceIoResponse, NoPartialResult);
 
     pub async fn new_generic(
@@ -9,16 +9,14 @@
 
 use std::env;
 use std::ffi::OsStr;
-use std::fs::File;
-use std::io::BufReader;
 use std::net::Ipv4Addr;
 use std::path::PathBuf;
 use std::time::Duration;
 
-use buck2_cli_proto::DaemonProcessInfo;
 use buck2_cli_proto::daemon_api_client::DaemonApiClient;
 use buck2_common::buckd_connection::BUCK_AUTH_TOKEN_HEADER;
 use buck2_common::buckd_connection::ConnectionType;
+use buck2_common::buckd_info::BuckdInfo;
 use buck2_common::client_utils::RetryError;
 use buck2_common::client_utils::get_channel_tcp;
 use buck2_common::client_utils::get_channel_uds;
@@ -514,7 +512,7 @@ impl<'a> BuckdLifecycle<'a> {
 /// BootstrapBuckdClient by querying constraints. This is a separate step so that we retry
 /// establishing the channel but not querying constraints.
 pub struct BuckdChannel {
-    info: DaemonProcessInfo,
+    info: BuckdInfo,
     daemon_dir: DaemonDir,
     client: DaemonApiClient<InterceptedService<Channel, BuckAddAuthTokenInterceptor>>,
 }
@@ -544,7 +542,7 @@ impl BuckdChannel {
 /// Client used for connection setup. Can be used to create BuckdClientConnector instances later.
 #[derive(Clone)]
 pub struct BootstrapBuckdClient {
-    info: DaemonProcessInfo,
+    info: BuckdInfo,
     daemon_dir: DaemonDir,
     client: DaemonApiClient<InterceptedService<Channel, BuckAddAuthTokenInterceptor>>,
     /// The constraints for the daemon we're connected to.
@@ -591,18 +589,22 @@ impl BootstrapBuckdClient {
         }
     }
 
-    pub(crate) async fn kill(&mut self, reason: &str) -> buck2_error::Result<Pid> {
-        kill::kill(&mut self.client, &self.info, reason).await?;
-        Pid::from_i64(self.info.pid)
+    pub(crate) async fn kill(
+        &mut self,
+        reason: &str,
+        drain_timeout: Option<Duration>,
+    ) -> buck2_error::Result<Pid> {
+        kill::kill(&mut self.client, &self.info, reason, drain_timeout).await?;
+        self.info.pid()
     }
 
     async fn kill_for_constraints_mismatch(&mut self) -> buck2_error::Result<Pid> {
-        self.kill("client expected different buckd constraints")
+        self.kill("client expected different buckd constraints", None)
             .await
     }
 
     pub fn pid(&self) -> i64 {
-        self.info.pid
+        self.info.info().pid
     }
 }
 
@@ -938,7 +940,7 @@ async fn try_connect_existing(
 }
 
 pub struct BuckdProcessInfo<'a> {
-    pub(crate) info: DaemonProcessInfo,
+    pub(crate) info: BuckdInfo,
     daemon_dir: &'a DaemonDir,
 }
 
@@ -960,33 +962,22 @@ impl<'a> BuckdProcessInfo<'a> {
     }
 
     pub fn load_if_exists(daemon_dir: &'a DaemonDir) -> buck2_error::Result<Option<Self>> {
-        let location = daemon_dir.buckd_info();
-        let file = match File::open(&location) {
-            Ok(file) => file,
-            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
-            Err(e) => {
-                return Err(e).with_buck_error_context(|| {
-                    format!("Trying to open buckd info, `{}`", location.display())
-                });
-            }
-        };
-        let reader = BufReader::new(file);
-        let info = serde_json::from_reader(reader).with_buck_error_context(|| {
-            format!(
-                "Error parsing daemon info in `{}`. \
-                Try deleting that file and running `buck2 killall` before running your command again",
-                location.display(),
-            )
-        })?;
-
-        Ok(Some(BuckdProcessInfo { info, daemon_dir }))
+        Ok(
+            BuckdInfo::read_if_exists(daemon_dir)?
+                .map(|info| BuckdProcessInfo { info, daemon_dir }),
+        )
     }
 
     pub async fn create_channel(&self) -> buck2_error::Result<BuckdChannel> {
-        tracing::debug!("Creating channel to: {}", self.info.endpoint);
-        let connection_type = ConnectionType::parse(&self.info.endpoint)?;
+        let connection_type = self.info.endpoint().with_buck_error_context(|| {
+            format!(
+                "Error reading daemon info `{}`",
+                self.daemon_dir.buckd_info()
+            )
+        })?;
+        tracing::debug!("Creating channel to: {}", connection_type);
 
-        let client = new_daemon_api_client(connection_type, self.info.auth_token.clone())
+        let client = new_daemon_api_client(connection_type, self.info.auth_token().to_owned())
             .await
             .buck_error_context("Error connecting")?;
 
@@ -1002,7 +993,7 @@ impl<'a> BuckdProcessInfo<'a> {
     }
 
     pub fn pid(&self) -> buck2_error::Result<Pid> {
-        Pid::from_i64(self.info.pid)
+        self.info.pid()
     }
 }
 
@@ -1015,6 +1006,7 @@ async fn get_constraints(
         .unpack_oneshot(None, {
             client.status(tonic::Request::new(buck2_cli_proto::StatusRequest {
                 snapshot: false,
+                include_active_commands: false,
             }))
         })
         .await?;
@@ -12,6 +12,7 @@ use std::time::Instant;
 
 use buck2_cli_proto::daemon_api_client::*;
 use buck2_cli_proto::*;
+use buck2_common::buckd_info::BuckdInfo;
 use buck2_data::error::ErrorTag;
 use buck2_error::buck2_error;
 use buck2_wrapper_common::kill;
@@ -32,9 +33,12 @@ const GRACEFUL_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(4);
 const KILL_REQUEST_TIMEOUT: Duration = Duration::from_secs(3);
 const FORCE_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
 
+/// If `drain_timeout` is set, the daemon waits for running commands to finish, for at most that
+/// long, before shutting down.
 pub async fn kill_command_impl(
     lifecycle_lock: &BuckdLifecycleLock,
     reason: &str,
+    drain_timeout: Option<Duration>,
 ) -> buck2_error::Result<()> {
     let process = match BuckdProcessInfo::load(lifecycle_lock.daemon_dir()) {
         Ok(p) => p,
@@ -52,8 +56,12 @@ pub async fn kill_command_impl(
 
     let pid = match buckd {
         Ok(Ok(mut buckd)) => {
-            crate::eprintln!("killing buckd server")?;
-            Some(buckd.kill(reason).await?)
+            if drain_timeout.is_some() {
+                crate::eprintln!("waiting for buckd commands to finish before killing it")?;
+            } else {
+                crate::eprintln!("killing buckd server")?;
+            }
+            Some(buckd.kill(reason, drain_timeout).await?)
         }
         Ok(Err(e)) => {
             // No time out: we just errored out. This is likely indicative that there is no
@@ -102,10 +110,11 @@ pub async fn kill_command_impl(
 
 pub(crate) async fn kill(
     client: &mut DaemonApiClient<InterceptedService<Channel, BuckAddAuthTokenInterceptor>>,
-    info: &DaemonProcessInfo,
+    info: &BuckdInfo,
     reason: &str,
+    drain_timeout: Option<Duration>,
 ) -> buck2_error::Result<()> {
-    let pid = Pid::from_i64(info.pid)?;
+    let pid = info.pid()?;
     let callers = get_callers_for_kill();
 
     tracing::debug!("Killing daemon with PID {}", pid);
@@ -114,26 +123,43 @@ pub(crate) async fn kill(
         reason: reason.to_owned(),
         timeout: Some(GRACEFUL_SHUTDOWN_TIMEOUT.try_into()?),
         callers,
+        wait_for_drain: drain_timeout.is_some(),
+        drain_timeout: drain_timeout.map(TryInto::try_into).transpose()?,
     }));
-    let time_to_kill = GRACEFUL_SHUTDOWN_TIMEOUT + FORCE_SHUTDOWN_TIMEOUT;
+    // The daemon only replies once commands have drained.
+    let drain_timeout = drain_timeout.unwrap_or_default();
+    let graceful_shutdown_timeout = drain_timeout + GRACEFUL_SHUTDOWN_TIMEOUT;
+    let time_to_kill = graceful_shutdown_timeout + FORCE_SHUTDOWN_TIMEOUT;
     let time_req_sent = Instant::now();
     // First we send a Kill request
-    match tokio::time::timeout(KILL_REQUEST_TIMEOUT, request_fut).await {
+    match tokio::time::timeout(drain_timeout + KILL_REQUEST_TIMEOUT, request_fut).await {
         Ok(inner_result) => {
             match inner_result {
-                Ok(_) => loop {
-                    if !kill::process_exists(pid)? {
-                        return Ok(());
-                    }
-                    if time_req_sent.elapsed() > GRACEFUL_SHUTDOWN_TIMEOUT {
+                Ok(response) => {
+                    if let Some(command_result::Result::KillResponse(KillResponse {
+                        drained: Some(false),
+                    })) = &response.get_ref().result
+                    {
                         crate::eprintln!(
-                            "Timed out waiting for graceful shutdown of buck2 daemon pid {}",
-                            pid
+                            "Commands were still running on buck2 daemon pid {} after waiting {} for them to finish",
+                            pid,
+                            humantime::format_duration(drain_timeout)
                         )?;
-                        break;
                     }
-                    tokio::time::sleep(Duration::from_millis(100)).await;
-                },
+                    loop {
+                        if !kill::process_exists(pid)? {
+                            return Ok(());
+                        }
+                        if time_req_sent.elapsed() > graceful_shutdown_timeout {
+                            crate::eprintln!(
+                                "Timed out waiting for graceful shutdown of buck2 daemon pid {}",
+                                pid
+                            )?;
+                            break;
+                        }
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                    }
+                }
                 Err(e) => {
                     // The kill request can fail if the server is in a bad state and we cannot
                     // authenticate to it.
@@ -157,17 +183,17 @@ pub(crate) async fn kill(
     hard_kill_impl(pid, time_req_sent, time_to_kill).await
 }
 
-pub(crate) async fn hard_kill(info: &DaemonProcessInfo) -> buck2_error::Result<()> {
-    let pid = Pid::from_i64(info.pid)?;
+pub(crate) async fn hard_kill(info: &BuckdInfo) -> buck2_error::Result<()> {
+    let pid = info.pid()?;
 
     hard_kill_impl(pid, Instant::now(), FORCE_SHUTDOWN_TIMEOUT).await
 }
 
 pub(crate) async fn hard_kill_until(
-    info: &DaemonProcessInfo,
+    info: &BuckdInfo,
     deadline: Instant,
 ) -> buck2_error::Result<()> {
-    let pid = Pid::from_i64(info.pid)?;
+    let pid = info.pid()?;
 
     let now = Instant::now();
     hard_kill_impl(pid, now, deadline.saturating_duration_since(now)).await
@@ -25,6 +25,7 @@ use buck2_error::Tier;
 use buck2_error::classify::ErrorLike;
 use buck2_error::classify::best_error;
 use buck2_error::conversion::from_any_with_tag;
+use buck2_error::interop::ErrorExitCode;
 use buck2_wrapper_common::invocation_id::TraceId;
 
 #[derive(Debug)]
@@ -149,16 +150,12 @@ impl ExitResult {
     }
 
     pub fn err(err: buck2_error::Error) -> Self {
-        let exit_code = if err.has_tag(ErrorTag::IoClientBrokenPipe) {
-            ExitCode::BrokenPipe
-        } else {
-            match err.get_tier() {
-                Some(tier) => match tier {
-                    Tier::Input => ExitCode::UserError,
-                    Tier::Tier0 | Tier::Environment => ExitCode::InfraError,
-                },
-                None => ExitCode::UnknownFailure,
-            }
+        let exit_code = match err.exit_code_kind() {
+            ErrorExitCode::UnknownFailure => ExitCode::UnknownFailure,
+            ErrorExitCode::InfraError => ExitCode::InfraError,
+            ErrorExitCode::UserError => ExitCode::UserError,
+            ErrorExitCode::BrokenPipe => ExitCode::BrokenPipe,
+            ErrorExitCode::Suggested(exit_code) => ExitCode::ErrorSuggested(exit_code),
         };
 
         Self {
@@ -215,6 +212,14 @@ impl ExitResult {
             }
         }
 
+        if let Some(exit_code) = best_error(&errors)
+            .and_then(|error| error.suggested_exit_code)
+            .and_then(|exit_code| u8::try_from(exit_code).ok())
+            .filter(|exit_code| !buck2_error::is_reserved_exit_code(*exit_code))
+        {
+            return status_with_error_report(ExitCode::ErrorSuggested(exit_code), errors);
+        }
+
         match best_error(&errors).map(|error| error.category()) {
             Some(category) => match category {
                 Tier::Input => status_with_error_report(ExitCode::UserError, errors),
@@ -488,6 +493,8 @@ pub enum ExitCode {
     BrokenPipe,
     /// Test runner explicitly requested that this exit code be returned
     TestRunner(u8),
+    /// The error that ended the command requested this exit code
+    ErrorSuggested(u8),
 }
 
 impl ExitCode {
@@ -495,16 +502,17 @@ impl ExitCode {
         use ExitCode::*;
         match self {
             Success => 0,
-            UnknownFailure => 1,
-            InfraError => 2,
-            UserError => 3,
+            UnknownFailure => ErrorExitCode::UnknownFailure.code() as u32,
+            InfraError => ErrorExitCode::InfraError.code() as u32,
+            UserError => ErrorExitCode::UserError.code() as u32,
             DaemonIsBusy => 4,
             DaemonPreempted => 5,
             Timeout => 6,
             ConnectError => 11,
-            BrokenPipe => 130,
+            BrokenPipe => ErrorExitCode::BrokenPipe.code() as u32,
             SignalInterrupt => 141,
             TestRunner(code) => code as u32,
+            ErrorSuggested(code) => code as u32,
         }
     }
 
@@ -522,6 +530,7 @@ impl ExitCode {
             BrokenPipe => "BROKEN_PIPE",
             SignalInterrupt => "SIGNAL_INTERRUPT",
             TestRunner(_) => "TEST_RUNNER",
+            ErrorSuggested(_) => "ERROR_SUGGESTED",
         }
     }
 }
@@ -16,6 +16,7 @@ use buck2_common::init::DaemonStartupConfig;
 use buck2_common::invocation_roots::InvocationRoots;
 use buck2_common::invocation_roots::find_invocation_roots;
 use buck2_common::legacy_configs::cells::BuckConfigBasedCells;
+use buck2_common::legacy_configs::key::BuckconfigKeyRef;
 use buck2_core::buck2_env;
 use buck2_core::cells::CellAliasResolver;
 use buck2_core::cells::CellResolver;
@@ -35,6 +36,7 @@ struct ImmediateConfig {
     cell_resolver: CellResolver,
     cwd_cell_alias_resolver: CellAliasResolver,
     daemon_startup_config: DaemonStartupConfig,
+    allowed_soft_error_categories: Vec<String>,
 }
 
 impl ImmediateConfig {
@@ -57,6 +59,21 @@ impl ImmediateConfig {
             cwd_cell_alias_resolver,
             daemon_startup_config: DaemonStartupConfig::new(&cells.root_config)
                 .buck_error_context("Error loading daemon startup config")?,
+            allowed_soft_error_categories: cells
+                .root_config
+                .get(BuckconfigKeyRef {
+                    section: "buck2",
+                    property: "allowed_soft_errors",
+                })
+                .map(|categories| {
+                    categories
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|category| !category.is_empty())
+                        .map(ToOwned::to_owned)
+                        .collect()
+                })
+                .unwrap_or_default(),
         })
     }
 }
@@ -67,6 +84,7 @@ struct ImmediateConfigContextData {
     cell_resolver: CellResolver,
     cwd_cell_alias_resolver: CellAliasResolver,
     daemon_startup_config: DaemonStartupConfig,
+    allowed_soft_error_categories: Vec<String>,
     project_filesystem: ProjectRoot,
 }
 
@@ -102,6 +120,12 @@ impl<'a> ImmediateConfigContext<'a> {
         Ok(&self.data()?.daemon_startup_config)
     }
 
+    /// Soft error categories from `buck2.allowed_soft_errors`, which don't fail commands run with
+    /// `--fail-on-soft-errors`.
+    pub(crate) fn allowed_soft_error_categories(&self) -> buck2_error::Result<&[String]> {
+        Ok(&self.data()?.allowed_soft_error_categories)
+    }
+
     /// Resolves a cell path (i.e., contains `//`) into an absolute path. The cell path must have
     /// been split into two components: `cell_alias` and `cell_path`. For example, if the cell path
     /// is `cell//path/to/file`, then:
@@ -169,6 +193,7 @@ impl<'a> ImmediateConfigContext<'a> {
                     cell_resolver: cfg.cell_resolver,
                     cwd_cell_alias_resolver: cfg.cwd_cell_alias_resolver,
                     daemon_startup_config,
+                    allowed_soft_error_categories: cfg.allowed_soft_error_categories,
                     project_filesystem: roots.project_root,
                 })
             })
@@ -158,6 +158,7 @@ pub(crate) struct InvocationRecorder {
     initial_sink_bytes_written: Option<u64>,
     sink_max_buffer_depth: u64,
     soft_error_categories: HashSet<SoftError>,
+    soft_error_escalation_count: u64,
     concurrent_command_blocking_duration: Option<Duration>,
     metadata: HashMap<String, String>,
     analysis_count: u64,
@@ -313,6 +314,7 @@ impl InvocationRecorder {
             initial_sink_bytes_written: None,
             sink_max_buffer_depth: 0,
             soft_error_categories: HashSet::new(),
+            soft_error_escalation_count: 0,
             concurrent_command_blocking_duration: None,
             metadata: buck2_events::metadata::collect(),
             analysis_count: 0,
@@ -768,6 +770,7 @@ impl InvocationRecorder {
             soft_error_categories: std::mem::take(&mut self.soft_error_categories)
                 .into_iter()
                 .collect(),
+            soft_error_escalation_count: Some(self.soft_error_escalation_count),
             concurrent_command_blocking_duration: self
                 .concurrent_command_blocking_duration
                 .and_then(|x| x.try_into().ok()),
@@ -1662,6 +1665,10 @@ impl InvocationRecorder {
                     buck2_data::instant_event::Data::StructuredError(err) => {
                         self.handle_structured_error(err)
                     }
+                    buck2_data::instant_event::Data::SoftErrorEscalated(_) => {
+                        self.soft_error_escalation_count += 1;
+                        Ok(())
+                    }
                     buck2_data::instant_event::Data::RestartConfiguration(conf) => {
                         self.enable_restarter = conf.enable_restarter;
                         Ok(())
@@ -1727,7 +1734,8 @@ fn process_error_report(error: buck2_data::ErrorReport) -> buck2_data::Processed
         .filter_map(|v| ErrorTag::try_from(v).ok());
 
     let source_area = source_area(tags.clone()).to_string().to_ascii_uppercase();
-    let tags = tags.map(|t| t.as_str_name().to_owned());
+    // Sorted, so that the record doesn't depend on the order the tags were attached in.
+    let tags = tags.map(|t| t.as_str_name().to_owned()).sorted();
 
     let string_tags = error.string_tags.iter().map(|t| t.tag.clone());
     let tags = tags.chain(string_tags).collect();
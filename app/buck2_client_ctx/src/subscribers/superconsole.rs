@@ -354,6 +354,7 @@ impl StatefulSuperConsole {
         let mut lines = Lines::new();
         if let buck2_cli_proto::CommandResult {
             result: Some(buck2_cli_proto::command_result::Result::Error(e)),
+            ..
         } = result
         {
             let style = ContentStyle {
@@ -1068,6 +1069,8 @@ mod tests {
                 result: Some(buck2_cli_proto::command_result::Result::GenericResponse(
                     GenericResponse {},
                 )),
+                prologue_timings: None,
+                original_result: None,
             })
             .await
             .unwrap();
@@ -1196,7 +1199,11 @@ mod tests {
         assert_frame_contains(&frame, "Remaining");
 
         console
-            .handle_command_result(&buck2_cli_proto::CommandResult { result: None })
+            .handle_command_result(&buck2_cli_proto::CommandResult {
+                result: None,
+                prologue_timings: None,
+                original_result: None,
+            })
             .await?;
 
         Ok(())
@@ -0,0 +1,276 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! The `buckd.info` file, through which a daemon tells clients how to reach it.
+//!
+//! The file is written by the daemon and read by clients which may be older or newer than it, so
+//! parsing is lenient: fields missing in files written by older daemons get a default, and fields
+//! only newer daemons know of are kept, so that rewriting the file doesn't drop them.
+
+use buck2_cli_proto::DaemonProcessInfo;
+use buck2_core::fs::fs_util;
+use buck2_error::BuckErrorContext;
+use buck2_wrapper_common::kill::process_exists;
+use buck2_wrapper_common::pid::Pid;
+use serde::de::DeserializeOwned;
+use serde_json::Map;
+use serde_json::Value;
+
+use crate::buckd_connection::ConnectionType;
+use crate::daemon_dir::DaemonDir;
+
+/// Version of the format of `buckd.info`. Files written before the version was recorded are
+/// version 0. Bump when the meaning of an existing field changes; adding a field doesn't need it.
+const BUCKD_INFO_FORMAT_VERSION: u64 = 1;
+
+const FORMAT_VERSION_FIELD: &str = "format_version";
+
+#[derive(Debug, buck2_error::Error)]
+#[buck2(tag = Tier0)]
+enum BuckdInfoError {
+    #[error("Expected a JSON object")]
+    NotAnObject,
+    #[error("Invalid `{0}`: {1}")]
+    InvalidField(&'static str, serde_json::Error),
+    #[error("Missing `pid`")]
+    MissingPid,
+}
+
+/// Contents of `buckd.info`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BuckdInfo {
+    info: DaemonProcessInfo,
+    /// Format version the file was read with.
+    format_version: u64,
+    /// Fields written by a newer daemon.
+    unknown_fields: Map<String, Value>,
+}
+
+impl BuckdInfo {
+    pub fn new(info: DaemonProcessInfo) -> BuckdInfo {
+        BuckdInfo {
+            info,
+            format_version: BUCKD_INFO_FORMAT_VERSION,
+            unknown_fields: Map::new(),
+        }
+    }
+
+    /// Reads `buckd.info` from `daemon_dir`, or `None` if there is none.
+    pub fn read_if_exists(daemon_dir: &DaemonDir) -> buck2_error::Result<Option<BuckdInfo>> {
+        let path = daemon_dir.buckd_info();
+        let Some(contents) = fs_util::read_to_string_if_exists(&path)
+            .with_buck_error_context(|| format!("Error reading daemon info `{}`", path))?
+        else {
+            return Ok(None);
+        };
+        let info = Self::from_json(&contents).with_buck_error_context(|| {
+            format!(
+                "Error parsing daemon info in `{}`. \
+                Try deleting that file and running `buck2 killall` before running your command again",
+                path
+            )
+        })?;
+        Ok(Some(info))
+    }
+
+    /// Writes this as `buckd.info` in `daemon_dir`.
+    pub fn write(&self, daemon_dir: &DaemonDir) -> buck2_error::Result<()> {
+        let path = daemon_dir.buckd_info();
+        fs_util::write(&path, self.to_json()?)
+            .with_buck_error_context(|| format!("Error writing daemon info `{}`", path))
+    }
+
+    pub fn from_json(json: &str) -> buck2_error::Result<BuckdInfo> {
+        let Value::Object(mut fields) = serde_json::from_str(json)? else {
+            return Err(BuckdInfoError::NotAnObject.into());
+        };
+        let format_version = take_field(&mut fields, FORMAT_VERSION_FIELD)?;
+        let info = DaemonProcessInfo {
+            pid: take_field(&mut fields, "pid")?,
+            endpoint: take_field(&mut fields, "endpoint")?,
+            version: take_field(&mut fields, "version")?,
+            auth_token: take_field(&mut fields, "auth_token")?,
+        };
+        Ok(BuckdInfo {
+            info,
+            format_version,
+            unknown_fields: fields,
+        })
+    }
+
+    pub fn to_json(&self) -> buck2_error::Result<String> {
+        let Value::Object(mut fields) = serde_json::to_value(&self.info)? else {
+            return Err(BuckdInfoError::NotAnObject.into());
+        };
+        fields.insert(
+            FORMAT_VERSION_FIELD.to_owned(),
+            self.format_version.max(BUCKD_INFO_FORMAT_VERSION).into(),
+        );
+        for (name, value) in &self.unknown_fields {
+            fields.entry(name.clone()).or_insert_with(|| value.clone());
+        }
+        Ok(serde_json::to_string(&fields)?)
+    }
+
+    pub fn info(&self) -> &DaemonProcessInfo {
+        &self.info
+    }
+
+    pub fn into_info(self) -> DaemonProcessInfo {
+        self.info
+    }
+
+    pub fn pid(&self) -> buck2_error::Result<Pid> {
+        if self.info.pid == 0 {
+            return Err(BuckdInfoError::MissingPid.into());
+        }
+        Pid::from_i64(self.info.pid)
+    }
+
+    /// Whether the daemon process still exists. It may not be reachable even if it does.
+    pub fn process_exists(&self) -> buck2_error::Result<bool> {
+        process_exists(self.pid()?)
+    }
+
+    pub fn endpoint(&self) -> buck2_error::Result<ConnectionType> {
+        ConnectionType::parse(&self.info.endpoint)
+            .with_buck_error_context(|| format!("Invalid daemon endpoint `{}`", self.info.endpoint))
+    }
+
+    /// Empty for daemons which predate auth tokens.
+    pub fn auth_token(&self) -> &str {
+        &self.info.auth_token
+    }
+
+    /// Unique id of the daemon binary, empty if the daemon didn't record it.
+    pub fn version(&self) -> &str {
+        &self.info.version
+    }
+}
+
+/// Removes `name` from `fields`, defaulting when it is missing or null.
+fn take_field<T: DeserializeOwned + Default>(
+    fields: &mut Map<String, Value>,
+    name: &'static str,
+) -> buck2_error::Result<T> {
+    match fields.remove(name) {
+        None | Some(Value::Null) => Ok(T::default()),
+        Some(value) => {
+            Ok(serde_json::from_value(value).map_err(|e| BuckdInfoError::InvalidField(name, e))?)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use buck2_core::fs::paths::abs_norm_path::AbsNormPathBuf;
+
+    use super::*;
+
+    fn info() -> DaemonProcessInfo {
+        DaemonProcessInfo {
+            pid: 1234,
+            endpoint: "tcp:5678".to_owned(),
+            version: "abc".to_owned(),
+            auth_token: "token".to_owned(),
+        }
+    }
+
+    fn json_fields(json: &str) -> Map<String, Value> {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn test_current_round_trip() -> buck2_error::Result<()> {
+        let json = BuckdInfo::new(info()).to_json()?;
+        assert_eq!(
+            json_fields(&json),
+            json_fields(
+                r#"{"format_version":1,"pid":1234,"endpoint":"tcp:5678","version":"abc","auth_token":"token"}"#
+            )
+        );
+        let read = BuckdInfo::from_json(&json)?;
+        assert_eq!(BuckdInfo::new(info()), read);
+        assert_eq!(1234, read.pid()?.to_u32());
+        assert!(matches!(
+            read.endpoint()?,
+            ConnectionType::Tcp { port: 5678 }
+        ));
+        assert_eq!("token", read.auth_token());
+        assert_eq!("abc", read.version());
+        Ok(())
+    }
+
+    #[test]
+    fn test_older_missing_fields() -> buck2_error::Result<()> {
+        // Written before the format version and auth tokens were recorded.
+        let read = BuckdInfo::from_json(r#"{"pid":1234,"endpoint":"tcp:5678","version":"abc"}"#)?;
+        assert_eq!(0, read.format_version);
+        assert_eq!("", read.auth_token());
+        assert!(matches!(
+            read.endpoint()?,
+            ConnectionType::Tcp { port: 5678 }
+        ));
+
+        // Rewriting upgrades the format version.
+        let rewritten = BuckdInfo::from_json(&read.to_json()?)?;
+        assert_eq!(BUCKD_INFO_FORMAT_VERSION, rewritten.format_version);
+        assert_eq!(read.info(), rewritten.info());
+        Ok(())
+    }
+
+    #[test]
+    fn test_future_extra_fields() -> buck2_error::Result<()> {
+        let json = r#"{"format_version":7,"pid":1234,"endpoint":"tcp:5678","version":"abc","auth_token":"token","socket_dir":"/tmp/x","features":["a"]}"#;
+        let read = BuckdInfo::from_json(json)?;
+        assert_eq!(&info(), read.info());
+        assert_eq!(json_fields(json), json_fields(&read.to_json()?));
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid() {
+        for (json, expected) in [
+            ("[]", "Expected a JSON object"),
+            (r#"{"pid":"1234"}"#, "Invalid `pid`"),
+            (r#"{"endpoint":5678}"#, "Invalid `endpoint`"),
+        ] {
+            let error = format!("{:#}", BuckdInfo::from_json(json).unwrap_err());
+            assert!(error.contains(expected), "{expected} in {error}");
+        }
+
+        let missing = BuckdInfo::from_json("{}").unwrap();
+        assert!(missing.pid().is_err());
+        let error = format!("{:#}", missing.endpoint().unwrap_err());
+        assert!(error.contains("Invalid daemon endpoint ``"), "{error}");
+    }
+
+    #[test]
+    fn test_read_names_file() -> buck2_error::Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let daemon_dir = DaemonDir {
+            path: AbsNormPathBuf::new(tempdir.path().to_path_buf())?,
+        };
+        assert_eq!(None, BuckdInfo::read_if_exists(&daemon_dir)?);
+
+        BuckdInfo::new(info()).write(&daemon_dir)?;
+        assert_eq!(
+            Some(BuckdInfo::new(info())),
+            BuckdInfo::read_if_exists(&daemon_dir)?
+        );
+
+        fs_util::write(daemon_dir.buckd_info(), "{")?;
+        let error = format!("{:#}", BuckdInfo::read_if_exists(&daemon_dir).unwrap_err());
+        assert!(
+            error.contains(&daemon_dir.buckd_info().to_string()),
+            "{error}"
+        );
+        Ok(())
+    }
+}
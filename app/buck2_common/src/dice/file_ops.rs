@@ -40,6 +40,7 @@ use crate::file_ops::RawPathMetadata;
 use crate::file_ops::ReadDirOutput;
 use crate::ignores::file_ignores::FileIgnoreResult;
 use crate::io::ReadDirError;
+use crate::legacy_configs::existence_cache::ConfigFileExistenceCache;
 
 pub mod delegate;
 
@@ -213,6 +214,12 @@ impl FileChangeTracker {
             }
         }
 
+        // Every file or directory that was added or removed dirties its parent's listing. Config
+        // file lookups made outside of DICE are cached until that happens.
+        if !self.dirs_to_dirty.is_empty() {
+            ConfigFileExistenceCache::process().invalidate_project_paths();
+        }
+
         ctx.changed(self.files_to_dirty)?;
         ctx.changed(self.dirs_to_dirty)?;
         ctx.changed(self.paths_to_dirty)?;
@@ -7,14 +7,20 @@
  * of this source tree.
  */
 
+use std::collections::HashMap;
 use std::sync::Arc;
 
 use async_trait::async_trait;
+use buck2_core::cells::CellResolver;
 use buck2_core::cells::cell_root_path::CellRootPath;
 use buck2_core::cells::external::ExternalCellOrigin;
+use buck2_core::cells::external::GitCellSetup;
+use buck2_core::cells::external::ResolvedGitCommits;
 use buck2_core::cells::name::CellName;
+use buck2_error::BuckErrorContext;
 use buck2_util::late_binding::LateBinding;
 use dice::DiceComputations;
+use dupe::Dupe;
 
 use crate::dice::file_ops::delegate::FileOpsDelegate;
 
@@ -29,6 +35,13 @@ pub trait ExternalCellsImpl: Send + Sync + 'static {
 
     fn check_bundled_cell_exists(&self, cell_name: CellName) -> buck2_error::Result<()>;
 
+    /// The commit that the revision of a git cell resolves to.
+    async fn resolve_git_commit(
+        &self,
+        ctx: &mut DiceComputations<'_>,
+        setup: GitCellSetup,
+    ) -> buck2_error::Result<Arc<str>>;
+
     async fn expand(
         &self,
         ctx: &mut DiceComputations<'_>,
@@ -40,3 +53,29 @@ pub trait ExternalCellsImpl: Send + Sync + 'static {
 
 pub static EXTERNAL_CELLS_IMPL: LateBinding<&'static dyn ExternalCellsImpl> =
     LateBinding::new("EXTERNAL_CELLS_IMPL");
+
+/// Resolves the revisions of all the git cells pinned to a ref or a tag, so that their sources can
+/// be found in buck-out.
+pub async fn get_resolved_git_commits(
+    ctx: &mut DiceComputations<'_>,
+    cell_resolver: &CellResolver,
+) -> buck2_error::Result<ResolvedGitCommits> {
+    let mut commits = HashMap::new();
+    for (name, instance) in cell_resolver.cells() {
+        let Some(ExternalCellOrigin::Git(setup)) = instance.external() else {
+            continue;
+        };
+        if !setup.needs_resolution() {
+            continue;
+        }
+        let commit = EXTERNAL_CELLS_IMPL
+            .get()?
+            .resolve_git_commit(ctx, setup.dupe())
+            .await
+            .with_buck_error_context(|| {
+                format!("Resolving `{}` of external cell `{}`", setup, name)
+            })?;
+        commits.insert(setup.dupe(), commit);
+    }
+    Ok(ResolvedGitCommits::new(commits))
+}
@@ -378,6 +378,18 @@ pub struct DaemonStartupConfig {
     pub resource_control: ResourceControlConfig,
     pub log_download_method: LogDownloadMethod,
     pub health_check_config: HealthCheckConfig,
+    /// How long the daemon waits without receiving any command before shutting down.
+    /// The corresponding buckconfig is `buck2.daemon_inactivity_timeout_secs`, which can't be 0.
+    pub inactivity_timeout_secs: Option<u64>,
+    /// If set, the daemon dumps its heap when its RSS goes above this many bytes.
+    /// The corresponding buckconfig is `buck2.heap_dump_rss_threshold_bytes`.
+    pub heap_dump_rss_threshold_bytes: Option<u64>,
+    /// Where those heap dumps are written, defaults to the log directory.
+    /// The corresponding buckconfig is `buck2.heap_dump_dir`.
+    pub heap_dump_dir: Option<String>,
+    /// Whether the daemon rejects commands from clients it is incompatible with.
+    /// The corresponding buckconfig is `buck2.check_client_compatibility`, which defaults to true.
+    pub check_client_compatibility: bool,
 }
 
 impl DaemonStartupConfig {
@@ -447,6 +459,23 @@ impl DaemonStartupConfig {
             resource_control: ResourceControlConfig::from_config(config)?,
             log_download_method,
             health_check_config: HealthCheckConfig::from_config(config)?,
+            inactivity_timeout_secs: parse_inactivity_timeout_secs(config)?,
+            heap_dump_rss_threshold_bytes: config.parse(BuckconfigKeyRef {
+                section: "buck2",
+                property: "heap_dump_rss_threshold_bytes",
+            })?,
+            heap_dump_dir: config
+                .get(BuckconfigKeyRef {
+                    section: "buck2",
+                    property: "heap_dump_dir",
+                })
+                .map(ToOwned::to_owned),
+            check_client_compatibility: config
+                .parse(BuckconfigKeyRef {
+                    section: "buck2",
+                    property: "check_client_compatibility",
+                })?
+                .unwrap_or(true),
         })
     }
 
@@ -473,6 +502,71 @@ impl DaemonStartupConfig {
                 LogDownloadMethod::None
             },
             health_check_config: HealthCheckConfig::default(),
+            inactivity_timeout_secs: None,
+            heap_dump_rss_threshold_bytes: None,
+            heap_dump_dir: None,
+            check_client_compatibility: true,
         }
     }
 }
+
+fn parse_inactivity_timeout_secs(config: &LegacyBuckConfig) -> buck2_error::Result<Option<u64>> {
+    let secs = config.parse(BuckconfigKeyRef {
+        section: "buck2",
+        property: "daemon_inactivity_timeout_secs",
+    })?;
+    if secs == Some(0) {
+        return Err(buck2_error::buck2_error!(
+            buck2_error::ErrorTag::Input,
+            "`buck2.daemon_inactivity_timeout_secs` must be greater than 0"
+        ));
+    }
+    Ok(secs)
+}
+
+#[cfg(test)]
+mod tests {
+    use indoc::indoc;
+
+    use super::*;
+    use crate::legacy_configs::configs::testing::parse;
+
+    fn inactivity_timeout_secs(value: &str) -> buck2_error::Result<Option<u64>> {
+        let config = parse(
+            &[(
+                "config",
+                &format!("[buck2]\ndaemon_inactivity_timeout_secs = {value}\n"),
+            )],
+            "config",
+        )?;
+        parse_inactivity_timeout_secs(&config)
+    }
+
+    #[test]
+    fn test_inactivity_timeout_secs() -> buck2_error::Result<()> {
+        let config = parse(
+            &[(
+                "config",
+                indoc!(
+                    r#"
+                    [buck2]
+                    materializations = deferred
+                    "#
+                ),
+            )],
+            "config",
+        )?;
+        assert_eq!(parse_inactivity_timeout_secs(&config)?, None);
+
+        assert_eq!(inactivity_timeout_secs("30")?, Some(30));
+        assert_eq!(inactivity_timeout_secs("86400")?, Some(86400));
+        Ok(())
+    }
+
+    #[test]
+    fn test_inactivity_timeout_secs_invalid() {
+        assert!(inactivity_timeout_secs("0").is_err());
+        assert!(inactivity_timeout_secs("-1").is_err());
+        assert!(inactivity_timeout_secs("soon").is_err());
+    }
+}
@@ -16,8 +16,10 @@ pub mod args;
 pub mod cells;
 pub mod configs;
 pub mod dice;
+pub mod existence_cache;
 pub mod file_ops;
 pub mod key;
+pub mod legacy_sections;
 mod parser;
 pub(crate) mod path;
 pub mod view;
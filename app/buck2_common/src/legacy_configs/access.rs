@@ -13,6 +13,7 @@ use std::sync::Arc;
 use buck2_error::BuckErrorContext;
 use gazebo::eq_chain;
 
+use crate::legacy_configs::configs::ConfigProvenance;
 use crate::legacy_configs::configs::ConfigValue;
 use crate::legacy_configs::configs::LegacyBuckConfig;
 use crate::legacy_configs::configs::LegacyBuckConfigSection;
@@ -82,6 +83,12 @@ impl LegacyBuckConfig {
         self.get_config_value(key).map(|s| s.as_str())
     }
 
+    /// Where the value of `key` was last set, for debugging which of several places setting it wins.
+    pub fn key_provenance(&self, key: BuckconfigKeyRef) -> Option<ConfigProvenance> {
+        self.get_config_value(key)
+            .map(|value| LegacyBuckConfigValue { value }.provenance())
+    }
+
     /// Iterate all entries.
     pub fn iter(&self) -> impl Iterator<Item = (&str, impl IntoIterator<Item = (&str, &str)>)> {
         self.0.values.iter().map(|(section, section_values)| {
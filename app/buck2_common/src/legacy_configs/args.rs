@@ -44,7 +44,7 @@ pub(crate) enum ResolvedConfigFile {
 pub(crate) struct ExternalConfigFile {
     pub(crate) parser: LegacyConfigParser,
     // The origin path of the config file
-    origin_path: AbsPathBuf,
+    pub(crate) origin_path: AbsPathBuf,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, allocative::Allocative)]
@@ -120,6 +120,8 @@ pub(crate) async fn resolve_config_args(
             )
         })?;
         let resolved = match config_type {
+            // Read with the other external buckconfigs, it doesn't override anything.
+            ConfigType::ExternalFile => continue,
             ConfigType::Value => {
                 let cell = u.get_cell()?.map(|p| p.to_buf());
                 let resolved_flag = resolve_config_flag_arg(cell, &u.config_override)?;
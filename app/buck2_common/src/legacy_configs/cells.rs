@@ -7,10 +7,14 @@
  * of this source tree.
  */
 
+use std::collections::HashMap;
 use std::collections::HashSet;
+use std::collections::hash_map;
+use std::fmt;
 use std::sync::Arc;
 
 use allocative::Allocative;
+use buck2_cli_proto::config_override::ConfigType;
 use buck2_core::buck2_env;
 use buck2_core::cells::CellAliasResolver;
 use buck2_core::cells::CellResolver;
@@ -18,6 +22,7 @@ use buck2_core::cells::alias::NonEmptyCellAlias;
 use buck2_core::cells::cell_root_path::CellRootPath;
 use buck2_core::cells::cell_root_path::CellRootPathBuf;
 use buck2_core::cells::external::ExternalCellOrigin;
+use buck2_core::cells::external::GitCellRevision;
 use buck2_core::cells::external::GitCellSetup;
 use buck2_core::cells::name::CellName;
 use buck2_core::fs::paths::RelativePath;
@@ -28,17 +33,22 @@ use buck2_core::fs::project_rel_path::ProjectRelativePath;
 use buck2_error::BuckErrorContext;
 use dice::DiceComputations;
 use dupe::Dupe;
+use parking_lot::Mutex;
 
 use crate::cas_digest::RawDigest;
 use crate::dice::cells::HasCellResolver;
 use crate::dice::data::HasIoProvider;
 use crate::external_cells::EXTERNAL_CELLS_IMPL;
 use crate::legacy_configs::aggregator::CellsAggregator;
+use crate::legacy_configs::args::ResolvedConfigFile;
 use crate::legacy_configs::args::ResolvedLegacyConfigArg;
 use crate::legacy_configs::args::resolve_config_args;
 use crate::legacy_configs::args::to_proto_config_args;
+use crate::legacy_configs::configs::ConfigProvenance;
 use crate::legacy_configs::configs::LegacyBuckConfig;
 use crate::legacy_configs::dice::HasInjectedLegacyConfigs;
+use crate::legacy_configs::existence_cache::CachedConfigParserFileOps;
+use crate::legacy_configs::existence_cache::ConfigFileExistenceCache;
 use crate::legacy_configs::file_ops::ConfigDirEntry;
 use crate::legacy_configs::file_ops::ConfigParserFileOps;
 use crate::legacy_configs::file_ops::ConfigPath;
@@ -46,6 +56,9 @@ use crate::legacy_configs::file_ops::DefaultConfigParserFileOps;
 use crate::legacy_configs::file_ops::DiceConfigFileOps;
 use crate::legacy_configs::file_ops::push_all_files_from_a_directory;
 use crate::legacy_configs::key::BuckconfigKeyRef;
+use crate::legacy_configs::legacy_sections::LegacySectionWarning;
+use crate::legacy_configs::legacy_sections::LegacySectionWarningDedup;
+use crate::legacy_configs::legacy_sections::get_section_with_legacy_fallback;
 use crate::legacy_configs::parser::LegacyConfigParser;
 use crate::legacy_configs::path::DEFAULT_EXTERNAL_CONFIG_SOURCES;
 use crate::legacy_configs::path::DEFAULT_PROJECT_CONFIG_SOURCES;
@@ -131,7 +144,7 @@ impl ExternalBuckconfigData {
 
                 let mut parser = LegacyConfigParser::new();
                 if parser
-                    .parse_file(&local_config, None, true, file_ops)
+                    .parse_file(&local_config, cell_instance.path(), None, true, file_ops)
                     .await
                     .is_ok()
                 {
@@ -178,6 +191,66 @@ impl ExternalBuckconfigData {
     }
 }
 
+/// Shows each legacy section warning once per process.
+fn emit_legacy_section_warnings(warnings: Vec<LegacySectionWarning>) {
+    static SHOWN: LegacySectionWarningDedup = LegacySectionWarningDedup::new();
+    for warning in SHOWN.unseen(warnings) {
+        tracing::warn!("{}", warning);
+    }
+}
+
+/// A key set to different values by two external config sources, reported when parsing with
+/// strict layering.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigLayeringConflict {
+    pub section: String,
+    pub key: String,
+    /// The source whose value was overridden.
+    pub overridden: ConfigPath,
+    /// The later source whose value is used instead.
+    pub winner: ConfigPath,
+}
+
+impl fmt::Display for ConfigLayeringConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Buckconfig `{}.{}` set in `{}` is overridden by `{}`",
+            self.section, self.key, self.overridden, self.winner
+        )
+    }
+}
+
+/// Finds the keys that a source sets to a different value than an earlier source.
+fn find_layering_conflicts<'a>(
+    sources: impl IntoIterator<Item = (&'a ConfigPath, &'a LegacyConfigParser)>,
+) -> Vec<ConfigLayeringConflict> {
+    let mut set_by: HashMap<(&str, &str), (&ConfigPath, &str)> = HashMap::new();
+    let mut conflicts = Vec::new();
+    for (path, parser) in sources {
+        for (section, key, value) in parser.raw_values() {
+            match set_by.entry((section, key)) {
+                hash_map::Entry::Vacant(entry) => {
+                    entry.insert((path, value));
+                }
+                hash_map::Entry::Occupied(mut entry) => {
+                    let (previous_path, previous_value) = *entry.get();
+                    if previous_value != value {
+                        conflicts.push(ConfigLayeringConflict {
+                            section: section.to_owned(),
+                            key: key.to_owned(),
+                            overridden: previous_path.clone(),
+                            winner: path.clone(),
+                        });
+                        entry.insert((path, value));
+                    }
+                }
+            }
+        }
+    }
+    conflicts
+}
+
 /// Used for creating a CellResolver in a buckv1-compatible way based on values
 /// in .buckconfig in each cell.
 ///
@@ -192,6 +265,10 @@ pub struct BuckConfigBasedCells {
     pub root_config: LegacyBuckConfig,
     pub config_paths: HashSet<ConfigPath>,
     pub external_data: ExternalBuckconfigData,
+    /// Keys overridden between external config sources. Only collected with strict layering.
+    pub layering_conflicts: Vec<ConfigLayeringConflict>,
+    /// Alias resolvers computed by `get_cell_alias_resolver_for_cwd_fast`, by cell.
+    alias_resolvers: Mutex<HashMap<CellName, CellAliasResolver>>,
 }
 
 impl BuckConfigBasedCells {
@@ -206,20 +283,31 @@ impl BuckConfigBasedCells {
         cwd: &ProjectRelativePath,
     ) -> buck2_error::Result<CellAliasResolver> {
         self.get_cell_alias_resolver_for_cwd_fast_with_file_ops(
-            &mut DefaultConfigParserFileOps {
-                project_fs: project_fs.dupe(),
+            &mut CachedConfigParserFileOps {
+                inner: &mut DefaultConfigParserFileOps {
+                    project_fs: project_fs.dupe(),
+                },
+                cache: ConfigFileExistenceCache::process_unless_disabled(false)?,
             },
             cwd,
         )
         .await
     }
 
+    /// Where the value of `key` in the root config was last set.
+    pub fn key_provenance(&self, key: BuckconfigKeyRef) -> Option<ConfigProvenance> {
+        self.root_config.key_provenance(key)
+    }
+
     pub(crate) async fn get_cell_alias_resolver_for_cwd_fast_with_file_ops(
         &self,
         file_ops: &mut dyn ConfigParserFileOps,
         cwd: &ProjectRelativePath,
     ) -> buck2_error::Result<CellAliasResolver> {
         let cell_name = self.cell_resolver.find(cwd)?;
+        if let Some(resolver) = self.alias_resolvers.lock().get(&cell_name) {
+            return Ok(resolver.dupe());
+        }
         let cell_path = self.cell_resolver.get(cell_name)?.path();
 
         let follow_includes = false;
@@ -235,23 +323,47 @@ impl BuckConfigBasedCells {
         )
         .await?;
 
-        CellAliasResolver::new_for_non_root_cell(
+        let resolver = CellAliasResolver::new_for_non_root_cell(
             cell_name,
             self.cell_resolver.root_cell_cell_alias_resolver(),
             BuckConfigBasedCells::get_cell_aliases_from_config(&config)?,
-        )
+        )?;
+        // Concurrent calls may both compute the resolver, they compute the same one.
+        self.alias_resolvers
+            .lock()
+            .insert(cell_name, resolver.dupe());
+        Ok(resolver)
     }
 
     pub async fn parse_with_config_args(
         project_fs: &ProjectRoot,
         config_args: &[buck2_cli_proto::ConfigOverride],
+    ) -> buck2_error::Result<Self> {
+        Self::parse_with_config_args_and_cache(
+            project_fs,
+            config_args,
+            ConfigFileExistenceCache::process_unless_disabled(false)?,
+        )
+        .await
+    }
+
+    /// Like `parse_with_config_args`, but looks up config files through `cache`, or directly on
+    /// disk if there is none.
+    pub async fn parse_with_config_args_and_cache(
+        project_fs: &ProjectRoot,
+        config_args: &[buck2_cli_proto::ConfigOverride],
+        cache: Option<&ConfigFileExistenceCache>,
     ) -> buck2_error::Result<Self> {
         Self::parse_with_file_ops_and_options(
-            &mut DefaultConfigParserFileOps {
-                project_fs: project_fs.dupe(),
+            &mut CachedConfigParserFileOps {
+                inner: &mut DefaultConfigParserFileOps {
+                    project_fs: project_fs.dupe(),
+                },
+                cache,
             },
             config_args,
             false, /* follow includes */
+            false, /* strict layering */
         )
         .await
     }
@@ -263,25 +375,36 @@ impl BuckConfigBasedCells {
         Self::parse_with_file_ops_and_options(
             file_ops,
             config_args,
-            true, /* follow includes */
+            true,  /* follow includes */
+            false, /* strict layering */
         )
         .await
     }
 
-    async fn parse_with_file_ops_and_options(
+    /// With `strict_layering`, keys that external config sources (global buckconfigs and
+    /// `--config-file` arguments outside of the project) set to different values are collected in
+    /// `layering_conflicts`. The last value wins either way.
+    pub async fn parse_with_file_ops_and_options(
         file_ops: &mut dyn ConfigParserFileOps,
         config_args: &[buck2_cli_proto::ConfigOverride],
         follow_includes: bool,
+        strict_layering: bool,
     ) -> buck2_error::Result<Self> {
-        Self::parse_with_file_ops_and_options_inner(file_ops, config_args, follow_includes)
-            .await
-            .buck_error_context("Parsing cells")
+        Self::parse_with_file_ops_and_options_inner(
+            file_ops,
+            config_args,
+            follow_includes,
+            strict_layering,
+        )
+        .await
+        .buck_error_context("Parsing cells")
     }
 
     async fn parse_with_file_ops_and_options_inner(
         file_ops: &mut dyn ConfigParserFileOps,
         config_args: &[buck2_cli_proto::ConfigOverride],
         follow_includes: bool,
+        strict_layering: bool,
     ) -> buck2_error::Result<Self> {
         // Tracing file ops to record config file accesses on command invocation.
         struct TracingFileOps<'a> {
@@ -320,7 +443,7 @@ impl BuckConfigBasedCells {
         // NOTE: This will _not_ perform IO unless it needs to.
         let processed_config_args = resolve_config_args(&config_args, &mut file_ops).await?;
 
-        let external_paths = get_external_buckconfig_paths(&mut file_ops).await?;
+        let external_paths = get_external_buckconfig_paths(&mut file_ops, config_args).await?;
         let started_parse = LegacyBuckConfig::start_parse_for_external_files(
             &external_paths,
             &mut file_ops,
@@ -328,6 +451,30 @@ impl BuckConfigBasedCells {
         )
         .await?;
 
+        let layering_conflicts = if strict_layering {
+            let global_config_files: Vec<(ConfigPath, &LegacyConfigParser)> = processed_config_args
+                .iter()
+                .filter_map(|arg| match arg {
+                    ResolvedLegacyConfigArg::File(ResolvedConfigFile::Global(file)) => {
+                        Some((ConfigPath::Global(file.origin_path.clone()), &file.parser))
+                    }
+                    _ => None,
+                })
+                .collect();
+            find_layering_conflicts(
+                started_parse
+                    .iter()
+                    .map(|config| (&config.origin_path, &config.parse_state))
+                    .chain(
+                        global_config_files
+                            .iter()
+                            .map(|(path, parser)| (path, *parser)),
+                    ),
+            )
+        } else {
+            Vec::new()
+        };
+
         let root_path = CellRootPathBuf::new(ProjectRelativePath::empty().to_owned());
 
         let buckconfig_paths = get_project_buckconfig_paths(&root_path, &mut file_ops).await?;
@@ -348,9 +495,10 @@ impl BuckConfigBasedCells {
         // that we'll ever remove `repositories` since that's probably unnecessary breakage in OSS.
         //
         // Note that `cells` is buck2-only
-        let repositories = root_config
-            .get_section("cells")
-            .or_else(|| root_config.get_section("repositories"));
+        let mut warnings = Vec::new();
+        let repositories =
+            get_section_with_legacy_fallback(&root_config, "cells", "repositories", &mut warnings)?;
+        emit_legacy_section_warnings(warnings);
         if let Some(repositories) = repositories {
             for (alias, alias_path) in repositories.iter() {
                 let alias_path = CellRootPathBuf::new(
@@ -405,6 +553,8 @@ impl BuckConfigBasedCells {
                 external_path_configs: started_parse,
                 args: processed_config_args,
             },
+            layering_conflicts,
+            alias_resolvers: Mutex::new(HashMap::new()),
         })
     }
 
@@ -413,10 +563,15 @@ impl BuckConfigBasedCells {
     ) -> buck2_error::Result<impl Iterator<Item = (NonEmptyCellAlias, NonEmptyCellAlias)> + use<>>
     {
         let mut aliases = Vec::new();
-        if let Some(section) = config
-            .get_section("cell_aliases")
-            .or_else(|| config.get_section("repository_aliases"))
-        {
+        let mut warnings = Vec::new();
+        let section = get_section_with_legacy_fallback(
+            config,
+            "cell_aliases",
+            "repository_aliases",
+            &mut warnings,
+        )?;
+        emit_legacy_section_warnings(warnings);
+        if let Some(section) = section {
             for (alias, destination) in section.iter() {
                 let alias = NonEmptyCellAlias::new(alias.to_owned())?;
                 let destination = NonEmptyCellAlias::new(destination.as_str().to_owned())?;
@@ -496,6 +651,16 @@ impl BuckConfigBasedCells {
             Unknown(String),
             #[error("Missing buckconfig `{0}.{1}` for external cell configuration")]
             MissingConfiguration(String, String),
+            #[error(
+                "Missing buckconfig `{0}.commit_hash`, `{0}.git_ref` or `{0}.tag` for external cell configuration"
+            )]
+            MissingGitRevision(String),
+            #[error(
+                "Only one of buckconfig `{0}.git_ref` and `{0}.{1}` may be set for external cell configuration"
+            )]
+            ConflictingGitRevision(String, &'static str),
+            #[error("Invalid git ref `{2}` in buckconfig `{0}.{1}`")]
+            InvalidGitRef(String, &'static str, String),
         }
 
         let get_config = |section: &str, property: &str| {
@@ -513,13 +678,57 @@ impl BuckConfigBasedCells {
             Ok(ExternalCellOrigin::Bundled(cell))
         } else if value == "git" {
             let section = &format!("external_cell_{}", cell.as_str());
-            let commit: Arc<str> = get_config(section, "commit_hash")?.into();
-            // No use in storing the commit hash as a byte array, but let's reuse existing code to
-            // check for validity
-            let _ = RawDigest::parse_sha1(commit.as_bytes())?;
+            let get_optional = |property| config.get(BuckconfigKeyRef { section, property });
+            let get_commit = || -> buck2_error::Result<Option<Arc<str>>> {
+                let Some(commit) = get_optional("commit_hash") else {
+                    return Ok(None);
+                };
+                // No use in storing the commit hash as a byte array, but let's reuse existing
+                // code to check for validity
+                let _ = RawDigest::parse_sha1(commit.as_bytes())?;
+                Ok(Some(commit.into()))
+            };
+            let get_ref = |property: &'static str| -> buck2_error::Result<Option<Arc<str>>> {
+                let Some(git_ref) = get_optional(property) else {
+                    return Ok(None);
+                };
+                // Refs are passed to git as arguments, so at least make sure they can't be
+                // mistaken for options.
+                if git_ref.is_empty()
+                    || git_ref.starts_with('-')
+                    || git_ref.contains(char::is_whitespace)
+                    || git_ref.contains("..")
+                {
+                    return Err(ExternalCellOriginParseError::InvalidGitRef(
+                        section.to_owned(),
+                        property,
+                        git_ref.to_owned(),
+                    )
+                    .into());
+                }
+                Ok(Some(git_ref.into()))
+            };
+            let revision = match (get_ref("git_ref")?, get_ref("tag")?, get_commit()?) {
+                (Some(git_ref), None, None) => GitCellRevision::Ref(git_ref),
+                (None, Some(tag), commit) => GitCellRevision::Tag { tag, commit },
+                (None, None, Some(commit)) => GitCellRevision::Commit(commit),
+                (Some(_), tag, _) => {
+                    return Err(ExternalCellOriginParseError::ConflictingGitRevision(
+                        section.to_owned(),
+                        if tag.is_some() { "tag" } else { "commit_hash" },
+                    )
+                    .into());
+                }
+                (None, None, None) => {
+                    return Err(ExternalCellOriginParseError::MissingGitRevision(
+                        section.to_owned(),
+                    )
+                    .into());
+                }
+            };
             Ok(ExternalCellOrigin::Git(GitCellSetup {
                 git_origin: get_config(section, "git_origin")?.into(),
-                commit,
+                revision,
             }))
         } else {
             Err(ExternalCellOriginParseError::Unknown(value.to_owned()).into())
@@ -529,6 +738,7 @@ impl BuckConfigBasedCells {
 
 async fn get_external_buckconfig_paths(
     file_ops: &mut dyn ConfigParserFileOps,
+    config_args: &[buck2_cli_proto::ConfigOverride],
 ) -> buck2_error::Result<Vec<ConfigPath>> {
     let skip_default_external_config = buck2_env!(
         "BUCK2_TEST_SKIP_DEFAULT_EXTERNAL_CONFIG",
@@ -576,6 +786,21 @@ async fn get_external_buckconfig_paths(
                     )
                     .await?;
                 }
+                ExternalConfigSource::ClientFile => {
+                    for arg in config_args {
+                        if arg.config_type == ConfigType::ExternalFile as i32 {
+                            let file = AbsPath::new(&arg.config_override).with_buck_error_context(
+                                || {
+                                    format!(
+                                        "Invalid external buckconfig file `{}`",
+                                        arg.config_override
+                                    )
+                                },
+                            )?;
+                            buckconfig_paths.push(ConfigPath::Global(file.to_owned()));
+                        }
+                    }
+                }
             }
         }
     }
@@ -629,8 +854,13 @@ mod tests {
     use buck2_core::cells::cell_root_path::CellRootPath;
     use buck2_core::cells::cell_root_path::CellRootPathBuf;
     use buck2_core::cells::external::ExternalCellOrigin;
+    use buck2_core::cells::external::GitCellRevision;
     use buck2_core::cells::external::GitCellSetup;
     use buck2_core::cells::name::CellName;
+    use buck2_core::fs::fs_util;
+    use buck2_core::fs::paths::abs_path::AbsPathBuf;
+    use buck2_core::fs::project_rel_path::ProjectRelativePath;
+    use buck2_core::fs::project_rel_path::ProjectRelativePathBuf;
     use dice::DiceComputations;
     use indoc::indoc;
 
@@ -638,8 +868,13 @@ mod tests {
     use crate::external_cells::EXTERNAL_CELLS_IMPL;
     use crate::external_cells::ExternalCellsImpl;
     use crate::legacy_configs::cells::BuckConfigBasedCells;
+    use crate::legacy_configs::cells::ConfigLayeringConflict;
+    use crate::legacy_configs::configs::ConfigProvenance;
     use crate::legacy_configs::configs::testing::TestConfigParserFileOps;
     use crate::legacy_configs::configs::tests::assert_config_value;
+    use crate::legacy_configs::file_ops::ConfigDirEntry;
+    use crate::legacy_configs::file_ops::ConfigParserFileOps;
+    use crate::legacy_configs::file_ops::ConfigPath;
     use crate::legacy_configs::key::BuckconfigKeyRef;
 
     #[tokio::test]
@@ -711,6 +946,85 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_cell_alias_resolver_for_cwd_cached() -> buck2_error::Result<()> {
+        /// Counts the files read from `inner`.
+        struct CountingFileOps {
+            inner: TestConfigParserFileOps,
+            reads: usize,
+        }
+
+        #[async_trait::async_trait]
+        impl ConfigParserFileOps for CountingFileOps {
+            async fn read_file_lines_if_exists(
+                &mut self,
+                path: &ConfigPath,
+            ) -> buck2_error::Result<Option<Vec<String>>> {
+                self.reads += 1;
+                self.inner.read_file_lines_if_exists(path).await
+            }
+
+            async fn read_dir(
+                &mut self,
+                path: &ConfigPath,
+            ) -> buck2_error::Result<Vec<ConfigDirEntry>> {
+                self.inner.read_dir(path).await
+            }
+        }
+
+        let mut file_ops = CountingFileOps {
+            inner: TestConfigParserFileOps::new(&[
+                (
+                    ".buckconfig",
+                    indoc!(
+                        r#"
+                            [cells]
+                                root = .
+                                other = other/
+                        "#
+                    ),
+                ),
+                (
+                    "other/.buckconfig",
+                    indoc!(
+                        r#"
+                            [cells]
+                                other = .
+                            [cell_aliases]
+                                o = other
+                        "#
+                    ),
+                ),
+            ])?,
+            reads: 0,
+        };
+
+        let cells = BuckConfigBasedCells::testing_parse_with_file_ops(&mut file_ops, &[]).await?;
+
+        file_ops.reads = 0;
+        let resolver = cells
+            .get_cell_alias_resolver_for_cwd_fast_with_file_ops(
+                &mut file_ops,
+                ProjectRelativePath::new("other/foo")?,
+            )
+            .await?;
+        assert_eq!("other", resolver.resolve("o")?.as_str());
+        let reads = file_ops.reads;
+        assert!(reads > 0);
+
+        // Another directory in the same cell reuses the resolver.
+        let resolver = cells
+            .get_cell_alias_resolver_for_cwd_fast_with_file_ops(
+                &mut file_ops,
+                ProjectRelativePath::new("other/bar/baz")?,
+            )
+            .await?;
+        assert_eq!("other", resolver.resolve("o")?.as_str());
+        assert_eq!(reads, file_ops.reads);
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_multi_cell_with_config_file() -> buck2_error::Result<()> {
         let mut file_ops = TestConfigParserFileOps::new(&[
@@ -923,6 +1237,59 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_cell_relative_include() -> buck2_error::Result<()> {
+        let mut file_ops = TestConfigParserFileOps::new(&[
+            (
+                ".buckconfig",
+                indoc!(
+                    r#"
+                            [cells]
+                                root = .
+                                other = other/
+                                shared = shared/
+                            <file:@shared//defaults.bcfg>
+                        "#
+                ),
+            ),
+            (
+                "other/.buckconfig",
+                indoc!(
+                    r#"
+                            [cells]
+                                other = .
+                                shared = ../shared/
+                            <file:@shared//defaults.bcfg>
+                        "#
+                ),
+            ),
+            (
+                "shared/defaults.bcfg",
+                indoc!(
+                    r#"
+                            [apple]
+                                key = shared
+                        "#
+                ),
+            ),
+        ])?;
+
+        let cells = BuckConfigBasedCells::testing_parse_with_file_ops(&mut file_ops, &[]).await?;
+        assert_config_value(&cells.root_config, "apple", "key", "shared");
+        // Included files are traced like any other config file.
+        assert!(cells.config_paths.contains(&ConfigPath::Project(
+            ProjectRelativePathBuf::testing_new("shared/defaults.bcfg")
+        )));
+
+        // Cell paths in the config of another cell are relative to that cell.
+        let other_config = cells
+            .parse_single_cell_with_file_ops(CellName::testing_new("other"), &mut file_ops)
+            .await?;
+        assert_config_value(&other_config, "apple", "key", "shared");
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_local_config_file_overwrite_config_file() -> buck2_error::Result<()> {
         let mut file_ops = TestConfigParserFileOps::new(&[
@@ -969,6 +1336,68 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_key_provenance() -> buck2_error::Result<()> {
+        let mut file_ops = TestConfigParserFileOps::new(&[
+            (
+                ".buckconfig",
+                indoc!(
+                    r#"
+                            [cells]
+                                root = .
+                            [apple]
+                                key = value1
+                                key2 = value2
+                        "#
+                ),
+            ),
+            (
+                ".buckconfig.local",
+                indoc!(
+                    r#"
+                            [apple]
+                                key = value3
+                                key2 = value4
+                        "#
+                ),
+            ),
+        ])?;
+
+        let cells = BuckConfigBasedCells::testing_parse_with_file_ops(
+            &mut file_ops,
+            &[ConfigOverride::flag_no_cell("apple.key=value5")],
+        )
+        .await?;
+
+        assert_config_value(&cells.root_config, "apple", "key", "value5");
+        let key = cells
+            .key_provenance(BuckconfigKeyRef {
+                section: "apple",
+                property: "key",
+            })
+            .unwrap();
+        assert!(key.is_command_line_argument());
+        assert_eq!(
+            Some(ConfigProvenance::File {
+                path: ConfigPath::Project(ProjectRelativePathBuf::testing_new(".buckconfig.local")),
+                line: 3,
+            }),
+            cells.key_provenance(BuckconfigKeyRef {
+                section: "apple",
+                property: "key2",
+            })
+        );
+        assert_eq!(
+            None,
+            cells.key_provenance(BuckconfigKeyRef {
+                section: "apple",
+                property: "key3",
+            })
+        );
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_multi_cell_local_config_file_overwrite_config_file() -> buck2_error::Result<()> {
         let mut file_ops = TestConfigParserFileOps::new(&[
@@ -1111,6 +1540,36 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_cell_config_sections_merged() -> buck2_error::Result<()> {
+        let mut file_ops = TestConfigParserFileOps::new(&[(
+            ".buckconfig",
+            indoc!(
+                r#"
+                            [cells]
+                                root = .
+                            [repositories]
+                                root = .
+                                other = other/
+                            [cell_aliases]
+                                other_alias = other
+                            [repository_aliases]
+                                legacy_alias = other
+                        "#
+            ),
+        )])?;
+
+        let resolver = BuckConfigBasedCells::testing_parse_with_file_ops(&mut file_ops, &[])
+            .await?
+            .cell_resolver;
+
+        let alias_resolver = resolver.root_cell_cell_alias_resolver();
+        assert_eq!("other", alias_resolver.resolve("other_alias")?.as_str());
+        assert_eq!("other", alias_resolver.resolve("legacy_alias")?.as_str());
+
+        Ok(())
+    }
+
     fn initialize_external_cells_impl() {
         struct TestExternalCellsImpl;
 
@@ -1138,6 +1597,15 @@ mod tests {
                 }
             }
 
+            async fn resolve_git_commit(
+                &self,
+                _ctx: &mut DiceComputations<'_>,
+                _setup: GitCellSetup,
+            ) -> buck2_error::Result<Arc<str>> {
+                // Not used in these tests
+                unreachable!()
+            }
+
             async fn expand(
                 &self,
                 _ctx: &mut DiceComputations<'_>,
@@ -1295,7 +1763,9 @@ mod tests {
             instance.external(),
             Some(&ExternalCellOrigin::Git(GitCellSetup {
                 git_origin: "https://github.com/jeff/libfoo.git".into(),
-                commit: "aaaaaaaabbbbbbbbccccccccddddddddeeeeeeee".into(),
+                revision: GitCellRevision::Commit(
+                    "aaaaaaaabbbbbbbbccccccccddddddddeeeeeeee".into()
+                ),
             })),
         );
 
@@ -1332,4 +1802,282 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_git_external_cell_ref() -> buck2_error::Result<()> {
+        initialize_external_cells_impl();
+
+        let mut file_ops = TestConfigParserFileOps::new(&[(
+            ".buckconfig",
+            indoc!(
+                r#"
+                    [cells]
+                        root = .
+                        libfoo = foo/
+                    [external_cells]
+                        libfoo = git
+                    [external_cell_libfoo]
+                        git_origin = https://github.com/jeff/libfoo.git
+                        git_ref = release/v1
+                "#
+            ),
+        )])?;
+
+        let resolver = BuckConfigBasedCells::testing_parse_with_file_ops(&mut file_ops, &[])
+            .await?
+            .cell_resolver;
+
+        let instance = resolver.get(CellName::testing_new("libfoo")).unwrap();
+
+        assert_eq!(
+            instance.external(),
+            Some(&ExternalCellOrigin::Git(GitCellSetup {
+                git_origin: "https://github.com/jeff/libfoo.git".into(),
+                revision: GitCellRevision::Ref("release/v1".into()),
+            })),
+        );
+
+        Ok(())
+    }
+
+    async fn git_external_cell_revision(
+        revision_config: &str,
+    ) -> buck2_error::Result<GitCellRevision> {
+        initialize_external_cells_impl();
+
+        let buckconfig = indoc::formatdoc!(
+            r#"
+                [cells]
+                    root = .
+                    libfoo = foo/
+                [external_cells]
+                    libfoo = git
+                [external_cell_libfoo]
+                    git_origin = https://github.com/jeff/libfoo.git
+                {}
+            "#,
+            revision_config
+        );
+        let mut file_ops = TestConfigParserFileOps::new(&[(".buckconfig", &buckconfig)])?;
+
+        let resolver = BuckConfigBasedCells::testing_parse_with_file_ops(&mut file_ops, &[])
+            .await?
+            .cell_resolver;
+        match resolver.get(CellName::testing_new("libfoo"))?.external() {
+            Some(ExternalCellOrigin::Git(setup)) => Ok(setup.revision.clone()),
+            origin => panic!("Expected a git external cell, got {:?}", origin),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_git_external_cell_tag() -> buck2_error::Result<()> {
+        assert_eq!(
+            GitCellRevision::Tag {
+                tag: "v1.0".into(),
+                commit: None,
+            },
+            git_external_cell_revision("    tag = v1.0").await?,
+        );
+        assert_eq!(
+            GitCellRevision::Tag {
+                tag: "v1.0".into(),
+                commit: Some("aaaaaaaabbbbbbbbccccccccddddddddeeeeeeee".into()),
+            },
+            git_external_cell_revision(
+                "    tag = v1.0\n    commit_hash = aaaaaaaabbbbbbbbccccccccddddddddeeeeeeee"
+            )
+            .await?,
+        );
+        Ok(())
+    }
+
+    async fn git_external_cell_error(revision_config: &str) -> String {
+        initialize_external_cells_impl();
+
+        let buckconfig = indoc::formatdoc!(
+            r#"
+                [cells]
+                    root = .
+                    libfoo = foo/
+                [external_cells]
+                    libfoo = git
+                [external_cell_libfoo]
+                    git_origin = https://github.com/jeff/libfoo.git
+                {}
+            "#,
+            revision_config
+        );
+        let mut file_ops = TestConfigParserFileOps::new(&[(".buckconfig", &buckconfig)]).unwrap();
+
+        let e = BuckConfigBasedCells::testing_parse_with_file_ops(&mut file_ops, &[])
+            .await
+            .err()
+            .unwrap();
+        format!("{:?}", e)
+    }
+
+    #[tokio::test]
+    async fn test_git_external_cell_missing_revision() {
+        let e = git_external_cell_error("").await;
+        assert!(
+            e.contains("Missing buckconfig `external_cell_libfoo.commit_hash`, `external_cell_libfoo.git_ref` or `external_cell_libfoo.tag`"),
+            "error: {}",
+            e
+        );
+    }
+
+    #[tokio::test]
+    async fn test_git_external_cell_conflicting_revision() {
+        let e = git_external_cell_error(
+            "    commit_hash = aaaaaaaabbbbbbbbccccccccddddddddeeeeeeee\n    git_ref = main",
+        )
+        .await;
+        assert!(e.contains("Only one of buckconfig"), "error: {}", e);
+
+        let e = git_external_cell_error("    tag = v1.0\n    git_ref = main").await;
+        assert!(
+            e.contains(
+                "Only one of buckconfig `external_cell_libfoo.git_ref` and `external_cell_libfoo.tag`"
+            ),
+            "error: {}",
+            e
+        );
+    }
+
+    #[tokio::test]
+    async fn test_git_external_cell_invalid_ref() {
+        let e = git_external_cell_error("    git_ref = --upload-pack=evil").await;
+        assert!(
+            e.contains("Invalid git ref `--upload-pack=evil`"),
+            "error: {}",
+            e
+        );
+
+        let e = git_external_cell_error("    tag = release..v1").await;
+        assert!(
+            e.contains("Invalid git ref `release..v1` in buckconfig `external_cell_libfoo.tag`"),
+            "error: {}",
+            e
+        );
+    }
+
+    /// Reads `files` from disk, and everything else from `inner`.
+    struct WithGlobalFiles {
+        inner: TestConfigParserFileOps,
+        files: Vec<AbsPathBuf>,
+    }
+
+    #[async_trait::async_trait]
+    impl ConfigParserFileOps for WithGlobalFiles {
+        async fn read_file_lines_if_exists(
+            &mut self,
+            path: &ConfigPath,
+        ) -> buck2_error::Result<Option<Vec<String>>> {
+            match path {
+                ConfigPath::Global(path) if self.files.contains(path) => {
+                    Ok(fs_util::read_to_string_if_exists(path)?
+                        .map(|contents| contents.lines().map(ToOwned::to_owned).collect()))
+                }
+                _ => self.inner.read_file_lines_if_exists(path).await,
+            }
+        }
+
+        async fn read_dir(
+            &mut self,
+            path: &ConfigPath,
+        ) -> buck2_error::Result<Vec<ConfigDirEntry>> {
+            self.inner.read_dir(path).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_client_external_config() -> buck2_error::Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let file = AbsPathBuf::new(tempdir.path().join("buckconfig"))?;
+        fs_util::write(&file, "[apple]\n  key = env\n  key2 = env\n")?;
+
+        let mut file_ops = WithGlobalFiles {
+            inner: TestConfigParserFileOps::new(&[(
+                ".buckconfig",
+                indoc!(
+                    r#"
+                        [cells]
+                            root = .
+                        [apple]
+                            key2 = project
+                    "#
+                ),
+            )])?,
+            files: vec![file.clone()],
+        };
+
+        let cells = BuckConfigBasedCells::testing_parse_with_file_ops(
+            &mut file_ops,
+            &[ConfigOverride::external_file(file.to_str().unwrap())],
+        )
+        .await?;
+
+        assert_config_value(&cells.root_config, "apple", "key", "env");
+        // Project configs take precedence over external ones.
+        assert_config_value(&cells.root_config, "apple", "key2", "project");
+        assert!(
+            cells
+                .config_paths
+                .contains(&ConfigPath::Global(file.clone()))
+        );
+
+        Ok(())
+    }
+    #[tokio::test]
+    async fn test_strict_layering_conflicts() -> buck2_error::Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let first = AbsPathBuf::new(tempdir.path().join("first"))?;
+        let second = AbsPathBuf::new(tempdir.path().join("second"))?;
+        fs_util::write(&first, "[build]\n  foo = first\n  bar = same\n")?;
+        fs_util::write(&second, "[build]\n  foo = second\n  bar = same\n")?;
+
+        let mut file_ops = WithGlobalFiles {
+            inner: TestConfigParserFileOps::new(&[(
+                ".buckconfig",
+                indoc!(
+                    r#"
+                        [cells]
+                            root = .
+                    "#
+                ),
+            )])?,
+            files: vec![first.clone(), second.clone()],
+        };
+        let config_args = [
+            ConfigOverride::file(first.to_str().unwrap(), None),
+            ConfigOverride::file(second.to_str().unwrap(), None),
+        ];
+
+        let cells = BuckConfigBasedCells::parse_with_file_ops_and_options(
+            &mut file_ops,
+            &config_args,
+            true, /* follow includes */
+            true, /* strict layering */
+        )
+        .await?;
+        assert_eq!(
+            vec![ConfigLayeringConflict {
+                section: "build".to_owned(),
+                key: "foo".to_owned(),
+                overridden: ConfigPath::Global(first),
+                winner: ConfigPath::Global(second),
+            }],
+            cells.layering_conflicts
+        );
+        assert_config_value(&cells.root_config, "build", "foo", "second");
+
+        let cells =
+            BuckConfigBasedCells::testing_parse_with_file_ops(&mut file_ops, &config_args).await?;
+        assert_eq!(
+            Vec::<ConfigLayeringConflict>::new(),
+            cells.layering_conflicts
+        );
+
+        Ok(())
+    }
 }
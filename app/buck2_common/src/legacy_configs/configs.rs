@@ -49,6 +49,7 @@ pub(crate) enum ResolvedValue {
 #[derive(Debug, PartialEq, Eq, Allocative)]
 pub(crate) struct ConfigFileLocation {
     pub(crate) path: String,
+    pub(crate) config_path: ConfigPath,
     pub(crate) include_source: Option<Location>,
 }
 
@@ -135,7 +136,7 @@ pub(crate) struct ConfigValue {
     pub(crate) source: Location,
 }
 
-#[derive(Debug, Default, Allocative)]
+#[derive(Debug, Default, Clone, Allocative)]
 pub struct LegacyBuckConfigSection {
     pub(crate) values: SortedMap<String, ConfigValue>,
 }
@@ -195,6 +196,21 @@ impl Display for LegacyBuckConfigLocation<'_> {
     }
 }
 
+/// Where the value of a buckconfig key was last set.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConfigProvenance {
+    /// Set at `line` of a buckconfig file, which may have been included by another file.
+    File { path: ConfigPath, line: usize },
+    /// Set with a `--config` argument.
+    CommandLineArgument,
+}
+
+impl ConfigProvenance {
+    pub fn is_command_line_argument(&self) -> bool {
+        matches!(self, Self::CommandLineArgument)
+    }
+}
+
 impl<'a> LegacyBuckConfigValue<'a> {
     pub fn as_str(&self) -> &'a str {
         self.value.as_str()
@@ -213,6 +229,16 @@ impl<'a> LegacyBuckConfigValue<'a> {
         }
     }
 
+    pub fn provenance(&self) -> ConfigProvenance {
+        match &self.value.source {
+            Location::File(file) => ConfigProvenance::File {
+                path: file.source_file.config_path.clone(),
+                line: file.line,
+            },
+            Location::CommandLineArgument => ConfigProvenance::CommandLineArgument,
+        }
+    }
+
     pub fn location_stack(&self) -> Vec<LegacyBuckConfigLocation> {
         let mut res = Vec::new();
         let mut location = Some(&self.value.source);
@@ -275,8 +301,15 @@ impl LegacyBuckConfig {
         let mut external_path_configs = Vec::new();
         for main_config_file in config_paths {
             let mut parser = LegacyConfigParser::new();
+            // External files belong to no cell, cell paths in them are relative to the project root.
             parser
-                .parse_file(&main_config_file, None, follow_includes, file_ops)
+                .parse_file(
+                    &main_config_file,
+                    CellRootPath::new(ProjectRelativePath::empty()),
+                    None,
+                    follow_includes,
+                    file_ops,
+                )
                 .await?;
             external_path_configs.push(ExternalPathBuckconfigData {
                 origin_path: main_config_file.clone(),
@@ -297,7 +330,13 @@ impl LegacyBuckConfig {
         let mut parser = LegacyConfigParser::combine(external_path_configs);
         for main_config_file in main_config_files {
             parser
-                .parse_file(&main_config_file, None, follow_includes, file_ops)
+                .parse_file(
+                    &main_config_file,
+                    current_cell,
+                    None,
+                    follow_includes,
+                    file_ops,
+                )
                 .await?;
         }
 
@@ -310,6 +349,7 @@ impl LegacyBuckConfig {
                     parser
                         .parse_file(
                             &ConfigPath::Project(path.to_owned()),
+                            current_cell,
                             Some(Location::CommandLineArgument),
                             follow_includes,
                             file_ops,
@@ -432,6 +472,7 @@ pub(crate) mod tests {
     use super::testing::*;
     use super::*;
     use crate::legacy_configs::key::BuckconfigKeyRef;
+    use crate::legacy_configs::parser::DEFAULT_MAX_INCLUDE_DEPTH;
 
     pub(crate) fn assert_config_value(
         config: &LegacyBuckConfig,
@@ -728,6 +769,166 @@ pub(crate) mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_cell_includes() -> buck2_error::Result<()> {
+        let config = parse(
+            &[
+                (
+                    "shared/defaults.bcfg",
+                    indoc!(
+                        r#"
+                            [apple]
+                                key = from_shared
+                            <file:@other//nested.bcfg>
+                        "#
+                    ),
+                ),
+                (
+                    "other/nested.bcfg",
+                    indoc!(
+                        r#"
+                            [apple]
+                                nested = from_other
+                        "#
+                    ),
+                ),
+                (
+                    ".buckconfig",
+                    indoc!(
+                        r#"
+                            [cells]
+                                root = .
+                                shared = shared
+                                other = other/
+                            <file:@shared//defaults.bcfg>
+                            [apple]
+                                other_key = local
+                        "#
+                    ),
+                ),
+            ],
+            ".buckconfig",
+        )?;
+
+        assert_config_value(&config, "apple", "key", "from_shared");
+        assert_config_value(&config, "apple", "nested", "from_other");
+        assert_config_value(&config, "apple", "other_key", "local");
+        Ok(())
+    }
+
+    #[test]
+    fn test_cell_include_unknown_cell() {
+        let error = parse(
+            &[
+                ("shared/defaults.bcfg", "[apple]\n  key = value\n"),
+                (
+                    ".buckconfig",
+                    indoc!(
+                        r#"
+                            <file:@shared//defaults.bcfg>
+                            [cells]
+                                root = .
+                                shared = shared
+                        "#
+                    ),
+                ),
+            ],
+            ".buckconfig",
+        )
+        .unwrap_err();
+        let message = format!("{:#}", error);
+        assert!(
+            message.contains("Unknown cell `shared` in include directive `@shared//defaults.bcfg`"),
+            "{message}"
+        );
+    }
+
+    #[test]
+    fn test_include_cycle() {
+        let error = parse(
+            &[
+                ("a.bcfg", "<file:@root//b.bcfg>\n"),
+                ("b.bcfg", "<file:a.bcfg>\n"),
+                (
+                    ".buckconfig",
+                    indoc!(
+                        r#"
+                            [cells]
+                                root = .
+                            <file:a.bcfg>
+                        "#
+                    ),
+                ),
+            ],
+            ".buckconfig",
+        )
+        .unwrap_err();
+        let message = format!("{:#}", error);
+        assert!(
+            message.contains(
+                "Detected cycle in buckconfig includes: .buckconfig -> a.bcfg -> b.bcfg -> a.bcfg"
+            ),
+            "{message}"
+        );
+    }
+
+    #[test]
+    fn test_include_self() {
+        let error = parse(&[(".buckconfig", "<file:.buckconfig>\n")], ".buckconfig").unwrap_err();
+        let message = format!("{:#}", error);
+        assert!(
+            message.contains("Detected cycle in buckconfig includes: .buckconfig -> .buckconfig"),
+            "{message}"
+        );
+    }
+
+    #[test]
+    fn test_include_depth_limit() -> buck2_error::Result<()> {
+        // `.buckconfig` includes `1.bcfg`, which includes `2.bcfg`, and so on, `depth` files deep.
+        let parse_nested = |depth: usize| {
+            let mut files: Vec<(String, String)> = (0..depth)
+                .map(|i| {
+                    let path = match i {
+                        0 => ".buckconfig".to_owned(),
+                        i => format!("{}.bcfg", i),
+                    };
+                    (path, format!("<file:{}.bcfg>\n", i + 1))
+                })
+                .collect();
+            files.push((
+                format!("{}.bcfg", depth),
+                "[apple]\nkey = deep\n".to_owned(),
+            ));
+            let files: Vec<(&str, &str)> = files
+                .iter()
+                .map(|(path, contents)| (path.as_str(), contents.as_str()))
+                .collect();
+            parse(&files, ".buckconfig")
+        };
+
+        let config = parse_nested(DEFAULT_MAX_INCLUDE_DEPTH)?;
+        assert_config_value(&config, "apple", "key", "deep");
+
+        let message = format!(
+            "{:#}",
+            parse_nested(DEFAULT_MAX_INCLUDE_DEPTH + 1).unwrap_err()
+        );
+        assert!(
+            message.contains(&format!(
+                "Buckconfig includes are nested deeper than {} files",
+                DEFAULT_MAX_INCLUDE_DEPTH
+            )),
+            "{message}"
+        );
+        // The error names the whole chain.
+        let chain = [".buckconfig".to_owned()]
+            .into_iter()
+            .chain((1..=DEFAULT_MAX_INCLUDE_DEPTH + 1).map(|i| format!("{}.bcfg", i)))
+            .join(" -> ");
+        assert!(message.contains(&chain), "{message}");
+        Ok(())
+    }
+
     #[test]
     fn test_config_args_ordering() -> buck2_error::Result<()> {
         let config_args = vec![
@@ -0,0 +1,315 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Every full config parse looks up a fixed list of candidate buckconfig files and directories,
+//! most of which usually don't exist. On network home directories those lookups are slow even
+//! though the answer rarely changes, so we remember which candidates are missing and what the
+//! candidate directories contain.
+//!
+//! Project paths are trusted until the file watcher reports a change in the daemon, and for a
+//! short TTL in the client. Global paths are never watched, so they always use the TTL.
+
+use std::collections::HashMap;
+use std::sync::LazyLock;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use buck2_core::buck2_env;
+use parking_lot::Mutex;
+use tokio::time::Instant;
+
+use crate::legacy_configs::file_ops::ConfigDirEntry;
+use crate::legacy_configs::file_ops::ConfigParserFileOps;
+use crate::legacy_configs::file_ops::ConfigPath;
+
+/// How long lookups of paths that no file watcher reports changes to are trusted for.
+const UNWATCHED_PATH_TTL: Duration = Duration::from_secs(5);
+
+static PROCESS_CACHE: LazyLock<ConfigFileExistenceCache> =
+    LazyLock::new(|| ConfigFileExistenceCache::new(UNWATCHED_PATH_TTL));
+
+#[derive(Default)]
+struct CachedLookups {
+    /// Files that `read_file_lines_if_exists` found to be missing.
+    missing_files: HashMap<ConfigPath, Instant>,
+    /// Results of `read_dir`, which is empty for missing directories.
+    dirs: HashMap<ConfigPath, (Vec<ConfigDirEntry>, Instant)>,
+}
+
+/// Remembers which config files are missing and what config directories contain.
+pub struct ConfigFileExistenceCache {
+    lookups: Mutex<CachedLookups>,
+    ttl: Duration,
+    /// Set in the daemon, whose file watcher invalidates project paths when they change.
+    project_paths_watched: AtomicBool,
+}
+
+impl ConfigFileExistenceCache {
+    fn new(ttl: Duration) -> Self {
+        Self {
+            lookups: Mutex::new(CachedLookups::default()),
+            ttl,
+            project_paths_watched: AtomicBool::new(false),
+        }
+    }
+
+    /// The cache shared by all config parses in this process.
+    pub fn process() -> &'static Self {
+        &PROCESS_CACHE
+    }
+
+    /// The cache to use for a config parse, or `None` if the command asked not to use it.
+    pub fn process_unless_disabled(disable: bool) -> buck2_error::Result<Option<&'static Self>> {
+        if disable
+            || buck2_env!(
+                "BUCK2_TEST_DISABLE_CONFIG_FILE_CACHE",
+                bool,
+                applicability = testing
+            )?
+        {
+            return Ok(None);
+        }
+        Ok(Some(Self::process()))
+    }
+
+    /// Trust lookups of project paths until `invalidate_project_paths` is called. The caller
+    /// must arrange for it to be called whenever a file or directory in the project is added or
+    /// removed.
+    pub fn watch_project_paths(&self) {
+        // Anything looked up before the watcher started may already be stale.
+        self.invalidate_project_paths();
+        self.project_paths_watched.store(true, Ordering::Relaxed);
+    }
+
+    pub fn invalidate_project_paths(&self) {
+        let mut lookups = self.lookups.lock();
+        lookups
+            .missing_files
+            .retain(|path, _| matches!(path, ConfigPath::Global(_)));
+        lookups
+            .dirs
+            .retain(|path, _| matches!(path, ConfigPath::Global(_)));
+    }
+
+    fn is_fresh(&self, path: &ConfigPath, checked_at: Instant) -> bool {
+        match path {
+            ConfigPath::Project(_) if self.project_paths_watched.load(Ordering::Relaxed) => true,
+            _ => checked_at.elapsed() < self.ttl,
+        }
+    }
+
+    fn is_missing_file(&self, path: &ConfigPath) -> bool {
+        self.lookups
+            .lock()
+            .missing_files
+            .get(path)
+            .is_some_and(|checked_at| self.is_fresh(path, *checked_at))
+    }
+
+    fn dir(&self, path: &ConfigPath) -> Option<Vec<ConfigDirEntry>> {
+        match self.lookups.lock().dirs.get(path) {
+            Some((entries, checked_at)) if self.is_fresh(path, *checked_at) => {
+                Some(entries.clone())
+            }
+            _ => None,
+        }
+    }
+}
+
+/// File ops that answer lookups from a `ConfigFileExistenceCache` where possible. With no cache,
+/// every lookup goes to `inner`.
+pub(crate) struct CachedConfigParserFileOps<'a> {
+    pub(crate) inner: &'a mut dyn ConfigParserFileOps,
+    pub(crate) cache: Option<&'a ConfigFileExistenceCache>,
+}
+
+#[async_trait::async_trait]
+impl ConfigParserFileOps for CachedConfigParserFileOps<'_> {
+    async fn read_file_lines_if_exists(
+        &mut self,
+        path: &ConfigPath,
+    ) -> buck2_error::Result<Option<Vec<String>>> {
+        let Some(cache) = self.cache else {
+            return self.inner.read_file_lines_if_exists(path).await;
+        };
+        if cache.is_missing_file(path) {
+            return Ok(None);
+        }
+
+        let checked_at = Instant::now();
+        let res = self.inner.read_file_lines_if_exists(path).await?;
+        let mut lookups = cache.lookups.lock();
+        match res {
+            Some(_) => lookups.missing_files.remove(path),
+            None => lookups.missing_files.insert(path.clone(), checked_at),
+        };
+        Ok(res)
+    }
+
+    async fn read_dir(&mut self, path: &ConfigPath) -> buck2_error::Result<Vec<ConfigDirEntry>> {
+        let Some(cache) = self.cache else {
+            return self.inner.read_dir(path).await;
+        };
+        if let Some(entries) = cache.dir(path) {
+            return Ok(entries);
+        }
+
+        let checked_at = Instant::now();
+        let entries = self.inner.read_dir(path).await?;
+        cache
+            .lookups
+            .lock()
+            .dirs
+            .insert(path.clone(), (entries.clone(), checked_at));
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use buck2_core::fs::project_rel_path::ProjectRelativePath;
+    use indoc::indoc;
+
+    use super::*;
+    use crate::legacy_configs::cells::BuckConfigBasedCells;
+    use crate::legacy_configs::configs::testing::TestConfigParserFileOps;
+
+    /// Records every lookup that reaches the underlying file ops.
+    struct CountingFileOps {
+        inner: TestConfigParserFileOps,
+        lookups: Vec<ConfigPath>,
+    }
+
+    #[async_trait::async_trait]
+    impl ConfigParserFileOps for CountingFileOps {
+        async fn read_file_lines_if_exists(
+            &mut self,
+            path: &ConfigPath,
+        ) -> buck2_error::Result<Option<Vec<String>>> {
+            self.lookups.push(path.clone());
+            self.inner.read_file_lines_if_exists(path).await
+        }
+
+        async fn read_dir(
+            &mut self,
+            path: &ConfigPath,
+        ) -> buck2_error::Result<Vec<ConfigDirEntry>> {
+            self.lookups.push(path.clone());
+            self.inner.read_dir(path).await
+        }
+    }
+
+    impl CountingFileOps {
+        fn new() -> buck2_error::Result<Self> {
+            Ok(Self {
+                inner: TestConfigParserFileOps::new(&[(
+                    ".buckconfig",
+                    indoc!(
+                        r#"
+                            [cells]
+                                root = .
+                        "#
+                    ),
+                )])?,
+                lookups: Vec::new(),
+            })
+        }
+
+        /// Parse the cells, returning the lookups that reached the file ops.
+        async fn parse(
+            &mut self,
+            cache: &ConfigFileExistenceCache,
+        ) -> buck2_error::Result<Vec<ConfigPath>> {
+            BuckConfigBasedCells::testing_parse_with_file_ops(
+                &mut CachedConfigParserFileOps {
+                    inner: self,
+                    cache: Some(cache),
+                },
+                &[],
+            )
+            .await?;
+            Ok(std::mem::take(&mut self.lookups))
+        }
+    }
+
+    fn is_project(path: &ConfigPath) -> bool {
+        matches!(path, ConfigPath::Project(_))
+    }
+
+    fn only_existing_file() -> Vec<ConfigPath> {
+        vec![ConfigPath::Project(
+            ProjectRelativePath::unchecked_new(".buckconfig").to_owned(),
+        )]
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_unwatched_paths_expire() -> buck2_error::Result<()> {
+        let cache = ConfigFileExistenceCache::new(Duration::from_secs(5));
+        let mut file_ops = CountingFileOps::new()?;
+
+        let first = file_ops.parse(&cache).await?;
+        assert!(first.len() > 1);
+        assert!(first.iter().any(|p| !is_project(p)));
+
+        // Only the file that exists is read again.
+        assert_eq!(file_ops.parse(&cache).await?, only_existing_file());
+
+        tokio::time::advance(Duration::from_secs(5)).await;
+        assert_eq!(file_ops.parse(&cache).await?, first);
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_watched_project_paths_are_invalidated() -> buck2_error::Result<()> {
+        let cache = ConfigFileExistenceCache::new(Duration::from_secs(5));
+        cache.watch_project_paths();
+        let mut file_ops = CountingFileOps::new()?;
+
+        let first = file_ops.parse(&cache).await?;
+        assert_eq!(file_ops.parse(&cache).await?, only_existing_file());
+
+        // Global paths expire, but project paths are trusted until the file watcher reports a
+        // change.
+        tokio::time::advance(Duration::from_secs(5)).await;
+        let mut expected = only_existing_file();
+        expected.extend(first.iter().filter(|p| !is_project(p)).cloned());
+        let mut after_ttl = file_ops.parse(&cache).await?;
+        after_ttl.sort_by_key(|p| p.to_string());
+        expected.sort_by_key(|p| p.to_string());
+        assert_eq!(after_ttl, expected);
+
+        cache.invalidate_project_paths();
+        let mut after_invalidation = file_ops.parse(&cache).await?;
+        let mut expected = first.into_iter().filter(is_project).collect::<Vec<_>>();
+        after_invalidation.sort_by_key(|p| p.to_string());
+        expected.sort_by_key(|p| p.to_string());
+        assert_eq!(after_invalidation, expected);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_no_cache() -> buck2_error::Result<()> {
+        let mut file_ops = CountingFileOps::new()?;
+        let mut lookups = Vec::new();
+        for _ in 0..2 {
+            BuckConfigBasedCells::testing_parse_with_file_ops(
+                &mut CachedConfigParserFileOps {
+                    inner: &mut file_ops,
+                    cache: None,
+                },
+                &[],
+            )
+            .await?;
+            lookups.push(std::mem::take(&mut file_ops.lookups));
+        }
+        assert_eq!(lookups[0], lookups[1]);
+        Ok(())
+    }
+}
@@ -71,6 +71,7 @@ impl ConfigPath {
     }
 }
 
+#[derive(Clone)]
 pub struct ConfigDirEntry {
     pub(crate) name: FileNameBuf,
     pub(crate) is_dir: bool,
@@ -0,0 +1,448 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Support for the buckv1 names of the cell sections (`[repositories]` and
+//! `[repository_aliases]`), which are still accepted but deprecated in favour of `[cells]` and
+//! `[cell_aliases]`.
+
+use std::borrow::Cow;
+use std::fmt;
+use std::fmt::Display;
+
+use itertools::Itertools;
+use parking_lot::Mutex;
+
+use crate::legacy_configs::configs::LegacyBuckConfig;
+use crate::legacy_configs::configs::LegacyBuckConfigLocation;
+use crate::legacy_configs::configs::LegacyBuckConfigSection;
+
+/// Pairs of `(legacy, replacement)` section names.
+pub const LEGACY_CELL_SECTIONS: &[(&str, &str)] = &[
+    ("repositories", "cells"),
+    ("repository_aliases", "cell_aliases"),
+];
+
+#[derive(buck2_error::Error, Debug)]
+#[buck2(input)]
+enum LegacySectionError {
+    #[error(
+        "Buckconfig sections `[{replacement}]` (in {replacement_files}) and `[{legacy}]` (in {legacy_files}) set conflicting values:{conflicts}\nRemove `[{legacy}]`, it is deprecated"
+    )]
+    Conflict {
+        legacy: &'static str,
+        replacement: &'static str,
+        conflicts: String,
+        legacy_files: String,
+        replacement_files: String,
+    },
+}
+
+/// Emitted when a config uses a legacy cell section name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LegacySectionWarning {
+    pub legacy: &'static str,
+    pub replacement: &'static str,
+    /// The files that the legacy section was set in.
+    pub files: Vec<String>,
+}
+
+impl Display for LegacySectionWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Buckconfig section `[{}]` is deprecated, rename it to `[{}]`",
+            self.legacy, self.replacement
+        )?;
+        if !self.files.is_empty() {
+            write!(f, " (used in {})", format_files(&self.files))?;
+        }
+        Ok(())
+    }
+}
+
+/// Remembers which legacy section warnings were already shown. Cell configs are parsed again on
+/// every command, so without this the same warning would be repeated each time.
+pub(crate) struct LegacySectionWarningDedup {
+    shown: Mutex<Vec<LegacySectionWarning>>,
+}
+
+impl LegacySectionWarningDedup {
+    pub(crate) const fn new() -> Self {
+        Self {
+            shown: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Returns the warnings that haven't been shown yet, and records them as shown.
+    pub(crate) fn unseen(&self, warnings: Vec<LegacySectionWarning>) -> Vec<LegacySectionWarning> {
+        let mut shown = self.shown.lock();
+        let mut unseen = Vec::new();
+        for warning in warnings {
+            if !shown.contains(&warning) {
+                shown.push(warning.clone());
+                unseen.push(warning);
+            }
+        }
+        unseen
+    }
+}
+
+fn section_files(section: &LegacyBuckConfigSection) -> Vec<String> {
+    section
+        .iter()
+        .map(|(_, value)| match value.location() {
+            LegacyBuckConfigLocation::File(path, _) => path.to_owned(),
+            LegacyBuckConfigLocation::CommandLineArgument => "the command line".to_owned(),
+        })
+        .unique()
+        .collect()
+}
+
+fn format_files(files: &[String]) -> String {
+    if files.is_empty() {
+        return "an empty section".to_owned();
+    }
+    files.iter().map(|f| format!("`{}`", f)).join(", ")
+}
+
+/// Get the section `replacement`, falling back to its deprecated name `legacy`.
+///
+/// Using the legacy name records a warning in `warnings`. If both sections are present and set a
+/// key to different values this is an error; otherwise the keys of both sections are merged.
+pub(crate) fn get_section_with_legacy_fallback<'a>(
+    config: &'a LegacyBuckConfig,
+    replacement: &'static str,
+    legacy: &'static str,
+    warnings: &mut Vec<LegacySectionWarning>,
+) -> buck2_error::Result<Option<Cow<'a, LegacyBuckConfigSection>>> {
+    let Some(legacy_section) = config.get_section(legacy) else {
+        return Ok(config.get_section(replacement).map(Cow::Borrowed));
+    };
+
+    warnings.push(LegacySectionWarning {
+        legacy,
+        replacement,
+        files: section_files(legacy_section),
+    });
+
+    let Some(replacement_section) = config.get_section(replacement) else {
+        return Ok(Some(Cow::Borrowed(legacy_section)));
+    };
+
+    let conflicts: Vec<String> = replacement_section
+        .iter()
+        .filter_map(|(key, value)| {
+            let legacy_value = legacy_section.get(key)?;
+            (legacy_value.as_str() != value.as_str()).then(|| {
+                format!(
+                    "\n  `{}` is `{}` in `[{}]` and `{}` in `[{}]`",
+                    key,
+                    value.as_str(),
+                    replacement,
+                    legacy_value.as_str(),
+                    legacy
+                )
+            })
+        })
+        .collect();
+    if !conflicts.is_empty() {
+        return Err(LegacySectionError::Conflict {
+            legacy,
+            replacement,
+            conflicts: conflicts.concat(),
+            legacy_files: format_files(&section_files(legacy_section)),
+            replacement_files: format_files(&section_files(replacement_section)),
+        }
+        .into());
+    }
+
+    Ok(Some(Cow::Owned(LegacyBuckConfigSection {
+        values: legacy_section
+            .values
+            .iter()
+            .chain(replacement_section.values.iter())
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect(),
+    })))
+}
+
+/// Rewrite the headers of legacy cell sections in the contents of a buckconfig file to their
+/// replacement names.
+///
+/// Only the section name is changed; comments, whitespace and line endings are preserved.
+pub fn rewrite_legacy_cell_sections(contents: &str) -> String {
+    let mut res = String::with_capacity(contents.len());
+    for line in contents.split_inclusive('\n') {
+        res.push_str(&rewrite_section_header(line).unwrap_or_else(|| line.to_owned()));
+    }
+    res
+}
+
+fn rewrite_section_header(line: &str) -> Option<String> {
+    let indent = line.len() - line.trim_start().len();
+    let rest = line[indent..].strip_prefix('[')?;
+    let end = rest.find(']')?;
+    let name = &rest[..end];
+    let (legacy, replacement) = LEGACY_CELL_SECTIONS
+        .iter()
+        .find(|(legacy, _)| *legacy == name)?;
+    let name_start = indent + 1;
+    Some(format!(
+        "{}{}{}",
+        &line[..name_start],
+        replacement,
+        &line[name_start + legacy.len()..]
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use indoc::indoc;
+
+    use super::*;
+    use crate::legacy_configs::configs::testing::parse;
+
+    #[test]
+    fn test_legacy_section_warns() -> buck2_error::Result<()> {
+        let config = parse(
+            &[(
+                "config",
+                indoc!(
+                    r#"
+                        [repositories]
+                            root = .
+                    "#
+                ),
+            )],
+            "config",
+        )?;
+
+        let mut warnings = Vec::new();
+        let section =
+            get_section_with_legacy_fallback(&config, "cells", "repositories", &mut warnings)?;
+        assert_eq!(section.unwrap().get("root").unwrap().as_str(), ".");
+        assert_eq!(
+            warnings,
+            vec![LegacySectionWarning {
+                legacy: "repositories",
+                replacement: "cells",
+                files: vec!["config".to_owned()],
+            }]
+        );
+        assert_eq!(
+            warnings[0].to_string(),
+            "Buckconfig section `[repositories]` is deprecated, rename it to `[cells]` (used in `config`)"
+        );
+
+        let mut warnings = Vec::new();
+        get_section_with_legacy_fallback(
+            &config,
+            "cell_aliases",
+            "repository_aliases",
+            &mut warnings,
+        )?;
+        assert_eq!(warnings, Vec::new());
+        Ok(())
+    }
+
+    #[test]
+    fn test_legacy_section_warning_dedup() {
+        let warning = |legacy, replacement, file: &str| LegacySectionWarning {
+            legacy,
+            replacement,
+            files: vec![file.to_owned()],
+        };
+        let dedup = LegacySectionWarningDedup::new();
+
+        assert_eq!(
+            dedup.unseen(vec![warning("repositories", "cells", "config")]),
+            vec![warning("repositories", "cells", "config")]
+        );
+        // Parsing the same config again doesn't repeat the warning.
+        assert_eq!(
+            dedup.unseen(vec![warning("repositories", "cells", "config")]),
+            Vec::new()
+        );
+        // Other sections and other files are still warned about.
+        assert_eq!(
+            dedup.unseen(vec![
+                warning("repositories", "cells", "config"),
+                warning("repository_aliases", "cell_aliases", "config"),
+                warning("repositories", "cells", "other"),
+            ]),
+            vec![
+                warning("repository_aliases", "cell_aliases", "config"),
+                warning("repositories", "cells", "other"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_legacy_section_conflict() -> buck2_error::Result<()> {
+        let config = parse(
+            &[
+                (
+                    "config",
+                    indoc!(
+                        r#"
+                            [cell_aliases]
+                                a = root
+                                b = root
+                            <file:other>
+                        "#
+                    ),
+                ),
+                (
+                    "other",
+                    indoc!(
+                        r#"
+                            [repository_aliases]
+                                a = root
+                                b = other
+                        "#
+                    ),
+                ),
+            ],
+            "config",
+        )?;
+
+        let mut warnings = Vec::new();
+        let err = get_section_with_legacy_fallback(
+            &config,
+            "cell_aliases",
+            "repository_aliases",
+            &mut warnings,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            indoc!(
+                r#"
+                    Buckconfig sections `[cell_aliases]` (in `config`) and `[repository_aliases]` (in `other`) set conflicting values:
+                      `b` is `root` in `[cell_aliases]` and `other` in `[repository_aliases]`
+                    Remove `[repository_aliases]`, it is deprecated"#
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_legacy_section_merged() -> buck2_error::Result<()> {
+        let config = parse(
+            &[(
+                "config",
+                indoc!(
+                    r#"
+                        [cells]
+                            root = .
+                            prelude = prelude
+                        [repositories]
+                            root = .
+                            other = other
+                    "#
+                ),
+            )],
+            "config",
+        )?;
+
+        let mut warnings = Vec::new();
+        let section =
+            get_section_with_legacy_fallback(&config, "cells", "repositories", &mut warnings)?
+                .unwrap();
+        assert_eq!(
+            section
+                .iter()
+                .map(|(key, value)| (key, value.as_str()))
+                .collect::<Vec<_>>(),
+            vec![("other", "other"), ("prelude", "prelude"), ("root", ".")]
+        );
+        assert_eq!(1, warnings.len());
+        Ok(())
+    }
+
+    #[test]
+    fn test_replacement_section_only() -> buck2_error::Result<()> {
+        let config = parse(
+            &[(
+                "config",
+                indoc!(
+                    r#"
+                        [cells]
+                            root = .
+                    "#
+                ),
+            )],
+            "config",
+        )?;
+
+        let mut warnings = Vec::new();
+        let section =
+            get_section_with_legacy_fallback(&config, "cells", "repositories", &mut warnings)?;
+        assert_eq!(section.unwrap().get("root").unwrap().as_str(), ".");
+        assert_eq!(warnings, Vec::new());
+        Ok(())
+    }
+
+    #[test]
+    fn test_rewrite_legacy_cell_sections() -> buck2_error::Result<()> {
+        let original = indoc!(
+            r#"
+                # The cells of this project
+                [repositories] # oss-disable
+                    root = .
+                    ; repositories below here
+                    prelude = prelude
+
+                  [repository_aliases]
+                    config = prelude
+
+                [repositories_extra]
+                    key = value
+            "#
+        );
+        let rewritten = rewrite_legacy_cell_sections(original);
+        assert_eq!(
+            rewritten,
+            indoc!(
+                r#"
+                    # The cells of this project
+                    [cells] # oss-disable
+                        root = .
+                        ; repositories below here
+                        prelude = prelude
+
+                      [cell_aliases]
+                        config = prelude
+
+                    [repositories_extra]
+                        key = value
+                "#
+            )
+        );
+        assert_eq!(rewrite_legacy_cell_sections(&rewritten), rewritten);
+
+        let (original, rewritten) = (
+            parse(&[("config", original)], "config")?,
+            parse(&[("config", &rewritten)], "config")?,
+        );
+        assert_eq!(
+            original
+                .get_section("repositories")
+                .unwrap()
+                .keys()
+                .collect::<Vec<_>>(),
+            rewritten
+                .get_section("cells")
+                .unwrap()
+                .keys()
+                .collect::<Vec<_>>(),
+        );
+        Ok(())
+    }
+}
@@ -11,9 +11,11 @@ use std::collections::BTreeMap;
 use std::sync::Arc;
 
 use allocative::Allocative;
+use buck2_core::buck2_env;
 use buck2_core::cells::cell_root_path::CellRootPath;
 use buck2_core::fs::paths::RelativePath;
 use buck2_core::fs::paths::abs_norm_path::AbsNormPath;
+use buck2_core::fs::paths::forward_rel_path::ForwardRelativePath;
 use buck2_error::BuckErrorContext;
 use dupe::Dupe;
 use futures::FutureExt;
@@ -57,6 +59,17 @@ enum ConfigError {
     InvalidLine(String),
     #[error("Detected cycles in buckconfig $(config) references: {}", format_cycle(.0))]
     ReferenceCycle(Vec<(String, String)>),
+    #[error("Detected cycle in buckconfig includes: {}", .0.join(" -> "))]
+    IncludeCycle(Vec<String>),
+    #[error(
+        "Buckconfig includes are nested deeper than {0} files, set `BUCK2_MAX_BUCKCONFIG_INCLUDE_DEPTH` to raise the limit: {}",
+        .1.join(" -> ")
+    )]
+    IncludeDepthExceeded(usize, Vec<String>),
+    #[error(
+        "Unknown cell `{0}` in include directive `{1}`. Cells used in includes must be defined in a `[cells]` section before the include"
+    )]
+    UnknownIncludeCell(String, String),
 }
 
 fn format_cycle(cycle: &[(String, String)]) -> String {
@@ -89,7 +102,11 @@ pub(crate) struct LegacyConfigParser {
 
 /// Represents the state associated with parsing a single file into a buckconfig.
 struct LegacyConfigFileParser<'p> {
+    /// Root of the cell the config belongs to, which cell paths in `[cells]` are relative to.
+    cell_root: &'p CellRootPath,
     include_stack: Vec<ConfigFileLocationWithLine>,
+    /// How many includes may be nested in the file being parsed.
+    max_include_depth: usize,
     current_file: Option<Arc<ConfigFileLocation>>,
     current_section: (String, BTreeMap<String, ConfigValue>),
     values: &'p mut LegacyConfigParser,
@@ -101,9 +118,13 @@ struct LegacyConfigFileParser<'p> {
 ///   <?file:/optional/absolute>
 ///   <file:relative/to/current>
 ///   <file:../../doesnt/need/to/be/forward/relative>
+///   <file:@cell//relative/to/cell>
 static FILE_INCLUDE: Lazy<Regex> =
     Lazy::new(|| Regex::new("<(?P<optional>\\?)?file:(?P<include>..*)>").unwrap());
 
+/// How many includes may be nested, unless overridden by `BUCK2_MAX_BUCKCONFIG_INCLUDE_DEPTH`.
+pub(crate) const DEFAULT_MAX_INCLUDE_DEPTH: usize = 32;
+
 impl LegacyConfigParser {
     pub(crate) fn new() -> Self {
         LegacyConfigParser {
@@ -114,11 +135,14 @@ impl LegacyConfigParser {
     pub(crate) async fn parse_file(
         &mut self,
         path: &ConfigPath,
+        cell_root: &CellRootPath,
         source: Option<Location>,
         follow_includes: bool,
         file_ops: &mut dyn ConfigParserFileOps,
     ) -> buck2_error::Result<()> {
-        let mut file_parser = LegacyConfigFileParser::new(self);
+        let max_include_depth = buck2_env!("BUCK2_MAX_BUCKCONFIG_INCLUDE_DEPTH", type = usize)?
+            .unwrap_or(DEFAULT_MAX_INCLUDE_DEPTH);
+        let mut file_parser = LegacyConfigFileParser::new(self, cell_root, max_include_depth);
         file_parser.start_file(path, source)?;
         file_parser
             .parse_file_on_stack(path, follow_includes, file_ops)
@@ -180,6 +204,16 @@ impl LegacyConfigParser {
         }
     }
 
+    /// The values set so far, as `(section, key, raw value)`.
+    pub(crate) fn raw_values(&self) -> impl Iterator<Item = (&str, &str, &str)> {
+        self.values.iter().flat_map(|(section, section_builder)| {
+            section_builder
+                .values
+                .iter()
+                .map(move |(key, value)| (section.as_str(), key.as_str(), value.raw_value()))
+        })
+    }
+
     pub(crate) fn filter_values<F>(mut self, filter: F) -> Self
     where
         F: Fn(&BuckconfigKeyRef) -> bool,
@@ -223,9 +257,15 @@ impl LegacyConfigParser {
 }
 
 impl<'p> LegacyConfigFileParser<'p> {
-    fn new(values: &'p mut LegacyConfigParser) -> Self {
+    fn new(
+        values: &'p mut LegacyConfigParser,
+        cell_root: &'p CellRootPath,
+        max_include_depth: usize,
+    ) -> Self {
         LegacyConfigFileParser {
+            cell_root,
             include_stack: Vec::new(),
+            max_include_depth,
             current_file: None,
             current_section: Self::unspecified_section(),
             values,
@@ -237,6 +277,22 @@ impl<'p> LegacyConfigFileParser<'p> {
     }
 
     fn push_file(&mut self, line: usize, path: &ConfigPath) -> buck2_error::Result<()> {
+        let including = self
+            .include_stack
+            .iter()
+            .map(|loc| &loc.source_file)
+            .chain(&self.current_file);
+        if including.clone().any(|file| &file.config_path == path) {
+            let mut cycle: Vec<String> = including.map(|file| file.path.clone()).collect();
+            cycle.push(path.to_string());
+            return Err(ConfigError::IncludeCycle(cycle).into());
+        }
+        if self.include_stack.len() >= self.max_include_depth {
+            let mut chain: Vec<String> = including.map(|file| file.path.clone()).collect();
+            chain.push(path.to_string());
+            return Err(ConfigError::IncludeDepthExceeded(self.max_include_depth, chain).into());
+        }
+
         let include_source = ConfigFileLocationWithLine {
                 source_file: self.current_file.dupe().unwrap_or_else(|| panic!("push_file() called without any files on the include stack. top-level files should use start_file()")),
                 line,
@@ -246,6 +302,7 @@ impl<'p> LegacyConfigFileParser<'p> {
 
         let source_file = Arc::new(ConfigFileLocation {
             path: path.to_string(),
+            config_path: path.clone(),
             include_source: Some(Location::File(include_source)),
         });
         self.current_file = Some(source_file);
@@ -259,6 +316,7 @@ impl<'p> LegacyConfigFileParser<'p> {
     ) -> buck2_error::Result<()> {
         let source_file = Arc::new(ConfigFileLocation {
             path: path.to_string(),
+            config_path: path.clone(),
             include_source: source,
         });
         self.current_file = Some(source_file);
@@ -383,7 +441,9 @@ impl<'p> LegacyConfigFileParser<'p> {
                     };
                     let optional = m.name("optional").is_some();
                     // Note: Using `AbsNormPath` to preserve existing behavior of requiring normalized paths
-                    let include_file = if let Ok(absolute) = AbsNormPath::new(include) {
+                    let include_file = if let Some(cell_relative) = include.strip_prefix('@') {
+                        self.resolve_cell_include(include, cell_relative)?
+                    } else if let Ok(absolute) = AbsNormPath::new(include) {
                         ConfigPath::Global(absolute.to_owned().into_abs_path_buf())
                     } else {
                         let relative = RelativePath::new(include);
@@ -412,6 +472,51 @@ impl<'p> LegacyConfigFileParser<'p> {
         Ok(())
     }
 
+    /// Resolves an `@cell//path` include through the cells defined so far.
+    fn resolve_cell_include(
+        &self,
+        include: &str,
+        cell_relative: &str,
+    ) -> buck2_error::Result<ConfigPath> {
+        let Some((cell, path)) = cell_relative.split_once("//") else {
+            return Err(ConfigError::BadIncludePath(include.to_owned()).into());
+        };
+        let Some(cell_path) = self.cell_definition(cell) else {
+            return Err(
+                ConfigError::UnknownIncludeCell(cell.to_owned(), include.to_owned()).into(),
+            );
+        };
+        let Ok(path) = ForwardRelativePath::new(path) else {
+            return Err(ConfigError::BadIncludePath(include.to_owned()).into());
+        };
+        let cell_dir = self
+            .cell_root
+            .as_project_relative_path()
+            .join_normalized(RelativePath::new(cell_path))
+            .with_buck_error_context(|| {
+                format!(
+                    "Invalid path `{}` of cell `{}` in `{}`",
+                    cell_path, cell, include
+                )
+            })?;
+        Ok(ConfigPath::Project(cell_dir.join(path)))
+    }
+
+    /// Path of `cell` relative to the cell root, if defined in `[cells]` (or the legacy
+    /// `[repositories]`) by what was parsed so far, including the currently open section.
+    fn cell_definition(&self, cell: &str) -> Option<&str> {
+        ["cells", "repositories"].into_iter().find_map(|section| {
+            let current = if self.current_section.0 == section {
+                self.current_section.1.get(cell)
+            } else {
+                None
+            };
+            current
+                .or_else(|| self.values.values.get(section)?.values.get(cell))
+                .map(|value| value.raw_value())
+        })
+    }
+
     fn commit_section(&mut self, section: (String, BTreeMap<String, ConfigValue>)) {
         let (section, values) = section;
         // Commit the previous section.
@@ -21,6 +21,10 @@ pub(crate) enum ExternalConfigSource {
 
     // Global buckconfig folder, assuming all files in this folder are buckconfig. Repo related config is not allowed
     GlobalFolder(&'static str),
+
+    // Buckconfig files the client passed as `EXTERNAL_FILE` config overrides, from its
+    // `BUCK2_EXTERNAL_BUCKCONFIG` environment variable
+    ClientFile,
 }
 
 pub(crate) enum ProjectConfigSource {
@@ -47,6 +51,7 @@ pub(crate) static DEFAULT_EXTERNAL_CONFIG_SOURCES: &[ExternalConfigSource] = &[
     ExternalConfigSource::GlobalFile("C:\\ProgramData\\buckconfig"),
     ExternalConfigSource::UserFolder(DOT_BUCKCONFIG_D),
     ExternalConfigSource::UserFile(DOT_BUCKCONFIG_LOCAL),
+    ExternalConfigSource::ClientFile,
 ];
 
 pub(crate) static DEFAULT_PROJECT_CONFIG_SOURCES: &[ProjectConfigSource] = &[
@@ -16,6 +16,7 @@ use parking_lot::Mutex;
 use rusqlite::Connection;
 
 /// A generic sqlite table for storing string key-value pairs.
+#[derive(Clone)]
 pub struct KeyValueSqliteTable {
     table_name: String,
     connection: Arc<Mutex<Connection>>,
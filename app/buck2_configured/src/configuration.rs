@@ -7,6 +7,8 @@
  * of this source tree.
  */
 
+use std::sync::Arc;
+
 use allocative::Allocative;
 use async_trait::async_trait;
 use buck2_build_api::analysis::calculation::RuleAnalysisCalculation;
@@ -271,54 +273,111 @@ impl Key for ConfigurationNodeKey {
     }
 }
 
+#[derive(derive_more::Display, Debug, Eq, Hash, PartialEq, Clone, Allocative)]
+struct PlatformConfigurationKey(TargetLabel);
+
+#[async_trait]
+impl Key for PlatformConfigurationKey {
+    type Value = buck2_error::Result<ConfigurationData>;
+
+    async fn compute(
+        &self,
+        ctx: &mut DiceComputations,
+        _cancellation: &CancellationContext,
+    ) -> Self::Value {
+        compute_platform_configuration(ctx, &self.0)
+            .await
+            .map_err(buck2_error::Error::from)
+    }
+
+    fn equality(x: &Self::Value, y: &Self::Value) -> bool {
+        match (x, y) {
+            (Ok(x), Ok(y)) => x == y,
+            _ => false,
+        }
+    }
+}
+
 pub(crate) async fn get_platform_configuration(
     ctx: &mut DiceComputations<'_>,
     target: &TargetLabel,
 ) -> buck2_error::Result<ConfigurationData> {
-    #[derive(derive_more::Display, Debug, Eq, Hash, PartialEq, Clone, Allocative)]
-    struct PlatformConfigurationKey(TargetLabel);
-
-    #[async_trait]
-    impl Key for PlatformConfigurationKey {
-        type Value = buck2_error::Result<ConfigurationData>;
-
-        async fn compute(
-            &self,
-            ctx: &mut DiceComputations,
-            _cancellation: &CancellationContext,
-        ) -> Self::Value {
-            compute_platform_configuration(ctx, &self.0)
-                .await
-                .map_err(buck2_error::Error::from)
+    ctx.compute(&PlatformConfigurationKey(target.dupe()))
+        .await?
+        .map_err(buck2_error::Error::from)
+}
+
+/// The configurations of the platforms in `platforms`, which are sorted and deduplicated so that
+/// all the targets depending on the same platforms share this key. The resulting map is therefore
+/// ordered by platform label rather than by declaration order; it's only used for lookups.
+#[derive(Clone, Display, Debug, Eq, Hash, PartialEq, Allocative)]
+#[display("PlatformConfigurations({} platforms)", platforms.len())]
+struct PlatformConfigurationsKey {
+    platforms: Arc<[TargetLabel]>,
+}
+
+impl PlatformConfigurationsKey {
+    fn new<'a>(platforms: impl IntoIterator<Item = &'a TargetLabel>) -> Self {
+        let mut platforms: Vec<TargetLabel> = platforms.into_iter().map(|t| t.dupe()).collect();
+        platforms.sort();
+        platforms.dedup();
+        PlatformConfigurationsKey {
+            platforms: platforms.into(),
         }
+    }
+}
 
-        fn equality(x: &Self::Value, y: &Self::Value) -> bool {
-            match (x, y) {
-                (Ok(x), Ok(y)) => x == y,
-                _ => false,
-            }
+#[async_trait]
+impl Key for PlatformConfigurationsKey {
+    type Value = buck2_error::Result<Arc<OrderedMap<TargetLabel, ConfigurationData>>>;
+
+    async fn compute(
+        &self,
+        ctx: &mut DiceComputations,
+        _cancellation: &CancellationContext,
+    ) -> Self::Value {
+        let configurations = ctx
+            .compute_join(self.platforms.iter(), |ctx, platform| {
+                async move {
+                    (
+                        platform.dupe(),
+                        get_platform_configuration(ctx, platform).await,
+                    )
+                }
+                .boxed()
+            })
+            .await;
+
+        let mut platform_map = OrderedMap::with_capacity(configurations.len());
+        for (platform, configuration) in configurations {
+            platform_map.insert(platform, configuration?);
         }
+        Ok(Arc::new(platform_map))
     }
 
-    ctx.compute(&PlatformConfigurationKey(target.dupe()))
-        .await?
-        .map_err(buck2_error::Error::from)
+    fn equality(x: &Self::Value, y: &Self::Value) -> bool {
+        match (x, y) {
+            (Ok(x), Ok(y)) => x == y,
+            _ => false,
+        }
+    }
 }
 
 pub(crate) async fn compute_platform_cfgs(
     ctx: &mut DiceComputations<'_>,
     node: TargetNodeRef<'_>,
-) -> buck2_error::Result<OrderedMap<TargetLabel, ConfigurationData>> {
-    let mut platform_map = OrderedMap::new();
-    for (platform_target, kind) in node.get_configuration_deps_with_kind() {
-        if kind == ConfigurationDepKind::ConfiguredDepPlatform {
-            let platform_target = platform_target.target();
-            let config = get_platform_configuration(ctx, platform_target).await?;
-            platform_map.insert(platform_target.dupe(), config);
-        }
+) -> buck2_error::Result<Arc<OrderedMap<TargetLabel, ConfigurationData>>> {
+    let key = PlatformConfigurationsKey::new(node.get_configuration_deps_with_kind().filter_map(
+        |(platform_target, kind)| {
+            (kind == ConfigurationDepKind::ConfiguredDepPlatform)
+                .then_some(platform_target.target())
+        },
+    ));
+    if key.platforms.is_empty() {
+        // Most targets don't depend on platforms, no need for a DICE node.
+        return Ok(Arc::new(OrderedMap::new()));
     }
-
-    Ok(platform_map)
+    ctx.compute(&key).await?
 }
 
 pub(crate) async fn get_matched_cfg_keys<
@@ -377,3 +436,73 @@ impl ConfigurationCalculationDyn for ConfigurationCalculationDynImpl {
 pub(crate) fn init_configuration_calculation() {
     CONFIGURATION_CALCULATION.init(&ConfigurationCalculationDynImpl);
 }
+
+#[cfg(test)]
+mod tests {
+    use dice::UserComputationData;
+    use dice::testing::DiceBuilder;
+
+    use super::*;
+    use crate::test_utils::ComputeCounter;
+
+    #[tokio::test]
+    async fn test_platform_configurations_computed_once() {
+        let labels = |labels: &[&str]| {
+            labels
+                .iter()
+                .map(|label| TargetLabel::testing_parse(label))
+                .collect::<Vec<_>>()
+        };
+        let [a, b] = ["root//platforms:a", "root//platforms:b"].map(TargetLabel::testing_parse);
+
+        let computed = ComputeCounter::new::<PlatformConfigurationsKey>();
+        let mut ctx = DiceBuilder::new()
+            .mock_and_return(
+                PlatformConfigurationKey(a.dupe()),
+                Ok(ConfigurationData::testing_new()),
+            )
+            .mock_and_return(
+                PlatformConfigurationKey(b.dupe()),
+                Ok(ConfigurationData::testing_new()),
+            )
+            .build(UserComputationData {
+                tracker: computed.dupe(),
+                ..Default::default()
+            })
+            .unwrap()
+            .commit()
+            .await;
+
+        // Two targets depending on the same platforms, in different orders, share the
+        // configurations.
+        let x = ctx
+            .compute(&PlatformConfigurationsKey::new(&labels(&[
+                "root//platforms:b",
+                "root//platforms:a",
+                "root//platforms:b",
+            ])))
+            .await
+            .unwrap()
+            .unwrap();
+        let y = ctx
+            .compute(&PlatformConfigurationsKey::new(&labels(&[
+                "root//platforms:a",
+                "root//platforms:b",
+            ])))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(Arc::ptr_eq(&x, &y));
+        assert_eq!(computed.computed(), 1);
+        // Ordered by label, not by declaration.
+        assert_eq!(vec![&a, &b], x.keys().collect::<Vec<_>>());
+
+        let z = ctx
+            .compute(&PlatformConfigurationsKey::new(&[a.dupe()]))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!Arc::ptr_eq(&x, &z));
+        assert_eq!(computed.computed(), 2);
+    }
+}
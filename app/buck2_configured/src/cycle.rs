@@ -9,9 +9,13 @@
 
 use std::sync::Arc;
 
+use allocative::Allocative;
 use buck2_common::dice::cycles::CycleAdapterDescriptor;
+use buck2_common::events::HasEvents;
+use buck2_node::nodes::configured_graph_cycle_events::HasConfiguredGraphCycleEvents;
 use buck2_util::cycle_detector::CycleDescriptor;
 use derive_more::Display;
+use dice::DiceComputations;
 use dice::DynKey;
 use dupe::Dupe;
 use gazebo::prelude::*;
@@ -27,6 +31,55 @@ pub struct ConfiguredGraphCycleError {
     cycle: Arc<Vec<ConfiguredGraphCycleKeys>>,
 }
 
+impl ConfiguredGraphCycleError {
+    /// Converts to an error that remembers the cycle, so that it is reported even when the error
+    /// is served from the DICE cache. See `report_configured_graph_cycle`.
+    pub(crate) fn into_reportable(self) -> buck2_error::Error {
+        let participants = ConfiguredGraphCycleParticipants(
+            self.cycle.iter().map(|key| key.to_string()).collect(),
+        );
+        buck2_error::Error::from(self).context(participants)
+    }
+}
+
+/// The participants of the configured graph cycle an error comes from.
+#[derive(Debug, Allocative, Eq, PartialEq)]
+struct ConfiguredGraphCycleParticipants(Vec<String>);
+
+impl std::fmt::Display for ConfiguredGraphCycleParticipants {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0.join(" -> "))
+    }
+}
+
+impl buck2_error::TypedContext for ConfiguredGraphCycleParticipants {
+    fn eq(&self, other: &dyn buck2_error::TypedContext) -> bool {
+        match (other as &dyn std::any::Any).downcast_ref::<Self>() {
+            Some(v) => self == v,
+            None => false,
+        }
+    }
+
+    fn should_display(&self) -> bool {
+        false
+    }
+}
+
+/// Reports the configured graph cycle `error` comes from, if any, in the event log of the current
+/// command, if it wasn't already.
+pub(crate) fn report_configured_graph_cycle(
+    ctx: &DiceComputations<'_>,
+    error: &buck2_error::Error,
+) {
+    let Some(cycle) = error.find_typed_context::<ConfiguredGraphCycleParticipants>() else {
+        return;
+    };
+    let data = ctx.per_transaction_data();
+    if let Some(events) = data.get_configured_graph_cycle_events() {
+        events.report(data.get_dispatcher(), cycle.0.clone());
+    }
+}
+
 fn display_configured_graph_cycle_error(cycle: &[ConfiguredGraphCycleKeys]) -> String {
     use std::fmt::Write;
 
@@ -17,6 +17,8 @@ use buck2_build_api::interpreter::rule_defs::provider::builtin::execution_platfo
 use buck2_common::dice::cells::HasCellResolver;
 use buck2_common::legacy_configs::dice::HasLegacyConfigs;
 use buck2_core::configuration::compatibility::MaybeCompatible;
+use buck2_core::configuration::constraints::ConstraintKey;
+use buck2_core::configuration::constraints::ConstraintValue;
 use buck2_core::configuration::data::ConfigurationData;
 use buck2_core::configuration::pair::ConfigurationNoExec;
 use buck2_core::execution_types::execution::ExecutionPlatform;
@@ -50,6 +52,7 @@ use derive_more::Display;
 use dice::DiceComputations;
 use dice::Key;
 use dupe::Dupe;
+use dupe::OptionDupedExt;
 use itertools::Itertools;
 use starlark_map::ordered_map::OrderedMap;
 
@@ -63,8 +66,8 @@ use crate::nodes::LookingUpConfiguredNodeContext;
 #[derive(Debug, buck2_error::Error)]
 #[buck2(input)]
 enum ExecutionPlatformComputationError {
-    #[error("Can't find toolchain_dep execution platform using configuration `{0}`")]
-    ToolchainDepMissingPlatform(ConfigurationData),
+    #[error("Can't find toolchain_dep execution platform using configuration `{0}`{1}")]
+    ToolchainDepMissingPlatform(ConfigurationData, NearestPlatforms),
     #[error("Target `{0}` has a transition_dep, which is not permitted on a toolchain rule")]
     ToolchainTransitionDep(TargetLabel),
     #[error(
@@ -96,13 +99,95 @@ pub async fn find_execution_platform_by_configuration(
                 }
             }
             Err(buck2_error::Error::from(
-                ExecutionPlatformComputationError::ToolchainDepMissingPlatform(exec_cfg.dupe()),
+                ExecutionPlatformComputationError::ToolchainDepMissingPlatform(
+                    exec_cfg.dupe(),
+                    NearestPlatforms::new(exec_cfg, platforms.candidates()),
+                ),
             ))
         }
         _ => Ok(legacy_execution_platform(ctx, &ConfigurationNoExec::new(cfg.dupe())).await),
     }
 }
 
+/// How many candidates [`NearestPlatforms`] lists.
+const NEAREST_PLATFORMS_SHOWN: usize = 3;
+
+/// The candidate execution platforms closest to a configuration none of them has, i.e. with the
+/// fewest constraints set to different values, and those differences.
+#[derive(Debug)]
+struct NearestPlatforms(Vec<(String, Vec<ConstraintDifference>)>);
+
+#[derive(Debug)]
+struct ConstraintDifference {
+    key: ConstraintKey,
+    expected: Option<ConstraintValue>,
+    platform: Option<ConstraintValue>,
+}
+
+impl NearestPlatforms {
+    fn new<'a>(
+        cfg: &ConfigurationData,
+        candidates: impl IntoIterator<Item = &'a ExecutionPlatform>,
+    ) -> Self {
+        let Ok(expected) = cfg.data() else {
+            return NearestPlatforms(Vec::new());
+        };
+        let mut nearest: Vec<_> = candidates
+            .into_iter()
+            .filter_map(|candidate| {
+                let platform = candidate.cfg().data().ok()?;
+                let differences = expected
+                    .constraints
+                    .keys()
+                    .chain(platform.constraints.keys())
+                    .sorted()
+                    .dedup()
+                    .filter_map(|key| {
+                        let expected = expected.constraints.get(key);
+                        let platform = platform.constraints.get(key);
+                        (expected != platform).then(|| ConstraintDifference {
+                            key: key.dupe(),
+                            expected: expected.duped(),
+                            platform: platform.duped(),
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                Some((candidate.id(), differences))
+            })
+            .collect();
+        // Stable, so ties keep the priority order of the candidates.
+        nearest.sort_by_key(|(_, differences)| differences.len());
+        nearest.truncate(NEAREST_PLATFORMS_SHOWN);
+        NearestPlatforms(nearest)
+    }
+}
+
+impl Display for NearestPlatforms {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.0.is_empty() {
+            return Ok(());
+        }
+        write!(f, "\nNearest execution platforms:")?;
+        for (id, differences) in &self.0 {
+            write!(f, "\n  `{}`:", id)?;
+            for difference in differences {
+                let value = |v: &Option<ConstraintValue>| match v {
+                    Some(v) => format!("`{}`", v),
+                    None => "unset".to_owned(),
+                };
+                write!(
+                    f,
+                    "\n    `{}` is {}, expected {}",
+                    difference.key,
+                    value(&difference.platform),
+                    value(&difference.expected)
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
 struct ExecutionPlatformConstraints {
     exec_deps: Arc<[TargetLabel]>,
     toolchain_deps: Arc<[TargetConfiguredTargetLabel]>,
@@ -115,10 +200,12 @@ impl ExecutionPlatformConstraints {
         toolchain_deps: Arc<[TargetConfiguredTargetLabel]>,
         exec_compatible_with: Arc<[ConfigurationSettingKey]>,
     ) -> Self {
+        // The order in which deps and constraints are declared doesn't affect the resolution, so
+        // normalize them for nodes that only differ in that to share the same DICE key.
         Self {
-            exec_deps,
-            toolchain_deps,
-            exec_compatible_with,
+            exec_deps: normalize(exec_deps),
+            toolchain_deps: normalize(toolchain_deps),
+            exec_compatible_with: normalize(exec_compatible_with),
         }
     }
 
@@ -175,14 +262,28 @@ impl ExecutionPlatformConstraints {
         ctx: &mut DiceComputations<'_>,
         cell: CellNameForConfigurationResolution,
     ) -> buck2_error::Result<ExecutionPlatformResolution> {
-        ctx.compute(&ExecutionPlatformResolutionKey {
+        ctx.compute(&self.key(cell)).await?
+    }
+
+    fn key(self, cell: CellNameForConfigurationResolution) -> ExecutionPlatformResolutionKey {
+        ExecutionPlatformResolutionKey {
             target_node_cell: cell,
             exec_compatible_with: self.exec_compatible_with,
             exec_deps: self.exec_deps,
             toolchain_deps: self.toolchain_deps,
-        })
-        .await?
+        }
+    }
+}
+
+/// Sorts and deduplicates `items`, without copying them if they already are.
+fn normalize<T: Ord + Clone>(items: Arc<[T]>) -> Arc<[T]> {
+    if items.windows(2).all(|w| w[0] < w[1]) {
+        return items;
     }
+    let mut items = items.to_vec();
+    items.sort();
+    items.dedup();
+    items.into()
 }
 
 #[derive(Clone, Display, Debug, Dupe, Eq, Hash, PartialEq, Allocative)]
@@ -424,17 +525,22 @@ async fn check_execution_platform(
     )
     .await?;
 
-    // Then check if the platform satisfies compatible_with
-    for constraint in exec_compatible_with {
-        if matched_cfg_keys
-            .settings()
-            .setting_matches(constraint)
-            .is_none()
-        {
-            return Ok(Err(
-                ExecutionPlatformIncompatibleReason::ConstraintNotSatisfied(constraint.dupe().0),
-            ));
-        }
+    // Then check if the platform satisfies compatible_with. All the unsatisfied constraints are
+    // reported, so that it's clear how far the platform is from being compatible.
+    let unsatisfied: Vec<_> = exec_compatible_with
+        .iter()
+        .filter(|constraint| {
+            matched_cfg_keys
+                .settings()
+                .setting_matches(constraint)
+                .is_none()
+        })
+        .map(|constraint| constraint.0.dupe())
+        .collect();
+    if !unsatisfied.is_empty() {
+        return Ok(Err(
+            ExecutionPlatformIncompatibleReason::constraints_not_satisfied(unsatisfied),
+        ));
     }
 
     // Then check that all exec_deps are compatible with the platform. We collect errors separately,
@@ -473,7 +579,12 @@ async fn check_execution_platform(
         {
             Ok(Ok(())) => {}
             Ok(Err(reason)) => {
-                return Ok(Err(reason));
+                return Ok(Err(
+                    ExecutionPlatformIncompatibleReason::ToolchainDependencyIncompatible(
+                        dep.dupe(),
+                        Arc::new(reason),
+                    ),
+                ));
             }
             Err(e) => errs.push(e),
         }
@@ -663,3 +774,190 @@ impl GetExecutionPlatformsImpl for GetExecutionPlatformsInstance {
 pub(crate) fn init_get_execution_platforms() {
     GET_EXECUTION_PLATFORMS.init(&GetExecutionPlatformsInstance);
 }
+
+#[cfg(test)]
+mod tests {
+    use buck2_core::cells::name::CellName;
+    use buck2_core::configuration::data::ConfigurationDataData;
+    use buck2_core::execution_types::executor_config::CommandExecutorConfig;
+
+    use super::*;
+
+    fn constraints(
+        exec_deps: &[&str],
+        toolchain_deps: &[&str],
+        exec_compatible_with: &[&str],
+    ) -> ExecutionPlatformConstraints {
+        ExecutionPlatformConstraints::new_constraints(
+            exec_deps
+                .iter()
+                .map(|label| TargetLabel::testing_parse(label))
+                .collect(),
+            toolchain_deps
+                .iter()
+                .map(|label| {
+                    TargetConfiguredTargetLabel::new_configure(
+                        &TargetLabel::testing_parse(label),
+                        ConfigurationData::testing_new(),
+                    )
+                })
+                .collect(),
+            exec_compatible_with
+                .iter()
+                .map(|label| ConfigurationSettingKey::testing_parse(label))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_key_ignores_order_and_duplicates() {
+        let cell = CellNameForConfigurationResolution(CellName::testing_new("root"));
+        let a = constraints(
+            &["root//:b", "root//:a", "root//:b"],
+            &["root//:t2", "root//:t1"],
+            &["root//:c2", "root//:c1", "root//:c1"],
+        )
+        .key(cell);
+        let b = constraints(
+            &["root//:a", "root//:b"],
+            &["root//:t1", "root//:t2"],
+            &["root//:c1", "root//:c2"],
+        )
+        .key(cell);
+        assert_eq!(a, b);
+        assert_eq!(
+            a.exec_deps
+                .iter()
+                .map(|l| l.to_string())
+                .collect::<Vec<_>>(),
+            vec!["root//:a", "root//:b"]
+        );
+
+        let c = constraints(
+            &["root//:a"],
+            &["root//:t1", "root//:t2"],
+            &["root//:c1", "root//:c2"],
+        )
+        .key(cell);
+        assert_ne!(a, c);
+    }
+
+    #[tokio::test]
+    async fn test_equivalent_constraints_resolved_once() {
+        static INIT: Once = Once::new();
+        INIT.call_once(init_get_execution_platforms);
+
+        let resolutions = ComputeCounter::new::<ExecutionPlatformResolutionKey>();
+        let mut ctx = DiceBuilder::new()
+            .mock_and_return(
+                ExecutionPlatformsKey,
+                Ok(Some(Arc::new(ExecutionPlatformsData::new(
+                    TargetLabel::testing_parse("root//platforms:platforms"),
+                    Vec::new(),
+                    ExecutionPlatformFallback::UseUnspecifiedExec,
+                )))),
+            )
+            .build(UserComputationData {
+                tracker: resolutions.dupe(),
+                ..Default::default()
+            })
+            .unwrap()
+            .commit()
+            .await;
+
+        let cell = CellNameForConfigurationResolution(CellName::testing_new("root"));
+        constraints(&["root//:b", "root//:a"], &[], &["root//:c2", "root//:c1"])
+            .one_for_cell(&mut ctx, cell)
+            .await
+            .unwrap();
+        constraints(&["root//:a", "root//:b"], &[], &["root//:c1", "root//:c2"])
+            .one_for_cell(&mut ctx, cell)
+            .await
+            .unwrap();
+        assert_eq!(resolutions.computed(), 1);
+
+        constraints(&["root//:a"], &[], &["root//:c1", "root//:c2"])
+            .one_for_cell(&mut ctx, cell)
+            .await
+            .unwrap();
+        assert_eq!(resolutions.computed(), 2);
+    }
+
+    #[test]
+    fn test_nearest_platforms() {
+        let cfg = |label: &str, constraints: &[(&str, &str)]| {
+            ConfigurationData::from_platform(
+                label.to_owned(),
+                ConfigurationDataData {
+                    constraints: constraints
+                        .iter()
+                        .map(|(k, v)| {
+                            (
+                                ConstraintKey::testing_new(k),
+                                ConstraintValue::testing_new(v),
+                            )
+                        })
+                        .collect(),
+                },
+            )
+            .unwrap()
+        };
+        let platform = |label: &str, constraints: &[(&str, &str)]| {
+            ExecutionPlatform::platform(
+                TargetLabel::testing_parse(label),
+                cfg(label, constraints),
+                CommandExecutorConfig::testing_local(),
+            )
+        };
+        let candidates = [
+            platform(
+                "root//:mac_arm",
+                &[
+                    ("root//:os", "root//:macos"),
+                    ("root//:cpu", "root//:arm64"),
+                ],
+            ),
+            platform(
+                "root//:linux_arm",
+                &[
+                    ("root//:os", "root//:linux"),
+                    ("root//:cpu", "root//:arm64"),
+                ],
+            ),
+            platform("root//:linux", &[("root//:os", "root//:linux")]),
+        ];
+
+        let expected = cfg(
+            "toolchain_exec",
+            &[
+                ("root//:os", "root//:linux"),
+                ("root//:cpu", "root//:x86_64"),
+            ],
+        );
+        let nearest = NearestPlatforms::new(&expected, &candidates);
+        assert_eq!(
+            nearest
+                .0
+                .iter()
+                .map(|(id, differences)| (id.as_str(), differences.len()))
+                .collect::<Vec<_>>(),
+            // Ties are in priority order.
+            vec![
+                ("root//:linux_arm", 1),
+                ("root//:linux", 1),
+                ("root//:mac_arm", 2)
+            ]
+        );
+        assert_eq!(
+            nearest.to_string(),
+            "\nNearest execution platforms:\
+             \n  `root//:linux_arm`:\
+             \n    `root//:cpu` is `root//:arm64`, expected `root//:x86_64`\
+             \n  `root//:linux`:\
+             \n    `root//:cpu` is unset, expected `root//:x86_64`\
+             \n  `root//:mac_arm`:\
+             \n    `root//:cpu` is `root//:arm64`, expected `root//:x86_64`\
+             \n    `root//:os` is `root//:macos`, expected `root//:linux`"
+        );
+    }
+}
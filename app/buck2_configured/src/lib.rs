@@ -15,6 +15,9 @@ pub mod execution;
 pub mod nodes;
 mod target_platform_resolution;
 
+#[cfg(test)]
+mod test_utils;
+
 pub fn init_late_bindings() {
     target_platform_resolution::init_configured_target_calculation();
     execution::init_get_execution_platforms();
@@ -11,6 +11,7 @@
 
 use std::iter;
 use std::sync::Arc;
+use std::time::Instant;
 
 use allocative::Allocative;
 use async_trait::async_trait;
@@ -64,12 +65,14 @@ use buck2_node::attrs::spec::internal::INCOMING_TRANSITION_ATTRIBUTE;
 use buck2_node::attrs::spec::internal::LEGACY_TARGET_COMPATIBLE_WITH_ATTRIBUTE;
 use buck2_node::attrs::spec::internal::TARGET_COMPATIBLE_WITH_ATTRIBUTE;
 use buck2_node::configuration::calculation::CellNameForConfigurationResolution;
+use buck2_node::configuration::resolved::ConfigurationSettingKey;
 use buck2_node::configuration::resolved::MatchedConfigurationSettingKeys;
 use buck2_node::configuration::resolved::MatchedConfigurationSettingKeysWithCfg;
 use buck2_node::nodes::configured::ConfiguredTargetNode;
 use buck2_node::nodes::configured_frontend::CONFIGURED_TARGET_NODE_CALCULATION;
 use buck2_node::nodes::configured_frontend::ConfiguredTargetNodeCalculation;
 use buck2_node::nodes::configured_frontend::ConfiguredTargetNodeCalculationImpl;
+use buck2_node::nodes::configured_recompute_stats::HasConfiguredNodeRecomputeStats;
 use buck2_node::nodes::frontend::TargetGraphCalculation;
 use buck2_node::nodes::unconfigured::TargetNode;
 use buck2_node::nodes::unconfigured::TargetNodeRef;
@@ -82,6 +85,7 @@ use dice::DiceComputations;
 use dice::Key;
 use dupe::Dupe;
 use futures::FutureExt;
+use futures::future::BoxFuture;
 use itertools::Itertools;
 use starlark_map::ordered_map::OrderedMap;
 use starlark_map::small_map::SmallMap;
@@ -90,6 +94,8 @@ use starlark_map::small_set::SmallSet;
 use crate::configuration::compute_platform_cfgs;
 use crate::configuration::get_matched_cfg_keys_for_node;
 use crate::cycle::ConfiguredGraphCycleDescriptor;
+use crate::cycle::ConfiguredGraphCycleError;
+use crate::cycle::report_configured_graph_cycle;
 use crate::execution::find_execution_platform_by_configuration;
 use crate::execution::resolve_execution_platform;
 
@@ -231,10 +237,20 @@ fn unpack_target_compatible_with_attr(
     }
 }
 
+/// How many unsatisfied constraints `check_compatible` reports.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum CompatibilityCheckMode {
+    /// Only the first one. This is what configured node computation uses.
+    FirstUnsatisfied,
+    /// All of them, to explain to users why a target is incompatible.
+    AllUnsatisfied,
+}
+
 fn check_compatible(
     target_label: &ConfiguredTargetLabel,
     target_node: TargetNodeRef,
     resolved_cfg: &MatchedConfigurationSettingKeysWithCfg,
+    mode: CompatibilityCheckMode,
 ) -> buck2_error::Result<MaybeCompatible<()>> {
     let target_compatible_with = unpack_target_compatible_with_attr(
         target_node,
@@ -262,25 +278,53 @@ fn check_compatible(
         }
     };
 
-    // We are compatible if the list of target expressions is empty,
-    // OR if we match ANY expression in the list of attributes.
+    let mut unsatisfied = unsatisfied_constraints(
+        compatibility_constraints,
+        |label| resolved_cfg.settings().setting_matches(label).is_some(),
+        mode,
+    )?;
+    let cause = match unsatisfied.len() {
+        0 => return Ok(MaybeCompatible::Compatible(())),
+        1 => IncompatiblePlatformReasonCause::UnsatisfiedConfig(unsatisfied.swap_remove(0).0),
+        _ => IncompatiblePlatformReasonCause::UnsatisfiedConfigs(
+            unsatisfied.into_iter().map(|label| label.0).collect(),
+        ),
+    };
+    Ok(MaybeCompatible::Incompatible(Arc::new(
+        IncompatiblePlatformReason {
+            target: target_label.dupe(),
+            cause,
+        },
+    )))
+}
+
+/// Constraints that make the target incompatible, empty if it is compatible.
+///
+/// With ANY semantics (`compatible_with`), the target is compatible if any constraint matches,
+/// and otherwise all of them are unsatisfied. With ALL semantics (`target_compatible_with`), every
+/// constraint that doesn't match is unsatisfied. `CompatibilityCheckMode::FirstUnsatisfied` only
+/// returns the first one.
+fn unsatisfied_constraints(
+    compatibility_constraints: CompatibilityConstraints,
+    matches: impl Fn(&ConfigurationSettingKey) -> bool,
+    mode: CompatibilityCheckMode,
+) -> buck2_error::Result<Vec<ConfigurationSettingKey>> {
     let check_compatibility = |attr| -> buck2_error::Result<(Vec<_>, Vec<_>)> {
         let mut left = Vec::new();
         let mut right = Vec::new();
         for label in ConfiguredTargetNode::attr_as_target_compatible_with(attr) {
             let label = label?;
-            match resolved_cfg.settings().setting_matches(&label) {
-                Some(_) => left.push(label),
-                None => right.push(label),
+            if matches(&label) {
+                left.push(label);
+            } else {
+                right.push(label);
             }
         }
 
         Ok((left, right))
     };
 
-    // We only record the first incompatibility, for either ANY or ALL.
-    // TODO(cjhopman): Should we report _all_ the things that are incompatible?
-    let incompatible_target = match compatibility_constraints {
+    let mut unsatisfied = match compatibility_constraints {
         CompatibilityConstraints::Any(attr) => {
             let (compatible, incompatible) =
                 check_compatibility(attr).with_buck_error_context(|| {
@@ -289,33 +333,89 @@ fn check_compatible(
                         LEGACY_TARGET_COMPATIBLE_WITH_ATTRIBUTE.name
                     )
                 })?;
-            let incompatible = incompatible.into_iter().next();
-            match (compatible.is_empty(), incompatible.into_iter().next()) {
-                (false, _) | (true, None) => {
-                    return Ok(MaybeCompatible::Compatible(()));
-                }
-                (true, Some(v)) => v,
+            if !compatible.is_empty() {
+                return Ok(Vec::new());
             }
+            incompatible
         }
         CompatibilityConstraints::All(attr) => {
             let (_compatible, incompatible) =
                 check_compatibility(attr).with_buck_error_context(|| {
                     format!("attribute `{}`", TARGET_COMPATIBLE_WITH_ATTRIBUTE.name)
                 })?;
-            match incompatible.into_iter().next() {
-                Some(label) => label,
-                None => {
-                    return Ok(MaybeCompatible::Compatible(()));
+            incompatible
+        }
+    };
+    if mode == CompatibilityCheckMode::FirstUnsatisfied {
+        unsatisfied.truncate(1);
+    }
+    Ok(unsatisfied)
+}
+
+/// Recomputes the root cause of `reason` with all the unsatisfied constraints.
+///
+/// Only done for targets already known to be incompatible, so the configured node computation
+/// keeps stopping at the first unsatisfied constraint.
+fn explain_incompatibility<'a>(
+    ctx: &'a mut DiceComputations<'_>,
+    reason: Arc<IncompatiblePlatformReason>,
+) -> BoxFuture<'a, buck2_error::Result<Arc<IncompatiblePlatformReason>>> {
+    async move {
+        match &reason.cause {
+            IncompatiblePlatformReasonCause::UnsatisfiedConfig(_) => {}
+            IncompatiblePlatformReasonCause::UnsatisfiedConfigs(_) => return Ok(reason),
+            IncompatiblePlatformReasonCause::Dependency(previous) => {
+                let previous = explain_incompatibility(ctx, previous.dupe()).await?;
+                return Ok(Arc::new(IncompatiblePlatformReason {
+                    target: reason.target.dupe(),
+                    cause: IncompatiblePlatformReasonCause::Dependency(previous),
+                }));
+            }
+            IncompatiblePlatformReasonCause::Dependencies(previous) => {
+                let mut explained = Vec::with_capacity(previous.len());
+                for previous in previous.iter() {
+                    explained.push(explain_incompatibility(ctx, previous.dupe()).await?);
                 }
+                return Ok(Arc::new(IncompatiblePlatformReason {
+                    target: reason.target.dupe(),
+                    cause: IncompatiblePlatformReasonCause::Dependencies(explained.into()),
+                }));
             }
         }
-    };
-    Ok(MaybeCompatible::Incompatible(Arc::new(
-        IncompatiblePlatformReason {
-            target: target_label.dupe(),
-            cause: IncompatiblePlatformReasonCause::UnsatisfiedConfig(incompatible_target.0.dupe()),
-        },
-    )))
+
+        let target_label = &reason.target;
+        let target_node = ctx
+            .get_target_node(target_label.unconfigured())
+            .await
+            .with_buck_error_context(|| {
+                format!(
+                    "looking up unconfigured target node `{}`",
+                    target_label.unconfigured()
+                )
+            })?;
+        let resolved_configuration = get_matched_cfg_keys_for_node(
+            ctx,
+            target_label.cfg(),
+            CellNameForConfigurationResolution(target_node.label().pkg().cell_name()),
+            target_node.as_ref(),
+        )
+        .await
+        .with_buck_error_context(|| {
+            format!("Error resolving configuration deps of `{}`", target_label)
+        })?;
+
+        match check_compatible(
+            target_label,
+            target_node.as_ref(),
+            &resolved_configuration,
+            CompatibilityCheckMode::AllUnsatisfied,
+        )? {
+            MaybeCompatible::Incompatible(explained) => Ok(explained),
+            // The unsatisfied constraint came from execution platform resolution.
+            MaybeCompatible::Compatible(()) => Ok(reason),
+        }
+    }
+    .boxed()
 }
 
 /// Ideally, we would check this much earlier. However, that turns out to be a bit tricky to
@@ -338,9 +438,10 @@ async fn check_plugin_deps(
                 return Err(PluginDepError::PluginDepIsToolchainRule(dep_label.dupe()).into());
             }
             if !dep_node.is_visible_to(target_label.unconfigured())? {
-                return Err(VisibilityError::NotVisibleTo(
+                return Err(VisibilityError::not_visible_to(
                     dep_label.dupe(),
                     target_label.unconfigured().dupe(),
+                    &dep_node.explain_visibility(target_label.unconfigured())?,
                 )
                 .into());
             }
@@ -358,7 +459,8 @@ pub(crate) enum CheckVisibility {
 #[derive(Default)]
 pub(crate) struct ErrorsAndIncompatibilities {
     errs: Vec<buck2_error::Error>,
-    incompats: Vec<Arc<IncompatiblePlatformReason>>,
+    /// The target being configured and the reason one of its dependencies is incompatible.
+    incompats: Vec<(ConfiguredTargetLabel, Arc<IncompatiblePlatformReason>)>,
 }
 
 impl ErrorsAndIncompatibilities {
@@ -383,10 +485,8 @@ impl ErrorsAndIncompatibilities {
                 self.errs.push(e);
             }
             Ok(MaybeCompatible::Incompatible(reason)) => {
-                self.incompats.push(Arc::new(IncompatiblePlatformReason {
-                    target: target_label.inner().dupe(),
-                    cause: IncompatiblePlatformReasonCause::Dependency(reason.dupe()),
-                }));
+                self.incompats
+                    .push((target_label.inner().dupe(), reason.dupe()));
             }
             Ok(MaybeCompatible::Compatible(dep)) => {
                 if CheckVisibility::No == check_visibility {
@@ -396,15 +496,21 @@ impl ErrorsAndIncompatibilities {
                     Ok(true) => {
                         return Some(dep);
                     }
-                    Ok(false) => {
-                        self.errs.push(
-                            VisibilityError::NotVisibleTo(
-                                dep.label().unconfigured().dupe(),
-                                target_label.unconfigured().dupe(),
-                            )
-                            .into(),
-                        );
-                    }
+                    Ok(false) => match dep.explain_visibility(target_label.unconfigured()) {
+                        Ok(decision) => {
+                            self.errs.push(
+                                VisibilityError::not_visible_to(
+                                    dep.label().unconfigured().dupe(),
+                                    target_label.unconfigured().dupe(),
+                                    &decision,
+                                )
+                                .into(),
+                            );
+                        }
+                        Err(e) => {
+                            self.errs.push(e);
+                        }
+                    },
                     Err(e) => {
                         self.errs.push(e.into());
                     }
@@ -415,13 +521,20 @@ impl ErrorsAndIncompatibilities {
     }
 
     /// Returns an error/incompatibility to return, if any, and `None` otherwise
-    pub(crate) fn finalize<T>(mut self) -> Option<buck2_error::Result<MaybeCompatible<T>>> {
-        // FIXME(JakobDegen): Report all incompatibilities
-        if let Some(incompat) = self.incompats.pop() {
-            return Some(Ok(MaybeCompatible::Incompatible(incompat)));
+    pub(crate) fn finalize<T>(self) -> Option<buck2_error::Result<MaybeCompatible<T>>> {
+        if let Some((target, _)) = self.incompats.first() {
+            let target = target.dupe();
+            let previous = self
+                .incompats
+                .into_iter()
+                .map(|(_, reason)| reason)
+                .collect();
+            return Some(Ok(MaybeCompatible::Incompatible(Arc::new(
+                IncompatiblePlatformReason::dependencies(target, previous),
+            ))));
         }
-        if let Some(err) = self.errs.pop() {
-            return Some(Err(err.into()));
+        if !self.errs.is_empty() {
+            return Some(Err(buck2_error::Error::aggregate(self.errs)));
         }
         None
     }
@@ -703,9 +816,12 @@ async fn compute_configured_target_node_no_transition(
     })?;
 
     // Must check for compatibility before evaluating non-compatibility attributes.
-    if let MaybeCompatible::Incompatible(reason) =
-        check_compatible(target_label, target_node.as_ref(), &resolved_configuration)?
-    {
+    if let MaybeCompatible::Incompatible(reason) = check_compatible(
+        target_label,
+        target_node.as_ref(),
+        &resolved_configuration,
+        CompatibilityCheckMode::FirstUnsatisfied,
+    )? {
         return Ok(MaybeCompatible::Incompatible(reason));
     }
 
@@ -1032,7 +1148,7 @@ pub(crate) fn init_configured_target_node_calculation() {
 }
 
 #[derive(Debug, Allocative, Eq, PartialEq)]
-pub(crate) struct LookingUpConfiguredNodeContext {
+pub struct LookingUpConfiguredNodeContext {
     target: ConfiguredTargetLabel,
     len: usize,
     rest: Option<Arc<Self>>,
@@ -1065,6 +1181,18 @@ impl LookingUpConfiguredNodeContext {
             || Self::new(target.dupe(), None),
         )
     }
+
+    /// The dependency chain, from the node that was requested to the one that failed, in the
+    /// order it is displayed.
+    pub fn chain(&self) -> Vec<ConfiguredTargetLabel> {
+        let mut chain = Vec::with_capacity(self.len);
+        let mut curr = Some(self);
+        while let Some(ctx) = curr {
+            chain.push(ctx.target.dupe());
+            curr = ctx.rest.as_deref();
+        }
+        chain
+    }
 }
 
 impl std::fmt::Display for LookingUpConfiguredNodeContext {
@@ -1118,15 +1246,33 @@ impl Key for ConfiguredTargetNodeKey {
         ctx: &mut DiceComputations,
         _cancellation: &CancellationContext,
     ) -> Self::Value {
-        let res = CycleGuard::<ConfiguredGraphCycleDescriptor>::new(ctx)?
-            .guard_this(compute_configured_target_node(self, ctx))
-            .await
-            .into_result(ctx)
-            .await??;
-        Ok(LookingUpConfiguredNodeContext::add_context(
-            res,
-            self.0.dupe(),
-        )?)
+        let recompute_stats = ctx
+            .per_transaction_data()
+            .get_configured_node_recompute_stats();
+        // The value DICE still holds from before, if this is a recomputation. Peeking doesn't
+        // record a dependency on the key itself.
+        let previous = match recompute_stats {
+            Some(_) => ctx.peek_validity(self).await.value().map(Dupe::dupe),
+            None => None,
+        };
+        let start = Instant::now();
+        let res: Self::Value = async {
+            let res = CycleGuard::<ConfiguredGraphCycleDescriptor>::new(ctx)?
+                .guard_this(compute_configured_target_node(self, ctx))
+                .await
+                .into_result(ctx)
+                .await?
+                .map_err(ConfiguredGraphCycleError::into_reportable)??;
+            Ok(LookingUpConfiguredNodeContext::add_context(
+                res,
+                self.0.dupe(),
+            )?)
+        }
+        .await;
+        if let (Some(recompute_stats), Some(previous)) = (recompute_stats, previous) {
+            recompute_stats.record_recompute(Self::equality(&previous, &res), start.elapsed());
+        }
+        res
     }
 
     fn equality(x: &Self::Value, y: &Self::Value) -> bool {
@@ -1153,12 +1299,16 @@ impl ConfiguredTargetNodeCalculationImpl for ConfiguredTargetNodeCalculationInst
     ) -> buck2_error::Result<MaybeCompatible<ConfiguredTargetNode>> {
         let maybe_compatible_node = ctx
             .compute(&ConfiguredTargetNodeKey(target.dupe()))
-            .await??;
+            .await?
+            // Reported here rather than when computing the node, so that cycles are reported by
+            // every command hitting them, including when the error comes from the cache.
+            .inspect_err(|e| report_configured_graph_cycle(ctx, e))?;
         if check_dependency_incompatibility {
             if let MaybeCompatible::Incompatible(reason) = &maybe_compatible_node {
                 if matches!(
                     &reason.cause,
                     &IncompatiblePlatformReasonCause::Dependency(_)
+                        | &IncompatiblePlatformReasonCause::Dependencies(_)
                 ) {
                     if check_error_on_incompatible_dep(ctx, target.unconfigured_label()).await? {
                         return Err(reason.to_err().into());
@@ -1189,6 +1339,14 @@ impl ConfiguredTargetNodeCalculationImpl for ConfiguredTargetNodeCalculationInst
         }
         Ok(maybe_compatible_node)
     }
+
+    async fn explain_incompatibility(
+        &self,
+        ctx: &mut DiceComputations<'_>,
+        reason: Arc<IncompatiblePlatformReason>,
+    ) -> buck2_error::Result<Arc<IncompatiblePlatformReason>> {
+        explain_incompatibility(ctx, reason).await
+    }
 }
 
 async fn check_error_on_incompatible_dep(
@@ -1370,3 +1528,149 @@ fn _assert_compute_configured_forward_target_node_size() {
         "compute_configured_forward_target_node size is larger than 700 bytes",
     );
 }
+
+#[cfg(test)]
+mod tests {
+    use buck2_node::attrs::attr_type::list::ListLiteral;
+
+    use super::*;
+
+    fn constraints(labels: &[&str]) -> ConfiguredAttr {
+        ConfiguredAttr::List(ListLiteral(
+            labels
+                .iter()
+                .map(|label| {
+                    ConfiguredAttr::ConfigurationDep(ProvidersLabel::default_for(
+                        TargetLabel::testing_parse(label),
+                    ))
+                })
+                .collect::<Vec<_>>()
+                .into(),
+        ))
+    }
+
+    fn unsatisfied(
+        compatibility_constraints: CompatibilityConstraints,
+        matching: &[&str],
+        mode: CompatibilityCheckMode,
+    ) -> Vec<ConfigurationSettingKey> {
+        let matching = matching
+            .iter()
+            .map(|label| ConfigurationSettingKey::testing_parse(label))
+            .collect::<Vec<_>>();
+        unsatisfied_constraints(
+            compatibility_constraints,
+            |label| matching.contains(label),
+            mode,
+        )
+        .unwrap()
+    }
+
+    fn keys(labels: &[&str]) -> Vec<ConfigurationSettingKey> {
+        labels
+            .iter()
+            .map(|label| ConfigurationSettingKey::testing_parse(label))
+            .collect()
+    }
+
+    #[test]
+    fn test_unsatisfied_constraints_all() {
+        let labels = ["root//:a", "root//:b", "root//:c"];
+        let all = || CompatibilityConstraints::All(constraints(&labels));
+
+        assert_eq!(
+            unsatisfied(all(), &["root//:b"], CompatibilityCheckMode::AllUnsatisfied),
+            keys(&["root//:a", "root//:c"])
+        );
+        assert_eq!(
+            unsatisfied(
+                all(),
+                &["root//:b"],
+                CompatibilityCheckMode::FirstUnsatisfied
+            ),
+            keys(&["root//:a"])
+        );
+        assert_eq!(
+            unsatisfied(all(), &labels, CompatibilityCheckMode::AllUnsatisfied),
+            keys(&[])
+        );
+        assert_eq!(
+            unsatisfied(all(), &labels, CompatibilityCheckMode::FirstUnsatisfied),
+            keys(&[])
+        );
+    }
+
+    #[test]
+    fn test_unsatisfied_constraints_any() {
+        let labels = ["root//:a", "root//:b", "root//:c"];
+        let any = || CompatibilityConstraints::Any(constraints(&labels));
+
+        assert_eq!(
+            unsatisfied(any(), &[], CompatibilityCheckMode::AllUnsatisfied),
+            keys(&labels)
+        );
+        assert_eq!(
+            unsatisfied(any(), &[], CompatibilityCheckMode::FirstUnsatisfied),
+            keys(&["root//:a"])
+        );
+        // A single match is enough.
+        assert_eq!(
+            unsatisfied(any(), &["root//:c"], CompatibilityCheckMode::AllUnsatisfied),
+            keys(&[])
+        );
+        assert_eq!(
+            unsatisfied(
+                any(),
+                &["root//:c"],
+                CompatibilityCheckMode::FirstUnsatisfied
+            ),
+            keys(&[])
+        );
+    }
+
+    #[test]
+    fn test_looking_up_configured_node_context_chain() {
+        let label = |target: &str| {
+            TargetLabel::testing_parse(target).configure(ConfigurationData::testing_new())
+        };
+        let leaf = Arc::new(LookingUpConfiguredNodeContext::new(
+            label("root//:leaf"),
+            None,
+        ));
+        let middle = Arc::new(LookingUpConfiguredNodeContext::new(
+            label("root//:middle"),
+            Some(leaf),
+        ));
+        let top = LookingUpConfiguredNodeContext::new(label("root//:top"), Some(middle));
+
+        let chain = top.chain();
+        assert_eq!(
+            vec![
+                label("root//:top"),
+                label("root//:middle"),
+                label("root//:leaf")
+            ],
+            chain
+        );
+
+        // Same order as the rendered chain, one node per line after the header.
+        let rendered = top.to_string();
+        let rendered_targets = rendered
+            .lines()
+            .skip(1)
+            .map(|line| {
+                line.trim_start_matches([' ', '-', '>'])
+                    .split(' ')
+                    .next()
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            chain
+                .iter()
+                .map(|target| target.unconfigured().to_string())
+                .collect::<Vec<_>>(),
+            rendered_targets
+        );
+    }
+}
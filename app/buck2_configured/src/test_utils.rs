@@ -0,0 +1,46 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use std::sync::Arc;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+
+use allocative::Allocative;
+use dice::DiceEvent;
+use dice::DiceEventListener;
+use dice::Key;
+
+/// Counts how many times DICE computes keys of one type.
+#[derive(Allocative)]
+pub(crate) struct ComputeCounter {
+    key_type: &'static str,
+    #[allocative(skip)]
+    computed: AtomicUsize,
+}
+
+impl ComputeCounter {
+    pub(crate) fn new<K: Key>() -> Arc<Self> {
+        Arc::new(ComputeCounter {
+            key_type: K::key_type_name(),
+            computed: AtomicUsize::new(0),
+        })
+    }
+
+    pub(crate) fn computed(&self) -> usize {
+        self.computed.load(Ordering::Relaxed)
+    }
+}
+
+impl DiceEventListener for ComputeCounter {
+    fn event(&self, ev: DiceEvent) {
+        if matches!(ev, DiceEvent::ComputeStarted { key_type } if key_type == self.key_type) {
+            self.computed.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
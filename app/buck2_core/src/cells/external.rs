@@ -7,10 +7,12 @@
  * of this source tree.
  */
 
+use std::collections::HashMap;
 use std::fmt;
 use std::sync::Arc;
 
 use dupe::Dupe;
+use dupe::OptionDupedExt;
 
 use crate::cells::name::CellName;
 
@@ -30,11 +32,75 @@ pub enum ExternalCellOrigin {
     Eq,
     Hash
 )]
-#[display("git({}, {})", git_origin, commit)]
+#[display("git({}, {})", git_origin, revision)]
 pub struct GitCellSetup {
     pub git_origin: Arc<str>,
-    // Guaranteed to be a valid sha1 commit hash
-    pub commit: Arc<str>,
+    pub revision: GitCellRevision,
+}
+
+#[derive(Debug, Clone, Dupe, allocative::Allocative, PartialEq, Eq, Hash)]
+pub enum GitCellRevision {
+    /// Guaranteed to be a valid sha1 commit hash
+    Commit(Arc<str>),
+    /// A branch or tag, resolved to a commit by the external cells implementation when the cell is
+    /// first accessed.
+    Ref(Arc<str>),
+    /// A tag, resolved to a commit like a ref. If `commit` is set, the tag must resolve to it.
+    Tag {
+        tag: Arc<str>,
+        /// Guaranteed to be a valid sha1 commit hash
+        commit: Option<Arc<str>>,
+    },
+}
+
+impl GitCellSetup {
+    /// Whether the revision is a ref or a tag that has to be resolved to find the commit the cell
+    /// is checked out at.
+    pub fn needs_resolution(&self) -> bool {
+        matches!(
+            self.revision,
+            GitCellRevision::Ref(_) | GitCellRevision::Tag { commit: None, .. }
+        )
+    }
+}
+
+/// The commits that git cells pinned to a ref or a tag were resolved to. These are computed in
+/// DICE by the external cells implementation.
+#[derive(Debug, Clone, Dupe, Default, allocative::Allocative, PartialEq, Eq)]
+pub struct ResolvedGitCommits(Arc<HashMap<GitCellSetup, Arc<str>>>);
+
+impl ResolvedGitCommits {
+    pub fn new(commits: HashMap<GitCellSetup, Arc<str>>) -> Self {
+        Self(Arc::new(commits))
+    }
+
+    /// The commit the cell is checked out at, if it is known.
+    pub fn commit(&self, setup: &GitCellSetup) -> Option<Arc<str>> {
+        match &setup.revision {
+            GitCellRevision::Commit(commit)
+            | GitCellRevision::Tag {
+                commit: Some(commit),
+                ..
+            } => Some(commit.dupe()),
+            GitCellRevision::Ref(_) | GitCellRevision::Tag { commit: None, .. } => {
+                self.0.get(setup).duped()
+            }
+        }
+    }
+}
+
+impl fmt::Display for GitCellRevision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Commit(commit) => write!(f, "{}", commit),
+            Self::Ref(git_ref) => write!(f, "ref:{}", git_ref),
+            Self::Tag { tag, commit: None } => write!(f, "tag:{}", tag),
+            Self::Tag {
+                tag,
+                commit: Some(commit),
+            } => write!(f, "tag:{}@{}", tag, commit),
+        }
+    }
 }
 
 impl fmt::Display for ExternalCellOrigin {
@@ -16,7 +16,9 @@ use std::sync::Arc;
 
 use allocative::Allocative;
 use dupe::Dupe;
+use itertools::Itertools;
 
+use crate::buck2_env;
 use crate::provider::label::ProvidersLabel;
 use crate::target::configured_target_label::ConfiguredTargetLabel;
 
@@ -41,6 +43,10 @@ enum CompatibilityErrors {
     DepOnlyIncompatibleSoftError(ConfiguredTargetLabel, IncompatiblePlatformReason),
 }
 
+/// How many incompatible dependencies of a single target are listed in its incompatibility
+/// message, unless overridden by `BUCK2_MAX_INCOMPATIBLE_DEPENDENCIES_SHOWN`.
+const DEFAULT_MAX_INCOMPATIBLE_DEPENDENCIES_SHOWN: usize = 10;
+
 /// MaybeCompatible is used to gracefully deal with things that are incompatible
 /// with the target platform. The main place this comes up is that targets provided on the
 /// cli may be incompatible with the default or requested platform, and we want to skip
@@ -88,8 +94,14 @@ impl<T> MaybeCompatible<T> {
 pub enum IncompatiblePlatformReasonCause {
     /// Target is incompatible because of unsatisfied config setting.
     UnsatisfiedConfig(ProvidersLabel),
+    /// Target is incompatible because of all these unsatisfied config settings. Only produced
+    /// when incompatibility is explained, configured node computation stops at the first one.
+    UnsatisfiedConfigs(Arc<[ProvidersLabel]>),
     /// Target is incompatible because dependency is incompatible.
     Dependency(Arc<IncompatiblePlatformReason>),
+    /// Target is incompatible because several dependencies are incompatible, ordered by
+    /// dependency.
+    Dependencies(Arc<[Arc<IncompatiblePlatformReason>]>),
 }
 
 #[derive(Debug, Eq, PartialEq, Hash, Clone, Dupe, Allocative)]
@@ -99,12 +111,31 @@ pub struct IncompatiblePlatformReason {
 }
 
 impl IncompatiblePlatformReason {
+    /// `target` is incompatible because of the incompatible dependencies in `previous`, which
+    /// must not be empty. All of them are kept, deduplicated and sorted, so that the result
+    /// doesn't depend on the order the dependencies were resolved in.
+    pub fn dependencies(
+        target: ConfiguredTargetLabel,
+        mut previous: Vec<Arc<IncompatiblePlatformReason>>,
+    ) -> Self {
+        previous.sort_by(|a, b| a.target.cmp(&b.target));
+        previous.dedup_by(|a, b| a.target == b.target);
+        let cause = if previous.len() == 1 {
+            IncompatiblePlatformReasonCause::Dependency(previous.pop().unwrap())
+        } else {
+            IncompatiblePlatformReasonCause::Dependencies(previous.into())
+        };
+        IncompatiblePlatformReason { target, cause }
+    }
+
     pub fn to_err(&self) -> buck2_error::Error {
         match self.cause {
-            IncompatiblePlatformReasonCause::UnsatisfiedConfig(_) => {
+            IncompatiblePlatformReasonCause::UnsatisfiedConfig(_)
+            | IncompatiblePlatformReasonCause::UnsatisfiedConfigs(_) => {
                 CompatibilityErrors::TargetIncompatible(self.dupe()).into()
             }
-            IncompatiblePlatformReasonCause::Dependency(_) => {
+            IncompatiblePlatformReasonCause::Dependency(_)
+            | IncompatiblePlatformReasonCause::Dependencies(_) => {
                 CompatibilityErrors::DepOnlyIncompatible(self.dupe()).into()
             }
         }
@@ -112,9 +143,11 @@ impl IncompatiblePlatformReason {
 
     pub fn to_soft_err(&self) -> buck2_error::Error {
         match &self.cause {
-            IncompatiblePlatformReasonCause::UnsatisfiedConfig(_) => self.to_err(),
-            IncompatiblePlatformReasonCause::Dependency(reason) => {
-                let root_cause = reason.get_root_cause();
+            IncompatiblePlatformReasonCause::UnsatisfiedConfig(_)
+            | IncompatiblePlatformReasonCause::UnsatisfiedConfigs(_) => self.to_err(),
+            IncompatiblePlatformReasonCause::Dependency(_)
+            | IncompatiblePlatformReasonCause::Dependencies(_) => {
+                let root_cause = self.get_root_cause();
                 CompatibilityErrors::DepOnlyIncompatibleSoftError(
                     self.target.dupe(),
                     root_cause.dupe(),
@@ -127,8 +160,14 @@ impl IncompatiblePlatformReason {
     fn get_root_cause(&self) -> &IncompatiblePlatformReason {
         // Recurse until we find the root UnsatisfiedConfig error that caused incompatibility errors
         match &self.cause {
-            IncompatiblePlatformReasonCause::UnsatisfiedConfig(_) => self,
+            IncompatiblePlatformReasonCause::UnsatisfiedConfig(_)
+            | IncompatiblePlatformReasonCause::UnsatisfiedConfigs(_) => self,
             IncompatiblePlatformReasonCause::Dependency(reason) => reason.get_root_cause(),
+            // Only the first one, the soft error is meant to be short.
+            IncompatiblePlatformReasonCause::Dependencies(reasons) => match reasons.first() {
+                Some(reason) => reason.get_root_cause(),
+                None => self,
+            },
         }
     }
 
@@ -164,6 +203,38 @@ impl IncompatiblePlatformReason {
         }
         message
     }
+
+    fn fmt_dependencies(
+        &self,
+        f: &mut Formatter<'_>,
+        previous: &[Arc<IncompatiblePlatformReason>],
+        max_shown: usize,
+    ) -> std::fmt::Result {
+        // Always show at least one, or there is nothing to act on.
+        let shown = &previous[..previous.len().min(max_shown.max(1))];
+        let hidden = previous.len() - shown.len();
+        if f.alternate() {
+            write!(
+                f,
+                "{} has {} incompatible dependencies:",
+                self.target,
+                previous.len()
+            )?;
+            for previous in shown {
+                write!(f, "\n-> {:#}", previous)?;
+            }
+            if hidden != 0 {
+                write!(f, "\n-> ... and {} more", hidden)?;
+            }
+            Ok(())
+        } else {
+            write!(f, "{} -> [{}", self.target, shown.iter().join(", "))?;
+            if hidden != 0 {
+                write!(f, ", ... and {} more", hidden)?;
+            }
+            write!(f, "]")
+        }
+    }
 }
 
 impl Display for IncompatiblePlatformReason {
@@ -178,6 +249,14 @@ impl Display for IncompatiblePlatformReason {
                 self.target.cfg(),
                 unsatisfied_config,
             ),
+            IncompatiblePlatformReasonCause::UnsatisfiedConfigs(unsatisfied_configs) => write!(
+                f,
+                // Same message as above, so that CI filters both.
+                "{}\n    is incompatible with {} ({} unsatisfied), check the target's compatibility attributes",
+                self.target.unconfigured(),
+                self.target.cfg(),
+                unsatisfied_configs.iter().join(", "),
+            ),
             IncompatiblePlatformReasonCause::Dependency(previous) => {
                 if f.alternate() {
                     write!(f, "{}\n-> {:#}", self.target, previous)
@@ -185,16 +264,171 @@ impl Display for IncompatiblePlatformReason {
                     write!(f, "{} -> {}", self.target, previous)
                 }
             }
+            IncompatiblePlatformReasonCause::Dependencies(previous) => {
+                let max_shown = buck2_env!(
+                    "BUCK2_MAX_INCOMPATIBLE_DEPENDENCIES_SHOWN",
+                    type = usize,
+                    default = DEFAULT_MAX_INCOMPATIBLE_DEPENDENCIES_SHOWN
+                )
+                .unwrap_or(DEFAULT_MAX_INCOMPATIBLE_DEPENDENCIES_SHOWN);
+                self.fmt_dependencies(f, previous, max_shown)
+            }
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::fmt::Display;
+    use std::fmt::Formatter;
+    use std::sync::Arc;
+
+    use dupe::Dupe;
+
     use crate::configuration::compatibility::IncompatiblePlatformReason;
+    use crate::configuration::compatibility::IncompatiblePlatformReasonCause;
     use crate::configuration::data::ConfigurationData;
+    use crate::provider::label::ProvidersLabel;
     use crate::target::label::label::TargetLabel;
 
+    #[test]
+    fn test_display_unsatisfied_configs() {
+        let reason = IncompatiblePlatformReason {
+            target: TargetLabel::testing_parse("root//foo:bar")
+                .configure(ConfigurationData::testing_new()),
+            cause: IncompatiblePlatformReasonCause::UnsatisfiedConfigs(
+                ["root//:a", "root//:b"]
+                    .iter()
+                    .map(|label| ProvidersLabel::default_for(TargetLabel::testing_parse(label)))
+                    .collect(),
+            ),
+        };
+        assert_eq!(
+            format!(
+                "root//foo:bar\n    is incompatible with {} (root//:a, root//:b unsatisfied), check the target's compatibility attributes",
+                ConfigurationData::testing_new()
+            ),
+            reason.to_string(),
+        );
+    }
+
+    #[test]
+    fn test_dependencies_sorted_and_deduplicated() {
+        let reason = |target: &str| {
+            Arc::new(IncompatiblePlatformReason {
+                target: TargetLabel::testing_parse(target)
+                    .configure(ConfigurationData::testing_new()),
+                cause: IncompatiblePlatformReasonCause::UnsatisfiedConfig(
+                    ProvidersLabel::default_for(TargetLabel::testing_parse("root//:c")),
+                ),
+            })
+        };
+        let target =
+            TargetLabel::testing_parse("root//foo:bar").configure(ConfigurationData::testing_new());
+
+        let single = IncompatiblePlatformReason::dependencies(
+            target.dupe(),
+            vec![reason("root//:a"), reason("root//:a")],
+        );
+        assert_eq!(
+            IncompatiblePlatformReasonCause::Dependency(reason("root//:a")),
+            single.cause,
+        );
+
+        let multiple = IncompatiblePlatformReason::dependencies(
+            target,
+            vec![reason("root//:b"), reason("root//:a"), reason("root//:b")],
+        );
+        assert_eq!(
+            IncompatiblePlatformReasonCause::Dependencies(Arc::new([
+                reason("root//:a"),
+                reason("root//:b")
+            ])),
+            multiple.cause,
+        );
+        assert!(multiple.to_string().contains("root//:a"));
+        assert!(multiple.to_string().contains("root//:b"));
+    }
+
+    fn dependencies_incompatible_on(
+        deps_and_constraints: &[(&str, &str)],
+    ) -> IncompatiblePlatformReason {
+        IncompatiblePlatformReason::dependencies(
+            TargetLabel::testing_parse("root//foo:bar").configure(ConfigurationData::testing_new()),
+            deps_and_constraints
+                .iter()
+                .map(|(dep, constraint)| {
+                    Arc::new(IncompatiblePlatformReason {
+                        target: TargetLabel::testing_parse(dep)
+                            .configure(ConfigurationData::testing_new()),
+                        cause: IncompatiblePlatformReasonCause::UnsatisfiedConfig(
+                            ProvidersLabel::default_for(TargetLabel::testing_parse(constraint)),
+                        ),
+                    })
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_display_dependencies_on_different_constraints() {
+        let reason = dependencies_incompatible_on(&[
+            ("root//:dep1", "root//:linux"),
+            ("root//:dep2", "root//:arm64"),
+        ]);
+        let message = format!("{:#}", reason);
+        let cfg = ConfigurationData::testing_new();
+        assert!(
+            message.starts_with(&format!(
+                "root//foo:bar ({cfg}) has 2 incompatible dependencies:"
+            )),
+            "{message}"
+        );
+        for expected in [
+            format!("\n-> root//:dep1\n    is incompatible with {cfg} (root//:linux unsatisfied)"),
+            format!("\n-> root//:dep2\n    is incompatible with {cfg} (root//:arm64 unsatisfied)"),
+        ] {
+            assert!(message.contains(&expected), "{expected} in {message}");
+        }
+    }
+
+    #[test]
+    fn test_display_dependencies_truncated() {
+        struct Truncated<'a>(&'a IncompatiblePlatformReason, usize);
+
+        impl Display for Truncated<'_> {
+            fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+                match &self.0.cause {
+                    IncompatiblePlatformReasonCause::Dependencies(previous) => {
+                        self.0.fmt_dependencies(f, previous, self.1)
+                    }
+                    cause => panic!("unexpected cause: {:?}", cause),
+                }
+            }
+        }
+
+        let reason = dependencies_incompatible_on(&[
+            ("root//:dep1", "root//:linux"),
+            ("root//:dep2", "root//:arm64"),
+            ("root//:dep3", "root//:arm64"),
+        ]);
+        let message = format!("{:#}", Truncated(&reason, 2));
+        assert!(
+            message.contains("has 3 incompatible dependencies"),
+            "{message}"
+        );
+        assert!(message.contains("root//:dep1"), "{message}");
+        assert!(message.contains("root//:dep2"), "{message}");
+        assert!(!message.contains("root//:dep3"), "{message}");
+        assert!(message.ends_with("\n-> ... and 1 more"), "{message}");
+
+        // At least one is always shown.
+        let message = format!("{}", Truncated(&reason, 0));
+        assert!(message.contains("root//:dep1"), "{message}");
+        assert!(!message.contains("root//:dep2"), "{message}");
+        assert!(message.ends_with(", ... and 2 more]"), "{message}");
+    }
+
     #[test]
     fn test_skipping_message_for_multiple() {
         let set = vec![
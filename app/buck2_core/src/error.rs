@@ -30,6 +30,19 @@ type StructuredErrorHandler = Box<
 
 static HANDLER: OnceLock<StructuredErrorHandler> = OnceLock::new();
 
+/// Called with the category, the matching policy and the original error whenever a soft error
+/// is escalated to a hard error.
+type SoftErrorEscalationHandler =
+    Box<dyn for<'a> Fn(&'a str, &'a str, &buck2_error::Error) + Send + Sync + 'static>;
+
+static ESCALATION_HANDLER: OnceLock<SoftErrorEscalationHandler> = OnceLock::new();
+
+/// Called with the category every time a soft error fires, including when it is not logged
+/// because the category already fired too many times.
+type SoftErrorObserver = Box<dyn for<'a> Fn(&'a str) + Send + Sync + 'static>;
+
+static OBSERVER: OnceLock<SoftErrorObserver> = OnceLock::new();
+
 pub fn buck2_hard_error_env() -> buck2_error::Result<Option<&'static str>> {
     buck2_env!("BUCK2_HARD_ERROR")
 }
@@ -189,6 +202,10 @@ pub fn handle_soft_error(
         ALL_SOFT_ERROR_COUNTERS.lock().unwrap().push(count);
     });
 
+    if let Some(observer) = OBSERVER.get() {
+        observer(category);
+    }
+
     // We want to limit each error to appearing at most 10 times in a build (no point spamming people)
     if count.fetch_add(1, Ordering::SeqCst) < 10 {
         if let Some(handler) = HANDLER.get() {
@@ -196,10 +213,13 @@ pub fn handle_soft_error(
         }
     }
 
-    if hard_error_config()?.should_hard_error(category) {
-        return Err(err
-            .context("Upgraded warning to failure via $BUCK2_HARD_ERROR")
-            .into());
+    if let Some(policy) = hard_error_config()?.matching_policy(category) {
+        if let Some(handler) = ESCALATION_HANDLER.get() {
+            handler(category, &policy, &err);
+        }
+        return Err(err.context(format!(
+            "Escalated from soft error `{category}` by policy `{policy}` (via $BUCK2_HARD_ERROR)"
+        )));
     }
 
     if is_open_source() {
@@ -228,6 +248,20 @@ pub fn initialize(handler: StructuredErrorHandler) -> buck2_error::Result<()> {
     Ok(())
 }
 
+/// Provide a handler to be notified of soft errors escalated to hard errors by `$BUCK2_HARD_ERROR`.
+pub fn initialize_escalation_handler(handler: SoftErrorEscalationHandler) {
+    if let Err(_e) = ESCALATION_HANDLER.set(handler) {
+        panic!("Cannot initialize SoftErrorEscalationHandler handler more than once");
+    }
+}
+
+/// Provide an observer to be notified of every soft error that fires.
+pub fn initialize_soft_error_observer(observer: SoftErrorObserver) {
+    if let Err(_e) = OBSERVER.set(observer) {
+        panic!("Cannot initialize SoftErrorObserver more than once");
+    }
+}
+
 /// Parse either a boolean or `only=category1,category2`
 #[derive(Debug, PartialEq, Eq)]
 enum HardErrorConfig {
@@ -236,10 +270,17 @@ enum HardErrorConfig {
 }
 
 impl HardErrorConfig {
+    #[cfg(test)]
     fn should_hard_error(&self, category: &str) -> bool {
+        self.matching_policy(category).is_some()
+    }
+
+    /// The policy entry that makes `category` a hard error, if any.
+    fn matching_policy(&self, category: &str) -> Option<String> {
         match self {
-            Self::Bool(v) => *v,
-            Self::Selected(s) => s.contains(category),
+            Self::Bool(true) => Some("true".to_owned()),
+            Self::Bool(false) => None,
+            Self::Selected(s) => s.contains(category).then(|| format!("only={category}")),
         }
     }
 }
@@ -346,6 +387,15 @@ pub(crate) mod tests {
         assert!(HardErrorConfig::from_str("only=foo,bar")?.should_hard_error("foo"));
         assert!(!HardErrorConfig::from_str("only=foo,bar")?.should_hard_error("baz"));
 
+        assert_eq!(
+            Some("true".to_owned()),
+            HardErrorConfig::from_str("true")?.matching_policy("foo")
+        );
+        assert_eq!(
+            Some("only=bar".to_owned()),
+            HardErrorConfig::from_str("only=foo,bar")?.matching_policy("bar")
+        );
+
         Ok(())
     }
 
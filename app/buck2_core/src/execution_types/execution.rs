@@ -22,6 +22,7 @@ use crate::execution_types::executor_config::CommandExecutorConfig;
 use crate::provider::label::ProvidersLabel;
 use crate::target::configured_target_label::ConfiguredTargetLabel;
 use crate::target::label::label::TargetLabel;
+use crate::target::target_configured_target_label::TargetConfiguredTargetLabel;
 
 /// An execution platform is used for the execution deps of a target, those dependencies that
 /// need to be invoked as part of a build action or otherwise need to be configured against the
@@ -109,10 +110,28 @@ impl ExecutionPlatform {
 #[derive(Clone, Dupe, Debug, Eq, PartialEq, Hash, Allocative)]
 pub enum ExecutionPlatformIncompatibleReason {
     ConstraintNotSatisfied(ProvidersLabel),
+    /// All the `exec_compatible_with` constraints the platform doesn't satisfy, when there are
+    /// several.
+    ConstraintsNotSatisfied(Arc<[ProvidersLabel]>),
     ExecutionDependencyIncompatible(Arc<IncompatiblePlatformReason>),
+    /// A toolchain dep can't be built for the platform.
+    ToolchainDependencyIncompatible(
+        TargetConfiguredTargetLabel,
+        Arc<ExecutionPlatformIncompatibleReason>,
+    ),
 }
 
 impl ExecutionPlatformIncompatibleReason {
+    /// The platform doesn't satisfy the `exec_compatible_with` constraints in `unsatisfied`,
+    /// which must not be empty.
+    pub fn constraints_not_satisfied(mut unsatisfied: Vec<ProvidersLabel>) -> Self {
+        if unsatisfied.len() == 1 {
+            Self::ConstraintNotSatisfied(unsatisfied.pop().unwrap())
+        } else {
+            Self::ConstraintsNotSatisfied(unsatisfied.into())
+        }
+    }
+
     pub fn into_incompatible_platform_reason(
         self,
         target: ConfiguredTargetLabel,
@@ -122,10 +141,24 @@ impl ExecutionPlatformIncompatibleReason {
                 target,
                 cause: IncompatiblePlatformReasonCause::UnsatisfiedConfig(unsatisfied_config),
             },
+            Self::ConstraintsNotSatisfied(unsatisfied_configs) => IncompatiblePlatformReason {
+                target,
+                cause: IncompatiblePlatformReasonCause::UnsatisfiedConfigs(unsatisfied_configs),
+            },
             Self::ExecutionDependencyIncompatible(previous) => IncompatiblePlatformReason {
                 target,
                 cause: IncompatiblePlatformReasonCause::Dependency(previous),
             },
+            Self::ToolchainDependencyIncompatible(toolchain, reason) => {
+                IncompatiblePlatformReason {
+                    target,
+                    cause: IncompatiblePlatformReasonCause::Dependency(Arc::new(
+                        (*reason)
+                            .clone()
+                            .into_incompatible_platform_reason(toolchain.inner().dupe()),
+                    )),
+                }
+            }
         }
     }
 }
@@ -138,7 +171,19 @@ impl std::fmt::Display for ExecutionPlatformIncompatibleReason {
                 "exec_compatible_with requires `{}` but it was not satisfied",
                 v
             ),
+            ExecutionPlatformIncompatibleReason::ConstraintsNotSatisfied(v) => write!(
+                f,
+                "exec_compatible_with requires {} but they were not satisfied",
+                v.iter().map(|v| format!("`{}`", v)).join(", ")
+            ),
             ExecutionPlatformIncompatibleReason::ExecutionDependencyIncompatible(v) => v.fmt(f),
+            ExecutionPlatformIncompatibleReason::ToolchainDependencyIncompatible(
+                toolchain,
+                reason,
+            ) => {
+                write!(f, "toolchain dep `{}`: ", toolchain)?;
+                reason.fmt(f)
+            }
         }
     }
 }
@@ -12,6 +12,7 @@ use dupe::Dupe;
 
 use crate::cells::CellResolver;
 use crate::cells::cell_path::CellPathRef;
+use crate::cells::external::ResolvedGitCommits;
 use crate::content_hash::ContentBasedPathHash;
 use crate::fs::buck_out_path::BuckOutPathResolver;
 use crate::fs::buck_out_path::BuildArtifactPath;
@@ -24,6 +25,8 @@ pub struct ArtifactFs {
     cell_resolver: CellResolver,
     buck_out_path_resolver: BuckOutPathResolver,
     project_filesystem: ProjectRoot,
+    /// Where the sources of git cells pinned to a ref or a tag are checked out.
+    resolved_git_commits: ResolvedGitCommits,
 }
 
 impl ArtifactFs {
@@ -36,6 +39,14 @@ impl ArtifactFs {
             cell_resolver: buck_path_resolver,
             buck_out_path_resolver,
             project_filesystem,
+            resolved_git_commits: ResolvedGitCommits::default(),
+        }
+    }
+
+    pub fn with_resolved_git_commits(self, resolved_git_commits: ResolvedGitCommits) -> Self {
+        Self {
+            resolved_git_commits,
+            ..self
         }
     }
 
@@ -70,10 +81,11 @@ impl ArtifactFs {
             .get(source_artifact_path.package().cell_name())?
             .external()
         {
-            Ok(self.buck_out_path_resolver.resolve_external_cell_source(
+            self.buck_out_path_resolver.resolve_external_cell_source(
                 source_artifact_path.to_cell_path().path(),
                 origin.dupe(),
-            ))
+                &self.resolved_git_commits,
+            )
         } else {
             Ok(cell_resolver
                 .resolve_path(source_artifact_path.package().as_cell_path())?
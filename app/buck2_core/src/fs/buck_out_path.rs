@@ -13,17 +13,21 @@ use std::hash::Hasher;
 use std::sync::Arc;
 
 use allocative::Allocative;
+use buck2_error::BuckErrorContext;
 use derive_more::Display;
 use dupe::Dupe;
 use itertools::Itertools;
 
 use crate::category::CategoryRef;
 use crate::cells::external::ExternalCellOrigin;
+use crate::cells::external::ResolvedGitCommits;
+use crate::cells::name::CellName;
 use crate::cells::paths::CellRelativePath;
 use crate::content_hash::ContentBasedPathHash;
 use crate::deferred::base_deferred_key::BaseDeferredKey;
 use crate::deferred::key::DeferredHolderKey;
 use crate::fs::dynamic_actions_action_key::DynamicActionsActionKey;
+use crate::fs::paths::file_name::FileName;
 use crate::fs::paths::forward_rel_path::ForwardRelativePath;
 use crate::fs::paths::forward_rel_path::ForwardRelativePathBuf;
 use crate::fs::project_rel_path::ProjectRelativePath;
@@ -299,26 +303,48 @@ impl BuckOutPathResolver {
         )
     }
 
+    /// Git cells are checked out by commit, so the commits of cells pinned to a ref or a tag are
+    /// looked up in `resolved_git_commits`.
     pub fn resolve_external_cell_source(
         &self,
         path: &CellRelativePath,
         origin: ExternalCellOrigin,
+        resolved_git_commits: &ResolvedGitCommits,
+    ) -> buck2_error::Result<ProjectRelativePathBuf> {
+        match origin {
+            ExternalCellOrigin::Bundled(cell) => Ok(self.resolve_bundled_cell_source(path, cell)),
+            ExternalCellOrigin::Git(setup) => {
+                let commit = resolved_git_commits
+                    .commit(&setup)
+                    .with_internal_error(|| format!("git revision `{}` was not resolved", setup))?;
+                Ok(self.resolve_git_cell_source(path, &commit))
+            }
+        }
+    }
+
+    pub fn resolve_bundled_cell_source(
+        &self,
+        path: &CellRelativePath,
+        cell: CellName,
     ) -> ProjectRelativePathBuf {
         ProjectRelativePathBuf::from(ForwardRelativePathBuf::concat([
             self.buck_out_v2.as_forward_relative_path(),
-            ForwardRelativePath::new("external_cells").unwrap(),
-            match origin {
-                ExternalCellOrigin::Bundled(_) => ForwardRelativePath::new("bundled").unwrap(),
-                ExternalCellOrigin::Git(_) => ForwardRelativePath::new("git").unwrap(),
-            },
-            match &origin {
-                ExternalCellOrigin::Bundled(cell) => {
-                    ForwardRelativePath::new(cell.as_str()).unwrap()
-                }
-                ExternalCellOrigin::Git(setup) => {
-                    ForwardRelativePath::new(setup.commit.as_ref()).unwrap()
-                }
-            },
+            ForwardRelativePath::new("external_cells/bundled").unwrap(),
+            ForwardRelativePath::new(cell.as_str()).unwrap(),
+            path.as_ref(),
+        ]))
+    }
+
+    /// `commit` must be a valid sha1 commit hash.
+    pub fn resolve_git_cell_source(
+        &self,
+        path: &CellRelativePath,
+        commit: &str,
+    ) -> ProjectRelativePathBuf {
+        ProjectRelativePathBuf::from(ForwardRelativePathBuf::concat([
+            self.buck_out_v2.as_forward_relative_path(),
+            ForwardRelativePath::new("external_cells/git").unwrap(),
+            ForwardRelativePath::new(commit).unwrap(),
             path.as_ref(),
         ]))
     }
@@ -418,9 +444,37 @@ impl BuckOutPathResolver {
     }
 }
 
+/// Directories under the buck-out root whose immediate children are named after the cell that
+/// owns everything below them, e.g. `gen/<cell>/<configuration hash>/...`.
+const CELL_SCOPED_DIRS: &[&str] = &[
+    "gen",
+    "gen-anon",
+    "gen-bxl",
+    "tmp",
+    "offline-cache",
+    "test_discovery",
+];
+
+/// Returns the name of the cell that owns `path`, which lives under the buck-out root `buck_out`.
+///
+/// Returns `None` if `path` is not under `buck_out`, is not in one of the per-cell directories
+/// (for example `test` or `external_cells`), or is the per-cell directory itself.
+pub fn buck_out_path_owning_cell<'a>(
+    buck_out: &ProjectRelativePath,
+    path: &'a ProjectRelativePath,
+) -> Option<&'a FileName> {
+    let mut components = path.strip_prefix_opt(buck_out)?.iter();
+    let dir = components.next()?;
+    if !CELL_SCOPED_DIRS.contains(&dir.as_str()) {
+        return None;
+    }
+    components.next()
+}
+
 #[cfg(test)]
 mod tests {
 
+    use std::collections::HashMap;
     use std::path::Path;
     use std::sync::Arc;
 
@@ -430,6 +484,10 @@ mod tests {
     use crate::category::CategoryRef;
     use crate::cells::CellResolver;
     use crate::cells::cell_root_path::CellRootPathBuf;
+    use crate::cells::external::ExternalCellOrigin;
+    use crate::cells::external::GitCellRevision;
+    use crate::cells::external::GitCellSetup;
+    use crate::cells::external::ResolvedGitCommits;
     use crate::cells::name::CellName;
     use crate::cells::paths::CellRelativePath;
     use crate::configuration::data::ConfigurationData;
@@ -443,6 +501,7 @@ mod tests {
     use crate::fs::buck_out_path::BuckOutPathResolver;
     use crate::fs::buck_out_path::BuckOutScratchPath;
     use crate::fs::buck_out_path::BuildArtifactPath;
+    use crate::fs::buck_out_path::buck_out_path_owning_cell;
     use crate::fs::paths::abs_norm_path::AbsNormPathBuf;
     use crate::fs::paths::forward_rel_path::ForwardRelativePathBuf;
     use crate::fs::project::ProjectRoot;
@@ -788,4 +847,87 @@ mod tests {
         assert!(expected_result.is_match(result.as_str()));
         Ok(())
     }
+
+    #[test]
+    fn test_buck_out_path_owning_cell() {
+        let buck_out = ProjectRelativePathBuf::unchecked_new("buck-out/v2".into());
+        let cell = |path: &str| {
+            buck_out_path_owning_cell(
+                &buck_out,
+                &ProjectRelativePathBuf::unchecked_new(path.into()),
+            )
+            .map(|c| c.as_str().to_owned())
+        };
+
+        assert_eq!(
+            cell("buck-out/v2/gen/root/0123456789abcdef/pkg/__t__/out"),
+            Some("root".to_owned())
+        );
+        assert_eq!(
+            cell("buck-out/v2/gen-anon/other/x"),
+            Some("other".to_owned())
+        );
+        assert_eq!(cell("buck-out/v2/gen-bxl/other"), Some("other".to_owned()));
+        assert_eq!(cell("buck-out/v2/tmp/root/x"), Some("root".to_owned()));
+        assert_eq!(cell("buck-out/v2/gen"), None);
+        assert_eq!(cell("buck-out/v2/test/root/x"), None);
+        assert_eq!(cell("buck-out/v2/external_cells/bundled/prelude/x"), None);
+        assert_eq!(cell("buck-out/v2"), None);
+        assert_eq!(cell("buck-out/gen/root/x"), None);
+        assert_eq!(cell("other/gen/root/x"), None);
+    }
+
+    #[test]
+    fn test_resolve_git_cell_revision_to_checkout() -> buck2_error::Result<()> {
+        let path_resolver =
+            BuckOutPathResolver::new(ProjectRelativePathBuf::unchecked_new("buck-out/v2".into()));
+        let commit = "1111111111111111111111111111111111111111";
+        let setup = |revision| GitCellSetup {
+            git_origin: "https://github.com/jeff/test_resolve_git_cell_ref.git".into(),
+            revision,
+        };
+        let git_ref = setup(GitCellRevision::Ref("main".into()));
+        let tag = setup(GitCellRevision::Tag {
+            tag: "v1".into(),
+            commit: None,
+        });
+        let unresolved = ResolvedGitCommits::default();
+        let resolved = ResolvedGitCommits::new(HashMap::from([
+            (git_ref.dupe(), commit.into()),
+            (tag.dupe(), commit.into()),
+        ]));
+        let path = CellRelativePath::unchecked_new("pkg/src.c");
+        let resolve = |setup: &GitCellSetup, resolved: &ResolvedGitCommits| {
+            path_resolver.resolve_external_cell_source(
+                path,
+                ExternalCellOrigin::Git(setup.dupe()),
+                resolved,
+            )
+        };
+
+        let checkout = resolve(&setup(GitCellRevision::Commit(commit.into())), &unresolved)?;
+        assert_eq!(
+            checkout.as_str(),
+            "buck-out/v2/external_cells/git/1111111111111111111111111111111111111111/pkg/src.c"
+        );
+
+        // Refs resolve to the checkout of the commit they were resolved to.
+        assert!(resolve(&git_ref, &unresolved).is_err());
+        assert_eq!(resolve(&git_ref, &resolved)?, checkout);
+
+        // Tags pinned to a commit don't need to be resolved first.
+        assert_eq!(
+            resolve(
+                &setup(GitCellRevision::Tag {
+                    tag: "v1".into(),
+                    commit: Some(commit.into()),
+                }),
+                &unresolved
+            )?,
+            checkout
+        );
+        assert!(resolve(&tag, &unresolved).is_err());
+        assert_eq!(resolve(&tag, &resolved)?, checkout);
+        Ok(())
+    }
 }
@@ -698,6 +698,22 @@ pub fn create_file<P: AsRef<AbsPath>>(path: P) -> Result<FileWriteGuard, IoError
     })
 }
 
+/// Open a file for writing at its end, creating it if it doesn't exist.
+pub fn append_file<P: AsRef<AbsPath>>(path: P) -> Result<FileWriteGuard, IoError> {
+    let guard = IoCounterKey::Write.guard();
+    let file = with_retries(|| {
+        File::options()
+            .append(true)
+            .create(true)
+            .open(path.as_ref().as_maybe_relativized())
+    })
+    .map_err(|e| IoError::new_with_path("append_file", path, e))?;
+    Ok(FileWriteGuard {
+        file,
+        _guard: guard,
+    })
+}
+
 fn create_file_if_not_exists_impl<P: AsRef<AbsPath>>(
     path: P,
 ) -> Result<Option<FileWriteGuard>, io::Error> {
@@ -13,12 +13,17 @@ use std::sync::Once;
 
 use buck2_core::error::StructuredErrorOptions;
 use buck2_core::error::initialize;
+use buck2_core::error::initialize_escalation_handler;
+use buck2_core::error::initialize_soft_error_observer;
+use buck2_core::error::reload_hard_error_config;
 use buck2_core::error::reset_soft_error_counters;
 use buck2_core::is_open_source;
 use buck2_core::soft_error;
 use buck2_error::buck2_error;
 
 static RESULT: Mutex<Vec<String>> = Mutex::new(Vec::new());
+static ESCALATIONS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+static OBSERVED: Mutex<Vec<String>> = Mutex::new(Vec::new());
 
 fn mock_handler(
     category: &str,
@@ -32,6 +37,13 @@ fn mock_handler(
     ));
 }
 
+fn mock_escalation_handler(category: &str, policy: &str, err: &buck2_error::Error) {
+    ESCALATIONS
+        .lock()
+        .unwrap()
+        .push(format!("{} : {} : {}", category, policy, err));
+}
+
 fn test_init() -> MutexGuard<'static, ()> {
     // Tests in Rust can be executed concurrently, and these tests work with global state,
     // so use mutex to ensure we only run one test at a time.
@@ -41,9 +53,15 @@ fn test_init() -> MutexGuard<'static, ()> {
     static ONCE: Once = Once::new();
     ONCE.call_once(|| {
         initialize(Box::new(mock_handler)).unwrap();
+        initialize_escalation_handler(Box::new(mock_escalation_handler));
+        initialize_soft_error_observer(Box::new(|category| {
+            OBSERVED.lock().unwrap().push(category.to_owned())
+        }));
     });
 
     RESULT.lock().unwrap().clear();
+    ESCALATIONS.lock().unwrap().clear();
+    OBSERVED.lock().unwrap().clear();
 
     guard
 }
@@ -91,6 +109,11 @@ fn test_reset_counters() {
         RESULT.lock().unwrap().len(),
         "Should be logged 10 times"
     );
+    assert_eq!(
+        100,
+        OBSERVED.lock().unwrap().len(),
+        "Should be observed every time"
+    );
 
     reset_soft_error_counters();
 
@@ -107,3 +130,45 @@ fn test_reset_counters() {
         "Should be logged 10 more times"
     );
 }
+
+#[test]
+fn test_escalated_soft_error() {
+    let _guard = test_init();
+
+    reload_hard_error_config("only=test_escalated_soft_error").unwrap();
+
+    for _ in 0..2 {
+        let err = soft_error!(
+            "test_escalated_soft_error",
+            buck2_error!(buck2_error::ErrorTag::Input, "Should be escalated").into(),
+        )
+        .unwrap_err();
+        assert!(
+            format!("{:#}", err).contains(
+                "Escalated from soft error `test_escalated_soft_error` by policy `only=test_escalated_soft_error`"
+            ),
+            "{:#}",
+            err
+        );
+    }
+
+    if !is_open_source() {
+        // Not matched by the policy, so not escalated.
+        let _ignore = soft_error!(
+            "test_not_escalated_soft_error",
+            buck2_error!(buck2_error::ErrorTag::Input, "Should not be escalated").into(),
+        )
+        .unwrap();
+    }
+
+    reload_hard_error_config("").unwrap();
+
+    assert_eq!(
+        vec![
+            "test_escalated_soft_error : only=test_escalated_soft_error : Should be escalated"
+                .to_owned();
+            2
+        ],
+        *ESCALATIONS.lock().unwrap()
+    );
+}
@@ -19,6 +19,7 @@ use buck2_cli_proto::DaemonProcessInfo;
 use buck2_client_ctx::daemon_constraints::gen_daemon_constraints;
 use buck2_client_ctx::version::BuckVersion;
 use buck2_common::buckd_connection::ConnectionType;
+use buck2_common::buckd_info::BuckdInfo;
 use buck2_common::daemon_dir::DaemonDir;
 use buck2_common::init::DaemonStartupConfig;
 use buck2_common::invocation_paths::InvocationPaths;
@@ -114,9 +115,7 @@ pub(crate) fn write_process_info(
     daemon_dir: &DaemonDir,
     process_info: &DaemonProcessInfo,
 ) -> buck2_error::Result<()> {
-    let file = File::create(daemon_dir.buckd_info())?;
-    serde_json::to_writer(&file, &process_info)?;
-    Ok(())
+    BuckdInfo::new(process_info.clone()).write(daemon_dir)
 }
 
 fn verify_current_daemon(daemon_dir: &DaemonDir) -> buck2_error::Result<()> {
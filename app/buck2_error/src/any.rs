@@ -240,8 +240,8 @@ mod tests {
                 &e.tags(),
                 &[
                     crate::ErrorTag::Input,
-                    crate::ErrorTag::StarlarkFail,
-                    crate::ErrorTag::WatchmanTimeout
+                    crate::ErrorTag::WatchmanTimeout,
+                    crate::ErrorTag::StarlarkFail
                 ]
             );
         }
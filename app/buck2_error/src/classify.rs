@@ -132,7 +132,9 @@ fn tag_metadata(tag: ErrorTag) -> TagMetadata {
         // Environment errors
         ErrorTag::NoValidCerts => rank!(environment),
         ErrorTag::ServerSigterm => rank!(environment),
+        ErrorTag::DaemonIncompatibleClient => rank!(environment),
         ErrorTag::IoMaterializerFileBusy => rank!(environment),
+        ErrorTag::IoMaterializerVerificationMismatch => rank!(environment),
         ErrorTag::IoClientBrokenPipe => rank!(environment),
         ErrorTag::IoReadOnlyFilesystem => rank!(environment),
         ErrorTag::WatchmanRootNotConnectedError => rank!(environment),
@@ -147,6 +149,8 @@ fn tag_metadata(tag: ErrorTag) -> TagMetadata {
         ErrorTag::IoNotConnected => rank!(environment), // This typically means eden is not mounted
         // Typically due to poor network performance and large artifacts.
         ErrorTag::ReDeadlineExceeded => rank!(environment),
+        // The daemon held onto the artifact for longer than RE kept it around.
+        ErrorTag::MaterializerCasExpired => rank!(environment),
 
         // Tier 0 errors
         ErrorTag::ServerJemallocAssert => rank!(tier0),
@@ -220,9 +224,11 @@ fn tag_metadata(tag: ErrorTag) -> TagMetadata {
         ErrorTag::CsvParse => rank!(tier0),
         ErrorTag::CasBlobCountMismatch => rank!(tier0),
         ErrorTag::DownloadSizeMismatch => rank!(tier0),
+        ErrorTag::DownloadDigestMismatch => rank!(tier0),
 
         ErrorTag::DigestTtlMismatch => rank!(tier0),
         ErrorTag::DigestTtlInvalidResponse => rank!(tier0),
+        ErrorTag::MaterializerCasNeverExisted => rank!(tier0),
 
         ErrorTag::Bxl => rank!(tier0),
         ErrorTag::Certs => rank!(tier0),
@@ -301,6 +307,7 @@ fn tag_metadata(tag: ErrorTag) -> TagMetadata {
         ErrorTag::WatchmanConnect => rank!(tier0),
         ErrorTag::WatchmanRequestError => rank!(tier0),
         ErrorTag::NotifyWatcher => rank!(tier0),
+        ErrorTag::FileWatcherWedged => rank!(tier0),
         ErrorTag::HttpServer => rank!(tier0),
         ErrorTag::StarlarkInternal => rank!(tier0),
         ErrorTag::ActionMismatchedOutputs => rank!(tier0),
@@ -335,6 +342,8 @@ fn tag_metadata(tag: ErrorTag) -> TagMetadata {
         ErrorTag::IoEdenFileNotFound => rank!(input), // user likely specified non-existing path
         ErrorTag::MissingTarget => rank!(input),
         ErrorTag::ActionMissingOutputs => rank!(input),
+        ErrorTag::DeclareConflict => rank!(input),
+        ErrorTag::TtlRefreshFailing => rank!(tier0),
         ErrorTag::ActionWrongOutputType => rank!(input),
         ErrorTag::ActionCommandFailure => rank!(input),
         ErrorTag::ProjectMissingPath => rank!(input),
@@ -443,6 +452,24 @@ pub fn best_tag(tags: impl IntoIterator<Item = ErrorTag>) -> Option<ErrorTag> {
     tags.into_iter().min_by_key(|t| tag_rank(*t))
 }
 
+/// Whether the error has been tagged with any of `tags`, anywhere in its context chain.
+pub fn matches_tags(err: &crate::Error, tags: &[ErrorTag]) -> bool {
+    tags.iter().any(|tag| err.has_tag(*tag))
+}
+
+/// The most specific tag of the error.
+///
+/// This is the tag the error root was created with, unless that tag is generic, in which case
+/// the best tag across the whole context chain is used.
+pub fn best_root_tag(err: &crate::Error) -> Option<ErrorTag> {
+    let root_tag = err.root_tag();
+    if !tag_is_generic(&root_tag) {
+        Some(root_tag)
+    } else {
+        err.best_tag()
+    }
+}
+
 /// Tag rank: smaller is more interesting.
 fn tag_rank(tag: ErrorTag) -> u32 {
     tag_metadata(tag).rank
@@ -473,6 +500,7 @@ mod tests {
     use buck2_data::error::ErrorTag;
 
     use super::*;
+    use crate as buck2_error;
     use crate::classify::best_tag;
 
     #[test]
@@ -557,6 +585,38 @@ mod tests {
         );
     }
 
+    #[derive(buck2_error_derive::Error, Debug)]
+    #[error("test error")]
+    #[buck2(tag = WatchmanTimeout)]
+    struct WatchmanError;
+
+    #[derive(buck2_error_derive::Error, Debug)]
+    #[error("test error")]
+    #[buck2(tag = UnusedDefaultTag)]
+    struct GenericError;
+
+    #[test]
+    fn test_matches_tags() {
+        let e = crate::Error::from(WatchmanError)
+            .context("context")
+            .tag([ErrorTag::Input]);
+        assert!(matches_tags(&e, &[ErrorTag::WatchmanTimeout]));
+        assert!(matches_tags(&e, &[ErrorTag::Tier0, ErrorTag::Input]));
+        assert!(!matches_tags(&e, &[ErrorTag::Tier0]));
+        assert!(!matches_tags(&e, &[]));
+    }
+
+    #[test]
+    fn test_best_root_tag() {
+        // The root tag wins even if a context tag ranks higher.
+        let e = crate::Error::from(WatchmanError).tag([ErrorTag::ServerJemallocAssert]);
+        assert_eq!(best_root_tag(&e), Some(ErrorTag::WatchmanTimeout));
+
+        // Generic root tags fall back to the best tag overall.
+        let e = crate::Error::from(GenericError).tag([ErrorTag::Input]);
+        assert_eq!(best_root_tag(&e), Some(ErrorTag::Input));
+    }
+
     #[test]
     fn test_source_area() {
         assert_eq!(
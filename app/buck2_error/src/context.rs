@@ -12,6 +12,7 @@ use std::sync::Arc;
 use smallvec::smallvec;
 
 use crate::context_value::ContextValue;
+use crate::context_value::Remediation;
 use crate::context_value::TypedContext;
 use crate::{self as buck2_error};
 
@@ -36,6 +37,26 @@ pub trait BuckErrorContext<T>: Sealed {
         self.buck_error_context(ContextValue::Tags(smallvec![tag]))
     }
 
+    /// Suggest the exit code the client should use if this error ends the command. See
+    /// [`crate::Error::suggested_exit_code`].
+    #[track_caller]
+    fn with_exit_code(self, exit_code: u8) -> crate::Result<T> {
+        self.buck_error_context(ContextValue::ExitCode(exit_code))
+    }
+
+    /// Point to where help with this error can be found. See [`crate::Error::with_remediation`].
+    #[track_caller]
+    fn remediation(
+        self,
+        url: impl Into<String>,
+        description: impl Into<String>,
+    ) -> crate::Result<T> {
+        self.buck_error_context(ContextValue::Remediation(Remediation {
+            url: url.into(),
+            description: description.into(),
+        }))
+    }
+
     #[track_caller]
     fn internal_error(self, message: &str) -> crate::Result<T> {
         self.with_internal_error(|| message.to_owned())
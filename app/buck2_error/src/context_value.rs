@@ -21,6 +21,10 @@ pub enum ContextValue {
     Typed(Arc<dyn TypedContext>),
     StarlarkError(StarlarkContext),
     StringTag(StringTag),
+    Metadata(Metadata),
+    /// The process exit code the client should use if this error ends the command.
+    ExitCode(u8),
+    Remediation(Remediation),
 }
 
 #[derive(allocative::Allocative, Debug, Clone, Eq, PartialEq)]
@@ -28,6 +32,63 @@ pub struct StringTag {
     pub tag: String,
 }
 
+/// A link to where help with an error can be found, e.g. a wiki page or a support group.
+#[derive(allocative::Allocative, Debug, Clone, Eq, PartialEq)]
+pub struct Remediation {
+    pub url: String,
+    pub description: String,
+}
+
+/// A typed key/value pair attached to an error, intended to be read by telemetry rather than
+/// rendered as part of the error message.
+#[derive(allocative::Allocative, Debug, Clone, Eq, PartialEq)]
+pub struct Metadata {
+    #[allocative(skip)]
+    pub key: &'static str,
+    pub value: MetadataValue,
+}
+
+#[derive(allocative::Allocative, Debug, Clone, Eq, PartialEq)]
+pub enum MetadataValue {
+    String(Arc<str>),
+    Int(i64),
+    Bool(bool),
+}
+
+impl std::fmt::Display for MetadataValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::String(v) => f.write_str(v),
+            Self::Int(v) => write!(f, "{}", v),
+            Self::Bool(v) => write!(f, "{}", v),
+        }
+    }
+}
+
+impl From<String> for MetadataValue {
+    fn from(value: String) -> Self {
+        MetadataValue::String(value.into())
+    }
+}
+
+impl From<&str> for MetadataValue {
+    fn from(value: &str) -> Self {
+        MetadataValue::String(value.into())
+    }
+}
+
+impl From<i64> for MetadataValue {
+    fn from(value: i64) -> Self {
+        MetadataValue::Int(value)
+    }
+}
+
+impl From<bool> for MetadataValue {
+    fn from(value: bool) -> Self {
+        MetadataValue::Bool(value)
+    }
+}
+
 impl ContextValue {
     /// Returns whether the context should be included in the error message
     pub(crate) fn should_display(&self) -> bool {
@@ -37,6 +98,10 @@ impl ContextValue {
             Self::Tags(_) => false,
             Self::StringTag(..) => false,
             Self::StarlarkError(..) => false,
+            Self::Metadata(..) => false,
+            Self::ExitCode(..) => false,
+            // Rendered after the error message instead, once per url.
+            Self::Remediation(..) => false,
         }
     }
 
@@ -58,6 +123,15 @@ impl ContextValue {
             (ContextValue::StarlarkError(a), ContextValue::StarlarkError(b)) => {
                 assert_eq!(a, b);
             }
+            (ContextValue::Metadata(a), ContextValue::Metadata(b)) => {
+                assert_eq!(a, b);
+            }
+            (ContextValue::ExitCode(a), ContextValue::ExitCode(b)) => {
+                assert_eq!(a, b);
+            }
+            (ContextValue::Remediation(a), ContextValue::Remediation(b)) => {
+                assert_eq!(a, b);
+            }
             (_, _) => panic!("context variants don't match!"),
         }
     }
@@ -71,6 +145,9 @@ impl std::fmt::Display for ContextValue {
             Self::Typed(v) => std::fmt::Display::fmt(v, f),
             Self::StringTag(v) => f.write_str(&v.tag),
             Self::StarlarkError(v) => write!(f, "{}", v),
+            Self::Metadata(v) => write!(f, "{}={}", v.key, v.value),
+            Self::ExitCode(v) => write!(f, "exit_code={}", v),
+            Self::Remediation(v) => write!(f, "{}: {}", v.description, v.url),
         }
     }
 }
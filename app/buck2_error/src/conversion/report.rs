@@ -12,6 +12,7 @@ use buck2_data::ErrorReport;
 use crate::ErrorTag;
 use crate::context_value::ContextValue;
 use crate::context_value::StringTag;
+use crate::format::into_anyhow_for_format;
 use crate::source_location::SourceLocation;
 
 impl From<ErrorReport> for crate::Error {
@@ -41,16 +42,26 @@ impl From<ErrorReport> for crate::Error {
             let tag = ContextValue::StringTag(StringTag { tag: tag.tag });
             error = error.context(tag);
         }
+        if let Some(exit_code) = value.suggested_exit_code {
+            error = error.with_exit_code(exit_code as u8);
+        }
+        // Outermost first, so that the order is preserved.
+        for remediation in value.remediations.into_iter().rev() {
+            error = error.with_remediation(remediation.url, remediation.description);
+        }
         error
     }
 }
 
 impl From<&crate::Error> for ErrorReport {
     fn from(err: &crate::Error) -> Self {
+        // Remediations are reported separately, and appended again when the report is converted
+        // back to an error, so they're left out of the messages.
+        let without_remediations = format!("{:?}", into_anyhow_for_format(err, false).0);
         let (message, telemetry_message) = if let Some(f) = err.is_emitted() {
-            (format!("{:?}", f), Some(format!("{:?}", err)))
+            (format!("{:?}", f), Some(without_remediations))
         } else {
-            (format!("{:?}", err), None)
+            (without_remediations, None)
         };
 
         let category_key = err.category_key();
@@ -91,6 +102,15 @@ impl From<&crate::Error> for ErrorReport {
             string_tags,
             sub_error_categories,
             category_key: Some(category_key),
+            suggested_exit_code: err.suggested_exit_code().map(u32::from),
+            remediations: err
+                .remediations()
+                .into_iter()
+                .map(|remediation| buck2_data::error_report::Remediation {
+                    url: remediation.url.clone(),
+                    description: remediation.description.clone(),
+                })
+                .collect(),
         }
     }
 }
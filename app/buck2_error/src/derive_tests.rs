@@ -218,6 +218,34 @@ fn test_error_tags_vec_fn() {
     assert_eq!(&b.tags(), &[ErrorTag::WatchmanTimeout]);
 }
 
+#[test]
+fn test_error_tags_from_context() {
+    use crate::BuckErrorContext;
+
+    #[derive(buck2_error_derive::Error, Debug)]
+    #[error("Unused")]
+    #[buck2(tag = WatchmanTimeout)]
+    struct TaggedError;
+
+    let res: crate::Result<()> = Err(TaggedError.into());
+    let e = res
+        .tag(ErrorTag::Http)
+        .buck_error_context("context")
+        .tag(ErrorTag::WatchmanTimeout)
+        .unwrap_err();
+
+    // In the order they were attached, `WatchmanTimeout` only the first time.
+    assert_eq!(&e.tags(), &[ErrorTag::WatchmanTimeout, ErrorTag::Http]);
+    assert!(e.has_tag(ErrorTag::Http));
+    assert!(!e.has_tag(ErrorTag::StarlarkFail));
+
+    let report = buck2_data::ErrorReport::from(&e);
+    assert_eq!(
+        report.tags,
+        vec![ErrorTag::WatchmanTimeout as i32, ErrorTag::Http as i32]
+    );
+}
+
 #[test]
 fn test_correct_transparent() {
     #[derive(buck2_error_derive::Error, Debug)]
@@ -273,3 +301,33 @@ fn test_recovery_through_transparent_buck2_error() {
         ]
     );
 }
+
+#[test]
+fn test_metadata() {
+    #[derive(buck2_error_derive::Error, Debug)]
+    #[error("base_display")]
+    #[buck2(tag = Environment)]
+    struct BaseError;
+
+    let e: crate::Error = BaseError.into();
+    assert_eq!(e.metadata().count(), 0);
+
+    let e = e
+        .with_metadata("digest", "abc:123")
+        .context("some context")
+        .with_metadata("attempt", 2i64)
+        .with_metadata("cached", false);
+
+    assert_eq!(
+        e.metadata().collect::<Vec<_>>(),
+        vec![
+            ("cached", &crate::MetadataValue::Bool(false)),
+            ("attempt", &crate::MetadataValue::Int(2)),
+            ("digest", &crate::MetadataValue::String("abc:123".into())),
+        ]
+    );
+
+    // Metadata is not part of the rendered error.
+    assert_eq!(format!("{:#}", e), "some context: base_display");
+    assert!(!format!("{:?}", e).contains("abc:123"));
+}
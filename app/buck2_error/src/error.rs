@@ -7,10 +7,12 @@
  * of this source tree.
  */
 
+use std::collections::HashSet;
 use std::fmt;
 use std::sync::Arc;
 
 use buck2_data::ActionError;
+use dupe::Dupe;
 use smallvec::SmallVec;
 
 use crate::ErrorTag;
@@ -21,6 +23,9 @@ use crate::classify::error_tag_category;
 use crate::classify::tag_is_generic;
 use crate::classify::tag_is_hidden;
 use crate::context_value::ContextValue;
+use crate::context_value::Metadata;
+use crate::context_value::MetadataValue;
+use crate::context_value::Remediation;
 use crate::context_value::StarlarkContext;
 use crate::context_value::StringTag;
 use crate::context_value::TypedContext;
@@ -74,6 +79,56 @@ impl Error {
         buck2_error.tag([error_tag])
     }
 
+    /// Combines errors that happened independently of each other, e.g. the failures of several
+    /// dependencies, into one error whose message lists all of them.
+    ///
+    /// The result carries the tags of all of them, and its tier is the worst tier among them.
+    /// [`Error::iter_roots`] gives the original errors back.
+    #[track_caller]
+    #[cold]
+    pub fn aggregate(errors: Vec<Error>) -> Self {
+        use fmt::Write;
+
+        if errors.len() == 1 {
+            return errors.into_iter().next().unwrap();
+        }
+
+        let mut message = format!("{} errors:", errors.len());
+        for (i, error) in errors.iter().enumerate() {
+            let error = format!("{:#}", error);
+            let mut lines = error.lines();
+            write!(message, "\n{}. {}", i + 1, lines.next().unwrap_or_default()).unwrap();
+            for line in lines {
+                write!(message, "\n   {}", line).unwrap();
+            }
+        }
+
+        let tags = errors.iter().flat_map(|e| e.tags()).collect::<Vec<_>>();
+        let error_tag = best_tag(tags.iter().copied()).unwrap_or(ErrorTag::Tier0);
+        Self::new(
+            message,
+            error_tag,
+            SourceLocation::new(std::panic::Location::caller().file()),
+            None,
+        )
+        .tag(tags)
+        .context(AggregatedErrors(errors))
+    }
+
+    /// The errors aggregated by [`Error::aggregate`] into this one, recursively, in order. An error
+    /// that doesn't aggregate others only contains itself.
+    pub fn iter_roots(&self) -> impl Iterator<Item = Error> {
+        let mut roots = Vec::new();
+        let mut stack = vec![self.dupe()];
+        while let Some(error) = stack.pop() {
+            match error.find_typed_context::<AggregatedErrors>() {
+                Some(aggregated) => stack.extend(aggregated.0.iter().rev().cloned()),
+                None => roots.push(error),
+            }
+        }
+        roots.into_iter()
+    }
+
     fn iter_kinds(&self) -> impl Iterator<Item = &ErrorKind> {
         let mut cur = Some(self);
         std::iter::from_fn(move || {
@@ -93,6 +148,11 @@ impl Error {
         r
     }
 
+    /// The tag the error root was created with.
+    pub(crate) fn root_tag(&self) -> ErrorTag {
+        self.root().error_tag()
+    }
+
     pub fn action_error(&self) -> Option<&buck2_data::ActionError> {
         self.root().action_error()
     }
@@ -151,7 +211,8 @@ impl Error {
     ///
     /// This tries to include the least information possible that can be used to uniquely identify an error type.
     pub fn category_key(&self) -> String {
-        let tags = self.tags();
+        let mut tags = self.tags();
+        tags.sort_unstable_by_key(|tag| tag.as_str_name());
 
         let non_generic_tags: Vec<ErrorTag> = tags
             .clone()
@@ -207,6 +268,60 @@ impl Error {
         )))
     }
 
+    /// Attach a typed key/value pair to the error. Unlike `context`, this is not included in the
+    /// error message, and is instead retrieved via [`Error::metadata`].
+    pub fn with_metadata(self, key: &'static str, value: impl Into<MetadataValue>) -> Self {
+        self.context(ContextValue::Metadata(Metadata {
+            key,
+            value: value.into(),
+        }))
+    }
+
+    /// Suggest the exit code the client should use if this error ends the command.
+    ///
+    /// Exit codes buck2 gives its own meaning to (see [`is_reserved_exit_code`]) are rejected: they
+    /// are never returned from [`Error::suggested_exit_code`], so the client picks the exit code as
+    /// usual.
+    pub fn with_exit_code(self, exit_code: u8) -> Self {
+        self.context(ContextValue::ExitCode(exit_code))
+    }
+
+    /// The exit code suggested via [`Error::with_exit_code`], if any. If several were attached,
+    /// the innermost one (closest to where the error was created) wins. Reserved exit codes are
+    /// skipped.
+    pub fn suggested_exit_code(&self) -> Option<u8> {
+        self.iter_context()
+            .filter_map(|kind| match kind {
+                ContextValue::ExitCode(code) if !is_reserved_exit_code(*code) => Some(*code),
+                _ => None,
+            })
+            .last()
+    }
+
+    /// Point to where help with this error can be found. The url is shown after the error message.
+    pub fn with_remediation(self, url: impl Into<String>, description: impl Into<String>) -> Self {
+        self.context(ContextValue::Remediation(Remediation {
+            url: url.into(),
+            description: description.into(),
+        }))
+    }
+
+    /// The remediations attached to this error, innermost first. If several were attached with
+    /// the same url, only the innermost one is returned.
+    pub fn remediations(&self) -> Vec<&Remediation> {
+        let mut remediations: Vec<&Remediation> = self
+            .iter_context()
+            .filter_map(|kind| match kind {
+                ContextValue::Remediation(remediation) => Some(remediation),
+                _ => None,
+            })
+            .collect();
+        remediations.reverse();
+        let mut urls = HashSet::new();
+        remediations.retain(|remediation| urls.insert(remediation.url.as_str()));
+        remediations
+    }
+
     pub fn context_for_starlark_backtrace(self, context: StarlarkContext) -> Self {
         Self(Arc::new(ErrorKind::WithContext(
             ContextValue::StarlarkError(context),
@@ -233,6 +348,9 @@ impl Error {
     }
 
     pub fn get_tier(&self) -> Option<Tier> {
+        if let Some(aggregated) = self.find_typed_context::<AggregatedErrors>() {
+            return aggregated.0.iter().filter_map(|e| e.get_tier()).max();
+        }
         best_tag(self.tags()).map(error_tag_category).flatten()
     }
 
@@ -262,11 +380,34 @@ impl Error {
             .collect()
     }
 
-    /// Get all the tags that have been added to this error
+    /// All metadata attached to this error, most recently added first. A key may appear more than
+    /// once if it was attached more than once.
+    pub fn metadata(&self) -> impl Iterator<Item = (&str, &MetadataValue)> {
+        self.iter_context().filter_map(|kind| match kind {
+            ContextValue::Metadata(m) => Some((m.key, &m.value)),
+            _ => None,
+        })
+    }
+
+    /// All tags attached to this error, both by the root and by any context added afterwards.
+    ///
+    /// Tags are returned in the order they were attached, starting with the root's. A tag attached
+    /// more than once is only returned the first time.
     pub fn tags(&self) -> Vec<crate::ErrorTag> {
-        let mut tags: Vec<_> = self.tags_unsorted().collect();
-        tags.sort_unstable_by_key(|tag| tag.as_str_name());
-        tags.dedup();
+        // Contexts are iterated most recently added first.
+        let levels: Vec<_> = self
+            .iter_context()
+            .filter_map(|kind| match kind {
+                ContextValue::Tags(tags) => Some(tags),
+                _ => None,
+            })
+            .collect();
+        let mut tags = Vec::new();
+        for tag in levels.into_iter().rev().flatten() {
+            if !tags.contains(tag) {
+                tags.push(*tag);
+            }
+        }
         tags
     }
 
@@ -275,6 +416,7 @@ impl Error {
         best_tag(self.tags_unsorted())
     }
 
+    /// Whether `tag` was attached anywhere in this error's context chain.
     pub fn has_tag(&self, tag: crate::ErrorTag) -> bool {
         self.tags_unsorted().any(|t| t == tag)
     }
@@ -331,6 +473,42 @@ impl Error {
     }
 }
 
+/// The errors combined by [`Error::aggregate`]. Not displayed, they are already in the message.
+#[derive(allocative::Allocative)]
+struct AggregatedErrors(Vec<Error>);
+
+impl fmt::Display for AggregatedErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} errors", self.0.len())
+    }
+}
+
+impl TypedContext for AggregatedErrors {
+    fn eq(&self, other: &dyn TypedContext) -> bool {
+        match (other as &dyn std::any::Any).downcast_ref::<Self>() {
+            Some(other) => {
+                self.0.len() == other.0.len()
+                    && self
+                        .0
+                        .iter()
+                        .zip(&other.0)
+                        .all(|(a, b)| a.root_id() == b.root_id())
+            }
+            None => false,
+        }
+    }
+
+    fn should_display(&self) -> bool {
+        false
+    }
+}
+
+/// Whether buck2 gives `exit_code` its own meaning, so an error can't suggest it: success, the
+/// failure categories and conditions the client reports itself, and signal exits.
+pub fn is_reserved_exit_code(exit_code: u8) -> bool {
+    matches!(exit_code, 0..=6 | 11 | 129..=192)
+}
+
 #[cfg(test)]
 mod tests {
     use std::sync::Arc;
@@ -380,6 +558,36 @@ mod tests {
         assert_eq!(e.get_tier(), Some(Tier::Environment));
     }
 
+    #[test]
+    fn test_aggregate() {
+        let input = crate::buck2_error!(crate::ErrorTag::Input, "input");
+        let environment = crate::buck2_error!(crate::ErrorTag::Environment, "line 1\nline 2");
+        let infra = crate::buck2_error!(crate::ErrorTag::Tier0, "infra");
+
+        let e = crate::Error::aggregate(vec![input.clone(), environment.clone()]);
+        assert_eq!(e.get_tier(), Some(Tier::Environment));
+        assert!(e.has_tag(crate::ErrorTag::Input));
+        assert!(e.has_tag(crate::ErrorTag::Environment));
+        assert_eq!(
+            format!("{:#}", e),
+            "2 errors:\n1. input\n2. line 1\n   line 2"
+        );
+
+        // Nested aggregates are flattened, and context added later doesn't hide the roots.
+        let e = crate::Error::aggregate(vec![e, infra.clone()]).context("context");
+        assert_eq!(e.get_tier(), Some(Tier::Tier0));
+        assert_eq!(
+            e.iter_roots().map(|e| e.root_id()).collect::<Vec<_>>(),
+            [input.root_id(), environment.root_id(), infra.root_id()]
+        );
+
+        // A single error is returned as is.
+        let e = crate::Error::aggregate(vec![input.clone()]);
+        assert_eq!(e.root_id(), input.root_id());
+        assert_eq!(e.get_tier(), Some(Tier::Input));
+        assert_eq!(e.iter_roots().count(), 1);
+    }
+
     #[test]
     fn test_category_key() {
         let err: crate::Error = TestError.into();
@@ -401,4 +609,96 @@ mod tests {
         ]);
         assert_eq!(err.category_key(), format!("RE_INTERNAL"));
     }
+
+    #[test]
+    fn test_remediations_deduped() {
+        use crate::BuckErrorContext;
+
+        let res: crate::Result<()> = Err(TestError.into());
+        let err = res
+            .remediation("https://wiki/cache", "Cache troubleshooting")
+            .buck_error_context("inner context")
+            .remediation("https://wiki/oncall", "Ask the oncall")
+            .remediation("https://wiki/cache", "Cache troubleshooting, again")
+            .buck_error_context("outer context")
+            .unwrap_err();
+
+        assert_eq!(
+            err.remediations()
+                .iter()
+                .map(|r| (r.url.as_str(), r.description.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("https://wiki/cache", "Cache troubleshooting"),
+                ("https://wiki/oncall", "Ask the oncall"),
+            ]
+        );
+        assert_eq!(
+            format!("{:#}", err),
+            "outer context: inner context: Test\nSee: https://wiki/cache\nSee: https://wiki/oncall"
+        );
+
+        // Round tripping through an error report neither loses nor repeats them.
+        let report = buck2_data::ErrorReport::from(&err);
+        assert_eq!(report.remediations.len(), 2);
+        assert!(!report.message.contains("See:"), "{}", report.message);
+        let restored = crate::Error::from(report);
+        assert_eq!(
+            restored
+                .remediations()
+                .iter()
+                .map(|r| r.url.as_str())
+                .collect::<Vec<_>>(),
+            vec!["https://wiki/cache", "https://wiki/oncall"]
+        );
+        let restored = format!("{:?}", restored);
+        assert_eq!(
+            restored.matches("See: https://wiki/cache").count(),
+            1,
+            "{}",
+            restored
+        );
+    }
+
+    #[test]
+    fn test_suggested_exit_code() {
+        use crate::BuckErrorContext;
+
+        let err: crate::Error = TestError.into();
+        assert_eq!(err.suggested_exit_code(), None);
+
+        let err = err.with_exit_code(42).context("context");
+        assert_eq!(err.suggested_exit_code(), Some(42));
+
+        // The innermost exit code takes precedence over ones attached further up.
+        let res: crate::Result<()> = Err(err);
+        let err = res.with_exit_code(7).unwrap_err();
+        assert_eq!(err.suggested_exit_code(), Some(42));
+
+        // It survives a round trip through an error report.
+        let report = buck2_data::ErrorReport::from(&err);
+        assert_eq!(report.suggested_exit_code, Some(42));
+        assert_eq!(crate::Error::from(report).suggested_exit_code(), Some(42));
+    }
+
+    #[test]
+    fn test_reserved_exit_code_rejected() {
+        use crate::BuckErrorContext;
+
+        for code in [0, 1, 3, 6, 11, 130, 141] {
+            let err: crate::Error = TestError.into();
+            assert_eq!(err.with_exit_code(code).suggested_exit_code(), None);
+
+            let res: crate::Result<()> = Err(TestError.into());
+            assert_eq!(
+                res.with_exit_code(code).unwrap_err().suggested_exit_code(),
+                None
+            );
+        }
+
+        // A reserved code doesn't hide a valid one attached elsewhere.
+        let err: crate::Error = TestError.into();
+        let err = err.with_exit_code(42).with_exit_code(0);
+        assert_eq!(err.suggested_exit_code(), Some(42));
+    }
 }
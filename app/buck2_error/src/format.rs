@@ -82,15 +82,41 @@ pub(crate) fn into_anyhow_for_format(
 // error message which is the first error message since stack is reversed so first error is the right one
 // span which is the same as error message
 
+/// Appends a `See: <url>` line per remediation of `error`.
+fn fmt_with_remediations(
+    error: &crate::Error,
+    message: String,
+    f: &mut fmt::Formatter<'_>,
+) -> fmt::Result {
+    f.write_str(&message)?;
+    for remediation in error.remediations() {
+        write!(f, "\nSee: {}", remediation.url)?;
+    }
+    Ok(())
+}
+
 impl fmt::Debug for crate::Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        fmt::Debug::fmt(&into_anyhow_for_format(self, false).0, f)
+        let anyhow = into_anyhow_for_format(self, false).0;
+        if self.remediations().is_empty() {
+            return fmt::Debug::fmt(&anyhow, f);
+        }
+        fmt_with_remediations(self, format!("{:?}", anyhow), f)
     }
 }
 
 impl fmt::Display for crate::Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        fmt::Display::fmt(&into_anyhow_for_format(self, false).0, f)
+        let anyhow = into_anyhow_for_format(self, false).0;
+        if self.remediations().is_empty() {
+            return fmt::Display::fmt(&anyhow, f);
+        }
+        let message = if f.alternate() {
+            format!("{:#}", anyhow)
+        } else {
+            format!("{}", anyhow)
+        };
+        fmt_with_remediations(self, message, f)
     }
 }
 
@@ -0,0 +1,316 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Interop with error reporting outside of buck2, for tools that embed buck2 crates.
+//!
+//! [`ErrorReportModel`] is a plain snapshot of an error that can be serialized or rendered by any
+//! reporting stack. With the `miette` feature, it also converts to a `miette::Diagnostic`.
+
+#[cfg(feature = "miette")]
+pub mod miette;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::ErrorTag;
+use crate::Tier;
+use crate::any::CrateAsStdError;
+use crate::any::recover_crate_error;
+use crate::source_location::SourceLocation;
+
+/// How an error classifies for the exit code of the process it ends. The buck2 client exits with
+/// these codes too.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorExitCode {
+    UnknownFailure,
+    InfraError,
+    UserError,
+    BrokenPipe,
+    /// Suggested with [`crate::Error::with_exit_code`].
+    Suggested(u8),
+}
+
+impl ErrorExitCode {
+    pub const fn code(self) -> u8 {
+        match self {
+            ErrorExitCode::UnknownFailure => 1,
+            ErrorExitCode::InfraError => 2,
+            ErrorExitCode::UserError => 3,
+            ErrorExitCode::BrokenPipe => 130,
+            ErrorExitCode::Suggested(code) => code,
+        }
+    }
+}
+
+/// A snapshot of a [`crate::Error`] for rendering and serialization outside of buck2.
+///
+/// Fields are only ever added to this, so that serialized reports stay readable.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ErrorReportModel {
+    /// The outermost message, i.e. what the error displays as without `{:#}`.
+    pub message: String,
+    /// The messages under `message`, outermost first. The last one is the error root.
+    pub source_chain: Vec<String>,
+    /// `INPUT`, `ENVIRONMENT` or `TIER0`, if the tags classify the error.
+    pub tier: Option<String>,
+    /// Names of the tags, sorted.
+    pub tags: Vec<String>,
+    /// The most interesting of `tags`.
+    pub best_tag: Option<String>,
+    /// Key/value pairs attached with [`crate::Error::with_metadata`], most recently added first.
+    pub metadata: Vec<(String, String)>,
+    /// Where in buck2 the error was created.
+    pub source_location: ReportSourceLocation,
+    pub remediations: Vec<ReportRemediation>,
+    /// See [`crate::Error::exit_code`].
+    pub exit_code: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReportSourceLocation {
+    pub path: String,
+    pub type_name: Option<String>,
+    pub line: Option<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReportRemediation {
+    pub url: String,
+    pub description: String,
+}
+
+fn tier_name(tier: Tier) -> &'static str {
+    match tier {
+        Tier::Input => "INPUT",
+        Tier::Environment => "ENVIRONMENT",
+        Tier::Tier0 => "TIER0",
+    }
+}
+
+impl crate::Error {
+    /// How this error classifies for the exit code: the code suggested with
+    /// [`crate::Error::with_exit_code`] if any, otherwise by tier.
+    pub fn exit_code_kind(&self) -> ErrorExitCode {
+        if self.has_tag(ErrorTag::IoClientBrokenPipe) {
+            ErrorExitCode::BrokenPipe
+        } else if let Some(exit_code) = self.suggested_exit_code() {
+            ErrorExitCode::Suggested(exit_code)
+        } else {
+            match self.get_tier() {
+                Some(Tier::Input) => ErrorExitCode::UserError,
+                Some(Tier::Tier0 | Tier::Environment) => ErrorExitCode::InfraError,
+                None => ErrorExitCode::UnknownFailure,
+            }
+        }
+    }
+
+    /// The exit code a process ending with this error should use, the same the buck2 client would.
+    ///
+    /// That is the code suggested with [`crate::Error::with_exit_code`] if any, otherwise 3 for
+    /// input errors, 2 for infra errors, and 1 for errors that aren't classified.
+    pub fn exit_code(&self) -> u8 {
+        self.exit_code_kind().code()
+    }
+
+    /// [`crate::Error::exit_code`], for returning from `main`.
+    pub fn to_exit_code(&self) -> std::process::ExitCode {
+        std::process::ExitCode::from(self.exit_code())
+    }
+}
+
+impl From<&crate::Error> for ErrorReportModel {
+    fn from(err: &crate::Error) -> Self {
+        let anyhow = crate::format::into_anyhow_for_format(err, false).0;
+        let mut chain = anyhow.chain().map(|e| e.to_string());
+        let message = chain.next().unwrap_or_default();
+        let source_location = err.source_location();
+
+        ErrorReportModel {
+            message,
+            source_chain: chain.collect(),
+            tier: err.get_tier().map(|tier| tier_name(tier).to_owned()),
+            tags: err
+                .tags()
+                .iter()
+                .map(|tag| tag.as_str_name().to_owned())
+                .collect(),
+            best_tag: err.best_tag().map(|tag| tag.as_str_name().to_owned()),
+            metadata: err
+                .metadata()
+                .map(|(key, value)| (key.to_owned(), value.to_string()))
+                .collect(),
+            source_location: ReportSourceLocation {
+                path: source_location.path().to_owned(),
+                type_name: source_location.type_name().map(str::to_owned),
+                line: source_location.source_line(),
+            },
+            remediations: err
+                .remediations()
+                .into_iter()
+                .map(|remediation| ReportRemediation {
+                    url: remediation.url.clone(),
+                    description: remediation.description.clone(),
+                })
+                .collect(),
+            exit_code: err.exit_code(),
+        }
+    }
+}
+
+impl ErrorReportModel {
+    /// A report of an error from outside buck2. If it comes from a [`crate::Error`], the report
+    /// has its tags, otherwise it has none rather than a guessed tier.
+    #[track_caller]
+    pub fn from_anyhow(err: &anyhow::Error) -> Self {
+        let caller = std::panic::Location::caller();
+        let source_location = SourceLocation::new(caller.file());
+        if err.chain().any(|e| e.is::<CrateAsStdError>()) {
+            // The tag is only used for a root that isn't a buck2 error, and there is none.
+            return Self::from(&recover_crate_error(
+                err.as_ref(),
+                source_location,
+                ErrorTag::Tier0,
+            ));
+        }
+        let mut chain = err.chain().map(|e| e.to_string());
+        ErrorReportModel {
+            message: chain.next().unwrap_or_default(),
+            source_chain: chain.collect(),
+            tier: None,
+            tags: Vec::new(),
+            best_tag: None,
+            metadata: Vec::new(),
+            source_location: ReportSourceLocation {
+                path: source_location.path().to_owned(),
+                type_name: None,
+                line: Some(caller.line()),
+            },
+            remediations: Vec::new(),
+            exit_code: ErrorExitCode::UnknownFailure.code(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::BuckErrorContext;
+
+    fn root(tag: ErrorTag) -> crate::Error {
+        crate::Error::new(
+            "file not found".to_owned(),
+            tag,
+            SourceLocation::new("fbcode/buck2/app/buck2_foo/src/lib.rs").with_source_line(12),
+            None,
+        )
+    }
+
+    #[test]
+    fn test_report_model() {
+        let err = root(ErrorTag::Input)
+            .context("reading config")
+            .with_metadata("path", "foo/.buckconfig")
+            .with_metadata("attempt", 2i64)
+            .with_remediation("https://example.com/config", "Config help")
+            .context("loading cell `root`");
+
+        assert_eq!(
+            serde_json::to_value(ErrorReportModel::from(&err)).unwrap(),
+            json!({
+                "message": "loading cell `root`",
+                "source_chain": ["reading config", "file not found"],
+                "tier": "INPUT",
+                "tags": ["INPUT"],
+                "best_tag": "INPUT",
+                "metadata": [["attempt", "2"], ["path", "foo/.buckconfig"]],
+                "source_location": {
+                    "path": "buck2_foo/src/lib.rs",
+                    "type_name": null,
+                    "line": 12,
+                },
+                "remediations": [{
+                    "url": "https://example.com/config",
+                    "description": "Config help",
+                }],
+                "exit_code": 3,
+            })
+        );
+    }
+
+    #[test]
+    fn test_report_model_suggested_exit_code() {
+        let err: crate::Result<()> = Err(root(ErrorTag::Tier0));
+        let err = err
+            .with_exit_code(7)
+            .buck_error_context("running action")
+            .unwrap_err();
+
+        assert_eq!(
+            serde_json::to_value(ErrorReportModel::from(&err)).unwrap(),
+            json!({
+                "message": "running action",
+                "source_chain": ["file not found"],
+                "tier": "TIER0",
+                "tags": ["TIER0"],
+                "best_tag": "TIER0",
+                "metadata": [],
+                "source_location": {
+                    "path": "buck2_foo/src/lib.rs",
+                    "type_name": null,
+                    "line": 12,
+                },
+                "remediations": [],
+                "exit_code": 7,
+            })
+        );
+    }
+
+    #[test]
+    fn test_report_model_round_trip() {
+        let model = ErrorReportModel::from(&root(ErrorTag::Input).context("context"));
+        let json = serde_json::to_string(&model).unwrap();
+        assert_eq!(
+            model,
+            serde_json::from_str::<ErrorReportModel>(&json).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_report_model_from_anyhow() {
+        let err = anyhow::Error::from(root(ErrorTag::Input)).context("loading targets");
+        let model = ErrorReportModel::from_anyhow(&err);
+        assert_eq!(model.message, "loading targets");
+        assert_eq!(model.source_chain, vec!["file not found"]);
+        assert_eq!(model.tags, vec!["INPUT"]);
+        assert_eq!(model.exit_code, 3);
+
+        let err = anyhow::anyhow!("No owning target found").context("resolving owners");
+        let model = ErrorReportModel::from_anyhow(&err);
+        assert_eq!(model.message, "resolving owners");
+        assert_eq!(model.source_chain, vec!["No owning target found"]);
+        assert_eq!(model.tier, None);
+        assert!(model.tags.is_empty());
+        assert_eq!(model.exit_code, 1);
+    }
+
+    #[test]
+    fn test_exit_code() {
+        assert_eq!(root(ErrorTag::Input).exit_code(), 3);
+        assert_eq!(root(ErrorTag::Environment).exit_code(), 2);
+        assert_eq!(root(ErrorTag::Tier0).exit_code(), 2);
+        assert_eq!(root(ErrorTag::IoClientBrokenPipe).exit_code(), 130);
+        assert_eq!(root(ErrorTag::Input).with_exit_code(42).exit_code(), 42);
+        assert_eq!(
+            root(ErrorTag::Input).with_exit_code(2).exit_code_kind(),
+            ErrorExitCode::Suggested(2)
+        );
+    }
+}
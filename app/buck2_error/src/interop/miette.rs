@@ -0,0 +1,179 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Conversion of errors into `miette` diagnostics.
+
+use std::error::Error as StdError;
+use std::fmt;
+
+use miette::Diagnostic;
+use miette::LabeledSpan;
+use miette::Severity;
+
+use crate::interop::ErrorReportModel;
+
+/// An [`ErrorReportModel`] as a `miette::Diagnostic`, e.g. to wrap in a `miette::Report`.
+///
+/// The source chain of the report is the `source()` chain of the diagnostic. The best tag is the
+/// diagnostic code, and remediations are its help. Where the error was created in buck2 is given as
+/// a label, which is only rendered if the embedder attaches source code.
+#[derive(Debug)]
+pub struct ReportDiagnostic {
+    model: ErrorReportModel,
+    source: Option<Box<ChainLink>>,
+}
+
+#[derive(Debug)]
+struct ChainLink {
+    message: String,
+    source: Option<Box<ChainLink>>,
+}
+
+impl ReportDiagnostic {
+    pub fn model(&self) -> &ErrorReportModel {
+        &self.model
+    }
+}
+
+impl From<ErrorReportModel> for ReportDiagnostic {
+    fn from(model: ErrorReportModel) -> Self {
+        let source = model
+            .source_chain
+            .iter()
+            .rev()
+            .fold(None, |source, message| {
+                Some(Box::new(ChainLink {
+                    message: message.clone(),
+                    source,
+                }))
+            });
+        ReportDiagnostic { model, source }
+    }
+}
+
+impl From<&crate::Error> for ReportDiagnostic {
+    fn from(err: &crate::Error) -> Self {
+        ErrorReportModel::from(err).into()
+    }
+}
+
+impl fmt::Display for ReportDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.model.message)
+    }
+}
+
+impl StdError for ReportDiagnostic {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        self.source.as_deref().map(|s| s as _)
+    }
+}
+
+impl fmt::Display for ChainLink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl StdError for ChainLink {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        self.source.as_deref().map(|s| s as _)
+    }
+}
+
+impl Diagnostic for ReportDiagnostic {
+    fn code<'a>(&'a self) -> Option<Box<dyn fmt::Display + 'a>> {
+        self.model
+            .best_tag
+            .as_ref()
+            .map(|tag| Box::new(tag) as Box<dyn fmt::Display>)
+    }
+
+    fn severity(&self) -> Option<Severity> {
+        Some(Severity::Error)
+    }
+
+    fn help<'a>(&'a self) -> Option<Box<dyn fmt::Display + 'a>> {
+        if self.model.remediations.is_empty() {
+            return None;
+        }
+        let help = self
+            .model
+            .remediations
+            .iter()
+            .map(|remediation| format!("{}: {}", remediation.description, remediation.url))
+            .collect::<Vec<_>>()
+            .join("\n");
+        Some(Box::new(help))
+    }
+
+    fn url<'a>(&'a self) -> Option<Box<dyn fmt::Display + 'a>> {
+        self.model
+            .remediations
+            .first()
+            .map(|remediation| Box::new(&remediation.url) as Box<dyn fmt::Display>)
+    }
+
+    fn labels(&self) -> Option<Box<dyn Iterator<Item = LabeledSpan> + '_>> {
+        let location = &self.model.source_location;
+        let line = location.line?;
+        let label = format!("raised at {}:{}", location.path, line);
+        Some(Box::new(std::iter::once(LabeledSpan::new(
+            Some(label),
+            0,
+            0,
+        ))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ErrorTag;
+    use crate::source_location::SourceLocation;
+
+    #[test]
+    fn test_diagnostic() {
+        let err = crate::Error::new(
+            "file not found".to_owned(),
+            ErrorTag::Input,
+            SourceLocation::new("fbcode/buck2/app/buck2_foo/src/lib.rs").with_source_line(12),
+            None,
+        )
+        .context("reading config")
+        .with_remediation("https://example.com/config", "Config help")
+        .context("loading cell `root`");
+
+        let diagnostic = ReportDiagnostic::from(&err);
+        assert_eq!(diagnostic.to_string(), "loading cell `root`");
+        let mut chain = Vec::new();
+        let mut source = diagnostic.source();
+        while let Some(s) = source {
+            chain.push(s.to_string());
+            source = s.source();
+        }
+        assert_eq!(chain, vec!["reading config", "file not found"]);
+
+        assert_eq!(diagnostic.code().unwrap().to_string(), "INPUT");
+        assert_eq!(
+            diagnostic.help().unwrap().to_string(),
+            "Config help: https://example.com/config"
+        );
+        assert_eq!(
+            diagnostic.url().unwrap().to_string(),
+            "https://example.com/config"
+        );
+        let labels: Vec<_> = diagnostic.labels().unwrap().collect();
+        assert_eq!(labels.len(), 1);
+        assert_eq!(labels[0].label(), Some("raised at buck2_foo/src/lib.rs:12"));
+
+        let report = miette::Report::new(diagnostic);
+        assert_eq!(report.chain().count(), 3);
+    }
+}
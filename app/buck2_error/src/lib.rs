@@ -20,6 +20,7 @@ mod conversion_test;
 mod derive_tests;
 mod error;
 mod format;
+pub mod interop;
 pub mod macros;
 mod root;
 pub mod source_location;
@@ -37,9 +38,11 @@ use std::error::Request;
 #[doc(inline)]
 pub use classify::Tier;
 pub use context::BuckErrorContext;
+pub use context_value::MetadataValue;
 pub use context_value::TypedContext;
 pub use error::DynLateFormat;
 pub use error::Error;
+pub use error::is_reserved_exit_code;
 pub use root::UniqueRootId;
 
 pub type Result<T> = std::result::Result<T, crate::Error>;
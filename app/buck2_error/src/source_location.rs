@@ -82,6 +82,15 @@ impl SourceLocation {
     pub fn type_name(&self) -> Option<&str> {
         self.type_name.as_deref()
     }
+
+    /// The path of the file, relative to `buck2/app`.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn source_line(&self) -> Option<u32> {
+        self.source_line
+    }
 }
 
 impl std::fmt::Display for SourceLocation {
@@ -97,9 +97,11 @@ fn error_with_starlark_context(
                     ContextValue::StarlarkError(_) => {
                         return buck2_error.context_for_starlark_backtrace(starlark_context);
                     }
-                    ContextValue::Tags(_) | ContextValue::StringTag(_) => {
-                        context_stack.push(context_value.clone())
-                    }
+                    ContextValue::Tags(_)
+                    | ContextValue::StringTag(_)
+                    | ContextValue::Metadata(_)
+                    | ContextValue::ExitCode(_)
+                    | ContextValue::Remediation(_) => context_stack.push(context_value.clone()),
                 }
 
                 buck2_error = inner.clone();
@@ -291,10 +293,10 @@ mod tests {
         assert_eq!(
             &e.tags(),
             &[
-                crate::ErrorTag::StarlarkFail,
-                crate::ErrorTag::StarlarkNativeInput,
                 crate::ErrorTag::Tier0,
-                crate::ErrorTag::WatchmanTimeout
+                crate::ErrorTag::WatchmanTimeout,
+                crate::ErrorTag::StarlarkNativeInput,
+                crate::ErrorTag::StarlarkFail
             ]
         );
     }
@@ -9,5 +9,7 @@
 
 pub mod http;
 
+pub mod io_pressure;
+pub mod manifest;
 pub mod materializer;
 pub mod nodisk;
@@ -8,8 +8,12 @@
  */
 
 use std::fmt;
+use std::io::Read;
 use std::io::Write;
 use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
 use std::time::Duration;
 
 use allocative::Allocative;
@@ -20,6 +24,7 @@ use buck2_common::cas_digest::SHA256_SIZE;
 use buck2_common::file_ops::FileDigest;
 use buck2_common::file_ops::TrackedFileDigest;
 use buck2_core::fs::fs_util;
+use buck2_core::fs::paths::abs_norm_path::AbsNormPath;
 use buck2_core::fs::project::ProjectRoot;
 use buck2_core::fs::project_rel_path::ProjectRelativePath;
 use buck2_error::BuckErrorContext;
@@ -34,6 +39,7 @@ use dupe::Dupe;
 use futures::StreamExt;
 use futures::stream::Stream;
 use hyper::Response;
+use hyper::StatusCode;
 use sha1::Digest;
 use sha1::Sha1;
 use sha2::Sha256;
@@ -176,6 +182,31 @@ enum HttpDownloadError {
         debug: MaybeResponseDebugInfo,
     },
 
+    #[error(
+        "Server sent a Content-Length of {received} bytes for {url}, expected {expected} bytes"
+    )]
+    ContentLengthMismatch {
+        url: String,
+        expected: u64,
+        received: u64,
+    },
+
+    #[error(
+        "Server sent an unexpected range for {url} when resuming from byte {offset}: `{content_range}`"
+    )]
+    InvalidRangeResponse {
+        url: String,
+        offset: u64,
+        content_range: String,
+    },
+
+    #[error("Received {received} bytes from {url}, expected {expected} bytes")]
+    BodySizeMismatch {
+        url: String,
+        expected: u64,
+        received: u64,
+    },
+
     #[error(transparent)]
     IoError(buck2_error::Error),
 }
@@ -183,7 +214,11 @@ enum HttpDownloadError {
 impl HttpDownloadError {
     fn into_final(mut self) -> Self {
         match &mut self {
-            Self::Client(..) | Self::IoError(..) => {}
+            Self::Client(..)
+            | Self::ContentLengthMismatch { .. }
+            | Self::InvalidRangeResponse { .. }
+            | Self::BodySizeMismatch { .. }
+            | Self::IoError(..) => {}
             Self::InvalidChecksum { debug, .. } | Self::MaybeNotAllowedOnVpnless { debug, .. } => {
                 debug.is_final = true;
             }
@@ -217,11 +252,29 @@ impl HttpErrorForRetry for HttpDownloadError {
                 // message body... so it's a good idea to retry those.
                 cfg!(fbcode_build)
             }
+            // Flaky mirrors sometimes cut responses short or report the wrong size.
+            Self::ContentLengthMismatch { .. }
+            | Self::InvalidRangeResponse { .. }
+            | Self::BodySizeMismatch { .. } => true,
             Self::IoError(..) | Self::MaybeNotAllowedOnVpnless { .. } => false,
         }
     }
 }
 
+impl HttpDownloadError {
+    /// Whether what was written before this error is a valid prefix of the file, so that the next
+    /// attempt can ask for just the rest of it.
+    fn is_resumable(&self) -> bool {
+        match self {
+            Self::Client(HttpError::Transfer { .. }) => true,
+            Self::BodySizeMismatch {
+                expected, received, ..
+            } => received < expected,
+            _ => false,
+        }
+    }
+}
+
 impl AsBuck2Error for HttpHeadError {
     fn as_buck2_error(self) -> buck2_error::Error {
         buck2_error::Error::from(self)
@@ -248,6 +301,8 @@ pub async fn http_head(client: &HttpClient, url: &str) -> buck2_error::Result<Re
     Ok(response)
 }
 
+/// Download `url` to `path`, checking it against `checksum` and, if known, `size`. Retries on
+/// transient errors, resuming from where the last attempt stopped if the server allows it.
 pub async fn http_download(
     client: &HttpClient,
     fs: &ProjectRoot,
@@ -255,37 +310,65 @@ pub async fn http_download(
     path: &ProjectRelativePath,
     url: &str,
     checksum: &Checksum,
+    size: Option<u64>,
+    executable: bool,
+) -> buck2_error::Result<TrackedFileDigest> {
+    http_download_with_retries(
+        client,
+        fs,
+        digest_config,
+        path,
+        url,
+        checksum,
+        size,
+        executable,
+        vec![2, 4, 8].into_iter().map(Duration::from_secs).collect(),
+    )
+    .await
+}
+
+async fn http_download_with_retries(
+    client: &HttpClient,
+    fs: &ProjectRoot,
+    digest_config: DigestConfig,
+    path: &ProjectRelativePath,
+    url: &str,
+    checksum: &Checksum,
+    size: Option<u64>,
     executable: bool,
+    retries: Vec<Duration>,
 ) -> buck2_error::Result<TrackedFileDigest> {
     let abs_path = fs.resolve(path);
     if let Some(dir) = abs_path.parent() {
         fs_util::create_dir_all(dir)?;
     }
 
-    Ok(http_retry(
-        || async {
-            let file = fs_util::create_file(&abs_path)
-                .map_err(|e| HttpDownloadError::IoError(buck2_error::Error::from(e)))?;
+    let attempts = AtomicUsize::new(0);
+    // Set when an attempt was cut short, so that the next one only asks for the rest of the file.
+    let resume = AtomicBool::new(false);
 
-            let response = client
-                .get(url)
-                .await
-                .map_err(|e| HttpDownloadError::Client(HttpError::Client(e)))?;
-
-            let (head, stream) = response.into_parts();
-            let buf_writer = std::io::BufWriter::new(file);
+    http_retry(
+        || async {
+            attempts.fetch_add(1, Ordering::Relaxed);
+            let offset = if resume.swap(false, Ordering::Relaxed) {
+                fs_util::metadata(&abs_path)
+                    .map_err(|e| HttpDownloadError::IoError(buck2_error::Error::from(e)))?
+                    .len()
+            } else {
+                0
+            };
 
-            let digest = copy_and_hash(
-                url,
-                Some(head),
+            let digest = download_from_offset(
+                client,
                 &abs_path,
-                stream,
-                buf_writer,
-                digest_config.cas_digest_config(),
+                digest_config,
+                url,
                 checksum,
-                client.supports_vpnless(),
+                size,
+                offset,
             )
-            .await?;
+            .await
+            .inspect_err(|e| resume.store(e.is_resumable(), Ordering::Relaxed))?;
 
             if executable {
                 fs.set_executable(path)
@@ -297,21 +380,152 @@ pub async fn http_download(
                 digest_config.cas_digest_config(),
             ))
         },
-        vec![2, 4, 8].into_iter().map(Duration::from_secs).collect(),
+        retries,
+    )
+    .await
+    .map_err(|e| buck2_error::Error::from(e.into_final()))
+    .with_buck_error_context(|| {
+        format!(
+            "Error downloading `{}` (attempts: {})",
+            url,
+            attempts.load(Ordering::Relaxed)
+        )
+    })
+}
+
+/// Download `url` to `abs_path`. If `offset` is not zero, the file already holds that many bytes
+/// from an earlier attempt, and only the rest is requested. That is only appended if the server
+/// honored the range, otherwise the file is rewritten from scratch.
+async fn download_from_offset(
+    client: &HttpClient,
+    abs_path: &AbsNormPath,
+    digest_config: DigestConfig,
+    url: &str,
+    checksum: &Checksum,
+    size: Option<u64>,
+    offset: u64,
+) -> Result<FileDigest, HttpDownloadError> {
+    let response = if offset > 0 {
+        match client
+            .get_with_headers(
+                url,
+                vec![(
+                    http::header::RANGE.to_string(),
+                    format!("bytes={}-", offset),
+                )],
+            )
+            .await
+        {
+            Err(buck2_http::HttpError::Status {
+                status: StatusCode::RANGE_NOT_SATISFIABLE,
+                ..
+            }) => client.get(url).await,
+            response => response,
+        }
+    } else {
+        client.get(url).await
+    }
+    .map_err(|e| HttpDownloadError::Client(HttpError::Client(e)))?;
+
+    let (head, stream) = response.into_parts();
+
+    let offset = if head.status != StatusCode::PARTIAL_CONTENT {
+        // Either we didn't ask for a range, or the server ignored it and sent the whole file.
+        0
+    } else if offset > 0 && honors_range(&head, offset, size) {
+        offset
+    } else {
+        return Err(HttpDownloadError::InvalidRangeResponse {
+            url: url.to_owned(),
+            offset,
+            content_range: head
+                .headers
+                .get(http::header::CONTENT_RANGE)
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or("<none>")
+                .to_owned(),
+        });
+    };
+
+    // The size of the whole file, according to the server.
+    let content_length = head
+        .headers
+        .get(http::header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok()?.parse::<u64>().ok())
+        .map(|len| offset + len);
+    if let (Some(expected), Some(received)) = (size, content_length) {
+        if expected != received {
+            return Err(HttpDownloadError::ContentLengthMismatch {
+                url: url.to_owned(),
+                expected,
+                received,
+            });
+        }
+    }
+
+    let io_error = |e: fs_util::IoError| HttpDownloadError::IoError(buck2_error::Error::from(e));
+    let mut empty = std::io::empty();
+    let (file, mut prefix) = if offset > 0 {
+        (
+            fs_util::append_file(abs_path).map_err(io_error)?,
+            Some(fs_util::open_file(abs_path).map_err(io_error)?),
+        )
+    } else {
+        (fs_util::create_file(abs_path).map_err(io_error)?, None)
+    };
+
+    copy_and_hash(
+        url,
+        Some(head),
+        abs_path,
+        match &mut prefix {
+            Some(prefix) => prefix,
+            None => &mut empty,
+        },
+        stream,
+        std::io::BufWriter::new(file),
+        digest_config.cas_digest_config(),
+        checksum,
+        content_length.or(size),
+        client.supports_vpnless(),
     )
     .await
-    .map_err(|e| e.into_final())?)
 }
 
-/// Copy a stream into a writer while producing its digest and checksumming it.
+/// Whether a 206 response to `Range: bytes={offset}-` has the rest of the file, so that it can be
+/// appended to the first `offset` bytes.
+fn honors_range(head: &http::response::Parts, offset: u64, size: Option<u64>) -> bool {
+    fn parse_content_range(value: &str) -> Option<(u64, u64, u64)> {
+        let (range, total) = value.strip_prefix("bytes ")?.split_once('/')?;
+        let (start, end) = range.split_once('-')?;
+        Some((start.parse().ok()?, end.parse().ok()?, total.parse().ok()?))
+    }
+
+    match head
+        .headers
+        .get(http::header::CONTENT_RANGE)
+        .and_then(|v| parse_content_range(v.to_str().ok()?))
+    {
+        Some((start, end, total)) => {
+            start == offset && end + 1 == total && size.is_none_or(|size| size == total)
+        }
+        None => false,
+    }
+}
+
+/// Copy a stream into a writer while producing its digest and checksumming it. `prefix` is what
+/// was already written by an earlier attempt, which counts towards the digest but isn't copied.
+/// If `size` is set, the prefix and the stream together must be that many bytes.
 async fn copy_and_hash(
     url: &str,
     head: Option<http::response::Parts>,
     abs_path: &(impl std::fmt::Display + ?Sized),
+    prefix: &mut (dyn Read + Send),
     mut stream: impl Stream<Item = Result<Bytes, hyper::Error>> + Unpin,
     mut writer: impl Write,
     digest_config: CasDigestConfig,
     checksum: &Checksum,
+    size: Option<u64>,
     is_vpnless: bool,
 ) -> Result<FileDigest, HttpDownloadError> {
     let mut digester = FileDigest::digester(digest_config);
@@ -346,6 +560,23 @@ async fn copy_and_hash(
         validators.push((validator, sha256, "sha256"));
     }
 
+    let mut prefix_buff = vec![0; 64 * 1024];
+    loop {
+        let n = prefix
+            .read(&mut prefix_buff)
+            .with_buck_error_context(|| format!("read({})", abs_path))
+            .map_err(HttpDownloadError::IoError)?;
+        if n == 0 {
+            break;
+        }
+        digester.update(&prefix_buff[..n]);
+        for (validator, _expected, _kind) in validators.iter_mut() {
+            if let Validator::ExtraDigest(hasher) = validator {
+                hasher.update(&prefix_buff[..n]);
+            }
+        }
+    }
+
     let mut buff = DebugBuffer::new(512);
 
     while let Some(chunk) = stream.next().await {
@@ -374,6 +605,18 @@ async fn copy_and_hash(
         .with_buck_error_context(|| format!("flush({})", abs_path))
         .map_err(HttpDownloadError::IoError)?;
 
+    // Check this before the checksum, which would fail with a less helpful error.
+    if let Some(expected) = size {
+        let received = digester.bytes_read();
+        if received != expected {
+            return Err(HttpDownloadError::BodySizeMismatch {
+                url: url.to_owned(),
+                expected,
+                received,
+            });
+        }
+    }
+
     let digest = digester.finalize();
 
     // Validate
@@ -509,9 +752,15 @@ impl fmt::Display for MaybeResponseDebugInfo {
 
 #[cfg(test)]
 mod tests {
+    use std::sync::Mutex;
+
     use assert_matches::assert_matches;
     use buck2_common::cas_digest::testing;
+    use buck2_core::fs::project::ProjectRootTemp;
+    use buck2_http::HttpClientBuilder;
     use futures::stream;
+    use tokio::io::AsyncReadExt;
+    use tokio::io::AsyncWriteExt;
 
     use super::*;
 
@@ -525,10 +774,12 @@ mod tests {
             "test",
             None,
             "test",
+            &mut std::io::empty(),
             stream::iter(vec![Ok(Bytes::from("foo")), Ok(Bytes::from("bar"))]),
             &mut out,
             digest_config,
             checksum,
+            None,
             false,
         )
         .await?;
@@ -589,6 +840,177 @@ mod tests {
         Ok(())
     }
 
+    /// A server that answers each connection with the next of `responses`, then closes it, and
+    /// records the `Range` header of each request.
+    struct StubServer {
+        url: String,
+        ranges: Arc<Mutex<Vec<Option<String>>>>,
+    }
+
+    impl StubServer {
+        async fn run(responses: Vec<Vec<u8>>) -> Self {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let url = format!("http://{}/file", listener.local_addr().unwrap());
+            let ranges = Arc::new(Mutex::new(Vec::new()));
+            tokio::spawn({
+                let ranges = ranges.dupe();
+                async move {
+                    for response in responses {
+                        let (mut socket, _) = listener.accept().await.unwrap();
+                        let mut request = Vec::new();
+                        let mut buf = [0; 1024];
+                        while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                            let n = socket.read(&mut buf).await.unwrap();
+                            request.extend_from_slice(&buf[..n]);
+                        }
+                        let range = String::from_utf8(request).unwrap().lines().find_map(|l| {
+                            let (name, value) = l.split_once(": ")?;
+                            name.eq_ignore_ascii_case("range").then(|| value.to_owned())
+                        });
+                        ranges.lock().unwrap().push(range);
+                        socket.write_all(&response).await.unwrap();
+                        socket.shutdown().await.unwrap();
+                    }
+                }
+            });
+            Self { url, ranges }
+        }
+
+        fn ranges(&self) -> Vec<Option<String>> {
+            self.ranges.lock().unwrap().clone()
+        }
+    }
+
+    fn response(status: &str, headers: &[String], body: &str) -> Vec<u8> {
+        let mut response = format!("HTTP/1.1 {}\r\nconnection: close\r\n", status);
+        for header in headers {
+            response += header;
+            response += "\r\n";
+        }
+        response += "\r\n";
+        response += body;
+        response.into_bytes()
+    }
+
+    const CONTENT: &str = "hello world";
+
+    async fn download(server: &StubServer) -> buck2_error::Result<(String, TrackedFileDigest)> {
+        let fs = ProjectRootTemp::new()?;
+        let path = ProjectRelativePath::new("out/file")?;
+        let client = HttpClientBuilder::https_with_system_roots().await?.build();
+        let digest = http_download_with_retries(
+            &client,
+            fs.path(),
+            DigestConfig::testing_default(),
+            path,
+            &server.url,
+            &Checksum::Sha1(Arc::from("2aae6c35c94fcfb415dbe95f408b9ce91ee846ed")),
+            Some(CONTENT.len() as u64),
+            false,
+            vec![Duration::ZERO; 2],
+        )
+        .await?;
+        Ok((fs_util::read_to_string(fs.path().resolve(path))?, digest))
+    }
+
+    #[tokio::test]
+    async fn test_http_download_resumes_truncated_body() -> buck2_error::Result<()> {
+        let server = StubServer::run(vec![
+            response("200 OK", &["content-length: 11".to_owned()], "hello"),
+            response(
+                "206 Partial Content",
+                &[
+                    "content-length: 6".to_owned(),
+                    "content-range: bytes 5-10/11".to_owned(),
+                ],
+                " world",
+            ),
+        ])
+        .await;
+
+        let (content, digest) = download(&server).await?;
+        assert_eq!(content, CONTENT);
+        assert_eq!(digest.size(), CONTENT.len() as u64);
+        assert_eq!(server.ranges(), vec![None, Some("bytes=5-".to_owned())]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_http_download_restarts_when_range_is_ignored() -> buck2_error::Result<()> {
+        let server = StubServer::run(vec![
+            response("200 OK", &["content-length: 11".to_owned()], "hello"),
+            response("200 OK", &["content-length: 11".to_owned()], CONTENT),
+        ])
+        .await;
+
+        let (content, _digest) = download(&server).await?;
+        assert_eq!(content, CONTENT);
+        assert_eq!(server.ranges(), vec![None, Some("bytes=5-".to_owned())]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_http_download_restarts_on_wrong_range() -> buck2_error::Result<()> {
+        let server = StubServer::run(vec![
+            response("200 OK", &["content-length: 11".to_owned()], "hello"),
+            response(
+                "206 Partial Content",
+                &[
+                    "content-length: 11".to_owned(),
+                    "content-range: bytes 0-10/11".to_owned(),
+                ],
+                CONTENT,
+            ),
+            response("200 OK", &["content-length: 11".to_owned()], CONTENT),
+        ])
+        .await;
+
+        let (content, _digest) = download(&server).await?;
+        assert_eq!(content, CONTENT);
+        assert_eq!(
+            server.ranges(),
+            vec![None, Some("bytes=5-".to_owned()), None]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_http_download_retries_wrong_content_length() -> buck2_error::Result<()> {
+        let server = StubServer::run(vec![
+            response("200 OK", &["content-length: 5".to_owned()], "hello"),
+            response("200 OK", &["content-length: 11".to_owned()], CONTENT),
+        ])
+        .await;
+
+        let (content, _digest) = download(&server).await?;
+        assert_eq!(content, CONTENT);
+        // A wrong size isn't a truncated body, so there is nothing to resume from.
+        assert_eq!(server.ranges(), vec![None, None]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_http_download_reports_attempts() -> buck2_error::Result<()> {
+        let server = StubServer::run(vec![
+            response("200 OK", &[], "hello"),
+            response("200 OK", &[], "hello"),
+            response("200 OK", &[], "hello"),
+        ])
+        .await;
+
+        let err = format!("{:#}", download(&server).await.unwrap_err());
+        assert!(err.contains("attempts: 3"), "{}", err);
+        assert!(
+            err.contains(&format!(
+                "Received 5 bytes from {}, expected 11 bytes",
+                server.url
+            )),
+            "{}",
+            err
+        );
+        Ok(())
+    }
+
     #[test]
     fn test_debug_buffer() {
         let mut buff = DebugBuffer::new(10);
@@ -0,0 +1,28 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use std::time::Duration;
+
+use allocative::Allocative;
+
+/// Advisory IO pressure signals exchanged between the materializer and local action execution.
+///
+/// Heavy materializations and local actions compete for disk bandwidth. Through this, each side
+/// can back off while the other one is busy. Neither side is required to act on the signals.
+pub trait IoPressureSignals: Allocative + Send + Sync + 'static {
+    /// Whether in-flight materializations currently put the disk under pressure.
+    fn materializer_under_pressure(&self) -> bool;
+
+    /// Reports the share of time, in percent, recently spent waiting on IO while local actions
+    /// ran.
+    fn report_local_io_wait(&self, percent: u8);
+
+    /// How long local actions may wait for the materializer to be under pressure no more.
+    fn max_local_action_delay(&self) -> Duration;
+}
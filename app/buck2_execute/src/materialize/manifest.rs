@@ -0,0 +1,169 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! A manifest of materialized artifacts, built from the materializer's own metadata rather than
+//! by re-hashing what is on disk.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use buck2_common::directory_metadata::DirectoryMetadata;
+use buck2_core::fs::project_rel_path::ProjectRelativePathBuf;
+use serde::Serialize;
+
+use crate::directory::ActionDirectoryMember;
+
+/// Increment this if the JSON format changes.
+const MANIFEST_VERSION: u32 = 1;
+
+#[derive(buck2_error::Error, Debug)]
+#[buck2(tag = Input)]
+pub enum ManifestError {
+    #[error(
+        "Cannot generate a manifest for paths that buck2 has not materialized:\n{}",
+        format_paths(.0)
+    )]
+    NotMaterialized(Vec<ProjectRelativePathBuf>),
+    #[error("The `{0}` materializer does not support generating manifests")]
+    Unsupported(String),
+}
+
+fn format_paths(paths: &[ProjectRelativePathBuf]) -> String {
+    let mut res = String::new();
+    for path in paths {
+        writeln!(res, "  {}", path).unwrap();
+    }
+    res
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ManifestEntry {
+    File {
+        digest: String,
+        size: u64,
+        is_executable: bool,
+    },
+    /// A directory whose contents were not retained, identified by its fingerprint.
+    Directory { fingerprint: String, size: u64 },
+    Symlink { target: String },
+    ExternalSymlink { target: String },
+}
+
+impl ManifestEntry {
+    pub fn from_member(member: &ActionDirectoryMember) -> Self {
+        match member {
+            ActionDirectoryMember::File(f) => ManifestEntry::File {
+                digest: f.digest.raw_digest().to_string(),
+                size: f.digest.size(),
+                is_executable: f.is_executable,
+            },
+            ActionDirectoryMember::Symlink(s) => ManifestEntry::Symlink {
+                target: s.target().to_string(),
+            },
+            ActionDirectoryMember::ExternalSymlink(s) => ManifestEntry::ExternalSymlink {
+                target: s.to_path_buf().to_string_lossy().into_owned(),
+            },
+        }
+    }
+
+    pub fn from_directory_metadata(metadata: &DirectoryMetadata) -> Self {
+        ManifestEntry::Directory {
+            fingerprint: metadata.fingerprint.raw_digest().to_string(),
+            size: metadata.total_size,
+        }
+    }
+}
+
+/// Path to digest, size and executable bit for a set of materialized artifacts.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Manifest {
+    entries: BTreeMap<ProjectRelativePathBuf, ManifestEntry>,
+}
+
+impl Manifest {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, path: ProjectRelativePathBuf, entry: ManifestEntry) {
+        self.entries.insert(path, entry);
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = (&ProjectRelativePathBuf, &ManifestEntry)> {
+        self.entries.iter()
+    }
+
+    /// Serialize to the stable JSON format: a version and a list of entries sorted by path.
+    pub fn to_json(&self) -> buck2_error::Result<String> {
+        #[derive(Serialize)]
+        struct JsonEntry<'a> {
+            path: &'a str,
+            #[serde(flatten)]
+            entry: &'a ManifestEntry,
+        }
+
+        #[derive(Serialize)]
+        struct JsonManifest<'a> {
+            version: u32,
+            entries: Vec<JsonEntry<'a>>,
+        }
+
+        let json = JsonManifest {
+            version: MANIFEST_VERSION,
+            entries: self
+                .entries
+                .iter()
+                .map(|(path, entry)| JsonEntry {
+                    path: path.as_str(),
+                    entry,
+                })
+                .collect(),
+        };
+        Ok(serde_json::to_string_pretty(&json)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_format() -> buck2_error::Result<()> {
+        let mut manifest = Manifest::new();
+        manifest.insert(
+            ProjectRelativePathBuf::unchecked_new("b".to_owned()),
+            ManifestEntry::Directory {
+                fingerprint: "ff".to_owned(),
+                size: 3,
+            },
+        );
+        manifest.insert(
+            ProjectRelativePathBuf::unchecked_new("a".to_owned()),
+            ManifestEntry::File {
+                digest: "aa".to_owned(),
+                size: 1,
+                is_executable: true,
+            },
+        );
+
+        let json: serde_json::Value = serde_json::from_str(&manifest.to_json()?)?;
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "version": 1,
+                "entries": [
+                    {"path": "a", "type": "file", "digest": "aa", "size": 1, "is_executable": true},
+                    {"path": "b", "type": "directory", "fingerprint": "ff", "size": 3},
+                ],
+            })
+        );
+        Ok(())
+    }
+}
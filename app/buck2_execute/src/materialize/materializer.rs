@@ -15,12 +15,15 @@ use async_trait::async_trait;
 use buck2_common::file_ops::FileMetadata;
 use buck2_core::deferred::base_deferred_key::BaseDeferredKey;
 use buck2_core::execution_types::executor_config::RemoteExecutorUseCase;
+use buck2_core::fs::paths::abs_norm_path::AbsNormPathBuf;
+use buck2_core::fs::paths::abs_path::AbsPathBuf;
 use buck2_core::fs::project_rel_path::ProjectRelativePathBuf;
 use buck2_directory::directory::directory_iterator::DirectoryIterator;
 use buck2_directory::directory::entry::DirectoryEntry;
 use buck2_directory::directory::walk::ordered_entry_walk;
 use buck2_events::dispatch::EventDispatcher;
 use buck2_futures::cancellation::CancellationContext;
+use buck2_wrapper_common::invocation_id::TraceId;
 use chrono::DateTime;
 use chrono::Duration;
 use chrono::Utc;
@@ -37,6 +40,8 @@ use crate::directory::ActionImmutableDirectory;
 use crate::directory::ActionSharedDirectory;
 use crate::execute::action_digest::TrackedActionDigest;
 use crate::materialize::http::Checksum;
+use crate::materialize::manifest::Manifest;
+use crate::materialize::manifest::ManifestError;
 
 pub struct WriteRequest {
     pub path: ProjectRelativePathBuf,
@@ -93,6 +98,7 @@ Debug information:
   Digest origin: {}
   Directory:\n{}", .path, .info.origin.as_display_for_not_found(), format_directory_entry_leaves(.directory))]
 #[buck2(tag = MaterializationError)]
+#[buck2(tags = info.origin.not_found_reason().tags())]
 pub struct CasNotFoundError {
     pub path: Arc<ProjectRelativePathBuf>,
     pub info: Arc<CasDownloadInfo>,
@@ -125,6 +131,15 @@ pub enum MaterializationError {
     },
 }
 
+#[derive(buck2_error::Error, Debug)]
+#[buck2(tag = Input)]
+pub enum ExportError {
+    #[error("The `{0}` materializer does not support exporting artifacts")]
+    Unsupported(String),
+    #[error("Cannot export `{0}` to `{1}`, which overlaps with buck-out (`{2}`)")]
+    OverlapsBuckOut(ProjectRelativePathBuf, AbsPathBuf, AbsNormPathBuf),
+}
+
 /// A trait providing methods to asynchronously materialize artifacts.
 ///
 /// # Invariants
@@ -210,6 +225,18 @@ pub trait Materializer: Allocative + Send + Sync + 'static {
     /// This method does not guarantee that the artifact was materialized.
     async fn has_artifact_at(&self, path: ProjectRelativePathBuf) -> buck2_error::Result<bool>;
 
+    /// Like `has_artifact_at`, answered for each of `paths` in order.
+    async fn has_artifacts_at(
+        &self,
+        paths: Vec<ProjectRelativePathBuf>,
+    ) -> buck2_error::Result<Vec<bool>> {
+        let mut res = Vec::with_capacity(paths.len());
+        for path in paths {
+            res.push(self.has_artifact_at(path).await?);
+        }
+        Ok(res)
+    }
+
     /// Declare an artifact at `path` exists. This will overwrite any pre-existing materialization
     /// methods for this file and indicate that no materialization is necessary.
     async fn invalidate(&self, path: ProjectRelativePathBuf) -> buck2_error::Result<()> {
@@ -280,6 +307,30 @@ pub trait Materializer: Allocative + Send + Sync + 'static {
         file_paths: Vec<ProjectRelativePathBuf>,
     ) -> buck2_error::Result<Vec<Result<ProjectRelativePathBuf, ArtifactNotMaterializedReason>>>;
 
+    /// Describe the artifacts at `paths` (path, digest, size and executable bit) using the
+    /// materializer's own metadata, without reading them from disk.
+    ///
+    /// Fails if any of the paths is not an artifact this materializer has materialized.
+    async fn generate_manifest(
+        &self,
+        _paths: Vec<ProjectRelativePathBuf>,
+    ) -> buck2_error::Result<Manifest> {
+        Err(ManifestError::Unsupported(self.name().to_owned()).into())
+    }
+
+    /// Materialize the artifact at the first path of each pair and copy it to the second path,
+    /// preserving its structure and permissions. Destinations may be outside the project root,
+    /// but not in or above buck-out. Whatever is at a destination is replaced.
+    ///
+    /// Returns one result per pair, in order. The outer [`Err`] is only for failures that
+    /// prevent exporting anything.
+    async fn export_artifacts(
+        &self,
+        _pairs: Vec<(ProjectRelativePathBuf, AbsPathBuf)>,
+    ) -> buck2_error::Result<Vec<buck2_error::Result<()>>> {
+        Err(ExportError::Unsupported(self.name().to_owned()).into())
+    }
+
     fn as_deferred_materializer_extension(&self) -> Option<&dyn DeferredMaterializerExtensions> {
         None
     }
@@ -290,6 +341,21 @@ pub trait Materializer: Allocative + Send + Sync + 'static {
     /// Inject stats into a snapshot. This is also used only for the deferred materializer at this
     /// time.
     fn add_snapshot_stats(&self, _snapshot: &mut buck2_data::Snapshot) {}
+
+    /// Total size of the artifacts this materializer knows it has materialized, if it keeps
+    /// track of that.
+    fn materialized_bytes(&self) -> Option<u64> {
+        None
+    }
+
+    /// Removes and returns what was materialized on behalf of the command with `trace_id`, if
+    /// this materializer keeps track of that. Called once the command is done.
+    fn take_command_stats(
+        &self,
+        _trace_id: &TraceId,
+    ) -> Option<buck2_data::CommandMaterializationStats> {
+        None
+    }
 }
 
 #[derive(Copy, Clone, Dupe, Debug)]
@@ -435,6 +501,27 @@ impl ActionExecutionOrigin {
     }
 }
 
+/// Why an artifact wasn't found in the CAS, as far as we can tell from its origin.
+#[derive(Debug, Clone, Copy, Dupe, PartialEq, Eq)]
+pub enum CasNotFoundReason {
+    /// The action that produced it is older than the TTL RE gave us for it.
+    Expired,
+    /// The action cache guaranteed it would still be there, so it likely never was uploaded.
+    NeverExisted,
+    /// We don't know its TTL.
+    Unknown,
+}
+
+impl CasNotFoundReason {
+    pub fn tags(self) -> Vec<buck2_error::ErrorTag> {
+        match self {
+            Self::Expired => vec![buck2_error::ErrorTag::MaterializerCasExpired],
+            Self::NeverExisted => vec![buck2_error::ErrorTag::MaterializerCasNeverExisted],
+            Self::Unknown => Vec::new(),
+        }
+    }
+}
+
 impl fmt::Display for CasDownloadInfoOrigin {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -472,6 +559,16 @@ impl CasDownloadInfoOrigin {
             }
         }
     }
+
+    pub fn not_found_reason(&self) -> CasNotFoundReason {
+        match self {
+            Self::Execution(_) if self.guaranteed_by_action_cache() => {
+                CasNotFoundReason::NeverExisted
+            }
+            Self::Execution(_) => CasNotFoundReason::Expired,
+            Self::Declared => CasNotFoundReason::Unknown,
+        }
+    }
 }
 
 /// A Display wrapper for CasDownloadInfoOrigin in cases where this origin was not found (in those
@@ -483,8 +580,20 @@ pub struct CasDownloadInfoOriginNotFound<'a> {
 impl fmt::Display for CasDownloadInfoOriginNotFound<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         self.inner.fmt(f)?;
-        if self.inner.guaranteed_by_action_cache() {
-            write!(f, " (not expired: action cache corruption)")?;
+        match self.inner {
+            CasDownloadInfoOrigin::Execution(_) if self.inner.guaranteed_by_action_cache() => {
+                write!(f, " (not expired: action cache corruption)")?;
+            }
+            CasDownloadInfoOrigin::Execution(execution) => {
+                write!(
+                    f,
+                    " (expired {} seconds ago)",
+                    (execution.action_age() - execution.ttl).num_seconds()
+                )?;
+            }
+            CasDownloadInfoOrigin::Declared => {
+                write!(f, " (ttl unknown)")?;
+            }
         }
 
         Ok(())
@@ -554,6 +663,10 @@ pub struct HttpDownloadInfo {
 
     /// Target that declared the action.
     pub owner: BaseDeferredKey,
+
+    /// RE use case of the executor the action ran on, used when refreshing the TTL of the file in
+    /// the CAS.
+    pub re_use_case: RemoteExecutorUseCase,
 }
 
 #[derive(Debug, buck2_error::Error)]
@@ -654,6 +767,21 @@ pub struct DeferredMaterializerIterItem {
     pub deps: Vec<(ProjectRelativePathBuf, &'static str)>,
 }
 
+/// An entry of the log returned by `DeferredMaterializerExtensions::get_ttl_refresh_log`.
+pub struct TtlRefreshLogEntry {
+    pub at: DateTime<Utc>,
+    /// `None` if the refresh was skipped.
+    pub outcome: Option<Result<(), String>>,
+}
+
+/// Returned by `DeferredMaterializerExtensions::test_iter`.
+pub struct TestIterTimings {
+    /// Time to iterate over the artifact tree `count` times.
+    pub iter: std::time::Duration,
+    /// Time to iterate over the artifact tree `count` times, with paths.
+    pub iter_with_paths: std::time::Duration,
+}
+
 /// Obtain notifications for entries as they are materialized, and request eager materialization of
 /// those paths.
 #[async_trait]
@@ -666,6 +794,14 @@ pub trait DeferredMaterializerSubscription: Send + Sync {
     /// received.
     fn unsubscribe_from_paths(&mut self, paths: Vec<ProjectRelativePathBuf>);
 
+    /// Get notifications for all paths under `prefix`, including ones declared later. This also
+    /// implicitly requests their eager materialization.
+    fn subscribe_to_prefix(&mut self, prefix: ProjectRelativePathBuf);
+
+    /// Stop getting notifications for paths under `prefix`. Paths covered by another prefix or
+    /// subscribed to exactly are still reported. In-flight notifications may still be received.
+    fn unsubscribe_from_prefix(&mut self, prefix: ProjectRelativePathBuf);
+
     /// Await the next materialization on this subscription.
     async fn next_materialization(&mut self) -> Option<ProjectRelativePathBuf>;
 }
@@ -686,20 +822,105 @@ pub trait DeferredMaterializerExtensions: Send + Sync {
 
     async fn refresh_ttls(&self, min_ttl: i64) -> buck2_error::Result<()>;
 
-    async fn get_ttl_refresh_log(&self) -> buck2_error::Result<String>;
+    /// The last `last` TTL refreshes, or all those the materializer remembers, oldest first.
+    async fn get_ttl_refresh_log(
+        &self,
+        last: Option<usize>,
+    ) -> buck2_error::Result<Vec<TtlRefreshLogEntry>>;
 
     async fn clean_stale_artifacts(
         &self,
         keep_since_time: DateTime<Utc>,
         dry_run: bool,
         tracked_only: bool,
+        cells: Vec<String>,
+        max_bytes_per_run: Option<u64>,
+        max_files_per_run: Option<u64>,
+        keep_paths: Vec<ProjectRelativePathBuf>,
     ) -> buck2_error::Result<buck2_cli_proto::CleanStaleResponse>;
 
-    async fn test_iter(&self, count: usize) -> buck2_error::Result<String>;
+    /// Describe the artifacts that invalidating `paths` would forget, without forgetting them.
+    /// Each artifact is reported with its stage (`Declared` or `Materialized`), suffixed with
+    /// `InFlight` if something is currently materializing or cleaning it.
+    async fn preview_invalidate(
+        &self,
+        paths: Vec<ProjectRelativePathBuf>,
+    ) -> buck2_error::Result<Vec<(ProjectRelativePathBuf, &'static str)>>;
+
+    async fn test_iter(&self, count: usize) -> buck2_error::Result<TestIterTimings>;
     async fn flush_all_access_times(&self) -> buck2_error::Result<String>;
 
+    /// The number of local copies and writes waiting on the concurrency limit for each command, in
+    /// the order the commands get their next turn.
+    async fn pending_by_trace(&self) -> buck2_error::Result<Vec<(TraceId, usize)>>;
+
+    /// Estimate the memory used by the materializer's artifact tree. This walks the whole tree.
+    async fn measure_artifact_tree(&self) -> buck2_error::Result<u64>;
+
     /// Create a new DeferredMaterializerSubscription.
     async fn create_subscription(
         &self,
     ) -> buck2_error::Result<Box<dyn DeferredMaterializerSubscription>>;
 }
+
+#[cfg(test)]
+mod tests {
+    use buck2_common::cas_digest::CasDigestConfig;
+    use buck2_core::fs::project_rel_path::ProjectRelativePath;
+
+    use super::*;
+    use crate::execute::action_digest::ActionDigest;
+
+    fn not_found(ttl: Duration) -> buck2_error::Error {
+        let config = CasDigestConfig::testing_default();
+        let info = CasDownloadInfo::new_execution(
+            TrackedActionDigest::new(ActionDigest::new_sha1([1; 20], 10), config),
+            RemoteExecutorUseCase::buck2_default(),
+            Utc::now() - Duration::seconds(100),
+            ttl,
+        );
+        MaterializationError::NotFound {
+            source: CasNotFoundError {
+                path: Arc::new(ProjectRelativePath::unchecked_new("out/foo").to_owned()),
+                info: Arc::new(info),
+                directory: ActionDirectoryEntry::Leaf(ActionDirectoryMember::File(
+                    FileMetadata::empty(config),
+                )),
+                error: Arc::new(buck2_error::buck2_error!(
+                    buck2_error::ErrorTag::ReNotFound,
+                    "not found"
+                )),
+            },
+        }
+        .into()
+    }
+
+    #[test]
+    fn test_not_found_expired() {
+        let e = not_found(Duration::seconds(40));
+        assert!(e.has_tag(buck2_error::ErrorTag::MaterializerCasExpired));
+        assert!(!e.has_tag(buck2_error::ErrorTag::MaterializerCasNeverExisted));
+
+        let message = format!("{:#}", e);
+        assert!(message.contains("Path: out/foo"), "message: {}", message);
+        assert!(
+            message.contains("with ttl = 40 seconds (expired 60 seconds ago)"),
+            "message: {}",
+            message
+        );
+    }
+
+    #[test]
+    fn test_not_found_never_existed() {
+        let e = not_found(Duration::seconds(1000));
+        assert!(e.has_tag(buck2_error::ErrorTag::MaterializerCasNeverExisted));
+        assert!(!e.has_tag(buck2_error::ErrorTag::MaterializerCasExpired));
+
+        let message = format!("{:#}", e);
+        assert!(
+            message.contains("with ttl = 1000 seconds (not expired: action cache corruption)"),
+            "message: {}",
+            message
+        );
+    }
+}
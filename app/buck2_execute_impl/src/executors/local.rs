@@ -61,6 +61,7 @@ use buck2_execute::execute::request::ExecutorPreference;
 use buck2_execute::execute::result::CommandExecutionMetadata;
 use buck2_execute::execute::result::CommandExecutionResult;
 use buck2_execute::knobs::ExecutorGlobalKnobs;
+use buck2_execute::materialize::io_pressure::IoPressureSignals;
 use buck2_execute::materialize::materializer::MaterializationError;
 use buck2_execute::materialize::materializer::Materializer;
 use buck2_forkserver::client::ForkserverClient;
@@ -101,6 +102,7 @@ enum LocalExecutionError {
 pub struct LocalExecutor {
     artifact_fs: ArtifactFs,
     materializer: Arc<dyn Materializer>,
+    io_pressure: Option<Arc<dyn IoPressureSignals>>,
     blocking_executor: Arc<dyn BlockingExecutor>,
     pub(crate) host_sharing_broker: Arc<HostSharingBroker>,
     root: AbsNormPathBuf,
@@ -116,6 +118,7 @@ impl LocalExecutor {
     pub fn new(
         artifact_fs: ArtifactFs,
         materializer: Arc<dyn Materializer>,
+        io_pressure: Option<Arc<dyn IoPressureSignals>>,
         blocking_executor: Arc<dyn BlockingExecutor>,
         host_sharing_broker: Arc<HostSharingBroker>,
         root: AbsNormPathBuf,
@@ -126,6 +129,7 @@ impl LocalExecutor {
         Self {
             artifact_fs,
             materializer,
+            io_pressure,
             blocking_executor,
             host_sharing_broker,
             root,
@@ -670,6 +674,26 @@ impl LocalExecutor {
         ))
     }
 
+    /// Holds the action back while the materializer reports IO pressure, but not for long: the
+    /// signal is advisory and only gives heavy materializations a head start.
+    async fn wait_for_materializer_io(&self) {
+        let Some(io_pressure) = &self.io_pressure else {
+            return;
+        };
+        let deadline = Instant::now() + io_pressure.max_local_action_delay();
+        while io_pressure.materializer_under_pressure() && Instant::now() < deadline {
+            tokio::time::sleep(MATERIALIZER_IO_PRESSURE_POLL_INTERVAL).await;
+        }
+    }
+
+    /// Reports the IO wait seen while local actions run, so that the materializer backs off when
+    /// it's high.
+    fn report_io_wait(&self) {
+        if let (Some(io_pressure), Some(percent)) = (&self.io_pressure, sampled_io_wait_percent()) {
+            io_pressure.report_local_io_wait(percent);
+        }
+    }
+
     async fn acquire_worker_permit(
         &self,
         request: &CommandExecutionRequest,
@@ -824,14 +848,18 @@ impl PreparedCommandExecutor for LocalExecutor {
             buck2_data::LocalStage {
                 stage: Some(buck2_data::LocalQueued {}.into()),
             },
-            self.host_sharing_broker
-                .acquire(request.host_sharing_requirements()),
+            async {
+                self.wait_for_materializer_io().await;
+                self.host_sharing_broker
+                    .acquire(request.host_sharing_requirements())
+                    .await
+            },
         )
         .await;
 
         // If we start running something, we don't want this task to get dropped, because if we do
         // we might interfere with e.g. clean up.
-        cancellations
+        let res = cancellations
             .with_structured_cancellation(|cancellation| {
                 Self::exec_request(
                     self,
@@ -844,7 +872,10 @@ impl PreparedCommandExecutor for LocalExecutor {
                     &local_resource_holders,
                 )
             })
-            .await
+            .await;
+
+        self.report_io_wait();
+        res
     }
 
     fn is_local_execution_possible(&self, _executor_preference: ExecutorPreference) -> bool {
@@ -852,6 +883,70 @@ impl PreparedCommandExecutor for LocalExecutor {
     }
 }
 
+const MATERIALIZER_IO_PRESSURE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How often the IO wait is sampled. The kernel averages it over 10 seconds anyway.
+#[cfg(target_os = "linux")]
+const IO_WAIT_SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The IO wait of the latest sample, see `io_wait_percent`. It's sampled on a thread started on
+/// first use, so that finishing actions don't read the pressure stall information themselves.
+#[cfg(target_os = "linux")]
+fn sampled_io_wait_percent() -> Option<u8> {
+    use std::sync::Once;
+    use std::sync::atomic::AtomicU8;
+    use std::sync::atomic::Ordering;
+
+    /// Stored while there is no sample. Samples are at most 100.
+    const NO_SAMPLE: u8 = u8::MAX;
+    static SAMPLE: AtomicU8 = AtomicU8::new(NO_SAMPLE);
+    static SAMPLER: Once = Once::new();
+
+    SAMPLER.call_once(|| {
+        let spawned = std::thread::Builder::new()
+            .name("buck2-io-wait-sampler".to_owned())
+            .spawn(|| {
+                loop {
+                    SAMPLE.store(io_wait_percent().unwrap_or(NO_SAMPLE), Ordering::Relaxed);
+                    std::thread::sleep(IO_WAIT_SAMPLE_INTERVAL);
+                }
+            });
+        if let Err(e) = spawned {
+            tracing::warn!("Failed to start sampling IO wait: {}", e);
+        }
+    });
+    match SAMPLE.load(Ordering::Relaxed) {
+        NO_SAMPLE => None,
+        percent => Some(percent),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn sampled_io_wait_percent() -> Option<u8> {
+    None
+}
+
+/// The share of the last 10 seconds during which some tasks were stalled on IO, from the pressure
+/// stall information of the kernel.
+#[cfg(target_os = "linux")]
+fn io_wait_percent() -> Option<u8> {
+    parse_io_pressure(&std::fs::read_to_string("/proc/pressure/io").ok()?)
+}
+
+/// Parses the `some avg10` field of `/proc/pressure/io`.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn parse_io_pressure(pressure: &str) -> Option<u8> {
+    let some = pressure
+        .lines()
+        .find_map(|line| line.strip_prefix("some "))?;
+    let avg10: f64 = some
+        .split_whitespace()
+        .find_map(|field| field.strip_prefix("avg10="))?
+        .parse()
+        .ok()?;
+    Some(avg10.round().clamp(0.0, 100.0) as u8)
+}
+
 /// Either a str or a OsStr, so that we can turn it back into a String without having to check for
 /// valid utf-8, while using the same struct.
 #[derive(Copy, Clone, Dupe, From)]
@@ -1278,6 +1373,7 @@ mod tests {
         let executor = LocalExecutor::new(
             artifact_fs,
             Arc::new(NoDiskMaterializer),
+            None,
             Arc::new(DummyBlockingExecutor {
                 fs: project_fs.dupe(),
             }),
@@ -1354,4 +1450,14 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_parse_io_pressure() {
+        let pressure = "some avg10=42.61 avg60=12.03 avg300=3.00 total=123456\n\
+            full avg10=20.00 avg60=5.00 avg300=1.00 total=65432\n";
+        assert_eq!(Some(43), parse_io_pressure(pressure));
+        assert_eq!(Some(0), parse_io_pressure("some avg10=0.00 total=0\n"));
+        assert_eq!(None, parse_io_pressure("full avg10=20.00 total=65432\n"));
+        assert_eq!(None, parse_io_pressure("some avg10=abc total=0\n"));
+    }
 }
@@ -8,10 +8,14 @@
  */
 
 pub mod clean_stale;
+pub mod clock_skew;
+mod command_stats;
 mod data_tree;
 mod extension;
 mod io_handler;
+pub mod io_pressure;
 mod materialize_stack;
+mod scheduling;
 mod subscriptions;
 
 pub(crate) mod artifact_tree;
@@ -20,7 +24,10 @@ pub(crate) mod file_tree;
 #[cfg(test)]
 mod tests;
 
+use std::borrow::Cow;
+use std::collections::HashMap;
 use std::collections::HashSet;
+use std::collections::VecDeque;
 use std::sync::Arc;
 use std::sync::atomic::AtomicU64;
 use std::sync::atomic::AtomicUsize;
@@ -36,7 +43,11 @@ use buck2_common::file_ops::FileMetadata;
 use buck2_common::file_ops::TrackedFileDigest;
 use buck2_common::liveliness_observer::LivelinessGuard;
 use buck2_core::buck2_env;
+use buck2_core::fs::paths::abs_norm_path::AbsNormPath;
+use buck2_core::fs::paths::abs_path::AbsPath;
+use buck2_core::fs::paths::abs_path::AbsPathBuf;
 use buck2_core::fs::project::ProjectRoot;
+use buck2_core::fs::project_rel_path::ProjectRelativePath;
 use buck2_core::fs::project_rel_path::ProjectRelativePathBuf;
 use buck2_directory::directory::directory::Directory;
 use buck2_directory::directory::directory_iterator::DirectoryIterator;
@@ -52,12 +63,14 @@ use buck2_execute::artifact_value::ArtifactValue;
 use buck2_execute::digest_config::DigestConfig;
 use buck2_execute::directory::ActionDirectoryMember;
 use buck2_execute::execute::blocking::BlockingExecutor;
+use buck2_execute::materialize::manifest::Manifest;
 use buck2_execute::materialize::materializer::ArtifactNotMaterializedReason;
 use buck2_execute::materialize::materializer::CasDownloadInfo;
 use buck2_execute::materialize::materializer::CasNotFoundError;
 use buck2_execute::materialize::materializer::CopiedArtifact;
 use buck2_execute::materialize::materializer::DeclareMatchOutcome;
 use buck2_execute::materialize::materializer::DeferredMaterializerExtensions;
+use buck2_execute::materialize::materializer::ExportError;
 use buck2_execute::materialize::materializer::HttpDownloadInfo;
 use buck2_execute::materialize::materializer::MaterializationError;
 use buck2_execute::materialize::materializer::Materializer;
@@ -66,12 +79,14 @@ use buck2_execute::re::manager::ReConnectionManager;
 use buck2_futures::cancellation::CancellationContext;
 use buck2_http::HttpClient;
 use buck2_util::threads::thread_spawn;
+use buck2_wrapper_common::invocation_id::TraceId;
 use chrono::DateTime;
 use chrono::Duration;
 use chrono::Utc;
 use derivative::Derivative;
 use dupe::Dupe;
 use futures::stream::BoxStream;
+use futures::stream::StreamExt;
 use parking_lot::Mutex;
 use tokio::runtime::Handle;
 use tokio::sync::mpsc;
@@ -80,13 +95,18 @@ use tokio::sync::oneshot;
 use crate::materializers::deferred::artifact_tree::ArtifactTree;
 use crate::materializers::deferred::artifact_tree::Version;
 use crate::materializers::deferred::clean_stale::CleanStaleConfig;
+use crate::materializers::deferred::clock_skew::ClockSkewConfig;
+use crate::materializers::deferred::clock_skew::ClockSkewGuard;
+use crate::materializers::deferred::command_processor::DeclareContext;
 use crate::materializers::deferred::command_processor::DeferredMaterializerCommandProcessor;
 use crate::materializers::deferred::command_processor::LogBuffer;
 use crate::materializers::deferred::command_processor::LowPriorityMaterializerCommand;
 use crate::materializers::deferred::command_processor::MaterializerCommand;
+use crate::materializers::deferred::command_stats::CommandStatsByTrace;
 use crate::materializers::deferred::file_tree::FileTree;
 use crate::materializers::deferred::io_handler::DefaultIoHandler;
 use crate::materializers::deferred::io_handler::IoHandler;
+use crate::materializers::deferred::io_pressure::MaterializerIoPressure;
 use crate::materializers::sqlite::MaterializerState;
 use crate::materializers::sqlite::MaterializerStateSqliteDb;
 
@@ -125,6 +145,7 @@ pub struct DeferredMaterializerAccessor<T: IoHandler + 'static> {
     /// materializes them, otherwise skips them.
     materialize_final_artifacts: bool,
     defer_write_actions: bool,
+    write_compression: WriteCompression,
 
     io: Arc<T>,
 
@@ -135,6 +156,10 @@ pub struct DeferredMaterializerAccessor<T: IoHandler + 'static> {
 
     /// Logs verbose events about materializer to the event log when enabled.
     verbose_materializer_log: bool,
+
+    /// Whether declaring different contents at an output already declared by another action of
+    /// the same command fails, rather than replacing it.
+    fail_on_declare_conflicts: bool,
 }
 
 pub type DeferredMaterializer = DeferredMaterializerAccessor<DefaultIoHandler>;
@@ -151,6 +176,98 @@ impl<T: IoHandler> Drop for DeferredMaterializerAccessor<T> {
 pub struct DeferredMaterializerStats {
     declares: AtomicU64,
     declares_reused: AtomicU64,
+    declared_pruned: AtomicU64,
+    artifact_tree_bytes: AtomicU64,
+    /// Bytes materialized per cell, as of the last `clean --stale` scan that covered the cell.
+    materialized_bytes_by_cell: Mutex<HashMap<String, u64>>,
+    /// Largest number of commands (of either priority) seen waiting for the command thread.
+    queue_size_high_watermark: AtomicU64,
+    /// Largest number of commands seen in the (bounded) low priority queue.
+    low_priority_queue_high_watermark: AtomicU64,
+    /// Number of low priority sends that found the queue full and had to wait.
+    low_priority_blocked_sends: AtomicU64,
+    materialization_latencies: MaterializationLatencies,
+    #[allocative(skip)]
+    command_stats: CommandStatsByTrace,
+}
+
+/// How many artifacts were materialized with a given method, and how long that took in total.
+#[derive(Allocative, Default)]
+struct MaterializationLatency {
+    count: AtomicU64,
+    total_nanos: AtomicU64,
+}
+
+#[derive(Allocative, Default)]
+struct MaterializationLatencies {
+    local_copy: MaterializationLatency,
+    write: MaterializationLatency,
+    cas_download: MaterializationLatency,
+    http_download: MaterializationLatency,
+    #[cfg(test)]
+    test: MaterializationLatency,
+}
+
+impl MaterializationLatencies {
+    /// The count and total duration of materializations for each method that was used at all.
+    fn snapshot(&self) -> (HashMap<String, u64>, HashMap<String, std::time::Duration>) {
+        let mut counts = HashMap::new();
+        let mut durations = HashMap::new();
+        for (method, latency) in [
+            ("local_copy", &self.local_copy),
+            ("write", &self.write),
+            ("cas_download", &self.cas_download),
+            ("http_download", &self.http_download),
+            #[cfg(test)]
+            ("test", &self.test),
+        ] {
+            let count = latency.count.load(Ordering::Relaxed);
+            if count == 0 {
+                continue;
+            }
+            counts.insert(method.to_owned(), count);
+            durations.insert(
+                method.to_owned(),
+                std::time::Duration::from_nanos(latency.total_nanos.load(Ordering::Relaxed)),
+            );
+        }
+        (counts, durations)
+    }
+
+    fn record(&self, method: &ArtifactMaterializationMethod, duration: std::time::Duration) {
+        let latency = match method {
+            ArtifactMaterializationMethod::LocalCopy(..) => &self.local_copy,
+            ArtifactMaterializationMethod::Write(..) => &self.write,
+            ArtifactMaterializationMethod::CasDownload { .. } => &self.cas_download,
+            ArtifactMaterializationMethod::HttpDownload { .. } => &self.http_download,
+            #[cfg(test)]
+            ArtifactMaterializationMethod::Test => &self.test,
+        };
+        latency.count.fetch_add(1, Ordering::Relaxed);
+        latency.total_nanos.fetch_add(
+            duration.as_nanos().try_into().unwrap_or(u64::MAX),
+            Ordering::Relaxed,
+        );
+    }
+}
+
+/// A copy of `DeferredMaterializerStats` taken at some point in time, plus the current size of
+/// the command queue.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DeferredMaterializerSnapshot {
+    pub declares: u64,
+    pub declares_reused: u64,
+    pub declared_pruned: u64,
+    pub artifact_tree_bytes: u64,
+    pub materialized_bytes_by_cell: HashMap<String, u64>,
+    pub queue_size: u64,
+    pub queue_size_high_watermark: u64,
+    pub low_priority_queue_high_watermark: u64,
+    pub low_priority_blocked_sends: u64,
+    /// Number of artifacts materialized, keyed by materialization method.
+    pub materializations_by_method: HashMap<String, u64>,
+    /// Total time spent materializing them, keyed by materialization method.
+    pub materialization_duration_by_method: HashMap<String, std::time::Duration>,
 }
 
 fn access_time_update_max_buffer_size() -> buck2_error::Result<usize> {
@@ -164,13 +281,223 @@ pub struct DeferredMaterializerConfigs {
     pub update_access_times: AccessTimesUpdates,
     pub verbose_materializer_log: bool,
     pub clean_stale_config: Option<CleanStaleConfig>,
+    pub clock_skew: ClockSkewConfig,
     pub disable_eager_write_dispatch: bool,
+    pub artifact_tree_sweep: ArtifactTreeSweepConfiguration,
+    /// Capacity of the low priority command queue. Once it is full, materialization tasks wait
+    /// for the command thread to catch up.
+    pub low_priority_queue_capacity: usize,
+    pub write_compression: WriteCompression,
+    /// When matching an artifact that is already materialized, also hash what is on disk in the
+    /// background, and invalidate the artifact if it doesn't match. Catches artifacts modified
+    /// behind the materializer's back, at the cost of re-reading them on every match.
+    pub paranoid_verification: bool,
+    /// When two actions of a command declare different contents at the same output, fail the
+    /// second declare instead of letting it replace the first. Conflicts are reported either way.
+    pub fail_on_declare_conflicts: bool,
+}
+
+/// How `declare_write` stores the contents of deferred writes until they are materialized.
+#[derive(Allocative, Clone, Copy, Debug, Dupe, Default)]
+pub struct WriteCompression {
+    /// The zstd compression level, where 0 is zstd's default level.
+    pub level: i32,
+    /// Contents smaller than this many bytes are stored uncompressed, since compressing them
+    /// isn't worth the CPU.
+    pub min_size: usize,
+}
+
+/// `declare_write` hashes and compresses writes on blocking tasks when they add up to at least this
+/// many bytes. Below that, it's cheaper to do it on the caller's task.
+const PARALLEL_WRITE_MIN_BYTES: usize = 1 << 20;
+
+/// Hashes and compresses the contents of `declare_write`. The output is in the same order as
+/// `contents`.
+async fn prepare_writes(
+    contents: Vec<WriteRequest>,
+    digest_config: DigestConfig,
+    compression: WriteCompression,
+) -> buck2_error::Result<
+    Vec<(
+        ProjectRelativePathBuf,
+        ArtifactValue,
+        ArtifactMaterializationMethod,
+    )>,
+> {
+    let total_bytes = contents.iter().map(|w| w.content.len()).sum::<usize>();
+    let prepare = move |write: WriteRequest| prepare_write(write, digest_config, compression);
+    if contents.len() < 2 || total_bytes < PARALLEL_WRITE_MIN_BYTES {
+        contents.into_iter().map(prepare).collect()
+    } else {
+        map_blocking_in_order(contents, prepare).await
+    }
+}
+
+fn prepare_write(
+    WriteRequest {
+        path,
+        content,
+        is_executable,
+    }: WriteRequest,
+    digest_config: DigestConfig,
+    compression: WriteCompression,
+) -> buck2_error::Result<(
+    ProjectRelativePathBuf,
+    ArtifactValue,
+    ArtifactMaterializationMethod,
+)> {
+    let digest = TrackedFileDigest::from_content(&content, digest_config.cas_digest_config());
+
+    let meta = FileMetadata {
+        digest,
+        is_executable,
+    };
+
+    let write = WriteFile::new(content, is_executable, compression)?;
+
+    Ok((
+        path,
+        ArtifactValue::file(meta),
+        ArtifactMaterializationMethod::Write(Arc::new(write)),
+    ))
+}
+
+/// Applies `f` to `items` on blocking tasks, one per available CPU, each handling a contiguous
+/// chunk of `items`. The output is in the same order as `items`.
+///
+/// Like applying `f` to each item in turn, this fails with the error of the first item that
+/// failed. Items after a failed one are skipped.
+async fn map_blocking_in_order<T, R>(
+    items: Vec<T>,
+    f: impl Fn(T) -> buck2_error::Result<R> + Send + Sync + 'static,
+) -> buck2_error::Result<Vec<R>>
+where
+    T: Send + 'static,
+    R: Send + 'static,
+{
+    let len = items.len();
+    let parallelism = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let chunk_size = len.div_ceil(parallelism).max(1);
+    let f = Arc::new(f);
+    // Index of the first item that failed so far.
+    let first_failure = Arc::new(AtomicUsize::new(usize::MAX));
+
+    let mut items = items.into_iter();
+    let mut tasks = Vec::with_capacity(parallelism);
+    for start in (0..len).step_by(chunk_size) {
+        let chunk = items.by_ref().take(chunk_size).collect::<Vec<_>>();
+        let f = f.dupe();
+        let first_failure = first_failure.dupe();
+        tasks.push(tokio::task::spawn_blocking(move || {
+            let mut results = Vec::with_capacity(chunk.len());
+            for (index, item) in (start..).zip(chunk) {
+                if first_failure.load(Ordering::Relaxed) < index {
+                    break;
+                }
+                let result = f(item);
+                let failed = result.is_err();
+                results.push(result);
+                if failed {
+                    first_failure.fetch_min(index, Ordering::Relaxed);
+                    break;
+                }
+            }
+            results
+        }));
+    }
+
+    // A chunk only stops early after an error at a lower index, which is returned before we get
+    // to the items it skipped.
+    let mut results = Vec::with_capacity(len);
+    for task in tasks {
+        for result in task.await? {
+            results.push(result?);
+        }
+    }
+    Ok(results)
 }
 
 pub struct TtlRefreshConfiguration {
     pub frequency: std::time::Duration,
     pub min_ttl: Duration,
     pub enabled: bool,
+    pub methods: TtlRefreshMethods,
+    /// How many past refreshes to remember for `debug materializer get-ttl-refresh-log`.
+    pub history_size: usize,
+}
+
+/// Which declared artifacts TTL refresh considers, by how they would be materialized. Only
+/// artifacts that will be downloaded from the CAS expire, so that is all we refresh by default.
+#[derive(Clone, Copy, Debug, Dupe)]
+pub struct TtlRefreshMethods {
+    pub cas_download: bool,
+    pub http_download: bool,
+}
+
+impl Default for TtlRefreshMethods {
+    fn default() -> Self {
+        Self {
+            cas_download: true,
+            http_download: false,
+        }
+    }
+}
+
+impl TtlRefreshMethods {
+    fn includes(&self, method: &ArtifactMaterializationMethod) -> bool {
+        match method {
+            ArtifactMaterializationMethod::CasDownload { .. } => self.cas_download,
+            ArtifactMaterializationMethod::HttpDownload { .. } => self.http_download,
+            ArtifactMaterializationMethod::LocalCopy(..)
+            | ArtifactMaterializationMethod::Write(..) => false,
+            #[cfg(test)]
+            ArtifactMaterializationMethod::Test => false,
+        }
+    }
+}
+
+/// Periodic low priority sweep of the artifact tree, which records how much each cell materialized
+/// and optionally prunes declared artifacts that have not been requested in a long time.
+pub struct ArtifactTreeSweepConfiguration {
+    pub frequency: std::time::Duration,
+    /// Declared (but not materialized) artifacts that have not been declared or requested for this
+    /// long are forgotten. If an action that produced a pruned artifact is still cached, the
+    /// artifact will not be known to the materializer anymore, so this is off by default.
+    pub declared_idle_ttl: Option<std::time::Duration>,
+}
+
+#[derive(Debug, buck2_error::Error)]
+#[buck2(tag = Input)]
+pub enum ArtifactTreeSweepConfigError {
+    #[error(
+        "Invalid value for buckconfig `[buck2] artifact_tree_sweep_frequency_seconds`: must be positive"
+    )]
+    ZeroFrequency,
+    #[error(
+        "Invalid value for buckconfig `[buck2] declared_artifact_idle_ttl_hours`. Got `{0}`, expected a non-negative number of hours."
+    )]
+    NegativeIdleTtl(i64),
+}
+
+impl ArtifactTreeSweepConfiguration {
+    pub fn try_new(
+        frequency_seconds: u64,
+        declared_idle_ttl_hours: Option<i64>,
+    ) -> buck2_error::Result<Self> {
+        if frequency_seconds == 0 {
+            return Err(ArtifactTreeSweepConfigError::ZeroFrequency.into());
+        }
+        let declared_idle_ttl = declared_idle_ttl_hours
+            .map(|hours| match u64::try_from(hours) {
+                Ok(hours) => Ok(std::time::Duration::from_secs(hours * 60 * 60)),
+                Err(_) => Err(ArtifactTreeSweepConfigError::NegativeIdleTtl(hours)),
+            })
+            .transpose()?;
+        Ok(Self {
+            frequency: std::time::Duration::from_secs(frequency_seconds),
+            declared_idle_ttl,
+        })
+    }
 }
 
 #[derive(Clone, Copy, Debug, Dupe, PartialEq)]
@@ -233,9 +560,10 @@ pub struct MaterializerSender<T: 'static> {
     /// High priority commands are processed in order.
     high_priority: mpsc::UnboundedSender<MaterializerCommand<T>>,
     /// Low priority commands are processed in order relative to each other, but high priority
-    /// commands can be reordered ahead of them.
-    low_priority: mpsc::UnboundedSender<LowPriorityMaterializerCommand>,
+    /// commands can be reordered ahead of them. This queue is bounded, see `send_low_priority`.
+    low_priority: mpsc::Sender<LowPriorityMaterializerCommand>,
     counters: MaterializerCounters,
+    stats: Arc<DeferredMaterializerStats>,
     /// Liveliness guard held while clean stale executes, dropped to interrupt clean.
     clean_guard: Mutex<Option<LivelinessGuard>>,
 }
@@ -246,24 +574,88 @@ impl<T> MaterializerSender<T> {
         command: MaterializerCommand<T>,
     ) -> Result<(), mpsc::error::SendError<MaterializerCommand<T>>> {
         *self.clean_guard.lock() = None;
+        self.send_without_interrupting_clean(command)
+    }
+
+    /// Like `send`, but doesn't interrupt a running clean. For follow-up commands enqueued from
+    /// the command thread, which aren't new activity.
+    fn send_without_interrupting_clean(
+        &self,
+        command: MaterializerCommand<T>,
+    ) -> Result<(), mpsc::error::SendError<MaterializerCommand<T>>> {
         let res = self.high_priority.send(command);
         self.counters.sent.fetch_add(1, Ordering::Relaxed);
+        self.record_queue_size();
         res
     }
 
-    fn send_low_priority(
+    /// Send a low priority command, waiting for the command thread to make room if the queue is
+    /// full. The command thread drains high priority commands first, so this must never be called
+    /// from the command thread itself.
+    async fn send_low_priority(
         &self,
         command: LowPriorityMaterializerCommand,
     ) -> Result<(), mpsc::error::SendError<LowPriorityMaterializerCommand>> {
-        let res = self.low_priority.send(command);
+        let res = match self.try_send_low_priority(command) {
+            Ok(()) => Ok(()),
+            Err(command) => self.low_priority.send(command).await,
+        };
         self.counters.sent.fetch_add(1, Ordering::Relaxed);
+        self.record_queue_size();
         res
     }
+
+    /// Like `send_low_priority`, but for blocking IO threads. Those must not wait for the command
+    /// thread, which may itself be waiting for IO, so if the queue is full the command goes on the
+    /// unbounded high priority queue instead. That can reorder it ahead of queued low priority
+    /// commands, which is fine since those carry the version they apply to.
+    ///
+    /// If the materializer has shut down, the command is dropped.
+    fn blocking_send_low_priority(&self, command: LowPriorityMaterializerCommand) {
+        match self.try_send_low_priority(command) {
+            Ok(()) => {
+                self.counters.sent.fetch_add(1, Ordering::Relaxed);
+                self.record_queue_size();
+            }
+            Err(command) => {
+                let _ignored =
+                    self.send_without_interrupting_clean(MaterializerCommand::LowPriority(command));
+            }
+        }
+    }
+
+    /// Returns the command back if it could not be sent right away, either because the queue is
+    /// full or because the command thread has exited.
+    fn try_send_low_priority(
+        &self,
+        command: LowPriorityMaterializerCommand,
+    ) -> Result<(), LowPriorityMaterializerCommand> {
+        match self.low_priority.try_send(command) {
+            Ok(()) => Ok(()),
+            Err(mpsc::error::TrySendError::Full(command)) => {
+                self.stats
+                    .low_priority_blocked_sends
+                    .fetch_add(1, Ordering::Relaxed);
+                Err(command)
+            }
+            Err(mpsc::error::TrySendError::Closed(command)) => Err(command),
+        }
+    }
+
+    fn record_queue_size(&self) {
+        self.stats
+            .queue_size_high_watermark
+            .fetch_max(self.counters.queue_size() as u64, Ordering::Relaxed);
+        let low_priority_len = self.low_priority.max_capacity() - self.low_priority.capacity();
+        self.stats
+            .low_priority_queue_high_watermark
+            .fetch_max(low_priority_len as u64, Ordering::Relaxed);
+    }
 }
 
 struct MaterializerReceiver<T: 'static> {
     high_priority: mpsc::UnboundedReceiver<MaterializerCommand<T>>,
-    low_priority: mpsc::UnboundedReceiver<LowPriorityMaterializerCommand>,
+    low_priority: mpsc::Receiver<LowPriorityMaterializerCommand>,
     counters: MaterializerCounters,
 }
 
@@ -272,6 +664,94 @@ struct TtlRefreshHistoryEntry {
     outcome: Option<buck2_error::Result<()>>,
 }
 
+/// Consecutive TTL refresh failures after which we dispatch a `MaterializerTtlRefreshFailing`.
+const TTL_REFRESH_FAILURES_TO_ESCALATE: u64 = 3;
+
+/// Ring buffer of the most recent TTL refreshes, oldest first.
+struct TtlRefreshHistory {
+    entries: VecDeque<TtlRefreshHistoryEntry>,
+    capacity: usize,
+    /// Failed refreshes since the last successful one. Skipped refreshes don't count either way.
+    consecutive_failures: u64,
+}
+
+impl TtlRefreshHistory {
+    fn new(capacity: usize) -> Self {
+        Self {
+            entries: VecDeque::with_capacity(capacity),
+            capacity,
+            consecutive_failures: 0,
+        }
+    }
+
+    /// Returns the event to dispatch if this failure is the one that makes refreshes fail
+    /// `TTL_REFRESH_FAILURES_TO_ESCALATE` times in a row.
+    fn push(
+        &mut self,
+        entry: TtlRefreshHistoryEntry,
+    ) -> Option<buck2_data::MaterializerTtlRefreshFailing> {
+        let escalation = match &entry.outcome {
+            None => None,
+            Some(Ok(())) => {
+                self.consecutive_failures = 0;
+                None
+            }
+            Some(Err(e)) => {
+                self.consecutive_failures += 1;
+                (self.consecutive_failures == TTL_REFRESH_FAILURES_TO_ESCALATE).then(|| {
+                    buck2_data::MaterializerTtlRefreshFailing {
+                        consecutive_failures: self.consecutive_failures,
+                        error: Some(
+                            (&e.clone().tag([buck2_error::ErrorTag::TtlRefreshFailing])).into(),
+                        ),
+                    }
+                })
+            }
+        };
+        if self.capacity == 0 {
+            return escalation;
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+        escalation
+    }
+
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &TtlRefreshHistoryEntry> {
+        self.entries.iter()
+    }
+}
+
+/// What the deferred materializer knows about an artifact. See
+/// [`DeferredMaterializerAccessor::artifact_state`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArtifactState {
+    /// The artifact is known but has not been materialized.
+    Declared {
+        /// How the artifact would be materialized.
+        method: String,
+        processing: ArtifactProcessing,
+    },
+    /// The artifact is on disk.
+    Materialized {
+        last_access_time: DateTime<Utc>,
+        processing: ArtifactProcessing,
+    },
+}
+
+/// Work currently in flight for an artifact.
+#[derive(Debug, Clone, Copy, Dupe, PartialEq, Eq)]
+pub enum ArtifactProcessing {
+    Idle,
+    Cleaning,
+    Materializing,
+}
+
 // NOTE: This doesn't derive `Error` and that's on purpose.  We don't want to make it easy (or
 // possible, in fact) to add  `context` to this SharedProcessingError and lose the variant.
 #[derive(Debug, Clone, Dupe)]
@@ -354,14 +834,15 @@ impl<T: IoHandler + Allocative> Materializer for DeferredMaterializerAccessor<T>
                 }
             }
         }
-        let cmd = MaterializerCommand::Declare(
-            path,
-            value,
-            Box::new(ArtifactMaterializationMethod::LocalCopy(srcs_tree, srcs)),
-            get_dispatcher(),
-        );
-        self.command_sender.send(cmd)?;
-        Ok(())
+        self.send_declare(|context| {
+            MaterializerCommand::Declare(
+                path,
+                value,
+                Box::new(ArtifactMaterializationMethod::LocalCopy(srcs_tree, srcs)),
+                context,
+            )
+        })
+        .await
     }
 
     async fn declare_cas_many_impl<'a, 'b>(
@@ -370,16 +851,8 @@ impl<T: IoHandler + Allocative> Materializer for DeferredMaterializerAccessor<T>
         artifacts: Vec<(ProjectRelativePathBuf, ArtifactValue)>,
         _cancellations: &CancellationContext,
     ) -> buck2_error::Result<()> {
-        for (path, value) in artifacts {
-            let cmd = MaterializerCommand::Declare(
-                path,
-                value,
-                Box::new(ArtifactMaterializationMethod::CasDownload { info: info.dupe() }),
-                get_dispatcher(),
-            );
-            self.command_sender.send(cmd)?;
-        }
-        Ok(())
+        self.send_declare(|context| MaterializerCommand::DeclareMany(artifacts, info, context))
+            .await
     }
 
     async fn declare_http(
@@ -388,15 +861,15 @@ impl<T: IoHandler + Allocative> Materializer for DeferredMaterializerAccessor<T>
         info: HttpDownloadInfo,
         _cancellations: &CancellationContext,
     ) -> buck2_error::Result<()> {
-        let cmd = MaterializerCommand::Declare(
-            path,
-            ArtifactValue::file(info.metadata.dupe()),
-            Box::new(ArtifactMaterializationMethod::HttpDownload { info }),
-            get_dispatcher(),
-        );
-        self.command_sender.send(cmd)?;
-
-        Ok(())
+        self.send_declare(|context| {
+            MaterializerCommand::Declare(
+                path,
+                ArtifactValue::file(info.metadata.dupe()),
+                Box::new(ArtifactMaterializationMethod::HttpDownload { info }),
+                context,
+            )
+        })
+        .await
     }
 
     async fn declare_write<'a>(
@@ -408,50 +881,16 @@ impl<T: IoHandler + Allocative> Materializer for DeferredMaterializerAccessor<T>
         }
 
         let contents = generate()?;
-
-        let mut paths = Vec::with_capacity(contents.len());
-        let mut values = Vec::with_capacity(contents.len());
-        let mut methods = Vec::with_capacity(contents.len());
-
-        for WriteRequest {
-            path,
-            content,
-            is_executable,
-        } in contents
-        {
-            let digest = TrackedFileDigest::from_content(
-                &content,
-                self.io.digest_config().cas_digest_config(),
-            );
-
-            let meta = FileMetadata {
-                digest,
-                is_executable,
-            };
-
-            // NOTE: The zstd crate doesn't release extra capacity of its encoding buffer so it's
-            // important to do so here (or the compressed Vec is the same capacity as the input!).
-            let compressed_data = zstd::bulk::compress(&content, 0)
-                .with_buck_error_context(|| format!("Error compressing {} bytes", content.len()))?
-                .into_boxed_slice();
-
-            paths.push(path);
-            values.push(ArtifactValue::file(meta));
-            methods.push(ArtifactMaterializationMethod::Write(Arc::new(WriteFile {
-                compressed_data,
-                decompressed_size: content.len(),
-                is_executable,
-            })));
-        }
-
-        for (path, (value, method)) in std::iter::zip(paths, std::iter::zip(values.iter(), methods))
-        {
-            self.command_sender.send(MaterializerCommand::Declare(
-                path,
-                value.dupe(),
-                Box::new(method),
-                get_dispatcher(),
-            ))?;
+        let writes =
+            prepare_writes(contents, self.io.digest_config(), self.write_compression).await?;
+
+        let mut values = Vec::with_capacity(writes.len());
+        for (path, value, method) in writes {
+            self.send_declare(|context| {
+                MaterializerCommand::Declare(path, value.dupe(), Box::new(method), context)
+            })
+            .await?;
+            values.push(value);
         }
 
         Ok(values)
@@ -486,6 +925,22 @@ impl<T: IoHandler + Allocative> Materializer for DeferredMaterializerAccessor<T>
         Ok(has_artifact)
     }
 
+    async fn has_artifacts_at(
+        &self,
+        paths: Vec<ProjectRelativePathBuf>,
+    ) -> buck2_error::Result<Vec<bool>> {
+        let (sender, recv) = oneshot::channel();
+
+        self.command_sender
+            .send(MaterializerCommand::HasArtifacts(paths, sender))?;
+
+        let has_artifacts = recv
+            .await
+            .buck_error_context("Recv'ing match future from command thread.")?;
+
+        Ok(has_artifacts)
+    }
+
     async fn invalidate_many(&self, paths: Vec<ProjectRelativePathBuf>) -> buck2_error::Result<()> {
         let (sender, recv) = oneshot::channel();
 
@@ -548,6 +1003,60 @@ impl<T: IoHandler + Allocative> Materializer for DeferredMaterializerAccessor<T>
         Ok(recv.await?)
     }
 
+    async fn generate_manifest(
+        &self,
+        paths: Vec<ProjectRelativePathBuf>,
+    ) -> buck2_error::Result<Manifest> {
+        let (sender, recv) = oneshot::channel();
+        self.command_sender
+            .send(MaterializerCommand::GenerateManifest(paths, sender))?;
+        recv.await
+            .buck_error_context("Recv'ing manifest from command thread.")?
+    }
+
+    async fn export_artifacts(
+        &self,
+        pairs: Vec<(ProjectRelativePathBuf, AbsPathBuf)>,
+    ) -> buck2_error::Result<Vec<buck2_error::Result<()>>> {
+        let buck_out = self.io.fs().resolve(self.io.buck_out_path());
+        let mut results = pairs
+            .iter()
+            .map(|(path, dest)| check_export_destination(&buck_out, path, dest))
+            .collect::<Vec<_>>();
+        let to_export = (0..pairs.len())
+            .filter(|i| results[*i].is_ok())
+            .collect::<Vec<_>>();
+
+        let materialized = self
+            .materialize_many(to_export.iter().map(|i| pairs[*i].0.clone()).collect())
+            .await?
+            .collect::<Vec<_>>()
+            .await;
+
+        let exported = futures::stream::iter(to_export.into_iter().zip(materialized))
+            .map(|(i, materialized)| {
+                let (path, dest) = pairs[i].clone();
+                async move {
+                    let res = match materialized {
+                        Ok(()) => {
+                            self.io
+                                .export_artifact(path, dest, CancellationContext::never_cancelled())
+                                .await
+                        }
+                        Err(e) => Err(e.into()),
+                    };
+                    (i, res)
+                }
+            })
+            .buffer_unordered(EXPORT_CONCURRENCY)
+            .collect::<Vec<_>>()
+            .await;
+        for (i, res) in exported {
+            results[i] = res;
+        }
+        Ok(results)
+    }
+
     fn as_deferred_materializer_extension(&self) -> Option<&dyn DeferredMaterializerExtensions> {
         Some(self as _)
     }
@@ -557,10 +1066,119 @@ impl<T: IoHandler + Allocative> Materializer for DeferredMaterializerAccessor<T>
     }
 
     fn add_snapshot_stats(&self, snapshot: &mut buck2_data::Snapshot) {
-        snapshot.deferred_materializer_declares = self.stats.declares.load(Ordering::Relaxed);
-        snapshot.deferred_materializer_declares_reused =
-            self.stats.declares_reused.load(Ordering::Relaxed);
-        snapshot.deferred_materializer_queue_size = self.command_sender.counters.queue_size() as _;
+        let stats = self.current_stats();
+        snapshot.deferred_materializer_declares = stats.declares;
+        snapshot.deferred_materializer_declares_reused = stats.declares_reused;
+        snapshot.deferred_materializer_declared_pruned = stats.declared_pruned;
+        snapshot.deferred_materializer_artifact_tree_bytes = stats.artifact_tree_bytes;
+        snapshot.deferred_materializer_materialized_bytes_by_cell =
+            stats.materialized_bytes_by_cell;
+        snapshot.deferred_materializer_queue_size = stats.queue_size;
+        snapshot.deferred_materializer_queue_size_high_watermark = stats.queue_size_high_watermark;
+        snapshot.deferred_materializer_low_priority_queue_high_watermark =
+            stats.low_priority_queue_high_watermark;
+        snapshot.deferred_materializer_low_priority_blocked_sends =
+            stats.low_priority_blocked_sends;
+        snapshot.deferred_materializer_materializations_by_method =
+            stats.materializations_by_method;
+        snapshot.deferred_materializer_materialization_duration_us_by_method = stats
+            .materialization_duration_by_method
+            .into_iter()
+            .map(|(method, duration)| (method, duration.as_micros() as u64))
+            .collect();
+    }
+
+    fn materialized_bytes(&self) -> Option<u64> {
+        Some(self.stats.materialized_bytes_by_cell.lock().values().sum())
+    }
+
+    fn take_command_stats(
+        &self,
+        trace_id: &TraceId,
+    ) -> Option<buck2_data::CommandMaterializationStats> {
+        self.stats.command_stats.take(trace_id)
+    }
+}
+
+/// Maximum number of artifacts copied concurrently by `export_artifacts`.
+const EXPORT_CONCURRENCY: usize = 16;
+
+/// Exporting into buck-out would corrupt the materializer's state, and exporting to a parent of
+/// buck-out would delete it.
+fn check_export_destination(
+    buck_out: &AbsNormPath,
+    path: &ProjectRelativePath,
+    dest: &AbsPath,
+) -> buck2_error::Result<()> {
+    if dest.as_path().starts_with(buck_out.as_path())
+        || buck_out.as_path().starts_with(dest.as_path())
+    {
+        return Err(ExportError::OverlapsBuckOut(
+            path.to_owned(),
+            dest.to_owned(),
+            buck_out.to_owned(),
+        )
+        .into());
+    }
+    Ok(())
+}
+
+impl<T: IoHandler> DeferredMaterializerAccessor<T> {
+    /// Sends a declare command. When conflicting declares fail, also waits for it to be processed.
+    async fn send_declare(
+        &self,
+        command: impl FnOnce(DeclareContext) -> MaterializerCommand<T>,
+    ) -> buck2_error::Result<()> {
+        let (context, result) =
+            DeclareContext::new(get_dispatcher(), self.fail_on_declare_conflicts);
+        self.command_sender.send(command(context))?;
+        if let Some(result) = result {
+            result
+                .await
+                .buck_error_context("Recv'ing declare result from command thread.")??;
+        }
+        Ok(())
+    }
+
+    /// Returns the materializer's current statistics. This is cheap and does not go through the
+    /// command thread.
+    pub fn current_stats(&self) -> DeferredMaterializerSnapshot {
+        let (materializations_by_method, materialization_duration_by_method) =
+            self.stats.materialization_latencies.snapshot();
+        DeferredMaterializerSnapshot {
+            declares: self.stats.declares.load(Ordering::Relaxed),
+            declares_reused: self.stats.declares_reused.load(Ordering::Relaxed),
+            declared_pruned: self.stats.declared_pruned.load(Ordering::Relaxed),
+            artifact_tree_bytes: self.stats.artifact_tree_bytes.load(Ordering::Relaxed),
+            materialized_bytes_by_cell: self.stats.materialized_bytes_by_cell.lock().clone(),
+            queue_size: self.command_sender.counters.queue_size() as u64,
+            queue_size_high_watermark: self.stats.queue_size_high_watermark.load(Ordering::Relaxed),
+            low_priority_queue_high_watermark: self
+                .stats
+                .low_priority_queue_high_watermark
+                .load(Ordering::Relaxed),
+            low_priority_blocked_sends: self
+                .stats
+                .low_priority_blocked_sends
+                .load(Ordering::Relaxed),
+            materializations_by_method,
+            materialization_duration_by_method,
+        }
+    }
+
+    /// Returns the state of the artifact at `path`, or of the artifact that contains `path`.
+    /// Returns `None` if the materializer doesn't know about any such artifact.
+    pub async fn artifact_state(
+        &self,
+        path: ProjectRelativePathBuf,
+    ) -> buck2_error::Result<Option<ArtifactState>> {
+        let (sender, recv) = oneshot::channel();
+
+        self.command_sender
+            .send(MaterializerCommand::GetArtifactState(path, sender))?;
+
+        recv.await
+            .buck_error_context("Recv'ing artifact state from command thread.")
     }
 }
 
@@ -575,20 +1193,25 @@ impl DeferredMaterializerAccessor<DefaultIoHandler> {
         re_client_manager: Arc<ReConnectionManager>,
         io_executor: Arc<dyn BlockingExecutor>,
         configs: DeferredMaterializerConfigs,
-        sqlite_db: Option<MaterializerStateSqliteDb>,
-        sqlite_state: Option<MaterializerState>,
+        mut sqlite_db: Option<MaterializerStateSqliteDb>,
+        mut sqlite_state: Option<MaterializerState>,
         http_client: HttpClient,
         daemon_dispatcher: EventDispatcher,
+        io_pressure: Option<Arc<MaterializerIoPressure>>,
     ) -> buck2_error::Result<Self> {
         let (high_priority_sender, high_priority_receiver) = mpsc::unbounded_channel();
-        let (low_priority_sender, low_priority_receiver) = mpsc::unbounded_channel();
+        let (low_priority_sender, low_priority_receiver) =
+            mpsc::channel(configs.low_priority_queue_capacity.max(1));
 
         let counters = MaterializerCounters::leak_new();
 
+        let stats = Arc::new(DeferredMaterializerStats::default());
+
         let command_sender = Arc::new(MaterializerSender {
             high_priority: high_priority_sender,
             low_priority: low_priority_sender,
             counters,
+            stats: stats.dupe(),
             clean_guard: Mutex::new(None),
         });
 
@@ -598,8 +1221,6 @@ impl DeferredMaterializerAccessor<DefaultIoHandler> {
             counters,
         };
 
-        let stats = Arc::new(DeferredMaterializerStats::default());
-
         let num_entries_from_sqlite = sqlite_state.as_ref().map_or(0, |s| s.len()) as u64;
         let materializer_state_info = buck2_data::MaterializerStateInfo {
             num_entries_from_sqlite,
@@ -608,6 +1229,12 @@ impl DeferredMaterializerAccessor<DefaultIoHandler> {
             (!matches!(configs.update_access_times, AccessTimesUpdates::Disabled))
                 .then(HashSet::new);
 
+        let clock_skew = ClockSkewGuard::load(
+            configs.clock_skew,
+            sqlite_db.as_mut(),
+            sqlite_state.as_mut(),
+            Utc::now(),
+        );
         let tree = ArtifactTree::initialize(sqlite_state);
 
         let io = Arc::new(DefaultIoHandler::new(
@@ -631,14 +1258,18 @@ impl DeferredMaterializerAccessor<DefaultIoHandler> {
                     rt,
                     configs.defer_write_actions,
                     LogBuffer::new(25),
+                    configs.ttl_refresh.history_size,
                     command_sender,
                     tree,
                     cancellations,
                     stats,
+                    io_pressure,
                     access_times_buffer,
                     configs.verbose_materializer_log,
                     daemon_dispatcher,
                     configs.disable_eager_write_dispatch,
+                    configs.paranoid_verification,
+                    clock_skew,
                 )
             }
         };
@@ -660,6 +1291,7 @@ impl DeferredMaterializerAccessor<DefaultIoHandler> {
                     access_time_update_max_buffer_size,
                     configs.update_access_times,
                     configs.clean_stale_config,
+                    configs.artifact_tree_sweep,
                 ));
             }
         })
@@ -670,10 +1302,12 @@ impl DeferredMaterializerAccessor<DefaultIoHandler> {
             command_sender,
             materialize_final_artifacts: configs.materialize_final_artifacts,
             defer_write_actions: configs.defer_write_actions,
+            write_compression: configs.write_compression,
             io,
             materializer_state_info,
             stats,
             verbose_materializer_log: configs.verbose_materializer_log,
+            fail_on_declare_conflicts: configs.fail_on_declare_conflicts,
         })
     }
 }
@@ -709,8 +1343,50 @@ async fn join_all_existing_futs(
 #[derive(Derivative)]
 #[derivative(Debug)]
 pub struct WriteFile {
+    /// The contents, zstd-compressed if `compressed` is set.
     #[derivative(Debug = "ignore")]
-    compressed_data: Box<[u8]>,
+    data: Box<[u8]>,
+    compressed: bool,
     decompressed_size: usize,
     is_executable: bool,
 }
+
+impl WriteFile {
+    fn new(
+        content: Vec<u8>,
+        is_executable: bool,
+        compression: WriteCompression,
+    ) -> buck2_error::Result<Self> {
+        let decompressed_size = content.len();
+        if decompressed_size < compression.min_size {
+            return Ok(Self {
+                data: content.into_boxed_slice(),
+                compressed: false,
+                decompressed_size,
+                is_executable,
+            });
+        }
+
+        // NOTE: The zstd crate doesn't release extra capacity of its encoding buffer so it's
+        // important to do so here (or the compressed Vec is the same capacity as the input!).
+        let data = zstd::bulk::compress(&content, compression.level)
+            .with_buck_error_context(|| format!("Error compressing {} bytes", decompressed_size))?
+            .into_boxed_slice();
+        Ok(Self {
+            data,
+            compressed: true,
+            decompressed_size,
+            is_executable,
+        })
+    }
+
+    /// The contents to write out.
+    fn contents(&self) -> buck2_error::Result<Cow<'_, [u8]>> {
+        if !self.compressed {
+            return Ok(Cow::Borrowed(&self.data));
+        }
+        let data = zstd::bulk::decompress(&self.data, self.decompressed_size)
+            .buck_error_context("Error decompressing data")?;
+        Ok(Cow::Owned(data))
+    }
+}
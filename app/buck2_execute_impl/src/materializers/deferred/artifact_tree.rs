@@ -9,21 +9,28 @@
 
 use std::sync::Arc;
 
+use allocative::Allocative;
 use buck2_common::directory_metadata::DirectoryMetadata;
+use buck2_core::fs::project_rel_path::ProjectRelativePath;
 use buck2_core::fs::project_rel_path::ProjectRelativePathBuf;
 use buck2_core::soft_error;
 use buck2_directory::directory::directory_ref::DirectoryRef;
 use buck2_directory::directory::entry::DirectoryEntry;
 use buck2_error::BuckErrorContext;
+use buck2_events::span::SpanId;
 use buck2_execute::digest_config::DigestConfig;
 use buck2_execute::directory::ActionDirectoryEntry;
 use buck2_execute::directory::ActionDirectoryMember;
 use buck2_execute::directory::ActionSharedDirectory;
+use buck2_execute::materialize::manifest::Manifest;
+use buck2_execute::materialize::manifest::ManifestEntry;
+use buck2_execute::materialize::manifest::ManifestError;
 use buck2_execute::materialize::materializer::ArtifactNotMaterializedReason;
 use buck2_execute::materialize::materializer::CasDownloadInfo;
 use buck2_execute::materialize::materializer::CopiedArtifact;
 use buck2_execute::materialize::materializer::HttpDownloadInfo;
 use buck2_execute::output_size::OutputSize;
+use buck2_wrapper_common::invocation_id::TraceId;
 use chrono::DateTime;
 use chrono::Utc;
 use derive_more::Display;
@@ -32,6 +39,8 @@ use futures::future::BoxFuture;
 use futures::future::Shared;
 use tracing::instrument;
 
+use crate::materializers::deferred::ArtifactProcessing;
+use crate::materializers::deferred::ArtifactState;
 use crate::materializers::deferred::SharedMaterializingError;
 use crate::materializers::deferred::WriteFile;
 use crate::materializers::deferred::file_tree::FileTree;
@@ -68,6 +77,38 @@ pub struct ArtifactMaterializationData {
     /// this path would need to wait on the existing future to finish.
     /// TODO(scottcao): Turn this into a queue of pending futures.
     pub processing: Processing,
+    /// What declared the artifact, if this daemon declared it. Used to detect conflicting declares.
+    pub declared_by: Option<Arc<DeclareOwner>>,
+}
+
+/// What declared an artifact: the command, and the span it was declared from, which is usually
+/// the one of the action producing it.
+#[derive(Debug, PartialEq, Eq)]
+pub struct DeclareOwner {
+    pub trace_id: TraceId,
+    pub span_id: Option<SpanId>,
+}
+
+impl DeclareOwner {
+    pub fn to_proto(&self) -> buck2_data::MaterializerDeclareOwner {
+        buck2_data::MaterializerDeclareOwner {
+            trace_id: self.trace_id.to_string(),
+            span_id: self.span_id.map(u64::from),
+        }
+    }
+}
+
+impl Allocative for ArtifactMaterializationData {
+    fn visit<'a, 'b: 'a>(&self, visitor: &'a mut allocative::Visitor<'b>) {
+        // This is only used to estimate the size of the tree, so we only visit the fields that
+        // can be large.
+        let mut visitor = visitor.enter_self_sized::<Self>();
+        visitor.visit_field(allocative::Key::new("deps"), &self.deps);
+        if let ArtifactMaterializationStage::Declared { entry, .. } = &self.stage {
+            visitor.visit_field(allocative::Key::new("entry"), entry);
+        }
+        visitor.exit();
+    }
 }
 
 /// Represents a processing future + the version at which it was issued. When receiving
@@ -109,6 +150,9 @@ impl Processing {
 pub struct ArtifactMetadata(pub ActionDirectoryEntry<DirectoryMetadata>);
 
 impl ArtifactMetadata {
+    /// Whether `entry` is the artifact this metadata was recorded for. Directories are compared by
+    /// fingerprint, so an empty directory only matches an empty directory, and never a missing
+    /// path, which has no entry to compare to.
     pub fn matches_entry(&self, entry: &ActionDirectoryEntry<ActionSharedDirectory>) -> bool {
         match (&self.0, entry) {
             (
@@ -144,6 +188,16 @@ impl ArtifactMetadata {
         Self(new_entry)
     }
 
+    /// Whether this is an empty directory, which takes no space on disk beyond its own entry.
+    pub fn is_empty_dir(&self, digest_config: DigestConfig) -> bool {
+        match &self.0 {
+            DirectoryEntry::Dir(dir) => {
+                &dir.fingerprint == digest_config.empty_directory().fingerprint()
+            }
+            DirectoryEntry::Leaf(_) => false,
+        }
+    }
+
     pub fn size(&self) -> u64 {
         match &self.0 {
             DirectoryEntry::Dir(dir) => dir.total_size,
@@ -164,6 +218,9 @@ pub enum ArtifactMaterializationStage {
         /// Taken from `entry` of `ArtifactValue`. Used to materialize the actual artifact.
         entry: ActionDirectoryEntry<ActionSharedDirectory>,
         method: Arc<ArtifactMaterializationMethod>,
+        /// Last time the artifact was declared or requested. Used to prune entries that
+        /// nothing has needed in a long time.
+        last_requested: tokio::time::Instant,
     },
     /// This artifact was materialized
     Materialized {
@@ -250,6 +307,7 @@ impl ArtifactTree {
                             active: false,
                         },
                         processing: Processing::Done(Version(0)),
+                        declared_by: None,
                     }),
                 );
             }
@@ -278,7 +336,7 @@ impl ArtifactTree {
             ArtifactMaterializationStage::Materialized { .. } => {
                 return Ok(path);
             }
-            ArtifactMaterializationStage::Declared { entry, method } => {
+            ArtifactMaterializationStage::Declared { entry, method, .. } => {
                 (entry.dupe(), method.dupe())
             }
         };
@@ -345,6 +403,100 @@ impl ArtifactTree {
         }
     }
 
+    /// Describe the artifacts at `paths` using the metadata recorded when they were materialized.
+    ///
+    /// Each path must be the root of a materialized artifact. We don't retain the structure of
+    /// materialized directories, so those are described by their fingerprint.
+    pub fn generate_manifest(
+        &self,
+        paths: Vec<ProjectRelativePathBuf>,
+    ) -> buck2_error::Result<Manifest> {
+        let mut manifest = Manifest::new();
+        let mut not_materialized = Vec::new();
+        for path in paths {
+            let mut path_iter = path.iter();
+            let metadata = match self.prefix_get(&mut path_iter).map(|data| &data.stage) {
+                Some(ArtifactMaterializationStage::Materialized { metadata, .. })
+                    if path_iter.next().is_none() =>
+                {
+                    metadata
+                }
+                _ => {
+                    not_materialized.push(path);
+                    continue;
+                }
+            };
+            let entry = match &metadata.0 {
+                DirectoryEntry::Dir(dir) => ManifestEntry::from_directory_metadata(dir),
+                DirectoryEntry::Leaf(member) => ManifestEntry::from_member(member),
+            };
+            manifest.insert(path, entry);
+        }
+        if !not_materialized.is_empty() {
+            return Err(ManifestError::NotMaterialized(not_materialized).into());
+        }
+        Ok(manifest)
+    }
+
+    /// Describes the artifact at `path`, or the artifact that contains `path`.
+    pub fn artifact_state(&self, path: &ProjectRelativePath) -> Option<ArtifactState> {
+        let data = self.prefix_get(&mut path.iter())?;
+
+        let processing = match &data.processing {
+            Processing::Done(..) => ArtifactProcessing::Idle,
+            Processing::Active {
+                future: ProcessingFuture::Cleaning(..),
+                ..
+            } => ArtifactProcessing::Cleaning,
+            Processing::Active {
+                future: ProcessingFuture::Materializing(..),
+                ..
+            } => ArtifactProcessing::Materializing,
+        };
+
+        Some(match &data.stage {
+            ArtifactMaterializationStage::Declared { method, .. } => ArtifactState::Declared {
+                method: method.to_string(),
+                processing,
+            },
+            ArtifactMaterializationStage::Materialized {
+                last_access_time, ..
+            } => ArtifactState::Materialized {
+                last_access_time: *last_access_time,
+                processing,
+            },
+        })
+    }
+
+    /// Removes artifacts that have been declared but not requested since `idle_since`, and that have
+    /// nothing processing them. Returns the paths that were removed.
+    ///
+    /// A future build can always declare them again. Declared artifacts have no state in sqlite,
+    /// so there is nothing to remove there.
+    pub fn prune_idle_declared(
+        &mut self,
+        idle_since: tokio::time::Instant,
+    ) -> Vec<ProjectRelativePathBuf> {
+        let idle = self
+            .iter_with_paths()
+            .filter(|(_, data)| {
+                matches!(data.processing, Processing::Done(..))
+                    && matches!(
+                        data.stage,
+                        ArtifactMaterializationStage::Declared {
+                            last_requested, ..
+                        } if last_requested < idle_since
+                    )
+            })
+            .map(|(path, _)| ProjectRelativePathBuf::from(path))
+            .collect::<Vec<_>>();
+
+        for path in &idle {
+            let _ignored = self.remove_path(path);
+        }
+        idle
+    }
+
     #[instrument(level = "debug", skip(self, result), fields(path = %artifact_path))]
     pub fn cleanup_finished(
         &mut self,
@@ -377,6 +529,31 @@ impl ArtifactTree {
         }
     }
 
+    /// Describes the artifacts that `invalidate_paths_and_collect_futures` would remove for
+    /// `paths`, without modifying the tree or sqlite. Each artifact is reported with its stage,
+    /// suffixed with `InFlight` if a future is currently processing it.
+    pub fn preview_invalidate_paths(
+        &self,
+        paths: &[ProjectRelativePathBuf],
+    ) -> Vec<(ProjectRelativePathBuf, &'static str)> {
+        let mut result = Vec::new();
+        for path in paths {
+            for (path, data) in self.get_path_entries(path) {
+                let in_flight = matches!(data.processing, Processing::Active { .. });
+                let state = match (&data.stage, in_flight) {
+                    (ArtifactMaterializationStage::Declared { .. }, false) => "Declared",
+                    (ArtifactMaterializationStage::Declared { .. }, true) => "DeclaredInFlight",
+                    (ArtifactMaterializationStage::Materialized { .. }, false) => "Materialized",
+                    (ArtifactMaterializationStage::Materialized { .. }, true) => {
+                        "MaterializedInFlight"
+                    }
+                };
+                result.push((path, state));
+            }
+        }
+        result
+    }
+
     /// Removes paths from tree and returns a pair of two vecs.
     /// First vec is a list of paths removed. Second vec is a list of
     /// pairs of removed paths to futures that haven't finished.
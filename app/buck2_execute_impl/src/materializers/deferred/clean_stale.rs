@@ -16,6 +16,7 @@ use buck2_common::legacy_configs::configs::LegacyBuckConfig;
 use buck2_common::legacy_configs::key::BuckconfigKeyRef;
 use buck2_common::liveliness_observer::LivelinessGuard;
 use buck2_common::liveliness_observer::LivelinessObserverSync;
+use buck2_core::fs::buck_out_path::buck_out_path_owning_cell;
 use buck2_core::fs::fs_util;
 use buck2_core::fs::paths::abs_norm_path::AbsNormPath;
 use buck2_core::fs::paths::file_name::FileName;
@@ -31,6 +32,7 @@ use buck2_error::ErrorTag;
 use buck2_error::buck2_error;
 use buck2_events::dispatch::EventDispatcher;
 use buck2_events::metadata;
+use buck2_execute::digest_config::DigestConfig;
 use buck2_execute::execute::blocking::IoRequest;
 use buck2_execute::execute::clean_output_paths::cleanup_path;
 use buck2_futures::cancellation::CancellationContext;
@@ -41,6 +43,7 @@ use derivative::Derivative;
 use dupe::Dupe;
 use futures::FutureExt;
 use futures::future::BoxFuture;
+use tokio::sync::oneshot;
 use tokio::sync::oneshot::Sender;
 use tracing::error;
 
@@ -50,14 +53,22 @@ use crate::materializers::deferred::artifact_tree::ArtifactMaterializationData;
 use crate::materializers::deferred::artifact_tree::ArtifactTree;
 use crate::materializers::deferred::extension::ExtensionCommand;
 use crate::materializers::deferred::io_handler::IoHandler;
-use crate::materializers::deferred::join_all_existing_futs;
-use crate::materializers::sqlite::MaterializerStateSqliteDb;
 
 #[derive(Debug, Clone)]
 pub struct CleanStaleArtifactsCommand {
     pub keep_since_time: DateTime<Utc>,
     pub dry_run: bool,
     pub tracked_only: bool,
+    /// If not empty, only artifacts owned by these cells are considered.
+    pub cells: Vec<String>,
+    /// Stop cleaning once this many bytes have been selected for deletion. Artifacts are cleaned
+    /// oldest first, so whatever is left is picked up by the next clean.
+    pub max_bytes_per_run: Option<u64>,
+    /// Stop cleaning once this many artifacts have been selected for deletion.
+    pub max_files_per_run: Option<u64>,
+    /// Artifacts under these paths, or containing one of them, are never cleaned, regardless of
+    /// age.
+    pub keep_paths: Vec<ProjectRelativePathBuf>,
     pub dispatcher: EventDispatcher,
 }
 
@@ -73,6 +84,8 @@ pub struct CleanStaleArtifactsExtensionCommand {
 pub struct CleanResult {
     kind: CleanStaleResultKind,
     stats: CleanStaleStats,
+    /// Stale artifacts exempted by the keep-list, reported by dry runs.
+    kept_paths: Vec<ProjectRelativePathBuf>,
 }
 
 enum PendingCleanResult {
@@ -85,6 +98,7 @@ impl From<CleanStaleResultKind> for PendingCleanResult {
         PendingCleanResult::Finished(CleanResult {
             kind: val,
             stats: CleanStaleStats::default(),
+            kept_paths: Vec::new(),
         })
     }
 }
@@ -107,12 +121,16 @@ impl From<CleanResult> for buck2_cli_proto::CleanStaleResponse {
             }
             CleanStaleResultKind::SkippedDryRun => None,
             CleanStaleResultKind::Interrupted => Some("Interrupted"),
+            CleanStaleResultKind::Finished if result.stats.truncated => {
+                Some("Stopped after reaching the per-run limit, run again to clean the rest")
+            }
             CleanStaleResultKind::Finished => None,
             CleanStaleResultKind::Failed => None,
         };
         Self {
             message: message.map(|m| m.to_owned()),
             stats: Some(result.stats),
+            kept_paths: result.kept_paths.iter().map(|p| p.to_string()).collect(),
         }
     }
 }
@@ -184,70 +202,90 @@ impl CleanStaleArtifactsCommand {
         let (liveliness_observer, liveliness_guard) = LivelinessGuard::create_sync();
         *processor.command_sender.clean_guard.lock() = Some(liveliness_guard);
 
-        if let Some(sqlite_db) = processor.sqlite_db.as_mut() {
-            if !processor.defer_write_actions {
-                Ok(CleanStaleResultKind::SkippedDeferWriteDisabled.into())
-            } else {
-                self.scan_and_create_clean_fut(
-                    &mut processor.tree,
-                    sqlite_db,
-                    &processor.io,
-                    processor.cancellations,
-                    liveliness_observer.clone(),
-                )
-            }
-        } else {
+        if processor.sqlite_db.is_none() {
             Ok(CleanStaleResultKind::SkippedSqliteDisabled.into())
+        } else if !processor.defer_write_actions {
+            Ok(CleanStaleResultKind::SkippedDeferWriteDisabled.into())
+        } else {
+            self.scan_and_create_clean_fut(processor, liveliness_observer.clone())
         }
     }
 
     fn scan_and_create_clean_fut<T: IoHandler>(
         &self,
-        tree: &mut ArtifactTree,
-        sqlite_db: &mut MaterializerStateSqliteDb,
-        io: &Arc<T>,
-        cancellations: &'static CancellationContext,
+        processor: &mut DeferredMaterializerCommandProcessor<T>,
         liveliness_observer: Arc<dyn LivelinessObserverSync>,
     ) -> buck2_error::Result<PendingCleanResult> {
         let start_time = Instant::now();
-        let gen_path = io
-            .buck_out_path()
-            .join(ProjectRelativePathBuf::unchecked_new("gen".to_owned()));
-        let gen_dir = io.fs().resolve(&gen_path);
-        if !fs_util::try_exists(&gen_dir)? {
+        // Notice a clock jump since the last tick before looking at access times.
+        processor.observe_clock(Utc::now());
+        let io = processor.io.dupe();
+        let tree = &processor.tree;
+        let mut scan_paths = Vec::new();
+        for scan_path in self.scan_paths(io.buck_out_path())? {
+            if fs_util::try_exists(io.fs().resolve(&scan_path))? {
+                scan_paths.push(scan_path);
+            }
+        }
+        if scan_paths.is_empty() {
             return Ok(CleanStaleResultKind::SkippedNoGenDir.into());
         }
-        tracing::trace!(gen_dir = %gen_dir, "Scanning");
+        tracing::trace!(scan_paths = ?scan_paths, "Scanning");
 
         let mut found_paths = Vec::new();
+        let mut bytes_by_cell = HashMap::new();
         if self.tracked_only {
-            find_stale_tracked_only(tree, self.keep_since_time, &mut found_paths)?
+            find_stale_tracked_only(
+                tree,
+                self.keep_since_time,
+                &self.keep_paths,
+                io.buck_out_path(),
+                io.digest_config(),
+                |path| self.includes_path(io.buck_out_path(), path),
+                &mut found_paths,
+                &mut bytes_by_cell,
+            )?
         } else {
-            let gen_subtree = tree
-                .get_subtree(&mut gen_path.iter())
-                .buck_error_context("Found a file where gen dir expected")?;
-
-            let empty;
+            for scan_path in scan_paths {
+                let subtree = tree
+                    .get_subtree(&mut scan_path.iter())
+                    .buck_error_context("Found a file where an artifact dir was expected")?;
+
+                let empty;
+
+                let subtree = match subtree {
+                    Some(t) => t,
+                    None => {
+                        empty = HashMap::new();
+                        &empty
+                    }
+                };
 
-            let gen_subtree = match gen_subtree {
-                Some(t) => t,
-                None => {
-                    empty = HashMap::new();
-                    &empty
+                StaleFinder {
+                    io: io.dupe(),
+                    keep_since_time: self.keep_since_time,
+                    keep_paths: &self.keep_paths,
+                    found_paths: &mut found_paths,
+                    bytes_by_cell: &mut bytes_by_cell,
+                    liveliness_observer: liveliness_observer.clone(),
                 }
-            };
-
-            StaleFinder {
-                io: io.dupe(),
-                keep_since_time: self.keep_since_time,
-                found_paths: &mut found_paths,
-                liveliness_observer: liveliness_observer.clone(),
+                .visit_recursively(scan_path, subtree)?;
             }
-            .visit_recursively(gen_path, gen_subtree)?;
         };
 
         let mut stats = stats_for_paths(&found_paths);
         stats.scan_duration_s = (Instant::now() - start_time).as_secs();
+        let kept_paths: Vec<_> = found_paths
+            .iter()
+            .filter_map(|x| match x {
+                FoundPath::Kept(path, _) => Some(path.clone()),
+                _ => None,
+            })
+            .collect();
+
+        let (found_paths, truncated) =
+            take_within_limits(found_paths, self.max_bytes_per_run, self.max_files_per_run);
+        stats.truncated = truncated;
 
         // Log limited number of untracked artifacts to avoid logging spikes if schema changes.
         for (path, file_type) in found_paths
@@ -268,14 +306,40 @@ impl CleanStaleArtifactsCommand {
             return Ok(PendingCleanResult::Finished(CleanResult {
                 kind: CleanStaleResultKind::Interrupted,
                 stats,
+                kept_paths: Vec::new(),
             }));
         }
 
-        // If no stale or retained artifact founds, the db should be empty.
-        if stats.stale_artifact_count + stats.retained_artifact_count == 0 {
+        // The scan saw every materialized artifact of the cells it covered, so their totals are
+        // now up to date.
+        {
+            let mut materialized_bytes_by_cell = processor.stats.materialized_bytes_by_cell.lock();
+            if self.cells.is_empty() {
+                *materialized_bytes_by_cell = bytes_by_cell;
+            } else {
+                for cell in &self.cells {
+                    match bytes_by_cell.remove(cell) {
+                        Some(bytes) => materialized_bytes_by_cell.insert(cell.clone(), bytes),
+                        None => materialized_bytes_by_cell.remove(cell),
+                    };
+                }
+            }
+        }
+
+        // If no stale or retained artifact founds, the db should be empty. That doesn't hold if we
+        // only looked at some cells.
+        if self.cells.is_empty()
+            && stats.stale_artifact_count
+                + stats.retained_artifact_count
+                + stats.kept_artifact_count
+                == 0
+        {
             // Just need to know if any entries exist, could be a simpler query.
             // Checking the db directly in case tree is somehow not in sync.
-            let materializer_state = sqlite_db
+            let materializer_state = processor
+                .sqlite_db
+                .as_mut()
+                .internal_error("Checked above")?
                 .materializer_state_table()
                 .read_all(io.digest_config())?;
 
@@ -295,19 +359,50 @@ impl CleanStaleArtifactsCommand {
             Ok(PendingCleanResult::Finished(CleanResult {
                 kind: CleanStaleResultKind::SkippedDryRun,
                 stats,
+                kept_paths,
             }))
+        } else if let Some(error) = processor
+            .clock_skew
+            // Kept artifacts are never cleaned, so they don't count towards the tracked artifacts.
+            .check_clean(stats.stale_artifact_count, stats.retained_artifact_count)
+        {
+            // Not quiet, since cleans are refused until the daemon restarts.
+            Err(
+                soft_error!("clean_stale_clock_skew", error.into(), quiet: false)
+                    .map(|e| e.into())?,
+            )
         } else {
             Ok(PendingCleanResult::Pending(create_clean_fut(
                 found_paths,
                 stats,
-                tree,
-                sqlite_db,
-                io,
-                cancellations,
+                processor,
                 liveliness_observer,
             )?))
         }
     }
+
+    /// The directories to walk on disk: all of `gen`, or just the requested cells within it.
+    /// Untracked paths found there are deleted, so this never covers buck-out's other per-cell
+    /// directories (`tmp`, `test_discovery`...), which hold state the materializer doesn't track.
+    fn scan_paths(
+        &self,
+        buck_out: &ProjectRelativePath,
+    ) -> buck2_error::Result<Vec<ProjectRelativePathBuf>> {
+        let gen_path = buck_out.join(FileName::unchecked_new("gen"));
+        if self.cells.is_empty() {
+            return Ok(vec![gen_path]);
+        }
+        self.cells
+            .iter()
+            .map(|cell| Ok(gen_path.join(FileName::new(cell)?)))
+            .collect()
+    }
+
+    fn includes_path(&self, buck_out: &ProjectRelativePath, path: &ProjectRelativePath) -> bool {
+        self.cells.is_empty()
+            || buck_out_path_owning_cell(buck_out, path)
+                .is_some_and(|cell| self.cells.iter().any(|c| c == cell.as_str()))
+    }
 }
 
 #[derive(Debug, Clone, buck2_error::Error)]
@@ -326,7 +421,7 @@ fn stats_for_paths(paths: &Vec<FoundPath>) -> buck2_data::CleanStaleStats {
                 stats.untracked_artifact_count += 1;
                 stats.untracked_bytes += *size;
             }
-            FoundPath::Stale(_, size) => {
+            FoundPath::Stale(_, size, _) => {
                 stats.stale_artifact_count += 1;
                 stats.stale_bytes += *size;
             }
@@ -334,21 +429,65 @@ fn stats_for_paths(paths: &Vec<FoundPath>) -> buck2_data::CleanStaleStats {
                 stats.retained_artifact_count += 1;
                 stats.retained_bytes += *size;
             }
+            FoundPath::Kept(_, size) => {
+                stats.kept_artifact_count += 1;
+                stats.kept_bytes += *size;
+            }
         }
     }
     stats
 }
 
+/// Pick the paths to clean in this run: untracked paths first, then stale artifacts from least to
+/// most recently accessed, stopping once either limit is reached. The artifact that reaches the
+/// byte limit is still cleaned, so that a single large artifact can't stall every run. Returns
+/// whether any paths were left for a later run.
+fn take_within_limits(
+    mut found_paths: Vec<FoundPath>,
+    max_bytes: Option<u64>,
+    max_files: Option<u64>,
+) -> (Vec<FoundPath>, bool) {
+    if max_bytes.is_none() && max_files.is_none() {
+        return (found_paths, false);
+    }
+
+    found_paths.retain(|x| !matches!(x, FoundPath::Retained(..) | FoundPath::Kept(..)));
+    found_paths.sort_by_key(|x| match x {
+        FoundPath::Stale(_, _, last_access_time) => Some(*last_access_time),
+        _ => None,
+    });
+
+    let mut bytes = 0;
+    let mut count = 0;
+    let limit_reached_at = found_paths.iter().position(|path| {
+        let limit_reached =
+            max_bytes.is_some_and(|max| bytes >= max) || max_files.is_some_and(|max| count >= max);
+        bytes += match path {
+            FoundPath::Untracked(_, _, size) | FoundPath::Stale(_, size, _) => *size,
+            FoundPath::Retained(..) | FoundPath::Kept(..) => 0,
+        };
+        count += 1;
+        limit_reached
+    });
+    match limit_reached_at {
+        Some(end) => {
+            found_paths.truncate(end);
+            (found_paths, true)
+        }
+        None => (found_paths, false),
+    }
+}
+
+/// Invalidates the stale paths, then deletes the found paths once the materializer is done
+/// processing them. The deletion is started from the command loop, so that paths declared again
+/// in the meantime are left alone.
 fn create_clean_fut<T: IoHandler>(
     found_paths: Vec<FoundPath>,
     mut stats: CleanStaleStats,
-    tree: &mut ArtifactTree,
-    sqlite_db: &mut MaterializerStateSqliteDb,
-    io: &Arc<T>,
-    cancellations: &'static CancellationContext,
+    processor: &mut DeferredMaterializerCommandProcessor<T>,
     liveliness_observer: Arc<dyn LivelinessObserverSync>,
 ) -> buck2_error::Result<BoxFuture<'static, buck2_error::Result<CleanResult>>> {
-    let io = io.dupe();
+    let start_time = Instant::now();
 
     let paths_to_invalidate: Vec<ProjectRelativePathBuf> = found_paths
         .iter()
@@ -358,45 +497,65 @@ fn create_clean_fut<T: IoHandler>(
         })
         .collect();
 
-    let existing_clean_futs =
-        tree.invalidate_paths_and_collect_futures(paths_to_invalidate, Some(sqlite_db))?;
-    let mut existing_materialization_futs = vec![];
-    for data in tree.iter_without_paths() {
-        match &data.processing {
-            super::Processing::Active {
-                future: super::ProcessingFuture::Materializing(future),
-                ..
-            } => existing_materialization_futs.push(future.clone()),
-            _ => (),
-        };
+    // Wait for all in-progress operations to finish on the paths we are about to remove from disk.
+    let mut existing_futs = processor
+        .tree
+        .invalidate_paths_and_collect_futures(paths_to_invalidate, processor.sqlite_db.as_mut())?;
+    // Untracked artifacts can be produced during materialization that should not be cleaned while materialization is in progress.
+    // Wait for all materializations since the path for the future may not be associated with the untracked path.
+    for (path, data) in processor.tree.iter_with_paths() {
+        if let super::Processing::Active {
+            future: future @ super::ProcessingFuture::Materializing(_),
+            ..
+        } = &data.processing
+        {
+            existing_futs.push((path.into(), future.clone()));
+        }
     }
 
-    let fut = async move {
-        let start_time = Instant::now();
-        // Wait for all in-progress operations to finish on the paths we are about to
-        // remove from disk.
-        join_all_existing_futs(existing_clean_futs).await?;
-        // Untracked artifacts can be produced during materialization that should not be cleaned while materialization is in progress.
-        // Wait for all materializations since the path for the future may not be associated with the untracked path.
-        for fut in existing_materialization_futs.into_iter() {
-            fut.await.ok();
-        }
+    let (sender, receiver) = oneshot::channel();
+    let io = processor.io.dupe();
+    let cancellations = processor.cancellations;
+    let deletion_liveliness_observer = liveliness_observer.dupe();
+    processor
+        .scheduling()
+        .on_completion(existing_futs, move |processor, waited| {
+            let deletion = waited.map(|()| {
+                // Artifacts declared at or over these paths since they were found aren't stale.
+                let found_paths: Vec<_> = found_paths
+                    .into_iter()
+                    .filter_map(|x| match x {
+                        FoundPath::Untracked(p, _, size) | FoundPath::Stale(p, size, _) => {
+                            Some((p, size))
+                        }
+                        FoundPath::Retained(..) | FoundPath::Kept(..) => None,
+                    })
+                    .filter(|(p, _)| processor.tree.get_path_entries(p).is_empty())
+                    .collect();
+                async move {
+                    // Then actually delete them. Note that we kick off one CleanOutputPaths per
+                    // path. We do this to get parallelism.
+                    buck2_util::future::try_join_all(found_paths.into_iter().map(|(path, size)| {
+                        clean_artifact(
+                            path,
+                            size,
+                            cancellations,
+                            &io,
+                            deletion_liveliness_observer.dupe(),
+                        )
+                    }))
+                    .await
+                }
+                .boxed()
+            });
+            let _ignored = sender.send(deletion);
+        });
 
-        // Then actually delete them. Note that we kick off one CleanOutputPaths per path. We
-        // do this to get parallelism.
-        let res = buck2_util::future::try_join_all(
-            found_paths
-                .into_iter()
-                .filter_map(|x| match x {
-                    FoundPath::Untracked(p, _, size) => Some((p, size)),
-                    FoundPath::Stale(p, size) => Some((p, size)),
-                    _ => None,
-                })
-                .map(|(path, size)| {
-                    clean_artifact(path, size, cancellations, &io, liveliness_observer.dupe())
-                }),
-        )
-        .await?;
+    let fut = async move {
+        let deletion = receiver
+            .await
+            .buck_error_context("Materializer shut down before cleaning")??;
+        let res = deletion.await?;
 
         let cleaned_sizes: Vec<u64> = res.iter().filter_map(|x| *x).collect();
         stats.cleaned_artifact_count += cleaned_sizes.len() as u64;
@@ -407,7 +566,11 @@ fn create_clean_fut<T: IoHandler>(
         } else {
             CleanStaleResultKind::Finished
         };
-        Ok(CleanResult { kind, stats })
+        Ok(CleanResult {
+            kind,
+            stats,
+            kept_paths: Vec::new(),
+        })
     };
     Ok(fut.boxed())
 }
@@ -472,7 +635,10 @@ pub fn get_size(path: &AbsNormPath) -> buck2_error::Result<u64> {
 struct StaleFinder<'a, T: IoHandler> {
     io: Arc<T>,
     keep_since_time: DateTime<Utc>,
+    keep_paths: &'a [ProjectRelativePathBuf],
     found_paths: &'a mut Vec<FoundPath>,
+    /// Size of the materialized artifacts seen, keyed by the cell that owns them.
+    bytes_by_cell: &'a mut HashMap<String, u64>,
     liveliness_observer: Arc<dyn LivelinessObserverSync>,
 }
 
@@ -481,8 +647,30 @@ enum FoundPath {
     /// These will be deleted on disk.
     Untracked(ProjectRelativePathBuf, FileType, u64),
     /// These will be invalidated in the materiaizer.
-    Stale(ProjectRelativePathBuf, u64),
+    Stale(ProjectRelativePathBuf, u64, DateTime<Utc>),
     Retained(u64),
+    /// Stale or untracked, but exempted by the keep-list.
+    Kept(ProjectRelativePathBuf, u64),
+}
+
+/// Whether the artifact at `path` is exempted from cleaning by `keep_paths`: it is under one of
+/// them, or contains one.
+fn is_kept(keep_paths: &[ProjectRelativePathBuf], path: &ProjectRelativePath) -> bool {
+    keep_paths
+        .iter()
+        .any(|keep| path.starts_with(keep) || keep.starts_with(path))
+}
+
+/// Add the size of the materialized artifact at `path` to the total of the cell that owns it.
+fn record_materialized(
+    bytes_by_cell: &mut HashMap<String, u64>,
+    buck_out: &ProjectRelativePath,
+    path: &ProjectRelativePath,
+    size: u64,
+) {
+    if let Some(cell) = buck_out_path_owning_cell(buck_out, path) {
+        *bytes_by_cell.entry(cell.as_str().to_owned()).or_insert(0) += size;
+    }
 }
 
 impl<T: IoHandler> StaleFinder<'_, T> {
@@ -538,13 +726,16 @@ impl<T: IoHandler> StaleFinder<'_, T> {
             let subtree = match subtree.get(file_name) {
                 Some(subtree) => subtree,
                 None => {
-                    // This path is not tracked by the materializer, we can delete it.
-                    tracing::trace!(path = %path, file_type = ?file_type, "marking as untracked");
-                    self.found_paths.push(FoundPath::Untracked(
-                        path,
-                        file_type,
-                        get_size(&child.path())?,
-                    ));
+                    let size = get_size(&child.path())?;
+                    if is_kept(self.keep_paths, &path) {
+                        tracing::trace!(path = %path, file_type = ?file_type, "marking as kept");
+                        self.found_paths.push(FoundPath::Kept(path, size));
+                    } else {
+                        // This path is not tracked by the materializer, we can delete it.
+                        tracing::trace!(path = %path, file_type = ?file_type, "marking as untracked");
+                        self.found_paths
+                            .push(FoundPath::Untracked(path, file_type, size));
+                    }
                     continue;
                 }
             };
@@ -561,17 +752,42 @@ impl<T: IoHandler> StaleFinder<'_, T> {
                             metadata,
                         },
                     ..
-                }) if *last_access_time < self.keep_since_time => {
-                    // This is something we can invalidate.
-                    tracing::trace!(path = %path, file_type = ?file_type, "marking as stale");
-                    self.found_paths
-                        .push(FoundPath::Stale(path, metadata.size()));
+                }) if *last_access_time < self.keep_since_time
+                    // Cleaning an empty directory frees nothing, and would only make the next
+                    // build declare it again.
+                    && !metadata.is_empty_dir(self.io.digest_config()) =>
+                {
+                    record_materialized(
+                        self.bytes_by_cell,
+                        self.io.buck_out_path(),
+                        &path,
+                        metadata.size(),
+                    );
+                    if is_kept(self.keep_paths, &path) {
+                        tracing::trace!(path = %path, file_type = ?file_type, "marking as kept");
+                        self.found_paths
+                            .push(FoundPath::Kept(path, metadata.size()));
+                    } else {
+                        // This is something we can invalidate.
+                        tracing::trace!(path = %path, file_type = ?file_type, "marking as stale");
+                        self.found_paths.push(FoundPath::Stale(
+                            path,
+                            metadata.size(),
+                            *last_access_time,
+                        ));
+                    }
                 }
                 ArtifactTree::Data(box ArtifactMaterializationData {
                     stage: ArtifactMaterializationStage::Materialized { metadata, .. },
                     ..
                 }) => {
                     tracing::trace!(path = %path, file_type = ?file_type, "marking as retained");
+                    record_materialized(
+                        self.bytes_by_cell,
+                        self.io.buck_out_path(),
+                        &path,
+                        metadata.size(),
+                    );
                     self.found_paths.push(FoundPath::Retained(metadata.size()));
                 }
                 _ => {
@@ -589,22 +805,38 @@ impl<T: IoHandler> StaleFinder<'_, T> {
 fn find_stale_tracked_only(
     tree: &ArtifactTree,
     keep_since_time: DateTime<Utc>,
+    keep_paths: &[ProjectRelativePathBuf],
+    buck_out: &ProjectRelativePath,
+    digest_config: DigestConfig,
+    include_path: impl Fn(&ProjectRelativePath) -> bool,
     found_paths: &mut Vec<FoundPath>,
+    bytes_by_cell: &mut HashMap<String, u64>,
 ) -> buck2_error::Result<()> {
     for (f_path, v) in tree.iter_with_paths() {
         if let ArtifactMaterializationStage::Materialized {
             last_access_time,
             active,
-            ..
+            metadata,
         } = &v.stage
         {
             let path = ProjectRelativePathBuf::from(f_path);
-            if *last_access_time < keep_since_time && !active {
+            if !include_path(&path) {
+                continue;
+            }
+            let size = metadata.size();
+            record_materialized(bytes_by_cell, buck_out, &path, size);
+            let stale = *last_access_time < keep_since_time
+                && !active
+                && !metadata.is_empty_dir(digest_config);
+            if stale && is_kept(keep_paths, &path) {
+                tracing::trace!(path = %path, "kept artifact");
+                found_paths.push(FoundPath::Kept(path, size));
+            } else if stale {
                 tracing::trace!(path = %path, "stale artifact");
-                found_paths.push(FoundPath::Stale(path, 0));
+                found_paths.push(FoundPath::Stale(path, size, *last_access_time));
             } else {
                 tracing::trace!(path = %path, "retaining artifact");
-                found_paths.push(FoundPath::Retained(0));
+                found_paths.push(FoundPath::Retained(size));
             }
         }
     }
@@ -617,6 +849,11 @@ pub struct CleanStaleConfig {
     pub clean_period: std::time::Duration,
     pub artifact_ttl: std::time::Duration,
     pub dry_run: bool,
+    /// See `CleanStaleArtifactsCommand::max_bytes_per_run`.
+    pub max_bytes_per_run: Option<u64>,
+    pub max_files_per_run: Option<u64>,
+    /// See `CleanStaleArtifactsCommand::keep_paths`.
+    pub keep_paths: Vec<ProjectRelativePathBuf>,
 }
 
 impl CleanStaleConfig {
@@ -651,6 +888,23 @@ impl CleanStaleConfig {
                 property: "clean_stale_dry_run",
             })?
             .unwrap_or(false);
+        let clean_stale_max_bytes_per_run = root_config.parse(BuckconfigKeyRef {
+            section: "buck2",
+            property: "clean_stale_max_bytes_per_run",
+        })?;
+        let clean_stale_max_files_per_run = root_config.parse(BuckconfigKeyRef {
+            section: "buck2",
+            property: "clean_stale_max_files_per_run",
+        })?;
+        let clean_stale_keep_paths = root_config
+            .parse_list::<String>(BuckconfigKeyRef {
+                section: "buck2",
+                property: "clean_stale_keep_paths",
+            })?
+            .unwrap_or_default()
+            .into_iter()
+            .map(ProjectRelativePathBuf::try_from)
+            .collect::<buck2_error::Result<Vec<_>>>()?;
 
         let secs_in_hour = 60.0 * 60.0;
         let clean_stale_config = if clean_stale_enabled {
@@ -665,6 +919,9 @@ impl CleanStaleConfig {
                     secs_in_hour * clean_stale_start_offset_hours,
                 ),
                 dry_run: clean_stale_dry_run,
+                max_bytes_per_run: clean_stale_max_bytes_per_run,
+                max_files_per_run: clean_stale_max_files_per_run,
+                keep_paths: clean_stale_keep_paths,
             })
         } else {
             None
@@ -0,0 +1,411 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Guards against jumps of the wall clock.
+//!
+//! Access times, and the cutoffs cleans compare them to, come from the wall clock. That clock jumps
+//! when it is corrected or misconfigured: access times recorded while it was ahead look recent
+//! forever, and once it jumps ahead, every artifact looks stale. The latest time the materializer
+//! recorded is persisted in sqlite, so that backward jumps are noticed across daemon restarts too.
+//! Forward jumps are only noticed while the daemon runs: at startup, a gap since the latest
+//! recorded time can't be told apart from the daemon having been idle. While a jump is suspected,
+//! cleans that would delete most tracked artifacts are refused. The restarted daemon trusts the
+//! current time again.
+
+use buck2_common::legacy_configs::configs::LegacyBuckConfig;
+use buck2_common::legacy_configs::key::BuckconfigKeyRef;
+use buck2_core::fs::project_rel_path::ProjectRelativePathBuf;
+use buck2_core::soft_error;
+use chrono::DateTime;
+use chrono::Duration;
+use chrono::Utc;
+use derive_more::Display;
+
+use crate::materializers::sqlite::MaterializerState;
+use crate::materializers::sqlite::MaterializerStateSqliteDb;
+
+#[derive(Clone, Copy, Debug)]
+pub struct ClockSkewConfig {
+    /// Access times more than this far ahead of the current time are clamped to it, and taken as
+    /// a sign that the clock jumped backwards.
+    pub backward_tolerance: Duration,
+    /// A current time more than this far ahead of the latest recorded time is taken as a sign that
+    /// the clock jumped forwards. Not checked at startup.
+    pub forward_tolerance: Duration,
+    /// While a clock jump is suspected, cleans that would delete more than this fraction of the
+    /// tracked artifacts are refused.
+    pub max_clean_fraction: f64,
+}
+
+impl Default for ClockSkewConfig {
+    fn default() -> Self {
+        Self {
+            backward_tolerance: Duration::hours(1),
+            forward_tolerance: Duration::days(30),
+            max_clean_fraction: 0.5,
+        }
+    }
+}
+
+impl ClockSkewConfig {
+    pub fn from_buck_config(root_config: &LegacyBuckConfig) -> buck2_error::Result<Self> {
+        let default = Self::default();
+        let hours = |property: &str, default: Duration| -> buck2_error::Result<Duration> {
+            let hours: Option<f64> = root_config.parse(BuckconfigKeyRef {
+                section: "buck2",
+                property,
+            })?;
+            Ok(hours.map_or(default, |hours| {
+                Duration::milliseconds((hours * 60.0 * 60.0 * 1000.0) as i64)
+            }))
+        };
+        Ok(Self {
+            backward_tolerance: hours(
+                "materializer_clock_skew_backward_tolerance_hours",
+                default.backward_tolerance,
+            )?,
+            forward_tolerance: hours(
+                "materializer_clock_skew_forward_tolerance_hours",
+                default.forward_tolerance,
+            )?,
+            max_clean_fraction: root_config
+                .parse(BuckconfigKeyRef {
+                    section: "buck2",
+                    property: "clean_stale_max_fraction_on_clock_skew",
+                })?
+                .unwrap_or(default.max_clean_fraction),
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Display, PartialEq, Eq)]
+pub(crate) enum ClockJump {
+    #[display("backwards")]
+    Backwards,
+    #[display("forwards")]
+    Forwards,
+}
+
+#[derive(buck2_error::Error, Debug)]
+#[buck2(tag = Environment)]
+enum ClockSkewError {
+    #[error(
+        "{} materializer access times are ahead of the current time {} (latest: {}), the system clock likely jumped backwards. Treating them as accessed now",
+        .count,
+        .now,
+        .latest
+    )]
+    AccessTimesInFuture {
+        count: usize,
+        latest: DateTime<Utc>,
+        now: DateTime<Utc>,
+    },
+    #[error(
+        "The system clock jumped {} from {} to {}",
+        .jump,
+        .max_recorded,
+        .now
+    )]
+    Jumped {
+        jump: ClockJump,
+        max_recorded: DateTime<Utc>,
+        now: DateTime<Utc>,
+    },
+}
+
+#[derive(buck2_error::Error, Debug)]
+#[error(
+    "Refusing to clean {} of {} tracked artifacts because the system clock likely jumped {}. If the clock is correct now, restart the daemon with `buck2 kill` to clean",
+    .stale,
+    .tracked,
+    .jump
+)]
+#[buck2(tag = CleanStale)]
+pub(crate) struct CleanRefusedError {
+    jump: ClockJump,
+    stale: u64,
+    tracked: u64,
+}
+
+/// Tracks the latest time the materializer recorded, and whether the clock jumped since.
+pub(crate) struct ClockSkewGuard {
+    config: ClockSkewConfig,
+    max_recorded: Option<DateTime<Utc>>,
+    /// The `max_recorded` last written to sqlite.
+    persisted: Option<DateTime<Utc>>,
+    suspected_jump: Option<ClockJump>,
+}
+
+impl ClockSkewGuard {
+    pub(crate) fn new(config: ClockSkewConfig, persisted: Option<DateTime<Utc>>) -> Self {
+        Self {
+            config,
+            max_recorded: persisted,
+            persisted,
+            suspected_jump: None,
+        }
+    }
+
+    /// Creates the guard for a materializer starting at `now` with `state` loaded from
+    /// `sqlite_db`. Access times in `state` that are ahead of `now` are clamped, in sqlite too.
+    pub(crate) fn load(
+        config: ClockSkewConfig,
+        sqlite_db: Option<&mut MaterializerStateSqliteDb>,
+        state: Option<&mut MaterializerState>,
+        now: DateTime<Utc>,
+    ) -> Self {
+        let Some(sqlite_db) = sqlite_db else {
+            return Self::new(config, None);
+        };
+        let persisted = sqlite_db.read_max_access_time().unwrap_or_else(|e| {
+            tracing::warn!("Error reading the latest materializer access time: {:#}", e);
+            None
+        });
+        let mut guard = Self::new(config, persisted);
+
+        if let Some(state) = state {
+            let clamped = guard.clamp_loaded_state(state, now);
+            if !clamped.is_empty() {
+                if let Err(e) = sqlite_db
+                    .materializer_state_table()
+                    .update_access_times(clamped.iter().collect())
+                {
+                    tracing::warn!("Error clamping materializer access times: {:#}", e);
+                }
+            }
+        }
+
+        if let Some(max_recorded) = guard.observe_startup(now) {
+            if let Err(e) = sqlite_db.write_max_access_time(max_recorded) {
+                tracing::warn!("Error writing the latest materializer access time: {:#}", e);
+            }
+        }
+        guard
+    }
+
+    /// Clamps access times that are ahead of `now` to it. Returns the paths that were clamped.
+    fn clamp_loaded_state(
+        &mut self,
+        state: &mut MaterializerState,
+        now: DateTime<Utc>,
+    ) -> Vec<ProjectRelativePathBuf> {
+        let limit = now + self.config.backward_tolerance;
+        let mut clamped = Vec::new();
+        let mut latest = now;
+        for (path, (_, last_access_time)) in state.iter_mut() {
+            if *last_access_time > limit {
+                latest = latest.max(*last_access_time);
+                *last_access_time = now;
+                clamped.push(path.clone());
+            }
+        }
+        if !clamped.is_empty() {
+            self.flag(
+                ClockJump::Backwards,
+                ClockSkewError::AccessTimesInFuture {
+                    count: clamped.len(),
+                    latest,
+                    now,
+                },
+            );
+        }
+        clamped
+    }
+
+    /// Like `observe`, but for the time the materializer starts at. The daemon may have been idle
+    /// for a long time, so only backward jumps are flagged.
+    fn observe_startup(&mut self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        if let Some(max_recorded) = &mut self.max_recorded {
+            *max_recorded = (*max_recorded).max(now);
+        }
+        self.observe(now)
+    }
+
+    /// Records that the clock reads `now`, flagging a jump if it is too far from the latest
+    /// recorded time. Returns the latest recorded time if it should be persisted.
+    pub(crate) fn observe(&mut self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let max_recorded = match self.max_recorded {
+            Some(max_recorded) if now > max_recorded + self.config.forward_tolerance => {
+                self.flag(
+                    ClockJump::Forwards,
+                    ClockSkewError::Jumped {
+                        jump: ClockJump::Forwards,
+                        max_recorded,
+                        now,
+                    },
+                );
+                now
+            }
+            Some(max_recorded) if now + self.config.backward_tolerance < max_recorded => {
+                self.flag(
+                    ClockJump::Backwards,
+                    ClockSkewError::Jumped {
+                        jump: ClockJump::Backwards,
+                        max_recorded,
+                        now,
+                    },
+                );
+                // Start over from the current time, so that the jump is only flagged once.
+                now
+            }
+            Some(max_recorded) => max_recorded.max(now),
+            None => now,
+        };
+        self.max_recorded = Some(max_recorded);
+
+        // Only persist once the time moved a bit, not on every tick.
+        let should_persist = match self.persisted {
+            Some(persisted) => (max_recorded - persisted).abs() >= Duration::minutes(1),
+            None => true,
+        };
+        if should_persist {
+            self.persisted = Some(max_recorded);
+            Some(max_recorded)
+        } else {
+            None
+        }
+    }
+
+    fn flag(&mut self, jump: ClockJump, error: ClockSkewError) {
+        if self.suspected_jump == Some(jump) {
+            return;
+        }
+        tracing::warn!("{}", error);
+        let _ignored = soft_error!("materializer_clock_jump", error.into(), quiet: true);
+        self.suspected_jump = Some(jump);
+    }
+
+    pub(crate) fn suspected_jump(&self) -> Option<ClockJump> {
+        self.suspected_jump
+    }
+
+    /// Returns an error if a clean that found `stale` and `retained` tracked artifacts should be
+    /// refused because of a suspected clock jump.
+    pub(crate) fn check_clean(&self, stale: u64, retained: u64) -> Option<CleanRefusedError> {
+        let jump = self.suspected_jump?;
+        let tracked = stale + retained;
+        if tracked > 0 && stale as f64 > self.config.max_clean_fraction * tracked as f64 {
+            Some(CleanRefusedError {
+                jump,
+                stale,
+                tracked,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use buck2_directory::directory::entry::DirectoryEntry;
+    use buck2_execute::directory::new_symlink;
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::materializers::deferred::artifact_tree::ArtifactMetadata;
+
+    fn now() -> DateTime<Utc> {
+        Utc.timestamp_opt(1_700_000_000, 0).unwrap()
+    }
+
+    fn state(access_times: &[(&str, DateTime<Utc>)]) -> MaterializerState {
+        let metadata = ArtifactMetadata(DirectoryEntry::Leaf(new_symlink("target").unwrap()));
+        access_times
+            .iter()
+            .map(|(path, time)| {
+                (
+                    ProjectRelativePathBuf::unchecked_new((*path).to_owned()),
+                    (metadata.clone(), *time),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_clamp_future_access_times() {
+        let now = now();
+        let mut guard = ClockSkewGuard::new(ClockSkewConfig::default(), Some(now));
+        let mut state = state(&[
+            ("old", now - Duration::days(3)),
+            ("slightly_ahead", now + Duration::minutes(5)),
+            ("future", now + Duration::days(365)),
+        ]);
+
+        let clamped = guard.clamp_loaded_state(&mut state, now);
+
+        assert_eq!(
+            clamped,
+            vec![ProjectRelativePathBuf::unchecked_new("future".to_owned())]
+        );
+        assert_eq!(
+            state.iter().map(|(_, (_, time))| *time).collect::<Vec<_>>(),
+            vec![now - Duration::days(3), now + Duration::minutes(5), now]
+        );
+        assert_eq!(guard.suspected_jump(), Some(ClockJump::Backwards));
+    }
+
+    #[test]
+    fn test_observe() {
+        let now = now();
+        let mut guard = ClockSkewGuard::new(ClockSkewConfig::default(), None);
+        assert_eq!(guard.observe(now), Some(now));
+        // Small steps aren't persisted.
+        assert_eq!(guard.observe(now + Duration::seconds(5)), None);
+        assert_eq!(
+            guard.observe(now + Duration::minutes(2)),
+            Some(now + Duration::minutes(2))
+        );
+        // Within the tolerance, e.g. an NTP correction.
+        assert_eq!(guard.observe(now + Duration::minutes(1)), None);
+        assert_eq!(guard.suspected_jump(), None);
+
+        let later = now + Duration::days(400);
+        assert_eq!(guard.observe(later), Some(later));
+        assert_eq!(guard.suspected_jump(), Some(ClockJump::Forwards));
+
+        let mut guard = ClockSkewGuard::new(ClockSkewConfig::default(), Some(now));
+        let earlier = now - Duration::days(1);
+        assert_eq!(guard.observe(earlier), Some(earlier));
+        assert_eq!(guard.suspected_jump(), Some(ClockJump::Backwards));
+    }
+
+    #[test]
+    fn test_observe_startup() {
+        let now = now();
+        // The daemon was idle for longer than the forward tolerance.
+        let mut guard = ClockSkewGuard::new(ClockSkewConfig::default(), Some(now));
+        let later = now + Duration::days(400);
+        assert_eq!(guard.observe_startup(later), Some(later));
+        assert_eq!(guard.suspected_jump(), None);
+
+        let mut guard = ClockSkewGuard::new(ClockSkewConfig::default(), Some(now));
+        let earlier = now - Duration::days(1);
+        assert_eq!(guard.observe_startup(earlier), Some(earlier));
+        assert_eq!(guard.suspected_jump(), Some(ClockJump::Backwards));
+    }
+
+    #[test]
+    fn test_check_clean() {
+        let mut guard = ClockSkewGuard::new(ClockSkewConfig::default(), Some(now()));
+        assert!(guard.check_clean(10, 0).is_none());
+
+        guard.observe(now() + Duration::days(400));
+        assert!(guard.check_clean(0, 0).is_none());
+        assert!(guard.check_clean(5, 5).is_none());
+        let error = guard.check_clean(6, 4).unwrap();
+        assert!(
+            error
+                .to_string()
+                .contains("Refusing to clean 6 of 10 tracked artifacts"),
+            "{}",
+            error
+        );
+    }
+}
@@ -7,6 +7,7 @@
  * of this source tree.
  */
 
+use std::collections::HashMap;
 use std::collections::HashSet;
 use std::collections::VecDeque;
 use std::pin::Pin;
@@ -19,17 +20,23 @@ use buck2_core::buck2_env;
 use buck2_core::fs::project_rel_path::ProjectRelativePath;
 use buck2_core::fs::project_rel_path::ProjectRelativePathBuf;
 use buck2_core::soft_error;
+use buck2_core::tag_error;
 use buck2_data::error::ErrorTag;
 use buck2_error::BuckErrorContext;
 use buck2_error::buck2_error;
 use buck2_events::dispatch::EventDispatcher;
+use buck2_events::dispatch::current_span;
 use buck2_events::dispatch::get_dispatcher_opt;
 use buck2_events::dispatch::with_dispatcher_async;
 use buck2_events::span::SpanId;
 use buck2_execute::artifact_value::ArtifactValue;
+use buck2_execute::directory::ActionDirectoryEntry;
 use buck2_execute::directory::ActionSharedDirectory;
+use buck2_execute::materialize::manifest::Manifest;
 use buck2_execute::materialize::materializer::ArtifactNotMaterializedReason;
+use buck2_execute::materialize::materializer::CasDownloadInfo;
 use buck2_execute::materialize::materializer::MaterializationError;
+use buck2_execute::output_size::OutputSize;
 use buck2_futures::cancellation::CancellationContext;
 use buck2_util::threads::check_stack_overflow;
 use buck2_wrapper_common::invocation_id::TraceId;
@@ -42,6 +49,7 @@ use futures::future;
 use futures::future::BoxFuture;
 use futures::future::Either;
 use futures::future::FutureExt;
+use futures::future::Shared;
 use futures::future::TryFutureExt;
 use futures::stream::BoxStream;
 use futures::stream::FuturesOrdered;
@@ -51,6 +59,7 @@ use gazebo::prelude::*;
 use itertools::Itertools;
 use pin_project::pin_project;
 use tokio::runtime::Handle;
+use tokio::sync::mpsc::Receiver;
 use tokio::sync::mpsc::UnboundedReceiver;
 use tokio::sync::oneshot;
 use tokio::sync::oneshot::error::TryRecvError;
@@ -60,12 +69,14 @@ use tokio::time::Interval;
 use tracing::instrument;
 
 use crate::materializers::deferred::AccessTimesUpdates;
+use crate::materializers::deferred::ArtifactState;
+use crate::materializers::deferred::ArtifactTreeSweepConfiguration;
 use crate::materializers::deferred::DeferredMaterializerStats;
-use crate::materializers::deferred::MaterializeEntryError;
 use crate::materializers::deferred::MaterializerReceiver;
 use crate::materializers::deferred::MaterializerSender;
 use crate::materializers::deferred::SharedMaterializingError;
 use crate::materializers::deferred::TtlRefreshConfiguration;
+use crate::materializers::deferred::TtlRefreshHistory;
 use crate::materializers::deferred::TtlRefreshHistoryEntry;
 use crate::materializers::deferred::artifact_tree::ArtifactMaterializationData;
 use crate::materializers::deferred::artifact_tree::ArtifactMaterializationMethod;
@@ -73,6 +84,7 @@ use crate::materializers::deferred::artifact_tree::ArtifactMaterializationStage;
 use crate::materializers::deferred::artifact_tree::ArtifactMetadata;
 use crate::materializers::deferred::artifact_tree::ArtifactTree;
 use crate::materializers::deferred::artifact_tree::CleaningFuture;
+use crate::materializers::deferred::artifact_tree::DeclareOwner;
 use crate::materializers::deferred::artifact_tree::MaterializingFuture;
 use crate::materializers::deferred::artifact_tree::Processing;
 use crate::materializers::deferred::artifact_tree::ProcessingFuture;
@@ -80,10 +92,16 @@ use crate::materializers::deferred::artifact_tree::Version;
 use crate::materializers::deferred::clean_stale::CleanResult;
 use crate::materializers::deferred::clean_stale::CleanStaleArtifactsCommand;
 use crate::materializers::deferred::clean_stale::CleanStaleConfig;
+use crate::materializers::deferred::clock_skew::ClockSkewGuard;
 use crate::materializers::deferred::extension::ExtensionCommand;
+use crate::materializers::deferred::file_tree::FileTree;
 use crate::materializers::deferred::io_handler::IoHandler;
+use crate::materializers::deferred::io_pressure::MaterializerIoPressure;
 use crate::materializers::deferred::join_all_existing_futs;
 use crate::materializers::deferred::materialize_stack::MaterializeStack;
+use crate::materializers::deferred::scheduling::CompletionCallbacks;
+use crate::materializers::deferred::scheduling::CompletionToken;
+use crate::materializers::deferred::scheduling::MaterializerSchedulingContext;
 use crate::materializers::deferred::subscriptions::MaterializerSubscriptionOperation;
 use crate::materializers::deferred::subscriptions::MaterializerSubscriptions;
 use crate::materializers::sqlite::MaterializerStateSqliteDb;
@@ -103,19 +121,103 @@ pub(super) struct DeferredMaterializerCommandProcessor<T: 'static> {
     pub(super) command_sender: Arc<MaterializerSender<T>>,
     /// The actual materializer state.
     pub(super) tree: ArtifactTree,
+    /// Declared artifacts forgotten by `sweep_artifact_tree` that haven't been declared again.
+    /// DICE still caches the actions that produced them, so ensuring them is an error.
+    pub(super) pruned: FileTree<()>,
     /// Active subscriptions
     pub(super) subscriptions: MaterializerSubscriptions,
-    /// History of refreshes. This *does* grow without bound, but considering the data is pretty
-    /// small and we create it infrequently, that's fine.
-    pub(super) ttl_refresh_history: Vec<TtlRefreshHistoryEntry>,
+    /// History of the most recent refreshes.
+    pub(super) ttl_refresh_history: TtlRefreshHistory,
     /// The current ttl_refresh instance, if any exists.
     ttl_refresh_instance: Option<oneshot::Receiver<(DateTime<Utc>, buck2_error::Result<()>)>>,
     pub(super) cancellations: &'static CancellationContext,
-    stats: Arc<DeferredMaterializerStats>,
-    access_times_buffer: Option<HashSet<ProjectRelativePathBuf>>,
+    pub(super) stats: Arc<DeferredMaterializerStats>,
+    /// Limits concurrent local copies and writes, and tracks the IO pressure they cause. `None`
+    /// unless enabled by `buck2.materializer_io_pressure`.
+    pub(super) io_pressure: Option<Arc<MaterializerIoPressure>>,
+    pub(super) access_times_buffer: Option<HashSet<ProjectRelativePathBuf>>,
+    /// The access times flush currently writing to sqlite, if any.
+    pub(super) access_times_flush: Option<AccessTimesFlush>,
     verbose_materializer_log: bool,
     daemon_dispatcher: EventDispatcher,
     disable_eager_write_dispatch: bool,
+    /// Whether matching a materialized artifact also checks what is on disk against its digests.
+    pub(super) paranoid_verification: bool,
+    /// Callbacks registered with `MaterializerSchedulingContext::on_completion`, by token.
+    pub(super) completion_callbacks: CompletionCallbacks<T>,
+    /// Detects jumps of the wall clock that access times and clean cutoffs come from.
+    pub(super) clock_skew: ClockSkewGuard,
+}
+
+#[derive(buck2_error::Error, Debug)]
+#[error(
+    "Artifact `{0}` was forgotten by the materializer after being idle for longer than `buck2.declared_artifact_idle_ttl_hours`, and the action that produced it is still cached. Restart the daemon with `buck2 kill` to rebuild it"
+)]
+#[buck2(tag = MaterializationError)]
+struct PrunedArtifactError(ProjectRelativePathBuf);
+
+/// A flush of the access times buffer, running on a blocking thread so that the command loop
+/// isn't stalled by the sqlite write.
+pub(super) struct AccessTimesFlush {
+    /// Accesses to these paths aren't buffered again while the flush is running, since it will
+    /// record a later access time for them anyway.
+    pub(super) paths: Arc<HashSet<ProjectRelativePathBuf>>,
+    /// Sent a description of the outcome once the flush finishes.
+    on_finished: Option<oneshot::Sender<String>>,
+    /// Forced flushes requested while this one was running. They start once it finishes, so that
+    /// they also write accesses that were buffered in the meantime.
+    queued: Vec<oneshot::Sender<String>>,
+}
+
+/// Where a declare comes from.
+pub(super) struct DeclareContext {
+    pub(super) event_dispatcher: EventDispatcher,
+    pub(super) owner: Arc<DeclareOwner>,
+    /// Receives the outcome of the declare once it was processed. Only set when conflicting
+    /// declares fail, otherwise declares don't wait for the command loop.
+    pub(super) result_sender: Option<oneshot::Sender<buck2_error::Result<()>>>,
+}
+
+impl DeclareContext {
+    /// A context for declaring from the current span. Also returns the receiver of the outcome if
+    /// `fail_on_conflict` is set.
+    pub(super) fn new(
+        event_dispatcher: EventDispatcher,
+        fail_on_conflict: bool,
+    ) -> (Self, Option<oneshot::Receiver<buck2_error::Result<()>>>) {
+        let owner = Arc::new(DeclareOwner {
+            trace_id: event_dispatcher.trace_id().dupe(),
+            span_id: current_span(),
+        });
+        let (result_sender, result_receiver) = if fail_on_conflict {
+            let (sender, receiver) = oneshot::channel();
+            (Some(sender), Some(receiver))
+        } else {
+            (None, None)
+        };
+        (
+            Self {
+                event_dispatcher,
+                owner,
+                result_sender,
+            },
+            result_receiver,
+        )
+    }
+
+    fn fail_on_conflict(&self) -> bool {
+        self.result_sender.is_some()
+    }
+
+    /// Sends the outcome of the declare if it's awaited, and returns whether it succeeded.
+    fn reply(&mut self, result: buck2_error::Result<()>) -> bool {
+        let ok = result.is_ok();
+        if let Some(sender) = self.result_sender.take() {
+            // The declaring action may have been cancelled meanwhile.
+            let _ignored = sender.send(result);
+        }
+        ok
+    }
 }
 
 /// Message taken by the `DeferredMaterializer`'s command loop.
@@ -141,7 +243,15 @@ pub(super) enum MaterializerCommand<T: 'static> {
         ProjectRelativePathBuf,
         ArtifactValue,
         Box<ArtifactMaterializationMethod>, // Boxed to avoid growing all variants
-        EventDispatcher,
+        DeclareContext,
+    ),
+
+    /// Declares artifacts that are all downloaded from the CAS using the same `CasDownloadInfo`.
+    /// Sending them together lets them be materialized with a single CAS download.
+    DeclareMany(
+        Vec<(ProjectRelativePathBuf, ArtifactValue)>,
+        Arc<CasDownloadInfo>,
+        DeclareContext,
     ),
 
     MatchArtifacts(
@@ -151,6 +261,23 @@ pub(super) enum MaterializerCommand<T: 'static> {
 
     HasArtifact(ProjectRelativePathBuf, oneshot::Sender<bool>),
 
+    /// Like `HasArtifact`, answered for each of the paths in order.
+    HasArtifacts(Vec<ProjectRelativePathBuf>, oneshot::Sender<Vec<bool>>),
+
+    /// Describes the artifact at (or containing) the given path, if any.
+    /// See `DeferredMaterializerAccessor::artifact_state` for more information.
+    GetArtifactState(
+        ProjectRelativePathBuf,
+        oneshot::Sender<Option<ArtifactState>>,
+    ),
+
+    /// Describes the materialized artifacts at the given paths.
+    /// See `Materializer::generate_manifest` for more information.
+    GenerateManifest(
+        Vec<ProjectRelativePathBuf>,
+        oneshot::Sender<buck2_error::Result<Manifest>>,
+    ),
+
     /// Declares that given paths are no longer eligible to be materialized by this materializer.
     /// This typically should reflect a change made to the underlying filesystem, either because
     /// the file was created, or because it was removed..
@@ -160,6 +287,13 @@ pub(super) enum MaterializerCommand<T: 'static> {
         EventDispatcher,
     ),
 
+    /// Reports what `InvalidateFilePaths` would remove for the given paths, without removing it.
+    /// See `DeferredMaterializerExtensions::preview_invalidate` for more information.
+    PreviewInvalidate(
+        Vec<ProjectRelativePathBuf>,
+        oneshot::Sender<Vec<(ProjectRelativePathBuf, &'static str)>>,
+    ),
+
     /// Takes a list of artifact paths, and materializes all artifacts in the
     /// list that have been declared but not yet been materialized. When the
     /// materialization starts, a future is sent back through the provided
@@ -175,6 +309,10 @@ pub(super) enum MaterializerCommand<T: 'static> {
 
     Extension(Box<dyn ExtensionCommand<T>>),
 
+    /// A low priority command from a thread that can't wait for room in the bounded queue.
+    /// See `MaterializerSender::blocking_send_low_priority`.
+    LowPriority(LowPriorityMaterializerCommand),
+
     /// Terminate command processor loop, used by tests
     #[allow(dead_code)]
     Abort,
@@ -193,21 +331,37 @@ impl<T> std::fmt::Debug for MaterializerCommand<T> {
                     paths, current_span, trace_id
                 )
             }
-            MaterializerCommand::Declare(path, value, method, _dispatcher) => {
+            MaterializerCommand::Declare(path, value, method, _context) => {
                 write!(f, "Declare({:?}, {:?}, {:?})", path, value, method,)
             }
+            MaterializerCommand::DeclareMany(artifacts, info, _context) => {
+                write!(f, "DeclareMany({:?}, {:?})", artifacts, info)
+            }
             MaterializerCommand::MatchArtifacts(paths, _) => {
                 write!(f, "MatchArtifacts({:?})", paths)
             }
             MaterializerCommand::HasArtifact(path, _) => {
                 write!(f, "HasArtifact({:?})", path)
             }
+            MaterializerCommand::HasArtifacts(paths, _) => {
+                write!(f, "HasArtifacts({:?})", paths)
+            }
+            MaterializerCommand::GetArtifactState(path, _) => {
+                write!(f, "GetArtifactState({:?})", path)
+            }
+            MaterializerCommand::GenerateManifest(paths, _) => {
+                write!(f, "GenerateManifest({:?})", paths)
+            }
             MaterializerCommand::InvalidateFilePaths(paths, ..) => {
                 write!(f, "InvalidateFilePaths({:?})", paths)
             }
+            MaterializerCommand::PreviewInvalidate(paths, _) => {
+                write!(f, "PreviewInvalidate({:?})", paths)
+            }
             MaterializerCommand::Ensure(paths, _, _) => write!(f, "Ensure({:?}, _)", paths,),
             MaterializerCommand::Subscription(op) => write!(f, "Subscription({:?})", op,),
             MaterializerCommand::Extension(ext) => write!(f, "Extension({:?})", ext),
+            MaterializerCommand::LowPriority(command) => write!(f, "LowPriority({:?})", command),
             MaterializerCommand::Abort => write!(f, "Abort"),
         }
     }
@@ -224,6 +378,8 @@ pub(super) enum LowPriorityMaterializerCommand {
     MaterializationFinished {
         path: ProjectRelativePathBuf,
         timestamp: DateTime<Utc>,
+        /// When materialization started, for its latency, which shouldn't jump with the clock.
+        started: std::time::Instant,
         version: Version,
         result: Result<(), SharedMaterializingError>,
     },
@@ -233,6 +389,28 @@ pub(super) enum LowPriorityMaterializerCommand {
         version: Version,
         result: Result<(), SharedMaterializingError>,
     },
+
+    /// Notifies the command thread that the access times flush in progress finished.
+    AccessTimesFlushed {
+        size: usize,
+        elapsed: std::time::Duration,
+        result: buck2_error::Result<()>,
+    },
+
+    /// The futures a completion callback waits on finished.
+    /// See `MaterializerSchedulingContext::on_completion`.
+    CompletionReady {
+        token: CompletionToken,
+        result: buck2_error::Result<()>,
+    },
+
+    /// Paranoid verification of an artifact matched at `version` finished. See
+    /// `DeferredMaterializerConfigs::paranoid_verification`.
+    VerificationFinished {
+        path: ProjectRelativePathBuf,
+        version: Version,
+        result: buck2_error::Result<bool>,
+    },
 }
 
 #[derive(Debug)]
@@ -291,10 +469,11 @@ impl std::fmt::Display for LogBuffer {
 #[pin_project]
 struct CommandStream<T: 'static> {
     high_priority: UnboundedReceiver<MaterializerCommand<T>>,
-    low_priority: UnboundedReceiver<LowPriorityMaterializerCommand>,
+    low_priority: Receiver<LowPriorityMaterializerCommand>,
     refresh_ttl_ticker: Option<Interval>,
     io_buffer_ticker: Interval,
     clean_stale_ticker: Option<Interval>,
+    artifact_tree_sweep_ticker: Interval,
     clean_stale_fut: Option<BoxFuture<'static, buck2_error::Result<CleanResult>>>,
 }
 
@@ -304,6 +483,7 @@ enum Op<T: 'static> {
     RefreshTtls,
     Tick,
     CleanStaleRequest,
+    SweepArtifactTree,
 }
 
 impl<T: 'static> Stream for CommandStream<T> {
@@ -344,6 +524,10 @@ impl<T: 'static> Stream for CommandStream<T> {
             }
         }
 
+        if this.artifact_tree_sweep_ticker.poll_tick(cx).is_ready() {
+            return Poll::Ready(Some(Op::SweepArtifactTree));
+        }
+
         // We can never be done because we never drop the senders, so let's not bother.
         Poll::Pending
     }
@@ -356,17 +540,21 @@ impl<T: IoHandler> DeferredMaterializerCommandProcessor<T> {
         rt: Handle,
         defer_write_actions: bool,
         log_buffer: LogBuffer,
+        ttl_refresh_history_size: usize,
         command_sender: Arc<MaterializerSender<T>>,
         tree: ArtifactTree,
         cancellations: &'static CancellationContext,
         stats: Arc<DeferredMaterializerStats>,
+        io_pressure: Option<Arc<MaterializerIoPressure>>,
         access_times_buffer: Option<HashSet<ProjectRelativePathBuf>>,
         verbose_materializer_log: bool,
         daemon_dispatcher: EventDispatcher,
         disable_eager_write_dispatch: bool,
+        paranoid_verification: bool,
+        clock_skew: ClockSkewGuard,
     ) -> Self {
         let subscriptions = MaterializerSubscriptions::new();
-        let ttl_refresh_history = Vec::new();
+        let ttl_refresh_history = TtlRefreshHistory::new(ttl_refresh_history_size);
         let ttl_refresh_instance = None;
         let version_tracker = VersionTracker::new();
         Self {
@@ -378,18 +566,45 @@ impl<T: IoHandler> DeferredMaterializerCommandProcessor<T> {
             version_tracker,
             command_sender,
             tree,
+            pruned: FileTree::new(),
             subscriptions,
             ttl_refresh_history,
             ttl_refresh_instance,
             cancellations,
             stats,
+            io_pressure,
             access_times_buffer,
+            access_times_flush: None,
             verbose_materializer_log,
             daemon_dispatcher,
             disable_eager_write_dispatch,
+            paranoid_verification,
+            completion_callbacks: CompletionCallbacks::new(),
+            clock_skew,
         }
     }
 
+    /// Records the current time with the clock skew guard, persisting it in the background if
+    /// needed.
+    pub(super) fn observe_clock(&mut self, now: DateTime<Utc>) {
+        let Some(max_recorded) = self.clock_skew.observe(now) else {
+            return;
+        };
+        if let Some(sqlite_db) = self.sqlite_db.as_ref() {
+            let writer = sqlite_db.max_access_time_writer();
+            self.rt.spawn_blocking(move || {
+                if let Err(e) = writer.write(max_recorded) {
+                    let _ignored = soft_error!("materializer_clock_write_error", e, quiet: true);
+                }
+            });
+        }
+    }
+
+    /// Lets extension commands schedule follow-up work on the command loop.
+    pub(super) fn scheduling(&mut self) -> MaterializerSchedulingContext<'_, T> {
+        MaterializerSchedulingContext::new(self)
+    }
+
     fn spawn_from_rt<F>(rt: &Handle, f: F) -> JoinHandle<F::Output>
     where
         F: std::future::Future + Send + 'static,
@@ -421,6 +636,7 @@ impl<T: IoHandler> DeferredMaterializerCommandProcessor<T> {
         access_time_update_max_buffer_size: usize,
         access_time_updates: AccessTimesUpdates,
         clean_stale_config: Option<CleanStaleConfig>,
+        artifact_tree_sweep: ArtifactTreeSweepConfiguration,
     ) {
         let MaterializerReceiver {
             high_priority,
@@ -446,12 +662,18 @@ impl<T: IoHandler> DeferredMaterializerCommandProcessor<T> {
 
         let io_buffer_ticker = tokio::time::interval(std::time::Duration::from_secs(5));
 
+        let artifact_tree_sweep_ticker = tokio::time::interval_at(
+            tokio::time::Instant::now() + artifact_tree_sweep.frequency,
+            artifact_tree_sweep.frequency,
+        );
+
         let mut stream = CommandStream {
             high_priority,
             low_priority,
             refresh_ttl_ticker,
             io_buffer_ticker,
             clean_stale_ticker,
+            artifact_tree_sweep_ticker,
             clean_stale_fut: None,
         };
 
@@ -461,7 +683,7 @@ impl<T: IoHandler> DeferredMaterializerCommandProcessor<T> {
                     self.log_buffer.push(format!("{:?}", command));
                     self.process_one_command(command);
                     counters.ack_received();
-                    self.flush_access_times(access_time_update_max_buffer_size);
+                    self.flush_access_times(access_time_update_max_buffer_size, None);
                 }
                 Op::LowPriorityCommand(command) => {
                     self.log_buffer.push(format!("{:?}", command));
@@ -480,7 +702,11 @@ impl<T: IoHandler> DeferredMaterializerCommandProcessor<T> {
                     if self.ttl_refresh_instance.is_none() {
                         let ttl_refresh = self
                             .io
-                            .create_ttl_refresh(&self.tree, ttl_refresh.min_ttl)
+                            .create_ttl_refresh(
+                                &self.tree,
+                                ttl_refresh.min_ttl,
+                                ttl_refresh.methods,
+                            )
                             .map(|fut| {
                                 // We sue a channel here and not JoinHandle so we get blocking
                                 // `try_recv`.
@@ -498,7 +724,7 @@ impl<T: IoHandler> DeferredMaterializerCommandProcessor<T> {
                             Some(ttl_refresh) => {
                                 self.ttl_refresh_instance = Some(ttl_refresh);
                             }
-                            None => self.ttl_refresh_history.push(TtlRefreshHistoryEntry {
+                            None => self.record_ttl_refresh(TtlRefreshHistoryEntry {
                                 at: Utc::now(),
                                 outcome: None,
                             }),
@@ -506,9 +732,13 @@ impl<T: IoHandler> DeferredMaterializerCommandProcessor<T> {
                     }
                 }
                 Op::Tick => {
+                    if let Some(io_pressure) = &self.io_pressure {
+                        io_pressure.tick();
+                    }
+                    self.observe_clock(Utc::now());
                     if matches!(access_time_updates, AccessTimesUpdates::Full) {
                         // Force a periodic flush.
-                        self.flush_access_times(0);
+                        self.flush_access_times(0, None);
                     };
                 }
                 Op::CleanStaleRequest => {
@@ -518,6 +748,10 @@ impl<T: IoHandler> DeferredMaterializerCommandProcessor<T> {
                             keep_since_time: chrono::Utc::now() - config.artifact_ttl,
                             dry_run: config.dry_run,
                             tracked_only: false,
+                            cells: Vec::new(),
+                            max_bytes_per_run: config.max_bytes_per_run,
+                            max_files_per_run: config.max_files_per_run,
+                            keep_paths: config.keep_paths.clone(),
                             dispatcher,
                         };
                         stream.clean_stale_fut = Some(cmd.create_clean_fut(&mut self, None));
@@ -531,10 +765,40 @@ impl<T: IoHandler> DeferredMaterializerCommandProcessor<T> {
                             .unwrap();
                     }
                 }
+                Op::SweepArtifactTree => {
+                    self.sweep_artifact_tree(artifact_tree_sweep.declared_idle_ttl);
+                }
+            }
+        }
+    }
+
+    /// Forget declared artifacts that have been idle for longer than `idle_ttl` (if set).
+    fn sweep_artifact_tree(&mut self, idle_ttl: Option<std::time::Duration>) {
+        if let Some(idle_since) =
+            idle_ttl.and_then(|ttl| tokio::time::Instant::now().checked_sub(ttl))
+        {
+            let pruned = self.tree.prune_idle_declared(idle_since);
+            for path in &pruned {
+                self.pruned.insert(path.iter().map(|f| f.to_owned()), ());
+            }
+            if !pruned.is_empty() {
+                tracing::debug!("pruned {} idle declared artifacts", pruned.len());
+                self.stats
+                    .declared_pruned
+                    .fetch_add(pruned.len() as u64, Ordering::Relaxed);
             }
         }
     }
 
+    /// Estimate the size of the tree. This walks the whole tree, so it's only done on request.
+    pub(super) fn measure_artifact_tree(&mut self) -> u64 {
+        let bytes = allocative::size_of_unique(&self.tree) as u64;
+        self.stats
+            .artifact_tree_bytes
+            .store(bytes, Ordering::Relaxed);
+        bytes
+    }
+
     fn process_one_command(&mut self, command: MaterializerCommand<T>) {
         match command {
             // Entry point for `get_materialized_file_paths` calls
@@ -543,14 +807,15 @@ impl<T: IoHandler> DeferredMaterializerCommandProcessor<T> {
                     paths.into_map(|p| self.tree.file_contents_path(p, self.io.digest_config()));
                 result_sender.send(result).ok();
             }
-            MaterializerCommand::DeclareExisting(artifacts, ..) => {
+            MaterializerCommand::DeclareExisting(artifacts, span_id, trace_id) => {
+                let owner = trace_id.map(|trace_id| Arc::new(DeclareOwner { trace_id, span_id }));
                 for (path, artifact) in artifacts {
-                    self.declare_existing(&path, artifact);
+                    self.declare_existing(&path, artifact, owner.dupe());
                 }
             }
             // Entry point for `declare_{copy|cas}` calls
-            MaterializerCommand::Declare(path, value, method, event_dispatcher) => {
-                self.maybe_log_command(&event_dispatcher, || {
+            MaterializerCommand::Declare(path, value, method, mut context) => {
+                self.maybe_log_command(&context.event_dispatcher, || {
                     buck2_data::materializer_command::Data::Declare(
                         buck2_data::materializer_command::Declare {
                             path: path.to_string(),
@@ -558,10 +823,48 @@ impl<T: IoHandler> DeferredMaterializerCommandProcessor<T> {
                     )
                 });
 
-                self.declare(&path, value, method);
+                let result = self.declare(&path, value, method, &context);
+
+                if context.reply(result) && self.subscriptions.should_materialize_eagerly(&path) {
+                    self.materialize_artifact(&path, context.event_dispatcher);
+                }
+            }
+            // Entry point for `declare_cas_many` calls
+            MaterializerCommand::DeclareMany(artifacts, info, mut context) => {
+                let mut eager = Vec::new();
+                let mut result = Ok(());
+                for (path, value) in artifacts {
+                    self.maybe_log_command(&context.event_dispatcher, || {
+                        buck2_data::materializer_command::Data::Declare(
+                            buck2_data::materializer_command::Declare {
+                                path: path.to_string(),
+                            },
+                        )
+                    });
+
+                    // Artifacts that don't conflict are still declared, the first conflict is
+                    // reported.
+                    if let Err(e) = self.declare(
+                        &path,
+                        value,
+                        Box::new(ArtifactMaterializationMethod::CasDownload { info: info.dupe() }),
+                        &context,
+                    ) {
+                        if result.is_ok() {
+                            result = Err(e);
+                        }
+                        continue;
+                    }
+
+                    if self.subscriptions.should_materialize_eagerly(&path) {
+                        eager.push(path);
+                    }
+                }
+                context.reply(result);
 
-                if self.subscriptions.should_materialize_eagerly(&path) {
-                    self.materialize_artifact(&path, event_dispatcher);
+                if !eager.is_empty() {
+                    // The materializations are tracked in the tree, so the stream can be dropped.
+                    let _ignored = self.materialize_many_artifacts(eager, context.event_dispatcher);
                 }
             }
             MaterializerCommand::MatchArtifacts(paths, sender) => {
@@ -573,6 +876,15 @@ impl<T: IoHandler> DeferredMaterializerCommandProcessor<T> {
             MaterializerCommand::HasArtifact(path, sender) => {
                 sender.send(self.has_artifact(path)).ok();
             }
+            MaterializerCommand::HasArtifacts(paths, sender) => {
+                sender.send(self.has_artifacts(paths)).ok();
+            }
+            MaterializerCommand::GetArtifactState(path, sender) => {
+                sender.send(self.tree.artifact_state(&path)).ok();
+            }
+            MaterializerCommand::GenerateManifest(paths, sender) => {
+                sender.send(self.tree.generate_manifest(paths)).ok();
+            }
             MaterializerCommand::InvalidateFilePaths(paths, sender, event_dispatcher) => {
                 tracing::trace!(
                     paths = ?paths,
@@ -586,6 +898,9 @@ impl<T: IoHandler> DeferredMaterializerCommandProcessor<T> {
                     )
                 });
 
+                for path in &paths {
+                    let _ignored = self.pruned.remove_path(path);
+                }
                 let existing_futs = self
                     .tree
                     .invalidate_paths_and_collect_futures(paths, self.sqlite_db.as_mut());
@@ -603,6 +918,9 @@ impl<T: IoHandler> DeferredMaterializerCommandProcessor<T> {
                     )
                     .ok();
             }
+            MaterializerCommand::PreviewInvalidate(paths, sender) => {
+                sender.send(self.tree.preview_invalidate_paths(&paths)).ok();
+            }
             // Entry point for `ensure_materialized` calls
             MaterializerCommand::Ensure(paths, event_dispatcher, fut_sender) => {
                 self.maybe_log_command(&event_dispatcher, || {
@@ -613,12 +931,25 @@ impl<T: IoHandler> DeferredMaterializerCommandProcessor<T> {
                     )
                 });
 
+                let already_materialized = paths
+                    .iter()
+                    .filter(|path| self.is_materialized(path))
+                    .count();
+                self.stats.command_stats.record_ensure(
+                    event_dispatcher.trace_id(),
+                    paths.len() as u64,
+                    already_materialized as u64,
+                );
+
                 fut_sender
                     .send(self.materialize_many_artifacts(paths, event_dispatcher))
                     .ok();
             }
             MaterializerCommand::Subscription(sub) => sub.execute(self),
             MaterializerCommand::Extension(ext) => ext.execute(self),
+            MaterializerCommand::LowPriority(command) => {
+                self.process_one_low_priority_command(command)
+            }
             MaterializerCommand::Abort => unreachable!(),
         }
     }
@@ -629,10 +960,11 @@ impl<T: IoHandler> DeferredMaterializerCommandProcessor<T> {
             LowPriorityMaterializerCommand::MaterializationFinished {
                 path,
                 timestamp,
+                started,
                 version,
                 result,
             } => {
-                self.materialization_finished(path, timestamp, version, result);
+                self.materialization_finished(path, timestamp, started, version, result);
             }
             LowPriorityMaterializerCommand::CleanupFinished {
                 path,
@@ -641,6 +973,26 @@ impl<T: IoHandler> DeferredMaterializerCommandProcessor<T> {
             } => {
                 self.tree.cleanup_finished(path, version, result);
             }
+            LowPriorityMaterializerCommand::AccessTimesFlushed {
+                size,
+                elapsed,
+                result,
+            } => {
+                self.access_times_flushed(size, elapsed, result);
+            }
+            LowPriorityMaterializerCommand::CompletionReady { token, result } => {
+                // Not there if it was cancelled.
+                if let Some(callback) = self.completion_callbacks.take(token) {
+                    callback(self, result);
+                }
+            }
+            LowPriorityMaterializerCommand::VerificationFinished {
+                path,
+                version,
+                result,
+            } => {
+                self.verification_finished(path, version, result);
+            }
         }
     }
 
@@ -651,7 +1003,7 @@ impl<T: IoHandler> DeferredMaterializerCommandProcessor<T> {
             Some(mut curr) => match curr.try_recv() {
                 Ok((at, outcome)) => {
                     // Done
-                    self.ttl_refresh_history.push(TtlRefreshHistoryEntry {
+                    self.record_ttl_refresh(TtlRefreshHistoryEntry {
                         at,
                         outcome: Some(outcome),
                     });
@@ -663,7 +1015,7 @@ impl<T: IoHandler> DeferredMaterializerCommandProcessor<T> {
                 }
                 Err(TryRecvError::Closed) => {
                     // Shouldnt really happen unless Tokio is shutting down, but be safe.
-                    self.ttl_refresh_history.push(TtlRefreshHistoryEntry {
+                    self.record_ttl_refresh(TtlRefreshHistoryEntry {
                         at: Utc::now(),
                         outcome: Some(Err(buck2_error!(buck2_error::ErrorTag::Tier0, "Shutdown"))),
                     });
@@ -674,6 +1026,18 @@ impl<T: IoHandler> DeferredMaterializerCommandProcessor<T> {
         };
     }
 
+    /// Adds a TTL refresh to the history, escalating if refreshes keep failing, since expiring
+    /// artifacts would otherwise only be noticed once builds fail to download them.
+    fn record_ttl_refresh(&mut self, entry: TtlRefreshHistoryEntry) {
+        if let Some(escalation) = self.ttl_refresh_history.push(entry) {
+            tracing::warn!(
+                consecutive_failures = escalation.consecutive_failures,
+                "TTL refreshes keep failing"
+            );
+            self.daemon_dispatcher.instant_event(escalation);
+        }
+    }
+
     pub(super) fn is_path_materialized(&self, path: &ProjectRelativePath) -> bool {
         match self.tree.prefix_get(&mut path.iter()) {
             None => false,
@@ -686,37 +1050,104 @@ impl<T: IoHandler> DeferredMaterializerCommandProcessor<T> {
         }
     }
 
-    pub(super) fn flush_access_times(&mut self, max_buffer_size: usize) -> String {
-        if let Some(access_times_buffer) = self.access_times_buffer.as_mut() {
-            let size = access_times_buffer.len();
-            if size < max_buffer_size {
-                return "Access times buffer is not full yet".to_owned();
+    /// Start writing the buffered access times to sqlite, if the buffer holds at least
+    /// `max_buffer_size` entries. `on_finished` is sent a description of the outcome, once the
+    /// flush finishes if one was started. If a flush is already running, this waits for it to
+    /// finish when `on_finished` is set, and does nothing otherwise.
+    pub(super) fn flush_access_times(
+        &mut self,
+        max_buffer_size: usize,
+        on_finished: Option<oneshot::Sender<String>>,
+    ) {
+        let report = |on_finished: Option<oneshot::Sender<String>>, message: String| {
+            if let Some(on_finished) = on_finished {
+                let _ignored = on_finished.send(message);
             }
+        };
 
-            let buffer = std::mem::take(access_times_buffer);
-            let now = Instant::now();
-            tracing::debug!("Flushing access times buffer");
-            if let Some(sqlite_db) = self.sqlite_db.as_mut() {
-                if let Err(e) = sqlite_db
-                    .materializer_state_table()
-                    .update_access_times(buffer.iter().collect::<Vec<_>>())
-                {
-                    soft_error!(
-                        "materializer_materialize_error",
-                        e.context(format!("{}", self.log_buffer)).into(),
-                        quiet: true
-                    )
-                    .unwrap();
-                    return "Found error while updating access times in sqlite db".to_owned();
-                }
+        let Some(access_times_buffer) = self.access_times_buffer.as_mut() else {
+            return report(
+                on_finished,
+                "Access time updates are disabled. Consider removing `update_access_times = false` from your .buckconfig".to_owned(),
+            );
+        };
+        if let Some(flush) = self.access_times_flush.as_mut() {
+            flush.queued.extend(on_finished);
+            return;
+        }
+        let size = access_times_buffer.len();
+        if size < max_buffer_size {
+            return report(
+                on_finished,
+                "Access times buffer is not full yet".to_owned(),
+            );
+        }
+
+        let buffer = std::mem::take(access_times_buffer);
+        tracing::debug!("Flushing access times buffer");
+        let sqlite_db = match self.sqlite_db.as_mut() {
+            Some(sqlite_db) if size > 0 => sqlite_db,
+            _ => {
+                return report(
+                    on_finished,
+                    format!("Finished flushing {} entries in 0 ms", size),
+                );
             }
-            return format!(
+        };
+
+        let table = sqlite_db.materializer_state_table().clone();
+        let paths = Arc::new(buffer);
+        self.access_times_flush = Some(AccessTimesFlush {
+            paths: paths.dupe(),
+            on_finished,
+            queued: Vec::new(),
+        });
+
+        let command_sender = self.command_sender.dupe();
+        self.rt.spawn_blocking(move || {
+            let now = Instant::now();
+            let result = table.update_access_times(paths.iter().collect());
+            command_sender.blocking_send_low_priority(
+                LowPriorityMaterializerCommand::AccessTimesFlushed {
+                    size,
+                    elapsed: now.elapsed(),
+                    result,
+                },
+            );
+        });
+    }
+
+    fn access_times_flushed(
+        &mut self,
+        size: usize,
+        elapsed: std::time::Duration,
+        result: buck2_error::Result<()>,
+    ) {
+        let Some(flush) = self.access_times_flush.take() else {
+            return;
+        };
+        let message = match result {
+            Ok(()) => format!(
                 "Finished flushing {} entries in {} ms",
                 size,
-                now.elapsed().as_millis(),
-            );
+                elapsed.as_millis(),
+            ),
+            Err(e) => {
+                soft_error!(
+                    "materializer_materialize_error",
+                    e.context(format!("{}", self.log_buffer)).into(),
+                    quiet: true
+                )
+                .unwrap();
+                "Found error while updating access times in sqlite db".to_owned()
+            }
+        };
+        if let Some(on_finished) = flush.on_finished {
+            let _ignored = on_finished.send(message);
+        }
+        for on_finished in flush.queued {
+            self.flush_access_times(0, Some(on_finished));
         }
-        "Access time updates are disabled. Consider removing `update_access_times = false` from your .buckconfig".to_owned()
     }
 
     fn materialize_many_artifacts(
@@ -724,25 +1155,101 @@ impl<T: IoHandler> DeferredMaterializerCommandProcessor<T> {
         paths: Vec<ProjectRelativePathBuf>,
         event_dispatcher: EventDispatcher,
     ) -> BoxStream<'static, Result<(), MaterializationError>> {
+        let mut batches = self.plan_cas_batches(&paths, &event_dispatcher);
         let tasks = paths.into_iter().filter_map(|path| {
-            self.materialize_artifact(path.as_ref(), event_dispatcher.dupe())
-                .map(move |fut| {
-                    fut.map_err(move |e| match e {
-                        SharedMaterializingError::Error(source) => MaterializationError::Error {
-                            path,
-                            source: source.into(),
-                        },
-                        SharedMaterializingError::NotFound(source) => {
-                            MaterializationError::NotFound { source }
-                        }
-                    })
+            self.materialize_artifact_recurse(
+                MaterializeStack::Empty,
+                path.as_ref(),
+                event_dispatcher.dupe(),
+                &mut batches,
+            )
+            .map(move |fut| {
+                fut.map_err(move |e| match e {
+                    SharedMaterializingError::Error(source) => MaterializationError::Error {
+                        path,
+                        source: source.into(),
+                    },
+                    SharedMaterializingError::NotFound(source) => {
+                        MaterializationError::NotFound { source }
+                    }
                 })
+            })
         });
 
         tasks.collect::<FuturesOrdered<_>>().boxed()
     }
 
-    fn declare_existing(&mut self, path: &ProjectRelativePath, value: ArtifactValue) {
+    /// Whether ensuring `path` has nothing to do: the artifact that contains it is materialized
+    /// and has no deps that might need materializing.
+    fn is_materialized(&self, path: &ProjectRelativePath) -> bool {
+        self.tree.prefix_get(&mut path.iter()).is_some_and(|data| {
+            data.deps.is_none()
+                && matches!(
+                    data.stage,
+                    ArtifactMaterializationStage::Materialized { .. }
+                )
+        })
+    }
+
+    /// Group the artifacts among `paths` that are downloaded from the CAS with the same
+    /// `CasDownloadInfo` so that each group is materialized with a single CAS download.
+    ///
+    /// Only artifacts that are declared, not already materializing and have no deps are batched:
+    /// anything else needs work to happen before its own download that the batch can't wait on.
+    fn plan_cas_batches(
+        &mut self,
+        paths: &[ProjectRelativePathBuf],
+        event_dispatcher: &EventDispatcher,
+    ) -> CasBatches {
+        let mut groups: HashMap<*const CasDownloadInfo, CasBatchGroup> = HashMap::new();
+        // The same path may be requested more than once.
+        for path in paths.iter().unique() {
+            let mut path_iter = path.iter();
+            let Some(data) = self.tree.prefix_get_mut(&mut path_iter) else {
+                continue;
+            };
+            if path_iter.next().is_some() || data.deps.is_some() {
+                continue;
+            }
+            let cleaning = match &data.processing {
+                Processing::Active {
+                    future: ProcessingFuture::Materializing(_),
+                    ..
+                } => continue,
+                Processing::Active {
+                    future: ProcessingFuture::Cleaning(f),
+                    ..
+                } => Some(f.clone()),
+                Processing::Done(..) => None,
+            };
+            if let ArtifactMaterializationStage::Declared { entry, method, .. } = &data.stage {
+                if let ArtifactMaterializationMethod::CasDownload { info } = method.as_ref() {
+                    let group = groups
+                        .entry(Arc::as_ptr(info))
+                        .or_insert_with(|| CasBatchGroup {
+                            info: info.dupe(),
+                            entries: Vec::new(),
+                        });
+                    group.entries.push((path.clone(), entry.dupe(), cleaning));
+                }
+            }
+        }
+
+        let mut batches = CasBatches::default();
+        for group in groups.into_values() {
+            if group.entries.len() > 1 {
+                batches.add(group, &self.io, event_dispatcher);
+            }
+        }
+        batches
+    }
+
+    fn declare_existing(
+        &mut self,
+        path: &ProjectRelativePath,
+        value: ArtifactValue,
+        owner: Option<Arc<DeclareOwner>>,
+    ) {
         let metadata = ArtifactMetadata::new(value.entry());
         on_materialization(
             self.sqlite_db.as_mut(),
@@ -754,6 +1261,7 @@ impl<T: IoHandler> DeferredMaterializerCommandProcessor<T> {
             "materializer_declare_existing_error",
         );
 
+        let _ignored = self.pruned.remove_path(path);
         self.tree.insert(
             path.iter().map(|f| f.to_owned()),
             Box::new(ArtifactMaterializationData {
@@ -764,6 +1272,7 @@ impl<T: IoHandler> DeferredMaterializerCommandProcessor<T> {
                     active: true,
                 },
                 processing: Processing::Done(self.version_tracker.next()),
+                declared_by: owner,
             }),
         );
     }
@@ -773,12 +1282,15 @@ impl<T: IoHandler> DeferredMaterializerCommandProcessor<T> {
         path: &ProjectRelativePath,
         value: ArtifactValue,
         method: Box<ArtifactMaterializationMethod>,
-    ) {
+        context: &DeclareContext,
+    ) -> buck2_error::Result<()> {
         self.stats.declares.fetch_add(1, Ordering::Relaxed);
 
         // Check if artifact to be declared is same as artifact that's already materialized.
         let mut path_iter = path.iter();
+        let mut conflict = None;
         if let Some(data) = self.tree.prefix_get_mut(&mut path_iter) {
+            let at_path = path_iter.next().is_none();
             match &data.stage {
                 ArtifactMaterializationStage::Materialized {
                     metadata,
@@ -794,10 +1306,7 @@ impl<T: IoHandler> DeferredMaterializerCommandProcessor<T> {
                     )
                     .unwrap();
 
-                    if path_iter.next().is_none()
-                        && metadata.matches_entry(value.entry())
-                        && !force_mismatch
-                    {
+                    if at_path && metadata.matches_entry(value.entry()) && !force_mismatch {
                         // In this case, the entry declared matches the already materialized
                         // entry on disk, so just update the deps field but leave
                         // the artifact as materialized.
@@ -812,14 +1321,46 @@ impl<T: IoHandler> DeferredMaterializerCommandProcessor<T> {
                             active: true,
                         };
                         data.deps = deps;
+                        data.declared_by = Some(context.owner.dupe());
 
                         self.stats.declares_reused.fetch_add(1, Ordering::Relaxed);
 
-                        return;
+                        return Ok(());
                     }
                 }
                 _ => {}
             }
+            if at_path {
+                conflict = declare_conflict(data, value.entry(), &context.owner);
+            }
+        }
+
+        if let Some((first_owner, first_entry)) = conflict {
+            let second_entry = ArtifactMetadata::new(value.entry()).0.to_string();
+            let failed = context.fail_on_conflict();
+            context
+                .event_dispatcher
+                .instant_event(buck2_data::MaterializerDeclareConflict {
+                    path: path.to_string(),
+                    first_owner: Some(first_owner.to_proto()),
+                    first_entry: first_entry.clone(),
+                    second_owner: Some(context.owner.to_proto()),
+                    second_entry: second_entry.clone(),
+                    failed,
+                });
+            if failed {
+                return Err(buck2_error!(
+                    ErrorTag::DeclareConflict,
+                    "Output `{}` was declared twice in this command with different contents: \
+                    first as {} from span {}, then as {} from span {}. \
+                    Two actions are likely declaring the same output.",
+                    path,
+                    first_entry,
+                    display_span(first_owner.span_id),
+                    second_entry,
+                    display_span(context.owner.span_id),
+                ));
+            }
         }
 
         // We don't have a matching artifact. Declare it.
@@ -839,6 +1380,7 @@ impl<T: IoHandler> DeferredMaterializerCommandProcessor<T> {
         let existing_futs = self
             .tree
             .invalidate_paths_and_collect_futures(vec![path.to_owned()], self.sqlite_db.as_mut());
+        let _ignored = self.pruned.remove_path(path);
 
         let existing_futs = ExistingFutures(existing_futs);
 
@@ -881,10 +1423,13 @@ impl<T: IoHandler> DeferredMaterializerCommandProcessor<T> {
             stage: ArtifactMaterializationStage::Declared {
                 entry: value.entry().dupe(),
                 method,
+                last_requested: tokio::time::Instant::now(),
             },
             processing: Processing::Active { future, version },
+            declared_by: Some(context.owner.dupe()),
         });
         self.tree.insert(path.iter().map(|f| f.to_owned()), data);
+        Ok(())
     }
 
     /// Check if artifact to be declared is same as artifact that's already materialized.
@@ -905,17 +1450,29 @@ impl<T: IoHandler> DeferredMaterializerCommandProcessor<T> {
             return false;
         }
 
-        let is_match = match &data.stage {
+        let mut verify = None;
+        let is_match = match &mut data.stage {
             ArtifactMaterializationStage::Materialized { metadata, .. } => {
                 let is_match = value.entry();
                 tracing::trace!("materialized: found {}, is_match: {}", metadata.0, is_match);
-                metadata.matches_entry(is_match)
+                let is_match = metadata.matches_entry(is_match);
+                if is_match && self.paranoid_verification {
+                    verify = Some((metadata.dupe(), data.processing.current_version()));
+                }
+                is_match
             }
-            ArtifactMaterializationStage::Declared { entry, .. } => {
+            ArtifactMaterializationStage::Declared {
+                entry,
+                last_requested,
+                ..
+            } => {
                 // NOTE: In theory, if something was declared here, we should probably be able to
                 // just re-declare over it?
                 let is_match = value.entry() == entry;
                 tracing::trace!("declared: found {}, is_match: {}", entry, is_match);
+                if is_match {
+                    *last_requested = tokio::time::Instant::now();
+                }
                 is_match
             }
         };
@@ -932,43 +1489,141 @@ impl<T: IoHandler> DeferredMaterializerCommandProcessor<T> {
             }
         }
 
+        if let Some((metadata, version)) = verify {
+            self.verify_materialized(path, metadata, version);
+        }
+
         is_match
     }
 
-    fn has_artifact(&mut self, path: ProjectRelativePathBuf) -> bool {
+    /// Checks what is on disk at `path` against `metadata` in the background. The outcome is
+    /// handled by `verification_finished`.
+    fn verify_materialized(
+        &mut self,
+        path: ProjectRelativePathBuf,
+        metadata: ArtifactMetadata,
+        version: Version,
+    ) {
+        let io = self.io.dupe();
+        self.scheduling().enqueue_low_priority_after(async move {
+            let result = io.verify_materialized(path.clone(), metadata).await;
+            LowPriorityMaterializerCommand::VerificationFinished {
+                path,
+                version,
+                result,
+            }
+        });
+    }
+
+    /// Invalidates the artifact at `path` if what is on disk didn't match it, so that it is
+    /// declared and materialized again next time instead of matching. Nothing happens if the
+    /// artifact changed since it was verified.
+    fn verification_finished(
+        &mut self,
+        path: ProjectRelativePathBuf,
+        version: Version,
+        result: buck2_error::Result<bool>,
+    ) {
         let mut path_iter = path.iter();
-        let Some(data) = self.tree.prefix_get_mut(&mut path_iter) else {
-            return false;
+        match self.tree.prefix_get_mut(&mut path_iter) {
+            Some(data)
+                if path_iter.next().is_none()
+                    && data.processing.current_version() == version
+                    && matches!(
+                        data.stage,
+                        ArtifactMaterializationStage::Materialized { .. }
+                    ) => {}
+            _ => {
+                tracing::debug!(path = %path, "verified artifact changed, ignoring");
+                return;
+            }
+        }
+
+        let error = match result {
+            Ok(true) => return,
+            Ok(false) => buck2_error::buck2_error!(
+                buck2_error::ErrorTag::IoMaterializerVerificationMismatch,
+                "Materialized artifact `{}` doesn't match its digests on disk, invalidating it",
+                path
+            ),
+            Err(e) => e.context(format!(
+                "Error verifying materialized artifact `{}`, invalidating it",
+                path
+            )),
         };
-        // Something was declared above our path.
-        if path_iter.next().is_some() {
-            return false;
+        soft_error!(
+            "materializer_paranoid_verification_mismatch",
+            error.into(),
+            quiet: true
+        )
+        .unwrap();
+
+        if let Err(e) = self
+            .tree
+            .invalidate_paths_and_collect_futures(vec![path], self.sqlite_db.as_mut())
+        {
+            soft_error!(
+                "materializer_materialize_error",
+                e.context(format!("{}", self.log_buffer)).into(),
+                quiet: true
+            )
+            .unwrap();
         }
+    }
 
-        match &mut data.stage {
-            ArtifactMaterializationStage::Materialized {
-                metadata: _,
-                last_access_time,
-                active,
-            } => {
-                // Treat this case much like a `declare_existing`
-                *active = true;
-                *last_access_time = Utc::now();
-                if let Some(sqlite_db) = &mut self.sqlite_db {
-                    if let Err(e) = sqlite_db
-                        .materializer_state_table()
-                        .update_access_times(vec![&path])
-                    {
-                        soft_error!("has_artifact_update_time", e.context(format!("{}", self.log_buffer)).into(), quiet: true).unwrap();
+    fn has_artifact(&mut self, path: ProjectRelativePathBuf) -> bool {
+        self.has_artifacts(vec![path]) == [true]
+    }
+
+    /// Whether there is an artifact at each of `paths`. Access times of the materialized ones are
+    /// written to sqlite in one update.
+    fn has_artifacts(&mut self, paths: Vec<ProjectRelativePathBuf>) -> Vec<bool> {
+        let now = Utc::now();
+        let mut materialized = Vec::new();
+        let res = paths
+            .iter()
+            .map(|path| {
+                let mut path_iter = path.iter();
+                let Some(data) = self.tree.prefix_get_mut(&mut path_iter) else {
+                    return false;
+                };
+                // Something was declared above our path.
+                if path_iter.next().is_some() {
+                    return false;
+                }
+
+                match &mut data.stage {
+                    ArtifactMaterializationStage::Materialized {
+                        metadata: _,
+                        last_access_time,
+                        active,
+                    } => {
+                        // Treat this case much like a `declare_existing`
+                        *active = true;
+                        *last_access_time = now;
+                        materialized.push(path);
+                    }
+                    ArtifactMaterializationStage::Declared { last_requested, .. } => {
+                        *last_requested = tokio::time::Instant::now();
                     }
                 }
-            }
-            ArtifactMaterializationStage::Declared { .. } => {
-                // Nothing to do here
+
+                true
+            })
+            .collect();
+
+        if let Some(sqlite_db) = &mut self.sqlite_db {
+            if !materialized.is_empty() {
+                if let Err(e) = sqlite_db
+                    .materializer_state_table()
+                    .update_access_times(materialized)
+                {
+                    soft_error!("has_artifact_update_time", e.context(format!("{}", self.log_buffer)).into(), quiet: true).unwrap();
+                }
             }
         }
 
-        true
+        res
     }
 
     #[instrument(level = "debug", skip(self), fields(path = %path))]
@@ -977,7 +1632,12 @@ impl<T: IoHandler> DeferredMaterializerCommandProcessor<T> {
         path: &ProjectRelativePath,
         event_dispatcher: EventDispatcher,
     ) -> Option<MaterializingFuture> {
-        self.materialize_artifact_recurse(MaterializeStack::Empty, path, event_dispatcher)
+        self.materialize_artifact_recurse(
+            MaterializeStack::Empty,
+            path,
+            event_dispatcher,
+            &mut CasBatches::default(),
+        )
     }
 
     fn materialize_artifact_recurse(
@@ -985,11 +1645,12 @@ impl<T: IoHandler> DeferredMaterializerCommandProcessor<T> {
         stack: MaterializeStack<'_>,
         path: &ProjectRelativePath,
         event_dispatcher: EventDispatcher,
+        batches: &mut CasBatches,
     ) -> Option<MaterializingFuture> {
         let stack = MaterializeStack::Child(&stack, path);
         // We only add context to outer error, because adding context to the future
         // is expensive. Errors in futures should add stack context themselves.
-        match self.materialize_artifact_inner(stack, path, event_dispatcher) {
+        match self.materialize_artifact_inner(stack, path, event_dispatcher, batches) {
             Ok(res) => res,
             Err(e) => Some(
                 future::err(SharedMaterializingError::Error(
@@ -1017,6 +1678,7 @@ impl<T: IoHandler> DeferredMaterializerCommandProcessor<T> {
         stack: MaterializeStack<'_>,
         path: &ProjectRelativePath,
         event_dispatcher: EventDispatcher,
+        batches: &mut CasBatches,
     ) -> buck2_error::Result<Option<MaterializingFuture>> {
         // TODO(nga): rewrite without recursion or figure out why we overflow stack here.
         check_stack_overflow().tag(ErrorTag::ServerStackOverflow)?;
@@ -1024,6 +1686,17 @@ impl<T: IoHandler> DeferredMaterializerCommandProcessor<T> {
         // Get the data about the artifact, or return early if materializing/materialized
         let (path, data) = match Self::find_artifact_containing_path(&mut self.tree, path) {
             None => {
+                if self.pruned.prefix_get(&mut path.iter()).is_some() {
+                    // Declared, but forgotten while idle. The action that produced it won't run
+                    // again until DICE invalidates it, so there's no way to get it back.
+                    return Err(tag_error!(
+                        "materializer_pruned_artifact",
+                        PrunedArtifactError(path.to_owned()).into(),
+                        quiet: true,
+                        task: false,
+                        daemon_in_memory_state_is_corrupted: true
+                    ));
+                }
                 // Never declared, nothing to do
                 tracing::debug!("not known");
                 return Ok(None);
@@ -1049,7 +1722,12 @@ impl<T: IoHandler> DeferredMaterializerCommandProcessor<T> {
         let deps = data.deps.dupe();
         let check_deps = deps.is_some();
         let entry_and_method = match &mut data.stage {
-            ArtifactMaterializationStage::Declared { entry, method } => {
+            ArtifactMaterializationStage::Declared {
+                entry,
+                method,
+                last_requested,
+            } => {
+                *last_requested = tokio::time::Instant::now();
                 Some((entry.dupe(), method.dupe()))
             }
             ArtifactMaterializationStage::Materialized {
@@ -1072,7 +1750,11 @@ impl<T: IoHandler> DeferredMaterializerCommandProcessor<T> {
                         // if !active {
                         //     tracing::warn!(path = %path, "Expected artifact to be marked active by declare")
                         // }
-                        if buffer.insert(path.to_buf()) {
+                        let in_flight = self
+                            .access_times_flush
+                            .as_ref()
+                            .is_some_and(|flush| flush.paths.contains(path));
+                        if !in_flight && buffer.insert(path.to_buf()) {
                             tracing::debug!(
                                 "nothing to materialize, adding to access times buffer"
                             );
@@ -1098,20 +1780,66 @@ impl<T: IoHandler> DeferredMaterializerCommandProcessor<T> {
         let method = entry_and_method.as_ref().map(|(_, m)| m.as_ref());
         // Those are special because if the artifact copies from other artifacts, we must materialize them first
         let materialize_copy_source_tasks =
-            self.materialize_copy_source_tasks(&stack, &event_dispatcher, path, method);
+            self.materialize_copy_source_tasks(&stack, &event_dispatcher, path, method, batches);
 
         // The artifact might have symlinks pointing to other artifacts. We must
         // materialize them as well, to avoid dangling symlinks.
-        let materialize_symlink_destination_tasks =
-            self.materialize_symlink_destination_tasks(&stack, &event_dispatcher, path, deps);
+        let materialize_symlink_destination_tasks = self.materialize_symlink_destination_tasks(
+            &stack,
+            &event_dispatcher,
+            path,
+            deps,
+            batches,
+        );
+
+        // Attributed to the command that started the materialization, once it succeeds.
+        let performed = entry_and_method.as_ref().map(|(entry, method)| {
+            (
+                event_dispatcher.trace_id().dupe(),
+                entry.dupe(),
+                method.dupe(),
+            )
+        });
+        let stats = self.stats.dupe();
 
         let materialize_entry = if let Some((entry, method)) = entry_and_method {
             let io = self.io.dupe();
             let path_buf = path.to_buf();
+            let batched = batches.take(path);
             let cancellations = CancellationContext::never_cancelled(); // spawned
+            // Local copies and writes are the materializations competing with local actions for
+            // the disk. CAS downloads are limited by the RE client instead, see `io_pressure`.
+            let io_pressure = self.io_pressure.dupe().filter(|_| {
+                matches!(
+                    method.as_ref(),
+                    ArtifactMaterializationMethod::LocalCopy(..)
+                        | ArtifactMaterializationMethod::Write(..)
+                )
+            });
+            let trace_id = event_dispatcher.trace_id().dupe();
             Either::Left(async move {
-                io.materialize_entry(path_buf, method, entry, event_dispatcher, cancellations)
-                    .await
+                match batched {
+                    Some((batch, i)) => batch.await[i].clone(),
+                    None => {
+                        let _permit = match io_pressure {
+                            Some(io_pressure) => Some(
+                                io_pressure
+                                    .acquire(&trace_id, entry.calc_output_count_and_bytes().bytes)
+                                    .await,
+                            ),
+                            None => None,
+                        };
+                        io.materialize_entry(
+                            path_buf,
+                            method,
+                            entry,
+                            event_dispatcher,
+                            cancellations,
+                        )
+                        .await
+                        .map_err(SharedMaterializingError::from)
+                    }
+                }
             })
         } else {
             Either::Right(future::ready(Ok(())))
@@ -1123,6 +1851,7 @@ impl<T: IoHandler> DeferredMaterializerCommandProcessor<T> {
         let task = self
             .spawn(async move {
                 let timestamp = Utc::now();
+                let started = std::time::Instant::now();
                 // Materialize the deps and this entry. Regardless of whether this succeeds or fails we
                 // need to notify the materializer, so don't check the result.
                 let res = Self::perform_materialization(
@@ -1133,15 +1862,24 @@ impl<T: IoHandler> DeferredMaterializerCommandProcessor<T> {
                 )
                 .await;
 
+                if let (Ok(()), Some((trace_id, entry, method))) = (&res, performed) {
+                    stats
+                        .command_stats
+                        .record_materialization(&trace_id, &method, || {
+                            entry.calc_output_count_and_bytes().bytes
+                        });
+                }
+
                 // Materialization finished, notify the command thread
-                let _ignored = command_sender.send_low_priority(
-                    LowPriorityMaterializerCommand::MaterializationFinished {
+                let _ignored = command_sender
+                    .send_low_priority(LowPriorityMaterializerCommand::MaterializationFinished {
                         path: path_buf,
                         timestamp,
+                        started,
                         version,
                         result: res.dupe(),
-                    },
-                );
+                    })
+                    .await;
 
                 res
             })
@@ -1162,7 +1900,7 @@ impl<T: IoHandler> DeferredMaterializerCommandProcessor<T> {
         cleaning_future: Option<CleaningFuture>,
         materialize_copy_source_tasks: Vec<MaterializingFuture>,
         materialize_symlink_destination_tasks: Vec<MaterializingFuture>,
-        materialize_entry: impl Future<Output = Result<(), MaterializeEntryError>>,
+        materialize_entry: impl Future<Output = Result<(), SharedMaterializingError>>,
     ) -> Result<(), SharedMaterializingError> {
         // If there is an existing future trying to delete conflicting paths, we must wait for it
         // to finish before we can start materialization.
@@ -1202,6 +1940,7 @@ impl<T: IoHandler> DeferredMaterializerCommandProcessor<T> {
         event_dispatcher: &EventDispatcher,
         path: &ProjectRelativePath,
         deps: Option<ActionSharedDirectory>,
+        batches: &mut CasBatches,
     ) -> Vec<MaterializingFuture> {
         if let Some(deps) = deps.as_ref() {
             self.tree
@@ -1212,6 +1951,7 @@ impl<T: IoHandler> DeferredMaterializerCommandProcessor<T> {
                         MaterializeStack::Child(&stack, path),
                         p.as_ref(),
                         event_dispatcher.dupe(),
+                        batches,
                     )
                 })
                 .collect::<Vec<_>>()
@@ -1226,6 +1966,7 @@ impl<T: IoHandler> DeferredMaterializerCommandProcessor<T> {
         event_dispatcher: &EventDispatcher,
         path: &ProjectRelativePath,
         method: Option<&ArtifactMaterializationMethod>,
+        batches: &mut CasBatches,
     ) -> Vec<MaterializingFuture> {
         match method {
             Some(ArtifactMaterializationMethod::LocalCopy(_, copied_artifacts)) => copied_artifacts
@@ -1235,6 +1976,7 @@ impl<T: IoHandler> DeferredMaterializerCommandProcessor<T> {
                         MaterializeStack::Child(&stack, path),
                         a.src.as_ref(),
                         event_dispatcher.dupe(),
+                        batches,
                     )
                 })
                 .collect::<Vec<_>>(),
@@ -1247,6 +1989,7 @@ impl<T: IoHandler> DeferredMaterializerCommandProcessor<T> {
         &mut self,
         artifact_path: ProjectRelativePathBuf,
         timestamp: DateTime<Utc>,
+        started: std::time::Instant,
         version: Version,
         result: Result<(), SharedMaterializingError>,
     ) {
@@ -1297,10 +2040,10 @@ impl<T: IoHandler> DeferredMaterializerCommandProcessor<T> {
                             tracing::debug!("artifact is already materialized");
                             None
                         }
-                        ArtifactMaterializationStage::Declared {
-                            entry,
-                            method: _method,
-                        } => {
+                        ArtifactMaterializationStage::Declared { entry, method, .. } => {
+                            self.stats
+                                .materialization_latencies
+                                .record(method, started.elapsed());
                             let metadata = ArtifactMetadata::new(entry);
                             // NOTE: We only insert this artifact if there isn't an in-progress cleanup
                             // future on this path.
@@ -1347,6 +2090,45 @@ impl<T: IoHandler> DeferredMaterializerCommandProcessor<T> {
     }
 }
 
+/// If declaring `entry` from `owner` over `data` conflicts with it, returns what declared `data`
+/// and its entry. Declares conflict if they come from different spans of the same command, with
+/// different contents. Declares from later commands, or from the same span (e.g. retries), replace
+/// the artifact as usual.
+fn declare_conflict(
+    data: &ArtifactMaterializationData,
+    entry: &ActionDirectoryEntry<ActionSharedDirectory>,
+    owner: &DeclareOwner,
+) -> Option<(Arc<DeclareOwner>, String)> {
+    let first_owner = data.declared_by.as_ref()?;
+    if first_owner.trace_id != owner.trace_id || **first_owner == *owner {
+        return None;
+    }
+    let first_entry = match &data.stage {
+        ArtifactMaterializationStage::Declared {
+            entry: first_entry, ..
+        } => {
+            if first_entry == entry {
+                return None;
+            }
+            ArtifactMetadata::new(first_entry)
+        }
+        ArtifactMaterializationStage::Materialized { metadata, .. } => {
+            if metadata.matches_entry(entry) {
+                return None;
+            }
+            metadata.dupe()
+        }
+    };
+    Some((first_owner.dupe(), first_entry.0.to_string()))
+}
+
+fn display_span(span_id: Option<SpanId>) -> String {
+    match span_id {
+        Some(span_id) => span_id.to_string(),
+        None => "<none>".to_owned(),
+    }
+}
+
 /// Run callbacks for an artifact being materialized at `path`.
 fn on_materialization(
     sqlite_db: Option<&mut MaterializerStateSqliteDb>,
@@ -1421,6 +2203,79 @@ impl ExistingFutures {
     }
 }
 
+/// A CAS download shared by several artifacts. Resolves to one result per artifact.
+type CasBatchFuture = Shared<BoxFuture<'static, Arc<Vec<Result<(), SharedMaterializingError>>>>>;
+
+/// Artifacts declared with the same `CasDownloadInfo`, along with any cleanup that must finish
+/// before they can be downloaded.
+struct CasBatchGroup {
+    info: Arc<CasDownloadInfo>,
+    entries: Vec<(
+        ProjectRelativePathBuf,
+        ActionDirectoryEntry<ActionSharedDirectory>,
+        Option<CleaningFuture>,
+    )>,
+}
+
+/// Artifacts that are materialized as part of a batched CAS download. See `plan_cas_batches`.
+#[derive(Default)]
+struct CasBatches(HashMap<ProjectRelativePathBuf, (CasBatchFuture, usize)>);
+
+impl CasBatches {
+    fn add<T: IoHandler>(
+        &mut self,
+        group: CasBatchGroup,
+        io: &Arc<T>,
+        event_dispatcher: &EventDispatcher,
+    ) {
+        let CasBatchGroup { info, entries } = group;
+        let paths = entries.map(|(path, ..)| path.clone());
+        let io = io.dupe();
+        let event_dispatcher = event_dispatcher.dupe();
+
+        let batch = async move {
+            let mut results = vec![Ok(()); entries.len()];
+            let mut indices = Vec::with_capacity(entries.len());
+            let mut to_download = Vec::with_capacity(entries.len());
+            for (i, (path, entry, cleaning)) in entries.into_iter().enumerate() {
+                // The artifact's own task waits for its cleanup before the batch and reports the
+                // error if it fails, so it's enough to leave it out of the download.
+                if let Some(cleaning) = cleaning {
+                    if cleaning.await.is_err() {
+                        continue;
+                    }
+                }
+                indices.push(i);
+                to_download.push((path, entry));
+            }
+
+            let downloaded = io
+                .materialize_entries(
+                    info,
+                    to_download,
+                    event_dispatcher,
+                    CancellationContext::never_cancelled(), // spawned
+                )
+                .await;
+            for (i, res) in indices.into_iter().zip(downloaded) {
+                results[i] = res.map_err(SharedMaterializingError::from);
+            }
+            Arc::new(results)
+        }
+        .boxed()
+        .shared();
+
+        for (i, path) in paths.into_iter().enumerate() {
+            self.0.insert(path, (batch.clone(), i));
+        }
+    }
+
+    /// The batched download that materializes the artifact at `path`, and the index of its result.
+    fn take(&mut self, path: &ProjectRelativePath) -> Option<(CasBatchFuture, usize)> {
+        self.0.remove(path)
+    }
+}
+
 /// Exposing methods for testing purposes only.
 #[cfg(test)]
 #[doc(hidden)]
@@ -1432,6 +2287,13 @@ pub(super) trait TestingDeferredMaterializerCommandProcessor<T> {
 
     fn testing_declare(&mut self, path: &ProjectRelativePath, value: ArtifactValue);
 
+    fn testing_declare_with_context(
+        &mut self,
+        path: &ProjectRelativePath,
+        value: ArtifactValue,
+        context: DeclareContext,
+    ) -> buck2_error::Result<()>;
+
     fn testing_process_one_command(&mut self, command: MaterializerCommand<T>);
 
     fn testing_materialization_finished(
@@ -1440,6 +2302,10 @@ pub(super) trait TestingDeferredMaterializerCommandProcessor<T> {
         timestamp: DateTime<Utc>,
         result: Result<(), SharedMaterializingError>,
     );
+
+    fn testing_sweep_artifact_tree(&mut self, idle_ttl: Option<std::time::Duration>);
+
+    fn testing_stats(&self) -> &DeferredMaterializerStats;
 }
 
 #[cfg(test)]
@@ -1452,7 +2318,7 @@ impl<T: IoHandler> TestingDeferredMaterializerCommandProcessor<T>
     }
 
     fn testing_declare_existing(&mut self, path: &ProjectRelativePath, value: ArtifactValue) {
-        self.declare_existing(path, value)
+        self.declare_existing(path, value, None)
     }
 
     fn testing_process_one_low_priority_command(
@@ -1463,7 +2329,28 @@ impl<T: IoHandler> TestingDeferredMaterializerCommandProcessor<T>
     }
 
     fn testing_declare(&mut self, path: &ProjectRelativePath, value: ArtifactValue) {
-        self.declare(path, value, Box::new(ArtifactMaterializationMethod::Test))
+        let (context, _) = DeclareContext::new(EventDispatcher::null(), false);
+        self.declare(
+            path,
+            value,
+            Box::new(ArtifactMaterializationMethod::Test),
+            &context,
+        )
+        .unwrap()
+    }
+
+    fn testing_declare_with_context(
+        &mut self,
+        path: &ProjectRelativePath,
+        value: ArtifactValue,
+        context: DeclareContext,
+    ) -> buck2_error::Result<()> {
+        self.declare(
+            path,
+            value,
+            Box::new(ArtifactMaterializationMethod::Test),
+            &context,
+        )
     }
 
     fn testing_process_one_command(&mut self, command: MaterializerCommand<T>) {
@@ -1479,8 +2366,17 @@ impl<T: IoHandler> TestingDeferredMaterializerCommandProcessor<T>
         self.materialization_finished(
             artifact_path,
             timestamp,
+            std::time::Instant::now(),
             self.version_tracker.current(),
             result,
         )
     }
+
+    fn testing_sweep_artifact_tree(&mut self, idle_ttl: Option<std::time::Duration>) {
+        self.sweep_artifact_tree(idle_ttl)
+    }
+
+    fn testing_stats(&self) -> &DeferredMaterializerStats {
+        &self.stats
+    }
 }
@@ -0,0 +1,110 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Accounting of the materializer work done on behalf of each command, keyed by the trace id of
+//! the command's event dispatcher.
+
+use std::collections::HashMap;
+use std::time::Duration;
+use std::time::Instant;
+
+use buck2_wrapper_common::invocation_id::TraceId;
+use dupe::Dupe;
+use parking_lot::Mutex;
+
+use crate::materializers::deferred::artifact_tree::ArtifactMaterializationMethod;
+
+/// Stats of a command nobody collected (e.g. because the server went away before the command
+/// finished) are dropped once they haven't been updated for this long.
+const COMMAND_STATS_TTL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Default)]
+struct CommandStats {
+    ensures: u64,
+    already_materialized: u64,
+    materializations_by_method: HashMap<&'static str, u64>,
+    bytes_fetched: u64,
+}
+
+#[derive(Default)]
+pub(super) struct CommandStatsByTrace {
+    stats: Mutex<HashMap<TraceId, (CommandStats, Instant)>>,
+}
+
+impl CommandStatsByTrace {
+    fn update(&self, trace_id: &TraceId, f: impl FnOnce(&mut CommandStats)) {
+        let now = Instant::now();
+        let mut stats = self.stats.lock();
+        if !stats.contains_key(trace_id) {
+            // New commands are rare compared to updates, so that's when we drop stale entries.
+            stats
+                .retain(|_, (_, last_update)| now.duration_since(*last_update) < COMMAND_STATS_TTL);
+        }
+        let (command, last_update) = stats
+            .entry(trace_id.dupe())
+            .or_insert_with(|| (CommandStats::default(), now));
+        f(command);
+        *last_update = now;
+    }
+
+    /// `ensures` artifacts were requested, of which `already_materialized` needed no work.
+    pub(super) fn record_ensure(
+        &self,
+        trace_id: &TraceId,
+        ensures: u64,
+        already_materialized: u64,
+    ) {
+        self.update(trace_id, |stats| {
+            stats.ensures += ensures;
+            stats.already_materialized += already_materialized;
+        });
+    }
+
+    /// An artifact was materialized with `method`. `bytes` is its size, only computed if it was
+    /// fetched.
+    pub(super) fn record_materialization(
+        &self,
+        trace_id: &TraceId,
+        method: &ArtifactMaterializationMethod,
+        bytes: impl FnOnce() -> u64,
+    ) {
+        let (name, fetched) = match method {
+            ArtifactMaterializationMethod::LocalCopy(..) => ("local_copy", false),
+            ArtifactMaterializationMethod::Write(..) => ("write", false),
+            ArtifactMaterializationMethod::CasDownload { .. } => ("cas_download", true),
+            ArtifactMaterializationMethod::HttpDownload { .. } => ("http_download", true),
+            #[cfg(test)]
+            ArtifactMaterializationMethod::Test => ("test", true),
+        };
+        let bytes = if fetched { bytes() } else { 0 };
+        self.update(trace_id, |stats| {
+            *stats.materializations_by_method.entry(name).or_default() += 1;
+            stats.bytes_fetched += bytes;
+        });
+    }
+
+    /// Removes and returns the stats of the command with `trace_id`, `None` if the materializer
+    /// did nothing for it.
+    pub(super) fn take(
+        &self,
+        trace_id: &TraceId,
+    ) -> Option<buck2_data::CommandMaterializationStats> {
+        let (stats, _) = self.stats.lock().remove(trace_id)?;
+        Some(buck2_data::CommandMaterializationStats {
+            ensures: stats.ensures,
+            already_materialized: stats.already_materialized,
+            materializations_by_method: stats
+                .materializations_by_method
+                .into_iter()
+                .map(|(method, count)| (method.to_owned(), count))
+                .collect(),
+            bytes_fetched: stats.bytes_fetched,
+        })
+    }
+}
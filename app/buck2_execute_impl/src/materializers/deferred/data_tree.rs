@@ -27,6 +27,7 @@ use std::collections::hash_map::Iter;
 use std::hash::Hash;
 use std::marker::PhantomData;
 
+use allocative::Allocative;
 use buck2_error::buck2_error;
 
 /// Tree that stores data in the leaves. Think of the key as the path to the
@@ -34,7 +35,7 @@ use buck2_error::buck2_error;
 /// is of type `K` (making the key to a value a sequence of `K`).
 /// TODO(scottcao): This trie is not implemented properly. It should be merged into the directory trie
 /// we have at buck2_core/src/directory.
-#[derive(Debug)]
+#[derive(Debug, Allocative)]
 pub enum DataTree<K, V> {
     /// Stores data of type `V` with key of type `Iterator<Item = K>`.
     Tree(HashMap<K, DataTree<K, V>>),
@@ -198,6 +199,24 @@ impl<K: 'static + Eq + Hash + Clone, V: 'static> DataTree<K, V> {
         }
     }
 
+    /// Returns the subtree that [`remove`](Self::remove) would remove for `key`, without modifying
+    /// the tree. The same amount of `key` is consumed.
+    pub fn get<'a, I, Q>(&self, mut key: I) -> Option<&DataTree<K, V>>
+    where
+        K: 'a + Borrow<Q>,
+        Q: 'a + Hash + Eq + ?Sized,
+        I: Iterator<Item = &'a Q>,
+    {
+        if matches!(self, Self::Data(_)) {
+            return Some(self);
+        }
+        if let Some(k) = key.next() {
+            self.children().unwrap().get(k)?.get(key)
+        } else {
+            Some(self)
+        }
+    }
+
     pub fn children(&self) -> Option<&HashMap<K, DataTree<K, V>>> {
         match self {
             Self::Tree(children) => Some(children),
@@ -10,7 +10,6 @@
 use std::fmt;
 use std::fmt::Debug;
 use std::fmt::Display;
-use std::fmt::Write;
 use std::sync::Arc;
 
 use async_trait::async_trait;
@@ -24,6 +23,9 @@ use buck2_execute::materialize::materializer::DeferredMaterializerEntry;
 use buck2_execute::materialize::materializer::DeferredMaterializerExtensions;
 use buck2_execute::materialize::materializer::DeferredMaterializerIterItem;
 use buck2_execute::materialize::materializer::DeferredMaterializerSubscription;
+use buck2_execute::materialize::materializer::TestIterTimings;
+use buck2_execute::materialize::materializer::TtlRefreshLogEntry;
+use buck2_wrapper_common::invocation_id::TraceId;
 use chrono::DateTime;
 use chrono::Duration;
 use chrono::TimeZone;
@@ -46,12 +48,16 @@ use crate::materializers::deferred::DeferredMaterializerCommandProcessor;
 use crate::materializers::deferred::MaterializerCommand;
 use crate::materializers::deferred::Processing;
 use crate::materializers::deferred::ProcessingFuture;
+use crate::materializers::deferred::TtlRefreshMethods;
 use crate::materializers::deferred::clean_stale::CleanStaleArtifactsCommand;
 use crate::materializers::deferred::clean_stale::CleanStaleArtifactsExtensionCommand;
 use crate::materializers::deferred::io_handler::IoHandler;
 use crate::materializers::deferred::io_handler::create_ttl_refresh;
 use crate::materializers::deferred::subscriptions::MaterializerSubscriptionOperation;
 
+/// A command run on the materializer command loop. It runs synchronously, so it must not block;
+/// work that depends on materializations or cleans finishing is scheduled through
+/// `DeferredMaterializerCommandProcessor::scheduling`, see `scheduling` for the rules to follow.
 pub(super) trait ExtensionCommand<T>: Debug + Sync + Send + 'static {
     fn execute(self: Box<Self>, processor: &mut DeferredMaterializerCommandProcessor<T>);
 }
@@ -121,7 +127,7 @@ struct Iterate {
 impl<T: IoHandler> ExtensionCommand<T> for Iterate {
     fn execute(self: Box<Self>, processor: &mut DeferredMaterializerCommandProcessor<T>) {
         // Ensure up to date access times
-        processor.flush_access_times(0);
+        processor.flush_access_times(0, None);
         for (path, data) in processor.tree.iter_with_paths() {
             let stage = match &data.stage {
                 ArtifactMaterializationStage::Declared { method, .. } => {
@@ -250,6 +256,7 @@ impl<T: IoHandler> ExtensionCommand<T> for RefreshTtls {
             &processor.tree,
             processor.io.re_client_manager(),
             Duration::seconds(self.min_ttl),
+            TtlRefreshMethods::default(),
             processor.io.digest_config(),
         )
         .map(|f| processor.spawn(f));
@@ -260,7 +267,8 @@ impl<T: IoHandler> ExtensionCommand<T> for RefreshTtls {
 #[derive(Derivative)]
 #[derivative(Debug)]
 struct GetTtlRefreshLog {
-    sender: Sender<String>,
+    sender: Sender<Vec<TtlRefreshLogEntry>>,
+    last: Option<usize>,
 }
 
 impl<T: IoHandler> ExtensionCommand<T> for GetTtlRefreshLog {
@@ -268,38 +276,35 @@ impl<T: IoHandler> ExtensionCommand<T> for GetTtlRefreshLog {
         // We normally poll this very lazily, so actually force it to happen here.
         processor.poll_current_ttl_refresh();
 
-        let mut out = String::new();
-
-        for entry in &processor.ttl_refresh_history {
-            write!(&mut out, "{:?}\t", entry.at).unwrap();
-            match &entry.outcome {
-                None => {
-                    writeln!(&mut out, "SKIP").unwrap();
-                }
-                Some(Ok(())) => {
-                    writeln!(&mut out, "OK").unwrap();
-                }
-                Some(Err(e)) => {
-                    writeln!(&mut out, "ERR\t{:#}", e).unwrap();
-                }
-            }
-        }
-
-        let _ignored = self.sender.send(out);
+        let history = &processor.ttl_refresh_history;
+        let skip = self
+            .last
+            .map_or(0, |last| history.len().saturating_sub(last));
+        let log = history
+            .iter()
+            .skip(skip)
+            .map(|entry| TtlRefreshLogEntry {
+                at: entry.at,
+                outcome: entry
+                    .outcome
+                    .as_ref()
+                    .map(|outcome| outcome.as_ref().map(|_| ()).map_err(|e| format!("{:#}", e))),
+            })
+            .collect();
+
+        let _ignored = self.sender.send(log);
     }
 }
 
 #[derive(Derivative)]
 #[derivative(Debug)]
 struct TestIter {
-    sender: Sender<String>,
+    sender: Sender<TestIterTimings>,
     count: usize,
 }
 
 impl<T> ExtensionCommand<T> for TestIter {
     fn execute(self: Box<Self>, processor: &mut DeferredMaterializerCommandProcessor<T>) {
-        let mut out = String::new();
-
         let now = std::time::Instant::now();
 
         for _i in 0..self.count {
@@ -310,14 +315,7 @@ impl<T> ExtensionCommand<T> for TestIter {
             }
         }
 
-        writeln!(
-            &mut out,
-            "Elapsed for iter() ({} times): {:?}",
-            self.count,
-            now.elapsed()
-        )
-        .unwrap();
-
+        let iter = now.elapsed();
         let now = std::time::Instant::now();
 
         for _i in 0..self.count {
@@ -328,15 +326,10 @@ impl<T> ExtensionCommand<T> for TestIter {
             }
         }
 
-        writeln!(
-            &mut out,
-            "Elapsed for iter().with_paths() ({} times): {:?}",
-            self.count,
-            now.elapsed()
-        )
-        .unwrap();
-
-        let _ignored = self.sender.send(out);
+        let _ignored = self.sender.send(TestIterTimings {
+            iter,
+            iter_with_paths: now.elapsed(),
+        });
     }
 }
 
@@ -348,10 +341,36 @@ struct FlushAccessTimes {
 
 impl<T: IoHandler> ExtensionCommand<T> for FlushAccessTimes {
     fn execute(self: Box<Self>, processor: &mut DeferredMaterializerCommandProcessor<T>) {
-        let mut out = String::new();
+        // Replies once the flush has been written to sqlite.
+        processor.flush_access_times(0, Some(self.sender));
+    }
+}
+
+#[derive(Derivative)]
+#[derivative(Debug)]
+struct PendingByTrace {
+    sender: Sender<Vec<(TraceId, usize)>>,
+}
+
+impl<T: IoHandler> ExtensionCommand<T> for PendingByTrace {
+    fn execute(self: Box<Self>, processor: &mut DeferredMaterializerCommandProcessor<T>) {
+        let pending = processor
+            .io_pressure
+            .as_ref()
+            .map_or_else(Vec::new, |io_pressure| io_pressure.pending_by_trace());
+        let _ignored = self.sender.send(pending);
+    }
+}
+
+#[derive(Derivative)]
+#[derivative(Debug)]
+struct MeasureArtifactTree {
+    sender: Sender<u64>,
+}
 
-        writeln!(&mut out, "{}", processor.flush_access_times(0)).unwrap();
-        let _ignored = self.sender.send(out);
+impl<T: IoHandler> ExtensionCommand<T> for MeasureArtifactTree {
+    fn execute(self: Box<Self>, processor: &mut DeferredMaterializerCommandProcessor<T>) {
+        let _ignored = self.sender.send(processor.measure_artifact_tree());
     }
 }
 
@@ -405,11 +424,14 @@ impl<T: IoHandler> DeferredMaterializerExtensions for DeferredMaterializerAccess
         Ok(())
     }
 
-    async fn get_ttl_refresh_log(&self) -> buck2_error::Result<String> {
+    async fn get_ttl_refresh_log(
+        &self,
+        last: Option<usize>,
+    ) -> buck2_error::Result<Vec<TtlRefreshLogEntry>> {
         let (sender, receiver) = oneshot::channel();
         self.command_sender
             .send(MaterializerCommand::Extension(
-                Box::new(GetTtlRefreshLog { sender }) as _,
+                Box::new(GetTtlRefreshLog { sender, last }) as _,
             ))?;
         receiver
             .await
@@ -421,6 +443,10 @@ impl<T: IoHandler> DeferredMaterializerExtensions for DeferredMaterializerAccess
         keep_since_time: DateTime<Utc>,
         dry_run: bool,
         tracked_only: bool,
+        cells: Vec<String>,
+        max_bytes_per_run: Option<u64>,
+        max_files_per_run: Option<u64>,
+        keep_paths: Vec<ProjectRelativePathBuf>,
     ) -> buck2_error::Result<buck2_cli_proto::CleanStaleResponse> {
         let dispatcher = get_dispatcher();
         let (sender, recv) = oneshot::channel();
@@ -431,6 +457,10 @@ impl<T: IoHandler> DeferredMaterializerExtensions for DeferredMaterializerAccess
                         keep_since_time,
                         dry_run,
                         tracked_only,
+                        cells,
+                        max_bytes_per_run,
+                        max_files_per_run,
+                        keep_paths,
                         dispatcher,
                     },
                     sender,
@@ -439,7 +469,19 @@ impl<T: IoHandler> DeferredMaterializerExtensions for DeferredMaterializerAccess
         recv.await?.await.map(|res| res.into())
     }
 
-    async fn test_iter(&self, count: usize) -> buck2_error::Result<String> {
+    async fn preview_invalidate(
+        &self,
+        paths: Vec<ProjectRelativePathBuf>,
+    ) -> buck2_error::Result<Vec<(ProjectRelativePathBuf, &'static str)>> {
+        let (sender, receiver) = oneshot::channel();
+        self.command_sender
+            .send(MaterializerCommand::PreviewInvalidate(paths, sender))?;
+        receiver
+            .await
+            .buck_error_context("No response from materializer")
+    }
+
+    async fn test_iter(&self, count: usize) -> buck2_error::Result<TestIterTimings> {
         let (sender, receiver) = oneshot::channel();
         self.command_sender
             .send(MaterializerCommand::Extension(
@@ -461,6 +503,26 @@ impl<T: IoHandler> DeferredMaterializerExtensions for DeferredMaterializerAccess
             .buck_error_context("No response from materializer")
     }
 
+    async fn pending_by_trace(&self) -> buck2_error::Result<Vec<(TraceId, usize)>> {
+        let (sender, receiver) = oneshot::channel();
+        self.command_sender.send(MaterializerCommand::Extension(
+            Box::new(PendingByTrace { sender }) as _,
+        ))?;
+        receiver
+            .await
+            .buck_error_context("No response from materializer")
+    }
+
+    async fn measure_artifact_tree(&self) -> buck2_error::Result<u64> {
+        let (sender, receiver) = oneshot::channel();
+        self.command_sender.send(MaterializerCommand::Extension(
+            Box::new(MeasureArtifactTree { sender }) as _,
+        ))?;
+        receiver
+            .await
+            .buck_error_context("No response from materializer")
+    }
+
     async fn create_subscription(
         &self,
     ) -> buck2_error::Result<Box<dyn DeferredMaterializerSubscription>> {
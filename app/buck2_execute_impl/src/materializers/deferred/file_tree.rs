@@ -151,6 +151,32 @@ impl<V: 'static> FileTree<V> {
         assert!(path_buf.is_empty());
     }
 
+    /// Returns the pairs of path and entry that `remove_path` would remove from the tree, without
+    /// removing them.
+    pub fn get_path_entries(
+        &self,
+        path: &ProjectRelativePath,
+    ) -> Vec<(ProjectRelativePathBuf, &V)> {
+        let mut path_iter = path.iter();
+        let found = self.get(&mut path_iter);
+
+        let mut path = path;
+        // Rewind the `path` up to the entry we *actually* found.
+        for _ in path_iter {
+            path = path
+                .parent()
+                .expect("Path iterator cannot cause us to rewind past the last parent");
+        }
+
+        match found {
+            Some(tree) => tree
+                .iter_with_paths()
+                .map(|(k, v)| (path.join(k), v))
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
     /// Removes path from FileTree. Returns an iterator of pairs of path and entry removed
     /// from the tree.
     pub fn remove_path(
@@ -15,12 +15,18 @@ use std::sync::Arc;
 use allocative::Allocative;
 use async_trait::async_trait;
 use buck2_common::file_ops::FileDigest;
+use buck2_common::file_ops::FileDigestConfig;
+use buck2_common::file_ops::FileMetadata;
+use buck2_common::file_ops::TrackedFileDigest;
 use buck2_core::buck2_env;
+use buck2_core::execution_types::executor_config::RemoteExecutorUseCase;
 use buck2_core::fs::fs_util;
 use buck2_core::fs::fs_util::IoError;
 use buck2_core::fs::fs_util::ReadDir;
 use buck2_core::fs::paths::abs_norm_path::AbsNormPathBuf;
+use buck2_core::fs::paths::abs_path::AbsPathBuf;
 use buck2_core::fs::project::ProjectRoot;
+use buck2_core::fs::project_rel_path::ProjectRelativePath;
 use buck2_core::fs::project_rel_path::ProjectRelativePathBuf;
 use buck2_directory::directory::directory::Directory;
 use buck2_directory::directory::directory_iterator::DirectoryIterator;
@@ -38,10 +44,13 @@ use buck2_execute::digest_config::DigestConfig;
 use buck2_execute::directory::ActionDirectoryEntry;
 use buck2_execute::directory::ActionDirectoryMember;
 use buck2_execute::directory::ActionSharedDirectory;
+use buck2_execute::directory::INTERNER;
+use buck2_execute::entry::build_entry_from_disk;
 use buck2_execute::execute::blocking::BlockingExecutor;
 use buck2_execute::execute::blocking::IoRequest;
 use buck2_execute::execute::clean_output_paths::cleanup_path;
 use buck2_execute::materialize::http::http_download;
+use buck2_execute::materialize::materializer::CasDownloadInfo;
 use buck2_execute::materialize::materializer::CasNotFoundError;
 use buck2_execute::materialize::materializer::WriteRequest;
 use buck2_execute::output_size::OutputSize;
@@ -71,11 +80,14 @@ use crate::materializers::deferred::LowPriorityMaterializerCommand;
 use crate::materializers::deferred::MaterializeEntryError;
 use crate::materializers::deferred::MaterializerSender;
 use crate::materializers::deferred::SharedMaterializingError;
+use crate::materializers::deferred::TtlRefreshMethods;
 use crate::materializers::deferred::Version;
 use crate::materializers::deferred::WriteFile;
+use crate::materializers::deferred::artifact_tree::ArtifactMetadata;
 use crate::materializers::deferred::artifact_tree::MaterializationMethodToProto;
 use crate::materializers::deferred::clean_stale::CleanInvalidatedPathRequest;
 use crate::materializers::immediate;
+use crate::materializers::io::ExportArtifact;
 use crate::materializers::io::MaterializeTreeStructure;
 use crate::materializers::io::materialize_files;
 
@@ -95,6 +107,16 @@ struct MaterializationStat {
     total_bytes: u64,
 }
 
+impl MaterializationStat {
+    fn add_cas_files(&mut self, files: &[NamedDigestWithPermissions]) {
+        self.file_count += u64::try_from(files.len()).unwrap_or_default();
+        self.total_bytes += files
+            .iter()
+            .map(|x| u64::try_from(x.named_digest.digest.size_in_bytes).unwrap_or_default())
+            .sum::<u64>();
+    }
+}
+
 #[async_trait]
 pub trait IoHandler: Sized + Sync + Send + 'static {
     fn write<'a>(
@@ -134,12 +156,42 @@ pub trait IoHandler: Sized + Sync + Send + 'static {
         cancellations: &CancellationContext,
     ) -> Result<(), MaterializeEntryError>;
 
+    /// Materializes several entries that are all downloaded from the CAS using `info`, with a
+    /// single download. Returns one result per entry, in order.
+    async fn materialize_entries(
+        self: &Arc<Self>,
+        info: Arc<CasDownloadInfo>,
+        entries: Vec<(
+            ProjectRelativePathBuf,
+            ActionDirectoryEntry<ActionSharedDirectory>,
+        )>,
+        event_dispatcher: EventDispatcher,
+        cancellations: &CancellationContext,
+    ) -> Vec<Result<(), MaterializeEntryError>>;
+
+    /// Copies the materialized artifact at `path` to `dest`.
+    async fn export_artifact(
+        self: &Arc<Self>,
+        path: ProjectRelativePathBuf,
+        dest: AbsPathBuf,
+        cancellations: &CancellationContext,
+    ) -> buck2_error::Result<()>;
+
     fn create_ttl_refresh(
         self: &Arc<Self>,
         tree: &ArtifactTree,
         min_ttl: Duration,
+        methods: TtlRefreshMethods,
     ) -> Option<BoxFuture<'static, buck2_error::Result<()>>>;
 
+    /// Hashes what is on disk at `path` again, and checks that it matches `metadata`. Missing
+    /// paths don't match.
+    async fn verify_materialized(
+        self: &Arc<Self>,
+        path: ProjectRelativePathBuf,
+        metadata: ArtifactMetadata,
+    ) -> buck2_error::Result<bool>;
+
     fn read_dir(&self, path: &AbsNormPathBuf) -> Result<ReadDir, IoError>;
     fn buck_out_path(&self) -> &ProjectRelativePathBuf;
     fn re_client_manager(&self) -> &Arc<ReConnectionManager>;
@@ -165,6 +217,82 @@ impl DefaultIoHandler {
             http_client,
         }
     }
+    /// The files in `entry` that must be downloaded from the CAS to materialize it at `path`.
+    fn cas_files(
+        &self,
+        path: &ProjectRelativePath,
+        entry: &ActionDirectoryEntry<ActionSharedDirectory>,
+    ) -> buck2_error::Result<Vec<NamedDigestWithPermissions>> {
+        let mut files = Vec::new();
+        let mut walk = unordered_entry_walk(entry.as_ref().map_dir(Directory::as_ref));
+
+        while let Some((entry_path, entry)) = walk.next() {
+            if let DirectoryEntry::Leaf(ActionDirectoryMember::File(f)) = entry {
+                let name = path.join(entry_path.get());
+                let digest = maybe_tombstone_digest(f.digest.data())?.to_re();
+
+                tracing::trace!(name = %name, digest = %digest, "push download");
+                let name = self
+                    .fs
+                    .resolve(&name)
+                    .as_maybe_relativized_str()?
+                    .to_owned();
+
+                files.push(NamedDigestWithPermissions {
+                    named_digest: NamedDigest {
+                        name,
+                        digest,
+                        ..Default::default()
+                    },
+                    is_executable: f.is_executable,
+                    ..Default::default()
+                });
+            }
+        }
+        Ok(files)
+    }
+
+    async fn download_cas_files(
+        &self,
+        info: &CasDownloadInfo,
+        files: Vec<NamedDigestWithPermissions>,
+    ) -> buck2_error::Result<()> {
+        let connection = self.re_client_manager.get_re_connection();
+        let re_client = connection.get_client().with_use_case(info.re_use_case);
+        re_client.materialize_files(files).await
+    }
+
+    async fn materialize_entries_span(
+        &self,
+        info: &CasDownloadInfo,
+        entries: &[(
+            ProjectRelativePathBuf,
+            ActionDirectoryEntry<ActionSharedDirectory>,
+        )],
+        stat: &mut MaterializationStat,
+        cancellations: &CancellationContext,
+    ) -> buck2_error::Result<()> {
+        let mut files = Vec::new();
+        for (path, entry) in entries {
+            self.io_executor
+                .execute_io(
+                    Box::new(MaterializeTreeStructure {
+                        path: path.clone(),
+                        entry: entry.dupe(),
+                    }),
+                    cancellations,
+                )
+                .await?;
+            files.extend(self.cas_files(path, entry)?);
+        }
+        stat.add_cas_files(&files);
+        if files.is_empty() {
+            // Only empty directories and symlinks, created with the tree structure.
+            return Ok(());
+        }
+        self.download_cas_files(info, files).await
+    }
+
     /// Materializes an `entry` at `path`, using the materialization `method`
     #[instrument(level = "debug", skip(self, stat, cancellations), fields(path = %path, method = %method, entry = %entry))]
     async fn materialize_entry_span(
@@ -189,64 +317,14 @@ impl DefaultIoHandler {
         // Materialize files
         match method.as_ref() {
             ArtifactMaterializationMethod::CasDownload { info } => {
-                let mut files = Vec::new();
-
-                {
-                    let mut walk = unordered_entry_walk(entry.as_ref().map_dir(Directory::as_ref));
-
-                    while let Some((entry_path, entry)) = walk.next() {
-                        if let DirectoryEntry::Leaf(ActionDirectoryMember::File(f)) = entry {
-                            let name = path.join(entry_path.get());
-                            let digest = maybe_tombstone_digest(f.digest.data())?.to_re();
-
-                            tracing::trace!(name = %name, digest = %digest, "push download");
-                            let name = self
-                                .fs
-                                .resolve(&name)
-                                .as_maybe_relativized_str()?
-                                .to_owned();
-
-                            files.push(NamedDigestWithPermissions {
-                                named_digest: NamedDigest {
-                                    name,
-                                    digest,
-                                    ..Default::default()
-                                },
-                                is_executable: f.is_executable,
-                                ..Default::default()
-                            });
-                        }
-                    }
+                let files = self.cas_files(&path, &entry)?;
+                stat.add_cas_files(&files);
+                // Only empty directories and symlinks, created with the tree structure.
+                if !files.is_empty() {
+                    self.download_cas_files(info, files)
+                        .await
+                        .map_err(|e| cas_download_error(e, path, info, entry))?;
                 }
-                stat.file_count = files.len().try_into().unwrap_or_default();
-                stat.total_bytes = files
-                    .iter()
-                    .map(|x| u64::try_from(x.named_digest.digest.size_in_bytes).unwrap_or_default())
-                    .sum();
-
-                let connection = self.re_client_manager.get_re_connection();
-                let re_client = connection.get_client().with_use_case(info.re_use_case);
-
-                re_client.materialize_files(files).await.map_err(|e| {
-                    let e: buck2_error::Error = e.into();
-                    match e.find_typed_context::<RemoteExecutionError>() {
-                        Some(re_error) if re_error.code == TCode::NOT_FOUND => {
-                            let e: buck2_error::Error = e.into();
-                            MaterializeEntryError::NotFound(CasNotFoundError {
-                                path: Arc::from(path),
-                                info: info.dupe(),
-                                directory: entry,
-                                error: Arc::from(e),
-                            })
-                        }
-                        _ => MaterializeEntryError::Error(e.context({
-                            format!(
-                                "Error materializing files declared by action: {}",
-                                info.origin
-                            )
-                        })),
-                    }
-                })?;
             }
             ArtifactMaterializationMethod::HttpDownload { info } => {
                 async {
@@ -257,6 +335,7 @@ impl DefaultIoHandler {
                         &path,
                         &info.url,
                         &info.checksum,
+                        Some(info.metadata.digest.size()),
                         info.metadata.is_executable,
                     )
                     .await?;
@@ -268,9 +347,25 @@ impl DefaultIoHandler {
                     if downloaded.size() != info.metadata.digest.size() {
                         return Err(buck2_error::buck2_error!(
                             ErrorTag::DownloadSizeMismatch,
-                            "Downloaded size ({}) does not match expected size ({})",
+                            "Downloaded size ({}) does not match expected size ({}) for `{}`",
                             downloaded.size(),
                             info.metadata.digest.size(),
+                            info.url,
+                        ));
+                    }
+                    // The checksum was verified while downloading, but the declared digest is what
+                    // gets recorded as materialized, so check it too whenever it uses the same
+                    // algorithm as the one we computed. Unlike CAS downloads this is never sampled.
+                    if downloaded.raw_digest().algorithm()
+                        == info.metadata.digest.raw_digest().algorithm()
+                        && downloaded.data() != info.metadata.digest.data()
+                    {
+                        return Err(buck2_error::buck2_error!(
+                            ErrorTag::DownloadDigestMismatch,
+                            "Downloaded digest ({}) does not match declared digest ({}) for `{}`",
+                            downloaded,
+                            info.metadata.digest,
+                            info.url,
                         ));
                     }
                     stat.file_count = 1;
@@ -308,9 +403,7 @@ impl DefaultIoHandler {
                 stat.file_count = 1;
                 self.io_executor
                     .execute_io_inline(|| {
-                        let data =
-                            zstd::bulk::decompress(&write.compressed_data, write.decompressed_size)
-                                .buck_error_context("Error decompressing data")?;
+                        let data = write.contents()?;
                         stat.total_bytes = write.decompressed_size as u64;
                         self.fs.write_file(&path, data, write.is_executable)
                     })
@@ -439,13 +532,107 @@ impl IoHandler for DefaultIoHandler {
         Ok(())
     }
 
+    async fn materialize_entries(
+        self: &Arc<Self>,
+        info: Arc<CasDownloadInfo>,
+        entries: Vec<(
+            ProjectRelativePathBuf,
+            ActionDirectoryEntry<ActionSharedDirectory>,
+        )>,
+        event_dispatcher: EventDispatcher,
+        cancellations: &CancellationContext,
+    ) -> Vec<Result<(), MaterializeEntryError>> {
+        let action_digest = info.action_digest().map(|digest| digest.to_string());
+        let materialization_start = buck2_data::MaterializationStart {
+            action_digest: action_digest.clone(),
+        };
+        event_dispatcher
+            .span_async(materialization_start, async move {
+                let path_string = match entries.as_slice() {
+                    [] => String::new(),
+                    [(path, _)] => path.to_string(),
+                    [(path, _), rest @ ..] => format!("{} and {} other paths", path, rest.len()),
+                };
+                let mut stat = MaterializationStat {
+                    file_count: 0,
+                    total_bytes: 0,
+                };
+                let res = self
+                    .materialize_entries_span(&info, &entries, &mut stat, cancellations)
+                    .await;
+                let error = res.as_ref().err().map(|e| format!("{:#}", e));
+                let results = match res {
+                    Ok(()) => entries.into_map(|_| Ok(())),
+                    Err(e) => entries.into_map(|(path, entry)| {
+                        Err(cas_download_error(e.clone(), path, &info, entry))
+                    }),
+                };
+
+                (
+                    results,
+                    buck2_data::MaterializationEnd {
+                        action_digest,
+                        file_count: stat.file_count,
+                        total_bytes: stat.total_bytes,
+                        path: path_string,
+                        success: error.is_none(),
+                        error,
+                        method: Some(
+                            ArtifactMaterializationMethod::CasDownload { info: info.dupe() }
+                                .to_proto() as i32,
+                        ),
+                    },
+                )
+            })
+            .await
+    }
+
+    async fn export_artifact(
+        self: &Arc<Self>,
+        path: ProjectRelativePathBuf,
+        dest: AbsPathBuf,
+        cancellations: &CancellationContext,
+    ) -> buck2_error::Result<()> {
+        self.io_executor
+            .execute_io(Box::new(ExportArtifact { path, dest }), cancellations)
+            .await
+    }
+
     fn create_ttl_refresh(
         self: &Arc<Self>,
         tree: &ArtifactTree,
         min_ttl: Duration,
+        methods: TtlRefreshMethods,
     ) -> Option<BoxFuture<'static, buck2_error::Result<()>>> {
-        create_ttl_refresh(tree, &self.re_client_manager, min_ttl, self.digest_config)
-            .map(|f| f.boxed())
+        create_ttl_refresh(
+            tree,
+            &self.re_client_manager,
+            min_ttl,
+            methods,
+            self.digest_config,
+        )
+        .map(|f| f.boxed())
+    }
+
+    async fn verify_materialized(
+        self: &Arc<Self>,
+        path: ProjectRelativePathBuf,
+        metadata: ArtifactMetadata,
+    ) -> buck2_error::Result<bool> {
+        let (entry, _hashing_info) = build_entry_from_disk(
+            self.fs.resolve(&path),
+            FileDigestConfig::build(self.digest_config.cas_digest_config()),
+            self.io_executor.as_ref(),
+            self.fs.root(),
+        )
+        .await
+        .with_buck_error_context(|| format!("Error hashing `{}` for verification", path))?;
+        Ok(entry.is_some_and(|entry| {
+            metadata.matches_entry(&entry.map_dir(|dir| {
+                dir.fingerprint(self.digest_config.as_directory_serializer())
+                    .shared(&*INTERNER)
+            }))
+        }))
     }
 
     fn read_dir(&self, path: &AbsNormPathBuf) -> Result<ReadDir, IoError> {
@@ -469,6 +656,30 @@ impl IoHandler for DefaultIoHandler {
     }
 }
 
+/// Classify an error downloading `entry` from the CAS, distinguishing artifacts that are no longer
+/// in the CAS.
+fn cas_download_error(
+    e: buck2_error::Error,
+    path: ProjectRelativePathBuf,
+    info: &Arc<CasDownloadInfo>,
+    entry: ActionDirectoryEntry<ActionSharedDirectory>,
+) -> MaterializeEntryError {
+    match e.find_typed_context::<RemoteExecutionError>() {
+        Some(re_error) if re_error.code == TCode::NOT_FOUND => {
+            MaterializeEntryError::NotFound(CasNotFoundError {
+                path: Arc::from(path),
+                info: info.dupe(),
+                directory: entry,
+                error: Arc::from(e),
+            })
+        }
+        _ => MaterializeEntryError::Error(e.context(format!(
+            "Error materializing files declared by action: {}",
+            info.origin
+        ))),
+    }
+}
+
 /// This is used for testing to ingest digests (via BUCK2_TEST_TOMBSTONED_DIGESTS).
 fn maybe_tombstone_digest(digest: &FileDigest) -> buck2_error::Result<&FileDigest> {
     // This has to be of size 1 since size 0 will result in the RE client just producing an empty
@@ -504,42 +715,67 @@ fn maybe_tombstone_digest(digest: &FileDigest) -> buck2_error::Result<&FileDiges
     Ok(digest)
 }
 
-/// Spawn a task to refresh TTLs.
-pub(super) fn create_ttl_refresh(
+/// Collects the digests of declared artifacts that expire within `min_ttl`, grouped by the RE use
+/// case to refresh them with. Only artifacts whose materialization method is enabled in `methods`
+/// are considered.
+pub(super) fn ttl_refresh_candidates(
     tree: &ArtifactTree,
-    re_manager: &Arc<ReConnectionManager>,
     min_ttl: Duration,
-    digest_config: DigestConfig,
-) -> Option<impl Future<Output = buck2_error::Result<()>> + use<>> {
+    methods: TtlRefreshMethods,
+) -> HashMap<RemoteExecutorUseCase, HashSet<TrackedFileDigest>> {
     let mut digests_to_refresh = HashMap::<_, HashSet<_>>::new();
 
     let ttl_deadline = Utc::now() + min_ttl;
 
+    let mut check = |use_case: RemoteExecutorUseCase, file: &FileMetadata| {
+        let needs_refresh = file.digest.expires().unwrap_or_default() < ttl_deadline;
+        tracing::trace!("{} needs_refresh: {}", file, needs_refresh);
+        if needs_refresh {
+            digests_to_refresh
+                .entry(use_case)
+                .or_default()
+                .insert(file.digest.dupe());
+        }
+    };
+
     for data in tree.iter_without_paths() {
         match &data.stage {
-            ArtifactMaterializationStage::Declared { entry, method } => match method.as_ref() {
-                ArtifactMaterializationMethod::CasDownload { info } => {
-                    let mut walk = unordered_entry_walk(entry.as_ref().map_dir(Directory::as_ref));
-                    while let Some((_entry_path, entry)) = walk.next() {
-                        if let DirectoryEntry::Leaf(ActionDirectoryMember::File(file)) = entry {
-                            let needs_refresh =
-                                file.digest.expires().unwrap_or_default() < ttl_deadline;
-                            tracing::trace!("{} needs_refresh: {}", file, needs_refresh);
-                            if needs_refresh {
-                                digests_to_refresh
-                                    .entry(info.re_use_case)
-                                    .or_default()
-                                    .insert(file.digest.dupe());
+            ArtifactMaterializationStage::Declared { entry, method, .. }
+                if methods.includes(method) =>
+            {
+                match method.as_ref() {
+                    ArtifactMaterializationMethod::CasDownload { info } => {
+                        let mut walk =
+                            unordered_entry_walk(entry.as_ref().map_dir(Directory::as_ref));
+                        while let Some((_entry_path, entry)) = walk.next() {
+                            if let DirectoryEntry::Leaf(ActionDirectoryMember::File(file)) = entry {
+                                check(info.re_use_case, file);
                             }
                         }
                     }
+                    ArtifactMaterializationMethod::HttpDownload { info } => {
+                        check(info.re_use_case, &info.metadata);
+                    }
+                    _ => {}
                 }
-                _ => {}
-            },
+            }
             _ => {}
         }
     }
 
+    digests_to_refresh
+}
+
+/// Spawn a task to refresh TTLs.
+pub(super) fn create_ttl_refresh(
+    tree: &ArtifactTree,
+    re_manager: &Arc<ReConnectionManager>,
+    min_ttl: Duration,
+    methods: TtlRefreshMethods,
+    digest_config: DigestConfig,
+) -> Option<impl Future<Output = buck2_error::Result<()>> + use<>> {
+    let digests_to_refresh = ttl_refresh_candidates(tree, min_ttl, methods);
+
     if digests_to_refresh.is_empty() {
         return None;
     }
@@ -617,9 +853,7 @@ struct WriteIoRequest {
 impl WriteIoRequest {
     fn execute_inner(&self, project_fs: &ProjectRoot) -> buck2_error::Result<()> {
         cleanup_path(project_fs, &self.path)?;
-        let data =
-            zstd::bulk::decompress(&self.write.compressed_data, self.write.decompressed_size)
-                .buck_error_context("Error decompressing data")?;
+        let data = self.write.contents()?;
         project_fs.write_file(&self.path, data, self.write.is_executable)?;
         Ok(())
     }
@@ -629,15 +863,17 @@ impl IoRequest for WriteIoRequest {
     fn execute(self: Box<Self>, project_fs: &ProjectRoot) -> buck2_error::Result<()> {
         // NOTE: No spans here! We should perhaps add one, but this needs to be considered
         // carefully as it's a lot of spans, and we haven't historically emitted those for writes.
+        let timestamp = Utc::now();
+        let started = std::time::Instant::now();
         let res = self
             .execute_inner(project_fs)
             .map_err(buck2_error::Error::from);
 
-        // If the materializer has shut down, we ignore this.
-        let _ignored = self.command_sender.send_low_priority(
+        self.command_sender.blocking_send_low_priority(
             LowPriorityMaterializerCommand::MaterializationFinished {
                 path: self.path,
-                timestamp: Utc::now(),
+                timestamp,
+                started,
                 version: self.version,
                 result: res.dupe().map_err(SharedMaterializingError::Error),
             },
@@ -659,8 +895,7 @@ impl IoRequest for CleanIoRequest {
         // carefully as it's a lot of spans, and we haven't historically emitted those for writes.
         let res = cleanup_path(project_fs, &self.path).map_err(buck2_error::Error::from);
 
-        // If the materializer has shut down, we ignore this.
-        let _ignored = self.command_sender.send_low_priority(
+        self.command_sender.blocking_send_low_priority(
             LowPriorityMaterializerCommand::CleanupFinished {
                 path: self.path,
                 version: self.version,
@@ -0,0 +1,742 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! IO pressure coordination between the materializer and local action execution.
+//!
+//! Large local copies and writes compete with local actions for disk bandwidth. On every io buffer
+//! tick, the materializer publishes whether the bytes it is materializing, or the materializations
+//! waiting on its concurrency limit, put the disk under pressure. In turn, while local actions
+//! report high IO wait, it halves its concurrency limit on every tick, and raises it back once they
+//! don't anymore. Both signals go through a `Hysteresis`, so that values hovering around a single
+//! threshold don't make them flip on every tick.
+//!
+//! Materializations waiting on the concurrency limit start in turn for each of the commands that
+//! requested them, and in order for each command, so that a large batch requested by one command
+//! doesn't hold up a small one requested by another.
+//!
+//! CAS downloads, batched or not, don't go through the concurrency limit, and so aren't scheduled
+//! in turn either: they are bound by the network rather than the disk, and the RE client already
+//! limits how many run at once. Holding them to the limit on local copies and writes, which drops
+//! to `min_concurrency` while local actions see high IO wait, would throttle them to a fraction of
+//! what the RE client allows.
+
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU8;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+
+use allocative::Allocative;
+use buck2_common::legacy_configs::configs::LegacyBuckConfig;
+use buck2_common::legacy_configs::key::BuckconfigKeyRef;
+use buck2_execute::materialize::io_pressure::IoPressureSignals;
+use buck2_wrapper_common::invocation_id::TraceId;
+use dupe::Dupe;
+use parking_lot::Mutex;
+use tokio::sync::oneshot;
+
+#[derive(Debug, Clone, Copy, Dupe, Allocative)]
+pub struct Thresholds {
+    pub high: u64,
+    pub low: u64,
+}
+
+/// Turns on once a value reaches `high`, and only turns off again once it drops to `low`.
+#[derive(Debug)]
+struct Hysteresis {
+    thresholds: Thresholds,
+    on: bool,
+}
+
+impl Hysteresis {
+    fn new(thresholds: Thresholds) -> Self {
+        Self {
+            thresholds,
+            on: false,
+        }
+    }
+
+    fn update(&mut self, value: u64) -> bool {
+        if value >= self.thresholds.high {
+            self.on = true;
+        } else if value <= self.thresholds.low {
+            self.on = false;
+        }
+        self.on
+    }
+}
+
+#[derive(Debug, buck2_error::Error)]
+#[buck2(tag = Input)]
+enum IoPressureConfigError {
+    #[error("`buck2.{low}` ({low_value}) must not be greater than `buck2.{high}` ({high_value})")]
+    InvertedBounds {
+        low: &'static str,
+        low_value: u64,
+        high: &'static str,
+        high_value: u64,
+    },
+    #[error("`buck2.materializer_io_pressure_min_concurrency` must be positive")]
+    ZeroConcurrency,
+}
+
+#[derive(Debug, Clone, Copy, Dupe, Allocative)]
+pub struct IoPressureConfig {
+    /// Bytes of local copies and writes in flight.
+    pub in_flight_bytes: Thresholds,
+    /// Local copies and writes waiting on the concurrency limit.
+    pub queue_depth: Thresholds,
+    /// IO wait reported by local action execution, in percent.
+    pub local_io_wait_percent: Thresholds,
+    pub min_concurrency: usize,
+    pub max_concurrency: usize,
+    /// How long local actions may be held back while the materializer is under pressure.
+    pub max_local_action_delay: std::time::Duration,
+}
+
+impl Default for IoPressureConfig {
+    fn default() -> Self {
+        Self {
+            in_flight_bytes: Thresholds {
+                high: 1024 * 1024 * 1024,
+                low: 256 * 1024 * 1024,
+            },
+            queue_depth: Thresholds { high: 64, low: 16 },
+            local_io_wait_percent: Thresholds { high: 40, low: 10 },
+            min_concurrency: 2,
+            max_concurrency: 64,
+            max_local_action_delay: std::time::Duration::from_secs(2),
+        }
+    }
+}
+
+impl IoPressureConfig {
+    /// Returns `None` if IO pressure coordination is disabled.
+    pub fn from_buck_config(root_config: &LegacyBuckConfig) -> buck2_error::Result<Option<Self>> {
+        let enabled = root_config
+            .parse(BuckconfigKeyRef {
+                section: "buck2",
+                property: "materializer_io_pressure",
+            })?
+            .unwrap_or(false);
+        if !enabled {
+            return Ok(None);
+        }
+
+        let default = Self::default();
+        let parse = |property: &'static str, default: u64| -> buck2_error::Result<u64> {
+            Ok(root_config
+                .parse(BuckconfigKeyRef {
+                    section: "buck2",
+                    property,
+                })?
+                .unwrap_or(default))
+        };
+        let bounds = |low: &'static str,
+                      high: &'static str,
+                      default: (u64, u64)|
+         -> buck2_error::Result<(u64, u64)> {
+            let low_value = parse(low, default.0)?;
+            let high_value = parse(high, default.1)?;
+            if low_value > high_value {
+                return Err(IoPressureConfigError::InvertedBounds {
+                    low,
+                    low_value,
+                    high,
+                    high_value,
+                }
+                .into());
+            }
+            Ok((low_value, high_value))
+        };
+        let thresholds = |low, high, default: Thresholds| -> buck2_error::Result<Thresholds> {
+            let (low, high) = bounds(low, high, (default.low, default.high))?;
+            Ok(Thresholds { high, low })
+        };
+
+        let (min_concurrency, max_concurrency) = bounds(
+            "materializer_io_pressure_min_concurrency",
+            "materializer_io_pressure_max_concurrency",
+            (
+                default.min_concurrency as u64,
+                default.max_concurrency as u64,
+            ),
+        )?;
+        if min_concurrency == 0 {
+            return Err(IoPressureConfigError::ZeroConcurrency.into());
+        }
+
+        Ok(Some(Self {
+            in_flight_bytes: thresholds(
+                "materializer_io_pressure_in_flight_bytes_low",
+                "materializer_io_pressure_in_flight_bytes_high",
+                default.in_flight_bytes,
+            )?,
+            queue_depth: thresholds(
+                "materializer_io_pressure_queue_depth_low",
+                "materializer_io_pressure_queue_depth_high",
+                default.queue_depth,
+            )?,
+            local_io_wait_percent: thresholds(
+                "materializer_io_pressure_local_io_wait_percent_low",
+                "materializer_io_pressure_local_io_wait_percent_high",
+                default.local_io_wait_percent,
+            )?,
+            min_concurrency: min_concurrency as usize,
+            max_concurrency: max_concurrency as usize,
+            max_local_action_delay: std::time::Duration::from_millis(parse(
+                "local_action_io_pressure_max_delay_ms",
+                default.max_local_action_delay.as_millis() as u64,
+            )?),
+        }))
+    }
+}
+
+/// Only accessed on the io buffer tick.
+struct TickState {
+    in_flight_bytes: Hysteresis,
+    queue_depth: Hysteresis,
+    local_io_wait: Hysteresis,
+}
+
+/// Materializations running, and those waiting on the concurrency limit.
+#[derive(Default)]
+struct Waiters {
+    running: usize,
+    /// Commands with waiters, in the order they get their next turn.
+    turns: VecDeque<TraceId>,
+    /// Notified once they can start, at which point they count as running.
+    by_trace: HashMap<TraceId, VecDeque<oneshot::Sender<()>>>,
+}
+
+/// The materializer side of `IoPressureSignals`, also limiting concurrent local copies and writes.
+#[derive(Allocative)]
+pub struct MaterializerIoPressure {
+    config: IoPressureConfig,
+    in_flight_bytes: AtomicU64,
+    queued: AtomicUsize,
+    limit: AtomicUsize,
+    under_pressure: AtomicBool,
+    local_io_wait_percent: AtomicU8,
+    #[allocative(skip)]
+    tick_state: Mutex<TickState>,
+    #[allocative(skip)]
+    waiters: Mutex<Waiters>,
+}
+
+impl MaterializerIoPressure {
+    pub fn new(config: IoPressureConfig) -> Self {
+        Self {
+            config,
+            in_flight_bytes: AtomicU64::new(0),
+            queued: AtomicUsize::new(0),
+            limit: AtomicUsize::new(config.max_concurrency),
+            under_pressure: AtomicBool::new(false),
+            local_io_wait_percent: AtomicU8::new(0),
+            tick_state: Mutex::new(TickState {
+                in_flight_bytes: Hysteresis::new(config.in_flight_bytes),
+                queue_depth: Hysteresis::new(config.queue_depth),
+                local_io_wait: Hysteresis::new(config.local_io_wait_percent),
+            }),
+            waiters: Mutex::new(Waiters::default()),
+        }
+    }
+
+    /// The current concurrency limit.
+    pub(super) fn limit(&self) -> usize {
+        self.limit.load(Ordering::Acquire)
+    }
+
+    /// Updates the published pressure and the concurrency limit from the current state.
+    pub(super) fn tick(&self) {
+        let mut state = self.tick_state.lock();
+
+        let bytes = state
+            .in_flight_bytes
+            .update(self.in_flight_bytes.load(Ordering::Relaxed));
+        let queued = state
+            .queue_depth
+            .update(self.queued.load(Ordering::Relaxed) as u64);
+        self.under_pressure
+            .store(bytes || queued, Ordering::Relaxed);
+
+        let limit = self.limit();
+        let io_wait = state
+            .local_io_wait
+            .update(self.local_io_wait_percent.load(Ordering::Relaxed).into());
+        let new_limit = if io_wait {
+            (limit / 2).max(self.config.min_concurrency)
+        } else {
+            (limit + (self.config.max_concurrency / 8).max(1)).min(self.config.max_concurrency)
+        };
+        if new_limit != limit {
+            tracing::debug!(
+                limit,
+                new_limit,
+                io_wait,
+                "materializer io concurrency limit"
+            );
+            self.limit.store(new_limit, Ordering::Release);
+            self.dispatch();
+        }
+    }
+
+    /// Waits for the concurrency limit to allow another materialization of `bytes`, requested by
+    /// the command with `trace_id`.
+    pub(super) async fn acquire(self: &Arc<Self>, trace_id: &TraceId, bytes: u64) -> IoPermit {
+        let _queued = Queued::new(&self.queued);
+        let receiver = {
+            let mut waiters = self.waiters.lock();
+            let waiters = &mut *waiters;
+            if waiters.running < self.limit() && waiters.turns.is_empty() {
+                waiters.running += 1;
+                None
+            } else {
+                let (sender, receiver) = oneshot::channel();
+                let queue = waiters.by_trace.entry(trace_id.dupe()).or_default();
+                if queue.is_empty() {
+                    waiters.turns.push_back(trace_id.dupe());
+                }
+                queue.push_back(sender);
+                Some(receiver)
+            }
+        };
+        if let Some(receiver) = receiver {
+            let mut waiting = Waiting {
+                io_pressure: self,
+                receiver,
+                started: false,
+            };
+            (&mut waiting.receiver)
+                .await
+                .expect("Waiters are only removed to start them");
+            waiting.started = true;
+        }
+        self.in_flight_bytes.fetch_add(bytes, Ordering::Relaxed);
+        IoPermit {
+            io_pressure: self.dupe(),
+            bytes,
+        }
+    }
+
+    /// Starts waiters in turn while the concurrency limit allows it.
+    fn dispatch(&self) {
+        let limit = self.limit();
+        let mut waiters = self.waiters.lock();
+        let Waiters {
+            running,
+            turns,
+            by_trace,
+        } = &mut *waiters;
+        while *running < limit {
+            let Some(trace_id) = turns.pop_front() else {
+                break;
+            };
+            let Some(queue) = by_trace.get_mut(&trace_id) else {
+                continue;
+            };
+            let sender = queue.pop_front();
+            if queue.is_empty() {
+                by_trace.remove(&trace_id);
+            } else {
+                turns.push_back(trace_id);
+            }
+            // Waiters that were dropped don't take a slot.
+            if sender.is_some_and(|sender| sender.send(()).is_ok()) {
+                *running += 1;
+            }
+        }
+    }
+
+    fn release(&self) {
+        self.waiters.lock().running -= 1;
+        self.dispatch();
+    }
+
+    /// The number of materializations waiting on the concurrency limit for each command, in the
+    /// order the commands get their next turn.
+    pub(super) fn pending_by_trace(&self) -> Vec<(TraceId, usize)> {
+        let waiters = self.waiters.lock();
+        waiters
+            .turns
+            .iter()
+            .filter_map(|trace_id| {
+                let pending = waiters.by_trace.get(trace_id)?;
+                let pending = pending.iter().filter(|sender| !sender.is_closed()).count();
+                (pending > 0).then(|| (trace_id.dupe(), pending))
+            })
+            .collect()
+    }
+}
+
+impl IoPressureSignals for MaterializerIoPressure {
+    fn materializer_under_pressure(&self) -> bool {
+        self.under_pressure.load(Ordering::Relaxed)
+    }
+
+    fn report_local_io_wait(&self, percent: u8) {
+        self.local_io_wait_percent
+            .store(percent.min(100), Ordering::Relaxed);
+    }
+
+    fn max_local_action_delay(&self) -> std::time::Duration {
+        self.config.max_local_action_delay
+    }
+}
+
+/// Counts a materialization waiting on the concurrency limit, until it acquired a permit or was
+/// dropped.
+struct Queued<'a>(&'a AtomicUsize);
+
+impl<'a> Queued<'a> {
+    fn new(queued: &'a AtomicUsize) -> Self {
+        queued.fetch_add(1, Ordering::Relaxed);
+        Self(queued)
+    }
+}
+
+impl Drop for Queued<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// A materialization queued by `acquire`. If it's dropped after it was started but before it
+/// noticed, it gives its slot back.
+struct Waiting<'a> {
+    io_pressure: &'a MaterializerIoPressure,
+    receiver: oneshot::Receiver<()>,
+    started: bool,
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        if self.started {
+            return;
+        }
+        // Once closed, `dispatch` can't start this waiter anymore, so it was started iff there is
+        // a value to receive.
+        self.receiver.close();
+        if self.receiver.try_recv().is_ok() {
+            self.io_pressure.release();
+        }
+    }
+}
+
+/// Held while a materialization runs.
+pub(super) struct IoPermit {
+    io_pressure: Arc<MaterializerIoPressure>,
+    bytes: u64,
+}
+
+impl Drop for IoPermit {
+    fn drop(&mut self) {
+        self.io_pressure
+            .in_flight_bytes
+            .fetch_sub(self.bytes, Ordering::Relaxed);
+        self.io_pressure.release();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use buck2_common::legacy_configs::configs::testing;
+    use futures::FutureExt;
+
+    use super::*;
+
+    fn config() -> IoPressureConfig {
+        IoPressureConfig {
+            in_flight_bytes: Thresholds {
+                high: 1000,
+                low: 100,
+            },
+            queue_depth: Thresholds { high: 2, low: 0 },
+            local_io_wait_percent: Thresholds { high: 40, low: 10 },
+            min_concurrency: 2,
+            max_concurrency: 16,
+            max_local_action_delay: std::time::Duration::from_secs(2),
+        }
+    }
+
+    #[test]
+    fn test_config_from_buck_config() -> buck2_error::Result<()> {
+        let parse = |config: &str| {
+            IoPressureConfig::from_buck_config(&testing::parse(&[("config", config)], "config")?)
+        };
+
+        // Off unless opted into.
+        assert!(parse("")?.is_none());
+        assert!(parse("[buck2]\nmaterializer_io_pressure_max_concurrency = 8\n")?.is_none());
+
+        let default = parse("[buck2]\nmaterializer_io_pressure = true\n")?.unwrap();
+        assert_eq!(64, default.max_concurrency);
+        assert_eq!(
+            std::time::Duration::from_secs(2),
+            default.max_local_action_delay
+        );
+
+        assert!(parse("[buck2]\nmaterializer_io_pressure = false\n")?.is_none());
+
+        let config = parse(
+            "[buck2]\n\
+             materializer_io_pressure = true\n\
+             materializer_io_pressure_max_concurrency = 8\n\
+             materializer_io_pressure_queue_depth_high = 4\n\
+             materializer_io_pressure_queue_depth_low = 1\n\
+             local_action_io_pressure_max_delay_ms = 500\n",
+        )?
+        .unwrap();
+        assert_eq!(8, config.max_concurrency);
+        assert_eq!(2, config.min_concurrency);
+        assert_eq!(4, config.queue_depth.high);
+        assert_eq!(1, config.queue_depth.low);
+        assert_eq!(
+            std::time::Duration::from_millis(500),
+            config.max_local_action_delay
+        );
+
+        // Below the default low threshold.
+        let enabled = |config: &str| {
+            parse(&format!(
+                "[buck2]\nmaterializer_io_pressure = true\n{config}"
+            ))
+        };
+        assert!(enabled("materializer_io_pressure_queue_depth_high = 4\n").is_err());
+        assert!(enabled("materializer_io_pressure_max_concurrency = 1\n").is_err());
+        assert!(enabled("materializer_io_pressure_min_concurrency = 0\n").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_hysteresis() {
+        let mut hysteresis = Hysteresis::new(Thresholds { high: 40, low: 10 });
+        let values = [20, 40, 30, 11, 10, 30, 39, 90];
+        let expected = [false, true, true, true, false, false, false, true];
+        for (value, expected) in values.into_iter().zip(expected) {
+            assert_eq!(expected, hysteresis.update(value), "value: {}", value);
+        }
+    }
+
+    #[test]
+    fn test_limit_follows_local_io_wait() {
+        let io_pressure = MaterializerIoPressure::new(config());
+        assert_eq!(16, io_pressure.limit());
+
+        io_pressure.report_local_io_wait(80);
+        let mut limits = Vec::new();
+        for _ in 0..5 {
+            io_pressure.tick();
+            limits.push(io_pressure.limit());
+        }
+        assert_eq!(vec![8, 4, 2, 2, 2], limits);
+
+        // Between the thresholds, the limit keeps going down.
+        io_pressure.report_local_io_wait(25);
+        io_pressure.tick();
+        assert_eq!(2, io_pressure.limit());
+
+        io_pressure.report_local_io_wait(5);
+        let mut limits = Vec::new();
+        for _ in 0..10 {
+            io_pressure.tick();
+            limits.push(io_pressure.limit());
+        }
+        assert_eq!(vec![4, 6, 8, 10, 12, 14, 16, 16, 16, 16], limits);
+
+        // Between the thresholds, the limit keeps going up.
+        io_pressure.report_local_io_wait(25);
+        io_pressure.tick();
+        assert_eq!(16, io_pressure.limit());
+
+        // Out of range reports are clamped.
+        io_pressure.report_local_io_wait(u8::MAX);
+        io_pressure.tick();
+        assert_eq!(8, io_pressure.limit());
+    }
+
+    #[tokio::test]
+    async fn test_pressure_from_in_flight_bytes() {
+        let io_pressure = Arc::new(MaterializerIoPressure::new(config()));
+        io_pressure.tick();
+        assert!(!io_pressure.materializer_under_pressure());
+
+        let trace_id = TraceId::new();
+        let large = io_pressure.acquire(&trace_id, 800).await;
+        let small = io_pressure.acquire(&trace_id, 300).await;
+        io_pressure.tick();
+        assert!(io_pressure.materializer_under_pressure());
+
+        drop(large);
+        io_pressure.tick();
+        assert!(io_pressure.materializer_under_pressure());
+
+        drop(small);
+        io_pressure.tick();
+        assert!(!io_pressure.materializer_under_pressure());
+    }
+
+    #[tokio::test]
+    async fn test_pressure_from_queue_depth() {
+        let io_pressure = Arc::new(MaterializerIoPressure::new(IoPressureConfig {
+            local_io_wait_percent: Thresholds { high: 0, low: 0 },
+            ..config()
+        }));
+        // The limit drops to the minimum, since any IO wait is high.
+        for _ in 0..3 {
+            io_pressure.tick();
+        }
+        assert_eq!(2, io_pressure.limit());
+
+        let trace_id = TraceId::new();
+        let first = io_pressure.acquire(&trace_id, 0).await;
+        let _second = io_pressure.acquire(&trace_id, 0).await;
+        let mut third = Box::pin(io_pressure.acquire(&trace_id, 0));
+        let mut fourth = Box::pin(io_pressure.acquire(&trace_id, 0));
+        assert!((&mut third).now_or_never().is_none());
+        assert!((&mut fourth).now_or_never().is_none());
+        io_pressure.tick();
+        assert!(io_pressure.materializer_under_pressure());
+
+        drop(first);
+        let _third = third.await;
+        assert!((&mut fourth).now_or_never().is_none());
+        drop(fourth);
+        io_pressure.tick();
+        assert!(!io_pressure.materializer_under_pressure());
+    }
+
+    #[test]
+    fn test_fair_between_traces() {
+        let io_pressure = Arc::new(MaterializerIoPressure::new(IoPressureConfig {
+            min_concurrency: 1,
+            max_concurrency: 1,
+            ..config()
+        }));
+        let a = TraceId::new();
+        let b = TraceId::new();
+
+        let mut pending = (0..100)
+            .map(|i| (a.dupe(), i))
+            .chain((0..2).map(|i| (b.dupe(), i)))
+            .map(|(trace_id, i)| {
+                let io_pressure = io_pressure.dupe();
+                let label = (trace_id.dupe(), i);
+                let acquire = async move { io_pressure.acquire(&trace_id, 0).await }.boxed();
+                (label, acquire)
+            })
+            .collect::<Vec<_>>();
+
+        // Polling everything in order queues all but the first, then each completion starts one
+        // more.
+        let mut starts = Vec::new();
+        let mut running = None;
+        while !pending.is_empty() {
+            drop(running.take());
+            let mut i = 0;
+            while i < pending.len() {
+                match pending[i].1.as_mut().now_or_never() {
+                    Some(permit) => {
+                        assert!(running.is_none(), "Started more than the limit");
+                        running = Some(permit);
+                        starts.push(pending.remove(i).0);
+                    }
+                    None => i += 1,
+                }
+            }
+        }
+
+        let positions = |trace_id: &TraceId| {
+            starts
+                .iter()
+                .enumerate()
+                .filter(|(_, (t, _))| t == trace_id)
+                .map(|(position, (_, i))| (position, *i))
+                .collect::<Vec<_>>()
+        };
+        // B doesn't wait for all of A, and each command's materializations start in order.
+        assert_eq!(vec![(2, 0), (4, 1)], positions(&b));
+        let a_order = positions(&a)
+            .into_iter()
+            .map(|(_, i)| i)
+            .collect::<Vec<_>>();
+        assert_eq!((0..100).collect::<Vec<_>>(), a_order);
+        assert!(io_pressure.pending_by_trace().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_dropped_waiter_gives_slot_back() {
+        let io_pressure = Arc::new(MaterializerIoPressure::new(IoPressureConfig {
+            min_concurrency: 1,
+            max_concurrency: 1,
+            ..config()
+        }));
+        let trace_id = TraceId::new();
+
+        let running = io_pressure.acquire(&trace_id, 0).await;
+        let mut waiting = io_pressure.acquire(&trace_id, 0).boxed();
+        assert!(waiting.as_mut().now_or_never().is_none());
+        // Started, but dropped before it noticed.
+        drop(running);
+        drop(waiting);
+
+        let running = io_pressure
+            .acquire(&trace_id, 0)
+            .boxed()
+            .now_or_never()
+            .expect("The slot was given back");
+        let mut waiting = io_pressure.acquire(&trace_id, 0).boxed();
+        assert!(waiting.as_mut().now_or_never().is_none());
+        // Dropped before it was started, so it doesn't take the next slot.
+        drop(waiting);
+        drop(running);
+        assert!(
+            io_pressure
+                .acquire(&trace_id, 0)
+                .boxed()
+                .now_or_never()
+                .is_some()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_pending_by_trace() {
+        let io_pressure = Arc::new(MaterializerIoPressure::new(IoPressureConfig {
+            min_concurrency: 1,
+            max_concurrency: 1,
+            ..config()
+        }));
+        let a = TraceId::new();
+        let b = TraceId::new();
+
+        let _running = io_pressure.acquire(&a, 0).await;
+        let mut waiting = vec![
+            io_pressure.acquire(&a, 0).boxed(),
+            io_pressure.acquire(&b, 0).boxed(),
+            io_pressure.acquire(&a, 0).boxed(),
+        ];
+        for acquire in &mut waiting {
+            assert!(acquire.as_mut().now_or_never().is_none());
+        }
+        assert_eq!(
+            vec![(a.dupe(), 2), (b.dupe(), 1)],
+            io_pressure.pending_by_trace()
+        );
+
+        // Dropped waiters aren't pending anymore.
+        waiting.truncate(2);
+        assert_eq!(
+            vec![(a.dupe(), 1), (b.dupe(), 1)],
+            io_pressure.pending_by_trace()
+        );
+    }
+}
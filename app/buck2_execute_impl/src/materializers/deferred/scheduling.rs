@@ -0,0 +1,162 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Follow-up work scheduled from the materializer command loop.
+//!
+//! Extension commands run synchronously on the command loop, so they can't await anything the
+//! loop drives, like a materialization or a clean. Instead, they can enqueue more commands, or
+//! register a callback that the loop invokes once a set of processing futures finishes.
+//!
+//! Re-entrancy rules, for extension commands as well as callbacks:
+//! - Never block. Nothing else is processed meanwhile, and blocking on anything the loop drives
+//!   (e.g. a materialization, or the reply to an enqueued command) deadlocks.
+//! - Keep the work done per invocation bounded. Spawn IO and long walks instead, and split large
+//!   batches across callbacks.
+//! - Don't assume the tree is as it was when the callback was registered. The callback runs after
+//!   the completion notifications of the futures it waits on were processed, but commands processed
+//!   in the meantime may have declared or invalidated the same paths again.
+
+use std::collections::HashMap;
+
+use buck2_core::fs::project_rel_path::ProjectRelativePathBuf;
+use dupe::Dupe;
+use futures::Future;
+use futures::future;
+
+use crate::materializers::deferred::DeferredMaterializerCommandProcessor;
+use crate::materializers::deferred::LowPriorityMaterializerCommand;
+use crate::materializers::deferred::MaterializerCommand;
+use crate::materializers::deferred::Processing;
+use crate::materializers::deferred::ProcessingFuture;
+use crate::materializers::deferred::io_handler::IoHandler;
+use crate::materializers::deferred::join_all_existing_futs;
+
+/// Identifies a callback registered with `MaterializerSchedulingContext::on_completion`.
+#[derive(Clone, Copy, Dupe, Debug, PartialEq, Eq, Hash)]
+pub(super) struct CompletionToken(u64);
+
+/// Invoked on the command loop with the outcome of waiting for the futures, which is the first
+/// error cleaning one of their paths. Failed materializations aren't errors here, the tree records
+/// them.
+pub(super) type CompletionCallback<T> =
+    Box<dyn FnOnce(&mut DeferredMaterializerCommandProcessor<T>, buck2_error::Result<()>) + Send>;
+
+/// Callbacks waiting for processing futures to finish.
+pub(super) struct CompletionCallbacks<T: 'static> {
+    next_token: u64,
+    callbacks: HashMap<CompletionToken, CompletionCallback<T>>,
+}
+
+impl<T> CompletionCallbacks<T> {
+    pub(super) fn new() -> Self {
+        Self {
+            next_token: 0,
+            callbacks: HashMap::new(),
+        }
+    }
+
+    fn register(&mut self, callback: CompletionCallback<T>) -> CompletionToken {
+        let token = CompletionToken(self.next_token);
+        self.next_token += 1;
+        self.callbacks.insert(token, callback);
+        token
+    }
+
+    /// Removes the callback for `token`, if it wasn't invoked or cancelled yet.
+    pub(super) fn take(&mut self, token: CompletionToken) -> Option<CompletionCallback<T>> {
+        self.callbacks.remove(&token)
+    }
+}
+
+/// Lets extension commands schedule follow-up work on the command loop. See the module docs for
+/// what they may do from there.
+pub(super) struct MaterializerSchedulingContext<'a, T: 'static> {
+    processor: &'a mut DeferredMaterializerCommandProcessor<T>,
+}
+
+impl<'a, T: IoHandler> MaterializerSchedulingContext<'a, T> {
+    pub(super) fn new(processor: &'a mut DeferredMaterializerCommandProcessor<T>) -> Self {
+        Self { processor }
+    }
+
+    /// Enqueues a high priority command, processed after the current command. Unlike commands sent
+    /// from outside of the command loop, this doesn't interrupt a running clean.
+    #[allow(dead_code)] // Not used by an extension yet.
+    pub(super) fn enqueue(&self, command: MaterializerCommand<T>) {
+        // If the materializer has shut down, we ignore this.
+        let _ignored = self
+            .processor
+            .command_sender
+            .send_without_interrupting_clean(command);
+    }
+
+    /// Enqueues a low priority command. It may be processed after low priority commands enqueued
+    /// later, since the queue may be full and the command loop can't wait for room in it.
+    #[allow(dead_code)] // Not used by an extension yet.
+    pub(super) fn enqueue_low_priority(&self, command: LowPriorityMaterializerCommand) {
+        self.enqueue_low_priority_after(future::ready(command));
+    }
+
+    /// Like `enqueue_low_priority`, but for a command that is only known once `command` finishes,
+    /// such as the notification for work spawned off the command loop.
+    pub(super) fn enqueue_low_priority_after(
+        &self,
+        command: impl Future<Output = LowPriorityMaterializerCommand> + Send + 'static,
+    ) {
+        let command_sender = self.processor.command_sender.dupe();
+        self.processor.spawn(async move {
+            let command = command.await;
+            // If the materializer has shut down, we ignore this.
+            let _ignored = command_sender.send_low_priority(command).await;
+        });
+    }
+
+    /// The futures currently processing the artifacts at or under `paths`.
+    pub(super) fn active_futures(
+        &self,
+        paths: &[ProjectRelativePathBuf],
+    ) -> Vec<(ProjectRelativePathBuf, ProcessingFuture)> {
+        let mut futures = Vec::new();
+        for path in paths {
+            for (path, data) in self.processor.tree.get_path_entries(path) {
+                if let Processing::Active { future, .. } = &data.processing {
+                    futures.push((path, future.clone()));
+                }
+            }
+        }
+        futures
+    }
+
+    /// Invokes `callback` on the command loop once all of `futures` finish, after the completion
+    /// notifications they send were processed.
+    pub(super) fn on_completion(
+        &mut self,
+        futures: Vec<(ProjectRelativePathBuf, ProcessingFuture)>,
+        callback: impl FnOnce(&mut DeferredMaterializerCommandProcessor<T>, buck2_error::Result<()>)
+        + Send
+        + 'static,
+    ) -> CompletionToken {
+        let token = self
+            .processor
+            .completion_callbacks
+            .register(Box::new(callback));
+        self.enqueue_low_priority_after(async move {
+            // Processing futures only finish once they sent their completion notification, so the
+            // notification below is queued after theirs.
+            let result = join_all_existing_futs(futures).await;
+            LowPriorityMaterializerCommand::CompletionReady { token, result }
+        });
+        token
+    }
+
+    /// Drops the callback for `token`, returning whether it was still pending.
+    pub(super) fn cancel(&mut self, token: CompletionToken) -> bool {
+        self.processor.completion_callbacks.take(token).is_some()
+    }
+}
@@ -32,9 +32,10 @@ use crate::materializers::deferred::DeferredMaterializerCommandProcessor;
 use crate::materializers::deferred::IoHandler;
 use crate::materializers::deferred::MaterializerCommand;
 use crate::materializers::deferred::MaterializerSender;
+use crate::materializers::deferred::file_tree::FileTree;
 
-/// Subscriptions allow clients to request eager materialization of specific paths as well as
-/// notifications when those paths are materialized.
+/// Subscriptions allow clients to request eager materialization of specific paths, or of all
+/// paths under a prefix, as well as notifications when those paths are materialized.
 pub(super) struct MaterializerSubscriptions {
     index: SubscriptionIndex,
     active: HashMap<SubscriptionIndex, SubscriptionData>,
@@ -51,7 +52,7 @@ impl MaterializerSubscriptions {
     /// Return whether a given path should be materialized eagerly.
     pub fn should_materialize_eagerly(&self, path: &ProjectRelativePath) -> bool {
         for sub in self.active.values() {
-            if sub.paths.contains(path) {
+            if sub.matches(path) {
                 return true;
             }
         }
@@ -62,7 +63,7 @@ impl MaterializerSubscriptions {
     /// Notify this subscription that a given path has been materialized.
     pub fn on_materialization_finished(&self, path: &ProjectRelativePath) {
         for sub in self.active.values() {
-            if sub.paths.contains(path) {
+            if sub.matches(path) {
                 sub.sender.send(path.to_owned());
             }
         }
@@ -94,6 +95,10 @@ impl MaterializerSubscriptions {
 
 struct SubscriptionData {
     paths: HashSet<ProjectRelativePathBuf>,
+    prefixes: HashSet<ProjectRelativePathBuf>,
+    /// Index over `prefixes` to match paths in O(depth). When prefixes overlap, only the shortest
+    /// one is in the tree, since it covers the others.
+    prefix_tree: FileTree<()>,
     sender: UnboundedSender<ProjectRelativePathBuf>,
 }
 
@@ -101,9 +106,41 @@ impl SubscriptionData {
     fn new(sender: UnboundedSender<ProjectRelativePathBuf>) -> Self {
         Self {
             paths: HashSet::new(),
+            prefixes: HashSet::new(),
+            prefix_tree: FileTree::new(),
             sender,
         }
     }
+
+    /// Whether this subscription covers `path`, either exactly or via one of its prefixes.
+    fn matches(&self, path: &ProjectRelativePath) -> bool {
+        self.paths.contains(path) || self.prefix_tree.prefix_get(&mut path.iter()).is_some()
+    }
+
+    fn add_prefix(&mut self, prefix: ProjectRelativePathBuf) {
+        index_prefix(&mut self.prefix_tree, &prefix);
+        self.prefixes.insert(prefix);
+    }
+
+    fn remove_prefix(&mut self, prefix: &ProjectRelativePath) {
+        if !self.prefixes.remove(prefix) {
+            return;
+        }
+
+        // The removed prefix may have been covering others, so rebuild the index from scratch.
+        self.prefix_tree = FileTree::new();
+        for prefix in &self.prefixes {
+            index_prefix(&mut self.prefix_tree, prefix);
+        }
+    }
+}
+
+fn index_prefix(tree: &mut FileTree<()>, prefix: &ProjectRelativePath) {
+    // Inserting a prefix evicts any longer prefixes it covers, but inserting a prefix that is
+    // already covered would evict the shorter one, so skip it.
+    if tree.prefix_get(&mut prefix.iter()).is_none() {
+        tree.insert(prefix.iter().map(|f| f.to_owned()), ());
+    }
 }
 
 /// A index uniquely identifying a given Subscription.
@@ -144,6 +181,19 @@ pub(super) enum MaterializerSubscriptionOperation<T: 'static> {
         index: SubscriptionIndex,
         paths: Vec<ProjectRelativePathBuf>,
     },
+
+    /// Ask the materializer to send new notifications for all paths under this prefix.
+    SubscribeToPrefix {
+        index: SubscriptionIndex,
+        prefix: ProjectRelativePathBuf,
+    },
+
+    /// Ask the materializer to stop sending notifications for paths under this prefix. Paths
+    /// that are still covered by another prefix or by an exact subscription are still reported.
+    UnsubscribeFromPrefix {
+        index: SubscriptionIndex,
+        prefix: ProjectRelativePathBuf,
+    },
 }
 
 impl<T> MaterializerSubscriptionOperation<T>
@@ -209,6 +259,52 @@ where
                     subscription.paths.remove(path);
                 }
             }
+            Self::SubscribeToPrefix { index, prefix } => {
+                let mut paths_to_report = Vec::new();
+
+                // Artifacts that are already declared under the prefix. This excludes an artifact
+                // that the prefix points inside of.
+                let declared: Vec<_> = dm
+                    .tree
+                    .get_path_entries(&prefix)
+                    .into_iter()
+                    .map(|(path, _)| path)
+                    .filter(|path| path.starts_with(&prefix))
+                    .collect();
+
+                for path in declared {
+                    if dm.is_path_materialized(&path) {
+                        paths_to_report.push(path);
+                    } else {
+                        dm.materialize_artifact(&path, EventDispatcher::null());
+                    }
+                }
+
+                // Same as `Subscribe`, the subscription is guaranteed to exist.
+                let subscription = dm
+                    .subscriptions
+                    .active
+                    .get_mut(&index)
+                    .with_buck_error_context(|| format!("Invalid subscription: {}", index))
+                    .unwrap();
+
+                for path in paths_to_report {
+                    subscription.sender.send(path);
+                }
+
+                subscription.add_prefix(prefix);
+            }
+            Self::UnsubscribeFromPrefix { index, prefix } => {
+                // Same as `Unsubscribe`, the subscription is guaranteed to exist.
+                let subscription = dm
+                    .subscriptions
+                    .active
+                    .get_mut(&index)
+                    .with_buck_error_context(|| format!("Invalid subscription: {}", index))
+                    .unwrap();
+
+                subscription.remove_prefix(&prefix);
+            }
         }
     }
 }
@@ -253,6 +349,24 @@ impl<T: 'static> DeferredMaterializerSubscription for SubscriptionHandle<T> {
         ));
     }
 
+    fn subscribe_to_prefix(&mut self, prefix: ProjectRelativePathBuf) {
+        self.command_sender.send(MaterializerCommand::Subscription(
+            MaterializerSubscriptionOperation::SubscribeToPrefix {
+                index: self.index,
+                prefix,
+            },
+        ));
+    }
+
+    fn unsubscribe_from_prefix(&mut self, prefix: ProjectRelativePathBuf) {
+        self.command_sender.send(MaterializerCommand::Subscription(
+            MaterializerSubscriptionOperation::UnsubscribeFromPrefix {
+                index: self.index,
+                prefix,
+            },
+        ));
+    }
+
     async fn next_materialization(&mut self) -> Option<ProjectRelativePathBuf> {
         self.receiver.recv().await
     }
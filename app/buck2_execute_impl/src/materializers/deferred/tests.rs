@@ -10,15 +10,24 @@
 use std::collections::HashMap;
 
 use buck2_common::file_ops::FileMetadata;
+use buck2_core::configuration::data::ConfigurationData;
+use buck2_core::deferred::base_deferred_key::BaseDeferredKey;
+use buck2_core::execution_types::executor_config::RemoteExecutorUseCase;
 use buck2_core::fs::fs_util::IoError;
 use buck2_core::fs::paths::forward_rel_path::ForwardRelativePath;
 use buck2_core::fs::project_rel_path::ProjectRelativePath;
+use buck2_core::target::configured_target_label::ConfiguredTargetLabel;
+use buck2_error::ErrorTag;
+use buck2_error::buck2_error;
 use buck2_execute::digest_config::DigestConfig;
 use buck2_execute::directory::ActionDirectoryBuilder;
+use buck2_execute::directory::ActionDirectoryEntry;
 use buck2_execute::directory::insert_file;
+use buck2_execute::materialize::http::Checksum;
 use buck2_execute::materialize::materializer::DeferredMaterializerSubscription;
 
 use super::*;
+use crate::materializers::deferred::artifact_tree::ArtifactMaterializationData;
 
 #[test]
 fn test_find_artifacts() -> buck2_error::Result<()> {
@@ -88,10 +97,311 @@ fn test_remove_path() {
     assert_eq!(removed_subtree.get("a/b/c/e"), Some(&"a/b/c/e".to_owned()));
 }
 
+#[test]
+fn test_ttl_refresh_candidates() {
+    let file = FileMetadata::empty(DigestConfig::testing_default().cas_digest_config());
+
+    fn insert(
+        tree: &mut ArtifactTree,
+        path: &str,
+        file: &FileMetadata,
+        method: ArtifactMaterializationMethod,
+    ) {
+        tree.insert(
+            ProjectRelativePath::unchecked_new(path)
+                .iter()
+                .map(|f| f.to_owned()),
+            Box::new(ArtifactMaterializationData {
+                deps: None,
+                stage: ArtifactMaterializationStage::Declared {
+                    entry: ActionDirectoryEntry::Leaf(ActionDirectoryMember::File(file.dupe())),
+                    method: Arc::new(method),
+                    last_requested: tokio::time::Instant::now(),
+                },
+                processing: Processing::Done(Version(0)),
+                declared_by: None,
+            }),
+        );
+    }
+
+    let min_ttl = chrono::Duration::hours(1);
+
+    // The file has no TTL, so it would need refreshing if it came from the CAS.
+    let mut tree = ArtifactTree::new();
+    insert(
+        &mut tree,
+        "a/test",
+        &file,
+        ArtifactMaterializationMethod::Test,
+    );
+    assert!(
+        io_handler::ttl_refresh_candidates(&tree, min_ttl, TtlRefreshMethods::default()).is_empty()
+    );
+
+    insert(
+        &mut tree,
+        "a/cas",
+        &file,
+        ArtifactMaterializationMethod::CasDownload {
+            info: Arc::new(CasDownloadInfo::new_declared(
+                RemoteExecutorUseCase::buck2_default(),
+            )),
+        },
+    );
+    let candidates =
+        io_handler::ttl_refresh_candidates(&tree, min_ttl, TtlRefreshMethods::default());
+    assert_eq!(
+        candidates
+            .get(&RemoteExecutorUseCase::buck2_default())
+            .map(|digests| digests.len()),
+        Some(1)
+    );
+
+    let cas_disabled = TtlRefreshMethods {
+        cas_download: false,
+        http_download: true,
+    };
+    assert!(io_handler::ttl_refresh_candidates(&tree, min_ttl, cas_disabled).is_empty());
+
+    // HTTP downloads are refreshed with the use case of the action that declared them.
+    let use_case = RemoteExecutorUseCase::new("http-use-case".to_owned());
+    insert(
+        &mut tree,
+        "a/http",
+        &file,
+        ArtifactMaterializationMethod::HttpDownload {
+            info: HttpDownloadInfo {
+                url: Arc::from("https://example.com/file"),
+                metadata: file.dupe(),
+                checksum: Checksum::new(Some("da39a3ee5e6b4b0d3255bfef95601890afd80709"), None)
+                    .unwrap(),
+                owner: BaseDeferredKey::TargetLabel(ConfiguredTargetLabel::testing_parse(
+                    "cell//pkg:foo",
+                    ConfigurationData::testing_new(),
+                )),
+                re_use_case: use_case,
+            },
+        },
+    );
+    assert!(
+        !io_handler::ttl_refresh_candidates(&tree, min_ttl, TtlRefreshMethods::default())
+            .contains_key(&use_case)
+    );
+    let candidates = io_handler::ttl_refresh_candidates(&tree, min_ttl, cas_disabled);
+    assert_eq!(candidates.keys().collect::<Vec<_>>(), vec![&use_case]);
+}
+
+#[test]
+fn test_ttl_refresh_history_is_bounded() {
+    let start = Utc::now();
+    let at = |i: i64| start + Duration::seconds(i);
+
+    let mut history = TtlRefreshHistory::new(3);
+    for i in 0..5 {
+        history.push(TtlRefreshHistoryEntry {
+            at: at(i),
+            outcome: None,
+        });
+    }
+    assert_eq!(
+        history.iter().map(|e| e.at).collect::<Vec<_>>(),
+        vec![at(2), at(3), at(4)]
+    );
+
+    let mut history = TtlRefreshHistory::new(0);
+    history.push(TtlRefreshHistoryEntry {
+        at: at(0),
+        outcome: None,
+    });
+    assert_eq!(history.iter().count(), 0);
+}
+
+#[test]
+fn test_ttl_refresh_failures_escalate() {
+    let mut history = TtlRefreshHistory::new(2);
+    let mut push = |outcome: Option<buck2_error::Result<()>>| {
+        history
+            .push(TtlRefreshHistoryEntry {
+                at: Utc::now(),
+                outcome,
+            })
+            .map(|e| e.consecutive_failures)
+    };
+    let failure = || Some(Err(buck2_error!(ErrorTag::Tier0, "failed")));
+
+    assert_eq!(push(failure()), None);
+    assert_eq!(push(failure()), None);
+    // Skipped refreshes don't reset the counter.
+    assert_eq!(push(None), None);
+    assert_eq!(push(failure()), Some(3));
+    // Only escalate once per streak.
+    assert_eq!(push(failure()), None);
+
+    // A success resets the counter.
+    assert_eq!(push(Some(Ok(()))), None);
+    assert_eq!(push(failure()), None);
+    assert_eq!(push(failure()), None);
+    assert_eq!(push(failure()), Some(3));
+}
+
+#[test]
+fn test_write_file_compression() -> buck2_error::Result<()> {
+    let content = b"buck2 ".repeat(1000);
+
+    for level in [0, 9] {
+        let write = WriteFile::new(
+            content.clone(),
+            false,
+            WriteCompression { level, min_size: 0 },
+        )?;
+        assert!(write.compressed);
+        assert!(write.data.len() < content.len());
+        assert_eq!(write.decompressed_size, content.len());
+        assert_eq!(&*write.contents()?, content.as_slice());
+    }
+
+    // Below the threshold, contents are stored as is.
+    let write = WriteFile::new(
+        content.clone(),
+        true,
+        WriteCompression {
+            level: 9,
+            min_size: content.len() + 1,
+        },
+    )?;
+    assert!(!write.compressed);
+    assert_eq!(&*write.data, content.as_slice());
+    assert_eq!(write.decompressed_size, content.len());
+    assert_eq!(&*write.contents()?, content.as_slice());
+    assert!(write.is_executable);
+
+    Ok(())
+}
+
+fn write_requests(count: usize, size: usize) -> Vec<WriteRequest> {
+    (0..count)
+        .map(|i| WriteRequest {
+            path: ProjectRelativePathBuf::unchecked_new(format!("gen/{}.rs", i)),
+            content: format!("const X{}: u64 = {};\n", i, i * 7919)
+                .into_bytes()
+                .into_iter()
+                .cycle()
+                .take(size + i)
+                .collect(),
+            is_executable: i % 2 == 0,
+        })
+        .collect()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_prepare_writes_in_parallel() -> buck2_error::Result<()> {
+    let digest_config = DigestConfig::testing_default();
+    let compression = WriteCompression {
+        level: 3,
+        min_size: 0,
+    };
+
+    let serial = write_requests(200, 200 << 10)
+        .into_iter()
+        .map(|write| prepare_write(write, digest_config, compression))
+        .collect::<buck2_error::Result<Vec<_>>>()?;
+    let parallel =
+        prepare_writes(write_requests(200, 200 << 10), digest_config, compression).await?;
+
+    assert_eq!(parallel.len(), serial.len());
+    for (i, ((path, value, method), (serial_path, serial_value, _))) in
+        std::iter::zip(&parallel, &serial).enumerate()
+    {
+        assert_eq!(path.as_str(), format!("gen/{}.rs", i));
+        assert_eq!(path, serial_path);
+        assert_eq!(value, serial_value);
+        match method {
+            ArtifactMaterializationMethod::Write(write) => {
+                assert!(write.compressed);
+                assert_eq!(write.contents()?.len(), (200 << 10) + i);
+            }
+            _ => panic!("Expected a write"),
+        }
+    }
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_map_blocking_in_order_runs_in_parallel() -> buck2_error::Result<()> {
+    if std::thread::available_parallelism().map_or(1, |n| n.get()) < 2 {
+        // Everything is one chunk.
+        return Ok(());
+    }
+
+    // The first two items to start wait for each other. An item holds up the rest of its chunk, so
+    // this only gets through if two chunks run at the same time.
+    let barrier = Arc::new(std::sync::Barrier::new(2));
+    let started = Arc::new(AtomicUsize::new(0));
+    let in_flight = Arc::new(AtomicUsize::new(0));
+    let peak_in_flight = Arc::new(AtomicUsize::new(0));
+    let res = map_blocking_in_order((0..1000).collect(), {
+        let in_flight = in_flight.dupe();
+        let peak_in_flight = peak_in_flight.dupe();
+        move |i: usize| {
+            let now_in_flight = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            peak_in_flight.fetch_max(now_in_flight, Ordering::SeqCst);
+            if started.fetch_add(1, Ordering::SeqCst) < 2 {
+                barrier.wait();
+            }
+            in_flight.fetch_sub(1, Ordering::SeqCst);
+            Ok(i * 2)
+        }
+    })
+    .await?;
+
+    assert_eq!(res, (0..1000).map(|i| i * 2).collect::<Vec<_>>());
+    assert!(peak_in_flight.load(Ordering::SeqCst) >= 2);
+    assert_eq!(in_flight.load(Ordering::SeqCst), 0);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_map_blocking_in_order_returns_first_error() {
+    // Several chunks fail, the error is the one a serial loop would have hit first.
+    let res = map_blocking_in_order((0..1000).collect(), |i: usize| {
+        if i == 100 || i == 700 || i == 701 {
+            Err(buck2_error::buck2_error!(
+                buck2_error::ErrorTag::Tier0,
+                "failed on {}",
+                i
+            ))
+        } else {
+            Ok(i * 2)
+        }
+    })
+    .await;
+    assert_eq!(res.unwrap_err().to_string(), "failed on 100");
+
+    let res = map_blocking_in_order((0..1000).collect(), |i: usize| Ok(i * 2)).await;
+    assert_eq!(res.unwrap(), (0..1000).map(|i| i * 2).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_artifact_tree_sweep_config() -> buck2_error::Result<()> {
+    let config = ArtifactTreeSweepConfiguration::try_new(300, Some(24))?;
+    assert_eq!(config.frequency, std::time::Duration::from_secs(300));
+    assert_eq!(
+        config.declared_idle_ttl,
+        Some(std::time::Duration::from_secs(24 * 60 * 60))
+    );
+
+    assert!(ArtifactTreeSweepConfiguration::try_new(0, None).is_err());
+    assert!(ArtifactTreeSweepConfiguration::try_new(300, Some(-1)).is_err());
+    Ok(())
+}
+
 #[cfg(test)]
 mod state_machine {
     use std::path::Path;
     use std::sync::Barrier;
+    use std::sync::Once;
     use std::thread;
 
     use assert_matches::assert_matches;
@@ -99,16 +409,20 @@ mod state_machine {
     use buck2_core::fs::fs_util::ReadDir;
     use buck2_core::fs::paths::RelativePathBuf;
     use buck2_core::fs::paths::abs_norm_path::AbsNormPathBuf;
+    use buck2_core::fs::paths::abs_path::AbsPathBuf;
     use buck2_core::fs::paths::forward_rel_path::ForwardRelativePath;
     use buck2_core::fs::project::ProjectRootTemp;
     use buck2_error::BuckErrorContext;
     use buck2_error::buck2_error;
+    use buck2_events::EventSink;
     use buck2_events::source::ChannelEventSource;
+    use buck2_events::span::SpanId;
     use buck2_execute::directory::ActionDirectoryEntry;
     use buck2_execute::directory::ActionSharedDirectory;
     use buck2_execute::directory::INTERNER;
     use buck2_execute::directory::Symlink;
     use buck2_execute::execute::blocking::IoRequest;
+    use buck2_execute::materialize::manifest::ManifestEntry;
     use buck2_util::threads::ignore_stack_overflow_checks_for_future;
     use buck2_wrapper_common::invocation_id::TraceId;
     use futures::StreamExt;
@@ -118,10 +432,16 @@ mod state_machine {
     use tokio::time::sleep;
 
     use super::*;
+    use crate::materializers::deferred::artifact_tree::DeclareOwner;
     use crate::materializers::deferred::clean_stale::CleanInvalidatedPathRequest;
+    use crate::materializers::deferred::clean_stale::CleanStaleArtifactsCommand;
+    use crate::materializers::deferred::clock_skew::ClockJump;
+    use crate::materializers::deferred::command_processor::DeclareContext;
     use crate::materializers::deferred::command_processor::TestingDeferredMaterializerCommandProcessor;
     use crate::materializers::deferred::subscriptions::MaterializerSubscriptionOperation;
     use crate::materializers::deferred::subscriptions::SubscriptionHandle;
+    use crate::materializers::io::ExportArtifact;
+    use crate::materializers::io::MaterializeTreeStructure;
     use crate::materializers::sqlite::testing_materializer_state_sqlite_db;
 
     #[derive(Debug, Eq, PartialEq, Allocative)]
@@ -134,8 +454,12 @@ mod state_machine {
     #[derive(Allocative)]
     struct StubIoHandler {
         log: Mutex<Vec<(Op, ProjectRelativePathBuf)>>,
+        /// Number of calls to `materialize_entries`.
+        batched_materializations: AtomicUsize,
         fail: Mutex<bool>,
         fail_paths: Mutex<Vec<ProjectRelativePathBuf>>,
+        /// Paths whose contents on disk don't match during verification.
+        mismatch_paths: Mutex<Vec<ProjectRelativePathBuf>>,
         // If set, add a sleep when materializing to simulate a long materialization period
         materialization_config: HashMap<ProjectRelativePathBuf, TokioDuration>,
         #[allocative(skip)]
@@ -172,11 +496,17 @@ mod state_machine {
             *self.fail_paths.lock() = paths;
         }
 
+        fn set_mismatch_on(&self, paths: Vec<ProjectRelativePathBuf>) {
+            *self.mismatch_paths.lock() = paths;
+        }
+
         pub fn new(fs: ProjectRoot) -> Self {
             Self {
                 log: Default::default(),
+                batched_materializations: Default::default(),
                 fail: Default::default(),
                 fail_paths: Default::default(),
+                mismatch_paths: Default::default(),
                 materialization_config: HashMap::new(),
                 read_dir_barriers: None,
                 clean_barriers: None,
@@ -210,11 +540,26 @@ mod state_machine {
 
     impl StubIoHandler {
         fn actually_write(self: &Arc<Self>, path: &ProjectRelativePathBuf, write: &Arc<WriteFile>) {
-            let data = zstd::bulk::decompress(&write.compressed_data, write.decompressed_size)
-                .buck_error_context("Error decompressing data")
-                .unwrap();
+            let data = write.contents().unwrap();
             self.fs.write_file(path, data, write.is_executable).unwrap();
         }
+
+        /// Creates directories like the default IO handler does, so that empty directories exist
+        /// on disk. Files are not downloaded.
+        fn materialize_dirs(
+            &self,
+            path: &ProjectRelativePathBuf,
+            entry: ActionDirectoryEntry<ActionSharedDirectory>,
+        ) -> buck2_error::Result<()> {
+            if !matches!(entry, ActionDirectoryEntry::Dir(_)) {
+                return Ok(());
+            }
+            Box::new(MaterializeTreeStructure {
+                path: path.clone(),
+                entry,
+            })
+            .execute(&self.fs)
+        }
     }
 
     #[async_trait]
@@ -229,14 +574,15 @@ mod state_machine {
         ) -> BoxFuture<'a, Result<(), SharedMaterializingError>> {
             self.actually_write(&path, &write);
             async move {
-                let _ignored = command_sender.send_low_priority(
-                    LowPriorityMaterializerCommand::MaterializationFinished {
+                let _ignored = command_sender
+                    .send_low_priority(LowPriorityMaterializerCommand::MaterializationFinished {
                         path,
                         timestamp: Utc::now(),
+                        started: std::time::Instant::now(),
                         version,
                         result: Ok(()),
-                    },
-                );
+                    })
+                    .await;
                 Ok(())
             }
             .boxed()
@@ -259,13 +605,13 @@ mod state_machine {
             self.log.lock().push((Op::Clean, path.clone()));
 
             async move {
-                let _ignored = command_sender.send_low_priority(
-                    LowPriorityMaterializerCommand::CleanupFinished {
+                let _ignored = command_sender
+                    .send_low_priority(LowPriorityMaterializerCommand::CleanupFinished {
                         path,
                         version,
                         result: Ok(()),
-                    },
-                );
+                    })
+                    .await;
                 Ok(())
             }
             .boxed()
@@ -288,7 +634,7 @@ mod state_machine {
             self: &Arc<Self>,
             path: ProjectRelativePathBuf,
             _method: Arc<ArtifactMaterializationMethod>,
-            _entry: ActionDirectoryEntry<ActionSharedDirectory>,
+            entry: ActionDirectoryEntry<ActionSharedDirectory>,
             _event_dispatcher: EventDispatcher,
             _cancellations: &CancellationContext,
         ) -> Result<(), MaterializeEntryError> {
@@ -312,21 +658,62 @@ mod state_machine {
                     ArtifactMaterializationMethod::Write(write) => {
                         self.actually_write(&path, write);
                     }
-                    _ => {}
+                    _ => self.materialize_dirs(&path, entry)?,
                 }
                 self.log.lock().push((Op::Materialize, path));
                 Ok(())
             }
         }
 
+        async fn materialize_entries(
+            self: &Arc<Self>,
+            _info: Arc<CasDownloadInfo>,
+            entries: Vec<(
+                ProjectRelativePathBuf,
+                ActionDirectoryEntry<ActionSharedDirectory>,
+            )>,
+            _event_dispatcher: EventDispatcher,
+            _cancellations: &CancellationContext,
+        ) -> Vec<Result<(), MaterializeEntryError>> {
+            self.batched_materializations
+                .fetch_add(1, Ordering::Relaxed);
+            let mut log = self.log.lock();
+            entries
+                .into_iter()
+                .map(|(path, entry)| {
+                    self.materialize_dirs(&path, entry)?;
+                    log.push((Op::Materialize, path));
+                    Ok(())
+                })
+                .collect()
+        }
+
+        async fn export_artifact(
+            self: &Arc<Self>,
+            path: ProjectRelativePathBuf,
+            dest: AbsPathBuf,
+            _cancellations: &CancellationContext,
+        ) -> buck2_error::Result<()> {
+            Box::new(ExportArtifact { path, dest }).execute(&self.fs)
+        }
+
         fn create_ttl_refresh(
             self: &Arc<Self>,
             _tree: &ArtifactTree,
             _min_ttl: Duration,
+            _methods: TtlRefreshMethods,
         ) -> Option<BoxFuture<'static, buck2_error::Result<()>>> {
             unimplemented!()
         }
 
+        async fn verify_materialized(
+            self: &Arc<Self>,
+            path: ProjectRelativePathBuf,
+            _metadata: ArtifactMetadata,
+        ) -> buck2_error::Result<bool> {
+            Ok(!self.mismatch_paths.lock().contains(&path))
+        }
+
         fn read_dir(&self, path: &AbsNormPathBuf) -> Result<ReadDir, IoError> {
             if let Some(barriers) = self.read_dir_barriers.as_ref() {
                 // Allow tests to advance here, execute something and then continue
@@ -354,26 +741,22 @@ mod state_machine {
     }
 
     /// A stub command sender. We are calling materializer methods directly so that's all we need.
-    fn channel() -> (
+    fn channel(
+        low_priority_capacity: usize,
+    ) -> (
         Arc<MaterializerSender<StubIoHandler>>,
         MaterializerReceiver<StubIoHandler>,
     ) {
-        // We don't use those counts in tests.
-        static SENT: AtomicUsize = AtomicUsize::new(0);
-        static RECEIVED: AtomicUsize = AtomicUsize::new(0);
-
         let (hi_send, hi_recv) = mpsc::unbounded_channel();
-        let (lo_send, lo_recv) = mpsc::unbounded_channel();
-        let counters = MaterializerCounters {
-            sent: &SENT,
-            received: &RECEIVED,
-        };
+        let (lo_send, lo_recv) = mpsc::channel(low_priority_capacity);
+        let counters = MaterializerCounters::leak_new();
 
         (
             Arc::new(MaterializerSender {
                 high_priority: hi_send,
                 low_priority: lo_send,
                 counters,
+                stats: Arc::new(DeferredMaterializerStats::default()),
                 clean_guard: Default::default(),
             }),
             MaterializerReceiver {
@@ -438,14 +821,32 @@ mod state_machine {
         MaterializerReceiver<StubIoHandler>,
         ChannelEventSource,
     ) {
-        let (db, sqlite_state) = make_db(io.fs());
+        make_processor_for_io_with_low_priority_capacity(io, 1000)
+    }
+
+    fn make_processor_for_io_with_low_priority_capacity(
+        io: Arc<StubIoHandler>,
+        low_priority_capacity: usize,
+    ) -> (
+        DeferredMaterializerCommandProcessor<StubIoHandler>,
+        Arc<MaterializerSender<StubIoHandler>>,
+        MaterializerReceiver<StubIoHandler>,
+        ChannelEventSource,
+    ) {
+        let (mut db, mut sqlite_state) = make_db(io.fs());
+        let clock_skew = ClockSkewGuard::load(
+            ClockSkewConfig::default(),
+            Some(&mut db),
+            sqlite_state.as_mut(),
+            Utc::now(),
+        );
         let tree = ArtifactTree::initialize(sqlite_state);
 
         let (daemon_dispatcher_events, daemon_dispatcher_sink) =
             buck2_events::create_source_sink_pair();
         let daemon_dispatcher = EventDispatcher::new(TraceId::null(), daemon_dispatcher_sink);
 
-        let (command_sender, command_receiver) = channel();
+        let (command_sender, command_receiver) = channel(low_priority_capacity);
         (
             DeferredMaterializerCommandProcessor::new(
                 io,
@@ -453,14 +854,18 @@ mod state_machine {
                 Handle::current(),
                 true,
                 LogBuffer::new(1),
+                0,
                 command_sender.dupe(),
                 tree,
                 CancellationContext::testing(),
-                Arc::new(DeferredMaterializerStats::default()),
+                command_sender.stats.dupe(),
+                Default::default(),
                 Default::default(),
                 true,
                 daemon_dispatcher,
                 true,
+                false,
+                clock_skew,
             ),
             command_sender,
             command_receiver,
@@ -480,24 +885,12 @@ mod state_machine {
         (dm, receiver)
     }
 
-    async fn make_materializer(
-        io: Arc<StubIoHandler>,
+    fn spawn_command_loop(
+        processor: DeferredMaterializerCommandProcessor<StubIoHandler>,
+        command_receiver: MaterializerReceiver<StubIoHandler>,
         clean_stale_config: Option<CleanStaleConfig>,
-    ) -> (
-        DeferredMaterializerAccessor<StubIoHandler>,
-        SubscriptionHandle<StubIoHandler>,
-        ChannelEventSource,
-    ) {
-        let (mut processor, command_sender, command_receiver, daemon_dispatcher_events) =
-            make_processor_for_io(io.dupe());
-
-        let handle = {
-            let (sender, recv) = oneshot::channel();
-            MaterializerSubscriptionOperation::Create { sender }.execute(&mut processor);
-            recv.await.unwrap()
-        };
-
-        let command_thread = thread_spawn("buck2-dm", {
+    ) -> std::thread::JoinHandle<()> {
+        thread_spawn("buck2-dm", {
             move || {
                 let rt = tokio::runtime::Builder::new_current_thread()
                     .enable_all()
@@ -510,34 +903,223 @@ mod state_machine {
                         frequency: std::time::Duration::default(),
                         min_ttl: chrono::Duration::zero(),
                         enabled: false,
+                        methods: TtlRefreshMethods::default(),
+                        history_size: 0,
                     },
                     0,
                     AccessTimesUpdates::Disabled,
                     clean_stale_config,
+                    ArtifactTreeSweepConfiguration {
+                        frequency: std::time::Duration::from_secs(300),
+                        declared_idle_ttl: None,
+                    },
                 ));
             }
         })
         .buck_error_context("Cannot start materializer thread")
-        .unwrap();
+        .unwrap()
+    }
+
+    async fn make_materializer(
+        io: Arc<StubIoHandler>,
+        clean_stale_config: Option<CleanStaleConfig>,
+    ) -> (
+        DeferredMaterializerAccessor<StubIoHandler>,
+        SubscriptionHandle<StubIoHandler>,
+        ChannelEventSource,
+    ) {
+        let (mut processor, command_sender, command_receiver, daemon_dispatcher_events) =
+            make_processor_for_io(io.dupe());
+
+        let handle = {
+            let (sender, recv) = oneshot::channel();
+            MaterializerSubscriptionOperation::Create { sender }.execute(&mut processor);
+            recv.await.unwrap()
+        };
+
+        let command_thread = spawn_command_loop(processor, command_receiver, clean_stale_config);
 
         (
             DeferredMaterializerAccessor {
                 command_thread: Some(command_thread),
+                stats: command_sender.stats.dupe(),
                 command_sender,
                 materialize_final_artifacts: true,
                 defer_write_actions: true,
+                write_compression: WriteCompression::default(),
                 io,
                 materializer_state_info: buck2_data::MaterializerStateInfo {
                     num_entries_from_sqlite: 0,
                 },
-                stats: Arc::new(DeferredMaterializerStats::default()),
                 verbose_materializer_log: true,
+                fail_on_declare_conflicts: false,
             },
             handle,
             daemon_dispatcher_events,
         )
     }
 
+    /// Awaits `fut`, processing low priority commands meanwhile like the command loop does.
+    async fn process_low_priority_until<R>(
+        dm: &mut DeferredMaterializerCommandProcessor<StubIoHandler>,
+        channel: &mut MaterializerReceiver<StubIoHandler>,
+        fut: impl Future<Output = R>,
+    ) -> R {
+        let mut fut = std::pin::pin!(fut);
+        loop {
+            tokio::select! {
+                res = &mut fut => return res,
+                Some(command) = channel.low_priority.recv() => {
+                    dm.testing_process_one_low_priority_command(command);
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_declare_cas_many_is_batched() -> buck2_error::Result<()> {
+        ignore_stack_overflow_checks_for_future(async {
+            let io = Arc::new(StubIoHandler::new(temp_root()));
+            let (dm, _handle, _events) = make_materializer(io.dupe(), None).await;
+            let value = ArtifactValue::file(io.digest_config().empty_file());
+            let info = Arc::new(CasDownloadInfo::new_declared(
+                RemoteExecutorUseCase::buck2_default(),
+            ));
+
+            let paths = (0..1000)
+                .map(|i| make_path(&format!("foo/{}", i)))
+                .collect::<Vec<_>>();
+
+            let sent = dm.command_sender.counters.sent.load(Ordering::Relaxed);
+            dm.declare_cas_many_impl(
+                info,
+                paths.iter().map(|p| (p.clone(), value.dupe())).collect(),
+                CancellationContext::testing(),
+            )
+            .await?;
+            assert_eq!(
+                dm.command_sender.counters.sent.load(Ordering::Relaxed) - sent,
+                1
+            );
+
+            dm.ensure_materialized(paths.clone()).await?;
+            assert_eq!(io.batched_materializations.load(Ordering::Relaxed), 1);
+            let mut materialized = io
+                .take_log()
+                .into_iter()
+                .filter_map(|(op, path)| (op == Op::Materialize).then_some(path))
+                .collect::<Vec<_>>();
+            materialized.sort();
+            let mut expected = paths;
+            expected.sort();
+            assert_eq!(materialized, expected);
+
+            dm.abort();
+            Ok(())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_empty_dir_round_trip() -> buck2_error::Result<()> {
+        ignore_stack_overflow_checks_for_future(async {
+            let path = make_path("buck-out/v2/gen/foo/empty");
+            let project_root = temp_root();
+            let io = Arc::new(StubIoHandler::new(project_root.clone()));
+            let (dm, mut handle, _) = make_materializer(io.dupe(), None).await;
+            let value = ArtifactValue::dir(io.digest_config().empty_directory());
+            let info = Arc::new(CasDownloadInfo::new_declared(
+                RemoteExecutorUseCase::buck2_default(),
+            ));
+
+            // The directory doesn't exist until it's materialized, even though there is nothing
+            // to download.
+            dm.declare_cas_many_impl(
+                info.dupe(),
+                vec![(path.clone(), value.dupe())],
+                CancellationContext::testing(),
+            )
+            .await?;
+            assert_matches!(
+                dm.get_materialized_file_paths(vec![path.clone()])
+                    .await?
+                    .as_slice(),
+                [Err(
+                    ArtifactNotMaterializedReason::RequiresCasDownload { .. }
+                )]
+            );
+
+            handle.subscribe_to_paths(vec![path.clone()]);
+            dm.ensure_materialized(vec![path.clone()]).await?;
+            handle.receiver().recv().await;
+            assert!(fs_util::try_exists(project_root.resolve(&path))?);
+            assert_matches!(
+                dm.get_materialized_file_paths(vec![path.clone()])
+                    .await?
+                    .as_slice(),
+                [Ok(p)] if p == &path
+            );
+            // Drop dm and flush sqlite connection.
+            dm.abort();
+            // Create new materializer from db state so that artifacts are not active
+            let (dm, _, _) = make_materializer(io.dupe(), None).await;
+
+            // The directory is stale, but cleaning it would free nothing.
+            let res = dm
+                .clean_stale_artifacts(
+                    Utc::now() + Duration::hours(1),
+                    false,
+                    false,
+                    Vec::new(),
+                    None,
+                    None,
+                    Vec::new(),
+                )
+                .await?;
+            let stats = res
+                .stats
+                .as_ref()
+                .unwrap_or_else(|| panic!("{}", res.message.unwrap()));
+            assert_eq!(
+                (
+                    stats.stale_artifact_count,
+                    stats.retained_artifact_count,
+                    stats.cleaned_artifact_count,
+                ),
+                (0, 1, 0)
+            );
+            assert!(fs_util::try_exists(project_root.resolve(&path))?);
+
+            assert!(
+                dm.declare_match(vec![(path.clone(), value.dupe())])
+                    .await?
+                    .is_match()
+            );
+
+            dm.invalidate(path.clone()).await?;
+            assert!(!dm.has_artifact_at(path.clone()).await?);
+            assert!(
+                !dm.declare_match(vec![(path.clone(), value.dupe())])
+                    .await?
+                    .is_match()
+            );
+
+            dm.declare_cas_many_impl(
+                info,
+                vec![(path.clone(), value.dupe())],
+                CancellationContext::testing(),
+            )
+            .await?;
+            assert!(dm.has_artifact_at(path.clone()).await?);
+            dm.ensure_materialized(vec![path.clone()]).await?;
+            assert!(fs_util::try_exists(project_root.resolve(&path))?);
+
+            dm.abort();
+            Ok(())
+        })
+        .await
+    }
+
     #[tokio::test]
     async fn test_declare_reuse() -> buck2_error::Result<()> {
         ignore_stack_overflow_checks_for_future(async {
@@ -579,54 +1161,846 @@ mod state_machine {
         .await
     }
 
-    fn make_artifact_value_with_symlink_dep(
-        target_path: &ProjectRelativePathBuf,
-        target_from_symlink: &RelativePathBuf,
-        digest_config: DigestConfig,
-    ) -> buck2_error::Result<ArtifactValue> {
-        let mut deps = ActionDirectoryBuilder::empty();
-        let target = ActionDirectoryEntry::Leaf(ActionDirectoryMember::File(FileMetadata::empty(
-            digest_config.cas_digest_config(),
-        )));
-        deps.insert(target_path.as_forward_relative_path(), target)?;
-        let symlink_value = ArtifactValue::new(
-            ActionDirectoryEntry::Leaf(ActionDirectoryMember::Symlink(Arc::new(Symlink::new(
-                target_from_symlink.clone(),
-            )))),
-            Some(
-                deps.fingerprint(digest_config.as_directory_serializer())
-                    .shared(&*INTERNER),
-            ),
-        );
-        Ok(symlink_value)
+    fn declare_context(
+        dispatcher: &EventDispatcher,
+        span_id: u64,
+        fail_on_conflict: bool,
+    ) -> DeclareContext {
+        DeclareContext {
+            event_dispatcher: dispatcher.dupe(),
+            owner: Arc::new(DeclareOwner {
+                trace_id: dispatcher.trace_id().dupe(),
+                span_id: SpanId::from_u64_opt(span_id),
+            }),
+            result_sender: fail_on_conflict.then(|| oneshot::channel().0),
+        }
     }
 
-    #[tokio::test]
-    async fn test_materialize_symlink_and_target() -> buck2_error::Result<()> {
-        ignore_stack_overflow_checks_for_future(async {
-            // Construct a tree with a symlink and its target, materialize both at once
-            let symlink_path = make_path("foo/bar_symlink");
-            let target_path = make_path("foo/bar_target");
-            let target_from_symlink = RelativePathBuf::from_path(Path::new("bar_target"))?;
+    fn file_artifact(content: &[u8], digest_config: DigestConfig) -> ArtifactValue {
+        ArtifactValue::file(FileMetadata {
+            digest: TrackedFileDigest::from_content(content, digest_config.cas_digest_config()),
+            is_executable: false,
+        })
+    }
 
-            let mut materialization_config = HashMap::new();
-            // Materialize the symlink target slowly so that we actually hit the logic point where we
-            // await for symlink targets and the entry materialization
-            materialization_config.insert(target_path.clone(), TokioDuration::from_millis(100));
+    fn declare_conflicts(
+        events: &mut ChannelEventSource,
+    ) -> Vec<buck2_data::MaterializerDeclareConflict> {
+        let mut res = Vec::new();
+        while let Some(event) = events.try_receive() {
+            if let buck2_data::buck_event::Data::Instant(instant) =
+                event.unpack_buck().unwrap().data()
+            {
+                if let Some(buck2_data::instant_event::Data::MaterializerDeclareConflict(
+                    conflict,
+                )) = &instant.data
+                {
+                    res.push(conflict.clone());
+                }
+            }
+        }
+        res
+    }
 
-            let (mut dm, _) = make_processor(materialization_config);
+    fn entry_at(
+        dm: &DeferredMaterializerCommandProcessor<StubIoHandler>,
+        path: &ProjectRelativePath,
+    ) -> Option<String> {
+        match &dm.tree.prefix_get(&mut path.iter())?.stage {
+            ArtifactMaterializationStage::Declared { entry, .. } => {
+                Some(ArtifactMetadata::new(entry).0.to_string())
+            }
+            ArtifactMaterializationStage::Materialized { metadata, .. } => {
+                Some(metadata.0.to_string())
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_declare_conflict() -> buck2_error::Result<()> {
+        ignore_stack_overflow_checks_for_future(async {
+            let (mut dm, _) = make_processor(Default::default());
+            let (mut events, sink) = buck2_events::create_source_sink_pair();
+            let dispatcher = EventDispatcher::new(TraceId::new(), sink);
             let digest_config = dm.io.digest_config();
+            let path = make_path("foo/bar");
+            let first = ArtifactMetadata::new(file_artifact(b"first", digest_config).entry())
+                .0
+                .to_string();
+            let second = ArtifactMetadata::new(file_artifact(b"second", digest_config).entry())
+                .0
+                .to_string();
+
+            dm.testing_declare_with_context(
+                &path,
+                file_artifact(b"first", digest_config),
+                declare_context(&dispatcher, 1, false),
+            )?;
+            // Another action of the same command declares different contents, which replace the
+            // first ones.
+            dm.testing_declare_with_context(
+                &path,
+                file_artifact(b"second", digest_config),
+                declare_context(&dispatcher, 2, false),
+            )?;
+            assert_eq!(Some(second.clone()), entry_at(&dm, &path));
 
-            // Declare symlink target
-            dm.testing_declare(
-                &target_path,
-                ArtifactValue::file(digest_config.empty_file()),
+            assert_eq!(
+                vec![buck2_data::MaterializerDeclareConflict {
+                    path: path.to_string(),
+                    first_owner: Some(buck2_data::MaterializerDeclareOwner {
+                        trace_id: dispatcher.trace_id().to_string(),
+                        span_id: Some(1),
+                    }),
+                    first_entry: first,
+                    second_owner: Some(buck2_data::MaterializerDeclareOwner {
+                        trace_id: dispatcher.trace_id().to_string(),
+                        span_id: Some(2),
+                    }),
+                    second_entry: second,
+                    failed: false,
+                }],
+                declare_conflicts(&mut events)
             );
-            assert_eq!(dm.io.take_log(), &[(Op::Clean, target_path.clone())]);
 
-            // Declare symlink
-            let symlink_value = make_artifact_value_with_symlink_dep(
-                &target_path,
+            Ok(())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_declare_conflict_fails() -> buck2_error::Result<()> {
+        ignore_stack_overflow_checks_for_future(async {
+            let (mut dm, _) = make_processor(Default::default());
+            let (mut events, sink) = buck2_events::create_source_sink_pair();
+            let dispatcher = EventDispatcher::new(TraceId::new(), sink);
+            let digest_config = dm.io.digest_config();
+            let path = make_path("foo/bar");
+            let first = ArtifactMetadata::new(file_artifact(b"first", digest_config).entry())
+                .0
+                .to_string();
+
+            dm.testing_declare_with_context(
+                &path,
+                file_artifact(b"first", digest_config),
+                declare_context(&dispatcher, 1, true),
+            )?;
+            let err = dm
+                .testing_declare_with_context(
+                    &path,
+                    file_artifact(b"second", digest_config),
+                    declare_context(&dispatcher, 2, true),
+                )
+                .unwrap_err();
+            assert!(err.has_tag(buck2_error::ErrorTag::DeclareConflict));
+            // The first declare stays.
+            assert_eq!(Some(first.clone()), entry_at(&dm, &path));
+
+            // Conflicts are also detected once the artifact is materialized.
+            let res = dm
+                .materialize_artifact(&path, EventDispatcher::null())
+                .buck_error_context("Expected a future")?
+                .await;
+            dm.testing_materialization_finished(path.clone(), Utc::now(), res);
+            dm.testing_declare_with_context(
+                &path,
+                file_artifact(b"third", digest_config),
+                declare_context(&dispatcher, 3, true),
+            )
+            .unwrap_err();
+            assert_eq!(Some(first), entry_at(&dm, &path));
+
+            let conflicts = declare_conflicts(&mut events);
+            assert_eq!(2, conflicts.len());
+            assert!(conflicts.iter().all(|conflict| conflict.failed));
+
+            Ok(())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_declare_conflict_exemptions() -> buck2_error::Result<()> {
+        ignore_stack_overflow_checks_for_future(async {
+            let (mut dm, _) = make_processor(Default::default());
+            let (mut events, sink) = buck2_events::create_source_sink_pair();
+            let sink: Arc<dyn EventSink> = Arc::new(sink);
+            let dispatcher = EventDispatcher::new(TraceId::new(), sink.dupe());
+            let digest_config = dm.io.digest_config();
+            let path = make_path("foo/bar");
+
+            dm.testing_declare_with_context(
+                &path,
+                file_artifact(b"first", digest_config),
+                declare_context(&dispatcher, 1, true),
+            )?;
+            // The same action declares again, e.g. on a retry.
+            dm.testing_declare_with_context(
+                &path,
+                file_artifact(b"second", digest_config),
+                declare_context(&dispatcher, 1, true),
+            )?;
+            // Another action declares the same contents.
+            dm.testing_declare_with_context(
+                &path,
+                file_artifact(b"second", digest_config),
+                declare_context(&dispatcher, 2, true),
+            )?;
+            // A later command declares different contents, e.g. after a source change.
+            let next_command = EventDispatcher::new(TraceId::new(), sink);
+            dm.testing_declare_with_context(
+                &path,
+                file_artifact(b"third", digest_config),
+                declare_context(&next_command, 3, true),
+            )?;
+
+            assert_eq!(
+                Some(
+                    ArtifactMetadata::new(file_artifact(b"third", digest_config).entry())
+                        .0
+                        .to_string()
+                ),
+                entry_at(&dm, &path)
+            );
+            assert_eq!(
+                Vec::<buck2_data::MaterializerDeclareConflict>::new(),
+                declare_conflicts(&mut events)
+            );
+
+            Ok(())
+        })
+        .await
+    }
+
+    /// Matches `value` at `path`, and processes the verification the match schedules, if any.
+    async fn match_and_verify(
+        dm: &mut DeferredMaterializerCommandProcessor<StubIoHandler>,
+        channel: &mut MaterializerReceiver<StubIoHandler>,
+        path: &ProjectRelativePathBuf,
+        value: &ArtifactValue,
+    ) -> buck2_error::Result<bool> {
+        let (sender, recv) = oneshot::channel();
+        dm.testing_process_one_command(MaterializerCommand::MatchArtifacts(
+            vec![(path.clone(), value.dupe())],
+            sender,
+        ));
+        let is_match = recv.await?;
+        if is_match {
+            let command = channel.low_priority.recv().await.unwrap();
+            assert_matches!(
+                command,
+                LowPriorityMaterializerCommand::VerificationFinished { .. }
+            );
+            dm.testing_process_one_low_priority_command(command);
+        }
+        Ok(is_match)
+    }
+
+    #[tokio::test]
+    async fn test_paranoid_verification() -> buck2_error::Result<()> {
+        ignore_stack_overflow_checks_for_future(async {
+            let (mut dm, mut channel) = make_processor(Default::default());
+            dm.paranoid_verification = true;
+            let digest_config = dm.io.digest_config();
+
+            let path = make_path("foo/bar");
+            let value = ArtifactValue::file(digest_config.empty_file());
+            dm.testing_declare_existing(&path, value.dupe());
+
+            assert!(match_and_verify(&mut dm, &mut channel, &path, &value).await?);
+            assert!(dm.testing_has_artifact(path.clone()));
+
+            // The match itself doesn't wait for the verification, but the mismatch invalidates the
+            // artifact, so it doesn't match next time.
+            dm.io.set_mismatch_on(vec![path.clone()]);
+            assert!(match_and_verify(&mut dm, &mut channel, &path, &value).await?);
+            assert!(!dm.testing_has_artifact(path.clone()));
+            assert!(!match_and_verify(&mut dm, &mut channel, &path, &value).await?);
+
+            // Once declared again, it's materialized again.
+            dm.testing_declare(&path, value.dupe());
+            assert_eq!(dm.io.take_log(), &[(Op::Clean, path.clone())]);
+
+            Ok(())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_paranoid_verification_outdated() -> buck2_error::Result<()> {
+        ignore_stack_overflow_checks_for_future(async {
+            let (mut dm, mut channel) = make_processor(Default::default());
+            dm.paranoid_verification = true;
+            let digest_config = dm.io.digest_config();
+
+            let path = make_path("foo/bar");
+            let value = ArtifactValue::file(digest_config.empty_file());
+            dm.testing_declare_existing(&path, value.dupe());
+            dm.io.set_mismatch_on(vec![path.clone()]);
+
+            let (sender, recv) = oneshot::channel();
+            dm.testing_process_one_command(MaterializerCommand::MatchArtifacts(
+                vec![(path.clone(), value.dupe())],
+                sender,
+            ));
+            assert!(recv.await?);
+            let command = channel.low_priority.recv().await.unwrap();
+
+            // Declared again while verifying, the mismatch is about an older version.
+            dm.testing_declare_existing(&path, value.dupe());
+            dm.testing_process_one_low_priority_command(command);
+            assert!(dm.testing_has_artifact(path.clone()));
+
+            Ok(())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_materialization_latencies() -> buck2_error::Result<()> {
+        ignore_stack_overflow_checks_for_future(async {
+            let (mut dm, mut channel) = make_processor(Default::default());
+            let digest_config = dm.io.digest_config();
+            let latency = |dm: &DeferredMaterializerCommandProcessor<StubIoHandler>| {
+                let test = &dm.testing_stats().materialization_latencies.test;
+                (
+                    test.count.load(Ordering::Relaxed),
+                    test.total_nanos.load(Ordering::Relaxed),
+                )
+            };
+            assert_eq!(latency(&dm), (0, 0));
+
+            let path = make_path("foo/bar");
+            let value = ArtifactValue::file(digest_config.empty_file());
+            dm.testing_declare(&path, value.dupe());
+            dm.materialize_artifact(&path, EventDispatcher::null())
+                .buck_error_context("Expected a future")?
+                .await
+                .map_err(|err| {
+                    buck2_error!(
+                        buck2_error::ErrorTag::MaterializationError,
+                        "error materializing {:?}",
+                        err
+                    )
+                })?;
+            while let Ok(cmd) = channel.low_priority.try_recv() {
+                dm.testing_process_one_low_priority_command(cmd);
+            }
+            assert_eq!(latency(&dm).0, 1);
+
+            // Requesting the artifact again doesn't materialize it again.
+            assert!(
+                dm.materialize_artifact(&path, EventDispatcher::null())
+                    .is_none()
+            );
+            assert_eq!(latency(&dm).0, 1);
+
+            Ok(())
+        })
+        .await
+    }
+
+    /// Ensures `paths` on behalf of the command with `trace_id` and waits for them.
+    async fn ensure_for_trace(
+        dm: &mut DeferredMaterializerCommandProcessor<StubIoHandler>,
+        channel: &mut MaterializerReceiver<StubIoHandler>,
+        trace_id: &TraceId,
+        paths: Vec<ProjectRelativePathBuf>,
+    ) -> buck2_error::Result<()> {
+        let (sender, recv) = oneshot::channel();
+        dm.testing_process_one_command(MaterializerCommand::Ensure(
+            paths,
+            EventDispatcher::null_sink_with_trace(trace_id.dupe()),
+            sender,
+        ));
+        for res in recv.await?.collect::<Vec<_>>().await {
+            res?;
+        }
+        while let Ok(cmd) = channel.low_priority.try_recv() {
+            dm.testing_process_one_low_priority_command(cmd);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_command_stats() -> buck2_error::Result<()> {
+        ignore_stack_overflow_checks_for_future(async {
+            let (mut dm, mut channel) = make_processor(Default::default());
+            let digest_config = dm.io.digest_config();
+
+            let a = make_path("a");
+            let b = make_path("b");
+            let c = make_path("c");
+            dm.testing_declare(&a, file_artifact(&[0; 10], digest_config));
+            dm.testing_declare(&b, file_artifact(&[0; 100], digest_config));
+            dm.testing_declare(&c, file_artifact(&[0; 1000], digest_config));
+
+            let cold = TraceId::new();
+            let warm = TraceId::new();
+            ensure_for_trace(&mut dm, &mut channel, &cold, vec![a.clone(), b.clone()]).await?;
+            // `b` was materialized by the first command.
+            ensure_for_trace(&mut dm, &mut channel, &warm, vec![b.clone(), c.clone()]).await?;
+            ensure_for_trace(&mut dm, &mut channel, &warm, vec![a.clone()]).await?;
+
+            let stats = &dm.testing_stats().command_stats;
+            assert_eq!(
+                stats.take(&cold),
+                Some(buck2_data::CommandMaterializationStats {
+                    ensures: 2,
+                    already_materialized: 0,
+                    materializations_by_method: HashMap::from([("test".to_owned(), 2)]),
+                    bytes_fetched: 110,
+                })
+            );
+            assert_eq!(
+                stats.take(&warm),
+                Some(buck2_data::CommandMaterializationStats {
+                    ensures: 3,
+                    already_materialized: 2,
+                    materializations_by_method: HashMap::from([("test".to_owned(), 1)]),
+                    bytes_fetched: 1000,
+                })
+            );
+            // Taking the stats forgets them.
+            assert_eq!(stats.take(&cold), None);
+
+            Ok(())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_full_low_priority_queue_does_not_block_high_priority() -> buck2_error::Result<()>
+    {
+        let (processor, command_sender, command_receiver, _) =
+            make_processor_for_io_with_low_priority_capacity(
+                Arc::new(StubIoHandler::new(temp_root())),
+                1,
+            );
+        let cleanup_finished = |path: &str| LowPriorityMaterializerCommand::CleanupFinished {
+            path: make_path(path),
+            version: Version(0),
+            result: Ok(()),
+        };
+
+        // Fill the queue before the command thread starts, so that the next send has to wait.
+        command_sender
+            .send_low_priority(cleanup_finished("foo"))
+            .await
+            .unwrap();
+        let blocked = tokio::spawn({
+            let command_sender = command_sender.dupe();
+            let command = cleanup_finished("bar");
+            async move { command_sender.send_low_priority(command).await }
+        });
+        while command_sender
+            .stats
+            .low_priority_blocked_sends
+            .load(Ordering::Relaxed)
+            == 0
+        {
+            tokio::task::yield_now().await;
+        }
+        assert!(!blocked.is_finished());
+
+        let _command_thread = spawn_command_loop(processor, command_receiver, None);
+
+        // High priority commands are still processed.
+        let (sender, recv) = oneshot::channel();
+        command_sender
+            .send(MaterializerCommand::GetArtifactState(
+                make_path("baz"),
+                sender,
+            ))
+            .unwrap();
+        assert_matches!(recv.await?, None);
+
+        // And the blocked sender gets through once the command thread drains the queue.
+        blocked.await.unwrap().unwrap();
+
+        let stats = &command_sender.stats;
+        assert_eq!(stats.low_priority_blocked_sends.load(Ordering::Relaxed), 1);
+        assert_eq!(
+            stats
+                .low_priority_queue_high_watermark
+                .load(Ordering::Relaxed),
+            1
+        );
+        assert!(stats.queue_size_high_watermark.load(Ordering::Relaxed) >= 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_blocking_low_priority_send_does_not_wait() -> buck2_error::Result<()> {
+        let (_processor, command_sender, mut command_receiver, _) =
+            make_processor_for_io_with_low_priority_capacity(
+                Arc::new(StubIoHandler::new(temp_root())),
+                1,
+            );
+        let cleanup_finished = |path: &str| LowPriorityMaterializerCommand::CleanupFinished {
+            path: make_path(path),
+            version: Version(0),
+            result: Ok(()),
+        };
+
+        command_sender
+            .send_low_priority(cleanup_finished("foo"))
+            .await
+            .unwrap();
+
+        // Nothing drains the full queue, but a blocking IO thread still gets through, on the high
+        // priority queue.
+        tokio::task::spawn_blocking({
+            let command_sender = command_sender.dupe();
+            let command = cleanup_finished("bar");
+            move || command_sender.blocking_send_low_priority(command)
+        })
+        .await
+        .unwrap();
+
+        assert_matches!(
+            command_receiver.low_priority.try_recv(),
+            Ok(LowPriorityMaterializerCommand::CleanupFinished { path, .. }) if path == make_path("foo")
+        );
+        assert_matches!(
+            command_receiver.high_priority.try_recv(),
+            Ok(MaterializerCommand::LowPriority(
+                LowPriorityMaterializerCommand::CleanupFinished { path, .. }
+            )) if path == make_path("bar")
+        );
+        assert_eq!(
+            command_sender
+                .stats
+                .low_priority_blocked_sends
+                .load(Ordering::Relaxed),
+            1
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_access_times_flush_does_not_block_commands() -> buck2_error::Result<()> {
+        ignore_stack_overflow_checks_for_future(async {
+            let (mut dm, mut channel) = make_processor(Default::default());
+            let digest_config = dm.io.digest_config();
+            dm.access_times_buffer = Some(HashSet::new());
+
+            let existing = make_path("foo/existing");
+            let value = ArtifactValue::file(digest_config.empty_file());
+            dm.testing_declare_existing(&existing, value.dupe());
+            assert!(
+                dm.materialize_artifact(&existing, EventDispatcher::null())
+                    .is_none()
+            );
+            assert_eq!(dm.access_times_buffer.as_ref().unwrap().len(), 1);
+
+            // Stall the sqlite write.
+            let gate = dm
+                .sqlite_db
+                .as_mut()
+                .unwrap()
+                .materializer_state_table()
+                .access_times_gate
+                .dupe();
+            let guard = gate.lock();
+
+            let (sender, mut flushed) = oneshot::channel();
+            dm.flush_access_times(0, Some(sender));
+            assert!(dm.access_times_flush.is_some());
+            assert!(dm.access_times_buffer.as_ref().unwrap().is_empty());
+
+            // Accessing a path that is being flushed doesn't buffer it again.
+            assert!(
+                dm.materialize_artifact(&existing, EventDispatcher::null())
+                    .is_none()
+            );
+            assert!(dm.access_times_buffer.as_ref().unwrap().is_empty());
+
+            // Declares, which write to sqlite too, still go through.
+            let declared = make_path("foo/declared");
+            dm.testing_declare(&declared, value.dupe());
+            assert!(dm.tree.artifact_state(&declared).is_some());
+            assert!(flushed.try_recv().is_err());
+
+            drop(guard);
+            let message = loop {
+                let cmd = channel.low_priority.recv().await.unwrap();
+                dm.testing_process_one_low_priority_command(cmd);
+                if let Ok(message) = flushed.try_recv() {
+                    break message;
+                }
+            };
+            assert!(message.starts_with("Finished flushing 1 entries"));
+            assert!(dm.access_times_flush.is_none());
+
+            // Once the flush finished, accesses are buffered again.
+            assert!(
+                dm.materialize_artifact(&existing, EventDispatcher::null())
+                    .is_none()
+            );
+            assert_eq!(dm.access_times_buffer.as_ref().unwrap().len(), 1);
+
+            Ok(())
+        })
+        .await
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_prune_idle_declared() -> buck2_error::Result<()> {
+        ignore_stack_overflow_checks_for_future(async {
+            let (mut dm, mut channel) = make_processor(Default::default());
+            let digest_config = dm.io.digest_config();
+            let idle_ttl = std::time::Duration::from_secs(60 * 60);
+
+            let declared = make_path("foo/declared");
+            let existing = make_path("foo/existing");
+            let value = ArtifactValue::file(digest_config.empty_file());
+
+            dm.testing_declare(&declared, value.dupe());
+            dm.testing_declare_existing(&existing, value.dupe());
+
+            // Let the cleanup for the declared artifact finish, so it's no longer being processed.
+            let cleaning = match &dm
+                .tree
+                .prefix_get_mut(&mut declared.iter())
+                .buck_error_context("Expected an entry")?
+                .processing
+            {
+                Processing::Active {
+                    future: ProcessingFuture::Cleaning(fut),
+                    ..
+                } => fut.clone(),
+                _ => panic!("Expected a cleaning future"),
+            };
+            cleaning.await?;
+            while let Ok(cmd) = channel.low_priority.try_recv() {
+                dm.testing_process_one_low_priority_command(cmd);
+            }
+
+            // Nothing has been idle long enough yet.
+            tokio::time::advance(idle_ttl / 2).await;
+            dm.testing_sweep_artifact_tree(Some(idle_ttl));
+            assert_eq!(
+                dm.testing_stats().declared_pruned.load(Ordering::Relaxed),
+                0
+            );
+            // Requesting the artifact counts as using it.
+            assert!(dm.testing_has_artifact(declared.clone()));
+            tokio::time::advance(idle_ttl * 3 / 4).await;
+            dm.testing_sweep_artifact_tree(Some(idle_ttl));
+            assert_eq!(
+                dm.testing_stats().declared_pruned.load(Ordering::Relaxed),
+                0
+            );
+
+            // Once the threshold has passed, only the declared artifact is forgotten.
+            tokio::time::advance(idle_ttl).await;
+            dm.testing_sweep_artifact_tree(Some(idle_ttl));
+            assert!(!dm.testing_has_artifact(declared.clone()));
+            assert!(dm.testing_has_artifact(existing.clone()));
+            assert_eq!(
+                dm.testing_stats().declared_pruned.load(Ordering::Relaxed),
+                1
+            );
+
+            // DICE still caches the action that declared it, so ensuring it fails instead of
+            // reporting success with nothing on disk.
+            let res = dm
+                .materialize_artifact(&declared, EventDispatcher::null())
+                .buck_error_context("Expected a future")?
+                .await;
+            assert_matches!(
+                res,
+                Err(SharedMaterializingError::Error(e)) if format!("{:#}", e).contains("was forgotten by the materializer")
+            );
+            // Artifacts that were never declared are still unknown.
+            assert!(
+                dm.materialize_artifact(&make_path("foo/other"), EventDispatcher::null())
+                    .is_none()
+            );
+
+            // The artifact can be declared again, which clears the error.
+            dm.testing_declare(&declared, value.dupe());
+            assert!(dm.testing_has_artifact(declared.clone()));
+            assert!(dm.pruned.prefix_get(&mut declared.iter()).is_none());
+
+            // The size of the tree is only measured on request.
+            assert_eq!(
+                dm.testing_stats()
+                    .artifact_tree_bytes
+                    .load(Ordering::Relaxed),
+                0
+            );
+            assert!(dm.measure_artifact_tree() > 0);
+            assert!(
+                dm.testing_stats()
+                    .artifact_tree_bytes
+                    .load(Ordering::Relaxed)
+                    > 0
+            );
+
+            Ok(())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_artifact_state() -> buck2_error::Result<()> {
+        ignore_stack_overflow_checks_for_future(async {
+            let (mut dm, mut channel) = make_processor(Default::default());
+            let digest_config = dm.io.digest_config();
+
+            let declared = make_path("foo/declared");
+            let existing = make_path("foo/existing");
+            let value = ArtifactValue::file(digest_config.empty_file());
+
+            assert_eq!(dm.tree.artifact_state(&declared), None);
+
+            dm.testing_declare(&declared, value.dupe());
+            assert_matches!(
+                dm.tree.artifact_state(&declared),
+                Some(ArtifactState::Declared {
+                    processing: ArtifactProcessing::Cleaning,
+                    ..
+                })
+            );
+
+            let res = dm
+                .materialize_artifact(&declared, EventDispatcher::null())
+                .buck_error_context("Expected a future")?;
+            assert_matches!(
+                dm.tree.artifact_state(&declared),
+                Some(ArtifactState::Declared {
+                    processing: ArtifactProcessing::Materializing,
+                    ..
+                })
+            );
+
+            res.await.map_err(|err| {
+                buck2_error!(
+                    buck2_error::ErrorTag::MaterializationError,
+                    "error materializing {:?}",
+                    err
+                )
+            })?;
+            while let Ok(cmd) = channel.low_priority.try_recv() {
+                dm.testing_process_one_low_priority_command(cmd);
+            }
+            assert_matches!(
+                dm.tree
+                    .artifact_state(&declared.join(ForwardRelativePath::new("inner").unwrap())),
+                Some(ArtifactState::Materialized {
+                    processing: ArtifactProcessing::Idle,
+                    ..
+                })
+            );
+
+            dm.testing_declare_existing(&existing, value.dupe());
+            assert_matches!(
+                dm.tree.artifact_state(&existing),
+                Some(ArtifactState::Materialized {
+                    processing: ArtifactProcessing::Idle,
+                    ..
+                })
+            );
+
+            Ok(())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_preview_invalidate() -> buck2_error::Result<()> {
+        let (mut dm, _channel) = make_processor(Default::default());
+        let digest_config = dm.io.digest_config();
+
+        let materialized = make_path("foo/materialized");
+        let declared = make_path("foo/declared");
+        let value = ArtifactValue::file(digest_config.empty_file());
+        dm.testing_declare_existing(&materialized, value.dupe());
+        dm.testing_declare(&declared, value.dupe());
+
+        let mut preview = |paths: Vec<&str>| {
+            let (sender, mut recv) = oneshot::channel();
+            dm.testing_process_one_command(MaterializerCommand::PreviewInvalidate(
+                paths.into_iter().map(make_path).collect(),
+                sender,
+            ));
+            let mut res = recv.try_recv().unwrap();
+            res.sort();
+            res
+        };
+
+        assert_eq!(
+            preview(vec!["foo"]),
+            vec![
+                (declared.clone(), "DeclaredInFlight"),
+                (materialized.clone(), "Materialized"),
+            ]
+        );
+        // A path inside an artifact previews the artifact.
+        assert_eq!(
+            preview(vec!["foo/materialized/inner", "bar"]),
+            vec![(materialized.clone(), "Materialized")]
+        );
+
+        // Nothing was removed.
+        assert!(dm.tree.artifact_state(&materialized).is_some());
+        assert!(dm.tree.artifact_state(&declared).is_some());
+
+        Ok(())
+    }
+
+    fn make_artifact_value_with_symlink_dep(
+        target_path: &ProjectRelativePathBuf,
+        target_from_symlink: &RelativePathBuf,
+        digest_config: DigestConfig,
+    ) -> buck2_error::Result<ArtifactValue> {
+        let mut deps = ActionDirectoryBuilder::empty();
+        let target = ActionDirectoryEntry::Leaf(ActionDirectoryMember::File(FileMetadata::empty(
+            digest_config.cas_digest_config(),
+        )));
+        deps.insert(target_path.as_forward_relative_path(), target)?;
+        let symlink_value = ArtifactValue::new(
+            ActionDirectoryEntry::Leaf(ActionDirectoryMember::Symlink(Arc::new(Symlink::new(
+                target_from_symlink.clone(),
+            )))),
+            Some(
+                deps.fingerprint(digest_config.as_directory_serializer())
+                    .shared(&*INTERNER),
+            ),
+        );
+        Ok(symlink_value)
+    }
+
+    #[tokio::test]
+    async fn test_materialize_symlink_and_target() -> buck2_error::Result<()> {
+        ignore_stack_overflow_checks_for_future(async {
+            // Construct a tree with a symlink and its target, materialize both at once
+            let symlink_path = make_path("foo/bar_symlink");
+            let target_path = make_path("foo/bar_target");
+            let target_from_symlink = RelativePathBuf::from_path(Path::new("bar_target"))?;
+
+            let mut materialization_config = HashMap::new();
+            // Materialize the symlink target slowly so that we actually hit the logic point where we
+            // await for symlink targets and the entry materialization
+            materialization_config.insert(target_path.clone(), TokioDuration::from_millis(100));
+
+            let (mut dm, _) = make_processor(materialization_config);
+            let digest_config = dm.io.digest_config();
+
+            // Declare symlink target
+            dm.testing_declare(
+                &target_path,
+                ArtifactValue::file(digest_config.empty_file()),
+            );
+            assert_eq!(dm.io.take_log(), &[(Op::Clean, target_path.clone())]);
+
+            // Declare symlink
+            let symlink_value = make_artifact_value_with_symlink_dep(
+                &target_path,
                 &target_from_symlink,
                 digest_config,
             )?;
@@ -896,6 +2270,106 @@ mod state_machine {
         .await
     }
 
+    #[tokio::test]
+    async fn test_subscription_prefix_notifications() {
+        ignore_stack_overflow_checks_for_future(async {
+            let (mut dm, mut channel) = make_processor(Default::default());
+            let digest_config = dm.io.digest_config();
+            let value = ArtifactValue::file(digest_config.empty_file());
+
+            let mut handle = {
+                let (sender, recv) = oneshot::channel();
+                MaterializerSubscriptionOperation::Create { sender }.execute(&mut dm);
+                recv.await.unwrap()
+            };
+
+            let foo_existing = make_path("foo/existing");
+            let foo_a = make_path("foo/a");
+            let foo_bar_b = make_path("foo/bar/b");
+            let foob = make_path("foob");
+            let qux = make_path("qux");
+
+            dm.testing_declare_existing(&foo_existing, value.dupe());
+
+            // Overlapping prefixes only produce one notification per path.
+            handle.subscribe_to_prefix(make_path("foo"));
+            handle.subscribe_to_prefix(make_path("foo/bar"));
+            while let Ok(cmd) = channel.high_priority.try_recv() {
+                dm.testing_process_one_command(cmd);
+            }
+
+            assert!(dm.subscriptions.should_materialize_eagerly(&foo_a));
+            assert!(!dm.subscriptions.should_materialize_eagerly(&foob));
+
+            dm.testing_declare_existing(&foo_a, value.dupe());
+            dm.testing_declare_existing(&foo_bar_b, value.dupe());
+            dm.testing_declare_existing(&foob, value.dupe());
+            dm.testing_declare_existing(&qux, value.dupe());
+
+            let mut paths = Vec::new();
+            while let Ok(path) = handle.receiver().try_recv() {
+                paths.push(path);
+            }
+
+            assert_eq!(paths, vec![foo_existing, foo_a, foo_bar_b]);
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_subscription_unsubscribe_prefix() {
+        ignore_stack_overflow_checks_for_future(async {
+            let (mut dm, mut channel) = make_processor(Default::default());
+            let digest_config = dm.io.digest_config();
+            let value = ArtifactValue::file(digest_config.empty_file());
+
+            let mut handle = {
+                let (sender, recv) = oneshot::channel();
+                MaterializerSubscriptionOperation::Create { sender }.execute(&mut dm);
+                recv.await.unwrap()
+            };
+
+            let foo_a = make_path("foo/a");
+            let foo_bar_b = make_path("foo/bar/b");
+            let foo_bar_c = make_path("foo/bar/c");
+            let foo_bar_exact = make_path("foo/bar/exact");
+            let qux_exact = make_path("qux/exact");
+
+            handle.subscribe_to_prefix(make_path("foo"));
+            handle.subscribe_to_prefix(make_path("foo/bar"));
+            handle.subscribe_to_paths(vec![foo_bar_exact.clone()]);
+            handle.subscribe_to_prefix(make_path("qux"));
+            handle.subscribe_to_paths(vec![qux_exact.clone()]);
+
+            // `foo/bar` still covers its paths once `foo` is gone.
+            handle.unsubscribe_from_prefix(make_path("foo"));
+            while let Ok(cmd) = channel.high_priority.try_recv() {
+                dm.testing_process_one_command(cmd);
+            }
+            dm.testing_declare_existing(&foo_a, value.dupe());
+            dm.testing_declare_existing(&foo_bar_b, value.dupe());
+
+            // An exact subscription still applies once the prefix covering it is gone, and a
+            // prefix still applies once an exact subscription under it is gone.
+            handle.unsubscribe_from_prefix(make_path("foo/bar"));
+            handle.unsubscribe_from_paths(vec![qux_exact.clone()]);
+            while let Ok(cmd) = channel.high_priority.try_recv() {
+                dm.testing_process_one_command(cmd);
+            }
+            dm.testing_declare_existing(&foo_bar_c, value.dupe());
+            dm.testing_declare_existing(&foo_bar_exact, value.dupe());
+            dm.testing_declare_existing(&qux_exact, value.dupe());
+
+            let mut paths = Vec::new();
+            while let Ok(path) = handle.receiver().try_recv() {
+                paths.push(path);
+            }
+
+            assert_eq!(paths, vec![foo_bar_b, foo_bar_exact, qux_exact]);
+        })
+        .await
+    }
+
     #[tokio::test]
     async fn test_invalidate_error() -> buck2_error::Result<()> {
         ignore_stack_overflow_checks_for_future(async{
@@ -1034,52 +2508,275 @@ mod state_machine {
             // Make materializations fail
             dm.io.set_fail(true);
 
-            // Materializing it fails.
-            let res = dm
-                .materialize_artifact(&path, EventDispatcher::null())
-                .buck_error_context("Expected a future")?
-                .await;
+            // Materializing it fails.
+            let res = dm
+                .materialize_artifact(&path, EventDispatcher::null())
+                .buck_error_context("Expected a future")?
+                .await;
+
+            assert_matches!(
+                res,
+                Err(SharedMaterializingError::Error(e)) if format!("{:#}", e).contains("Injected error")
+            );
+
+            // Unset fail, but we haven't processed materialization_finished yet so this does nothing.
+            dm.io.set_fail(false);
+
+            // Rejoining the existing future fails.
+            let res = dm
+                .materialize_artifact(&path, EventDispatcher::null())
+                .buck_error_context("Expected a future")?
+                .await;
+
+            assert_matches!(
+                res,
+                Err(SharedMaterializingError::Error(e)) if format!("{:#}", e).contains("Injected error")
+            );
+
+            // Now process cleanup_finished_vacant and materialization_finished.
+            let mut processed = 0;
+
+            while let Ok(cmd) = channel.low_priority.try_recv() {
+                eprintln!("got cmd = {:?}", cmd);
+                dm.testing_process_one_low_priority_command(cmd);
+                processed += 1;
+            }
+
+            assert_eq!(processed, 2);
+
+            // Materializing works now:
+            let res = dm
+                .materialize_artifact(&path, EventDispatcher::null())
+                .buck_error_context("Expected a future")?
+                .await;
+
+            assert_matches!(res, Ok(()));
+
+            Ok(())
+        }).await
+    }
+
+    /// A completion callback recording the stage of the artifact at `path` when it runs.
+    fn record_stage(
+        log: &Arc<Mutex<Vec<&'static str>>>,
+        path: &ProjectRelativePathBuf,
+    ) -> impl FnOnce(
+        &mut DeferredMaterializerCommandProcessor<StubIoHandler>,
+        buck2_error::Result<()>,
+    ) + Send
+    + 'static {
+        let log = log.dupe();
+        let path = path.clone();
+        move |dm, result| {
+            result.unwrap();
+            let stage = match dm.tree.prefix_get(&mut path.iter()).map(|data| &data.stage) {
+                Some(ArtifactMaterializationStage::Declared { .. }) => "declared",
+                Some(ArtifactMaterializationStage::Materialized { .. }) => "materialized",
+                None => "vacant",
+            };
+            log.lock().push(stage);
+        }
+    }
+
+    /// Processes low priority commands until `log` is written to, returning what was processed.
+    async fn process_low_priority_until_logged(
+        dm: &mut DeferredMaterializerCommandProcessor<StubIoHandler>,
+        channel: &mut MaterializerReceiver<StubIoHandler>,
+        log: &Arc<Mutex<Vec<&'static str>>>,
+    ) -> Vec<String> {
+        let mut processed = Vec::new();
+        while log.lock().is_empty() {
+            let command = channel.low_priority.recv().await.unwrap();
+            let name = format!("{:?}", command);
+            processed.push(name.split_whitespace().next().unwrap().to_owned());
+            dm.testing_process_one_low_priority_command(command);
+        }
+        processed
+    }
+
+    #[tokio::test]
+    async fn test_completion_callback_after_completion() -> buck2_error::Result<()> {
+        ignore_stack_overflow_checks_for_future(async {
+            let (mut dm, mut channel) = make_processor(Default::default());
+            let digest_config = dm.io.digest_config();
+            let path = make_path("test");
+            dm.testing_declare(&path, ArtifactValue::file(digest_config.empty_file()));
+            let materialization = dm
+                .materialize_artifact(&path, EventDispatcher::null())
+                .buck_error_context("Expected a future")?;
+
+            let log = Arc::new(Mutex::new(Vec::new()));
+            let mut scheduling = dm.scheduling();
+            let futures = scheduling.active_futures(&[path.clone()]);
+            assert_eq!(futures.len(), 1);
+            scheduling.on_completion(futures, record_stage(&log, &path));
+
+            assert_matches!(materialization.await, Ok(()));
+            let processed = process_low_priority_until_logged(&mut dm, &mut channel, &log).await;
+
+            // The callback runs after the tree recorded the materialization.
+            assert_eq!(
+                processed,
+                [
+                    "CleanupFinished",
+                    "MaterializationFinished",
+                    "CompletionReady"
+                ]
+            );
+            assert_eq!(*log.lock(), ["materialized"]);
+            Ok(())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_completion_callback_after_outdated_completion() -> buck2_error::Result<()> {
+        ignore_stack_overflow_checks_for_future(async {
+            let (mut dm, mut channel) = make_processor(Default::default());
+            let digest_config = dm.io.digest_config();
+            let path = make_path("test");
+            dm.testing_declare(&path, ArtifactValue::file(digest_config.empty_file()));
+            let materialization = dm
+                .materialize_artifact(&path, EventDispatcher::null())
+                .buck_error_context("Expected a future")?;
+
+            let log = Arc::new(Mutex::new(Vec::new()));
+            let mut scheduling = dm.scheduling();
+            let futures = scheduling.active_futures(&[path.clone()]);
+            scheduling.on_completion(futures, record_stage(&log, &path));
+
+            // Declared again while materializing, so the materialization finishes for an
+            // outdated version.
+            dm.testing_declare(&path, ArtifactValue::dir(digest_config.empty_directory()));
+
+            assert_matches!(materialization.await, Ok(()));
+            let processed = process_low_priority_until_logged(&mut dm, &mut channel, &log).await;
+
+            let position = |name: &str| processed.iter().position(|p| p == name);
+            assert!(
+                position("MaterializationFinished") < position("CompletionReady"),
+                "{:?}",
+                processed
+            );
+            // The outdated materialization was ignored by the time the callback runs.
+            assert_eq!(*log.lock(), ["declared"]);
+            Ok(())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_completion_callback_cancel() -> buck2_error::Result<()> {
+        ignore_stack_overflow_checks_for_future(async {
+            let (mut dm, mut channel) = make_processor(Default::default());
+            let path = make_path("test");
+
+            let log = Arc::new(Mutex::new(Vec::new()));
+            let cancelled = dm
+                .scheduling()
+                .on_completion(Vec::new(), record_stage(&log, &path));
+            dm.scheduling()
+                .on_completion(Vec::new(), record_stage(&log, &path));
+            assert!(dm.scheduling().cancel(cancelled));
+            assert!(!dm.scheduling().cancel(cancelled));
+
+            let processed = process_low_priority_until_logged(&mut dm, &mut channel, &log).await;
+            assert_eq!(processed, ["CompletionReady", "CompletionReady"]);
+            assert_eq!(*log.lock(), ["vacant"]);
+            Ok(())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_scheduling_enqueue() -> buck2_error::Result<()> {
+        ignore_stack_overflow_checks_for_future(async {
+            let (mut dm, mut channel) = make_processor(Default::default());
+            let (clean_observer, clean_guard) = LivelinessGuard::create_sync();
+            *dm.command_sender.clean_guard.lock() = Some(clean_guard);
+
+            dm.scheduling()
+                .enqueue(MaterializerCommand::DeclareExisting(vec![], None, None));
+            dm.scheduling().enqueue_low_priority(
+                LowPriorityMaterializerCommand::AccessTimesFlushed {
+                    size: 0,
+                    elapsed: std::time::Duration::ZERO,
+                    result: Ok(()),
+                },
+            );
 
             assert_matches!(
-                res,
-                Err(SharedMaterializingError::Error(e)) if format!("{:#}", e).contains("Injected error")
+                channel.high_priority.try_recv(),
+                Ok(MaterializerCommand::DeclareExisting(..))
             );
-
-            // Unset fail, but we haven't processed materialization_finished yet so this does nothing.
-            dm.io.set_fail(false);
-
-            // Rejoining the existing future fails.
-            let res = dm
-                .materialize_artifact(&path, EventDispatcher::null())
-                .buck_error_context("Expected a future")?
-                .await;
-
             assert_matches!(
-                res,
-                Err(SharedMaterializingError::Error(e)) if format!("{:#}", e).contains("Injected error")
+                channel.low_priority.recv().await,
+                Some(LowPriorityMaterializerCommand::AccessTimesFlushed { .. })
             );
+            // Follow-up commands aren't new activity, so they don't interrupt a running clean.
+            assert!(clean_observer.is_alive_sync());
+            Ok(())
+        })
+        .await
+    }
 
-            // Now process cleanup_finished_vacant and materialization_finished.
-            let mut processed = 0;
-
-            while let Ok(cmd) = channel.low_priority.try_recv() {
-                eprintln!("got cmd = {:?}", cmd);
-                dm.testing_process_one_low_priority_command(cmd);
-                processed += 1;
+    #[tokio::test]
+    async fn test_current_stats() -> buck2_error::Result<()> {
+        ignore_stack_overflow_checks_for_future(async {
+            let foo_path = make_path("foo");
+            let bar_path = make_path("bar");
+            let io = Arc::new(StubIoHandler::new(temp_root()));
+            let (dm, mut handle, _) = make_materializer(io, None).await;
+
+            assert_eq!(dm.current_stats(), DeferredMaterializerSnapshot::default());
+
+            materialize_write(&foo_path, b"foo", &mut handle, &dm).await?;
+            materialize_write(&bar_path, b"bar", &mut handle, &dm).await?;
+            // Declaring the same contents again reuses the materialized artifact.
+            dm.declare_write(Box::new(|| {
+                Ok(vec![WriteRequest {
+                    path: foo_path.clone(),
+                    content: b"foo".to_vec(),
+                    is_executable: false,
+                }])
+            }))
+            .await?;
+            // Commands are processed in order, so once this returns the declare was processed.
+            dm.artifact_state(foo_path.clone()).await?;
+            // The command is acknowledged just after its response is sent.
+            while dm.current_stats().queue_size != 0 {
+                tokio::task::yield_now().await;
             }
 
-            assert_eq!(processed, 2);
-
-            // Materializing works now:
-            let res = dm
-                .materialize_artifact(&path, EventDispatcher::null())
-                .buck_error_context("Expected a future")?
-                .await;
+            let stats = dm.current_stats();
+            assert_eq!(stats.declares, 3);
+            assert_eq!(stats.declares_reused, 1);
+            assert_eq!(stats.queue_size, 0);
+            assert_eq!(
+                stats.materializations_by_method,
+                HashMap::from([("write".to_owned(), 2)])
+            );
 
-            assert_matches!(res, Ok(()));
+            let mut snapshot = buck2_data::Snapshot::default();
+            dm.add_snapshot_stats(&mut snapshot);
+            assert_eq!(snapshot.deferred_materializer_declares, 3);
+            assert_eq!(snapshot.deferred_materializer_declares_reused, 1);
+            assert_eq!(snapshot.deferred_materializer_queue_size, 0);
+            assert_eq!(
+                snapshot.deferred_materializer_materializations_by_method,
+                HashMap::from([("write".to_owned(), 2)])
+            );
+            assert_eq!(
+                snapshot
+                    .deferred_materializer_materialization_duration_us_by_method
+                    .keys()
+                    .collect::<Vec<_>>(),
+                vec!["write"]
+            );
 
             Ok(())
-        }).await
+        })
+        .await
     }
 
     #[tokio::test]
@@ -1096,7 +2793,15 @@ mod state_machine {
             let (dm, _, _) = make_materializer(io, None).await;
 
             let res = dm
-                .clean_stale_artifacts(DateTime::<Utc>::MAX_UTC, false, false)
+                .clean_stale_artifacts(
+                    DateTime::<Utc>::MAX_UTC,
+                    false,
+                    false,
+                    Vec::new(),
+                    None,
+                    None,
+                    Vec::new(),
+                )
                 .await?;
 
             let &buck2_data::CleanStaleStats {
@@ -1123,6 +2828,538 @@ mod state_machine {
         .await
     }
 
+    #[tokio::test]
+    async fn test_clean_stale_future_access_times() -> buck2_error::Result<()> {
+        static OBSERVED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+        static ONCE: Once = Once::new();
+        ONCE.call_once(|| {
+            buck2_core::error::initialize_soft_error_observer(Box::new(|category| {
+                OBSERVED.lock().push(category.to_owned())
+            }));
+        });
+
+        ignore_stack_overflow_checks_for_future(async {
+            let path = make_path("buck-out/v2/gen/foo/bar");
+            let io = Arc::new(StubIoHandler::new(temp_root()));
+            let (dm, mut handle, _) = make_materializer(io.dupe(), None).await;
+            materialize_write(&path, b"contents", &mut handle, &dm).await?;
+            // Drop dm and flush sqlite connection.
+            dm.abort();
+
+            // Date the stored access time a year ahead, as if it was recorded while the clock was
+            // wrong.
+            let future = Utc::now() + Duration::days(365);
+            {
+                let (mut db, state) = make_db(io.fs());
+                for (path, (metadata, _)) in state.unwrap() {
+                    db.materializer_state_table()
+                        .insert(&path, &metadata, future)?;
+                }
+                db.write_max_access_time(future)?;
+            }
+
+            let (mut dm, _, mut channel, _) = make_processor_for_io(io.dupe());
+            assert_eq!(dm.clock_skew.suspected_jump(), Some(ClockJump::Backwards));
+            // The access time was clamped to now, in the tree and in sqlite.
+            let is_clamped = |time: DateTime<Utc>| time < future - Duration::days(1);
+            assert_matches!(
+                &dm.tree.prefix_get(&mut path.iter()).unwrap().stage,
+                ArtifactMaterializationStage::Materialized { last_access_time, .. } => {
+                    assert!(is_clamped(*last_access_time));
+                }
+            );
+            let stored = dm
+                .sqlite_db
+                .as_mut()
+                .unwrap()
+                .materializer_state_table()
+                .read_all(dm.io.digest_config())?;
+            assert!(stored.iter().all(|(_, (_, time))| is_clamped(*time)));
+
+            // Cleaning would delete every tracked artifact, which is refused while the clock is
+            // suspect.
+            let fut = CleanStaleArtifactsCommand {
+                keep_since_time: DateTime::<Utc>::MAX_UTC,
+                dry_run: false,
+                tracked_only: false,
+                cells: Vec::new(),
+                max_bytes_per_run: None,
+                max_files_per_run: None,
+                keep_paths: Vec::new(),
+                dispatcher: EventDispatcher::null(),
+            }
+            .create_clean_fut(&mut dm, None);
+            let res = process_low_priority_until(&mut dm, &mut channel, fut).await;
+            let err = res.err().unwrap();
+            assert!(
+                format!("{:#}", err).contains("Refusing to clean 1 of 1 tracked artifacts"),
+                "{:#}",
+                err
+            );
+            // The refusal is also reported as a soft error.
+            assert!(
+                OBSERVED
+                    .lock()
+                    .iter()
+                    .any(|category| category == "clean_stale_clock_skew")
+            );
+            assert!(fs_util::try_exists(io.fs().resolve(&path))?);
+            assert!(dm.testing_has_artifact(path.clone()));
+            Ok(())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_clean_stale_cells() -> buck2_error::Result<()> {
+        ignore_stack_overflow_checks_for_future(async {
+            let foo_path = make_path("buck-out/v2/gen/foo/0123456789abcdef/out");
+            let bar_path = make_path("buck-out/v2/gen/bar/0123456789abcdef/out");
+            let project_root = temp_root();
+            let io = Arc::new(StubIoHandler::new(project_root.clone()));
+            let (dm, mut handle, _) = make_materializer(io.dupe(), None).await;
+            materialize_write(&foo_path, b"foo", &mut handle, &dm).await?;
+            materialize_write(&bar_path, b"bar", &mut handle, &dm).await?;
+            // Drop dm and flush sqlite connection.
+            dm.abort();
+            // Create new materializer from db state so that artifacts are not active
+            let (dm, _, _) = make_materializer(io.dupe(), None).await;
+
+            let res = dm
+                .clean_stale_artifacts(
+                    DateTime::<Utc>::MAX_UTC,
+                    false,
+                    false,
+                    vec!["foo".to_owned()],
+                    None,
+                    None,
+                    Vec::new(),
+                )
+                .await?;
+
+            let &buck2_data::CleanStaleStats {
+                stale_artifact_count,
+                cleaned_artifact_count,
+                untracked_artifact_count,
+                ..
+            } = res
+                .stats
+                .as_ref()
+                .unwrap_or_else(|| panic!("{}", res.message.unwrap()));
+            assert_eq!(
+                (
+                    stale_artifact_count,
+                    cleaned_artifact_count,
+                    untracked_artifact_count
+                ),
+                (1, 1, 0)
+            );
+
+            assert!(!fs_util::try_exists(io.fs().resolve(&foo_path))?);
+            assert!(fs_util::try_exists(io.fs().resolve(&bar_path))?);
+            assert!(dm.has_artifact_at(bar_path.clone()).await?);
+            assert!(!dm.has_artifact_at(foo_path.clone()).await?);
+            Ok(())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_clean_stale_cell_scan() -> buck2_error::Result<()> {
+        ignore_stack_overflow_checks_for_future(async {
+            let io = Arc::new(StubIoHandler::new(temp_root()));
+            let (mut dm, _, mut channel, _) = make_processor_for_io(io.dupe());
+            let digest_config = dm.io.digest_config();
+            let now = Utc::now();
+
+            let write = |path: &ProjectRelativePath, content: &[u8]| -> buck2_error::Result<()> {
+                let path = io.fs().resolve(path);
+                fs_util::create_dir_all(path.parent().unwrap())?;
+                fs_util::write(path, content)?;
+                Ok(())
+            };
+            let mut materialized = |path: &str, content: &[u8]| -> buck2_error::Result<_> {
+                let path = make_path(path);
+                let value = ArtifactValue::file(FileMetadata {
+                    digest: TrackedFileDigest::from_content(
+                        content,
+                        digest_config.cas_digest_config(),
+                    ),
+                    is_executable: false,
+                });
+                dm.tree.insert(
+                    path.iter().map(|f| f.to_owned()),
+                    Box::new(ArtifactMaterializationData {
+                        deps: None,
+                        stage: ArtifactMaterializationStage::Materialized {
+                            metadata: ArtifactMetadata::new(value.entry()),
+                            last_access_time: now,
+                            active: false,
+                        },
+                        processing: Processing::Done(Version(0)),
+                        declared_by: None,
+                    }),
+                );
+                write(&path, content)?;
+                Ok(path)
+            };
+
+            materialized("buck-out/v2/gen/foo/0123456789abcdef/out", b"foo")?;
+            let bar_path = materialized("buck-out/v2/gen/bar/0123456789abcdef/out", b"bar bar")?;
+            // Outside `gen`, so neither walked nor counted.
+            materialized("buck-out/v2/gen-anon/bar/0123456789abcdef/out", b"anon")?;
+            materialized("buck-out/v2/offline-cache/foo/out", b"cached")?;
+            // Scratch state the materializer doesn't track.
+            let foo_tmp_path = make_path("buck-out/v2/tmp/foo/0123456789abcdef/scratch");
+            write(&foo_tmp_path, b"scratch")?;
+            let bar_tmp_path = make_path("buck-out/v2/tmp/bar/0123456789abcdef/scratch");
+            write(&bar_tmp_path, b"scratch")?;
+            let test_discovery_path = make_path("buck-out/v2/test_discovery/foo/cache");
+            write(&test_discovery_path, b"cache")?;
+
+            let clean = |dm: &mut DeferredMaterializerCommandProcessor<StubIoHandler>,
+                         cells: &[&str]| {
+                CleanStaleArtifactsCommand {
+                    keep_since_time: now - Duration::hours(1),
+                    dry_run: false,
+                    tracked_only: false,
+                    cells: cells.iter().map(|c| (*c).to_owned()).collect(),
+                    max_bytes_per_run: None,
+                    max_files_per_run: None,
+                    keep_paths: Vec::new(),
+                    dispatcher: EventDispatcher::null(),
+                }
+                .create_clean_fut(dm, None)
+            };
+            let exists = |path: &ProjectRelativePath| fs_util::try_exists(io.fs().resolve(path));
+
+            // Only `gen` is walked, so untracked scratch state elsewhere survives the clean.
+            let fut = clean(&mut dm, &[]);
+            let res: buck2_cli_proto::CleanStaleResponse =
+                process_low_priority_until(&mut dm, &mut channel, fut)
+                    .await?
+                    .into();
+            let stats = res.stats.unwrap();
+            assert_eq!(
+                (
+                    stats.retained_artifact_count,
+                    stats.untracked_artifact_count,
+                    stats.cleaned_artifact_count
+                ),
+                (2, 0, 0)
+            );
+            assert!(exists(&foo_tmp_path)?);
+            assert!(exists(&bar_tmp_path)?);
+            assert!(exists(&test_discovery_path)?);
+            assert_eq!(
+                *dm.testing_stats().materialized_bytes_by_cell.lock(),
+                HashMap::from([("foo".to_owned(), 3), ("bar".to_owned(), 7)])
+            );
+
+            // A scan of some cells only looks at, and only updates the totals of, those cells.
+            let foo_untracked_path = make_path("buck-out/v2/gen/foo/0123456789abcdef/stray");
+            write(&foo_untracked_path, b"stray")?;
+            dm.tree
+                .invalidate_paths_and_collect_futures(vec![bar_path.clone()], None)?;
+            let fut = clean(&mut dm, &["bar"]);
+            let res: buck2_cli_proto::CleanStaleResponse =
+                process_low_priority_until(&mut dm, &mut channel, fut)
+                    .await?
+                    .into();
+            let stats = res.stats.unwrap();
+            assert_eq!(
+                (stats.untracked_artifact_count, stats.cleaned_artifact_count),
+                (1, 1)
+            );
+            assert!(!exists(&bar_path)?);
+            assert!(exists(&foo_untracked_path)?);
+            assert!(exists(&bar_tmp_path)?);
+            assert_eq!(
+                *dm.testing_stats().materialized_bytes_by_cell.lock(),
+                HashMap::from([("foo".to_owned(), 3)])
+            );
+            Ok(())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_clean_stale_limits() -> buck2_error::Result<()> {
+        ignore_stack_overflow_checks_for_future(async {
+            let io = Arc::new(StubIoHandler::new(temp_root()));
+            let (mut dm, _, mut channel, _) = make_processor_for_io(io.dupe());
+            let digest_config = dm.io.digest_config();
+            let now = Utc::now();
+
+            let write = |path: &ProjectRelativePath| -> buck2_error::Result<()> {
+                let path = io.fs().resolve(path);
+                fs_util::create_dir_all(path.parent().unwrap())?;
+                fs_util::write(path, b"abc")?;
+                Ok(())
+            };
+
+            let untracked_path = make_path("buck-out/v2/gen/foo/untracked");
+            write(&untracked_path)?;
+
+            // Declared newest first, to check that cleaning goes by access time.
+            let stale_paths = [("c", 1), ("b", 2), ("a", 3)].map(|(name, age_hours)| {
+                let path = make_path(&format!("buck-out/v2/gen/foo/{}", name));
+                let value = ArtifactValue::file(FileMetadata {
+                    digest: TrackedFileDigest::from_content(
+                        b"abc",
+                        digest_config.cas_digest_config(),
+                    ),
+                    is_executable: false,
+                });
+                dm.tree.insert(
+                    path.iter().map(|f| f.to_owned()),
+                    Box::new(ArtifactMaterializationData {
+                        deps: None,
+                        stage: ArtifactMaterializationStage::Materialized {
+                            metadata: ArtifactMetadata::new(value.entry()),
+                            last_access_time: now - Duration::hours(age_hours),
+                            active: false,
+                        },
+                        processing: Processing::Done(Version(0)),
+                        declared_by: None,
+                    }),
+                );
+                path
+            });
+            for path in &stale_paths {
+                write(path)?;
+            }
+            let [c_path, b_path, a_path] = stale_paths;
+
+            let clean = |dm: &mut DeferredMaterializerCommandProcessor<StubIoHandler>,
+                         max_bytes_per_run,
+                         max_files_per_run| {
+                CleanStaleArtifactsCommand {
+                    keep_since_time: now,
+                    dry_run: false,
+                    tracked_only: false,
+                    cells: Vec::new(),
+                    max_bytes_per_run,
+                    max_files_per_run,
+                    keep_paths: Vec::new(),
+                    dispatcher: EventDispatcher::null(),
+                }
+                .create_clean_fut(dm, None)
+            };
+            let exists = |path: &ProjectRelativePath| fs_util::try_exists(io.fs().resolve(path));
+
+            // Untracked files go first, then the least recently accessed artifacts.
+            let fut = clean(&mut dm, None, Some(2));
+            let res: buck2_cli_proto::CleanStaleResponse =
+                process_low_priority_until(&mut dm, &mut channel, fut)
+                    .await?
+                    .into();
+            let stats = res.stats.unwrap();
+            assert_eq!((stats.cleaned_artifact_count, stats.truncated), (2, true));
+            assert!(!exists(&untracked_path)?);
+            assert!(!exists(&a_path)?);
+            assert!(exists(&b_path)?);
+            assert!(exists(&c_path)?);
+            assert!(dm.testing_has_artifact(b_path.clone()));
+            assert!(!dm.testing_has_artifact(a_path.clone()));
+
+            // The next run picks up where the last one stopped. The artifact that reaches the
+            // byte limit is still cleaned.
+            let fut = clean(&mut dm, Some(4), None);
+            let res: buck2_cli_proto::CleanStaleResponse =
+                process_low_priority_until(&mut dm, &mut channel, fut)
+                    .await?
+                    .into();
+            let stats = res.stats.unwrap();
+            assert_eq!(
+                (
+                    stats.stale_artifact_count,
+                    stats.cleaned_artifact_count,
+                    stats.cleaned_bytes,
+                    stats.truncated
+                ),
+                (2, 2, 6, false)
+            );
+            assert!(!exists(&b_path)?);
+            assert!(!exists(&c_path)?);
+            Ok(())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_clean_stale_tracked_only_limits() -> buck2_error::Result<()> {
+        ignore_stack_overflow_checks_for_future(async {
+            let io = Arc::new(StubIoHandler::new(temp_root()));
+            let (mut dm, _, mut channel, _) = make_processor_for_io(io.dupe());
+            let digest_config = dm.io.digest_config();
+            let now = Utc::now();
+
+            let stale_paths = [("c", 1), ("b", 2), ("a", 3)].map(|(name, age_hours)| {
+                let path = make_path(&format!("buck-out/v2/gen/foo/{}", name));
+                let value = ArtifactValue::file(FileMetadata {
+                    digest: TrackedFileDigest::from_content(
+                        b"abc",
+                        digest_config.cas_digest_config(),
+                    ),
+                    is_executable: false,
+                });
+                dm.tree.insert(
+                    path.iter().map(|f| f.to_owned()),
+                    Box::new(ArtifactMaterializationData {
+                        deps: None,
+                        stage: ArtifactMaterializationStage::Materialized {
+                            metadata: ArtifactMetadata::new(value.entry()),
+                            last_access_time: now - Duration::hours(age_hours),
+                            active: false,
+                        },
+                        processing: Processing::Done(Version(0)),
+                        declared_by: None,
+                    }),
+                );
+                path
+            });
+            for path in &stale_paths {
+                let path = io.fs().resolve(path);
+                fs_util::create_dir_all(path.parent().unwrap())?;
+                fs_util::write(path, b"abc")?;
+            }
+            let [c_path, b_path, a_path] = stale_paths;
+
+            // Sizes come from the materializer's metadata, so the byte limit applies without
+            // looking at the disk.
+            let fut = CleanStaleArtifactsCommand {
+                keep_since_time: now,
+                dry_run: false,
+                tracked_only: true,
+                cells: Vec::new(),
+                max_bytes_per_run: Some(4),
+                max_files_per_run: None,
+                keep_paths: Vec::new(),
+                dispatcher: EventDispatcher::null(),
+            }
+            .create_clean_fut(&mut dm, None);
+            let res: buck2_cli_proto::CleanStaleResponse =
+                process_low_priority_until(&mut dm, &mut channel, fut)
+                    .await?
+                    .into();
+            let stats = res.stats.unwrap();
+            assert_eq!(
+                (
+                    stats.stale_artifact_count,
+                    stats.stale_bytes,
+                    stats.cleaned_artifact_count,
+                    stats.cleaned_bytes,
+                    stats.truncated
+                ),
+                (3, 9, 2, 6, true)
+            );
+            assert!(!dm.testing_has_artifact(a_path.clone()));
+            assert!(!dm.testing_has_artifact(b_path.clone()));
+            assert!(dm.testing_has_artifact(c_path.clone()));
+            Ok(())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_clean_stale_keep_paths() -> buck2_error::Result<()> {
+        ignore_stack_overflow_checks_for_future(async {
+            let io = Arc::new(StubIoHandler::new(temp_root()));
+            let (mut dm, _, mut channel, _) = make_processor_for_io(io.dupe());
+            let digest_config = dm.io.digest_config();
+            let now = Utc::now();
+
+            let [kept_path, sibling_path] = [
+                "buck-out/v2/gen/foo/models/big",
+                "buck-out/v2/gen/foo/small",
+            ]
+            .map(|path| {
+                let path = make_path(path);
+                let value = ArtifactValue::file(FileMetadata {
+                    digest: TrackedFileDigest::from_content(
+                        b"abc",
+                        digest_config.cas_digest_config(),
+                    ),
+                    is_executable: false,
+                });
+                dm.tree.insert(
+                    path.iter().map(|f| f.to_owned()),
+                    Box::new(ArtifactMaterializationData {
+                        deps: None,
+                        stage: ArtifactMaterializationStage::Materialized {
+                            metadata: ArtifactMetadata::new(value.entry()),
+                            last_access_time: now - Duration::days(30),
+                            active: false,
+                        },
+                        processing: Processing::Done(Version(0)),
+                        declared_by: None,
+                    }),
+                );
+                let abs_path = io.fs().resolve(&path);
+                fs_util::create_dir_all(abs_path.parent().unwrap()).unwrap();
+                fs_util::write(abs_path, b"abc").unwrap();
+                path
+            });
+            // Untracked, but also under the keep-list.
+            let untracked_kept_path = make_path("buck-out/v2/gen/foo/models/untracked");
+            fs_util::write(io.fs().resolve(&untracked_kept_path), b"abcd").unwrap();
+
+            let clean = |dm: &mut DeferredMaterializerCommandProcessor<StubIoHandler>, dry_run| {
+                CleanStaleArtifactsCommand {
+                    keep_since_time: now,
+                    dry_run,
+                    tracked_only: false,
+                    cells: Vec::new(),
+                    max_bytes_per_run: None,
+                    max_files_per_run: None,
+                    // Prefix of the kept artifact.
+                    keep_paths: vec![make_path("buck-out/v2/gen/foo/models")],
+                    dispatcher: EventDispatcher::null(),
+                }
+                .create_clean_fut(dm, None)
+            };
+            let exists = |path: &ProjectRelativePath| fs_util::try_exists(io.fs().resolve(path));
+
+            // Dry runs report the kept paths.
+            let fut = clean(&mut dm, true);
+            let res: buck2_cli_proto::CleanStaleResponse =
+                process_low_priority_until(&mut dm, &mut channel, fut)
+                    .await?
+                    .into();
+            let mut kept_paths = res.kept_paths;
+            kept_paths.sort();
+            assert_eq!(
+                kept_paths,
+                vec![kept_path.to_string(), untracked_kept_path.to_string()]
+            );
+            let stats = res.stats.unwrap();
+            assert_eq!(
+                (
+                    stats.stale_artifact_count,
+                    stats.untracked_artifact_count,
+                    stats.kept_artifact_count,
+                    stats.kept_bytes
+                ),
+                (1, 0, 2, 7)
+            );
+
+            let fut = clean(&mut dm, false);
+            let res: buck2_cli_proto::CleanStaleResponse =
+                process_low_priority_until(&mut dm, &mut channel, fut)
+                    .await?
+                    .into();
+            assert_eq!(res.stats.unwrap().cleaned_artifact_count, 1);
+            assert!(exists(&kept_path)?);
+            assert!(dm.testing_has_artifact(kept_path.clone()));
+            assert!(exists(&untracked_kept_path)?);
+            assert!(!exists(&sibling_path)?);
+            assert!(!dm.testing_has_artifact(sibling_path.clone()));
+            Ok(())
+        })
+        .await
+    }
+
     #[tokio::test]
     async fn test_clean_stale_interrupt() -> buck2_error::Result<()> {
         ignore_stack_overflow_checks_for_future(async {
@@ -1143,7 +3380,15 @@ mod state_machine {
             // Interrupt while scanning buck-out
             let dm = Arc::new(dm);
             let dm_dup = dm.dupe();
-            let fut = dm_dup.clean_stale_artifacts(DateTime::<Utc>::MAX_UTC, false, false);
+            let fut = dm_dup.clean_stale_artifacts(
+                DateTime::<Utc>::MAX_UTC,
+                false,
+                false,
+                Vec::new(),
+                None,
+                None,
+                Vec::new(),
+            );
             thread::spawn(move || {
                 // Wait until a read_dir request is about to execute
                 read_dir_barriers.0.wait();
@@ -1180,7 +3425,15 @@ mod state_machine {
             // Interrupt while deleting files
             let dm = Arc::new(dm);
             let dm_dup = dm.dupe();
-            let fut = dm_dup.clean_stale_artifacts(DateTime::<Utc>::MAX_UTC, false, false);
+            let fut = dm_dup.clean_stale_artifacts(
+                DateTime::<Utc>::MAX_UTC,
+                false,
+                false,
+                Vec::new(),
+                None,
+                None,
+                Vec::new(),
+            );
             thread::spawn(move || {
                 // Wait until a single clean request is about to execute
                 clean_barriers.0.wait();
@@ -1225,6 +3478,9 @@ mod state_machine {
                 artifact_ttl: std::time::Duration::from_secs(0),
                 start_offset: std::time::Duration::from_secs(0),
                 dry_run: true,
+                max_bytes_per_run: None,
+                max_files_per_run: None,
+                keep_paths: Vec::new(),
             };
             let io = Arc::new(StubIoHandler::new(project_root.dupe()));
             let (dm, mut handle, mut daemon_dispatcher_events) =
@@ -1302,4 +3558,218 @@ mod state_machine {
         })
         .await
     }
+
+    #[tokio::test]
+    async fn test_has_artifacts_at() -> buck2_error::Result<()> {
+        ignore_stack_overflow_checks_for_future(async {
+            let (mut dm, _channel) = make_processor(Default::default());
+            let digest_config = dm.io.digest_config();
+
+            let declared = make_path("foo/declared");
+            let existing = make_path("foo/existing");
+            let value = ArtifactValue::file(digest_config.empty_file());
+            dm.testing_declare(&declared, value.dupe());
+            dm.testing_declare_existing(&existing, value.dupe());
+
+            let mut has_artifacts = |paths: Vec<&ProjectRelativePathBuf>| {
+                let (sender, mut recv) = oneshot::channel();
+                dm.testing_process_one_command(MaterializerCommand::HasArtifacts(
+                    paths.into_iter().cloned().collect(),
+                    sender,
+                ));
+                recv.try_recv().unwrap()
+            };
+
+            let unknown = make_path("bar");
+            let inside = existing.join(ForwardRelativePath::new("inner").unwrap());
+            let parent = make_path("foo");
+            assert_eq!(
+                has_artifacts(vec![
+                    &declared, &unknown, &existing, &inside, &parent, &existing
+                ]),
+                vec![true, false, true, false, false, true]
+            );
+            assert_eq!(has_artifacts(vec![]), Vec::<bool>::new());
+
+            Ok(())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_generate_manifest() -> buck2_error::Result<()> {
+        let (mut dm, _channel) = make_processor(Default::default());
+        let digest_config = dm.io.digest_config();
+
+        let file = make_path("out/file");
+        let dir = make_path("out/dir");
+        let symlink = make_path("out/symlink");
+        let declared = make_path("out/declared");
+
+        let file_metadata = digest_config.empty_file();
+        dm.testing_declare_existing(&file, ArtifactValue::file(file_metadata.dupe()));
+        dm.testing_declare_existing(&dir, ArtifactValue::dir(digest_config.empty_directory()));
+        dm.testing_declare_existing(
+            &symlink,
+            ArtifactValue::new(
+                ActionDirectoryEntry::Leaf(ActionDirectoryMember::Symlink(Arc::new(Symlink::new(
+                    RelativePathBuf::from("file"),
+                )))),
+                None,
+            ),
+        );
+        dm.testing_declare(&declared, ArtifactValue::file(file_metadata.dupe()));
+
+        let manifest =
+            dm.tree
+                .generate_manifest(vec![symlink.clone(), file.clone(), dir.clone()])?;
+        let entries = manifest
+            .entries()
+            .map(|(path, entry)| (path.clone(), entry.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            entries,
+            vec![
+                (
+                    dir,
+                    ManifestEntry::Directory {
+                        fingerprint: digest_config
+                            .empty_directory()
+                            .fingerprint()
+                            .raw_digest()
+                            .to_string(),
+                        size: 0,
+                    },
+                ),
+                (
+                    file.clone(),
+                    ManifestEntry::File {
+                        digest: file_metadata.digest.raw_digest().to_string(),
+                        size: 0,
+                        is_executable: false,
+                    },
+                ),
+                (
+                    symlink,
+                    ManifestEntry::Symlink {
+                        target: "file".to_owned(),
+                    },
+                ),
+            ]
+        );
+
+        // Paths that are declared but not materialized, that are within an artifact, or that
+        // buck2 doesn't know about at all are all rejected.
+        let err = dm
+            .tree
+            .generate_manifest(vec![
+                file.clone(),
+                declared.clone(),
+                file.join(ForwardRelativePath::new("sub")?),
+                make_path("unknown"),
+            ])
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("out/declared"), "{}", err);
+        assert!(err.contains("out/file/sub"), "{}", err);
+        assert!(err.contains("unknown"), "{}", err);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_export_artifacts() -> buck2_error::Result<()> {
+        let fs = temp_root();
+        let io = Arc::new(StubIoHandler::new(fs.dupe()));
+        let (dm, _handle, _) = make_materializer(io.dupe(), None).await;
+        let digest_config = io.digest_config();
+
+        // A file artifact, materialized on export.
+        let file = make_path("buck-out/v2/gen/file.sh");
+        dm.declare_write(Box::new(|| {
+            Ok(vec![WriteRequest {
+                path: file.clone(),
+                content: b"#!/bin/sh".to_vec(),
+                is_executable: true,
+            }])
+        }))
+        .await?;
+
+        // A directory artifact, already on disk.
+        let dir = make_path("buck-out/v2/gen/dir");
+        fs_util::create_dir_all(fs.resolve(&dir).join(ForwardRelativePath::new("sub")?))?;
+        fs_util::write(fs.resolve(&dir).join(ForwardRelativePath::new("a")?), "a")?;
+        fs_util::write(
+            fs.resolve(&dir).join(ForwardRelativePath::new("sub/b")?),
+            "b",
+        )?;
+        fs_util::symlink(
+            "a",
+            fs.resolve(&dir).join(ForwardRelativePath::new("link")?),
+        )?;
+        dm.declare_existing(vec![(
+            dir.clone(),
+            ArtifactValue::dir(digest_config.empty_directory()),
+        )])
+        .await?;
+
+        let export = ProjectRootTemp::new()?;
+        let export_root = export.path().root().to_buf().into_abs_path_buf();
+        // A file where the destination's parent directory should be.
+        fs_util::write(export_root.join("blocker"), "")?;
+
+        let results = dm
+            .export_artifacts(vec![
+                (file.clone(), export_root.join("out/file.sh")),
+                (dir.clone(), export_root.join("out/dir")),
+                (file.clone(), export_root.join("blocker/file.sh")),
+                (
+                    file.clone(),
+                    fs.resolve(&make_path("buck-out/v2/export"))
+                        .into_abs_path_buf(),
+                ),
+            ])
+            .await?;
+        assert_eq!(results.len(), 4);
+        assert_matches!(results[0], Ok(()));
+        assert_matches!(results[1], Ok(()));
+        let err = format!("{:#}", results[2].as_ref().unwrap_err());
+        assert!(err.contains("blocker/file.sh"), "{}", err);
+        let err = format!("{:#}", results[3].as_ref().unwrap_err());
+        assert!(err.contains("overlaps with buck-out"), "{}", err);
+        // Checked before materializing anything.
+        assert!(!fs_util::try_exists(
+            fs.resolve(&make_path("buck-out/v2/export"))
+        )?);
+
+        assert_eq!(
+            fs_util::read_to_string(export_root.join("out/file.sh"))?,
+            "#!/bin/sh"
+        );
+        assert_eq!(fs_util::read_to_string(export_root.join("out/dir/a"))?, "a");
+        assert_eq!(
+            fs_util::read_to_string(export_root.join("out/dir/sub/b"))?,
+            "b"
+        );
+        assert_eq!(
+            fs_util::read_link(export_root.join("out/dir/link"))?,
+            Path::new("a")
+        );
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            let mode = |path: &str| {
+                fs_util::metadata(export_root.join(path))
+                    .unwrap()
+                    .permissions()
+                    .mode()
+            };
+            assert_ne!(mode("out/file.sh") & 0o111, 0);
+            assert_eq!(mode("out/dir/a") & 0o111, 0);
+        }
+
+        dm.abort();
+        Ok(())
+    }
 }
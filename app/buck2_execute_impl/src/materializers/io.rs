@@ -12,10 +12,13 @@ use std::collections::HashMap;
 use buck2_core::fs::fs_util;
 use buck2_core::fs::paths::abs_norm_path::AbsNormPath;
 use buck2_core::fs::paths::abs_norm_path::AbsNormPathBuf;
+use buck2_core::fs::paths::abs_path::AbsPath;
+use buck2_core::fs::paths::abs_path::AbsPathBuf;
 use buck2_core::fs::project::ProjectRoot;
 use buck2_core::fs::project_rel_path::ProjectRelativePathBuf;
 use buck2_directory::directory::directory::Directory;
 use buck2_directory::directory::entry::DirectoryEntry;
+use buck2_error::BuckErrorContext;
 use buck2_execute::directory::ActionDirectory;
 use buck2_execute::directory::ActionDirectoryEntry;
 use buck2_execute::directory::ActionDirectoryMember;
@@ -36,6 +39,51 @@ impl IoRequest for MaterializeTreeStructure {
     }
 }
 
+/// Copies a materialized artifact out of buck-out. See `Materializer::export_artifacts`.
+pub struct ExportArtifact {
+    pub path: ProjectRelativePathBuf,
+    pub dest: AbsPathBuf,
+}
+
+impl IoRequest for ExportArtifact {
+    fn execute(self: Box<Self>, project_fs: &ProjectRoot) -> buck2_error::Result<()> {
+        export_path(&project_fs.root().join(&self.path), &self.dest).with_buck_error_context(|| {
+            format!("Error exporting `{}` to `{}`", self.path, self.dest)
+        })
+    }
+}
+
+/// Replaces whatever is at `dest` with a copy of `src`, creating the parent directories of `dest`
+/// as needed.
+fn export_path(src: &AbsNormPath, dest: &AbsPath) -> buck2_error::Result<()> {
+    if let Some(parent) = dest.parent() {
+        fs_util::create_dir_all(parent)?;
+    }
+    fs_util::remove_all(dest)?;
+    copy_recursively(src, dest)
+}
+
+/// Copies files, directories and symlinks, preserving permissions. Symlinks are copied as they
+/// are, not followed. Files are copied with `std::fs::copy`, which clones them on filesystems that
+/// support it.
+fn copy_recursively(src: &AbsNormPath, dest: &AbsPath) -> buck2_error::Result<()> {
+    let metadata = fs_util::symlink_metadata(src)?;
+    if metadata.is_symlink() {
+        fs_util::symlink(fs_util::read_link(src)?, dest)?;
+    } else if metadata.is_dir() {
+        fs_util::create_dir(dest)?;
+        for entry in fs_util::read_dir(src)? {
+            let entry = entry?;
+            copy_recursively(&entry.path(), &dest.join(entry.file_name()))?;
+        }
+        // Set last, the directory may not be writable.
+        fs_util::set_permissions(dest, metadata.permissions())?;
+    } else {
+        fs_util::copy(src, dest)?;
+    }
+    Ok(())
+}
+
 /// Materializes the entry at `dest`.
 ///
 /// - `materialize_dirs_and_syms`: if `true`, materializes directories and
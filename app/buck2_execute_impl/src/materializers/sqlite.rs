@@ -29,24 +29,31 @@ use derive_more::From;
 use dupe::Dupe;
 use parking_lot::Mutex;
 use rusqlite::Connection;
+use rusqlite::Transaction;
 
 use crate::materializers::deferred::artifact_tree::ArtifactMetadata;
 use crate::materializers::sqlite::materializer_state_table::MaterializerStateSqliteTable;
 
 pub(crate) mod materializer_state_table;
+mod schema_migrations;
 
 #[derive(Display, Allocative, Clone, From, PartialEq, Eq, Debug)]
 pub struct MaterializerStateIdentity(String);
 
 /// Hand-maintained schema version for the materializer state sqlite db.
 /// PLEASE bump this version if you are making a breaking change to the
-/// materializer state sqlite db schema! If you forget to bump this version,
+/// materializer state sqlite db schema that can't be done by a migration in
+/// `schema_migrations`! If you forget to bump this version,
 /// then you can fix forward by bumping the `buck2.sqlite_materializer_state_version`
 /// buckconfig in the project root's .buckconfig.
 pub const DB_SCHEMA_VERSION: u64 = 6;
 
 const IDENTITY_KEY: &str = "timestamp_on_initialization";
 
+const CLOCK_TABLE_NAME: &str = "clock";
+
+const MAX_ACCESS_TIME_KEY: &str = "max_access_time";
+
 pub type MaterializerState = Vec<(ProjectRelativePathBuf, (ArtifactMetadata, DateTime<Utc>))>;
 
 #[derive(buck2_error::Error, Debug, PartialEq, Eq)]
@@ -64,6 +71,18 @@ enum MaterializerStateSqliteDbError {
 
     #[error("Materializer identity was rejected: {}", .identity)]
     RejectedIdentity { identity: MaterializerStateIdentity },
+
+    #[error(
+        "Materializer state sqlite db at {} has schema version {}, but this buck2 only supports up to version {}. It was likely written by a newer buck2. Run `buck2 clean` to delete it",
+        .path,
+        .found,
+        .supported
+    )]
+    SchemaVersionTooNew {
+        found: u64,
+        supported: u64,
+        path: AbsNormPathBuf,
+    },
 }
 
 /// DB that opens the sqlite connection to the materializer state db on disk and
@@ -94,7 +113,9 @@ impl MaterializerStateSqliteDb {
     /// Given path to the sqlite DB, attempts to read `MaterializerState` from the DB. If we encounter
     /// any failure along the way, such as if the DB path does not exist, the sqlite read fails,
     /// or the DB has a different set of versions than the versions this buck2 expects, we
-    /// throw away the existing DB and initialize a new DB. Returns (1) the connected sqlite DB and
+    /// throw away the existing DB and initialize a new DB. A DB at an older schema version is
+    /// migrated instead, and a DB at a newer schema version is an error, since throwing it away
+    /// would break the buck2 that wrote it. Returns (1) the connected sqlite DB and
     /// (2) the `MaterializerState` if loading was successful or the load error.
     /// The `Result<MaterializerState>` captures any failure encountered when attempting to load
     /// from the existing DB. These failures are expected if db doesn't exist or versions don't match.
@@ -156,6 +177,17 @@ impl MaterializerStateSqliteDb {
                 })?;
             }
 
+            let schema_version = schema_migrations::read_schema_version(&tables.connection)?;
+            if schema_version > schema_migrations::LATEST_SCHEMA_VERSION {
+                return Err(MaterializerStateSqliteDbError::SchemaVersionTooNew {
+                    found: schema_version,
+                    supported: schema_migrations::LATEST_SCHEMA_VERSION,
+                    path: db_path,
+                }
+                .into());
+            }
+            schema_migrations::migrate(&tables.connection, schema_version)?;
+
             // Update "last_read_by" inside of the try block so that
             // just in case it fails, we can create a new db and start over
             tables
@@ -214,9 +246,44 @@ impl MaterializerStateSqliteDb {
     pub fn identity(&self) -> &MaterializerStateIdentity {
         &self.identity
     }
+
+    /// The latest time the materializer recorded, to detect clock jumps across daemon restarts.
+    pub(crate) fn read_max_access_time(&self) -> buck2_error::Result<Option<DateTime<Utc>>> {
+        let Some(value) = self.tables.clock_table.get(MAX_ACCESS_TIME_KEY)? else {
+            return Ok(None);
+        };
+        let time = DateTime::parse_from_rfc3339(&value)
+            .with_buck_error_context(|| {
+                format!("parsing `{}` from sqlite table {}", value, CLOCK_TABLE_NAME)
+            })?
+            .with_timezone(&Utc);
+        Ok(Some(time))
+    }
+
+    pub(crate) fn write_max_access_time(&self, time: DateTime<Utc>) -> buck2_error::Result<()> {
+        self.max_access_time_writer().write(time)
+    }
+
+    /// A handle to write the latest recorded time from another thread.
+    pub(crate) fn max_access_time_writer(&self) -> MaxAccessTimeWriter {
+        MaxAccessTimeWriter(self.tables.clock_table.clone())
+    }
+}
+
+pub(crate) struct MaxAccessTimeWriter(KeyValueSqliteTable);
+
+impl MaxAccessTimeWriter {
+    pub(crate) fn write(&self, time: DateTime<Utc>) -> buck2_error::Result<()> {
+        self.0.insert_all(HashMap::from([(
+            MAX_ACCESS_TIME_KEY.to_owned(),
+            time.to_rfc3339(),
+        )]))
+    }
 }
 
 struct MaterializerStateTables {
+    /// Connection shared by all the tables, for changes to the schema.
+    connection: Arc<Mutex<Connection>>,
     /// Table storing actual materializer state
     materializer_state_table: MaterializerStateSqliteTable,
     /// Table for holding any metadata used to check version match. When loading
@@ -229,6 +296,8 @@ struct MaterializerStateTables {
     created_by_table: KeyValueSqliteTable,
     /// Table for logging metadata associated with the buck2 that last updated the db.
     last_read_by_table: KeyValueSqliteTable,
+    /// Table for the latest time recorded by the materializer.
+    clock_table: KeyValueSqliteTable,
 }
 
 impl MaterializerStateTables {
@@ -264,13 +333,17 @@ impl MaterializerStateTables {
         let materializer_state_table = MaterializerStateSqliteTable::new(connection.dupe());
         let versions_table = KeyValueSqliteTable::new("versions".to_owned(), connection.dupe());
         let created_by_table = KeyValueSqliteTable::new("created_by".to_owned(), connection.dupe());
-        let last_read_by_table = KeyValueSqliteTable::new("last_read_by".to_owned(), connection);
+        let last_read_by_table =
+            KeyValueSqliteTable::new("last_read_by".to_owned(), connection.dupe());
+        let clock_table = KeyValueSqliteTable::new(CLOCK_TABLE_NAME.to_owned(), connection.dupe());
 
         Ok(Self {
+            connection,
             materializer_state_table,
             versions_table,
             created_by_table,
             last_read_by_table,
+            clock_table,
         })
     }
 
@@ -279,6 +352,24 @@ impl MaterializerStateTables {
         self.versions_table.create_table()?;
         self.created_by_table.create_table()?;
         self.last_read_by_table.create_table()?;
+        self.clock_table.create_table()?;
+        schema_migrations::create_table(&self.connection)?;
+        Ok(())
+    }
+
+    /// Migration creating the `clock` table, with the same schema as `KeyValueSqliteTable`.
+    fn add_clock_table(tx: &Transaction) -> buck2_error::Result<()> {
+        tx.execute(
+            &format!(
+                "CREATE TABLE IF NOT EXISTS {} (
+                    key     TEXT PRIMARY KEY NOT NULL,
+                    value   TEXT NOT NULL
+                )",
+                CLOCK_TABLE_NAME
+            ),
+            [],
+        )
+        .with_buck_error_context(|| format!("creating sqlite table {}", CLOCK_TABLE_NAME))?;
         Ok(())
     }
 }
@@ -555,6 +646,156 @@ mod tests {
         Ok(())
     }
 
+    /// Writes a db with the schema from before `schema_version` was recorded, holding `state`.
+    fn write_v1_db(
+        fs: &ProjectRoot,
+        versions: HashMap<String, String>,
+        state: &[(&str, &str, Option<&str>)],
+    ) -> buck2_error::Result<AbsNormPathBuf> {
+        let dir = fs.resolve(ProjectRelativePath::unchecked_new(
+            "buck-out/v2/cache/materializer_state",
+        ));
+        fs_util::create_dir_all(&dir)?;
+        let connection = Arc::new(Mutex::new(Connection::open(dir.join(
+            FileName::unchecked_new(MaterializerStateSqliteDb::DB_FILENAME),
+        ))?));
+        connection.lock().execute_batch(
+            "CREATE TABLE materializer_state (
+                path                    TEXT NOT NULL PRIMARY KEY,
+                artifact_type           TEXT CHECK(artifact_type IN ('directory','file','symlink','external_symlink')) NOT NULL,
+                digest_size             INTEGER NULL DEFAULT NULL,
+                entry_hash              BLOB NULL DEFAULT NULL,
+                entry_hash_kind         INTEGER NULL DEFAULT NULL,
+                file_is_executable      INTEGER NULL DEFAULT NULL,
+                symlink_target          TEXT NULL DEFAULT NULL,
+                last_access_time        INTEGER NOT NULL,
+                directory_size          INTEGER NULL DEFAULT NULL
+            )",
+        )?;
+        let digest = TrackedFileDigest::from_content(
+            b"abc",
+            DigestConfig::testing_default().cas_digest_config(),
+        );
+        for (path, artifact_type, symlink_target) in state {
+            connection.lock().execute(
+                "INSERT INTO materializer_state (path, artifact_type, digest_size, entry_hash, entry_hash_kind, file_is_executable, symlink_target, directory_size, last_access_time) VALUES (?1, ?2, ?3, ?4, ?5, 0, ?6, 3, 0)",
+                rusqlite::params![
+                    path,
+                    artifact_type,
+                    digest.size(),
+                    digest.raw_digest().as_bytes(),
+                    digest.raw_digest().algorithm() as u8,
+                    symlink_target,
+                ],
+            )?;
+        }
+        for (name, map) in [
+            ("versions", versions),
+            (
+                "created_by",
+                HashMap::from([(IDENTITY_KEY.to_owned(), "v1".to_owned())]),
+            ),
+            ("last_read_by", HashMap::new()),
+        ] {
+            let table = KeyValueSqliteTable::new(name.to_owned(), connection.dupe());
+            table.create_table()?;
+            table.insert_all(map)?;
+        }
+        Ok(dir)
+    }
+
+    fn read_artifact_kinds(db: &MaterializerStateSqliteDb) -> Vec<(String, String)> {
+        let connection = db.tables.connection.lock();
+        let mut stmt = connection
+            .prepare("SELECT path, artifact_kind FROM materializer_state ORDER BY path")
+            .unwrap();
+        stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_migrate_from_v1() -> buck2_error::Result<()> {
+        let fs = ProjectRootTemp::new()?;
+        let versions = HashMap::from([("version".to_owned(), "0".to_owned())]);
+        write_v1_db(
+            fs.path(),
+            versions.clone(),
+            &[
+                ("dir", "directory", None),
+                ("dir2/file", "file", None),
+                ("link", "symlink", Some("dir")),
+                ("external", "external_symlink", Some("/external")),
+            ],
+        )?;
+
+        let (mut db, loaded_state) =
+            testing_materializer_state_sqlite_db(fs.path(), versions, HashMap::new(), None)?;
+        // The db was migrated rather than thrown away.
+        assert_eq!(db.identity.0, "v1");
+        assert_eq!(loaded_state?.len(), 4);
+        assert_eq!(
+            schema_migrations::read_schema_version(&db.tables.connection)?,
+            schema_migrations::LATEST_SCHEMA_VERSION
+        );
+        assert_eq!(
+            read_artifact_kinds(&db),
+            [
+                ("dir", "dir"),
+                ("dir2/file", "file"),
+                ("external", "symlink"),
+                ("link", "symlink"),
+            ]
+            .map(|(path, kind)| (path.to_owned(), kind.to_owned()))
+        );
+        assert_eq!(db.read_max_access_time()?, None);
+        let max_access_time = now_seconds();
+        db.write_max_access_time(max_access_time)?;
+        assert_eq!(db.read_max_access_time()?, Some(max_access_time));
+
+        // Migrations are idempotent.
+        schema_migrations::migrate(&db.tables.connection, 1)?;
+        db.materializer_state_table().insert(
+            ProjectRelativePath::unchecked_new("new"),
+            &ArtifactMetadata(DirectoryEntry::Leaf(new_symlink("dir").unwrap())),
+            now_seconds(),
+        )?;
+        assert_eq!(read_artifact_kinds(&db).len(), 5);
+        assert_eq!(
+            read_artifact_kinds(&db)[4],
+            ("new".to_owned(), "symlink".to_owned())
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_schema_version_too_new() -> buck2_error::Result<()> {
+        let fs = ProjectRootTemp::new()?;
+        let versions = HashMap::from([("version".to_owned(), "0".to_owned())]);
+        let dir = write_v1_db(fs.path(), versions.clone(), &[("file", "file", None)])?;
+        {
+            let db = Connection::open(dir.join(FileName::unchecked_new(
+                MaterializerStateSqliteDb::DB_FILENAME,
+            )))?;
+            db.execute_batch(&format!(
+                "CREATE TABLE schema_version (version INTEGER NOT NULL);
+                INSERT INTO schema_version (version) VALUES ({});",
+                schema_migrations::LATEST_SCHEMA_VERSION + 1
+            ))?;
+        }
+
+        let e = testing_materializer_state_sqlite_db(fs.path(), versions, HashMap::new(), None)
+            .err()
+            .unwrap();
+        assert!(e.to_string().contains("Run `buck2 clean`"), "error: {}", e);
+        // The db is left alone for the buck2 that wrote it.
+        assert!(dir.join(FileName::unchecked_new("db.sqlite")).exists());
+
+        Ok(())
+    }
+
     #[test]
     fn test_delete_many() -> buck2_error::Result<()> {
         let conn = Connection::open_in_memory()?;
@@ -32,6 +32,7 @@ use itertools::Itertools;
 use once_cell::sync::Lazy;
 use parking_lot::Mutex;
 use rusqlite::Connection;
+use rusqlite::Transaction;
 
 use crate::materializers::deferred::artifact_tree::ArtifactMetadata;
 use crate::materializers::sqlite::MaterializerState;
@@ -43,6 +44,29 @@ const ARTIFACT_TYPE_FILE: &str = "file";
 const ARTIFACT_TYPE_SYMLINK: &str = "symlink";
 const ARTIFACT_TYPE_EXTERNAL_SYMLINK: &str = "external_symlink";
 
+/// Coarser than `artifact_type`, for queries that only care about the kind of filesystem entry,
+/// like deciding which entries to clean first.
+const ARTIFACT_KIND_DIR: &str = "dir";
+const ARTIFACT_KIND_FILE: &str = "file";
+const ARTIFACT_KIND_SYMLINK: &str = "symlink";
+
+/// Added in schema version 2. Nullable, since sqlite can't add a `NOT NULL` column without a
+/// default, and tables created fresh should have the same schema as migrated ones.
+static ARTIFACT_KIND_COLUMN: Lazy<String> = Lazy::new(|| {
+    format!(
+        "artifact_kind TEXT CHECK(artifact_kind IN ('{}','{}','{}')) NULL DEFAULT NULL",
+        ARTIFACT_KIND_DIR, ARTIFACT_KIND_FILE, ARTIFACT_KIND_SYMLINK,
+    )
+});
+
+fn artifact_kind(artifact_type: &str) -> &'static str {
+    match artifact_type {
+        ARTIFACT_TYPE_DIRECTORY => ARTIFACT_KIND_DIR,
+        ARTIFACT_TYPE_FILE => ARTIFACT_KIND_FILE,
+        _ => ARTIFACT_KIND_SYMLINK,
+    }
+}
+
 #[derive(buck2_error::Error, Debug, PartialEq, Eq)]
 #[buck2(tag = Tier0)]
 enum ArtifactMetadataSqliteConversionError {
@@ -277,13 +301,21 @@ fn convert_artifact_metadata(
     Ok(ArtifactMetadata(metadata))
 }
 
+#[derive(Clone)]
 pub(crate) struct MaterializerStateSqliteTable {
     connection: Arc<Mutex<Connection>>,
+    /// Held by tests to stall `update_access_times` between chunks.
+    #[cfg(test)]
+    pub(crate) access_times_gate: Arc<Mutex<()>>,
 }
 
 impl MaterializerStateSqliteTable {
     pub fn new(connection: Arc<Mutex<Connection>>) -> Self {
-        Self { connection }
+        Self {
+            connection,
+            #[cfg(test)]
+            access_times_gate: Arc::new(Mutex::new(())),
+        }
     }
 
     pub(crate) fn create_table(&self) -> buck2_error::Result<()> {
@@ -297,9 +329,11 @@ impl MaterializerStateSqliteTable {
                 file_is_executable      INTEGER NULL DEFAULT NULL,
                 symlink_target          TEXT NULL DEFAULT NULL,
                 last_access_time        INTEGER NOT NULL,
-                directory_size          INTEGER NULL DEFAULT NULL
+                directory_size          INTEGER NULL DEFAULT NULL,
+                {artifact_kind_column}
             )",
             table_name = STATE_TABLE_NAME,
+            artifact_kind_column = &*ARTIFACT_KIND_COLUMN,
             artifact_type_directory = ARTIFACT_TYPE_DIRECTORY,
             artifact_type_file = ARTIFACT_TYPE_FILE,
             artifact_type_symlink = ARTIFACT_TYPE_SYMLINK,
@@ -313,6 +347,48 @@ impl MaterializerStateSqliteTable {
         Ok(())
     }
 
+    /// Migration to schema version 2: adds the `artifact_kind` column and fills it in for existing
+    /// rows. Idempotent.
+    pub(crate) fn add_artifact_kind_column(tx: &Transaction) -> buck2_error::Result<()> {
+        let has_column: bool = tx
+            .query_row(
+                "SELECT COUNT(*) > 0 FROM pragma_table_info(?1) WHERE name = 'artifact_kind'",
+                [STATE_TABLE_NAME],
+                |row| row.get(0),
+            )
+            .with_buck_error_context(|| format!("reading columns of {}", STATE_TABLE_NAME))?;
+        if !has_column {
+            tx.execute(
+                &format!(
+                    "ALTER TABLE {} ADD COLUMN {}",
+                    STATE_TABLE_NAME, &*ARTIFACT_KIND_COLUMN
+                ),
+                [],
+            )
+            .with_buck_error_context(|| {
+                format!("adding column artifact_kind to {}", STATE_TABLE_NAME)
+            })?;
+        }
+        tx.execute(
+            &format!(
+                "UPDATE {table_name} SET artifact_kind = CASE artifact_type
+                    WHEN '{artifact_type_directory}' THEN '{artifact_kind_dir}'
+                    WHEN '{artifact_type_file}' THEN '{artifact_kind_file}'
+                    ELSE '{artifact_kind_symlink}'
+                END WHERE artifact_kind IS NULL",
+                table_name = STATE_TABLE_NAME,
+                artifact_type_directory = ARTIFACT_TYPE_DIRECTORY,
+                artifact_type_file = ARTIFACT_TYPE_FILE,
+                artifact_kind_dir = ARTIFACT_KIND_DIR,
+                artifact_kind_file = ARTIFACT_KIND_FILE,
+                artifact_kind_symlink = ARTIFACT_KIND_SYMLINK,
+            ),
+            [],
+        )
+        .with_buck_error_context(|| format!("filling artifact_kind in {}", STATE_TABLE_NAME))?;
+        Ok(())
+    }
+
     pub(crate) fn insert(
         &self,
         path: &ProjectRelativePath,
@@ -322,7 +398,7 @@ impl MaterializerStateSqliteTable {
         let entry: ArtifactMetadataSqliteEntry = metadata.into();
         static SQL: Lazy<String> = Lazy::new(|| {
             format!(
-                "INSERT INTO {} (path, artifact_type, digest_size, entry_hash, entry_hash_kind, file_is_executable, symlink_target, directory_size, last_access_time) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                "INSERT INTO {} (path, artifact_type, digest_size, entry_hash, entry_hash_kind, file_is_executable, symlink_target, directory_size, last_access_time, artifact_kind) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
                 STATE_TABLE_NAME
             )
         });
@@ -341,6 +417,7 @@ impl MaterializerStateSqliteTable {
                     entry.symlink_target,
                     entry.directory_size,
                     timestamp.timestamp(),
+                    artifact_kind(&entry.artifact_type),
                 ],
             )
             .with_buck_error_context(|| {
@@ -352,13 +429,17 @@ impl MaterializerStateSqliteTable {
         Ok(())
     }
 
+    /// Each chunk is written in its own transaction, so that other writers (e.g. declares deleting
+    /// entries) only ever wait for one chunk rather than for the whole update.
     pub(crate) fn update_access_times(
         &self,
         updates: Vec<&ProjectRelativePathBuf>,
     ) -> buck2_error::Result<()> {
-        let mut conn = self.connection.lock();
-        let tx = conn.transaction()?;
         for chunk in updates.chunks(100) {
+            #[cfg(test)]
+            let _gate = self.access_times_gate.lock();
+            let mut conn = self.connection.lock();
+            let tx = conn.transaction()?;
             let sql = format!(
                 "UPDATE {} SET last_access_time = {} WHERE path IN ({})",
                 STATE_TABLE_NAME,
@@ -370,8 +451,8 @@ impl MaterializerStateSqliteTable {
                 .with_buck_error_context(|| {
                     format!("updating sqlite table {}", STATE_TABLE_NAME)
                 })?;
+            tx.commit()?;
         }
-        tx.commit()?;
         Ok(())
     }
 
@@ -0,0 +1,124 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Online migrations of the materializer state sqlite db schema.
+//!
+//! The `schema_version` table records the schema version a db was migrated to. Dbs created before
+//! it existed are at version 1. When opening a db at an older version, every migration after it
+//! runs in order, each in its own transaction along with the version update, so that a db is never
+//! left at a version that doesn't match its schema. Migrations are idempotent anyway, so that a db
+//! written with `synchronous = OFF` that lost the version update can be migrated again.
+//!
+//! Changes that can't be migrated should bump `DB_SCHEMA_VERSION` instead, which throws away dbs
+//! written with the previous version.
+
+use buck2_error::BuckErrorContext;
+use parking_lot::Mutex;
+use rusqlite::Connection;
+use rusqlite::Transaction;
+
+use crate::materializers::sqlite::MaterializerStateTables;
+use crate::materializers::sqlite::materializer_state_table::MaterializerStateSqliteTable;
+
+const SCHEMA_VERSION_TABLE_NAME: &str = "schema_version";
+
+/// Version of dbs created before the schema version was recorded.
+const INITIAL_SCHEMA_VERSION: u64 = 1;
+
+struct Migration {
+    description: &'static str,
+    run: fn(&Transaction) -> buck2_error::Result<()>,
+}
+
+/// `MIGRATIONS[i]` migrates from version `INITIAL_SCHEMA_VERSION + i` to the next one. Only ever
+/// append to this.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        description: "add artifact_kind to materializer_state",
+        run: MaterializerStateSqliteTable::add_artifact_kind_column,
+    },
+    Migration {
+        description: "add clock table",
+        run: MaterializerStateTables::add_clock_table,
+    },
+];
+
+/// The schema version this binary creates dbs at and migrates dbs to.
+pub(crate) const LATEST_SCHEMA_VERSION: u64 = INITIAL_SCHEMA_VERSION + MIGRATIONS.len() as u64;
+
+/// Creates the `schema_version` table of a new db, whose other tables are created at the latest
+/// version.
+pub(crate) fn create_table(connection: &Mutex<Connection>) -> buck2_error::Result<()> {
+    let mut connection = connection.lock();
+    let tx = connection.transaction()?;
+    write_schema_version(&tx, LATEST_SCHEMA_VERSION)?;
+    tx.commit()?;
+    Ok(())
+}
+
+pub(crate) fn read_schema_version(connection: &Mutex<Connection>) -> buck2_error::Result<u64> {
+    let connection = connection.lock();
+    let has_table: bool = connection
+        .query_row(
+            "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type = 'table' AND name = ?1",
+            [SCHEMA_VERSION_TABLE_NAME],
+            |row| row.get(0),
+        )
+        .with_buck_error_context(|| format!("checking for table {}", SCHEMA_VERSION_TABLE_NAME))?;
+    if !has_table {
+        return Ok(INITIAL_SCHEMA_VERSION);
+    }
+    let version: i64 = connection
+        .query_row(
+            &format!("SELECT version FROM {}", SCHEMA_VERSION_TABLE_NAME),
+            [],
+            |row| row.get(0),
+        )
+        .with_buck_error_context(|| {
+            format!("reading from sqlite table {}", SCHEMA_VERSION_TABLE_NAME)
+        })?;
+    Ok(version as u64)
+}
+
+/// Migrates a db at `version`, which must not be newer than `LATEST_SCHEMA_VERSION`, to the latest
+/// version.
+pub(crate) fn migrate(connection: &Mutex<Connection>, version: u64) -> buck2_error::Result<()> {
+    let mut connection = connection.lock();
+    let first = version.saturating_sub(INITIAL_SCHEMA_VERSION) as usize;
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(first) {
+        let to_version = INITIAL_SCHEMA_VERSION + i as u64 + 1;
+        tracing::info!(
+            to_version,
+            "Migrating materializer state sqlite db: {}",
+            migration.description
+        );
+        let tx = connection.transaction()?;
+        (migration.run)(&tx).with_buck_error_context(|| {
+            format!(
+                "migrating materializer state to schema version {}: {}",
+                to_version, migration.description
+            )
+        })?;
+        write_schema_version(&tx, to_version)?;
+        tx.commit()?;
+    }
+    Ok(())
+}
+
+fn write_schema_version(tx: &Transaction, version: u64) -> buck2_error::Result<()> {
+    tx.execute_batch(&format!(
+        "CREATE TABLE IF NOT EXISTS {table_name} (version INTEGER NOT NULL);
+        DELETE FROM {table_name};
+        INSERT INTO {table_name} (version) VALUES ({version});",
+        table_name = SCHEMA_VERSION_TABLE_NAME,
+        version = version,
+    ))
+    .with_buck_error_context(|| format!("writing sqlite table {}", SCHEMA_VERSION_TABLE_NAME))?;
+    Ok(())
+}
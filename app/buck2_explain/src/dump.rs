@@ -0,0 +1,269 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Dumps of the explain flatbuffer, for offline analysis.
+//!
+//! Dumps can be written as base64 text (what the html embeds), raw flatbuffer bytes, or zstd
+//! compressed flatbuffer bytes. `load_dump` reads any of them, so tools consuming dumps don't need
+//! to know how they were written.
+
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use buck2_error::BuckErrorContext;
+use buck2_error::ErrorTag;
+
+use crate::explain_generated::explain::Build;
+
+/// First bytes of a zstd frame.
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DumpFormat {
+    Base64,
+    Raw,
+    Zstd,
+}
+
+impl fmt::Display for DumpFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            DumpFormat::Base64 => "base64",
+            DumpFormat::Raw => "raw",
+            DumpFormat::Zstd => "zstd",
+        })
+    }
+}
+
+impl DumpFormat {
+    /// The format implied by the extension of `path`: `.zst` or `.zstd` for zstd, `.fbs` or `.bin`
+    /// for raw, and base64 otherwise.
+    pub fn from_path(path: &Path) -> DumpFormat {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("zst" | "zstd") => DumpFormat::Zstd,
+            Some("fbs" | "bin") => DumpFormat::Raw,
+            _ => DumpFormat::Base64,
+        }
+    }
+
+    fn detect(contents: &[u8]) -> DumpFormat {
+        if contents.starts_with(&ZSTD_MAGIC) {
+            DumpFormat::Zstd
+        } else if !contents.is_empty()
+            && contents.iter().all(|b| {
+                b.is_ascii_alphanumeric()
+                    || matches!(b, b'+' | b'/' | b'=')
+                    || b.is_ascii_whitespace()
+            })
+        {
+            DumpFormat::Base64
+        } else {
+            DumpFormat::Raw
+        }
+    }
+}
+
+/// Writes the flatbuffer `fbs` to `path` in `format`.
+pub fn write_dump(path: &Path, fbs: &[u8], format: DumpFormat) -> buck2_error::Result<()> {
+    let contents = match format {
+        DumpFormat::Base64 => STANDARD.encode(fbs).into_bytes(),
+        DumpFormat::Raw => fbs.to_vec(),
+        DumpFormat::Zstd => zstd::encode_all(fbs, 0)?,
+    };
+    fs::write(path, contents)
+        .with_buck_error_context(|| format!("Error writing explain dump `{}`", path.display()))
+}
+
+/// A dump read by `load_dump`.
+pub struct LoadedDump {
+    format: DumpFormat,
+    fbs: Vec<u8>,
+}
+
+impl LoadedDump {
+    /// The format the dump was written in.
+    pub fn format(&self) -> DumpFormat {
+        self.format
+    }
+
+    /// The decoded flatbuffer.
+    pub fn fbs(&self) -> &[u8] {
+        &self.fbs
+    }
+
+    /// The root of the flatbuffer.
+    pub fn build(&self) -> Build<'_> {
+        // Verified when loading.
+        flatbuffers::root::<Build>(&self.fbs).unwrap()
+    }
+
+    /// Labels of the targets in the dump.
+    pub fn targets(&self) -> impl Iterator<Item = &str> {
+        self.build()
+            .targets()
+            .into_iter()
+            .flatten()
+            .filter_map(|target| target.label()?.target_label())
+    }
+
+    /// Dependency edges between targets in the dump, as `(target, dep)` label pairs.
+    pub fn deps(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.build()
+            .targets()
+            .into_iter()
+            .flatten()
+            .filter_map(|target| Some((target.label()?.target_label()?, target.deps()?)))
+            .flat_map(|(label, deps)| {
+                deps.into_iter()
+                    .filter_map(move |dep| Some((label, dep.target_label()?)))
+            })
+    }
+}
+
+/// Reads a dump written by `write_dump` in any format.
+pub fn load_dump(path: &Path) -> buck2_error::Result<LoadedDump> {
+    let contents = fs::read(path)
+        .with_buck_error_context(|| format!("Error reading explain dump `{}`", path.display()))?;
+    let format = DumpFormat::detect(&contents);
+    let fbs = decode(format, contents).with_buck_error_context(|| {
+        format!(
+            "Invalid explain dump `{}` (detected format: {})",
+            path.display(),
+            format
+        )
+    })?;
+    Ok(LoadedDump { format, fbs })
+}
+
+fn decode(format: DumpFormat, contents: Vec<u8>) -> buck2_error::Result<Vec<u8>> {
+    let fbs = match format {
+        DumpFormat::Base64 => {
+            let text: Vec<u8> = contents
+                .into_iter()
+                .filter(|b| !b.is_ascii_whitespace())
+                .collect();
+            STANDARD
+                .decode(text)
+                .map_err(|e| buck2_error::buck2_error!(ErrorTag::Input, "Invalid base64: {}", e))?
+        }
+        DumpFormat::Raw => contents,
+        DumpFormat::Zstd => {
+            zstd::decode_all(contents.as_slice()).buck_error_context("Error decompressing zstd")?
+        }
+    };
+    flatbuffers::root::<Build>(&fbs)
+        .map_err(|e| buck2_error::buck2_error!(ErrorTag::Input, "Invalid flatbuffer: {}", e))?;
+    Ok(fbs)
+}
+
+#[cfg(test)]
+mod tests {
+    use flatbuffers::FlatBufferBuilder;
+
+    use super::*;
+    use crate::explain_generated::explain::BuildArgs;
+    use crate::explain_generated::explain::ConfiguredTargetLabel;
+    use crate::explain_generated::explain::ConfiguredTargetLabelArgs;
+    use crate::explain_generated::explain::ConfiguredTargetNode;
+    use crate::explain_generated::explain::ConfiguredTargetNodeArgs;
+
+    fn gen_fbs() -> Vec<u8> {
+        let mut builder = FlatBufferBuilder::new();
+        let label = |builder: &mut FlatBufferBuilder<'static>, name| {
+            let target_label = builder.create_string(name);
+            ConfiguredTargetLabel::create(
+                builder,
+                &ConfiguredTargetLabelArgs {
+                    target_label: Some(target_label),
+                    ..Default::default()
+                },
+            )
+        };
+        let foo_label = label(&mut builder, "cell//pkg:foo");
+        let bar_dep = label(&mut builder, "cell//pkg:bar");
+        let deps = builder.create_vector(&[bar_dep]);
+        let foo = ConfiguredTargetNode::create(
+            &mut builder,
+            &ConfiguredTargetNodeArgs {
+                label: Some(foo_label),
+                deps: Some(deps),
+                ..Default::default()
+            },
+        );
+        let bar_label = label(&mut builder, "cell//pkg:bar");
+        let bar = ConfiguredTargetNode::create(
+            &mut builder,
+            &ConfiguredTargetNodeArgs {
+                label: Some(bar_label),
+                ..Default::default()
+            },
+        );
+        let targets = builder.create_vector(&[foo, bar]);
+        let build = Build::create(
+            &mut builder,
+            &BuildArgs {
+                targets: Some(targets),
+                ..Default::default()
+            },
+        );
+        builder.finish(build, None);
+        builder.finished_data().to_vec()
+    }
+
+    #[test]
+    fn test_round_trip() -> buck2_error::Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let fbs = gen_fbs();
+        for (name, format) in [
+            ("dump.txt", DumpFormat::Base64),
+            ("dump.fbs", DumpFormat::Raw),
+            ("dump.zst", DumpFormat::Zstd),
+        ] {
+            let path = tempdir.path().join(name);
+            assert_eq!(format, DumpFormat::from_path(&path));
+            write_dump(&path, &fbs, format)?;
+
+            let dump = load_dump(&path)?;
+            assert_eq!(format, dump.format());
+            assert_eq!(fbs, dump.fbs());
+            assert_eq!(
+                vec!["cell//pkg:foo", "cell//pkg:bar"],
+                dump.targets().collect::<Vec<_>>()
+            );
+            assert_eq!(
+                vec![("cell//pkg:foo", "cell//pkg:bar")],
+                dump.deps().collect::<Vec<_>>()
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_corrupted() -> buck2_error::Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let path = tempdir.path().join("dump.zst");
+        let mut contents = zstd::encode_all(gen_fbs().as_slice(), 0)?;
+        contents.truncate(contents.len() / 2);
+        fs::write(&path, contents)?;
+
+        let e = format!("{:?}", load_dump(&path).err().unwrap());
+        assert!(
+            e.contains(&format!(
+                "Invalid explain dump `{}` (detected format: zstd)",
+                path.display()
+            )),
+            "error: {}",
+            e
+        );
+        Ok(())
+    }
+}
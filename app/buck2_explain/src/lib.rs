@@ -11,12 +11,18 @@
 
 use std::fs;
 use std::io::Cursor;
+use std::path::Path;
 
 use base64::Engine;
 use base64::engine::general_purpose::STANDARD;
 use buck2_core::buck2_env;
 use buck2_core::fs::paths::abs_path::AbsPathBuf;
+pub use dump::DumpFormat;
+pub use dump::LoadedDump;
+pub use dump::load_dump;
+pub use dump::write_dump;
 
+mod dump;
 #[allow(unsafe_op_in_unsafe_fn)]
 #[allow(unused_imports)]
 #[allow(unused_extern_crates)]
@@ -100,13 +106,14 @@ pub fn inline_fbs(
     html_in: &str,
 ) -> buck2_error::Result<String> {
     let base64 = STANDARD.encode(fbs);
-    // For dev purposes, dump the base64 encoded flatbuffer to a file
+    // For dev purposes, dump the flatbuffer to a file, in the format implied by its extension
     if let Some(fbs_dump) = fbs_dump {
-        fs::write(fbs_dump, &base64)?;
+        write_dump(fbs_dump, fbs, DumpFormat::from_path(fbs_dump))?;
     }
     let env = buck2_env!("BUCK2_DUMP_FBS", applicability = testing)?;
     if let Some(fbs_dump) = env {
-        fs::write(fbs_dump, &base64)?;
+        let fbs_dump = Path::new(fbs_dump);
+        write_dump(fbs_dump, fbs, DumpFormat::from_path(fbs_dump))?;
     }
     if !html_in.contains(HTML_PLACEHOLDER) {
         return Err(buck2_error::buck2_error!(
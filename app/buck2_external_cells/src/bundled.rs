@@ -21,7 +21,6 @@ use buck2_common::file_ops::RawDirEntry;
 use buck2_common::file_ops::RawPathMetadata;
 use buck2_common::file_ops::TrackedFileDigest;
 use buck2_common::io::fs::is_executable;
-use buck2_core::cells::external::ExternalCellOrigin;
 use buck2_core::cells::name::CellName;
 use buck2_core::cells::paths::CellRelativePath;
 use buck2_core::cells::paths::CellRelativePathBuf;
@@ -300,10 +299,8 @@ async fn declare_all_source_artifacts(
     let buck_out_resolver = artifact_fs.buck_out_path_resolver();
 
     for (path, entry) in ops.dir.unordered_walk_leaves().with_paths() {
-        let path = buck_out_resolver.resolve_external_cell_source(
-            CellRelativePath::new(path.as_ref()),
-            ExternalCellOrigin::Bundled(cell_name),
-        );
+        let path = buck_out_resolver
+            .resolve_bundled_cell_source(CellRelativePath::new(path.as_ref()), cell_name);
         requests.push(WriteRequest {
             path,
             content: entry.contents.to_vec(),
@@ -370,18 +367,13 @@ pub(crate) async fn materialize_all(
     let materializer = ctx.per_transaction_data().get_materializer();
     let mut paths = Vec::new();
     for (path, _entry) in ops.dir.unordered_walk_leaves().with_paths() {
-        let path = buck_out_resolver.resolve_external_cell_source(
-            CellRelativePath::new(path.as_ref()),
-            ExternalCellOrigin::Bundled(cell),
-        );
+        let path = buck_out_resolver
+            .resolve_bundled_cell_source(CellRelativePath::new(path.as_ref()), cell);
         paths.push(path);
     }
 
     materializer.ensure_materialized(paths).await?;
-    Ok(buck_out_resolver.resolve_external_cell_source(
-        CellRelativePath::unchecked_new(""),
-        ExternalCellOrigin::Bundled(cell),
-    ))
+    Ok(buck_out_resolver.resolve_bundled_cell_source(CellRelativePath::unchecked_new(""), cell))
 }
 
 #[cfg(test)]
@@ -25,7 +25,7 @@ use buck2_common::file_ops::RawPathMetadata;
 use buck2_common::io::IoProvider;
 use buck2_common::io::fs::FsIoProvider;
 use buck2_core::cells::cell_path::CellPath;
-use buck2_core::cells::external::ExternalCellOrigin;
+use buck2_core::cells::external::GitCellRevision;
 use buck2_core::cells::external::GitCellSetup;
 use buck2_core::cells::name::CellName;
 use buck2_core::cells::paths::CellRelativePath;
@@ -47,6 +47,7 @@ use buck2_execute::execute::blocking::IoRequest;
 use buck2_execute::execute::clean_output_paths::CleanOutputPaths;
 use buck2_execute::materialize::materializer::HasMaterializer;
 use buck2_execute::materialize::materializer::Materializer;
+use buck2_util::process::async_background_command;
 use buck2_util::process::background_command;
 use cmp_any::PartialEqAny;
 use dice::CancellationContext;
@@ -65,10 +66,27 @@ enum GitError {
     },
     #[error("Expected git to create a directory at the checkout location")]
     NoDirectory,
+    #[error("git ref `{git_ref}` not found in `{git_origin}`")]
+    #[buck2(tag = Input)]
+    RefNotFound {
+        git_origin: Arc<str>,
+        git_ref: Arc<str>,
+    },
+    #[error(
+        "git tag `{tag}` in `{git_origin}` is at commit `{actual}`, but `{expected}` is pinned"
+    )]
+    #[buck2(tag = Input)]
+    TagMismatch {
+        git_origin: Arc<str>,
+        tag: Arc<str>,
+        expected: Arc<str>,
+        actual: Arc<str>,
+    },
 }
 
 struct GitFetchIoRequest {
-    setup: GitCellSetup,
+    git_origin: Arc<str>,
+    commit: Arc<str>,
     path: ProjectRelativePathBuf,
 }
 
@@ -112,11 +130,11 @@ impl IoRequest for GitFetchIoRequest {
             c.arg("remote")
                 .arg("add")
                 .arg("origin")
-                .arg(self.setup.git_origin.as_ref());
+                .arg(self.git_origin.as_ref());
         })?;
 
         run_git(&path, |c| {
-            c.arg("fetch").arg("origin").arg(self.setup.commit.as_ref());
+            c.arg("fetch").arg("origin").arg(self.commit.as_ref());
         })?;
 
         run_git(&path, |c| {
@@ -127,9 +145,139 @@ impl IoRequest for GitFetchIoRequest {
     }
 }
 
+/// Lists the refs of a git origin.
+#[async_trait::async_trait]
+trait LsRemote: Send + Sync {
+    /// The output of `git ls-remote` for `pattern`.
+    async fn ls_remote(&self, git_origin: &str, pattern: &str) -> buck2_error::Result<String>;
+}
+
+struct GitCli;
+
+#[async_trait::async_trait]
+impl LsRemote for GitCli {
+    async fn ls_remote(&self, git_origin: &str, pattern: &str) -> buck2_error::Result<String> {
+        let output = async_background_command("git")
+            .arg("ls-remote")
+            .arg(git_origin)
+            .arg(pattern)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .output()
+            .await
+            .buck_error_context("Could not run git to resolve external cell ref")?;
+
+        if !output.status.success() {
+            return Err(GitError::Unsuccessful {
+                exit_code: output.status,
+                stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+            }
+            .into());
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+}
+
+/// Commits that refs and tags were resolved to. A ref is only resolved once, so that builds in a
+/// daemon keep seeing the same commit even if the ref moves in the origin.
+#[derive(Default)]
+struct ResolvedCommits(Mutex<HashMap<(Arc<str>, Arc<str>), Arc<str>>>);
+
+impl ResolvedCommits {
+    /// The commit `pattern` resolves to in `git_origin`, resolving it if it wasn't yet.
+    async fn resolve(
+        &self,
+        ls_remote: &dyn LsRemote,
+        git_origin: &Arc<str>,
+        pattern: &Arc<str>,
+    ) -> buck2_error::Result<Arc<str>> {
+        let key = (git_origin.dupe(), pattern.dupe());
+        if let Some(commit) = self.0.lock().unwrap().get(&key) {
+            return Ok(commit.dupe());
+        }
+
+        let output = ls_remote.ls_remote(git_origin, pattern).await?;
+        let commit =
+            find_ref_in_ls_remote(&output, pattern).ok_or_else(|| GitError::RefNotFound {
+                git_origin: git_origin.dupe(),
+                git_ref: pattern.dupe(),
+            })?;
+        // If the ref was resolved concurrently, keep the first result.
+        Ok(self.0.lock().unwrap().entry(key).or_insert(commit).dupe())
+    }
+
+    /// The commit `setup` refers to. Refs and tags are resolved against the origin, without
+    /// fetching anything.
+    async fn resolve_commit(
+        &self,
+        ls_remote: &dyn LsRemote,
+        setup: &GitCellSetup,
+    ) -> buck2_error::Result<Arc<str>> {
+        match &setup.revision {
+            GitCellRevision::Commit(commit) => Ok(commit.dupe()),
+            GitCellRevision::Ref(git_ref) => {
+                self.resolve(ls_remote, &setup.git_origin, git_ref).await
+            }
+            GitCellRevision::Tag { tag, commit } => {
+                let actual = self
+                    .resolve(
+                        ls_remote,
+                        &setup.git_origin,
+                        &format!("refs/tags/{}", tag).into(),
+                    )
+                    .await?;
+                match commit {
+                    Some(expected) if *expected != actual => Err(GitError::TagMismatch {
+                        git_origin: setup.git_origin.dupe(),
+                        tag: tag.dupe(),
+                        expected: expected.dupe(),
+                        actual,
+                    }
+                    .into()),
+                    _ => Ok(actual),
+                }
+            }
+        }
+    }
+}
+
+/// The commit `setup` refers to.
+async fn resolve_commit(setup: &GitCellSetup) -> buck2_error::Result<Arc<str>> {
+    static RESOLVED_COMMITS: OnceLock<ResolvedCommits> = OnceLock::new();
+    RESOLVED_COMMITS
+        .get_or_init(Default::default)
+        .resolve_commit(&GitCli, setup)
+        .await
+}
+
+/// Finds the commit of `git_ref` in the output of `git ls-remote`, which lists every ref `git_ref`
+/// is a suffix of. Exact matches win over branches, which win over tags. Annotated tags are listed
+/// twice, the `^{}` entry is the commit the tag points to.
+fn find_ref_in_ls_remote(output: &str, git_ref: &str) -> Option<Arc<str>> {
+    let refs: HashMap<&str, &str> = output
+        .lines()
+        .filter_map(|line| line.split_once('\t'))
+        .map(|(commit, name)| (name.trim(), commit.trim()))
+        .collect();
+    [
+        git_ref.to_owned(),
+        format!("refs/heads/{}", git_ref),
+        format!("refs/tags/{}", git_ref),
+    ]
+    .iter()
+    .find_map(|name| {
+        refs.get(format!("{}^{{}}", name).as_str())
+            .or_else(|| refs.get(name.as_str()))
+    })
+    .map(|commit| (*commit).into())
+}
+
 async fn download_impl(
     ctx: &mut DiceComputations<'_>,
-    setup: &GitCellSetup,
+    git_origin: &Arc<str>,
+    commit: &Arc<str>,
     path: &ProjectRelativePath,
     materializer: &dyn Materializer,
     cancellations: &CancellationContext,
@@ -145,7 +293,8 @@ async fn download_impl(
 
     io.execute_io(
         Box::new(GitFetchIoRequest {
-            setup: setup.dupe(),
+            git_origin: git_origin.dupe(),
+            commit: commit.dupe(),
             path: path.to_owned(),
         }),
         cancellations,
@@ -191,7 +340,8 @@ async fn download_impl(
 async fn download_and_materialize(
     ctx: &mut DiceComputations<'_>,
     path: &ProjectRelativePath,
-    setup: &GitCellSetup,
+    git_origin: &Arc<str>,
+    commit: &Arc<str>,
     cancellations: &CancellationContext,
 ) -> buck2_error::Result<()> {
     let materializer = ctx.per_transaction_data().get_materializer();
@@ -214,7 +364,7 @@ async fn download_and_materialize(
                 .get_or_init(Default::default)
                 .lock()
                 .unwrap();
-            let entry = map_guard.entry(setup.commit.dupe());
+            let entry = map_guard.entry(commit.dupe());
 
             match entry {
                 hash_map::Entry::Occupied(entry) => {
@@ -243,7 +393,9 @@ async fn download_and_materialize(
     // to deal with another key that might be waiting on this download to finish, which would be
     // pretty complicated to deal with.
     let res = cancellations
-        .critical_section(|| download_impl(ctx, setup, path, &*materializer, cancellations))
+        .critical_section(|| {
+            download_impl(ctx, git_origin, commit, path, &*materializer, cancellations)
+        })
         .await;
 
     // Give up our lock
@@ -253,7 +405,7 @@ async fn download_and_materialize(
         .unwrap()
         .lock()
         .unwrap()
-        .remove(&setup.commit)
+        .remove(commit)
         .unwrap();
 
     res
@@ -263,7 +415,9 @@ async fn download_and_materialize(
 pub(crate) struct GitFileOpsDelegate {
     buck_out_resolver: BuckOutPathResolver,
     cell: CellName,
-    setup: GitCellSetup,
+    git_origin: Arc<str>,
+    /// The commit the revision of the cell was resolved to.
+    commit: Arc<str>,
     // The fs accesses in this code are sort of a mix between source file accesses and buck-out
     // accesses. Unconditionally using an `FsIoProvider` turns out to give all the right behavior
     io: FsIoProvider,
@@ -272,7 +426,7 @@ pub(crate) struct GitFileOpsDelegate {
 impl GitFileOpsDelegate {
     fn resolve(&self, path: &CellRelativePath) -> ProjectRelativePathBuf {
         self.buck_out_resolver
-            .resolve_external_cell_source(path, ExternalCellOrigin::Git(self.setup.dupe()))
+            .resolve_git_cell_source(path, &self.commit)
     }
 
     fn get_base_path(&self) -> ProjectRelativePathBuf {
@@ -365,17 +519,27 @@ pub(crate) async fn get_file_ops_delegate(
             ctx: &mut DiceComputations,
             cancellations: &CancellationContext,
         ) -> Self::Value {
+            // Source artifacts of the cell are resolved to the checkout of this same commit.
+            let commit = resolve_git_commit(ctx, self.1.dupe()).await?;
             let artifact_fs = ctx.get_artifact_fs().await?;
             let ops = GitFileOpsDelegate {
                 buck_out_resolver: artifact_fs.buck_out_path_resolver().clone(),
                 cell: self.0,
-                setup: self.1.dupe(),
+                git_origin: self.1.git_origin.dupe(),
+                commit,
                 io: FsIoProvider::new(
                     artifact_fs.fs().dupe(),
                     ctx.global_data().get_digest_config().cas_digest_config(),
                 ),
             };
-            download_and_materialize(ctx, &ops.get_base_path(), &self.1, cancellations).await?;
+            download_and_materialize(
+                ctx,
+                &ops.get_base_path(),
+                &ops.git_origin,
+                &ops.commit,
+                cancellations,
+            )
+            .await?;
             Ok(Arc::new(ops))
         }
 
@@ -387,6 +551,63 @@ pub(crate) async fn get_file_ops_delegate(
     ctx.compute(&GitFileOpsDelegateKey(cell, setup)).await?
 }
 
+/// The commit `setup` is checked out at. Refs and tags are resolved once, and then stay the same
+/// for the lifetime of the daemon.
+pub(crate) async fn resolve_git_commit(
+    ctx: &mut DiceComputations<'_>,
+    setup: GitCellSetup,
+) -> buck2_error::Result<Arc<str>> {
+    #[derive(
+        dupe::Dupe,
+        Clone,
+        Debug,
+        derive_more::Display,
+        PartialEq,
+        Eq,
+        Hash,
+        allocative::Allocative
+    )]
+    struct GitCommitKey(GitCellSetup);
+
+    #[async_trait::async_trait]
+    impl Key for GitCommitKey {
+        type Value = buck2_error::Result<Arc<str>>;
+
+        async fn compute(
+            &self,
+            _ctx: &mut DiceComputations,
+            _cancellations: &CancellationContext,
+        ) -> Self::Value {
+            let commit = resolve_commit(&self.0).await?;
+            match &self.0.revision {
+                GitCellRevision::Commit(_) => {}
+                GitCellRevision::Ref(git_ref) => tracing::info!(
+                    "Resolved git ref `{}` of `{}` to commit `{}`",
+                    git_ref,
+                    self.0.git_origin,
+                    commit
+                ),
+                GitCellRevision::Tag { tag, .. } => tracing::info!(
+                    "Resolved git tag `{}` of `{}` to commit `{}`",
+                    tag,
+                    self.0.git_origin,
+                    commit
+                ),
+            }
+            Ok(commit)
+        }
+
+        fn equality(x: &Self::Value, y: &Self::Value) -> bool {
+            match (x, y) {
+                (Ok(x), Ok(y)) => x == y,
+                _ => false,
+            }
+        }
+    }
+
+    ctx.compute(&GitCommitKey(setup)).await?
+}
+
 pub(crate) async fn materialize_all(
     ctx: &mut DiceComputations<'_>,
     cell: CellName,
@@ -396,3 +617,124 @@ pub(crate) async fn materialize_all(
     let ops = get_file_ops_delegate(ctx, cell, setup.dupe()).await?;
     Ok(ops.get_base_path())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_ref_in_ls_remote() {
+        let output = "\
+1111111111111111111111111111111111111111\trefs/heads/main
+2222222222222222222222222222222222222222\trefs/remotes/origin/main
+3333333333333333333333333333333333333333\trefs/tags/v1
+4444444444444444444444444444444444444444\trefs/tags/v1^{}
+";
+        assert_eq!(
+            Some("1111111111111111111111111111111111111111".into()),
+            find_ref_in_ls_remote(output, "main")
+        );
+        assert_eq!(
+            Some("1111111111111111111111111111111111111111".into()),
+            find_ref_in_ls_remote(output, "refs/heads/main")
+        );
+        assert_eq!(
+            Some("2222222222222222222222222222222222222222".into()),
+            find_ref_in_ls_remote(output, "refs/remotes/origin/main")
+        );
+        assert_eq!(
+            Some("4444444444444444444444444444444444444444".into()),
+            find_ref_in_ls_remote(output, "v1")
+        );
+        assert_eq!(None, find_ref_in_ls_remote(output, "v2"));
+    }
+
+    /// Serves `git ls-remote` output from memory, counting the calls.
+    #[derive(Default)]
+    struct FakeLsRemote {
+        output: Mutex<String>,
+        calls: Mutex<usize>,
+    }
+
+    #[async_trait::async_trait]
+    impl LsRemote for FakeLsRemote {
+        async fn ls_remote(
+            &self,
+            _git_origin: &str,
+            _pattern: &str,
+        ) -> buck2_error::Result<String> {
+            *self.calls.lock().unwrap() += 1;
+            Ok(self.output.lock().unwrap().clone())
+        }
+    }
+
+    fn tag_setup(tag: &str, commit: Option<&str>) -> GitCellSetup {
+        GitCellSetup {
+            git_origin: "https://github.com/jeff/libfoo.git".into(),
+            revision: GitCellRevision::Tag {
+                tag: tag.into(),
+                commit: commit.map(Into::into),
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resolve_tag() -> buck2_error::Result<()> {
+        let ls_remote = FakeLsRemote::default();
+        *ls_remote.output.lock().unwrap() = "\
+1111111111111111111111111111111111111111\trefs/tags/v1
+2222222222222222222222222222222222222222\trefs/tags/v2
+3333333333333333333333333333333333333333\trefs/tags/v2^{}
+"
+        .to_owned();
+        let resolved = ResolvedCommits::default();
+
+        assert_eq!(
+            "1111111111111111111111111111111111111111",
+            &*resolved
+                .resolve_commit(&ls_remote, &tag_setup("v1", None))
+                .await?
+        );
+        assert_eq!(
+            "3333333333333333333333333333333333333333",
+            &*resolved
+                .resolve_commit(
+                    &ls_remote,
+                    &tag_setup("v2", Some("3333333333333333333333333333333333333333"))
+                )
+                .await?
+        );
+
+        // The tag moves, but stays resolved to the commit it was first resolved to.
+        *ls_remote.output.lock().unwrap() =
+            "4444444444444444444444444444444444444444\trefs/tags/v1\n".to_owned();
+        assert_eq!(
+            "1111111111111111111111111111111111111111",
+            &*resolved
+                .resolve_commit(&ls_remote, &tag_setup("v1", None))
+                .await?
+        );
+        assert_eq!(2, *ls_remote.calls.lock().unwrap());
+
+        let e = resolved
+            .resolve_commit(
+                &ls_remote,
+                &tag_setup("v1", Some("5555555555555555555555555555555555555555")),
+            )
+            .await
+            .unwrap_err();
+        let e = format!("{:?}", e);
+        assert!(
+            e.contains("is at commit `1111111111111111111111111111111111111111`, but `5555555555555555555555555555555555555555` is pinned"),
+            "error: {}",
+            e
+        );
+
+        let e = resolved
+            .resolve_commit(&ls_remote, &tag_setup("v3", None))
+            .await
+            .unwrap_err();
+        assert!(format!("{:?}", e).contains("`refs/tags/v3` not found"));
+        Ok(())
+    }
+}
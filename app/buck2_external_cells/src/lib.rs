@@ -19,6 +19,7 @@ use buck2_common::dice::file_ops::delegate::FileOpsDelegate;
 use buck2_common::file_ops::RawPathMetadata;
 use buck2_core::cells::cell_root_path::CellRootPath;
 use buck2_core::cells::external::ExternalCellOrigin;
+use buck2_core::cells::external::GitCellSetup;
 use buck2_core::cells::name::CellName;
 use buck2_core::fs::project_rel_path::ProjectRelativePathBuf;
 use dice::DiceComputations;
@@ -57,6 +58,14 @@ impl buck2_common::external_cells::ExternalCellsImpl for ConcreteExternalCellsIm
         bundled::find_bundled_data(cell_name).map(|_| ())
     }
 
+    async fn resolve_git_commit(
+        &self,
+        ctx: &mut DiceComputations<'_>,
+        setup: GitCellSetup,
+    ) -> buck2_error::Result<Arc<str>> {
+        git::resolve_git_commit(ctx, setup).await
+    }
+
     async fn expand(
         &self,
         ctx: &mut DiceComputations<'_>,
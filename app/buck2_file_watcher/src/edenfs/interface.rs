@@ -17,6 +17,7 @@ use async_trait::async_trait;
 use buck2_common::dice::file_ops::FileChangeTracker;
 use buck2_common::ignores::ignore_set::IgnoreSet;
 use buck2_common::legacy_configs::configs::LegacyBuckConfig;
+use buck2_common::legacy_configs::existence_cache::ConfigFileExistenceCache;
 use buck2_common::legacy_configs::key::BuckconfigKeyRef;
 use buck2_core::cells::CellResolver;
 use buck2_core::cells::name::CellName;
@@ -660,6 +661,7 @@ impl EdenFsFileWatcher {
         // are a lot of destructors to run. On the other hand, we don't have to wait for
         // it. So, we just send it off to its own thread.
         let dice = dice.unstable_take();
+        ConfigFileExistenceCache::process().invalidate_project_paths();
 
         // Get mergebase state
         let last_mergebase_info = self.last_mergebase.read().await.clone();
@@ -762,4 +764,8 @@ impl FileWatcher for EdenFsFileWatcher {
         )
         .await
     }
+
+    fn provider(&self) -> buck2_data::FileWatcherProvider {
+        buck2_data::FileWatcherProvider::EdenFs
+    }
 }
@@ -40,6 +40,13 @@ pub trait FileWatcher: Allocative + Send + Sync + 'static {
         &self,
         dice: DiceTransactionUpdater,
     ) -> buck2_error::Result<(DiceTransactionUpdater, Mergebase)>;
+
+    fn provider(&self) -> buck2_data::FileWatcherProvider;
+
+    /// Changes received but not applied by a sync yet, if this watcher queues them.
+    fn pending_changes(&self) -> Option<u64> {
+        None
+    }
 }
 
 impl dyn FileWatcher {
@@ -107,6 +107,10 @@ impl FileWatcher for FsHashCrawler {
         )
         .await
     }
+
+    fn provider(&self) -> buck2_data::FileWatcherProvider {
+        buck2_data::FileWatcherProvider::FsHashCrawler
+    }
 }
 
 #[derive(Ord, PartialOrd, Eq, PartialEq, Debug)]
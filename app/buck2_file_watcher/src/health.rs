@@ -0,0 +1,423 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Health of the file watcher.
+//!
+//! When a backend stops delivering events or every sync fails, commands would otherwise run on
+//! stale file state without any signal. `HealthCheckedFileWatcher` records the outcome of every
+//! sync, reports it in `buck2 status`, dispatches an event for slow or failed syncs, and tells the
+//! daemon when the watcher is wedged, so that commands can be rejected rather than run on stale
+//! state. Commands are what sync the watcher, so while it is wedged, one command at a time is still
+//! let through to retry, and the watcher recovers once a sync succeeds.
+
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+
+use allocative::Allocative;
+use async_trait::async_trait;
+use buck2_common::legacy_configs::configs::LegacyBuckConfig;
+use buck2_common::legacy_configs::key::BuckconfigKeyRef;
+use buck2_error::ErrorTag;
+use buck2_events::dispatch::get_dispatcher_opt;
+use dice::DiceTransactionUpdater;
+use dupe::Dupe;
+
+use crate::file_watcher::FileWatcher;
+use crate::mergebase::Mergebase;
+
+#[derive(Debug, Clone, Copy)]
+pub struct FileWatcherHealthConfig {
+    /// Syncs taking longer than this dispatch a `FileWatcherSyncUnhealthy` event.
+    pub slow_sync_threshold: Duration,
+    /// The watcher is wedged once no sync succeeded for this long, while syncs were attempted.
+    pub wedged_timeout: Duration,
+}
+
+impl FileWatcherHealthConfig {
+    pub fn from_config(root_config: &LegacyBuckConfig) -> buck2_error::Result<Self> {
+        let slow_sync_threshold_ms = root_config
+            .parse(BuckconfigKeyRef {
+                section: "buck2",
+                property: "file_watcher_slow_sync_threshold_ms",
+            })?
+            .unwrap_or(10_000);
+        let wedged_timeout_s = root_config
+            .parse(BuckconfigKeyRef {
+                section: "buck2",
+                property: "file_watcher_wedged_timeout_s",
+            })?
+            .unwrap_or(600);
+        Ok(Self {
+            slow_sync_threshold: Duration::from_millis(slow_sync_threshold_ms),
+            wedged_timeout: Duration::from_secs(wedged_timeout_s),
+        })
+    }
+}
+
+/// Health of the file watcher, as reported in `buck2 status`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileWatcherHealth {
+    pub provider: buck2_data::FileWatcherProvider,
+    /// `None` if no sync succeeded yet.
+    pub last_successful_sync: Option<SystemTime>,
+    pub pending_changes: Option<u64>,
+    pub failed_syncs_since_success: u64,
+    pub wedged: bool,
+}
+
+struct SyncState {
+    last_successful_sync: Option<SystemTime>,
+    /// When the first sync failed since the last successful one. Tracked separately from the last
+    /// success, so that a single failure after the daemon was idle for a while doesn't make the
+    /// watcher wedged.
+    failing_since: Option<Instant>,
+    failed_syncs_since_success: u64,
+    running: usize,
+    /// When the oldest of the running syncs started.
+    running_since: Option<Instant>,
+    /// Whether a command was let through to retry syncing while the watcher is wedged.
+    retrying: bool,
+}
+
+impl SyncState {
+    fn is_wedged(&self, now: Instant, wedged_timeout: Duration) -> bool {
+        let failing = self
+            .failing_since
+            .is_some_and(|since| now.saturating_duration_since(since) >= wedged_timeout);
+        let hung = self
+            .running_since
+            .is_some_and(|since| now.saturating_duration_since(since) >= wedged_timeout);
+        failing || hung
+    }
+}
+
+/// Wraps a `FileWatcher` to keep track of its health.
+#[derive(Allocative)]
+pub struct HealthCheckedFileWatcher {
+    inner: Arc<dyn FileWatcher>,
+    #[allocative(skip)]
+    config: FileWatcherHealthConfig,
+    #[allocative(skip)]
+    state: Mutex<SyncState>,
+}
+
+/// Marks a sync as running until dropped, so that a sync whose command was cancelled doesn't
+/// count as hung.
+struct RunningSync<'a>(&'a Mutex<SyncState>);
+
+impl<'a> RunningSync<'a> {
+    fn new(state: &'a Mutex<SyncState>, now: Instant) -> Self {
+        let mut guard = state.lock().unwrap();
+        guard.running += 1;
+        guard.running_since.get_or_insert(now);
+        Self(state)
+    }
+}
+
+impl Drop for RunningSync<'_> {
+    fn drop(&mut self) {
+        let mut state = self.0.lock().unwrap();
+        state.running -= 1;
+        if state.running == 0 {
+            state.running_since = None;
+        }
+    }
+}
+
+impl HealthCheckedFileWatcher {
+    pub fn new(inner: Arc<dyn FileWatcher>, config: FileWatcherHealthConfig) -> Self {
+        Self {
+            inner,
+            config,
+            state: Mutex::new(SyncState {
+                last_successful_sync: None,
+                failing_since: None,
+                failed_syncs_since_success: 0,
+                running: 0,
+                running_since: None,
+                retrying: false,
+            }),
+        }
+    }
+
+    pub fn health(&self) -> FileWatcherHealth {
+        let state = self.state.lock().unwrap();
+        FileWatcherHealth {
+            provider: self.inner.provider(),
+            last_successful_sync: state.last_successful_sync,
+            pending_changes: self.inner.pending_changes(),
+            failed_syncs_since_success: state.failed_syncs_since_success,
+            wedged: state.is_wedged(Instant::now(), self.config.wedged_timeout),
+        }
+    }
+
+    /// Errors if the watcher is wedged, since running a command would use stale file state. Unless
+    /// another command is already doing so, the command is let through to retry syncing instead:
+    /// it holds the returned `WedgedRetry` while it runs.
+    pub fn check_not_wedged(self: &Arc<Self>) -> buck2_error::Result<Option<WedgedRetry>> {
+        let failed_syncs_since_success = {
+            let mut state = self.state.lock().unwrap();
+            if !state.is_wedged(Instant::now(), self.config.wedged_timeout) {
+                return Ok(None);
+            }
+            if !state.retrying {
+                state.retrying = true;
+                return Ok(Some(WedgedRetry(self.dupe())));
+            }
+            state.failed_syncs_since_success
+        };
+        Err(buck2_error::buck2_error!(
+            ErrorTag::FileWatcherWedged,
+            "The file watcher ({}) is wedged: no sync succeeded in the last {}s ({} failed since the last success), so file changes may not be picked up. \
+            Another command is retrying the sync. If it doesn't recover, run `buck2 killall` (and restart watchman if it is the file watcher), \
+            or pass `--ignore-wedged-file-watcher` to run the command on possibly stale file state",
+            self.inner.provider().as_str_name(),
+            self.config.wedged_timeout.as_secs(),
+            failed_syncs_since_success,
+        ))
+    }
+}
+
+/// Held by the command let through to retry syncing while the watcher is wedged. Once dropped,
+/// another command can retry.
+pub struct WedgedRetry(Arc<HealthCheckedFileWatcher>);
+
+impl Drop for WedgedRetry {
+    fn drop(&mut self) {
+        self.0.state.lock().unwrap().retrying = false;
+    }
+}
+
+#[async_trait]
+impl FileWatcher for HealthCheckedFileWatcher {
+    async fn sync(
+        &self,
+        dice: DiceTransactionUpdater,
+    ) -> buck2_error::Result<(DiceTransactionUpdater, Mergebase)> {
+        let start = Instant::now();
+        let running = RunningSync::new(&self.state, start);
+        let res = self.inner.sync(dice).await;
+        drop(running);
+        let duration = start.elapsed();
+
+        let failed_syncs_since_success = {
+            let mut state = self.state.lock().unwrap();
+            if res.is_ok() {
+                state.last_successful_sync = Some(SystemTime::now());
+                state.failing_since = None;
+                state.failed_syncs_since_success = 0;
+            } else {
+                state.failing_since.get_or_insert(start);
+                state.failed_syncs_since_success += 1;
+            }
+            state.failed_syncs_since_success
+        };
+
+        if res.is_err() || duration >= self.config.slow_sync_threshold {
+            if let Some(dispatcher) = get_dispatcher_opt() {
+                dispatcher.instant_event(buck2_data::FileWatcherSyncUnhealthy {
+                    provider: self.inner.provider() as i32,
+                    duration: duration.try_into().ok(),
+                    error: res.as_ref().err().map(|e| format!("{:#}", e)),
+                    failed_syncs_since_success,
+                });
+            }
+        }
+
+        res
+    }
+
+    fn provider(&self) -> buck2_data::FileWatcherProvider {
+        self.inner.provider()
+    }
+
+    fn pending_changes(&self) -> Option<u64> {
+        self.inner.pending_changes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use buck2_events::dispatch::EventDispatcher;
+    use buck2_events::dispatch::with_dispatcher_async;
+    use buck2_events::source::ChannelEventSource;
+    use buck2_wrapper_common::invocation_id::TraceId;
+    use dice::DetectCycles;
+    use dice::Dice;
+
+    use super::*;
+
+    #[derive(Clone, Copy)]
+    enum Behavior {
+        Healthy,
+        Slow,
+        Failing,
+    }
+
+    #[derive(Allocative)]
+    struct MockFileWatcher {
+        #[allocative(skip)]
+        behavior: Mutex<Behavior>,
+    }
+
+    #[async_trait]
+    impl FileWatcher for MockFileWatcher {
+        async fn sync(
+            &self,
+            dice: DiceTransactionUpdater,
+        ) -> buck2_error::Result<(DiceTransactionUpdater, Mergebase)> {
+            let behavior = *self.behavior.lock().unwrap();
+            match behavior {
+                Behavior::Healthy => Ok((dice, Mergebase::default())),
+                Behavior::Slow => {
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                    Ok((dice, Mergebase::default()))
+                }
+                Behavior::Failing => Err(buck2_error::buck2_error!(
+                    ErrorTag::WatchmanTimeout,
+                    "mock sync failed"
+                )),
+            }
+        }
+
+        fn provider(&self) -> buck2_data::FileWatcherProvider {
+            buck2_data::FileWatcherProvider::Watchman
+        }
+
+        fn pending_changes(&self) -> Option<u64> {
+            Some(3)
+        }
+    }
+
+    fn unhealthy_events(
+        source: &mut ChannelEventSource,
+    ) -> Vec<buck2_data::FileWatcherSyncUnhealthy> {
+        let mut res = Vec::new();
+        while let Some(event) = source.try_receive() {
+            if let buck2_data::buck_event::Data::Instant(instant) =
+                event.unpack_buck().unwrap().data()
+            {
+                if let Some(buck2_data::instant_event::Data::FileWatcherSyncUnhealthy(e)) =
+                    &instant.data
+                {
+                    res.push(e.clone());
+                }
+            }
+        }
+        res
+    }
+
+    #[tokio::test]
+    async fn test_health() -> buck2_error::Result<()> {
+        let mock = Arc::new(MockFileWatcher {
+            behavior: Mutex::new(Behavior::Healthy),
+        });
+        let watcher = Arc::new(HealthCheckedFileWatcher::new(
+            mock.clone(),
+            FileWatcherHealthConfig {
+                slow_sync_threshold: Duration::from_millis(20),
+                wedged_timeout: Duration::ZERO,
+            },
+        ));
+        let dice = Dice::builder().build(DetectCycles::Enabled);
+        let (mut source, sink) = buck2_events::create_source_sink_pair();
+        let dispatcher = EventDispatcher::new(TraceId::null(), sink);
+        let sync = |behavior| {
+            *mock.behavior.lock().unwrap() = behavior;
+            with_dispatcher_async(dispatcher.clone(), watcher.sync(dice.updater()))
+        };
+
+        // Nothing was attempted yet, so the watcher isn't wedged although no sync succeeded.
+        let health = watcher.health();
+        assert_eq!(health.provider, buck2_data::FileWatcherProvider::Watchman);
+        assert_eq!(health.last_successful_sync, None);
+        assert_eq!(health.pending_changes, Some(3));
+        assert!(!health.wedged);
+
+        sync(Behavior::Healthy).await?;
+        let health = watcher.health();
+        assert!(health.last_successful_sync.is_some());
+        assert!(!health.wedged);
+        assert!(watcher.check_not_wedged()?.is_none());
+        assert!(unhealthy_events(&mut source).is_empty());
+
+        sync(Behavior::Slow).await?;
+        assert!(!watcher.health().wedged);
+        let events = unhealthy_events(&mut source);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].error, None);
+        assert_eq!(events[0].failed_syncs_since_success, 0);
+
+        assert!(sync(Behavior::Failing).await.is_err());
+        assert!(sync(Behavior::Failing).await.is_err());
+        let health = watcher.health();
+        assert_eq!(health.failed_syncs_since_success, 2);
+        assert!(health.wedged);
+        let events = unhealthy_events(&mut source);
+        assert_eq!(events.len(), 2);
+        assert!(
+            events[1]
+                .error
+                .as_ref()
+                .unwrap()
+                .contains("mock sync failed")
+        );
+        assert_eq!(events[1].failed_syncs_since_success, 2);
+        // One command is let through to retry, the others are rejected meanwhile.
+        let retry = watcher.check_not_wedged()?.expect("Expected a retry");
+        let e = watcher.check_not_wedged().err().expect("Expected an error");
+        assert_eq!(e.best_tag(), Some(ErrorTag::FileWatcherWedged));
+        assert!(e.to_string().contains("buck2 killall"), "error: {}", e);
+        // The retry fails, so the next command retries again.
+        assert!(sync(Behavior::Failing).await.is_err());
+        drop(retry);
+        let retry = watcher.check_not_wedged()?.expect("Expected a retry");
+
+        // A successful sync recovers.
+        sync(Behavior::Healthy).await?;
+        drop(retry);
+        assert!(!watcher.health().wedged);
+        assert_eq!(watcher.health().failed_syncs_since_success, 0);
+        assert!(watcher.check_not_wedged()?.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_wedged_window() {
+        let start = Instant::now();
+        let timeout = Duration::from_secs(60);
+        let mut state = SyncState {
+            last_successful_sync: None,
+            failing_since: None,
+            failed_syncs_since_success: 0,
+            running: 0,
+            running_since: None,
+            retrying: false,
+        };
+        assert!(!state.is_wedged(start + timeout * 2, timeout));
+
+        // Failures within the window are tolerated.
+        state.failing_since = Some(start);
+        state.failed_syncs_since_success = 1;
+        assert!(!state.is_wedged(start + timeout / 2, timeout));
+        assert!(state.is_wedged(start + timeout, timeout));
+
+        // So is a sync that takes a while, but not one that hangs.
+        state.failing_since = None;
+        state.failed_syncs_since_success = 0;
+        state.running = 1;
+        state.running_since = Some(start + timeout);
+        assert!(!state.is_wedged(start + timeout * 3 / 2, timeout));
+        assert!(state.is_wedged(start + timeout * 2, timeout));
+    }
+}
@@ -295,4 +295,15 @@ impl FileWatcher for NotifyFileWatcher {
         )
         .await
     }
+
+    fn provider(&self) -> buck2_data::FileWatcherProvider {
+        buck2_data::FileWatcherProvider::RustNotify
+    }
+
+    fn pending_changes(&self) -> Option<u64> {
+        match &*self.data.lock().unwrap() {
+            Ok(data) => Some(data.events.len() as u64),
+            Err(_) => None,
+        }
+    }
 }
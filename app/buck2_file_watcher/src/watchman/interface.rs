@@ -15,6 +15,7 @@ use async_trait::async_trait;
 use buck2_common::dice::file_ops::FileChangeTracker;
 use buck2_common::ignores::ignore_set::IgnoreSet;
 use buck2_common::legacy_configs::configs::LegacyBuckConfig;
+use buck2_common::legacy_configs::existence_cache::ConfigFileExistenceCache;
 use buck2_common::legacy_configs::key::BuckconfigKeyRef;
 use buck2_core::cells::CellResolver;
 use buck2_core::cells::name::CellName;
@@ -306,6 +307,7 @@ impl SyncableQueryProcessor for WatchmanQueryProcessor {
         // are a lot of destructors to run. On the other hand, we don't have to wait for
         // it. So, we just send it off to its own thread.
         let ctx = ctx.unstable_take();
+        ConfigFileExistenceCache::process().invalidate_project_paths();
 
         let mut base_stats = buck2_data::FileWatcherStats {
             fresh_instance: true,
@@ -427,4 +429,8 @@ impl FileWatcher for WatchmanFileWatcher {
         )
         .await
     }
+
+    fn provider(&self) -> buck2_data::FileWatcherProvider {
+        buck2_data::FileWatcherProvider::Watchman
+    }
 }
@@ -81,6 +81,22 @@ impl HttpClient {
         self.request(req).await
     }
 
+    /// Send a GET request with extra headers.
+    pub async fn get_with_headers(
+        &self,
+        uri: &str,
+        headers: Vec<(String, String)>,
+    ) -> Result<Response<BoxStream<hyper::Result<Bytes>>>, HttpError> {
+        let mut builder = self.request_builder(uri).method(Method::GET);
+        for (name, value) in headers {
+            builder = builder.header(name, value);
+        }
+        let req = builder
+            .body(Bytes::new())
+            .map_err(HttpError::BuildRequest)?;
+        self.request(req).await
+    }
+
     pub async fn post(
         &self,
         uri: &str,
@@ -203,6 +203,7 @@ impl ModuleInternals {
                             package: Arc::new(Package {
                                 buildfile_path: self.buildfile_path.dupe(),
                                 oncall,
+                                visibility: self.super_package.visibility().dupe(),
                             }),
                             recorder: TargetsRecorder::new(),
                         });
@@ -9,8 +9,10 @@
 
 pub mod configured;
 pub mod configured_frontend;
+pub mod configured_graph_cycle_events;
 pub mod configured_node_ref;
 pub mod configured_node_visit_all_deps;
+pub mod configured_recompute_stats;
 pub mod configured_ref;
 pub mod eval_result;
 pub mod frontend;
@@ -73,6 +73,7 @@ use crate::nodes::unconfigured::TargetNode;
 use crate::provider_id_set::ProviderIdSet;
 use crate::rule_type::RuleType;
 use crate::rule_type::StarlarkRuleType;
+use crate::visibility::VisibilityDecision;
 
 /// ConfiguredTargetNode contains the information for a target in a particular configuration.
 ///
@@ -144,6 +145,13 @@ impl TargetNodeOrForward {
         }
     }
 
+    fn explain_visibility(&self, target: &TargetLabel) -> buck2_error::Result<VisibilityDecision> {
+        match self {
+            TargetNodeOrForward::TargetNode(node) => node.explain_visibility(target),
+            TargetNodeOrForward::Forward(_, forward) => forward.explain_visibility(target),
+        }
+    }
+
     fn oncall(&self) -> Option<&str> {
         match self {
             TargetNodeOrForward::TargetNode(node) => node.oncall(),
@@ -188,7 +196,8 @@ struct ConfiguredTargetNodeData {
     // and includes exec deps and configuration deps.
     // TODO(cjhopman): Should this be a diff against the node's deps?
     all_deps: ConfiguredTargetNodeDeps,
-    platform_cfgs: OrderedMap<TargetLabel, ConfigurationData>,
+    // Shared by all the nodes depending on the same platforms.
+    platform_cfgs: Arc<OrderedMap<TargetLabel, ConfigurationData>>,
     // TODO(JakobDegen): Consider saving some memory by using a more tset like representation of
     // the plugin lists
     plugin_lists: PluginLists,
@@ -229,7 +238,7 @@ impl ConfiguredTargetNode {
             execution_platform_resolution,
             Vec::new(),
             Vec::new(),
-            OrderedMap::new(),
+            Arc::new(OrderedMap::new()),
             PluginLists::new(),
         )
     }
@@ -242,7 +251,7 @@ impl ConfiguredTargetNode {
         execution_platform_resolution: ExecutionPlatformResolution,
         deps: Vec<ConfiguredTargetNode>,
         exec_deps: Vec<ConfiguredTargetNode>,
-        platform_cfgs: OrderedMap<TargetLabel, ConfigurationData>,
+        platform_cfgs: Arc<OrderedMap<TargetLabel, ConfigurationData>>,
         plugin_lists: PluginLists,
     ) -> Self {
         Self(triomphe::Arc::new(Hashed::new(ConfiguredTargetNodeData {
@@ -308,7 +317,7 @@ impl ConfiguredTargetNode {
                     .dupe(),
                 plugin_lists: transitioned_node.plugin_lists().clone(),
                 all_deps: ConfiguredTargetNodeDeps::new(vec![transitioned_node], vec![]),
-                platform_cfgs: OrderedMap::new(),
+                platform_cfgs: Arc::new(OrderedMap::new()),
             },
         ))))
     }
@@ -451,6 +460,13 @@ impl ConfiguredTargetNode {
         self.0.target_node.is_visible_to(target)
     }
 
+    pub fn explain_visibility(
+        &self,
+        target: &TargetLabel,
+    ) -> buck2_error::Result<VisibilityDecision> {
+        self.0.target_node.explain_visibility(target)
+    }
+
     #[inline]
     pub fn special_attr_or_none(&self, key: &str) -> Option<ConfiguredAttr> {
         self.as_ref().special_attr_or_none(key)
@@ -7,7 +7,10 @@
  * of this source tree.
  */
 
+use std::sync::Arc;
+
 use async_trait::async_trait;
+use buck2_core::configuration::compatibility::IncompatiblePlatformReason;
 use buck2_core::configuration::compatibility::MaybeCompatible;
 use buck2_core::target::configured_target_label::ConfiguredTargetLabel;
 use buck2_util::late_binding::LateBinding;
@@ -24,6 +27,14 @@ pub trait ConfiguredTargetNodeCalculationImpl: Send + Sync + 'static {
         target: &ConfiguredTargetLabel,
         check_dependency_incompatibility: bool,
     ) -> buck2_error::Result<MaybeCompatible<ConfiguredTargetNode>>;
+
+    /// Recomputes the root cause of an incompatibility with all the unsatisfied constraints
+    /// instead of only the first one.
+    async fn explain_incompatibility(
+        &self,
+        ctx: &mut DiceComputations<'_>,
+        reason: Arc<IncompatiblePlatformReason>,
+    ) -> buck2_error::Result<Arc<IncompatiblePlatformReason>>;
 }
 
 pub static CONFIGURED_TARGET_NODE_CALCULATION: LateBinding<
@@ -47,6 +58,14 @@ pub trait ConfiguredTargetNodeCalculation {
         &mut self,
         target: &ConfiguredTargetLabel,
     ) -> buck2_error::Result<MaybeCompatible<ConfiguredTargetNode>>;
+
+    /// Same as `get_configured_target_node` except that when the target is incompatible, the
+    /// reason lists all the constraints it doesn't satisfy, not just the first one. This is
+    /// slower, use it to explain incompatibility to users.
+    async fn get_configured_target_node_explaining_incompatibility(
+        &mut self,
+        target: &ConfiguredTargetLabel,
+    ) -> buck2_error::Result<MaybeCompatible<ConfiguredTargetNode>>;
 }
 
 #[async_trait]
@@ -70,4 +89,20 @@ impl ConfiguredTargetNodeCalculation for DiceComputations<'_> {
             .get_configured_target_node(self, target, false)
             .await
     }
+
+    async fn get_configured_target_node_explaining_incompatibility(
+        &mut self,
+        target: &ConfiguredTargetLabel,
+    ) -> buck2_error::Result<MaybeCompatible<ConfiguredTargetNode>> {
+        let calculation = CONFIGURED_TARGET_NODE_CALCULATION.get()?;
+        match calculation
+            .get_configured_target_node(self, target, true)
+            .await?
+        {
+            MaybeCompatible::Incompatible(reason) => Ok(MaybeCompatible::Incompatible(
+                calculation.explain_incompatibility(self, reason).await?,
+            )),
+            compatible => Ok(compatible),
+        }
+    }
 }
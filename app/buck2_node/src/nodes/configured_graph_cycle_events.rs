@@ -0,0 +1,166 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Events for cycles detected in the configured target graph.
+//!
+//! Cycle errors only show up in the error of the command that hit them. These events make it
+//! possible to measure how often cycles happen, and on which targets, from event logs.
+
+use std::hash::DefaultHasher;
+use std::hash::Hash;
+use std::hash::Hasher;
+use std::sync::Arc;
+use std::sync::OnceLock;
+
+use buck2_events::dispatch::EventDispatcher;
+use dashmap::DashSet;
+use dice::UserComputationData;
+use dupe::Dupe;
+
+/// Participants reported in an event, longer cycles are truncated.
+const MAX_REPORTED_PARTICIPANTS: usize = 20;
+
+/// Reports the configured graph cycles detected in a command, once per distinct cycle.
+#[derive(Default)]
+pub struct ConfiguredGraphCycleEvents {
+    top_level_targets: OnceLock<Vec<String>>,
+    /// Hashes of the sorted participants of the cycles reported so far. Every key in a cycle may
+    /// detect it, each starting the cycle at itself.
+    reported: DashSet<u64>,
+}
+
+impl ConfiguredGraphCycleEvents {
+    /// Records the target patterns the command was run with. Only the first call has an effect.
+    pub fn set_top_level_targets(&self, targets: Vec<String>) {
+        let _ignored = self.top_level_targets.set(targets);
+    }
+
+    /// Dispatches a `ConfiguredGraphCycleDetected` event for the cycle formed by `participants`,
+    /// unless this cycle was already reported.
+    pub fn report(&self, dispatcher: &EventDispatcher, participants: Vec<String>) {
+        let mut sorted: Vec<&String> = participants.iter().collect();
+        sorted.sort();
+        let mut hasher = DefaultHasher::new();
+        sorted.hash(&mut hasher);
+        if !self.reported.insert(hasher.finish()) {
+            return;
+        }
+
+        dispatcher.instant_event(buck2_data::ConfiguredGraphCycleDetected {
+            cycle_length: participants.len() as u64,
+            participants: participants
+                .into_iter()
+                .take(MAX_REPORTED_PARTICIPANTS)
+                .collect(),
+            top_level_targets: self.top_level_targets.get().cloned().unwrap_or_default(),
+        });
+    }
+}
+
+struct ConfiguredGraphCycleEventsHolder(Arc<ConfiguredGraphCycleEvents>);
+
+pub trait HasConfiguredGraphCycleEvents {
+    fn set_configured_graph_cycle_events(&mut self, events: Arc<ConfiguredGraphCycleEvents>);
+
+    /// Returns `None` if the events aren't set, e.g. outside of commands.
+    fn get_configured_graph_cycle_events(&self) -> Option<Arc<ConfiguredGraphCycleEvents>>;
+}
+
+impl HasConfiguredGraphCycleEvents for UserComputationData {
+    fn set_configured_graph_cycle_events(&mut self, events: Arc<ConfiguredGraphCycleEvents>) {
+        self.data.set(ConfiguredGraphCycleEventsHolder(events));
+    }
+
+    fn get_configured_graph_cycle_events(&self) -> Option<Arc<ConfiguredGraphCycleEvents>> {
+        self.data
+            .get::<ConfiguredGraphCycleEventsHolder>()
+            .ok()
+            .map(|holder| holder.0.dupe())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use buck2_events::source::ChannelEventSource;
+    use buck2_wrapper_common::invocation_id::TraceId;
+
+    use super::*;
+
+    fn reported(events: &mut ChannelEventSource) -> Vec<buck2_data::ConfiguredGraphCycleDetected> {
+        let mut res = Vec::new();
+        while let Some(event) = events.try_receive() {
+            if let buck2_data::buck_event::Data::Instant(instant) =
+                event.unpack_buck().unwrap().data()
+            {
+                if let Some(buck2_data::instant_event::Data::ConfiguredGraphCycleDetected(cycle)) =
+                    &instant.data
+                {
+                    res.push(cycle.clone());
+                }
+            }
+        }
+        res
+    }
+
+    #[test]
+    fn test_reported_once_per_cycle() {
+        let (mut source, sink) = buck2_events::create_source_sink_pair();
+        let dispatcher = EventDispatcher::new(TraceId::null(), sink);
+        let events = ConfiguredGraphCycleEvents::default();
+        events.set_top_level_targets(vec!["root//:top".to_owned()]);
+
+        // Both keys of the cycle detect it.
+        events.report(
+            &dispatcher,
+            vec!["root//:a".to_owned(), "root//:b".to_owned()],
+        );
+        events.report(
+            &dispatcher,
+            vec!["root//:b".to_owned(), "root//:a".to_owned()],
+        );
+        // A different cycle through one of them.
+        events.report(
+            &dispatcher,
+            vec!["root//:a".to_owned(), "root//:c".to_owned()],
+        );
+
+        assert_eq!(
+            vec![
+                buck2_data::ConfiguredGraphCycleDetected {
+                    participants: vec!["root//:a".to_owned(), "root//:b".to_owned()],
+                    cycle_length: 2,
+                    top_level_targets: vec!["root//:top".to_owned()],
+                },
+                buck2_data::ConfiguredGraphCycleDetected {
+                    participants: vec!["root//:a".to_owned(), "root//:c".to_owned()],
+                    cycle_length: 2,
+                    top_level_targets: vec!["root//:top".to_owned()],
+                },
+            ],
+            reported(&mut source)
+        );
+    }
+
+    #[test]
+    fn test_long_cycle_truncated() {
+        let (mut source, sink) = buck2_events::create_source_sink_pair();
+        let dispatcher = EventDispatcher::new(TraceId::null(), sink);
+        let participants: Vec<String> = (0..50).map(|i| format!("root//:t{}", i)).collect();
+        ConfiguredGraphCycleEvents::default().report(&dispatcher, participants.clone());
+
+        let reported = reported(&mut source);
+        assert_eq!(1, reported.len());
+        assert_eq!(50, reported[0].cycle_length);
+        assert_eq!(
+            &participants[..MAX_REPORTED_PARTICIPANTS],
+            &reported[0].participants[..]
+        );
+        assert!(reported[0].top_level_targets.is_empty());
+    }
+}
@@ -0,0 +1,109 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Instrumentation for how often recomputing a configured target node produces the same value
+//! as before, i.e. how much work DICE early cutoff on `ConfiguredTargetNodeKey` is throwing away.
+//!
+//! DICE doesn't expose the result of `Key::equality` to users, so the computation peeks at the
+//! previous value DICE still holds for the key and compares it with the new one itself. Nothing
+//! is kept across commands besides the daemon totals.
+
+use std::sync::Arc;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use allocative::Allocative;
+use dice::UserComputationData;
+use dupe::Dupe;
+
+#[derive(Default, Allocative)]
+struct RecomputeCounters {
+    recomputed: AtomicU64,
+    recomputed_equal: AtomicU64,
+    recomputed_equal_duration_us: AtomicU64,
+}
+
+impl RecomputeCounters {
+    const fn new() -> Self {
+        Self {
+            recomputed: AtomicU64::new(0),
+            recomputed_equal: AtomicU64::new(0),
+            recomputed_equal_duration_us: AtomicU64::new(0),
+        }
+    }
+
+    fn record(&self, equal: bool, duration: Duration) {
+        self.recomputed.fetch_add(1, Ordering::Relaxed);
+        if equal {
+            self.recomputed_equal.fetch_add(1, Ordering::Relaxed);
+            self.recomputed_equal_duration_us
+                .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+        }
+    }
+
+    fn to_proto(&self) -> buck2_data::ConfiguredNodeRecomputeStats {
+        buck2_data::ConfiguredNodeRecomputeStats {
+            recomputed: self.recomputed.load(Ordering::Relaxed),
+            recomputed_equal: self.recomputed_equal.load(Ordering::Relaxed),
+            recomputed_equal_duration_us: self.recomputed_equal_duration_us.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Totals across all commands, reported in snapshots so that deltas can be computed client-side.
+static DAEMON_TOTALS: RecomputeCounters = RecomputeCounters::new();
+
+pub fn add_configured_node_recompute_snapshot_stats(snapshot: &mut buck2_data::Snapshot) {
+    let totals = DAEMON_TOTALS.to_proto();
+    snapshot.configured_node_recomputed = totals.recomputed;
+    snapshot.configured_node_recomputed_equal = totals.recomputed_equal;
+    snapshot.configured_node_recomputed_equal_duration_us = totals.recomputed_equal_duration_us;
+}
+
+/// Per-command recompute counters for configured target nodes.
+#[derive(Default, Allocative)]
+pub struct ConfiguredNodeRecomputeStats(RecomputeCounters);
+
+impl ConfiguredNodeRecomputeStats {
+    pub fn to_proto(&self) -> buck2_data::ConfiguredNodeRecomputeStats {
+        self.0.to_proto()
+    }
+
+    /// Record a recomputation of a configured target node that had a previous value, and whether
+    /// the new value is `equal` to it.
+    pub fn record_recompute(&self, equal: bool, duration: Duration) {
+        self.0.record(equal, duration);
+        DAEMON_TOTALS.record(equal, duration);
+    }
+}
+
+struct ConfiguredNodeRecomputeStatsHolder(Option<Arc<ConfiguredNodeRecomputeStats>>);
+
+pub trait HasConfiguredNodeRecomputeStats {
+    fn set_configured_node_recompute_stats(&mut self, enabled: bool);
+
+    /// Returns `None` if the stats are disabled for this command.
+    fn get_configured_node_recompute_stats(&self) -> Option<Arc<ConfiguredNodeRecomputeStats>>;
+}
+
+impl HasConfiguredNodeRecomputeStats for UserComputationData {
+    fn set_configured_node_recompute_stats(&mut self, enabled: bool) {
+        self.data.set(ConfiguredNodeRecomputeStatsHolder(
+            enabled.then(|| Arc::new(ConfiguredNodeRecomputeStats::default())),
+        ));
+    }
+
+    fn get_configured_node_recompute_stats(&self) -> Option<Arc<ConfiguredNodeRecomputeStats>> {
+        self.data
+            .get::<ConfiguredNodeRecomputeStatsHolder>()
+            .ok()
+            .and_then(|holder| holder.0.dupe())
+    }
+}
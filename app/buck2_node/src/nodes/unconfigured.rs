@@ -55,6 +55,8 @@ use crate::nodes::attributes::TYPE;
 use crate::package::Package;
 use crate::rule::Rule;
 use crate::rule_type::RuleType;
+use crate::visibility::VisibilityDecision;
+use crate::visibility::VisibilitySource;
 use crate::visibility::VisibilitySpecification;
 
 /// Describes a target including its name, type, and the values that the user provided.
@@ -269,6 +271,20 @@ impl TargetNode {
         Ok(self.visibility()?.0.matches_target(target))
     }
 
+    /// Like `is_visible_to`, but also explains which pattern allowed `target` to see this target,
+    /// or which ones didn't.
+    pub fn explain_visibility(
+        &self,
+        target: &TargetLabel,
+    ) -> buck2_error::Result<VisibilityDecision> {
+        if self.label().pkg() == target.pkg() {
+            return Ok(VisibilityDecision::SamePackage);
+        }
+        let visibility = self.visibility()?;
+        let source = VisibilitySource::of(visibility, &self.0.package.visibility);
+        Ok(visibility.explain(target, source))
+    }
+
     /// Returns an iterator of all attributes.
     ///
     /// "attribute" here is a user defined attribute, not including "special" attributes.
@@ -695,6 +711,7 @@ pub mod testing {
                 Arc::new(Package {
                     buildfile_path,
                     oncall: None,
+                    visibility: VisibilitySpecification::DEFAULT,
                 }),
                 label,
                 attributes,
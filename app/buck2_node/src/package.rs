@@ -13,6 +13,7 @@ use allocative::Allocative;
 use buck2_core::build_file_path::BuildFilePath;
 
 use crate::oncall::Oncall;
+use crate::visibility::VisibilitySpecification;
 
 /// Package-specific data for `TargetNode`.
 ///
@@ -24,4 +25,6 @@ pub struct Package {
     pub buildfile_path: Arc<BuildFilePath>,
     /// The oncall attribute, if set
     pub oncall: Option<Oncall>,
+    /// The visibility from `PACKAGE` files, which targets without a `visibility` get.
+    pub visibility: VisibilitySpecification,
 }
@@ -24,10 +24,130 @@ use crate::attrs::attr_type::any_matches::AnyMatches;
 #[derive(Debug, buck2_error::Error)]
 pub enum VisibilityError {
     #[error(
-        "`{0}` is not visible to `{1}` (run `buck2 uquery --output-attribute visibility {0}` to check the visibility)"
+        "`{0}` is not visible to `{1}`{2} (run `buck2 uquery --output-attribute visibility {0}` to check the visibility)"
     )]
     #[buck2(input, tag = Visibility)]
-    NotVisibleTo(TargetLabel, TargetLabel),
+    NotVisibleTo(TargetLabel, TargetLabel, NearestMiss),
+}
+
+impl VisibilityError {
+    /// `dep` is not visible to `target`, as explained by `decision`.
+    pub fn not_visible_to(
+        dep: TargetLabel,
+        target: TargetLabel,
+        decision: &VisibilityDecision,
+    ) -> VisibilityError {
+        let nearest_miss = match decision {
+            VisibilityDecision::Denied {
+                nearest_miss: Some(pattern),
+                source,
+                ..
+            } => NearestMiss(Some((pattern.clone(), *source))),
+            _ => NearestMiss(None),
+        };
+        VisibilityError::NotVisibleTo(dep, target, nearest_miss)
+    }
+}
+
+/// The pattern of a denied visibility that came closest to matching, if any.
+#[derive(Debug, Default)]
+pub struct NearestMiss(Option<(VisibilityPattern, VisibilitySource)>);
+
+impl Display for NearestMiss {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match &self.0 {
+            Some((pattern, source)) => {
+                write!(f, ": closest pattern is `{}` in the {}", pattern, source)
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+/// Where the visibility of a target comes from.
+#[derive(Debug, Clone, Copy, Dupe, PartialEq, Eq, derive_more::Display)]
+pub enum VisibilitySource {
+    #[display("`visibility` attribute")]
+    TargetAttr,
+    #[display("package default visibility (from `PACKAGE` files)")]
+    PackageDefault,
+}
+
+impl VisibilitySource {
+    /// The source of `visibility`, the visibility of a target in a package whose `PACKAGE` files
+    /// set `package_visibility`. A `visibility` attribute that is the same as the package default
+    /// is indistinguishable from it, and reported as the package default.
+    pub fn of(
+        visibility: &VisibilitySpecification,
+        package_visibility: &VisibilitySpecification,
+    ) -> VisibilitySource {
+        if visibility == package_visibility {
+            VisibilitySource::PackageDefault
+        } else {
+            VisibilitySource::TargetAttr
+        }
+    }
+}
+
+/// Why a target is or isn't visible to another, see `TargetNode::explain_visibility`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VisibilityDecision {
+    /// Targets in the same package always see each other.
+    SamePackage,
+    Allowed {
+        /// The pattern that matched, or `None` if the visibility is `PUBLIC`.
+        pattern: Option<VisibilityPattern>,
+        source: VisibilitySource,
+    },
+    Denied {
+        /// All the patterns, none of which matched.
+        patterns: Vec<VisibilityPattern>,
+        /// The pattern closest to matching: the one in the same cell sharing the most leading
+        /// path components with the package of the target that can't see. `None` if no pattern
+        /// shares any.
+        nearest_miss: Option<VisibilityPattern>,
+        source: VisibilitySource,
+    },
+}
+
+impl VisibilityDecision {
+    pub fn is_visible(&self) -> bool {
+        !matches!(self, VisibilityDecision::Denied { .. })
+    }
+}
+
+impl Display for VisibilityDecision {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            VisibilityDecision::SamePackage => write!(f, "visible: same package"),
+            VisibilityDecision::Allowed { pattern, source } => match pattern {
+                Some(pattern) => write!(f, "visible: matches `{}` in the {}", pattern, source),
+                None => write!(
+                    f,
+                    "visible: `{}` in the {}",
+                    VisibilityPattern::PUBLIC,
+                    source
+                ),
+            },
+            VisibilityDecision::Denied {
+                patterns,
+                nearest_miss,
+                source,
+            } => {
+                write!(f, "not visible: no pattern in the {} matches ", source)?;
+                display_container::fmt_container(
+                    f,
+                    "[",
+                    "]",
+                    patterns.iter().map(VisibilityPatternQuoted),
+                )?;
+                match nearest_miss {
+                    Some(nearest_miss) => write!(f, ", closest pattern is `{}`", nearest_miss),
+                    None => write!(f, ", no close match"),
+                }
+            }
+        }
+    }
 }
 
 #[derive(Debug, Eq, PartialEq, Hash, Clone, Allocative, derive_more::Display)]
@@ -36,6 +156,26 @@ pub struct VisibilityPattern(pub ParsedPattern<TargetPatternExtra>);
 impl VisibilityPattern {
     pub const PUBLIC: &'static str = "PUBLIC";
 
+    /// Number of leading path components shared with the package of `target`, or `None` if they
+    /// are in different cells.
+    fn shared_path_len(&self, target: &TargetLabel) -> Option<usize> {
+        let path = match &self.0 {
+            ParsedPattern::Target(pkg, _, _) | ParsedPattern::Package(pkg) => pkg.as_cell_path(),
+            ParsedPattern::Recursive(path) => path.as_ref(),
+        };
+        let target_path = target.pkg().as_cell_path();
+        if path.cell() != target_path.cell() {
+            return None;
+        }
+        Some(
+            path.path()
+                .iter()
+                .zip(target_path.path().iter())
+                .take_while(|(a, b)| a == b)
+                .count(),
+        )
+    }
+
     pub fn testing_new(pattern: &str) -> VisibilityPattern {
         VisibilityPattern(ParsedPattern::testing_parse(pattern))
     }
@@ -95,6 +235,38 @@ impl VisibilityPatternList {
         }
     }
 
+    fn explain(&self, target: &TargetLabel, source: VisibilitySource) -> VisibilityDecision {
+        let patterns = match self {
+            VisibilityPatternList::Public => {
+                return VisibilityDecision::Allowed {
+                    pattern: None,
+                    source,
+                };
+            }
+            VisibilityPatternList::List(patterns) => patterns,
+        };
+        if let Some(pattern) = patterns.iter().find(|p| p.0.matches(target)) {
+            return VisibilityDecision::Allowed {
+                pattern: Some(pattern.clone()),
+                source,
+            };
+        }
+        let mut nearest_miss: Option<(&VisibilityPattern, usize)> = None;
+        for pattern in patterns {
+            if let Some(len) = pattern.shared_path_len(target) {
+                // A pattern sharing no path component isn't close to matching.
+                if len > 0 && nearest_miss.is_none_or(|(_, nearest_len)| len > nearest_len) {
+                    nearest_miss = Some((pattern, len));
+                }
+            }
+        }
+        VisibilityDecision::Denied {
+            patterns: patterns.to_vec(),
+            nearest_miss: nearest_miss.map(|(pattern, _)| pattern.clone()),
+            source,
+        }
+    }
+
     pub fn matches_target(&self, target: &TargetLabel) -> bool {
         match self {
             VisibilityPatternList::Public => true,
@@ -175,6 +347,12 @@ impl VisibilitySpecification {
         VisibilitySpecification(self.0.extend_with(&other.0))
     }
 
+    /// Why this visibility, coming from `source`, does or doesn't allow `target` to see the
+    /// target it belongs to. This doesn't take the package of either target into account.
+    pub fn explain(&self, target: &TargetLabel, source: VisibilitySource) -> VisibilityDecision {
+        self.0.explain(target, source)
+    }
+
     pub fn testing_parse(patterns: &[&str]) -> VisibilitySpecification {
         VisibilitySpecification(VisibilityPatternList::testing_parse(patterns))
     }
@@ -271,3 +449,113 @@ impl VisibilityWithinViewBuilder {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pattern(pattern: &str) -> VisibilityPattern {
+        VisibilityPattern::testing_new(pattern)
+    }
+
+    #[test]
+    fn test_explain_attr_allow() {
+        let visibility =
+            VisibilitySpecification::testing_parse(&["root//foo:bar", "root//baz/..."]);
+        assert_eq!(
+            VisibilityDecision::Allowed {
+                pattern: Some(pattern("root//baz/...")),
+                source: VisibilitySource::TargetAttr,
+            },
+            visibility.explain(
+                &TargetLabel::testing_parse("root//baz/qux:x"),
+                VisibilitySource::TargetAttr
+            )
+        );
+    }
+
+    #[test]
+    fn test_explain_package_default_allow() {
+        let package_visibility = VisibilitySpecification::testing_parse(&["PUBLIC"]);
+        let source = VisibilitySource::of(&package_visibility, &package_visibility);
+        assert_eq!(VisibilitySource::PackageDefault, source);
+        assert_eq!(
+            VisibilitySource::TargetAttr,
+            VisibilitySource::of(
+                &VisibilitySpecification::testing_parse(&["root//foo:bar"]),
+                &package_visibility
+            )
+        );
+
+        let decision =
+            package_visibility.explain(&TargetLabel::testing_parse("root//foo:bar"), source);
+        assert_eq!(
+            VisibilityDecision::Allowed {
+                pattern: None,
+                source: VisibilitySource::PackageDefault,
+            },
+            decision
+        );
+        assert_eq!(
+            "visible: `PUBLIC` in the package default visibility (from `PACKAGE` files)",
+            decision.to_string()
+        );
+    }
+
+    #[test]
+    fn test_explain_deny_nearest_miss() {
+        let visibility = VisibilitySpecification::testing_parse(&[
+            "other//foo/baz/...",
+            "root//a:",
+            "root//foo/bar/...",
+            "root//foo:x",
+        ]);
+        let dep = TargetLabel::testing_parse("root//dep:dep");
+        let target = TargetLabel::testing_parse("root//foo/baz:t");
+
+        let decision = visibility.explain(&target, VisibilitySource::TargetAttr);
+        assert!(!decision.is_visible());
+        assert_eq!(
+            VisibilityDecision::Denied {
+                patterns: vec![
+                    pattern("other//foo/baz/..."),
+                    pattern("root//a:"),
+                    pattern("root//foo/bar/..."),
+                    pattern("root//foo:x"),
+                ],
+                // Different cells never match, and ties go to the first pattern.
+                nearest_miss: Some(pattern("root//foo/bar/...")),
+                source: VisibilitySource::TargetAttr,
+            },
+            decision
+        );
+        assert_eq!(
+            "`root//dep:dep` is not visible to `root//foo/baz:t`: closest pattern is \
+            `root//foo/bar/...` in the `visibility` attribute (run `buck2 uquery \
+            --output-attribute visibility root//dep:dep` to check the visibility)",
+            VisibilityError::not_visible_to(dep.dupe(), target.dupe(), &decision).to_string()
+        );
+
+        for patterns in [&["other//foo/..."][..], &["root//a/...", "root//:x"]] {
+            let decision = VisibilitySpecification::testing_parse(patterns)
+                .explain(&target, VisibilitySource::TargetAttr);
+            assert!(matches!(
+                decision,
+                VisibilityDecision::Denied {
+                    nearest_miss: None,
+                    ..
+                }
+            ));
+            assert!(
+                decision.to_string().ends_with(", no close match"),
+                "{}",
+                decision
+            );
+            assert_eq!(
+                "`root//dep:dep` is not visible to `root//foo/baz:t` (run `buck2 uquery \
+                --output-attribute visibility root//dep:dep` to check the visibility)",
+                VisibilityError::not_visible_to(dep.dupe(), target.dupe(), &decision).to_string()
+            );
+        }
+    }
+}
@@ -283,10 +283,11 @@ impl CqueryDelegate for DiceQueryDelegate<'_, '_> {
         &self,
         target: &ConfiguredTargetLabel,
     ) -> buck2_error::Result<ConfiguredTargetNode> {
+        // Incompatibility is an error here, so it's worth listing all the unsatisfied constraints.
         Ok(self
             .ctx
             .get()
-            .get_configured_target_node(target)
+            .get_configured_target_node_explaining_incompatibility(target)
             .await?
             .require_compatible()?)
     }
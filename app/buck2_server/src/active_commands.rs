@@ -10,6 +10,8 @@
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::sync::Arc;
+use std::time::Duration;
+use std::time::SystemTime;
 
 use buck2_event_observer::dice_state::DiceState;
 use buck2_event_observer::pending_estimate::pending_estimate;
@@ -19,16 +21,28 @@ use buck2_event_observer::span_tracker::Roots;
 use buck2_events::BuckEvent;
 use buck2_events::dispatch::EventDispatcher;
 use buck2_events::span::SpanId;
+use buck2_util::truncate::truncate;
 use buck2_wrapper_common::invocation_id::TraceId;
 use dupe::Dupe;
 use once_cell::sync::Lazy;
 use parking_lot::Mutex;
 use parking_lot::MutexGuard;
+use tokio::sync::Notify;
 use tokio::sync::oneshot;
 
 static ACTIVE_COMMANDS: Lazy<Mutex<HashMap<TraceId, ActiveCommandHandle>>> =
     Lazy::new(|| Mutex::new(HashMap::new()));
 
+const MAX_ARGV_SUMMARY_LENGTH: usize = 200;
+
+/// Notified whenever the last active command finishes.
+static NO_ACTIVE_COMMANDS: Lazy<Notify> = Lazy::new(Notify::new);
+
+/// Held by tests that register active commands, since they all share `ACTIVE_COMMANDS`.
+#[cfg(test)]
+pub(crate) static ACTIVE_COMMANDS_TEST_LOCK: tokio::sync::Mutex<()> =
+    tokio::sync::Mutex::const_new(());
+
 /// Return the active commands, if you can access them.
 pub fn try_active_commands() -> Option<HashMap<TraceId, ActiveCommandHandle>> {
     // Note that this function is accessed during panic, so have to be super careful
@@ -53,6 +67,24 @@ pub fn broadcast_instant_event<E: Into<buck2_data::instant_event::Data> + Clone>
     has_subscribers
 }
 
+/// Wait until there are no active commands, for at most `timeout`. Returns whether they drained.
+pub async fn wait_for_active_commands_to_drain(timeout: Duration) -> bool {
+    tokio::time::timeout(timeout, async {
+        loop {
+            let drained = NO_ACTIVE_COMMANDS.notified();
+            tokio::pin!(drained);
+            // Register before checking, so that a command finishing in between isn't missed.
+            drained.as_mut().enable();
+            if ACTIVE_COMMANDS.lock().is_empty() {
+                return;
+            }
+            drained.await;
+        }
+    })
+    .await
+    .is_ok()
+}
+
 pub fn broadcast_shutdown(shutdown: &buck2_data::DaemonShutdown) {
     for cmd in ACTIVE_COMMANDS.lock().values() {
         cmd.notify_shutdown(shutdown.clone());
@@ -93,15 +125,24 @@ pub struct ActiveCommandDropGuard {
 
 impl Drop for ActiveCommandDropGuard {
     fn drop(&mut self) {
-        ACTIVE_COMMANDS.lock().remove(&self.trace_id);
+        let mut active_commands = ACTIVE_COMMANDS.lock();
+        active_commands.remove(&self.trace_id);
+        if active_commands.is_empty() {
+            NO_ACTIVE_COMMANDS.notify_waiters();
+        }
     }
 }
 
 /// A handle to the stats for this command. We use this to broadcast state about this command.
 pub struct ActiveCommandState {
-    #[allow(unused)]
     pub argv: Vec<String>,
 
+    /// The name of the command, as reported by the client.
+    pub command_name: String,
+
+    /// When this command was registered.
+    pub start_time: SystemTime,
+
     spans: Mutex<SpansSnapshot>,
 }
 
@@ -110,9 +151,16 @@ impl ActiveCommandState {
         *self.spans.lock()
     }
 
-    fn new(argv: Vec<String>) -> Self {
+    /// The argv joined into a single line, with the middle elided if it's long.
+    pub fn argv_summary(&self) -> String {
+        truncate(&self.argv.join(" "), MAX_ARGV_SUMMARY_LENGTH)
+    }
+
+    fn new(argv: Vec<String>, command_name: String) -> Self {
         Self {
             argv,
+            command_name,
+            start_time: SystemTime::now(),
             spans: Mutex::new(SpansSnapshot::default()),
         }
     }
@@ -221,10 +269,14 @@ pub struct ActiveCommand {
 }
 
 impl ActiveCommand {
-    pub fn new(event_dispatcher: &EventDispatcher, sanitized_argv: Vec<String>) -> Self {
+    pub fn new(
+        event_dispatcher: &EventDispatcher,
+        sanitized_argv: Vec<String>,
+        command_name: String,
+    ) -> Self {
         let (sender, receiver) = oneshot::channel();
 
-        let state = Arc::new(ActiveCommandState::new(sanitized_argv));
+        let state = Arc::new(ActiveCommandState::new(sanitized_argv, command_name));
 
         let trace_id = event_dispatcher.trace_id().dupe();
         let result = {
@@ -284,8 +336,10 @@ mod tests {
 
     #[test]
     fn test_active_command_state() {
-        let mut writer =
-            ActiveCommandStateWriter::new(Arc::new(ActiveCommandState::new(Vec::new())));
+        let mut writer = ActiveCommandStateWriter::new(Arc::new(ActiveCommandState::new(
+            Vec::new(),
+            String::new(),
+        )));
 
         let root = SpanId::next();
         let child = SpanId::next();
@@ -444,17 +498,19 @@ mod tests {
 
     #[test]
     fn test_multiple_active_commands() {
+        let _lock = ACTIVE_COMMANDS_TEST_LOCK.blocking_lock();
+
         let (dispatcher1, mut source1, id1) = create_dispatcher();
-        let _active1 = ActiveCommand::new(&dispatcher1, Vec::new());
+        let _active1 = ActiveCommand::new(&dispatcher1, Vec::new(), String::new());
 
         let (dispatcher2, mut source2, id2) = create_dispatcher();
-        let _active2 = ActiveCommand::new(&dispatcher2, Vec::new());
+        let _active2 = ActiveCommand::new(&dispatcher2, Vec::new(), String::new());
 
         check_concurrent_command_trace_ids_eq(source1.try_receive(), &[id2.to_string()]);
         check_concurrent_command_trace_ids_eq(source2.try_receive(), &[id1.to_string()]);
 
         let (dispatcher3, mut source3, id3) = create_dispatcher();
-        let _active3 = ActiveCommand::new(&dispatcher3, Vec::new());
+        let _active3 = ActiveCommand::new(&dispatcher3, Vec::new(), String::new());
 
         check_concurrent_command_trace_ids_eq(source1.try_receive(), &[id3.to_string()]);
         check_concurrent_command_trace_ids_eq(source2.try_receive(), &[id3.to_string()]);
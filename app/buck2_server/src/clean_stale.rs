@@ -8,6 +8,9 @@
  */
 
 use async_trait::async_trait;
+use buck2_common::dice::cells::HasCellResolver;
+use buck2_core::cells::CellResolver;
+use buck2_core::fs::project_rel_path::ProjectRelativePathBuf;
 use buck2_error::BuckErrorContext;
 use buck2_server_ctx::ctx::ServerCommandContextTrait;
 use buck2_server_ctx::partial_result_dispatcher::NoPartialResult;
@@ -20,6 +23,13 @@ use dice::DiceTransaction;
 
 use crate::ctx::ServerCommandContext;
 
+#[derive(Debug, buck2_error::Error)]
+#[buck2(tag = Input)]
+enum CleanStaleCommandError {
+    #[error("Unknown cell `{0}`")]
+    UnknownCell(String),
+}
+
 pub(crate) async fn clean_stale_command(
     ctx: &ServerCommandContext<'_>,
     partial_result_dispatcher: PartialResultDispatcher<NoPartialResult>,
@@ -48,8 +58,10 @@ impl ServerCommandTemplate for CleanStaleServerCommand {
         &self,
         server_ctx: &dyn ServerCommandContextTrait,
         _partial_result_dispatcher: PartialResultDispatcher<Self::PartialResult>,
-        _ctx: DiceTransaction,
+        mut ctx: DiceTransaction,
     ) -> buck2_error::Result<Self::Response> {
+        check_cells(&ctx.get_cell_resolver().await?, &self.req.cells)?;
+
         server_ctx
             .cancellation_context()
             .critical_section(|| async move {
@@ -64,8 +76,26 @@ impl ServerCommandTemplate for CleanStaleServerCommand {
                     .single()
                     .buck_error_context("Invalid timestamp")?;
 
+                let keep_paths = self
+                    .req
+                    .keep_paths
+                    .iter()
+                    .map(|path| {
+                        ProjectRelativePathBuf::try_from(path.clone())
+                            .with_buck_error_context(|| format!("Invalid keep path `{}`", path))
+                    })
+                    .collect::<buck2_error::Result<Vec<_>>>()?;
+
                 extension
-                    .clean_stale_artifacts(keep_since_time, self.req.dry_run, self.req.tracked_only)
+                    .clean_stale_artifacts(
+                        keep_since_time,
+                        self.req.dry_run,
+                        self.req.tracked_only,
+                        self.req.cells.clone(),
+                        self.req.max_bytes_per_run,
+                        self.req.max_files_per_run,
+                        keep_paths,
+                    )
                     .await
                     .buck_error_context("Failed to clean stale artifacts.")
             })
@@ -86,3 +116,49 @@ impl ServerCommandTemplate for CleanStaleServerCommand {
         buck2_data::CleanCommandEnd { clean_stale_stats }
     }
 }
+
+/// Artifacts are stored in buck-out under the name of the cell that owns them, so that's what
+/// `--cell` takes.
+fn check_cells(cell_resolver: &CellResolver, cells: &[String]) -> buck2_error::Result<()> {
+    for cell in cells {
+        if !cell_resolver
+            .cells()
+            .any(|(name, _)| name.as_str() == cell.as_str())
+        {
+            return Err(CleanStaleCommandError::UnknownCell(cell.clone()).into());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use buck2_core::cells::CellResolver;
+    use buck2_core::cells::cell_root_path::CellRootPathBuf;
+    use buck2_core::cells::name::CellName;
+
+    use super::check_cells;
+
+    #[test]
+    fn test_check_cells() {
+        let cell_resolver = CellResolver::testing_with_names_and_paths(&[
+            (
+                CellName::testing_new("root"),
+                CellRootPathBuf::testing_new(""),
+            ),
+            (
+                CellName::testing_new("foo"),
+                CellRootPathBuf::testing_new("foo"),
+            ),
+        ]);
+
+        assert!(check_cells(&cell_resolver, &[]).is_ok());
+        assert!(check_cells(&cell_resolver, &["root".to_owned(), "foo".to_owned()]).is_ok());
+        assert_eq!(
+            check_cells(&cell_resolver, &["foo".to_owned(), "bar".to_owned()])
+                .unwrap_err()
+                .to_string(),
+            "Unknown cell `bar`"
+        );
+    }
+}
@@ -50,6 +50,7 @@ use buck2_common::io::trace::TracingIoProvider;
 use buck2_common::legacy_configs::cells::BuckConfigBasedCells;
 use buck2_common::legacy_configs::configs::LegacyBuckConfig;
 use buck2_common::legacy_configs::dice::HasInjectedLegacyConfigs;
+use buck2_common::legacy_configs::existence_cache::ConfigFileExistenceCache;
 use buck2_common::legacy_configs::file_ops::ConfigPath;
 use buck2_common::legacy_configs::key::BuckconfigKeyRef;
 use buck2_configured::cycle::ConfiguredGraphCycleDescriptor;
@@ -81,6 +82,7 @@ use buck2_execute::re::manager::ReConnectionHandle;
 use buck2_execute::re::manager::ReConnectionObserver;
 use buck2_execute_impl::executors::worker::WorkerPool;
 use buck2_execute_impl::low_pass_filter::LowPassFilter;
+use buck2_file_watcher::file_watcher::FileWatcher;
 use buck2_file_watcher::mergebase::SetMergebase;
 use buck2_futures::cancellation::CancellationContext;
 use buck2_interpreter::dice::starlark_debug::SetStarlarkDebugger;
@@ -92,6 +94,9 @@ use buck2_interpreter::starlark_profiler::config::StarlarkProfilerConfiguration;
 use buck2_interpreter_for_build::interpreter::configuror::BuildInterpreterConfiguror;
 use buck2_interpreter_for_build::interpreter::cycles::LoadCycleDescriptor;
 use buck2_interpreter_for_build::interpreter::interpreter_setup::setup_interpreter;
+use buck2_node::nodes::configured_graph_cycle_events::ConfiguredGraphCycleEvents;
+use buck2_node::nodes::configured_graph_cycle_events::HasConfiguredGraphCycleEvents;
+use buck2_node::nodes::configured_recompute_stats::HasConfiguredNodeRecomputeStats;
 use buck2_server_ctx::bxl::InitBxlStreamingTracker;
 use buck2_server_ctx::concurrency::DiceUpdater;
 use buck2_server_ctx::ctx::DiceAccessor;
@@ -175,6 +180,7 @@ pub struct ServerCommandContext<'a> {
     host_xcode_version_override: Option<String>,
 
     reuse_current_config: bool,
+    no_config_file_cache: bool,
     config_overrides: Vec<ConfigOverride>,
 
     // This ensures that there's only one RE connection during the lifetime of this context. It's possible
@@ -220,6 +226,9 @@ pub struct ServerCommandContext<'a> {
 
     exit_when_different_state: bool,
     preemptible: PreemptibleWhen,
+
+    /// Shared with the DICE transaction, which reports configured graph cycles to it.
+    configured_graph_cycle_events: Arc<ConfiguredGraphCycleEvents>,
 }
 
 impl<'a> ServerCommandContext<'a> {
@@ -313,6 +322,7 @@ impl<'a> ServerCommandContext<'a> {
             host_arch_override: client_context.host_arch(),
             host_xcode_version_override: client_context.host_xcode_version.clone(),
             reuse_current_config: client_context.reuse_current_config,
+            no_config_file_cache: client_context.no_config_file_cache,
             config_overrides: client_context.config_overrides.clone(),
             oncall,
             client_id_from_client_metadata,
@@ -334,6 +344,7 @@ impl<'a> ServerCommandContext<'a> {
             cancellations,
             exit_when_different_state: client_context.exit_when_different_state,
             preemptible: client_context.preemptible(),
+            configured_graph_cycle_events: Arc::new(ConfiguredGraphCycleEvents::default()),
         })
     }
 
@@ -439,9 +450,10 @@ impl ServerCommandContext<'_> {
         &self,
         dice_ctx: &mut DiceComputations<'_>,
     ) -> buck2_error::Result<BuckConfigBasedCells> {
-        let new_configs = BuckConfigBasedCells::parse_with_config_args(
+        let mut new_configs = BuckConfigBasedCells::parse_with_config_args_and_cache(
             &self.base_context.project_root,
             &self.config_overrides,
+            ConfigFileExistenceCache::process_unless_disabled(self.no_config_file_cache)?,
         )
         .await?;
 
@@ -455,6 +467,7 @@ impl ServerCommandContext<'_> {
                     let config_type_str = |c| match ConfigType::try_from(c) {
                         Ok(ConfigType::Value) => "--config",
                         Ok(ConfigType::File) => "--config-file",
+                        Ok(ConfigType::ExternalFile) => "BUCK2_EXTERNAL_BUCKCONFIG",
                         Err(_) => "",
                     };
                     warn!(
@@ -469,13 +482,10 @@ impl ServerCommandContext<'_> {
                 }
                 // If `--reuse-current-config` is set, use the external config data from the
                 // previous command.
-                Ok(BuckConfigBasedCells {
-                    cell_resolver: new_configs.cell_resolver,
-                    root_config: new_configs.root_config,
-                    config_paths: HashSet::new(),
-                    external_data: (*dice_ctx.get_injected_external_buckconfig_data().await?)
-                        .clone(),
-                })
+                new_configs.config_paths = HashSet::new();
+                new_configs.external_data =
+                    (*dice_ctx.get_injected_external_buckconfig_data().await?).clone();
+                Ok(new_configs)
             } else {
                 // If there is no previous command but the flag was set, then the flag is ignored,
                 // the command behaves as if there isn't the reuse config flag.
@@ -537,8 +547,18 @@ fn create_cycle_detector() -> Arc<dyn UserCycleDetector> {
 impl DiceUpdater for DiceCommandUpdater<'_, '_> {
     async fn update(
         &self,
-        mut ctx: DiceTransactionUpdater,
+        ctx: DiceTransactionUpdater,
     ) -> buck2_error::Result<(DiceTransactionUpdater, UserComputationData)> {
+        // Sync the file watcher first, so that config file lookups it invalidates aren't served
+        // from the cache when loading the configs.
+        let (mut ctx, mergebase) = self
+            .cmd_ctx
+            .base_context
+            .daemon
+            .file_watcher
+            .sync(ctx)
+            .await?;
+
         let existing_state = &mut ctx.existing_state().await.clone();
         let cells_and_configs = self.cmd_ctx.load_new_configs(existing_state).await?;
         let cell_resolver = cells_and_configs.cell_resolver;
@@ -577,14 +597,6 @@ impl DiceUpdater for DiceCommandUpdater<'_, '_> {
             self.cmd_ctx.unstable_typecheck,
         )?;
 
-        let (ctx, mergebase) = self
-            .cmd_ctx
-            .base_context
-            .daemon
-            .file_watcher
-            .sync(ctx)
-            .await?;
-
         let mut user_data = self.make_user_computation_data(&cells_and_configs.root_config)?;
         user_data.set_mergebase(mergebase);
 
@@ -641,6 +653,13 @@ impl DiceCommandUpdater<'_, '_> {
             })?
             .unwrap_or(false);
 
+        let configured_node_recompute_stats = root_config
+            .parse::<bool>(BuckconfigKeyRef {
+                section: "buck2",
+                property: "configured_node_recompute_stats",
+            })?
+            .unwrap_or(false);
+
         let persistent_worker_shutdown_timeout_s = root_config
             .parse::<u32>(BuckconfigKeyRef {
                 section: "build",
@@ -728,6 +747,7 @@ impl DiceCommandUpdater<'_, '_> {
             host_sharing_broker,
             low_pass_filter,
             self.cmd_ctx.base_context.daemon.materializer.dupe(),
+            self.cmd_ctx.base_context.daemon.io_pressure.dupe(),
             self.cmd_ctx.base_context.daemon.blocking_executor.dupe(),
             self.execution_strategy,
             executor_global_knobs,
@@ -768,6 +788,8 @@ impl DiceCommandUpdater<'_, '_> {
         data.init_local_resource_registry();
         data.init_bxl_streaming_tracker();
         data.set_read_dir_cache(DashMap::new());
+        data.set_configured_node_recompute_stats(configured_node_recompute_stats);
+        data.set_configured_graph_cycle_events(self.cmd_ctx.configured_graph_cycle_events.dupe());
         data.spawner = self.cmd_ctx.base_context.daemon.spawner.dupe();
 
         let tags = vec![
@@ -1026,6 +1048,8 @@ impl ServerCommandContextTrait for ServerCommandContext<'_> {
         let patterns = providers_patterns.map(|pat| buck2_data::TargetPattern {
             value: format!("{}", pat),
         });
+        self.configured_graph_cycle_events
+            .set_top_level_targets(patterns.map(|pat| pat.value.clone()));
 
         self.events()
             .instant_event(buck2_data::ParsedTargetPatterns {
@@ -7,13 +7,19 @@
  * of this source tree.
  */
 
+pub(crate) mod buck_out_layout;
+pub(crate) mod buck_out_usage;
 pub mod check_working_dir;
+pub(crate) mod client_compatibility;
+pub(crate) mod client_context_validation;
+pub(crate) mod command_setup;
 pub mod common;
 pub mod crash;
 pub mod daemon_tcp;
 pub mod dice_dump;
 pub mod disk_state;
 pub mod forkserver;
+pub(crate) mod heap_dump;
 pub(crate) mod io_provider;
 mod multi_event_stream;
 pub mod panic;
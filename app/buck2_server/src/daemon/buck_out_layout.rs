@@ -0,0 +1,198 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! A marker file in buck-out recording the layout it was created for.
+//!
+//! The materializer state and the action caches refer to buck-out by project relative paths. If
+//! the checkout is moved, or the same buck-out ends up reachable from another project root (e.g.
+//! through a symlink or a worktree), those records still "exist" but no longer describe what is on
+//! disk. The marker lets the daemon notice instead of silently producing wrong outputs.
+
+use std::fmt::Write;
+
+use buck2_cli_proto::BuckOutLayout;
+use buck2_common::invocation_paths::InvocationPaths;
+use buck2_core::buck2_env;
+use buck2_core::fs::fs_util;
+use buck2_core::fs::paths::abs_norm_path::AbsNormPath;
+use buck2_core::fs::paths::abs_norm_path::AbsNormPathBuf;
+use buck2_core::fs::paths::file_name::FileName;
+use buck2_error::BuckErrorContext;
+
+/// Bump when the layout of buck-out changes in a way an existing buck-out can't be reused with.
+const BUCK_OUT_LAYOUT_VERSION: u32 = 1;
+
+const MARKER_FILE_NAME: &str = "buck_out_layout.json";
+
+#[derive(Debug, buck2_error::Error)]
+#[buck2(tag = Input)]
+#[error(
+    "buck-out at `{buck_out}` was created for a different layout:\n{changes}\
+     It refers to its outputs relative to the project root it was created for, so it can't be reused as is. Either:\n\
+     \x20 - use a fresh isolation dir with `--isolation-dir`,\n\
+     \x20 - run `buck2 clean` to delete this buck-out, or\n\
+     \x20 - set `BUCK2_IGNORE_BUCK_OUT_LAYOUT_MISMATCH=1` to reuse it anyway, at the risk of incorrect outputs"
+)]
+struct BuckOutLayoutMismatch {
+    buck_out: String,
+    changes: String,
+}
+
+/// The layout of buck-out for the current invocation.
+fn current_layout(paths: &InvocationPaths) -> buck2_error::Result<BuckOutLayout> {
+    // Canonicalized so that reaching the same checkout through a symlink isn't a change, but a
+    // different checkout sharing buck-out through one is.
+    let project_root = fs_util::canonicalize(paths.project_root().root())?;
+    Ok(BuckOutLayout {
+        version: BUCK_OUT_LAYOUT_VERSION,
+        project_root: project_root.to_string(),
+        isolation_dir: paths.isolation.to_string(),
+    })
+}
+
+/// Checks that the buck-out of `paths` was created for the current layout, and records the
+/// current layout if it is new. Must be called once buck-out exists.
+pub(crate) fn verify_buck_out_layout(
+    paths: &InvocationPaths,
+) -> buck2_error::Result<BuckOutLayout> {
+    let current = current_layout(paths)?;
+    let force = buck2_env!("BUCK2_IGNORE_BUCK_OUT_LAYOUT_MISMATCH", bool)?;
+    verify_or_write(&paths.buck_out_path(), current, force)
+}
+
+fn verify_or_write(
+    buck_out: &AbsNormPath,
+    current: BuckOutLayout,
+    force: bool,
+) -> buck2_error::Result<BuckOutLayout> {
+    let marker_path = marker_path(buck_out);
+    if let Some(contents) = fs_util::read_to_string_if_exists(&marker_path)? {
+        match serde_json::from_str::<BuckOutLayout>(&contents) {
+            Ok(previous) if previous == current => return Ok(current),
+            Ok(previous) => {
+                let error = BuckOutLayoutMismatch {
+                    buck_out: buck_out.to_string(),
+                    changes: describe_changes(&previous, &current),
+                };
+                if !force {
+                    return Err(error.into());
+                }
+                tracing::warn!("Reusing buck-out anyway: {}", error);
+            }
+            // Written by a daemon that crashed mid-write, nothing to compare to.
+            Err(e) => tracing::warn!(
+                "Ignoring unreadable buck-out layout marker `{}`: {}",
+                marker_path,
+                e
+            ),
+        }
+    }
+
+    fs_util::write(&marker_path, serde_json::to_string_pretty(&current)?)
+        .with_buck_error_context(|| format!("Error writing `{}`", marker_path))?;
+    Ok(current)
+}
+
+fn marker_path(buck_out: &AbsNormPath) -> AbsNormPathBuf {
+    buck_out.join(FileName::unchecked_new(MARKER_FILE_NAME))
+}
+
+fn describe_changes(previous: &BuckOutLayout, current: &BuckOutLayout) -> String {
+    let mut changes = String::new();
+    if previous.project_root != current.project_root {
+        writeln!(
+            changes,
+            "  project root: `{}`, now `{}`",
+            previous.project_root, current.project_root
+        )
+        .unwrap();
+    }
+    if previous.isolation_dir != current.isolation_dir {
+        writeln!(
+            changes,
+            "  isolation dir: `{}`, now `{}`",
+            previous.isolation_dir, current.isolation_dir
+        )
+        .unwrap();
+    }
+    if previous.version != current.version {
+        writeln!(
+            changes,
+            "  layout version: {}, now {}",
+            previous.version, current.version
+        )
+        .unwrap();
+    }
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layout(project_root: &str) -> BuckOutLayout {
+        BuckOutLayout {
+            version: BUCK_OUT_LAYOUT_VERSION,
+            project_root: project_root.to_owned(),
+            isolation_dir: "v2".to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_marker_written_then_verified() -> buck2_error::Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let buck_out = AbsNormPathBuf::new(tempdir.path().to_path_buf())?;
+
+        assert_eq!(
+            layout("/repo"),
+            verify_or_write(&buck_out, layout("/repo"), false)?
+        );
+        assert!(fs_util::try_exists(marker_path(&buck_out))?);
+        assert_eq!(
+            layout("/repo"),
+            verify_or_write(&buck_out, layout("/repo"), false)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_moved_project_root() -> buck2_error::Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let buck_out = AbsNormPathBuf::new(tempdir.path().to_path_buf())?;
+        verify_or_write(&buck_out, layout("/repo"), false)?;
+
+        // The checkout was moved: buck-out comes along, with the marker of the old root.
+        let error = verify_or_write(&buck_out, layout("/moved/repo"), false).unwrap_err();
+        let message = format!("{:#}", error);
+        assert!(
+            message.contains("project root: `/repo`, now `/moved/repo`"),
+            "{message}"
+        );
+        assert!(!message.contains("isolation dir:"), "{message}");
+        assert!(message.contains("--isolation-dir"), "{message}");
+        assert_eq!(error.get_tier(), Some(buck2_error::Tier::Input));
+
+        // Forcing records the new root.
+        verify_or_write(&buck_out, layout("/moved/repo"), true)?;
+        verify_or_write(&buck_out, layout("/moved/repo"), false)?;
+        assert!(verify_or_write(&buck_out, layout("/repo"), false).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_unreadable_marker_is_replaced() -> buck2_error::Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let buck_out = AbsNormPathBuf::new(tempdir.path().to_path_buf())?;
+        fs_util::write(marker_path(&buck_out), "{")?;
+
+        verify_or_write(&buck_out, layout("/repo"), false)?;
+        assert!(verify_or_write(&buck_out, layout("/moved/repo"), false).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,240 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Accounting of the disk space used by buck-out.
+//!
+//! Walking buck-out is slow, so it is done rarely, in the background, and throttled. Between
+//! walks, the estimate follows the materializer's own accounting of the artifacts it materialized
+//! and cleaned, which is cheap to read. Files the materializer doesn't track (logs, caches,
+//! outputs left by other daemons) are only picked up by the next walk.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::Weak;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use std::time::SystemTime;
+
+use buck2_core::fs::paths::abs_norm_path::AbsNormPathBuf;
+use buck2_execute::materialize::materializer::Materializer;
+use dupe::Dupe;
+use parking_lot::Mutex;
+
+/// Delay before the first walk, so that it doesn't compete with the first commands.
+const FIRST_WALK_DELAY: Duration = Duration::from_secs(5 * 60);
+
+/// Time between the end of a walk and the start of the next one.
+const WALK_INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
+
+/// The walk sleeps for `WALK_PAUSE` after every `WALK_BATCH_SIZE` directory entries.
+const WALK_BATCH_SIZE: u64 = 1000;
+const WALK_PAUSE: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Walk {
+    /// Size of buck-out found by the walk.
+    bytes: u64,
+    /// What the materializer reported as materialized when the walk started.
+    materialized_bytes_at_start: u64,
+    end: SystemTime,
+}
+
+impl Walk {
+    /// Estimated size of buck-out once the materializer reports `materialized_bytes`.
+    fn estimate(&self, materialized_bytes: u64) -> u64 {
+        self.bytes
+            .saturating_add(materialized_bytes)
+            .saturating_sub(self.materialized_bytes_at_start)
+    }
+}
+
+pub(crate) struct BuckOutUsageTracker {
+    materializer: Arc<dyn Materializer>,
+    last_walk: Mutex<Option<Walk>>,
+    /// Interrupts an ongoing walk and stops the background task, set on drop.
+    stop: Arc<AtomicBool>,
+}
+
+impl BuckOutUsageTracker {
+    pub(crate) fn spawn(
+        buck_out: AbsNormPathBuf,
+        materializer: Arc<dyn Materializer>,
+    ) -> Arc<Self> {
+        let tracker = Arc::new(Self {
+            materializer,
+            last_walk: Mutex::new(None),
+            stop: Arc::new(AtomicBool::new(false)),
+        });
+        tokio::task::spawn(Self::run(
+            Arc::downgrade(&tracker),
+            tracker.stop.dupe(),
+            buck_out.into_path_buf(),
+        ));
+        tracker
+    }
+
+    async fn run(tracker: Weak<Self>, stop: Arc<AtomicBool>, buck_out: PathBuf) {
+        tokio::time::sleep(FIRST_WALK_DELAY).await;
+        loop {
+            let Some(materialized_bytes_at_start) =
+                tracker.upgrade().map(|t| t.materialized_bytes())
+            else {
+                return;
+            };
+
+            let walk = tokio::task::spawn_blocking({
+                let stop = stop.dupe();
+                let buck_out = buck_out.clone();
+                move || walk_size(&buck_out, &stop, WALK_BATCH_SIZE, WALK_PAUSE)
+            })
+            .await;
+
+            match walk {
+                Ok(Ok(Some(bytes))) => {
+                    let Some(tracker) = tracker.upgrade() else {
+                        return;
+                    };
+                    *tracker.last_walk.lock() = Some(Walk {
+                        bytes,
+                        materialized_bytes_at_start,
+                        end: SystemTime::now(),
+                    });
+                }
+                // Interrupted.
+                Ok(Ok(None)) => return,
+                Ok(Err(e)) => tracing::warn!("failed to compute the size of buck-out: {:#}", e),
+                Err(e) => tracing::warn!("failed to compute the size of buck-out: {:#}", e),
+            }
+
+            tokio::time::sleep(WALK_INTERVAL).await;
+        }
+    }
+
+    fn materialized_bytes(&self) -> u64 {
+        self.materializer.materialized_bytes().unwrap_or_default()
+    }
+
+    /// Current disk usage, `None` until the first walk completes.
+    pub(crate) fn usage(&self) -> Option<buck2_cli_proto::BuckOutUsage> {
+        let walk = (*self.last_walk.lock())?;
+        Some(buck2_cli_proto::BuckOutUsage {
+            bytes: walk.estimate(self.materialized_bytes()),
+            walked_bytes: walk.bytes,
+            walked_at: Some(walk.end.into()),
+        })
+    }
+}
+
+impl Drop for BuckOutUsageTracker {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Total size of the files under `root`, without following symlinks. Sleeps for `pause` after
+/// every `batch_size` entries, and returns `None` as soon as `stop` is set.
+///
+/// Entries that disappear or can't be read during the walk are skipped: buck-out changes while
+/// commands run, and an approximate answer is fine.
+fn walk_size(
+    root: &Path,
+    stop: &AtomicBool,
+    batch_size: u64,
+    pause: Duration,
+) -> io::Result<Option<u64>> {
+    let mut bytes = 0;
+    let mut entries = 0;
+    let mut dirs = vec![root.to_path_buf()];
+    let mut is_root = true;
+    while let Some(dir) = dirs.pop() {
+        let read_dir = match fs::read_dir(&dir) {
+            Ok(read_dir) => read_dir,
+            Err(e) if is_root => return Err(e),
+            Err(_) => continue,
+        };
+        is_root = false;
+        for entry in read_dir {
+            if stop.load(Ordering::Relaxed) {
+                return Ok(None);
+            }
+            entries += 1;
+            if entries % batch_size == 0 {
+                std::thread::sleep(pause);
+            }
+            let Ok(entry) = entry else {
+                continue;
+            };
+            // `DirEntry::metadata` does not traverse symlinks.
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            if metadata.is_dir() {
+                dirs.push(entry.path());
+            } else {
+                bytes += metadata.len();
+            }
+        }
+    }
+    Ok(Some(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_estimate_follows_materializer() {
+        let walk = Walk {
+            bytes: 10_000,
+            materialized_bytes_at_start: 4_000,
+            end: SystemTime::UNIX_EPOCH,
+        };
+        assert_eq!(walk.estimate(4_000), 10_000);
+        // Artifacts materialized since the walk.
+        assert_eq!(walk.estimate(6_500), 12_500);
+        // Artifacts cleaned since the walk.
+        assert_eq!(walk.estimate(1_000), 7_000);
+        assert_eq!(walk.estimate(0), 6_000);
+        // The materializer can't have cleaned more than what was on disk.
+        let walk = Walk {
+            bytes: 1_000,
+            materialized_bytes_at_start: 4_000,
+            end: SystemTime::UNIX_EPOCH,
+        };
+        assert_eq!(walk.estimate(0), 0);
+    }
+
+    #[test]
+    fn test_walk_size() -> io::Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let root = tempdir.path();
+        fs::create_dir_all(root.join("a/b"))?;
+        fs::write(root.join("x"), [0; 10])?;
+        fs::write(root.join("a/y"), [0; 100])?;
+        fs::write(root.join("a/b/z"), [0; 1000])?;
+
+        let stop = AtomicBool::new(false);
+        assert_eq!(
+            walk_size(root, &stop, 2, Duration::from_millis(1))?,
+            Some(1110)
+        );
+        assert_eq!(
+            walk_size(&root.join("missing"), &stop, 2, Duration::ZERO).map_err(|e| e.kind()),
+            Err(io::ErrorKind::NotFound)
+        );
+
+        stop.store(true, Ordering::Relaxed);
+        assert_eq!(walk_size(root, &stop, 2, Duration::ZERO)?, None);
+        Ok(())
+    }
+}
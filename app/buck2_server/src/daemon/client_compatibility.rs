@@ -0,0 +1,132 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Rejection of commands sent by clients the daemon is incompatible with.
+//!
+//! Clients restart daemons built from a different binary, but a daemon can still receive commands
+//! from other clients, e.g. when the client was told not to restart it. Rather than fail in
+//! confusing ways in the middle of the command, the daemon rejects it upfront, and tells the
+//! client to restart it.
+
+use buck2_cli_proto::CLIENT_COMPATIBILITY_VERSION;
+use buck2_cli_proto::ClientContext;
+use buck2_cli_proto::IncompatibleClientVersion;
+use buck2_cli_proto::MIN_CLIENT_COMPATIBILITY_VERSION;
+use prost::Message;
+use tonic::Code;
+use tonic::Status;
+
+/// Whether a daemon with compatibility version `daemon` serves clients with compatibility
+/// version `client`. Clients that predate compatibility versions send 0, and are served.
+fn is_compatible(client: u32, daemon: u32, min_client: u32) -> bool {
+    client == 0 || (min_client..=daemon).contains(&client)
+}
+
+/// Returns a `FAILED_PRECONDITION` status with an [`IncompatibleClientVersion`] as details if
+/// this daemon can't serve the command sent with `ctx`.
+pub(crate) fn check_client_compatibility(
+    ctx: &ClientContext,
+    daemon_version: &str,
+) -> Result<(), Status> {
+    check_client_compatibility_impl(
+        ctx,
+        daemon_version,
+        CLIENT_COMPATIBILITY_VERSION,
+        MIN_CLIENT_COMPATIBILITY_VERSION,
+    )
+}
+
+fn check_client_compatibility_impl(
+    ctx: &ClientContext,
+    daemon_version: &str,
+    daemon_compatibility_version: u32,
+    min_client_compatibility_version: u32,
+) -> Result<(), Status> {
+    if is_compatible(
+        ctx.client_compatibility_version,
+        daemon_compatibility_version,
+        min_client_compatibility_version,
+    ) {
+        return Ok(());
+    }
+
+    let details = IncompatibleClientVersion {
+        client_version: ctx.client_version.clone(),
+        client_compatibility_version: ctx.client_compatibility_version,
+        daemon_version: daemon_version.to_owned(),
+        daemon_compatibility_version,
+        min_client_compatibility_version,
+        restart_daemon: true,
+    };
+    let message = format!(
+        "buck2 daemon `{}` (compatibility version {}, serving clients from {}) can't serve commands from client `{}` (compatibility version {})",
+        details.daemon_version,
+        details.daemon_compatibility_version,
+        details.min_client_compatibility_version,
+        details.client_version,
+        details.client_compatibility_version,
+    );
+    tracing::warn!("Rejected `{}` command: {}", ctx.command_name, message);
+    Err(Status::with_details(
+        Code::FailedPrecondition,
+        message,
+        details.encode_to_vec().into(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(client: u32) -> Result<(), Status> {
+        let ctx = ClientContext {
+            client_version: "client-rev".to_owned(),
+            client_compatibility_version: client,
+            ..Default::default()
+        };
+        check_client_compatibility_impl(&ctx, "daemon-id", 5, 3)
+    }
+
+    #[test]
+    fn test_equal_version() {
+        assert!(check(5).is_ok());
+    }
+
+    #[test]
+    fn test_compatible_older_version() {
+        assert!(check(3).is_ok());
+        assert!(check(4).is_ok());
+        // Clients that don't send a compatibility version.
+        assert!(check(0).is_ok());
+    }
+
+    #[test]
+    fn test_incompatible_version() {
+        for client in [2, 6] {
+            let status = check(client).unwrap_err();
+            assert_eq!(status.code(), Code::FailedPrecondition);
+            assert!(
+                status.message().contains("daemon-id"),
+                "{}",
+                status.message()
+            );
+            assert_eq!(
+                IncompatibleClientVersion::decode(status.details()).unwrap(),
+                IncompatibleClientVersion {
+                    client_version: "client-rev".to_owned(),
+                    client_compatibility_version: client,
+                    daemon_version: "daemon-id".to_owned(),
+                    daemon_compatibility_version: 5,
+                    min_client_compatibility_version: 3,
+                    restart_daemon: true,
+                }
+            );
+        }
+    }
+}
@@ -0,0 +1,234 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Validation of the `ClientContext` sent with each command.
+//!
+//! The buck2 client always sends well formed contexts, but people also script the gRPC API
+//! directly. Without this, a malformed field surfaces as a bare parse error somewhere in command
+//! setup, without saying which field was wrong or what was expected.
+
+use buck2_cli_proto::ClientContext;
+use buck2_cli_proto::client_context::HostArchOverride;
+use buck2_cli_proto::client_context::HostPlatformOverride;
+use buck2_cli_proto::client_context::PreemptibleWhen;
+use buck2_cli_proto::config_override::ConfigType;
+use buck2_core::fs::paths::abs_norm_path::AbsNormPath;
+use buck2_wrapper_common::invocation_id::TraceId;
+
+#[derive(Debug, buck2_error::Error)]
+#[buck2(tag = Input)]
+enum InvalidClientContextField {
+    #[error(
+        "Invalid `trace_id` `{0}`: expected a UUID, e.g. `3fa85f64-5717-4562-b3fc-2c963f66afa6`"
+    )]
+    TraceId(String),
+    #[error("Invalid `working_dir` `{0}`: expected a normalized absolute path")]
+    WorkingDirNotAbsolute(String),
+    #[error("Invalid `working_dir` `{0}`: expected an existing directory")]
+    WorkingDirDoesNotExist(String),
+    #[error(
+        "Invalid `config_overrides[{0}]` `{1}`: expected a value of the form `section.key=value`"
+    )]
+    ConfigOverrideValue(usize, String),
+    #[error(
+        "Invalid `config_overrides[{0}]` `{1}`: expected an absolute path to a config file, or a `cell` it is relative to"
+    )]
+    ConfigOverrideFile(usize, String),
+    #[error("Invalid `config_overrides[{0}].config_type` `{1}`: expected one of {2}")]
+    ConfigOverrideType(usize, i32, &'static str),
+    #[error("Invalid `{0}` `{1}`: expected one of {2}")]
+    Enum(&'static str, i32, &'static str),
+}
+
+/// Checks all the fields of `ctx` that commands parse, and reports all the invalid ones in a
+/// single error.
+pub(crate) fn validate_client_context(ctx: &ClientContext) -> buck2_error::Result<()> {
+    let errors = invalid_fields(ctx);
+    if errors.is_empty() {
+        return Ok(());
+    }
+    let error = buck2_error::Error::aggregate(errors.into_iter().map(Into::into).collect())
+        .context("Invalid client context");
+    tracing::warn!(
+        "Rejected a client context sent by client version `{}` for command `{}`: {:#}",
+        ctx.client_version,
+        ctx.command_name,
+        error
+    );
+    Err(error)
+}
+
+fn invalid_fields(ctx: &ClientContext) -> Vec<InvalidClientContextField> {
+    let mut errors = Vec::new();
+
+    if ctx.trace_id.parse::<TraceId>().is_err() {
+        errors.push(InvalidClientContextField::TraceId(ctx.trace_id.clone()));
+    }
+
+    match AbsNormPath::new(&ctx.working_dir) {
+        Err(_) => errors.push(InvalidClientContextField::WorkingDirNotAbsolute(
+            ctx.working_dir.clone(),
+        )),
+        Ok(path) if !path.as_path().is_dir() => errors.push(
+            InvalidClientContextField::WorkingDirDoesNotExist(ctx.working_dir.clone()),
+        ),
+        Ok(_) => {}
+    }
+
+    for (i, config_override) in ctx.config_overrides.iter().enumerate() {
+        match ConfigType::try_from(config_override.config_type) {
+            Ok(ConfigType::Value) => {
+                // Values may contain secrets, only the key is worth reporting.
+                let valid = config_override
+                    .config_override
+                    .split_once('=')
+                    .and_then(|(section_and_key, _)| section_and_key.split_once('.'))
+                    .is_some_and(|(section, key)| {
+                        !section.trim().is_empty()
+                            && !key.is_empty()
+                            && !key.contains(char::is_whitespace)
+                    });
+                if !valid {
+                    errors.push(InvalidClientContextField::ConfigOverrideValue(
+                        i,
+                        redact_config_value(&config_override.config_override),
+                    ));
+                }
+            }
+            Ok(ConfigType::File) => {
+                if config_override.cell.is_none()
+                    && AbsNormPath::new(&config_override.config_override).is_err()
+                {
+                    errors.push(InvalidClientContextField::ConfigOverrideFile(
+                        i,
+                        config_override.config_override.clone(),
+                    ));
+                }
+            }
+            Ok(ConfigType::ExternalFile) => {
+                if config_override.cell.is_some()
+                    || AbsNormPath::new(&config_override.config_override).is_err()
+                {
+                    errors.push(InvalidClientContextField::ConfigOverrideFile(
+                        i,
+                        config_override.config_override.clone(),
+                    ));
+                }
+            }
+            Err(_) => errors.push(InvalidClientContextField::ConfigOverrideType(
+                i,
+                config_override.config_type,
+                "`VALUE`, `FILE`, `EXTERNAL_FILE`",
+            )),
+        }
+    }
+
+    if HostPlatformOverride::try_from(ctx.host_platform).is_err() {
+        errors.push(InvalidClientContextField::Enum(
+            "host_platform",
+            ctx.host_platform,
+            "`DEFAULT_PLATFORM`, `LINUX`, `MAC_OS`, `WINDOWS`",
+        ));
+    }
+    if HostArchOverride::try_from(ctx.host_arch).is_err() {
+        errors.push(InvalidClientContextField::Enum(
+            "host_arch",
+            ctx.host_arch,
+            "`DEFAULT_ARCH`, `AArch64`, `X86_64`",
+        ));
+    }
+    if PreemptibleWhen::try_from(ctx.preemptible).is_err() {
+        errors.push(InvalidClientContextField::Enum(
+            "preemptible",
+            ctx.preemptible,
+            "`NEVER`, `ALWAYS`, `ON_DIFFERENT_STATE`",
+        ));
+    }
+
+    errors
+}
+
+/// `section.key=<redacted>`, or `<redacted>` if there is no `=` to tell the key apart.
+fn redact_config_value(config_override: &str) -> String {
+    match config_override.split_once('=') {
+        Some((section_and_key, _)) => format!("{}=<redacted>", section_and_key),
+        None => "<redacted>".to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use buck2_cli_proto::ConfigOverride;
+
+    use super::*;
+
+    fn valid_context(working_dir: &str) -> ClientContext {
+        ClientContext {
+            working_dir: working_dir.to_owned(),
+            trace_id: TraceId::new().to_string(),
+            config_overrides: vec![ConfigOverride {
+                cell: None,
+                config_override: "section.key=value".to_owned(),
+                config_type: ConfigType::Value as i32,
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_valid() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let ctx = valid_context(tempdir.path().to_str().unwrap());
+        assert!(validate_client_context(&ctx).is_ok());
+    }
+
+    #[test]
+    fn test_all_invalid_fields_reported() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let mut ctx = valid_context(tempdir.path().join("missing").to_str().unwrap());
+        ctx.trace_id = "not-a-trace-id".to_owned();
+        ctx.config_overrides.push(ConfigOverride {
+            cell: None,
+            config_override: "no_section=secret".to_owned(),
+            config_type: ConfigType::Value as i32,
+        });
+        ctx.config_overrides.push(ConfigOverride {
+            cell: None,
+            config_override: "relative/.buckconfig".to_owned(),
+            config_type: ConfigType::File as i32,
+        });
+        ctx.host_platform = 42;
+
+        let error = validate_client_context(&ctx).unwrap_err();
+        assert_eq!(error.get_tier(), Some(buck2_error::Tier::Input));
+        assert_eq!(error.iter_roots().count(), 5);
+
+        let message = format!("{:#}", error);
+        for expected in [
+            "`trace_id` `not-a-trace-id`",
+            "`working_dir`",
+            "`config_overrides[1]` `no_section=<redacted>`",
+            "`config_overrides[2]` `relative/.buckconfig`",
+            "`host_platform` `42`",
+        ] {
+            assert!(message.contains(expected), "{expected} in {message}");
+        }
+        assert!(!message.contains("secret"), "{message}");
+    }
+
+    #[test]
+    fn test_relative_working_dir() {
+        let ctx = valid_context("relative/dir");
+        let error = validate_client_context(&ctx).unwrap_err();
+        assert!(
+            format!("{:#}", error).contains("expected a normalized absolute path"),
+            "{error:#}"
+        );
+    }
+}
@@ -0,0 +1,290 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! The work a streaming command does before its body runs: the phases it is made of, how their
+//! failures are reported and how long each of them took.
+
+use std::mem;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::SystemTime;
+
+use allocative::Allocative;
+use buck2_core::buck2_env;
+use buck2_error::BuckErrorContext;
+use buck2_events::BuckEvent;
+use buck2_events::dispatch::EventDispatcher;
+use buck2_wrapper_common::invocation_id::TraceId;
+use dupe::Dupe;
+use futures::Future;
+use parking_lot::Mutex;
+use tokio::time::Instant;
+
+/// The steps `run_streaming` takes to set up a command before running it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Allocative)]
+pub(crate) enum CommandSetupPhase {
+    PrepareEvents,
+    Snapshot,
+    PrepareCommand,
+    /// Nested in `PrepareCommand`.
+    FileWatcherSync,
+    CreateContext,
+}
+
+impl CommandSetupPhase {
+    fn name(self) -> &'static str {
+        match self {
+            CommandSetupPhase::PrepareEvents => "prepare_events",
+            CommandSetupPhase::Snapshot => "snapshot",
+            CommandSetupPhase::PrepareCommand => "prepare_command",
+            CommandSetupPhase::FileWatcherSync => "file_watcher_sync",
+            CommandSetupPhase::CreateContext => "create_context",
+        }
+    }
+
+    /// Run this phase, timing it and attaching the phase to any error it returns. Tests can force
+    /// a phase to fail by setting `BUCK2_TEST_FAIL_COMMAND_SETUP` to its name, or slow it down by
+    /// setting `BUCK2_TEST_SLOW_COMMAND_SETUP` to its name.
+    pub(crate) async fn run<T>(
+        self,
+        trace_id: &TraceId,
+        timer: &CommandPrologueTimer,
+        fut: impl Future<Output = buck2_error::Result<T>>,
+    ) -> buck2_error::Result<T> {
+        let res = timer
+            .time(self, async {
+                if buck2_env!("BUCK2_TEST_FAIL_COMMAND_SETUP", applicability = testing)?
+                    == Some(self.name())
+                {
+                    return Err(buck2_error::buck2_error!(
+                        buck2_error::ErrorTag::Input,
+                        "Injected command setup error"
+                    ));
+                }
+                if buck2_env!("BUCK2_TEST_SLOW_COMMAND_SETUP", applicability = testing)?
+                    == Some(self.name())
+                {
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                }
+                fut.await
+            })
+            .await;
+        match res {
+            // Already attributed to a phase nested in this one.
+            Err(e) if e.find_typed_context::<CommandSetupFailed>().is_some() => Err(e),
+            res => res.buck_error_context(CommandSetupFailed {
+                phase: self,
+                trace_id: trace_id.dupe(),
+            }),
+        }
+    }
+}
+
+/// Typed context attached to errors returned while setting up a streaming command.
+#[derive(Debug, Clone, PartialEq, Eq, Allocative)]
+pub(crate) struct CommandSetupFailed {
+    phase: CommandSetupPhase,
+    trace_id: TraceId,
+}
+
+impl CommandSetupFailed {
+    fn to_proto(&self) -> buck2_data::CommandSetupFailed {
+        buck2_data::CommandSetupFailed {
+            phase: self.phase.name().to_owned(),
+        }
+    }
+
+    pub(crate) fn to_buck_event(&self) -> BuckEvent {
+        BuckEvent::new(
+            SystemTime::now(),
+            self.trace_id.dupe(),
+            None,
+            None,
+            buck2_data::InstantEvent {
+                data: Some(self.to_proto().into()),
+            }
+            .into(),
+        )
+    }
+}
+
+impl std::fmt::Display for CommandSetupFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Command setup failed in phase `{}`", self.phase.name())
+    }
+}
+
+impl buck2_error::TypedContext for CommandSetupFailed {
+    fn eq(&self, other: &dyn buck2_error::TypedContext) -> bool {
+        match (other as &dyn std::any::Any).downcast_ref::<Self>() {
+            Some(v) => self == v,
+            None => false,
+        }
+    }
+}
+
+/// Once the event dispatcher exists, setup failures are reported through it rather than
+/// synthesized into the response stream.
+pub(crate) fn emit_command_setup_failed(dispatch: &EventDispatcher, e: &buck2_error::Error) {
+    if let Some(setup_failed) = e.find_typed_context::<CommandSetupFailed>() {
+        dispatch.instant_event(setup_failed.to_proto());
+    }
+}
+
+/// Records how long each setup phase took. Phases are emitted as events as they complete; those
+/// that complete before the event dispatcher exists are buffered and emitted together once it
+/// does.
+#[derive(Clone, Dupe, Default)]
+pub(crate) struct CommandPrologueTimer(Arc<Mutex<CommandPrologueTimerState>>);
+
+#[derive(Default)]
+struct CommandPrologueTimerState {
+    dispatch: Option<EventDispatcher>,
+    /// Phases that completed before there was a dispatcher to emit them to.
+    pending: Vec<buck2_data::CommandProloguePhase>,
+    /// Every phase that completed, in order.
+    phases: Vec<buck2_data::CommandProloguePhase>,
+    /// Time spent in phases nested in the one currently running.
+    nested: Duration,
+}
+
+impl CommandPrologueTimer {
+    pub(crate) fn set_dispatcher(&self, dispatch: EventDispatcher) {
+        let mut state = self.0.lock();
+        let pending = mem::take(&mut state.pending);
+        if !pending.is_empty() {
+            dispatch.instant_event(buck2_data::CommandPrologueTimings { phases: pending });
+        }
+        state.dispatch = Some(dispatch);
+    }
+
+    /// Time `fut` as `phase`. Time spent in phases nested in it is attributed to those phases
+    /// only, so that the recorded durations add up to the total time spent in setup.
+    async fn time<T>(&self, phase: CommandSetupPhase, fut: impl Future<Output = T>) -> T {
+        let outer_nested = mem::take(&mut self.0.lock().nested);
+        let start = Instant::now();
+        let res = fut.await;
+        let elapsed = start.elapsed();
+
+        let mut state = self.0.lock();
+        let duration = elapsed.saturating_sub(state.nested);
+        state.nested = outer_nested + elapsed;
+        state.record(phase, duration);
+        res
+    }
+
+    pub(crate) fn to_proto(&self) -> buck2_data::CommandPrologueTimings {
+        buck2_data::CommandPrologueTimings {
+            phases: self.0.lock().phases.clone(),
+        }
+    }
+}
+
+impl CommandPrologueTimerState {
+    fn record(&mut self, phase: CommandSetupPhase, duration: Duration) {
+        let phase = buck2_data::CommandProloguePhase {
+            phase: phase.name().to_owned(),
+            duration: duration.try_into().ok(),
+        };
+        self.phases.push(phase.clone());
+        match &self.dispatch {
+            Some(dispatch) => dispatch.instant_event(buck2_data::CommandPrologueTimings {
+                phases: vec![phase],
+            }),
+            None => self.pending.push(phase),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use buck2_events::source::ChannelEventSource;
+
+    use super::*;
+
+    fn sleep(ms: u64) -> impl Future<Output = buck2_error::Result<()>> {
+        async move {
+            tokio::time::sleep(Duration::from_millis(ms)).await;
+            Ok(())
+        }
+    }
+
+    fn duration(phase: &buck2_data::CommandProloguePhase) -> Duration {
+        Duration::try_from(phase.duration.clone().unwrap()).unwrap()
+    }
+
+    fn emitted_timings(
+        events: &mut ChannelEventSource,
+    ) -> Vec<Vec<buck2_data::CommandProloguePhase>> {
+        let mut res = Vec::new();
+        while let Some(event) = events.try_receive() {
+            if let buck2_data::buck_event::Data::Instant(instant) =
+                event.unpack_buck().unwrap().data()
+            {
+                if let Some(buck2_data::instant_event::Data::CommandPrologueTimings(timings)) =
+                    &instant.data
+                {
+                    res.push(timings.phases.clone());
+                }
+            }
+        }
+        res
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_prologue_timings() -> buck2_error::Result<()> {
+        let trace_id = TraceId::new();
+        let timer = CommandPrologueTimer::default();
+        let (mut events, sink) = buck2_events::create_source_sink_pair();
+        let start = Instant::now();
+
+        CommandSetupPhase::PrepareEvents
+            .run(&trace_id, &timer, sleep(10))
+            .await?;
+        timer.set_dispatcher(EventDispatcher::new(trace_id.dupe(), sink));
+
+        CommandSetupPhase::Snapshot
+            .run(&trace_id, &timer, sleep(20))
+            .await?;
+        CommandSetupPhase::PrepareCommand
+            .run(&trace_id, &timer, async {
+                sleep(30).await?;
+                CommandSetupPhase::FileWatcherSync
+                    .run(&trace_id, &timer, sleep(1000))
+                    .await?;
+                sleep(40).await
+            })
+            .await?;
+        let elapsed = start.elapsed();
+
+        let phases = timer.to_proto().phases;
+        assert_eq!(
+            phases.iter().map(|p| p.phase.as_str()).collect::<Vec<_>>(),
+            vec![
+                "prepare_events",
+                "snapshot",
+                "file_watcher_sync",
+                "prepare_command"
+            ]
+        );
+        for (phase, min) in phases.iter().zip([10, 20, 1000, 70]) {
+            assert!(duration(phase) >= Duration::from_millis(min));
+        }
+        // The nested phase is not counted twice.
+        assert!(duration(&phases[3]) < Duration::from_millis(1000));
+        assert_eq!(phases.iter().map(duration).sum::<Duration>(), elapsed);
+
+        // The phase that completed before the dispatcher existed is attached to the first event.
+        assert_eq!(
+            emitted_timings(&mut events),
+            phases.into_iter().map(|p| vec![p]).collect::<Vec<_>>()
+        );
+        Ok(())
+    }
+}
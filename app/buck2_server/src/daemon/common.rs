@@ -41,6 +41,7 @@ use buck2_execute::execute::prepared::PreparedCommandExecutor;
 use buck2_execute::execute::prepared::PreparedCommandOptionalExecutor;
 use buck2_execute::execute::request::ExecutorPreference;
 use buck2_execute::knobs::ExecutorGlobalKnobs;
+use buck2_execute::materialize::io_pressure::IoPressureSignals;
 use buck2_execute::materialize::materializer::Materializer;
 use buck2_execute::re::manager::ManagedRemoteExecutionClient;
 use buck2_execute::re::manager::ReConnectionHandle;
@@ -85,6 +86,7 @@ pub struct CommandExecutorFactory {
     host_sharing_broker: Arc<HostSharingBroker>,
     low_pass_filter: Arc<LowPassFilter>,
     materializer: Arc<dyn Materializer>,
+    io_pressure: Option<Arc<dyn IoPressureSignals>>,
     blocking_executor: Arc<dyn BlockingExecutor>,
     strategy: ExecutionStrategy,
     executor_global_knobs: ExecutorGlobalKnobs,
@@ -110,6 +112,7 @@ impl CommandExecutorFactory {
         host_sharing_broker: HostSharingBroker,
         low_pass_filter: LowPassFilter,
         materializer: Arc<dyn Materializer>,
+        io_pressure: Option<Arc<dyn IoPressureSignals>>,
         blocking_executor: Arc<dyn BlockingExecutor>,
         strategy: ExecutionStrategy,
         executor_global_knobs: ExecutorGlobalKnobs,
@@ -134,6 +137,7 @@ impl CommandExecutorFactory {
             host_sharing_broker: Arc::new(host_sharing_broker),
             low_pass_filter: Arc::new(low_pass_filter),
             materializer,
+            io_pressure,
             blocking_executor,
             strategy,
             executor_global_knobs,
@@ -180,6 +184,7 @@ impl HasCommandExecutor for CommandExecutorFactory {
             LocalExecutor::new(
                 artifact_fs.clone(),
                 self.materializer.dupe(),
+                self.io_pressure.dupe(),
                 self.blocking_executor.dupe(),
                 self.host_sharing_broker.dupe(),
                 self.project_root.root().to_owned(),
@@ -0,0 +1,236 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Dump the daemon heap when its RSS crosses `buck2.heap_dump_rss_threshold_bytes`, to
+//! catch what is using memory before the daemon gets OOM killed.
+
+use std::time::Duration;
+
+use buck2_common::init::DaemonStartupConfig;
+use buck2_common::invocation_paths::InvocationPaths;
+use buck2_common::memory;
+use buck2_core::fs::fs_util;
+use buck2_core::fs::paths::abs_path::AbsPathBuf;
+use buck2_error::BuckErrorContext;
+use buck2_util::process_stats::process_stats;
+use tokio::time::Instant;
+
+use crate::active_commands::broadcast_instant_event;
+
+/// How often RSS is sampled.
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Minimum time between two dumps. A daemon that stays above the threshold would otherwise
+/// write a dump on every sample.
+const MIN_DUMP_INTERVAL: Duration = Duration::from_secs(30 * 60);
+
+/// Abstracts memory sampling and heap dumping, so this can be tested without jemalloc.
+pub(crate) trait HeapDumper: Send + 'static {
+    fn rss_bytes(&self) -> Option<u64>;
+
+    fn write_heap_to_file(&self, path: &str) -> buck2_error::Result<()>;
+}
+
+struct JemallocHeapDumper;
+
+impl HeapDumper for JemallocHeapDumper {
+    fn rss_bytes(&self) -> Option<u64> {
+        process_stats().rss_bytes
+    }
+
+    fn write_heap_to_file(&self, path: &str) -> buck2_error::Result<()> {
+        memory::write_heap_to_file(path)
+    }
+}
+
+pub(crate) struct HeapDumpOnThreshold {
+    threshold_bytes: u64,
+    dir: AbsPathBuf,
+}
+
+impl HeapDumpOnThreshold {
+    /// `None` unless a threshold is configured. A relative `buck2.heap_dump_dir` is
+    /// relative to the project root.
+    pub(crate) fn from_config(
+        config: &DaemonStartupConfig,
+        paths: &InvocationPaths,
+    ) -> Option<Self> {
+        let threshold_bytes = config.heap_dump_rss_threshold_bytes?;
+        let dir = match &config.heap_dump_dir {
+            Some(dir) => paths.project_root().root().as_abs_path().join(dir),
+            None => paths.log_dir().into_abs_path_buf(),
+        };
+        Some(Self {
+            threshold_bytes,
+            dir,
+        })
+    }
+
+    pub(crate) fn spawn(self) {
+        tokio::task::spawn(self.run(JemallocHeapDumper));
+    }
+
+    async fn run(self, dumper: impl HeapDumper) {
+        tracing::debug!(
+            "heap will be dumped to `{}` when RSS exceeds {} bytes",
+            self.dir,
+            self.threshold_bytes
+        );
+        let mut last_dump = None;
+        loop {
+            tokio::time::sleep(SAMPLE_INTERVAL).await;
+            self.sample(&dumper, &mut last_dump);
+        }
+    }
+
+    fn sample(&self, dumper: &impl HeapDumper, last_dump: &mut Option<Instant>) {
+        let Some(rss_bytes) = dumper.rss_bytes() else {
+            return;
+        };
+        if rss_bytes < self.threshold_bytes {
+            return;
+        }
+        if let Some(last_dump) = last_dump {
+            if last_dump.elapsed() < MIN_DUMP_INTERVAL {
+                return;
+            }
+        }
+        // Rate limit failed attempts too, so that a misconfigured daemon doesn't log
+        // an error on every sample.
+        *last_dump = Some(Instant::now());
+
+        match self.dump(dumper) {
+            Ok(path) => {
+                tracing::warn!(
+                    "RSS is {} bytes, above the threshold of {} bytes, heap dumped to `{}`",
+                    rss_bytes,
+                    self.threshold_bytes,
+                    path
+                );
+                broadcast_instant_event(&buck2_data::HeapDumpWritten {
+                    path,
+                    rss_bytes,
+                    threshold_bytes: self.threshold_bytes,
+                });
+            }
+            Err(e) => {
+                tracing::warn!(
+                    "RSS is {} bytes, above the threshold of {} bytes, but the heap dump failed: {:#}",
+                    rss_bytes,
+                    self.threshold_bytes,
+                    e
+                );
+            }
+        }
+    }
+
+    fn dump(&self, dumper: &impl HeapDumper) -> buck2_error::Result<String> {
+        fs_util::create_dir_all(&self.dir)?;
+        let path = self.dir.join(format!(
+            "heap-{}.prof",
+            chrono::Local::now().format("%Y%m%d-%H%M%S")
+        ));
+        let path = path
+            .to_str()
+            .buck_error_context("heap dump path is not valid UTF-8")?
+            .to_owned();
+        dumper.write_heap_to_file(&path)?;
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use buck2_events::dispatch::EventDispatcher;
+    use buck2_events::source::ChannelEventSource;
+    use buck2_wrapper_common::invocation_id::TraceId;
+    use parking_lot::Mutex;
+
+    use super::*;
+    use crate::active_commands::ACTIVE_COMMANDS_TEST_LOCK;
+    use crate::active_commands::ActiveCommand;
+
+    #[derive(Clone, Default)]
+    struct StubHeapDumper {
+        rss_bytes: Arc<Mutex<u64>>,
+        dumps: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl HeapDumper for StubHeapDumper {
+        fn rss_bytes(&self) -> Option<u64> {
+            Some(*self.rss_bytes.lock())
+        }
+
+        fn write_heap_to_file(&self, path: &str) -> buck2_error::Result<()> {
+            self.dumps.lock().push(path.to_owned());
+            Ok(())
+        }
+    }
+
+    fn emitted_dumps(events: &mut ChannelEventSource) -> Vec<buck2_data::HeapDumpWritten> {
+        let mut res = Vec::new();
+        while let Some(event) = events.try_receive() {
+            if let buck2_data::buck_event::Data::Instant(instant) =
+                event.unpack_buck().unwrap().data()
+            {
+                if let Some(buck2_data::instant_event::Data::HeapDumpWritten(dump)) = &instant.data
+                {
+                    res.push(dump.clone());
+                }
+            }
+        }
+        res
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_dump_on_threshold() {
+        let _lock = ACTIVE_COMMANDS_TEST_LOCK.lock().await;
+
+        let (mut events, sink) = buck2_events::create_source_sink_pair();
+        let _command = ActiveCommand::new(
+            &EventDispatcher::new(TraceId::new(), sink),
+            Vec::new(),
+            String::new(),
+        );
+
+        let tempdir = tempfile::tempdir().unwrap();
+        let dir = AbsPathBuf::new(tempdir.path().join("dumps")).unwrap();
+        let dumper = StubHeapDumper::default();
+        *dumper.rss_bytes.lock() = 100;
+        tokio::task::spawn(
+            HeapDumpOnThreshold {
+                threshold_bytes: 1000,
+                dir: dir.clone(),
+            }
+            .run(dumper.clone()),
+        );
+
+        tokio::time::sleep(SAMPLE_INTERVAL * 3).await;
+        assert!(dumper.dumps.lock().is_empty());
+
+        *dumper.rss_bytes.lock() = 2000;
+        tokio::time::sleep(SAMPLE_INTERVAL * 3).await;
+        // Only one dump, later samples are rate limited.
+        assert_eq!(dumper.dumps.lock().len(), 1);
+        assert!(dumper.dumps.lock()[0].starts_with(dir.to_str().unwrap()));
+        assert!(fs_util::try_exists(&dir).unwrap());
+
+        let emitted = emitted_dumps(&mut events);
+        assert_eq!(emitted.len(), 1);
+        assert_eq!(emitted[0].path, dumper.dumps.lock()[0]);
+        assert_eq!(emitted[0].rss_bytes, 2000);
+        assert_eq!(emitted[0].threshold_bytes, 1000);
+
+        tokio::time::sleep(MIN_DUMP_INTERVAL).await;
+        assert_eq!(dumper.dumps.lock().len(), 2);
+        assert_eq!(emitted_dumps(&mut events).len(), 1);
+    }
+}
@@ -55,6 +55,9 @@ use buck2_events::source::ChannelEventSource;
 use buck2_execute::digest_config::DigestConfig;
 use buck2_execute::materialize::materializer::MaterializationMethod;
 use buck2_execute_impl::materializers::sqlite::MaterializerStateIdentity;
+use buck2_file_watcher::health::HealthCheckedFileWatcher;
+use buck2_file_watcher::health::WedgedRetry;
+use buck2_forkserver::client::ForkserverClient;
 use buck2_futures::cancellation::CancellationContext;
 use buck2_futures::drop::DropTogether;
 use buck2_futures::spawn::spawn_dropcancel;
@@ -73,6 +76,7 @@ use buck2_server_starlark_debug::run::run_dap_server_command;
 use buck2_test::executor_launcher::get_all_test_executors;
 use buck2_util::system_stats::system_memory_stats;
 use buck2_util::threads::thread_spawn;
+use buck2_wrapper_common::invocation_id::TraceId;
 use dice::DetectCycles;
 use dice::Dice;
 use dice::WhichDice;
@@ -104,7 +108,15 @@ use crate::active_commands::ActiveCommand;
 use crate::active_commands::ActiveCommandStateWriter;
 use crate::clean_stale::clean_stale_command;
 use crate::ctx::ServerCommandContext;
+use crate::daemon::buck_out_layout::verify_buck_out_layout;
+use crate::daemon::client_compatibility::check_client_compatibility;
+use crate::daemon::client_context_validation::validate_client_context;
+use crate::daemon::command_setup::CommandPrologueTimer;
+use crate::daemon::command_setup::CommandSetupFailed;
+use crate::daemon::command_setup::CommandSetupPhase;
+use crate::daemon::command_setup::emit_command_setup_failed;
 use crate::daemon::crash::crash;
+use crate::daemon::heap_dump::HeapDumpOnThreshold;
 use crate::daemon::multi_event_stream::MultiEventStream;
 use crate::daemon::server_allocative::spawn_allocative;
 use crate::daemon::state::DaemonState;
@@ -114,6 +126,7 @@ use crate::new_generic::new_generic_command;
 use crate::profile::profile_command;
 use crate::snapshot;
 use crate::snapshot::SnapshotCollector;
+use crate::soft_error_tally::SoftErrorTally;
 use crate::subscription::run_subscription_server_command;
 use crate::trace_io::trace_io_command;
 use crate::version_control_revision;
@@ -121,6 +134,9 @@ use crate::version_control_revision;
 // TODO(cjhopman): Figure out a reasonable value for this.
 static DEFAULT_KILL_TIMEOUT: Duration = Duration::from_millis(500);
 
+/// How long a kill that waits for commands to drain waits if the client didn't say.
+static DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(300);
+
 static DEFAULT_INACTIVITY_TIMEOUT: Duration = Duration::from_secs(4 * 86400);
 
 pub trait BuckdServerDelegate: Allocative + Send + Sync {
@@ -136,15 +152,31 @@ struct DaemonShutdown {
     /// and once current requests are finished the server will shutdown.
     #[allocative(skip)]
     shutdown_channel: UnboundedSender<()>,
+
+    /// Set once shutdown has started, so that concurrent kills only trigger it once.
+    started: AtomicBool,
 }
 
 impl DaemonShutdown {
+    fn new(delegate: Box<dyn BuckdServerDelegate>, shutdown_channel: UnboundedSender<()>) -> Self {
+        Self {
+            delegate,
+            shutdown_channel,
+            started: AtomicBool::new(false),
+        }
+    }
+
     /// Trigger a graceful server shutdown with a timeout. After the timeout expires, a hard shutdown
-    /// will be triggered.
+    /// will be triggered. Does nothing if shutdown has already started.
     ///
     /// As we might be processing a `kill()` (or other) request, we cannot wait for the server to actually
     /// shutdown (as it will wait for current requests to finish), so this returns immediately.
     fn start_shutdown(&self, reason: buck2_data::DaemonShutdown, timeout: Option<Duration>) {
+        if self.started.swap(true, Ordering::Relaxed) {
+            tracing::debug!("shutdown already started, ignoring: {}", reason.reason);
+            return;
+        }
+
         // It would be better to pass reason to be logged later in a more structured way, but this can't be done via
         // the existing graceful (not forced) shutdown mechanism (serve_with_incoming_shutdown), so logging here instead.
         tracing::warn!("triggered shutdown: {}", reason.reason);
@@ -157,6 +189,30 @@ impl DaemonShutdown {
         self.delegate
             .force_shutdown_with_timeout(reason.to_string(), timeout);
     }
+
+    /// Like `start_shutdown`, but first wait for the active commands to finish, for at most
+    /// `drain_timeout`. Commands still running after that are told about the shutdown as usual.
+    /// The caller must already have stopped accepting new commands.
+    ///
+    /// Returns whether all commands finished in time.
+    async fn drain_and_start_shutdown(
+        &self,
+        reason: buck2_data::DaemonShutdown,
+        timeout: Option<Duration>,
+        drain_timeout: Option<Duration>,
+    ) -> bool {
+        let drain_timeout = drain_timeout.unwrap_or(DEFAULT_DRAIN_TIMEOUT);
+        let drained =
+            crate::active_commands::wait_for_active_commands_to_drain(drain_timeout).await;
+        if !drained {
+            tracing::warn!(
+                "commands still running after waiting {:?} for them to drain",
+                drain_timeout
+            );
+        }
+        self.start_shutdown(reason, timeout);
+        drained
+    }
 }
 
 #[derive(Allocative)]
@@ -216,6 +272,9 @@ pub(crate) struct BuckdServerData {
     #[allocative(skip)]
     process_info: DaemonProcessInfo,
     base_daemon_constraints: buck2_cli_proto::DaemonConstraints,
+    buck_out_layout: buck2_cli_proto::BuckOutLayout,
+    /// From `buck2.check_client_compatibility`.
+    check_client_compatibility: bool,
     start_time: prost_types::Timestamp,
     start_instant: Instant,
     daemon_shutdown: DaemonShutdown,
@@ -262,6 +321,7 @@ impl BuckdServer {
         // Create buck-out and potentially chdir to there.
         fs_util::create_dir_all(paths.buck_out_path())
             .buck_error_context("Error creating buck_out_path")?;
+        let buck_out_layout = verify_buck_out_layout(&paths)?;
 
         // TODO(scottcao): make this not optional
         let cwd = {
@@ -273,10 +333,29 @@ impl BuckdServer {
         let cert_state = CertState::new().await;
         certs_validation_background_job(cert_state.dupe()).await;
 
+        let inactivity_timeout = init_ctx
+            .daemon_startup_config
+            .inactivity_timeout_secs
+            .map(Duration::from_secs);
+
+        let heap_dump_on_threshold =
+            HeapDumpOnThreshold::from_config(&init_ctx.daemon_startup_config, &paths);
+        let check_client_compatibility = init_ctx.daemon_startup_config.check_client_compatibility;
+
         let daemon_state = Arc::new(
             DaemonState::new(fb, paths, init_ctx, rt.clone(), materializations, cwd).await?,
         );
 
+        if let Some(heap_dump_on_threshold) = heap_dump_on_threshold {
+            heap_dump_on_threshold.spawn();
+        }
+
+        // Events not associated with a command, same as the daemon dispatcher in `DaemonState`.
+        let daemon_dispatcher = match daemon_state.data().scribe_sink.dupe() {
+            Some(sink) => EventDispatcher::new(TraceId::null(), sink.to_event_sync()),
+            None => EventDispatcher::null(),
+        };
+
         #[cfg(fbcode_build)]
         {
             let root_path =
@@ -295,15 +374,14 @@ impl BuckdServer {
             stop_accepting_requests: AtomicBool::new(false),
             process_info,
             base_daemon_constraints,
+            buck_out_layout,
+            check_client_compatibility,
             start_time: prost_types::Timestamp {
                 seconds: now.as_secs() as i64,
                 nanos: now.subsec_nanos() as i32,
             },
             start_instant: Instant::now(),
-            daemon_shutdown: DaemonShutdown {
-                delegate,
-                shutdown_channel,
-            },
+            daemon_shutdown: DaemonShutdown::new(delegate, shutdown_channel),
             daemon_state,
             cert_state,
             command_channel,
@@ -311,7 +389,12 @@ impl BuckdServer {
             rt,
         }));
 
-        let shutdown = server_shutdown_signal(command_receiver, shutdown_receiver)?;
+        let shutdown = server_shutdown_signal(
+            command_receiver,
+            shutdown_receiver,
+            inactivity_timeout,
+            daemon_dispatcher,
+        )?;
         let server = Server::builder()
             .layer(interceptor(BuckCheckAuthTokenInterceptor { auth_token }))
             .add_service(
@@ -407,6 +490,7 @@ impl BuckdServer {
         }
 
         let client_ctx = req.get_ref().client_context()?;
+        validate_client_context(client_ctx)?;
 
         // This will reset counters incorrectly if commands are running concurrently.
         // This is fine.
@@ -417,13 +501,26 @@ impl BuckdServer {
         OneshotCommandOptions::pre_run(&opts, self)?;
 
         let daemon_state = self.0.daemon_state.dupe();
-        let trace_id = client_ctx.trace_id.parse()?;
-        let (events, dispatch) = daemon_state.prepare_events(trace_id).await?;
+        let wedged_retry = check_file_watcher(&daemon_state.data().file_watcher, client_ctx)?;
+        let trace_id: TraceId = client_ctx.trace_id.parse()?;
+        let soft_error_tally = client_ctx
+            .fail_on_soft_errors
+            .then(|| SoftErrorTally::register(trace_id.dupe()));
+        let allowed_soft_error_categories = client_ctx.allowed_soft_error_categories.clone();
+        let timer = CommandPrologueTimer::default();
+        let (events, dispatch) = CommandSetupPhase::PrepareEvents
+            .run(
+                &trace_id,
+                &timer,
+                daemon_state.prepare_events(trace_id.dupe()),
+            )
+            .await?;
+        timer.set_dispatcher(dispatch.dupe());
         let ActiveCommand {
             guard,
             daemon_shutdown_channel,
             state,
-        } = ActiveCommand::new(&dispatch, client_ctx.sanitized_argv.clone());
+        } = register_active_command(&dispatch, client_ctx);
         let data = daemon_state.data();
 
         // Fire off a system-wide event to record the memory usage of this process.
@@ -453,7 +550,18 @@ impl BuckdServer {
         // as a baseline.
         let snapshot_collector =
             SnapshotCollector::new(data.dupe(), daemon_state.paths.buck_out_path());
-        dispatch.instant_event(Box::new(snapshot_collector.create_snapshot()));
+        let start_snapshot = CommandSetupPhase::Snapshot
+            .run(&trace_id, &timer, async {
+                let snapshot = snapshot_collector.create_snapshot();
+                dispatch.instant_event(Box::new(snapshot.clone()));
+                Ok(snapshot)
+            })
+            .await?;
+        // Compared to a snapshot taken at the end of the command.
+        let start_snapshot = data
+            .command_snapshot_delta
+            .then(|| (snapshot_collector.dupe(), start_snapshot));
+        let materializer = data.materializer.dupe();
         let cert_state = self.0.cert_state.dupe();
 
         let repo_root = daemon_state.paths.project_root().root().to_buf();
@@ -471,33 +579,56 @@ impl BuckdServer {
             move |req, cancellations| {
                 async move {
                     let result: buck2_error::Result<Res> = try {
-                        let base_context =
-                            daemon_state.prepare_command(dispatch.dupe(), guard).await?;
-
-                        let context = ServerCommandContext::new(
-                            base_context,
-                            req.client_context()?,
-                            opts.starlark_profiler_instrumentation_override(&req)?,
-                            req.build_options(),
-                            &daemon_state.paths,
-                            cert_state.dupe(),
-                            snapshot_collector,
-                            cancellations,
-                        )?;
+                        let base_context = CommandSetupPhase::PrepareCommand
+                            .run(
+                                &trace_id,
+                                &timer,
+                                daemon_state.prepare_command(dispatch.dupe(), guard, &timer),
+                            )
+                            .await
+                            .inspect_err(|e| emit_command_setup_failed(&dispatch, e))?;
+
+                        let context = CommandSetupPhase::CreateContext
+                            .run(&trace_id, &timer, async {
+                                ServerCommandContext::new(
+                                    base_context,
+                                    req.client_context()?,
+                                    opts.starlark_profiler_instrumentation_override(&req)?,
+                                    req.build_options(),
+                                    &daemon_state.paths,
+                                    cert_state.dupe(),
+                                    snapshot_collector,
+                                    cancellations,
+                                )
+                            })
+                            .await
+                            .inspect_err(|e| emit_command_setup_failed(&dispatch, e))?;
 
                         func(&context, PartialResultDispatcher::new(dispatch.dupe()), req).await?
                     };
                     // Do not kill the process prematurely.
                     drop(version_control_revision_collector);
-                    match result {
-                        Ok(_) => dispatch.command_result(result_to_command_result(result)),
-                        Err(e) => match check_cert_state(cert_state).await {
-                            Some(err) => dispatch.command_result(error_to_command_result(
-                                err.context(format!("{e:?}")).into(),
-                            )),
-                            _ => dispatch.command_result(error_to_command_result(e)),
-                        },
-                    }
+                    // The command synced the file watcher, if it was let through to retry.
+                    drop(wedged_retry);
+                    finish_streaming_command(
+                        &dispatch,
+                        result,
+                        cert_state,
+                        soft_error_tally
+                            .as_ref()
+                            .map(|tally| (tally, allowed_soft_error_categories.as_slice())),
+                        &timer,
+                        start_snapshot.map(|(collector, start)| {
+                            move || {
+                                snapshot::command_snapshot_delta(
+                                    &start,
+                                    &collector.create_snapshot(),
+                                )
+                            }
+                        }),
+                        &*materializer,
+                    )
+                    .await;
                 }
                 .boxed()
             },
@@ -530,6 +661,16 @@ impl BuckdServer {
         // send signal to register new command time
         _ = self.0.command_channel.unbounded_send(());
 
+        // Checked here rather than in `run_streaming_anyhow`, whose errors become command results,
+        // so that the client gets a status it can tell apart. A missing client context is
+        // reported by `run_streaming_anyhow`.
+        if let (true, Ok(client_ctx)) = (
+            self.0.check_client_compatibility,
+            req.get_ref().client_context(),
+        ) {
+            check_client_compatibility(client_ctx, &self.0.process_info.version)?;
+        }
+
         match self.run_streaming_anyhow(req, opts, func).await {
             Ok(resp) => Ok(resp),
             Err(e) => match check_cert_state(self.0.cert_state.dupe()).await {
@@ -571,6 +712,62 @@ impl BuckdServer {
     }
 }
 
+/// Registers the command described by `client_ctx` as active for as long as the returned
+/// `ActiveCommand` is alive.
+fn register_active_command(
+    dispatch: &EventDispatcher,
+    client_ctx: &ClientContext,
+) -> ActiveCommand {
+    ActiveCommand::new(
+        dispatch,
+        client_ctx.sanitized_argv.clone(),
+        client_ctx.command_name.clone(),
+    )
+}
+
+/// Rejects the command if the file watcher is wedged, rather than run it on stale file state, unless
+/// it is let through to retry syncing: the returned `WedgedRetry` must live as long as the command.
+fn check_file_watcher(
+    file_watcher: &Arc<HealthCheckedFileWatcher>,
+    client_ctx: &ClientContext,
+) -> buck2_error::Result<Option<WedgedRetry>> {
+    if client_ctx.ignore_wedged_file_watcher {
+        return Ok(None);
+    }
+    file_watcher.check_not_wedged()
+}
+
+/// The active commands to report in response to `req`.
+fn status_active_commands(req: &StatusRequest) -> Vec<ActiveCommandStatus> {
+    if req.include_active_commands {
+        active_commands_status()
+    } else {
+        Vec::new()
+    }
+}
+
+/// Lists the commands currently registered as active, oldest first.
+fn active_commands_status() -> Vec<ActiveCommandStatus> {
+    let mut commands: Vec<_> = crate::active_commands::active_commands()
+        .iter()
+        .map(|(trace_id, handle)| {
+            let state = handle.state();
+            (
+                state.start_time,
+                ActiveCommandStatus {
+                    trace_id: trace_id.to_string(),
+                    argv: state.argv.clone(),
+                    start_time: Some(state.start_time.into()),
+                    command_name: state.command_name.clone(),
+                    argv_summary: state.argv_summary(),
+                },
+            )
+        })
+        .collect();
+    commands.sort_by_key(|(start_time, _)| *start_time);
+    commands.into_iter().map(|(_, status)| status).collect()
+}
+
 fn convert_positive_duration(proto_duration: &prost_types::Duration) -> Result<Duration, Status> {
     if proto_duration.seconds < 0 || proto_duration.nanos < 0 {
         return Err(Status::new(
@@ -587,6 +784,8 @@ fn error_to_command_result(e: buck2_error::Error) -> CommandResult {
         result: Some(command_result::Result::Error(
             buck2_data::ErrorReport::from(&e),
         )),
+        prologue_timings: None,
+        original_result: None,
     }
 }
 
@@ -596,6 +795,8 @@ fn result_to_command_result<R: Into<command_result::Result>>(
     match result {
         Ok(result) => CommandResult {
             result: Some(result.into()),
+            prologue_timings: None,
+            original_result: None,
         },
         Err(e) => error_to_command_result(e),
     }
@@ -610,10 +811,19 @@ fn error_to_command_progress(e: buck2_error::Error) -> CommandProgress {
 }
 
 fn error_to_response_stream(e: buck2_error::Error) -> Response<ResponseStream> {
+    let mut messages = Vec::new();
+    // If setup failed before an event dispatcher existed, nothing has been sent to the client
+    // yet. Synthesize an event so that the event log still records the attempt.
+    if let Some(setup_failed) = e.find_typed_context::<CommandSetupFailed>() {
+        messages.push(CommandProgress {
+            progress: Some(command_progress::Progress::Event(
+                setup_failed.to_buck_event().into(),
+            )),
+        });
+    }
+    messages.push(error_to_command_progress(e));
     tonic::Response::new(Box::pin(stream::once(future::ready(Ok(
-        buck2_cli_proto::MultiCommandProgress {
-            messages: vec![error_to_command_progress(e)],
-        },
+        buck2_cli_proto::MultiCommandProgress { messages },
     )))))
 }
 
@@ -831,36 +1041,38 @@ impl DaemonApi for BuckdServer {
                 .map(convert_positive_duration)
                 .transpose()?;
 
+            let drain_timeout = req
+                .drain_timeout
+                .as_ref()
+                .map(convert_positive_duration)
+                .transpose()?;
+
             let reason = buck2_data::DaemonShutdown {
                 reason: req.reason,
                 callers: req.callers,
             };
 
-            self.0.daemon_shutdown.start_shutdown(reason, timeout);
-            Ok(KillResponse {})
+            let drained = if req.wait_for_drain {
+                Some(
+                    self.0
+                        .daemon_shutdown
+                        .drain_and_start_shutdown(reason, timeout, drain_timeout)
+                        .await,
+                )
+            } else {
+                self.0.daemon_shutdown.start_shutdown(reason, timeout);
+                None
+            };
+            Ok(KillResponse { drained })
         })
         .await
     }
 
     async fn ping(&self, req: Request<PingRequest>) -> Result<Response<CommandResult>, Status> {
-        self.oneshot(req, DefaultCommandOptions, move |req| async move {
-            match &req.delay {
-                Some(delay) => {
-                    let delay = convert_positive_duration(delay)?;
-                    tokio::time::sleep(delay).await;
-                }
-                _ => {}
-            }
-
-            let mut payload = vec![
-                0;
-                req.response_payload_size
-                    .try_into()
-                    .buck_error_context("requested payload too large")?
-            ];
-            rand::rngs::SmallRng::seed_from_u64(10).fill_bytes(&mut payload);
-
-            Ok(PingResponse { payload })
+        let start_instant = self.0.start_instant;
+        let received = start_instant.elapsed();
+        self.oneshot(req, DefaultCommandOptions, move |req| {
+            ping(req, start_instant, received)
         })
         .await
     }
@@ -898,6 +1110,17 @@ impl DaemonApi for BuckdServer {
 
             let io_provider = daemon_state.data().io.name().to_owned();
 
+            let file_watcher = daemon_state.data().file_watcher.health();
+            let file_watcher = buck2_cli_proto::FileWatcherStatus {
+                provider: file_watcher.provider as i32,
+                last_successful_sync: file_watcher.last_successful_sync.map(Into::into),
+                pending_changes: file_watcher.pending_changes,
+                failed_syncs_since_success: file_watcher.failed_syncs_since_success,
+                wedged: file_watcher.wedged,
+            };
+
+            let active_commands = status_active_commands(&req);
+
             let uptime = self.0.start_instant.elapsed();
             let base = StatusResponse {
                 process_info: Some(self.0.process_info.clone()),
@@ -913,6 +1136,10 @@ impl DaemonApi for BuckdServer {
                 valid_working_directory: Some(valid_working_directory),
                 valid_buck_out_mount: Some(valid_buck_out_mount),
                 io_provider: Some(io_provider),
+                active_commands,
+                buck_out_usage: daemon_state.data().buck_out_usage.usage(),
+                buck_out_layout: Some(self.0.buck_out_layout.clone()),
+                file_watcher: Some(file_watcher),
                 ..Default::default()
             };
             Ok(base)
@@ -1275,9 +1502,10 @@ impl DaemonApi for BuckdServer {
 
         let res: buck2_error::Result<_> = try {
             let client_ctx = req.get_ref().client_context()?;
+            validate_client_context(client_ctx)?;
             let trace_id = client_ctx.trace_id.parse()?;
             let (event_source, dispatcher) = self.0.daemon_state.prepare_events(trace_id).await?;
-            let active_command = ActiveCommand::new(&dispatcher, client_ctx.sanitized_argv.clone());
+            let active_command = register_active_command(&dispatcher, client_ctx);
             (event_source, dispatcher, active_command)
         };
 
@@ -1452,28 +1680,15 @@ impl DaemonApi for BuckdServer {
         &self,
         req: Request<SetLogFilterRequest>,
     ) -> Result<Response<SetLogFilterResponse>, Status> {
-        let req = req.into_inner();
-
-        if req.daemon {
-            self.0
-                .log_reload_handle
-                .update_log_filter(&req.log_filter)
-                .buck_error_context("Error updating daemon log filter")
-                .map_err(|e| Status::invalid_argument(format!("{:#}", e)))?;
-        }
-
-        if req.forkserver {
-            let data = self.0.daemon_state.data();
-            if let Some(forkserver) = data.forkserver.as_ref() {
-                forkserver
-                    .set_log_filter(req.log_filter)
-                    .await
-                    .buck_error_context("Error forwarding daemon log filter to forkserver")
-                    .map_err(|e| Status::invalid_argument(format!("{:#}", e)))?;
-            }
-        }
-
-        Ok(Response::new(SetLogFilterResponse {}))
+        let data = self.0.daemon_state.data();
+        Ok(Response::new(
+            set_log_filter(
+                &*self.0.log_reload_handle,
+                data.forkserver.as_ref(),
+                req.into_inner(),
+            )
+            .await,
+        ))
     }
 
     type TraceIoStream = ResponseStream;
@@ -1490,6 +1705,74 @@ impl DaemonApi for BuckdServer {
         )
         .await
     }
+
+    type StreamingPingStream = ResponseStream;
+    async fn streaming_ping(
+        &self,
+        req: Request<StreamingPingRequest>,
+    ) -> Result<Response<ResponseStream>, Status> {
+        self.run_streaming(
+            req,
+            DefaultCommandOptions,
+            |_context, partial_result_dispatcher, req| {
+                streaming_ping(partial_result_dispatcher, req).boxed()
+            },
+        )
+        .await
+    }
+}
+
+/// `received` is when the request was received, relative to `start_instant`.
+async fn ping(
+    req: PingRequest,
+    start_instant: Instant,
+    received: Duration,
+) -> buck2_error::Result<PingResponse> {
+    if let Some(delay) = &req.delay {
+        let delay = convert_positive_duration(delay)?;
+        tokio::time::sleep(delay).await;
+    }
+
+    let payload = ping_payload(req.response_payload_size)?;
+
+    Ok(PingResponse {
+        payload,
+        echo: req.echo,
+        received_nanos: received.as_nanos() as u64,
+        responded_nanos: start_instant.elapsed().as_nanos() as u64,
+    })
+}
+
+fn ping_payload(size: u64) -> buck2_error::Result<Vec<u8>> {
+    let mut payload = vec![
+        0;
+        size.try_into()
+            .buck_error_context("requested payload too large")?
+    ];
+    rand::rngs::SmallRng::seed_from_u64(10).fill_bytes(&mut payload);
+    Ok(payload)
+}
+
+async fn streaming_ping(
+    mut partial_result_dispatcher: PartialResultDispatcher<PingResponse>,
+    req: StreamingPingRequest,
+) -> buck2_error::Result<StreamingPingResponse> {
+    let interval = match &req.interval {
+        Some(interval) => convert_positive_duration(interval)?,
+        None => Duration::ZERO,
+    };
+    let payload = ping_payload(req.response_payload_size)?;
+
+    for i in 0..req.count {
+        if i != 0 {
+            tokio::time::sleep(interval).await;
+        }
+        partial_result_dispatcher.emit(PingResponse {
+            payload: payload.clone(),
+        });
+    }
+
+    Ok(StreamingPingResponse {})
 }
 
 /// Options to configure the execution of a oneshot command (i.e. what happens in `oneshot()`).
@@ -1509,31 +1792,103 @@ trait StreamingCommandOptions<Req>: OneshotCommandOptions {
     }
 }
 
+/// Emits the result of a streaming command, preceded by what the materializer did for it and, if
+/// `snapshot_delta` is set, by what changed in the daemon while it ran.
+async fn finish_streaming_command<Res: Into<command_result::Result>>(
+    dispatch: &EventDispatcher,
+    result: buck2_error::Result<Res>,
+    cert_state: CertState,
+    soft_error_tally: Option<(&SoftErrorTally, &[String])>,
+    timer: &CommandPrologueTimer,
+    snapshot_delta: Option<impl FnOnce() -> buck2_data::CommandSnapshotDelta>,
+    materializer: &dyn Materializer,
+) {
+    let mut command_result = match result {
+        Ok(_) => result_to_command_result(result),
+        Err(e) => match check_cert_state(cert_state).await {
+            Some(err) => error_to_command_result(err.context(format!("{e:?}")).into()),
+            _ => error_to_command_result(e),
+        },
+    };
+    if let Some((soft_error_tally, allowed_soft_error_categories)) = soft_error_tally {
+        command_result =
+            soft_error_tally.fail_on_soft_errors(allowed_soft_error_categories, command_result);
+    }
+    command_result.prologue_timings = Some(timer.to_proto());
+    if let Some(stats) = materializer.take_command_stats(dispatch.trace_id()) {
+        dispatch.instant_event(stats);
+    }
+    if let Some(snapshot_delta) = snapshot_delta {
+        dispatch.instant_event(snapshot_delta());
+    }
+    dispatch.command_result(command_result);
+}
+
+/// Resolves once a shutdown is requested, or once no command was received for
+/// `inactivity_timeout` (4 days if unset).
 fn server_shutdown_signal(
     command_receiver: UnboundedReceiver<()>,
-    mut shutdown_receiver: UnboundedReceiver<()>,
+    shutdown_receiver: UnboundedReceiver<()>,
+    inactivity_timeout: Option<Duration>,
+    dispatcher: EventDispatcher,
 ) -> buck2_error::Result<impl Future<Output = ()>> {
-    let mut duration = DEFAULT_INACTIVITY_TIMEOUT;
-    if buck2_env!(
+    let testing_override = buck2_env!(
         "BUCK2_TESTING_INACTIVITY_TIMEOUT",
         bool,
         applicability = testing
-    )? {
-        duration = Duration::from_secs(1);
+    )?;
+    let duration = effective_inactivity_timeout(inactivity_timeout, testing_override);
+    if duration < Duration::from_secs(60) && !testing_override {
+        tracing::warn!(
+            "inactivity timeout is only {:?}, the daemon will restart often",
+            duration
+        );
     }
+    tracing::info!(
+        "daemon will shut down after {:?} without receiving a command",
+        duration
+    );
 
-    Ok(async move {
-        let timeout = inactivity_timeout(command_receiver, duration);
-        let shutdown = shutdown_receiver.next();
+    Ok(shutdown_signal(
+        command_receiver,
+        shutdown_receiver,
+        duration,
+        dispatcher,
+    ))
+}
 
-        futures::pin_mut!(shutdown);
-        futures::pin_mut!(timeout);
+/// `$BUCK2_TESTING_INACTIVITY_TIMEOUT` overrides the configured timeout.
+fn effective_inactivity_timeout(
+    inactivity_timeout: Option<Duration>,
+    testing_override: bool,
+) -> Duration {
+    if testing_override {
+        Duration::from_secs(1)
+    } else {
+        inactivity_timeout.unwrap_or(DEFAULT_INACTIVITY_TIMEOUT)
+    }
+}
 
-        futures::future::select(timeout, shutdown).await;
-    })
+async fn shutdown_signal(
+    command_receiver: UnboundedReceiver<()>,
+    mut shutdown_receiver: UnboundedReceiver<()>,
+    duration: Duration,
+    dispatcher: EventDispatcher,
+) {
+    let timeout = inactivity_timeout(command_receiver, duration, dispatcher);
+    let shutdown = shutdown_receiver.next();
+
+    futures::pin_mut!(shutdown);
+    futures::pin_mut!(timeout);
+
+    futures::future::select(timeout, shutdown).await;
 }
 
-async fn inactivity_timeout(mut command_receiver: UnboundedReceiver<()>, duration: Duration) {
+async fn inactivity_timeout(
+    mut command_receiver: UnboundedReceiver<()>,
+    duration: Duration,
+    dispatcher: EventDispatcher,
+) {
     // this restarts the timer everytime there is a new command
     while (timeout(duration, command_receiver.next()).await).is_ok() {}
 
@@ -1541,6 +1896,58 @@ async fn inactivity_timeout(mut command_receiver: UnboundedReceiver<()>, duratio
         "inactivity timeout elapsed ({:?}), shutting down server",
         duration
     );
+    // Lets event log consumers tell an idle exit from a crash.
+    dispatcher.instant_event(buck2_data::DaemonInactivityShutdown {
+        timeout: duration.try_into().ok(),
+    });
+}
+
+/// Applies the log filter to the processes `req` asks for. Each one is updated
+/// regardless of whether the others failed.
+async fn set_log_filter(
+    log_reload_handle: &dyn LogConfigurationReloadHandle,
+    forkserver: Option<&ForkserverClient>,
+    req: SetLogFilterRequest,
+) -> SetLogFilterResponse {
+    fn update(result: buck2_error::Result<()>) -> LogFilterUpdate {
+        match result {
+            Ok(()) => LogFilterUpdate {
+                success: true,
+                error: None,
+            },
+            Err(e) => LogFilterUpdate {
+                success: false,
+                error: Some(format!("{:#}", e)),
+            },
+        }
+    }
+
+    let daemon = req.daemon.then(|| {
+        update(
+            log_reload_handle
+                .update_log_filter(&req.log_filter)
+                .buck_error_context("Error updating daemon log filter"),
+        )
+    });
+
+    let forkserver = if req.forkserver {
+        Some(match forkserver {
+            Some(forkserver) => update(
+                forkserver
+                    .set_log_filter(req.log_filter)
+                    .await
+                    .buck_error_context("Error forwarding daemon log filter to forkserver"),
+            ),
+            None => LogFilterUpdate {
+                success: false,
+                error: Some("No forkserver is running".to_owned()),
+            },
+        })
+    } else {
+        None
+    };
+
+    SetLogFilterResponse { daemon, forkserver }
 }
 
 async fn certs_validation_background_job(cert_state: CertState) {
@@ -1630,3 +2037,497 @@ struct DefaultCommandOptions;
 impl OneshotCommandOptions for DefaultCommandOptions {}
 
 impl<Req> StreamingCommandOptions<Req> for DefaultCommandOptions {}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+
+    use buck2_execute::materialize::nodisk::NoDiskMaterializer;
+    use buck2_file_watcher::file_watcher::FileWatcher;
+    use buck2_file_watcher::health::FileWatcherHealthConfig;
+    use buck2_file_watcher::mergebase::Mergebase;
+    use dice::DiceTransactionUpdater;
+    use parking_lot::Mutex;
+
+    use super::*;
+    use crate::active_commands::ACTIVE_COMMANDS_TEST_LOCK;
+
+    #[derive(Allocative, Default)]
+    struct RecordingDelegate {
+        #[allocative(skip)]
+        shutdowns: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl BuckdServerDelegate for RecordingDelegate {
+        fn force_shutdown_with_timeout(&self, reason: String, _timeout: Duration) {
+            self.shutdowns.lock().push(reason);
+        }
+    }
+
+    fn daemon_shutdown() -> (DaemonShutdown, Arc<Mutex<Vec<String>>>) {
+        let delegate = RecordingDelegate::default();
+        let shutdowns = delegate.shutdowns.dupe();
+        let (shutdown_channel, _receiver) = mpsc::unbounded();
+        (
+            DaemonShutdown::new(Box::new(delegate), shutdown_channel),
+            shutdowns,
+        )
+    }
+
+    fn reason(reason: &str) -> buck2_data::DaemonShutdown {
+        buck2_data::DaemonShutdown {
+            reason: reason.to_owned(),
+            callers: Vec::new(),
+        }
+    }
+
+    fn long_command() -> ActiveCommand {
+        ActiveCommand::new(
+            &EventDispatcher::null_sink_with_trace(TraceId::new()),
+            Vec::new(),
+            String::new(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_status_lists_active_commands() {
+        let _lock = ACTIVE_COMMANDS_TEST_LOCK.lock().await;
+
+        let client_context =
+            |trace_id: &TraceId, argv: Vec<String>, command_name: &str| ClientContext {
+                trace_id: trace_id.to_string(),
+                sanitized_argv: argv,
+                command_name: command_name.to_owned(),
+                ..Default::default()
+            };
+        let include_active_commands = StatusRequest {
+            include_active_commands: true,
+            ..Default::default()
+        };
+
+        let trace_id = TraceId::new();
+        let command = register_active_command(
+            &EventDispatcher::null_sink_with_trace(trace_id.dupe()),
+            &client_context(
+                &trace_id,
+                vec!["buck2".to_owned(), "build".to_owned(), "//:foo".to_owned()],
+                "build",
+            ),
+        );
+        let long_trace_id = TraceId::new();
+        let long_argv = vec!["buck2".to_owned(), "targets".to_owned(), "x".repeat(1000)];
+        let long_command = register_active_command(
+            &EventDispatcher::null_sink_with_trace(long_trace_id.dupe()),
+            &client_context(&long_trace_id, long_argv.clone(), "targets"),
+        );
+
+        // Only listed on request.
+        assert!(status_active_commands(&StatusRequest::default()).is_empty());
+
+        let status = status_active_commands(&include_active_commands);
+        assert_eq!(status.len(), 2);
+        let (build, targets) = if status[0].trace_id == trace_id.to_string() {
+            (&status[0], &status[1])
+        } else {
+            (&status[1], &status[0])
+        };
+        assert_eq!(build.command_name, "build");
+        assert_eq!(build.argv, ["buck2", "build", "//:foo"]);
+        assert_eq!(build.argv_summary, "buck2 build //:foo");
+        assert!(build.start_time.is_some());
+        assert_eq!(targets.trace_id, long_trace_id.to_string());
+        assert_eq!(targets.command_name, "targets");
+        // The full argv is still there, only the summary is shortened.
+        assert_eq!(targets.argv, long_argv);
+        assert!(targets.argv_summary.starts_with("buck2 targets x"));
+        assert!(targets.argv_summary.contains("<<omitted>>"));
+        assert!(targets.argv_summary.len() < long_argv.join(" ").len());
+
+        drop(command);
+        drop(long_command);
+        assert!(status_active_commands(&include_active_commands).is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_configured_inactivity_timeout() -> buck2_error::Result<()> {
+        let (command_channel, command_receiver) = mpsc::unbounded();
+        let (_shutdown_channel, shutdown_receiver) = mpsc::unbounded();
+
+        let shutdown = server_shutdown_signal(
+            command_receiver,
+            shutdown_receiver,
+            Some(Duration::from_secs(60)),
+            EventDispatcher::null(),
+        )?;
+        tokio::pin!(shutdown);
+
+        // Commands keep the daemon alive past the timeout.
+        for _ in 0..3 {
+            assert!(
+                tokio::time::timeout(Duration::from_secs(40), shutdown.as_mut())
+                    .await
+                    .is_err()
+            );
+            command_channel.unbounded_send(()).unwrap();
+        }
+
+        // Once the command channel goes quiet, the daemon shuts down after the configured timeout
+        // rather than the default.
+        tokio::time::timeout(Duration::from_secs(61), shutdown)
+            .await
+            .expect("Shutdown after inactivity timeout");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_delta_emitted_once_before_result() {
+        async fn emitted(
+            result: buck2_error::Result<PingResponse>,
+            with_delta: bool,
+        ) -> (Vec<&'static str>, usize) {
+            let (mut events, sink) = buck2_events::create_source_sink_pair();
+            let dispatch = EventDispatcher::new(TraceId::new(), sink);
+            let deltas_computed = AtomicUsize::new(0);
+            finish_streaming_command(
+                &dispatch,
+                result,
+                CertState {
+                    state: Arc::new(tokio::sync::Mutex::new(true)),
+                },
+                None,
+                &CommandPrologueTimer::default(),
+                with_delta.then_some(|| {
+                    deltas_computed.fetch_add(1, Ordering::Relaxed);
+                    buck2_data::CommandSnapshotDelta::default()
+                }),
+                &NoDiskMaterializer,
+            )
+            .await;
+
+            let mut emitted = Vec::new();
+            while let Some(event) = events.try_receive() {
+                emitted.push(match event {
+                    Event::Buck(event) => match event.data() {
+                        buck2_data::buck_event::Data::Instant(buck2_data::InstantEvent {
+                            data: Some(buck2_data::instant_event::Data::CommandSnapshotDelta(_)),
+                        }) => "delta",
+                        _ => "other",
+                    },
+                    Event::CommandResult(_) => "result",
+                    Event::PartialResult(_) => "partial",
+                });
+            }
+            (emitted, deltas_computed.load(Ordering::Relaxed))
+        }
+
+        let error = || {
+            Err(buck2_error::buck2_error!(
+                buck2_error::ErrorTag::Input,
+                "failed"
+            ))
+        };
+
+        assert_eq!(
+            emitted(Ok(PingResponse::default()), true).await,
+            (vec!["delta", "result"], 1)
+        );
+        assert_eq!(emitted(error(), true).await, (vec!["delta", "result"], 1));
+        // Disabled by `buck2.command_snapshot_delta`.
+        assert_eq!(emitted(error(), false).await, (vec!["result"], 0));
+    }
+
+    #[tokio::test]
+    async fn test_ping_echo() -> buck2_error::Result<()> {
+        let start_instant = Instant::now();
+
+        let response = ping(
+            PingRequest::default(),
+            start_instant,
+            start_instant.elapsed(),
+        )
+        .await?;
+        assert!(response.echo.is_empty());
+        assert!(response.payload.is_empty());
+        assert!(response.received_nanos <= response.responded_nanos);
+
+        let echo = (0..(10 << 20)).map(|i| i as u8).collect::<Vec<_>>();
+        let response = ping(
+            PingRequest {
+                echo: echo.clone(),
+                response_payload_size: 1 << 10,
+                ..PingRequest::default()
+            },
+            start_instant,
+            start_instant.elapsed(),
+        )
+        .await?;
+        assert_eq!(response.echo, echo);
+        assert_eq!(response.payload.len(), 1 << 10);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_ping_timestamps_include_delay() -> buck2_error::Result<()> {
+        let start_instant = Instant::now();
+        let received = start_instant.elapsed();
+        let response = ping(
+            PingRequest {
+                delay: Duration::from_millis(20).try_into().ok(),
+                ..PingRequest::default()
+            },
+            start_instant,
+            received,
+        )
+        .await?;
+        assert_eq!(response.received_nanos, received.as_nanos() as u64);
+        assert!(
+            response.responded_nanos - response.received_nanos
+                >= Duration::from_millis(20).as_nanos() as u64
+        );
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_streaming_ping_spacing() -> buck2_error::Result<()> {
+        let (mut events, sink) = buck2_events::create_source_sink_pair();
+        let interval = Duration::from_secs(10);
+        let start = tokio::time::Instant::now();
+        let ping = tokio::spawn(streaming_ping(
+            PartialResultDispatcher::new(EventDispatcher::new(TraceId::new(), sink)),
+            StreamingPingRequest {
+                interval: interval.try_into().ok(),
+                count: 3,
+                response_payload_size: 16,
+                ..StreamingPingRequest::default()
+            },
+        ));
+
+        let mut arrivals = Vec::new();
+        while !ping.is_finished() {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            while let Some(event) = events.try_receive() {
+                match event {
+                    Event::PartialResult(PartialResult {
+                        partial_result: Some(partial_result::PartialResult::PingResponse(keepalive)),
+                    }) => {
+                        assert_eq!(keepalive.payload.len(), 16);
+                        arrivals.push(start.elapsed());
+                    }
+                    _ => panic!("Unexpected event"),
+                }
+            }
+        }
+        ping.await.unwrap()?;
+
+        assert_eq!(arrivals.len(), 3);
+        for (i, arrival) in arrivals.into_iter().enumerate() {
+            let expected = interval * i as u32;
+            assert!(
+                arrival >= expected && arrival < expected + Duration::from_secs(1),
+                "keepalive {i} arrived after {arrival:?}"
+            );
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_set_log_filter_without_forkserver() {
+        let handle = <dyn LogConfigurationReloadHandle>::noop();
+        let request = |daemon, forkserver| SetLogFilterRequest {
+            log_filter: "buck2=debug".to_owned(),
+            daemon,
+            forkserver,
+        };
+
+        // The daemon filter is updated even though there is no forkserver to update.
+        let response = set_log_filter(&*handle, None, request(true, true)).await;
+        assert_eq!(
+            response.daemon,
+            Some(LogFilterUpdate {
+                success: true,
+                error: None,
+            })
+        );
+        assert_eq!(
+            response.forkserver,
+            Some(LogFilterUpdate {
+                success: false,
+                error: Some("No forkserver is running".to_owned()),
+            })
+        );
+
+        // Processes that weren't asked for are not reported.
+        let response = set_log_filter(&*handle, None, request(true, false)).await;
+        assert!(response.daemon.is_some());
+        assert_eq!(response.forkserver, None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_testing_inactivity_timeout_emits_event() {
+        let (_command_channel, command_receiver) = mpsc::unbounded();
+        let (_shutdown_channel, shutdown_receiver) = mpsc::unbounded();
+        let (mut events, sink) = buck2_events::create_source_sink_pair();
+
+        // `$BUCK2_TESTING_INACTIVITY_TIMEOUT` wins over the configured timeout.
+        let duration = effective_inactivity_timeout(Some(Duration::from_secs(60)), true);
+        assert_eq!(duration, Duration::from_secs(1));
+        assert_eq!(
+            effective_inactivity_timeout(None, false),
+            DEFAULT_INACTIVITY_TIMEOUT
+        );
+
+        tokio::time::timeout(
+            Duration::from_secs(2),
+            shutdown_signal(
+                command_receiver,
+                shutdown_receiver,
+                duration,
+                EventDispatcher::new(TraceId::new(), sink),
+            ),
+        )
+        .await
+        .expect("Shutdown after inactivity timeout");
+
+        let event = events.try_receive().expect("Inactivity shutdown event");
+        match event.unpack_buck().unwrap().data() {
+            buck2_data::buck_event::Data::Instant(buck2_data::InstantEvent {
+                data: Some(buck2_data::instant_event::Data::DaemonInactivityShutdown(shutdown)),
+            }) => {
+                assert_eq!(shutdown.timeout, duration.try_into().ok());
+            }
+            data => panic!("Unexpected event: {data:?}"),
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_drain_waits_for_active_commands() {
+        let _lock = ACTIVE_COMMANDS_TEST_LOCK.lock().await;
+        let (shutdown, shutdowns) = daemon_shutdown();
+
+        let command = long_command();
+        let drain =
+            shutdown.drain_and_start_shutdown(reason("drain"), None, Some(Duration::from_secs(60)));
+        tokio::pin!(drain);
+
+        assert!(
+            tokio::time::timeout(Duration::from_secs(10), drain.as_mut())
+                .await
+                .is_err()
+        );
+        assert!(shutdowns.lock().is_empty());
+
+        drop(command);
+        assert!(drain.await);
+        assert_eq!(shutdowns.lock().len(), 1);
+
+        // A second kill doesn't trigger shutdown again.
+        shutdown.start_shutdown(reason("again"), None);
+        assert_eq!(shutdowns.lock().len(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_drain_gives_up_at_deadline() {
+        let _lock = ACTIVE_COMMANDS_TEST_LOCK.lock().await;
+        let (shutdown, shutdowns) = daemon_shutdown();
+
+        let _command = long_command();
+        let start = tokio::time::Instant::now();
+        assert!(
+            !shutdown
+                .drain_and_start_shutdown(reason("drain"), None, Some(Duration::from_secs(60)))
+                .await
+        );
+        assert!(start.elapsed() >= Duration::from_secs(60));
+        assert_eq!(shutdowns.lock().len(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_concurrent_kills_shut_down_once() {
+        let _lock = ACTIVE_COMMANDS_TEST_LOCK.lock().await;
+        let (shutdown, shutdowns) = daemon_shutdown();
+
+        let command = long_command();
+        let drain =
+            shutdown.drain_and_start_shutdown(reason("drain"), None, Some(Duration::from_secs(60)));
+        tokio::pin!(drain);
+        assert!(
+            tokio::time::timeout(Duration::from_secs(1), drain.as_mut())
+                .await
+                .is_err()
+        );
+
+        // A kill that doesn't wait shuts down right away, and the draining one then doesn't.
+        shutdown.start_shutdown(reason("kill"), None);
+        assert_eq!(shutdowns.lock().len(), 1);
+
+        drop(command);
+        assert!(drain.await);
+        assert_eq!(shutdowns.lock().len(), 1);
+    }
+
+    #[derive(Allocative)]
+    struct FlakyFileWatcher {
+        #[allocative(skip)]
+        failing: AtomicBool,
+    }
+
+    #[async_trait]
+    impl FileWatcher for FlakyFileWatcher {
+        async fn sync(
+            &self,
+            dice: DiceTransactionUpdater,
+        ) -> buck2_error::Result<(DiceTransactionUpdater, Mergebase)> {
+            if self.failing.load(Ordering::Relaxed) {
+                return Err(buck2_error::buck2_error!(
+                    buck2_error::ErrorTag::WatchmanTimeout,
+                    "sync failed"
+                ));
+            }
+            Ok((dice, Mergebase::default()))
+        }
+
+        fn provider(&self) -> buck2_data::FileWatcherProvider {
+            buck2_data::FileWatcherProvider::Watchman
+        }
+    }
+
+    #[tokio::test]
+    async fn test_wedged_file_watcher_recovers() -> buck2_error::Result<()> {
+        let inner = Arc::new(FlakyFileWatcher {
+            failing: AtomicBool::new(true),
+        });
+        let file_watcher = Arc::new(HealthCheckedFileWatcher::new(
+            inner.dupe(),
+            FileWatcherHealthConfig {
+                slow_sync_threshold: Duration::from_secs(10),
+                wedged_timeout: Duration::ZERO,
+            },
+        ));
+        let dice = Dice::builder().build(DetectCycles::Enabled);
+        let client_ctx = ClientContext::default();
+        let ignore_wedged = ClientContext {
+            ignore_wedged_file_watcher: true,
+            ..Default::default()
+        };
+
+        assert!(file_watcher.sync(dice.updater()).await.is_err());
+        assert!(file_watcher.health().wedged);
+
+        // The first command is let through to sync, the others are rejected until it is done.
+        let retry = check_file_watcher(&file_watcher, &client_ctx)?.expect("Expected a retry");
+        assert!(check_file_watcher(&file_watcher, &client_ctx).is_err());
+        assert!(check_file_watcher(&file_watcher, &ignore_wedged)?.is_none());
+
+        inner.failing.store(false, Ordering::Relaxed);
+        file_watcher.sync(dice.updater()).await?;
+        drop(retry);
+
+        // The daemon recovered.
+        assert!(!file_watcher.health().wedged);
+        assert!(check_file_watcher(&file_watcher, &client_ctx)?.is_none());
+        assert!(check_file_watcher(&file_watcher, &client_ctx)?.is_none());
+        Ok(())
+    }
+}
@@ -27,6 +27,7 @@ use buck2_common::init::Timeout;
 use buck2_common::invocation_paths::InvocationPaths;
 use buck2_common::io::IoProvider;
 use buck2_common::legacy_configs::cells::BuckConfigBasedCells;
+use buck2_common::legacy_configs::existence_cache::ConfigFileExistenceCache;
 use buck2_common::legacy_configs::key::BuckconfigKeyRef;
 use buck2_common::memory_tracker::MemoryTracker;
 use buck2_common::systemd::SystemdCreationDecision;
@@ -52,19 +53,28 @@ use buck2_events::source::ChannelEventSource;
 use buck2_execute::digest_config::DigestConfig;
 use buck2_execute::execute::blocking::BlockingExecutor;
 use buck2_execute::execute::blocking::BuckBlockingExecutor;
+use buck2_execute::materialize::io_pressure::IoPressureSignals;
 use buck2_execute::materialize::materializer::MaterializationMethod;
 use buck2_execute::materialize::materializer::Materializer;
 use buck2_execute::re::manager::ReConnectionManager;
 use buck2_execute_impl::materializers::deferred::AccessTimesUpdates;
+use buck2_execute_impl::materializers::deferred::ArtifactTreeSweepConfiguration;
 use buck2_execute_impl::materializers::deferred::DeferredMaterializer;
 use buck2_execute_impl::materializers::deferred::DeferredMaterializerConfigs;
 use buck2_execute_impl::materializers::deferred::TtlRefreshConfiguration;
+use buck2_execute_impl::materializers::deferred::TtlRefreshMethods;
+use buck2_execute_impl::materializers::deferred::WriteCompression;
 use buck2_execute_impl::materializers::deferred::clean_stale::CleanStaleConfig;
+use buck2_execute_impl::materializers::deferred::clock_skew::ClockSkewConfig;
+use buck2_execute_impl::materializers::deferred::io_pressure::IoPressureConfig;
+use buck2_execute_impl::materializers::deferred::io_pressure::MaterializerIoPressure;
 use buck2_execute_impl::materializers::sqlite::MaterializerState;
 use buck2_execute_impl::materializers::sqlite::MaterializerStateIdentity;
 use buck2_execute_impl::materializers::sqlite::MaterializerStateSqliteDb;
 use buck2_execute_impl::re::paranoid_download::ParanoidDownloader;
 use buck2_file_watcher::file_watcher::FileWatcher;
+use buck2_file_watcher::health::FileWatcherHealthConfig;
+use buck2_file_watcher::health::HealthCheckedFileWatcher;
 use buck2_forkserver::client::ForkserverClient;
 use buck2_http::HttpClient;
 use buck2_http::HttpClientBuilder;
@@ -84,7 +94,10 @@ use tokio::sync::Mutex;
 
 use crate::active_commands::ActiveCommandDropGuard;
 use crate::ctx::BaseServerCommandContext;
+use crate::daemon::buck_out_usage::BuckOutUsageTracker;
 use crate::daemon::check_working_dir;
+use crate::daemon::command_setup::CommandPrologueTimer;
+use crate::daemon::command_setup::CommandSetupPhase;
 use crate::daemon::disk_state::DiskStateOptions;
 use crate::daemon::disk_state::delete_unknown_disk_state;
 use crate::daemon::disk_state::maybe_initialize_materializer_sqlite_db;
@@ -122,7 +135,7 @@ pub struct DaemonStateData {
     pub(crate) dice_manager: Arc<ConcurrencyHandler>,
 
     /// Synced every time we run a command.
-    pub(crate) file_watcher: Arc<dyn FileWatcher>,
+    pub(crate) file_watcher: Arc<HealthCheckedFileWatcher>,
 
     /// Settled every time we run a command.
     pub io: Arc<dyn IoProvider>,
@@ -142,6 +155,10 @@ pub struct DaemonStateData {
     /// materializations to work properly between distinct build commands.
     pub(crate) materializer: Arc<dyn Materializer>,
 
+    /// Advisory IO pressure signals exchanged between the materializer and local action
+    /// execution, unless disabled.
+    pub(crate) io_pressure: Option<Arc<dyn IoPressureSignals>>,
+
     pub(crate) forkserver: Option<ForkserverClient>,
 
     #[allocative(skip)]
@@ -172,6 +189,10 @@ pub struct DaemonStateData {
     /// the daemon when we hit an error.
     pub enable_restarter: bool,
 
+    /// Whether to emit a `CommandSnapshotDelta` at the end of each command, controlled by
+    /// `buck2.command_snapshot_delta`.
+    pub command_snapshot_delta: bool,
+
     /// Http client used for materializer and RunAction implementations.
     pub http_client: HttpClient,
 
@@ -192,6 +213,10 @@ pub struct DaemonStateData {
 
     /// Tracks data about previous command (e.g. configs)
     pub previous_command_data: Arc<LockedPreviousCommandData>,
+
+    /// Tracks the disk space used by buck-out.
+    #[allocative(skip)]
+    pub(crate) buck_out_usage: Arc<BuckOutUsageTracker>,
 }
 
 impl DaemonStateData {
@@ -415,6 +440,31 @@ impl DaemonState {
                     .unwrap_or_else(RolloutPercentage::never)
                     .roll();
 
+                let ttl_refresh_methods = {
+                    let default = TtlRefreshMethods::default();
+                    TtlRefreshMethods {
+                        cas_download: root_config
+                            .parse(BuckconfigKeyRef {
+                                section: "buck2",
+                                property: "ttl_refresh_cas_download",
+                            })?
+                            .unwrap_or(default.cas_download),
+                        http_download: root_config
+                            .parse(BuckconfigKeyRef {
+                                section: "buck2",
+                                property: "ttl_refresh_http_download",
+                            })?
+                            .unwrap_or(default.http_download),
+                    }
+                };
+
+                let ttl_refresh_history_size = root_config
+                    .parse(BuckconfigKeyRef {
+                        section: "buck2",
+                        property: "ttl_refresh_history_size",
+                    })?
+                    .unwrap_or(256);
+
                 let update_access_times = AccessTimesUpdates::try_new_from_config_value(
                     root_config.get(BuckconfigKeyRef {
                         section: "buck2",
@@ -430,6 +480,7 @@ impl DaemonState {
                     .unwrap_or(false);
 
                 let clean_stale_config = CleanStaleConfig::from_buck_config(root_config)?;
+                let clock_skew = ClockSkewConfig::from_buck_config(root_config)?;
 
                 let disable_eager_write_dispatch = root_config
                     .parse::<RolloutPercentage>(BuckconfigKeyRef {
@@ -439,6 +490,63 @@ impl DaemonState {
                     .unwrap_or_else(RolloutPercentage::never)
                     .roll();
 
+                let artifact_tree_sweep_frequency = root_config
+                    .parse(BuckconfigKeyRef {
+                        section: "buck2",
+                        property: "artifact_tree_sweep_frequency_seconds",
+                    })?
+                    .unwrap_or(300);
+
+                // Off by default: a pruned artifact is unknown to the materializer even if the
+                // action that produced it is still cached.
+                let declared_artifact_idle_ttl = root_config.parse::<i64>(BuckconfigKeyRef {
+                    section: "buck2",
+                    property: "declared_artifact_idle_ttl_hours",
+                })?;
+                let artifact_tree_sweep = ArtifactTreeSweepConfiguration::try_new(
+                    artifact_tree_sweep_frequency,
+                    declared_artifact_idle_ttl,
+                )?;
+
+                let low_priority_queue_capacity = root_config
+                    .parse(BuckconfigKeyRef {
+                        section: "buck2",
+                        property: "materializer_low_priority_queue_capacity",
+                    })?
+                    .unwrap_or(100_000);
+
+                let write_compression = {
+                    let default = WriteCompression::default();
+                    WriteCompression {
+                        level: root_config
+                            .parse(BuckconfigKeyRef {
+                                section: "buck2",
+                                property: "write_compression_level",
+                            })?
+                            .unwrap_or(default.level),
+                        min_size: root_config
+                            .parse(BuckconfigKeyRef {
+                                section: "buck2",
+                                property: "write_compression_min_size_bytes",
+                            })?
+                            .unwrap_or(default.min_size),
+                    }
+                };
+
+                let paranoid_verification = root_config
+                    .parse(BuckconfigKeyRef {
+                        section: "buck2",
+                        property: "materializer_paranoid_verification",
+                    })?
+                    .unwrap_or(false);
+
+                let fail_on_declare_conflicts = root_config
+                    .parse(BuckconfigKeyRef {
+                        section: "buck2",
+                        property: "materializer_fail_on_declare_conflicts",
+                    })?
+                    .unwrap_or(false);
+
                 DeferredMaterializerConfigs {
                     materialize_final_artifacts: matches!(
                         materializations,
@@ -449,11 +557,19 @@ impl DaemonState {
                         frequency: std::time::Duration::from_secs(ttl_refresh_frequency),
                         min_ttl: chrono::Duration::seconds(ttl_refresh_min_ttl),
                         enabled: ttl_refresh_enabled,
+                        methods: ttl_refresh_methods,
+                        history_size: ttl_refresh_history_size,
                     },
                     update_access_times,
                     verbose_materializer_log,
                     clean_stale_config,
+                    clock_skew,
                     disable_eager_write_dispatch,
+                    artifact_tree_sweep,
+                    low_priority_queue_capacity,
+                    write_compression,
+                    paranoid_verification,
+                    fail_on_declare_conflicts,
                 }
             };
             let disable_eager_write_dispatch =
@@ -529,6 +645,8 @@ impl DaemonState {
                 // but for now seems fine to drop events if scribe isn't enabled.
                 EventDispatcher::null()
             };
+            let io_pressure = IoPressureConfig::from_buck_config(root_config)?
+                .map(|config| Arc::new(MaterializerIoPressure::new(config)));
             let materializer = Self::create_materializer(
                 io.project_root().dupe(),
                 digest_config,
@@ -541,7 +659,10 @@ impl DaemonState {
                 materializer_state,
                 http_client.dupe(),
                 daemon_dispatcher,
+                io_pressure.dupe(),
             )?;
+            let buck_out_usage =
+                BuckOutUsageTracker::spawn(paths.buck_out_path(), materializer.dupe());
 
             // Create this after the materializer because it'll want to write to buck-out, and an Eden
             // materializer would create buck-out now.
@@ -573,6 +694,12 @@ impl DaemonState {
                     paths.project_root()
                 )
             })?;
+            let file_watcher = Arc::new(HealthCheckedFileWatcher::new(
+                file_watcher,
+                FileWatcherHealthConfig::from_config(root_config)?,
+            ));
+            // The file watcher now reports every change to project paths.
+            ConfigFileExistenceCache::process().watch_project_paths();
 
             let use_network_action_output_cache = root_config
                 .parse(BuckconfigKeyRef {
@@ -591,6 +718,13 @@ impl DaemonState {
                 .unwrap_or_else(RolloutPercentage::never)
                 .roll();
 
+            let command_snapshot_delta = root_config
+                .parse(BuckconfigKeyRef {
+                    section: "buck2",
+                    property: "command_snapshot_delta",
+                })?
+                .unwrap_or(true);
+
             let paranoid = if init_ctx.daemon_startup_config.paranoid {
                 Some(ParanoidDownloader::new(
                     fs.clone(),
@@ -654,6 +788,7 @@ impl DaemonState {
                 re_client_manager,
                 blocking_executor,
                 materializer,
+                io_pressure: io_pressure.map(|io_pressure| io_pressure as _),
                 forkserver,
                 scribe_sink,
                 use_network_action_output_cache,
@@ -662,6 +797,7 @@ impl DaemonState {
                 create_unhashed_outputs_lock,
                 materializer_state_identity,
                 enable_restarter,
+                command_snapshot_delta,
                 http_client,
                 paranoid,
                 spawner: Arc::new(BuckSpawner::new(daemon_state_data_rt)),
@@ -669,6 +805,7 @@ impl DaemonState {
                 system_warning_config,
                 memory_tracker,
                 previous_command_data: LockedPreviousCommandData::new(),
+                buck_out_usage,
             }))
         })
         .await?
@@ -718,6 +855,7 @@ impl DaemonState {
         materializer_state: Option<MaterializerState>,
         http_client: HttpClient,
         daemon_dispatcher: EventDispatcher,
+        io_pressure: Option<Arc<MaterializerIoPressure>>,
     ) -> buck2_error::Result<Arc<dyn Materializer>> {
         match materializations {
             MaterializationMethod::Deferred | MaterializationMethod::DeferredSkipFinalArtifacts => {
@@ -732,6 +870,7 @@ impl DaemonState {
                     materializer_state,
                     http_client,
                     daemon_dispatcher,
+                    io_pressure,
                 )?))
             }
         }
@@ -767,10 +906,11 @@ impl DaemonState {
     /// Prepares a ServerCommandContext for processing a complex command (that accesses the dice computation graph, for example).
     ///
     /// This initializes (if necessary) the shared daemon state and syncs the watchman query (to flush any recent filesystem events).
-    pub async fn prepare_command(
+    pub(crate) async fn prepare_command(
         &self,
         dispatcher: EventDispatcher,
         drop_guard: ActiveCommandDropGuard,
+        timer: &CommandPrologueTimer,
     ) -> buck2_error::Result<BaseServerCommandContext> {
         let data = self.data();
 
@@ -798,8 +938,13 @@ impl DaemonState {
 
         // Sync any FS changes and invalidate DICE state if necessary.  Get the Eden
         // version of the underlying system in parallel if available.
-        let (_, eden_version) =
-            futures::future::try_join(data.io.settle(), data.io.eden_version()).await?;
+        let (_, eden_version) = CommandSetupPhase::FileWatcherSync
+            .run(
+                dispatcher.trace_id(),
+                timer,
+                futures::future::try_join(data.io.settle(), data.io.eden_version()),
+            )
+            .await?;
 
         dispatcher.instant_event(buck2_data::IoProviderInfo { eden_version });
 
@@ -28,6 +28,7 @@ mod net_io;
 pub(crate) mod new_generic;
 pub mod profile;
 mod snapshot;
+pub mod soft_error_tally;
 mod subscription;
 mod trace_io;
 mod version_control_revision;
@@ -17,6 +17,7 @@ use buck2_core::io_counters::IoCounterKey;
 use buck2_error::BuckErrorContext;
 use buck2_events::EventSinkStats;
 use buck2_execute::re::manager::ReConnectionManager;
+use buck2_node::nodes::configured_recompute_stats::add_configured_node_recompute_snapshot_stats;
 use buck2_util::process_stats::process_stats;
 use buck2_util::system_stats::UnixSystemStats;
 use dupe::Dupe;
@@ -36,6 +37,30 @@ pub struct SnapshotCollector {
     cpu_usage_collector: Option<CpuUsageCollector>,
 }
 
+/// What changed between the snapshots taken at the start and at the end of a command.
+pub(crate) fn command_snapshot_delta(
+    start: &buck2_data::Snapshot,
+    end: &buck2_data::Snapshot,
+) -> buck2_data::CommandSnapshotDelta {
+    fn delta(start: u64, end: u64) -> i64 {
+        (end as i128 - start as i128) as i64
+    }
+
+    buck2_data::CommandSnapshotDelta {
+        buck2_rss_delta: match (start.buck2_rss, end.buck2_rss) {
+            (Some(start), Some(end)) => Some(delta(start, end)),
+            _ => None,
+        },
+        deferred_materializer_queue_size_delta: delta(
+            start.deferred_materializer_queue_size,
+            end.deferred_materializer_queue_size,
+        ),
+        deferred_materializer_declares: end
+            .deferred_materializer_declares
+            .saturating_sub(start.deferred_materializer_declares),
+    }
+}
+
 impl SnapshotCollector {
     pub fn new(daemon: Arc<DaemonStateData>, buck_out_path: AbsNormPathBuf) -> SnapshotCollector {
         SnapshotCollector {
@@ -65,6 +90,7 @@ impl SnapshotCollector {
     fn add_daemon_metrics(&self, snapshot: &mut buck2_data::Snapshot) {
         snapshot.blocking_executor_io_queue_size =
             self.daemon.blocking_executor.queue_size() as u64;
+        snapshot.buck_out_bytes = self.daemon.buck_out_usage.usage().map(|usage| usage.bytes);
     }
 
     fn add_io_metrics(&self, snapshot: &mut buck2_data::Snapshot) {
@@ -176,6 +202,7 @@ impl SnapshotCollector {
         snapshot.dice_key_count = metrics.key_count as u64;
         snapshot.dice_currently_active_key_count = metrics.currently_active_key_count as u64;
         snapshot.dice_active_transaction_count = metrics.active_transaction_count;
+        add_configured_node_recompute_snapshot_stats(snapshot);
     }
 
     fn add_materializer_metrics(&self, snapshot: &mut buck2_data::Snapshot) {
@@ -290,3 +317,38 @@ impl SnapshotCollector {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_snapshot_delta() {
+        let start = buck2_data::Snapshot {
+            buck2_rss: Some(1000),
+            deferred_materializer_queue_size: 10,
+            deferred_materializer_declares: 100,
+            ..Default::default()
+        };
+        let end = buck2_data::Snapshot {
+            buck2_rss: Some(400),
+            deferred_materializer_queue_size: 15,
+            deferred_materializer_declares: 130,
+            ..Default::default()
+        };
+        assert_eq!(
+            command_snapshot_delta(&start, &end),
+            buck2_data::CommandSnapshotDelta {
+                buck2_rss_delta: Some(-600),
+                deferred_materializer_queue_size_delta: 5,
+                deferred_materializer_declares: 30,
+            }
+        );
+
+        let end = buck2_data::Snapshot {
+            buck2_rss: None,
+            ..end
+        };
+        assert_eq!(command_snapshot_delta(&start, &end).buck2_rss_delta, None);
+    }
+}
@@ -0,0 +1,183 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Per command counts of soft errors, for commands run with `--fail-on-soft-errors`.
+//!
+//! The soft error counters in `buck2_core` are global and capped, so they can't tell which command
+//! a soft error fired in, or how often.
+
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::sync::Arc;
+
+use buck2_cli_proto::CommandResult;
+use buck2_cli_proto::command_result;
+use buck2_error::ErrorTag;
+use buck2_events::dispatch::get_dispatcher_opt;
+use buck2_wrapper_common::invocation_id::TraceId;
+use dupe::Dupe;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+
+type Counts = Arc<Mutex<BTreeMap<String, u64>>>;
+
+static TALLIES: Lazy<Mutex<HashMap<TraceId, Counts>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Records a soft error against the command it fired in. Soft errors fired outside of any command
+/// (e.g. in the materializer) are recorded against all commands, like their events are broadcast
+/// to all commands.
+pub fn record_soft_error(category: &str) {
+    let tallies = TALLIES.lock();
+    if tallies.is_empty() {
+        return;
+    }
+    let increment = |counts: &Counts| *counts.lock().entry(category.to_owned()).or_default() += 1;
+    match get_dispatcher_opt() {
+        Some(dispatcher) => {
+            if let Some(counts) = tallies.get(dispatcher.trace_id()) {
+                increment(counts);
+            }
+        }
+        None => tallies.values().for_each(increment),
+    }
+}
+
+/// Counts the soft errors fired in a command while it is alive.
+pub(crate) struct SoftErrorTally {
+    trace_id: TraceId,
+    counts: Counts,
+}
+
+impl SoftErrorTally {
+    pub(crate) fn register(trace_id: TraceId) -> SoftErrorTally {
+        let counts = Counts::default();
+        TALLIES.lock().insert(trace_id.dupe(), counts.dupe());
+        SoftErrorTally { trace_id, counts }
+    }
+
+    /// Turns a successful `result` into a failure if a soft error whose category isn't in
+    /// `allowed_categories` fired. The original result is kept in `original_result`.
+    pub(crate) fn fail_on_soft_errors(
+        &self,
+        allowed_categories: &[String],
+        mut result: CommandResult,
+    ) -> CommandResult {
+        if matches!(result.result, None | Some(command_result::Result::Error(_))) {
+            return result;
+        }
+
+        let allowed: HashSet<&str> = allowed_categories.iter().map(|c| c.as_str()).collect();
+        let mut fired = String::new();
+        for (category, count) in self.counts.lock().iter() {
+            if !allowed.contains(category.as_str()) {
+                write!(fired, "\n  `{}`: {} time(s)", category, count).unwrap();
+            }
+        }
+        if fired.is_empty() {
+            return result;
+        }
+
+        let error = buck2_error::buck2_error!(
+            ErrorTag::Input,
+            "Soft errors fired with `--fail-on-soft-errors`:{}\n\
+            Allow a category with `--allow-soft-error` or `buck2.allowed_soft_errors` in the root buckconfig",
+            fired
+        );
+        let original = std::mem::replace(
+            &mut result.result,
+            Some(command_result::Result::Error(
+                buck2_data::ErrorReport::from(&error),
+            )),
+        );
+        result.original_result = Some(Box::new(CommandResult {
+            result: original,
+            ..Default::default()
+        }));
+        result
+    }
+}
+
+impl Drop for SoftErrorTally {
+    fn drop(&mut self) {
+        TALLIES.lock().remove(&self.trace_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use buck2_cli_proto::GenericResponse;
+    use buck2_events::dispatch::EventDispatcher;
+    use buck2_events::dispatch::with_dispatcher;
+
+    use super::*;
+
+    fn success() -> CommandResult {
+        CommandResult {
+            result: Some(command_result::Result::GenericResponse(GenericResponse {})),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_only_disallowed_categories_fail() {
+        let trace_id = TraceId::new();
+        let tally = SoftErrorTally::register(trace_id.dupe());
+        let allowed = vec!["noisy_category".to_owned()];
+
+        with_dispatcher(EventDispatcher::null_sink_with_trace(trace_id), || {
+            record_soft_error("noisy_category");
+            record_soft_error("noisy_category");
+        });
+        assert_eq!(success(), tally.fail_on_soft_errors(&allowed, success()));
+
+        // Fired in another command.
+        with_dispatcher(
+            EventDispatcher::null_sink_with_trace(TraceId::new()),
+            || record_soft_error("latent_issue"),
+        );
+        assert_eq!(success(), tally.fail_on_soft_errors(&allowed, success()));
+
+        // Fired outside of any command.
+        record_soft_error("latent_issue");
+        let result = tally.fail_on_soft_errors(&allowed, success());
+        let Some(command_result::Result::Error(report)) = &result.result else {
+            panic!("Expected an error, got {:?}", result);
+        };
+        assert!(
+            report.message.contains("`latent_issue`: 1 time(s)"),
+            "{}",
+            report.message
+        );
+        assert!(
+            !report.message.contains("noisy_category"),
+            "{}",
+            report.message
+        );
+        assert_eq!(
+            Some(success().result),
+            result.original_result.map(|r| r.result)
+        );
+    }
+
+    #[test]
+    fn test_failures_are_kept() {
+        let tally = SoftErrorTally::register(TraceId::new());
+        with_dispatcher(
+            EventDispatcher::null_sink_with_trace(tally.trace_id.dupe()),
+            || record_soft_error("latent_issue"),
+        );
+        let failure = CommandResult {
+            result: Some(command_result::Result::Error(Default::default())),
+            ..Default::default()
+        };
+        assert_eq!(failure, tally.fail_on_soft_errors(&[], failure.clone()));
+    }
+}
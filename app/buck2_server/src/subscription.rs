@@ -70,6 +70,12 @@ pub(crate) async fn run_subscription_server_command(
                                 let paths = paths.into_try_map(|path| path.try_into())?;
                                 materializer_subscription.unsubscribe_from_paths(paths);
                             }
+                            Request::SubscribeToPrefix(buck2_subscription_proto::SubscribeToPrefix { prefix }) => {
+                                materializer_subscription.subscribe_to_prefix(prefix.try_into()?);
+                            }
+                            Request::UnsubscribeFromPrefix(buck2_subscription_proto::UnsubscribeFromPrefix { prefix }) => {
+                                materializer_subscription.unsubscribe_from_prefix(prefix.try_into()?);
+                            }
                             Request::SubscribeToActiveCommands(buck2_subscription_proto::SubscribeToActiveCommands {}) => {
                                 wants_active_commands = true;
                             }
@@ -52,6 +52,7 @@ use buck2_error::BuckErrorContext;
 use buck2_events::dispatch::console_message;
 use buck2_events::dispatch::instant_event;
 use buck2_events::dispatch::span_async;
+use buck2_execute::materialize::materializer::HasMaterializer;
 use buck2_node::configured_universe::CqueryUniverse;
 use buck2_node::load_patterns::MissingTargetBehavior;
 use buck2_node::nodes::frontend::TargetGraphCalculation;
@@ -72,10 +73,14 @@ use futures::stream::futures_unordered::FuturesUnordered;
 use itertools::Either;
 use itertools::Itertools;
 
+use crate::commands::build::export_outputs::export_outputs;
+use crate::commands::build::output_manifest::write_output_manifest;
 use crate::commands::build::result_report::ResultReporter;
 use crate::commands::build::result_report::ResultReporterOptions;
 use crate::commands::build::unhashed_outputs::create_unhashed_outputs;
 
+mod export_outputs;
+mod output_manifest;
 #[allow(unused)]
 mod result_report;
 mod unhashed_outputs;
@@ -309,6 +314,32 @@ async fn process_build_result(
         provider_artifacts.extend(&mut outputs);
     }
 
+    if let Some(output_manifest) = &request.output_manifest {
+        let materializer = ctx.per_transaction_data().get_materializer();
+        write_output_manifest(
+            &provider_artifacts,
+            &artifact_fs,
+            &*materializer,
+            fs,
+            cwd,
+            output_manifest,
+        )
+        .await?;
+    }
+
+    if let Some(export_dir) = &request.export_dir {
+        let materializer = ctx.per_transaction_data().get_materializer();
+        export_outputs(
+            &provider_artifacts,
+            &artifact_fs,
+            &*materializer,
+            fs,
+            cwd,
+            export_dir,
+        )
+        .await?;
+    }
+
     let should_create_unhashed_links = ctx
         .parse_legacy_config_property(
             cell_resolver.root_cell(),
@@ -0,0 +1,70 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use std::fmt::Write;
+
+use buck2_build_api::build::ProviderArtifacts;
+use buck2_core::fs::artifact_path_resolver::ArtifactFs;
+use buck2_core::fs::fs_util;
+use buck2_core::fs::project::ProjectRoot;
+use buck2_core::fs::project_rel_path::ProjectRelativePath;
+use buck2_error::BuckErrorContext;
+use buck2_execute::materialize::materializer::Materializer;
+
+use crate::commands::build::output_manifest::default_output_paths;
+
+/// Copy the default outputs in `provider_artifacts` to `export_dir`, relative to `cwd`. Outputs
+/// keep their path relative to buck-out, so outputs of different targets don't collide.
+pub(crate) async fn export_outputs(
+    provider_artifacts: &[ProviderArtifacts],
+    artifact_fs: &ArtifactFs,
+    materializer: &dyn Materializer,
+    fs: &ProjectRoot,
+    cwd: &ProjectRelativePath,
+    export_dir: &str,
+) -> buck2_error::Result<()> {
+    let export_dir = fs.resolve(cwd).as_abs_path().join(export_dir);
+    fs_util::create_dir_all(&export_dir)?;
+    // Canonicalized so that the materializer can tell whether it overlaps buck-out.
+    let export_dir = fs_util::canonicalize(&export_dir)?;
+
+    let buck_out = artifact_fs.buck_out_path_resolver().root();
+    let mut pairs = Vec::new();
+    for path in default_output_paths(provider_artifacts, artifact_fs)? {
+        let dest = export_dir
+            .join(path.strip_prefix(buck_out)?)
+            .into_abs_path_buf();
+        pairs.push((path, dest));
+    }
+
+    let results = materializer
+        .export_artifacts(pairs.clone())
+        .await
+        .buck_error_context("Error exporting outputs")?;
+
+    let mut errors = String::new();
+    let mut error_count = 0;
+    for ((path, _dest), res) in pairs.iter().zip(results) {
+        if let Err(e) = res {
+            error_count += 1;
+            writeln!(errors, "  {}: {:#}", path, e).unwrap();
+        }
+    }
+    if error_count > 0 {
+        return Err(buck2_error::buck2_error!(
+            buck2_error::ErrorTag::CopyOutputs,
+            "Failed to export {} of {} outputs to `{}`:\n{}",
+            error_count,
+            pairs.len(),
+            export_dir,
+            errors
+        ));
+    }
+    Ok(())
+}
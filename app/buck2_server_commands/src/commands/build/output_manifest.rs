@@ -0,0 +1,80 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use buck2_artifact::artifact::artifact_type::BaseArtifactKind;
+use buck2_build_api::build::BuildProviderType;
+use buck2_build_api::build::ProviderArtifacts;
+use buck2_core::fs::artifact_path_resolver::ArtifactFs;
+use buck2_core::fs::fs_util;
+use buck2_core::fs::project::ProjectRoot;
+use buck2_core::fs::project_rel_path::ProjectRelativePath;
+use buck2_core::fs::project_rel_path::ProjectRelativePathBuf;
+use buck2_error::BuckErrorContext;
+use buck2_execute::materialize::materializer::Materializer;
+
+/// Project relative paths of the default outputs in `provider_artifacts`, sorted.
+///
+/// Outputs are described by the build artifact that was materialized, so a projected output is
+/// described by the artifact it was projected from. Source artifacts are skipped.
+pub(crate) fn default_output_paths(
+    provider_artifacts: &[ProviderArtifacts],
+    artifact_fs: &ArtifactFs,
+) -> buck2_error::Result<Vec<ProjectRelativePathBuf>> {
+    let mut paths = Vec::new();
+    for provider_artifact in provider_artifacts {
+        if !matches!(provider_artifact.provider_type, BuildProviderType::Default) {
+            continue;
+        }
+
+        for (artifact, value) in provider_artifact.values.iter() {
+            if let (BaseArtifactKind::Build(build), _projected_path) = artifact.as_parts() {
+                paths.push(
+                    artifact_fs.resolve_build(
+                        build.get_path(),
+                        if build.get_path().is_content_based_path() {
+                            Some(value.content_based_path_hash())
+                        } else {
+                            None
+                        }
+                        .as_ref(),
+                    )?,
+                );
+            }
+        }
+    }
+    paths.sort();
+    paths.dedup();
+    Ok(paths)
+}
+
+/// Write a manifest of the default outputs in `provider_artifacts` to `output_manifest`, relative
+/// to `cwd`.
+pub(crate) async fn write_output_manifest(
+    provider_artifacts: &[ProviderArtifacts],
+    artifact_fs: &ArtifactFs,
+    materializer: &dyn Materializer,
+    fs: &ProjectRoot,
+    cwd: &ProjectRelativePath,
+    output_manifest: &str,
+) -> buck2_error::Result<()> {
+    let paths = default_output_paths(provider_artifacts, artifact_fs)?;
+
+    let manifest = materializer
+        .generate_manifest(paths)
+        .await
+        .buck_error_context("Error generating output manifest")?;
+
+    let path = fs.resolve(cwd).as_abs_path().join(output_manifest);
+    if let Some(parent) = path.parent() {
+        fs_util::create_dir_all(parent)?;
+    }
+    fs_util::write(&path, manifest.to_json()?)
+        .buck_error_context("Error writing output manifest")?;
+    Ok(())
+}
@@ -7,8 +7,6 @@
  * of this source tree.
  */
 
-#![allow(clippy::drop_non_drop)] // FIXME?
-
 use std::collections::BTreeMap;
 use std::fmt::Display;
 use std::fmt::Formatter;
@@ -28,6 +26,7 @@ use buck2_query::query::syntax::simple::eval::multi_query::MultiQueryResult;
 use buck2_query::query::syntax::simple::eval::set::TargetSet;
 use buck2_query::query::syntax::simple::eval::values::QueryEvaluationValue;
 use buck2_util::indent::indent;
+use buck2_util::json_writer::JsonWriter;
 use dupe::Clone_;
 use dupe::Copy_;
 use dupe::Dupe_;
@@ -38,7 +37,7 @@ use regex::RegexSet;
 use serde::Serialize;
 use serde::Serializer;
 use serde::ser::SerializeMap;
-use serde::ser::SerializeSeq;
+use serde_json::ser::PrettyFormatter;
 
 use crate::commands::query::QueryCommandError;
 use crate::commands::query::query_target_ext::QueryCommandTarget;
@@ -184,16 +183,26 @@ impl<T: QueryCommandTarget> Serialize for PrintableQueryTarget<'_, T> {
     }
 }
 
-impl<T: QueryCommandTarget> Serialize for TargetSetJsonPrinter<'_, T> {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
+impl<T: QueryCommandTarget> TargetSetJsonPrinter<'_, T> {
+    fn write_json<W: Write>(
+        &self,
+        writer: &mut JsonWriter<W, PrettyFormatter<'static>>,
+    ) -> buck2_error::Result<()> {
         if self.is_complex {
-            serializer.collect_map(self.value.iter().map(|target| (target.label(), target)))
+            writer.begin_object()?;
+            for target in &self.value {
+                writer.key(&target.label())?;
+                writer.value(target)?;
+            }
+            writer.end_object()?;
         } else {
-            serializer.collect_seq(self.value.iter().map(|target| target.label()))
+            writer.begin_array()?;
+            for target in &self.value {
+                writer.display(target.value.node_key())?;
+            }
+            writer.end_array()?;
         }
+        Ok(())
     }
 }
 
@@ -202,22 +211,17 @@ struct FileSetJsonPrinter<'a> {
     resolver: &'a CellResolver,
 }
 
-impl Serialize for FileSetJsonPrinter<'_> {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        let mut seq = serializer.serialize_seq(None)?;
+impl FileSetJsonPrinter<'_> {
+    fn write_json<W: Write>(
+        &self,
+        writer: &mut JsonWriter<W, PrettyFormatter<'static>>,
+    ) -> buck2_error::Result<()> {
+        writer.begin_array()?;
         for file in self.value.iter() {
-            seq.serialize_element(
-                &self
-                    .resolver
-                    .resolve_path(file.as_ref())
-                    .map_err(serde::ser::Error::custom)?
-                    .to_string(),
-            )?;
+            writer.display(self.resolver.resolve_path(file.as_ref())?)?;
         }
-        SerializeSeq::end(seq)
+        writer.end_array()?;
+        Ok(())
     }
 }
 
@@ -277,40 +281,34 @@ impl<'a> QueryResultPrinter<'a> {
                 let multi_result = multi_result.0;
                 let mut captured_error = Ok(());
 
-                let mut ser = serde_json::Serializer::pretty(&mut output);
-                let mut seq = ser.serialize_map(Some(multi_result.len()))?;
+                let mut writer = JsonWriter::pretty(&mut output);
+                writer.begin_object()?;
                 for (arg, result) in multi_result {
+                    writer.key(&arg)?;
                     match result {
                         Ok(v) => match v {
-                            QueryEvaluationValue::TargetSet(targets) => seq.serialize_entry(
-                                &arg,
-                                &TargetSetJsonPrinter::new(
-                                    target_call_stacks,
-                                    print_providers,
-                                    &self.attributes,
-                                    &targets,
-                                )
-                                .await?,
-                            )?,
-                            QueryEvaluationValue::FileSet(files) => seq.serialize_entry(
-                                &arg,
-                                &FileSetJsonPrinter {
-                                    resolver: self.resolver,
-                                    value: &files,
-                                },
-                            )?,
+                            QueryEvaluationValue::TargetSet(targets) => TargetSetJsonPrinter::new(
+                                target_call_stacks,
+                                print_providers,
+                                &self.attributes,
+                                &targets,
+                            )
+                            .await?
+                            .write_json(&mut writer)?,
+                            QueryEvaluationValue::FileSet(files) => FileSetJsonPrinter {
+                                resolver: self.resolver,
+                                value: &files,
+                            }
+                            .write_json(&mut writer)?,
                         },
                         Err(e) => {
-                            seq.serialize_entry(
-                                &arg,
-                                &serde_json::json!({ "$error": format!("{:#}", e) }),
-                            )?;
+                            writer.value(&serde_json::json!({ "$error": format!("{:#}", e) }))?;
                             captured_error = Err(e);
                         }
                     }
                 }
-                SerializeMap::end(seq)?;
-                std::mem::drop(ser);
+                writer.end_object()?;
+                writer.finish()?;
                 // need to add a newline to flush the output.
                 writeln!(&mut output)?;
                 Ok(captured_error?)
@@ -381,7 +379,7 @@ impl<'a> QueryResultPrinter<'a> {
                     }
                 }
                 QueryOutputFormatInfo::Json => {
-                    let mut ser = serde_json::Serializer::pretty(&mut output);
+                    let mut writer = JsonWriter::pretty(&mut output);
                     TargetSetJsonPrinter::new(
                         call_stack,
                         print_providers,
@@ -389,8 +387,8 @@ impl<'a> QueryResultPrinter<'a> {
                         &targets,
                     )
                     .await?
-                    .serialize(&mut ser)?;
-                    std::mem::drop(ser);
+                    .write_json(&mut writer)?;
+                    writer.finish()?;
                     // need to add a newline to flush the output.
                     writeln!(&mut output)?
                 }
@@ -431,13 +429,13 @@ impl<'a> QueryResultPrinter<'a> {
                         }
                     }
                     QueryOutputFormatInfo::Json => {
-                        let mut ser = serde_json::Serializer::pretty(&mut output);
+                        let mut writer = JsonWriter::pretty(&mut output);
                         FileSetJsonPrinter {
                             resolver: self.resolver,
                             value: &files,
                         }
-                        .serialize(&mut ser)?;
-                        std::mem::drop(ser);
+                        .write_json(&mut writer)?;
+                        writer.finish()?;
                         // need to add a newline to flush the output.
                         writeln!(&mut output)?;
                     }
@@ -14,6 +14,7 @@ use buck2_core::logging::log_file::TracingLogFile;
 use buck2_data::BuildResult;
 use buck2_events::dispatch::span_async;
 use buck2_execute::materialize::materializer::HasMaterializer;
+use buck2_node::nodes::configured_recompute_stats::HasConfiguredNodeRecomputeStats;
 use dice::DiceTransaction;
 
 use crate::commands::command_end_ext;
@@ -92,8 +93,21 @@ pub async fn run_server_command<T: ServerCommandTemplate>(
                     ctx.per_transaction_data()
                         .get_materializer()
                         .log_materializer_state(server_ctx.events());
+                    let recompute_stats = ctx
+                        .per_transaction_data()
+                        .get_configured_node_recompute_stats();
 
-                    command.command(server_ctx, partial_result_dispatcher, ctx)
+                    async move {
+                        let result = command
+                            .command(server_ctx, partial_result_dispatcher, ctx)
+                            .await;
+                        if let Some(recompute_stats) = recompute_stats {
+                            server_ctx
+                                .events()
+                                .instant_event(recompute_stats.to_proto());
+                        }
+                        result
+                    }
                 },
                 command.exclusive_command_name(),
                 Some(command_start),
@@ -0,0 +1,489 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Streaming JSON writer.
+//!
+//! Commands like `query` can produce very large JSON documents. Building a
+//! `serde_json::Value` (or collecting everything into a serializable container)
+//! first means the whole document is held in memory before anything is written.
+//! [`JsonWriter`] instead writes each token to the sink as it is produced, so memory
+//! use is bounded by the nesting depth rather than by the size of the output.
+//!
+//! Output is byte-for-byte identical to what `serde_json` produces for the same
+//! document with the same formatter.
+
+use std::fmt;
+use std::fmt::Display;
+use std::io;
+use std::io::Write;
+
+use serde::Serialize;
+use serde_json::ser::CharEscape;
+use serde_json::ser::CompactFormatter;
+use serde_json::ser::Formatter;
+use serde_json::ser::PrettyFormatter;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Container {
+    Array { first: bool },
+    Object { first: bool, after_key: bool },
+}
+
+/// Writes a JSON document to `W` one token at a time.
+///
+/// Containers are opened and closed with `begin_*`/`end_*`, object members are
+/// introduced with [`JsonWriter::key`], and everything else is a leaf value.
+/// Misuse (e.g. a value in an object without a key, or unbalanced containers) is a
+/// programming error and panics.
+pub struct JsonWriter<W: Write, F: Formatter = CompactFormatter> {
+    out: W,
+    formatter: F,
+    /// Currently open containers, innermost last.
+    stack: Vec<Container>,
+}
+
+impl<W: Write> JsonWriter<W> {
+    /// Writer producing the same output as `serde_json::to_writer`.
+    pub fn compact(out: W) -> Self {
+        Self::with_formatter(out, CompactFormatter)
+    }
+}
+
+impl<W: Write> JsonWriter<W, PrettyFormatter<'static>> {
+    /// Writer producing the same output as `serde_json::to_writer_pretty`.
+    pub fn pretty(out: W) -> Self {
+        Self::with_formatter(out, PrettyFormatter::new())
+    }
+}
+
+impl<W: Write, F: Formatter + Clone> JsonWriter<W, F> {
+    pub fn with_formatter(out: W, formatter: F) -> Self {
+        Self {
+            out,
+            formatter,
+            stack: Vec::new(),
+        }
+    }
+
+    fn begin_value(&mut self) -> io::Result<()> {
+        match self.stack.last_mut() {
+            None => Ok(()),
+            Some(Container::Array { first }) => {
+                let was_first = *first;
+                *first = false;
+                self.formatter.begin_array_value(&mut self.out, was_first)
+            }
+            Some(Container::Object { after_key, .. }) => {
+                assert!(*after_key, "JSON object value written without a key");
+                Ok(())
+            }
+        }
+    }
+
+    fn end_value(&mut self) -> io::Result<()> {
+        match self.stack.last_mut() {
+            None => Ok(()),
+            Some(Container::Array { .. }) => self.formatter.end_array_value(&mut self.out),
+            Some(Container::Object { after_key, .. }) => {
+                *after_key = false;
+                self.formatter.end_object_value(&mut self.out)
+            }
+        }
+    }
+
+    pub fn begin_array(&mut self) -> io::Result<()> {
+        self.begin_value()?;
+        self.stack.push(Container::Array { first: true });
+        self.formatter.begin_array(&mut self.out)
+    }
+
+    pub fn end_array(&mut self) -> io::Result<()> {
+        match self.stack.pop() {
+            Some(Container::Array { .. }) => {}
+            c => panic!("`end_array` called with open container {c:?}"),
+        }
+        self.formatter.end_array(&mut self.out)?;
+        self.end_value()
+    }
+
+    pub fn begin_object(&mut self) -> io::Result<()> {
+        self.begin_value()?;
+        self.stack.push(Container::Object {
+            first: true,
+            after_key: false,
+        });
+        self.formatter.begin_object(&mut self.out)
+    }
+
+    pub fn end_object(&mut self) -> io::Result<()> {
+        match self.stack.pop() {
+            Some(Container::Object {
+                after_key: false, ..
+            }) => {}
+            c => panic!("`end_object` called with open container {c:?}"),
+        }
+        self.formatter.end_object(&mut self.out)?;
+        self.end_value()
+    }
+
+    /// Start an object member. Must be followed by exactly one value.
+    pub fn key(&mut self, key: &str) -> io::Result<()> {
+        let was_first = match self.stack.last_mut() {
+            Some(Container::Object { first, after_key }) if !*after_key => {
+                let was_first = *first;
+                *first = false;
+                *after_key = true;
+                was_first
+            }
+            c => panic!("JSON object key written with open container {c:?}"),
+        };
+        self.formatter.begin_object_key(&mut self.out, was_first)?;
+        self.write_str(key)?;
+        self.formatter.end_object_key(&mut self.out)?;
+        self.formatter.begin_object_value(&mut self.out)
+    }
+
+    pub fn string(&mut self, value: &str) -> io::Result<()> {
+        self.begin_value()?;
+        self.write_str(value)?;
+        self.end_value()
+    }
+
+    /// Write the `Display` of `value` as a JSON string, without formatting it to an
+    /// intermediate `String` first.
+    pub fn display(&mut self, value: impl Display) -> io::Result<()> {
+        self.begin_value()?;
+        self.formatter.begin_string(&mut self.out)?;
+        let mut escaper = Escaper {
+            out: &mut self.out,
+            formatter: &mut self.formatter,
+            error: None,
+        };
+        if fmt::write(&mut escaper, format_args!("{value}")).is_err() {
+            return Err(escaper
+                .error
+                .unwrap_or_else(|| io::Error::other("formatter error")));
+        }
+        self.formatter.end_string(&mut self.out)?;
+        self.end_value()
+    }
+
+    pub fn bool(&mut self, value: bool) -> io::Result<()> {
+        self.begin_value()?;
+        self.formatter.write_bool(&mut self.out, value)?;
+        self.end_value()
+    }
+
+    pub fn null(&mut self) -> io::Result<()> {
+        self.begin_value()?;
+        self.formatter.write_null(&mut self.out)?;
+        self.end_value()
+    }
+
+    pub fn u64(&mut self, value: u64) -> io::Result<()> {
+        self.begin_value()?;
+        self.formatter.write_u64(&mut self.out, value)?;
+        self.end_value()
+    }
+
+    pub fn i64(&mut self, value: i64) -> io::Result<()> {
+        self.begin_value()?;
+        self.formatter.write_i64(&mut self.out, value)?;
+        self.end_value()
+    }
+
+    /// Non-finite values are written as `null`, like `serde_json` does.
+    pub fn f64(&mut self, value: f64) -> io::Result<()> {
+        self.begin_value()?;
+        if value.is_finite() {
+            self.formatter.write_f64(&mut self.out, value)?;
+        } else {
+            self.formatter.write_null(&mut self.out)?;
+        }
+        self.end_value()
+    }
+
+    /// Write any serializable value with `serde_json`, indented to fit at the current
+    /// position in the document.
+    pub fn value<T: Serialize + ?Sized>(&mut self, value: &T) -> io::Result<()> {
+        self.begin_value()?;
+        // The formatter only carries indentation state, which is the same before and
+        // after a complete value, so serializing with a copy of it is equivalent.
+        let mut serializer =
+            serde_json::Serializer::with_formatter(&mut self.out, self.formatter.clone());
+        value.serialize(&mut serializer).map_err(io::Error::from)?;
+        self.end_value()
+    }
+
+    /// Finish the document and return the sink. All containers must have been closed.
+    pub fn finish(mut self) -> io::Result<W> {
+        assert!(
+            self.stack.is_empty(),
+            "JSON document finished with {} open containers",
+            self.stack.len()
+        );
+        self.out.flush()?;
+        Ok(self.out)
+    }
+
+    fn write_str(&mut self, value: &str) -> io::Result<()> {
+        self.formatter.begin_string(&mut self.out)?;
+        write_escaped(&mut self.out, &mut self.formatter, value)?;
+        self.formatter.end_string(&mut self.out)
+    }
+}
+
+/// Same escaping rules as `serde_json`: quote, backslash and control characters are
+/// escaped, everything else (including non-ASCII) is written as is.
+fn write_escaped<W: Write, F: Formatter>(
+    out: &mut W,
+    formatter: &mut F,
+    value: &str,
+) -> io::Result<()> {
+    let bytes = value.as_bytes();
+    let mut start = 0;
+    for (i, &byte) in bytes.iter().enumerate() {
+        let escape = match byte {
+            b'"' => CharEscape::Quote,
+            b'\\' => CharEscape::ReverseSolidus,
+            b'\x08' => CharEscape::Backspace,
+            b'\x0c' => CharEscape::FormFeed,
+            b'\n' => CharEscape::LineFeed,
+            b'\r' => CharEscape::CarriageReturn,
+            b'\t' => CharEscape::Tab,
+            0x00..=0x1f => CharEscape::AsciiControl(byte),
+            _ => continue,
+        };
+        if start < i {
+            formatter.write_string_fragment(out, &value[start..i])?;
+        }
+        formatter.write_char_escape(out, escape)?;
+        start = i + 1;
+    }
+    if start < bytes.len() {
+        formatter.write_string_fragment(out, &value[start..])?;
+    }
+    Ok(())
+}
+
+/// Adapts `fmt::Write` to escaped string contents, keeping the underlying IO error.
+struct Escaper<'a, W, F> {
+    out: &'a mut W,
+    formatter: &'a mut F,
+    error: Option<io::Error>,
+}
+
+impl<W: Write, F: Formatter> fmt::Write for Escaper<'_, W, F> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        write_escaped(self.out, self.formatter, s).map_err(|e| {
+            self.error = Some(e);
+            fmt::Error
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::alloc::GlobalAlloc;
+    use std::alloc::Layout;
+    use std::alloc::System;
+    use std::cell::Cell;
+
+    use serde_json::Value;
+    use serde_json::json;
+
+    use super::*;
+
+    fn write_value<W: Write, F: Formatter + Clone>(
+        writer: &mut JsonWriter<W, F>,
+        value: &Value,
+    ) -> io::Result<()> {
+        match value {
+            Value::Null => writer.null(),
+            Value::Bool(b) => writer.bool(*b),
+            Value::Number(n) => {
+                if let Some(n) = n.as_u64() {
+                    writer.u64(n)
+                } else if let Some(n) = n.as_i64() {
+                    writer.i64(n)
+                } else {
+                    writer.f64(n.as_f64().unwrap())
+                }
+            }
+            Value::String(s) => writer.string(s),
+            Value::Array(items) => {
+                writer.begin_array()?;
+                for item in items {
+                    write_value(writer, item)?;
+                }
+                writer.end_array()
+            }
+            Value::Object(map) => {
+                writer.begin_object()?;
+                for (k, v) in map {
+                    writer.key(k)?;
+                    write_value(writer, v)?;
+                }
+                writer.end_object()
+            }
+        }
+    }
+
+    fn tricky_values() -> Vec<Value> {
+        vec![
+            json!(null),
+            json!("plain"),
+            json!("quote \" backslash \\ slash / tab \t newline \n cr \r"),
+            json!("\u{0} \u{8} \u{c} \u{1f} \u{7f} \u{80}"),
+            json!("unicode: é ß 漢字 🦀 \u{2028} \u{fffd}"),
+            json!(""),
+            json!(0.1),
+            json!(-0.0),
+            json!(1e300),
+            json!(f64::MIN_POSITIVE),
+            json!(f64::NAN),
+            json!(1.5e-7),
+            json!(u64::MAX),
+            json!(i64::MIN),
+            json!(0),
+            json!(true),
+            json!([]),
+            json!({}),
+            json!([[]]),
+            json!([{}]),
+            json!({"a": {}, "b": [], "c": [[], {}], "d": {"e": []}}),
+            json!({"k\"ey\n": [1, "two", 3.0, null, {"x": [true, false]}]}),
+            json!([[[1, [2, []]], {}], "end"]),
+        ]
+    }
+
+    #[test]
+    fn test_matches_serde_json_compact() {
+        for value in tricky_values() {
+            let mut writer = JsonWriter::compact(Vec::new());
+            write_value(&mut writer, &value).unwrap();
+            let written = writer.finish().unwrap();
+            assert_eq!(
+                String::from_utf8(written).unwrap(),
+                serde_json::to_string(&value).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_matches_serde_json_pretty() {
+        for value in tricky_values() {
+            let mut writer = JsonWriter::pretty(Vec::new());
+            write_value(&mut writer, &value).unwrap();
+            let written = writer.finish().unwrap();
+            assert_eq!(
+                String::from_utf8(written).unwrap(),
+                serde_json::to_string_pretty(&value).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_serde_and_display_leaves() {
+        let nested = json!({"x": [1, {"y": []}], "z": "\u{1}"});
+        let expected = json!({"a": nested, "b": ["1 + 1 = \"2\"\n", nested]});
+
+        let mut writer = JsonWriter::pretty(Vec::new());
+        writer.begin_object().unwrap();
+        writer.key("a").unwrap();
+        writer.value(&nested).unwrap();
+        writer.key("b").unwrap();
+        writer.begin_array().unwrap();
+        writer
+            .display(format_args!("{} + {} = \"{}\"\n", 1, 1, 2))
+            .unwrap();
+        writer.value(&nested).unwrap();
+        writer.end_array().unwrap();
+        writer.end_object().unwrap();
+
+        assert_eq!(
+            String::from_utf8(writer.finish().unwrap()).unwrap(),
+            serde_json::to_string_pretty(&expected).unwrap()
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "without a key")]
+    fn test_object_value_without_key() {
+        let mut writer = JsonWriter::compact(Vec::new());
+        writer.begin_object().unwrap();
+        writer.null().unwrap();
+    }
+
+    /// Counts allocations made on the current thread while enabled.
+    struct CountingAlloc;
+
+    thread_local! {
+        static COUNT_ALLOCATIONS: Cell<bool> = const { Cell::new(false) };
+        static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    unsafe impl GlobalAlloc for CountingAlloc {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            if COUNT_ALLOCATIONS.with(|c| c.get()) {
+                ALLOCATIONS.with(|a| a.set(a.get() + 1));
+            }
+            unsafe { System.alloc(layout) }
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            unsafe { System.dealloc(ptr, layout) }
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAlloc = CountingAlloc;
+
+    /// Discards output but remembers how much was written.
+    struct CountingSink(usize);
+
+    impl Write for CountingSink {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0 += buf.len();
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_million_element_array_bounded_allocations() {
+        let mut writer = JsonWriter::pretty(CountingSink(0));
+        writer.begin_object().unwrap();
+        writer.key("items").unwrap();
+        writer.begin_array().unwrap();
+
+        COUNT_ALLOCATIONS.with(|c| c.set(true));
+        for i in 0..1_000_000u64 {
+            writer.begin_object().unwrap();
+            writer.key("name").unwrap();
+            writer.display(format_args!("target_{i}")).unwrap();
+            writer.key("index").unwrap();
+            writer.u64(i).unwrap();
+            writer.key("ratio").unwrap();
+            writer.f64(i as f64 / 7.0).unwrap();
+            writer.end_object().unwrap();
+        }
+        COUNT_ALLOCATIONS.with(|c| c.set(false));
+
+        writer.end_array().unwrap();
+        writer.end_object().unwrap();
+        let sink = writer.finish().unwrap();
+
+        assert!(sink.0 > 50_000_000, "wrote {} bytes", sink.0);
+        assert_eq!(ALLOCATIONS.with(|a| a.get()), 0);
+    }
+}
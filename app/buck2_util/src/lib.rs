@@ -20,6 +20,7 @@ pub mod future;
 pub mod golden_test_helper;
 pub mod hash;
 pub mod indent;
+pub mod json_writer;
 pub mod late_binding;
 pub mod network_speed_average;
 pub mod os;
@@ -25,4 +25,5 @@ pub(crate) mod projection;
 pub(crate) mod storage_type;
 pub(crate) mod transaction;
 pub(crate) mod user_data;
+pub(crate) mod validity;
 pub(crate) mod which;
@@ -23,6 +23,7 @@ use crate::api::data::DiceData;
 use crate::api::key::Key;
 use crate::api::opaque::OpaqueValue;
 use crate::api::user_data::UserComputationData;
+use crate::api::validity::DiceKeyValidity;
 use crate::ctx::DiceComputationsImpl;
 use crate::ctx::LinearRecomputeDiceComputationsImpl;
 
@@ -344,6 +345,18 @@ impl DiceComputations<'_> {
     pub fn get_invalidation_paths(&mut self) -> DiceKeyTrackedInvalidationPaths {
         self.0.get_invalidation_paths()
     }
+
+    /// Reports whether DICE has a value for the key that is valid at this version, without
+    /// computing it or checking its dependencies. This does not record a dependency on the key.
+    pub fn peek_validity<K>(
+        &self,
+        key: &K,
+    ) -> impl Future<Output = DiceKeyValidity<<K as Key>::Value>> + use<K>
+    where
+        K: Key,
+    {
+        self.0.peek_validity(key)
+    }
 }
 
 pub struct LinearRecomputeDiceComputations<'a>(pub(crate) LinearRecomputeDiceComputationsImpl<'a>);
@@ -0,0 +1,38 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use gazebo::variants::VariantName;
+
+/// What DICE currently knows about a key at the version of a computation context, as returned
+/// by [`crate::DiceComputations::peek_validity`].
+#[derive(Debug, Clone, PartialEq, Eq, VariantName)]
+pub enum DiceKeyValidity<V> {
+    /// The key has a value that is valid at this version, requesting it will not recompute it.
+    Valid(V),
+    /// The key has a value from an earlier version, but some of its transitive dependencies have
+    /// changed since. Requesting it will check its dependencies and recompute it if any of them
+    /// changed value, which may still produce a value equal to this one.
+    Dirty(V),
+    /// DICE has no value for the key, requesting it will compute it from scratch.
+    Missing,
+}
+
+impl<V> DiceKeyValidity<V> {
+    /// The last known value for the key, whether or not it is still valid.
+    pub fn value(&self) -> Option<&V> {
+        match self {
+            DiceKeyValidity::Valid(v) | DiceKeyValidity::Dirty(v) => Some(v),
+            DiceKeyValidity::Missing => None,
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        matches!(self, DiceKeyValidity::Valid(_))
+    }
+}
@@ -25,6 +25,7 @@ use crate::api::key::Key;
 use crate::api::opaque::OpaqueValue;
 use crate::api::user_data::UserComputationData;
 use crate::api::user_data::UserCycleDetectorGuard;
+use crate::api::validity::DiceKeyValidity;
 use crate::impls::ctx::LinearRecomputeModern;
 use crate::impls::ctx::ModernComputeCtx;
 use crate::opaque::OpaqueValueImpl;
@@ -204,6 +205,18 @@ impl DiceComputationsImpl<'_> {
             DiceComputationsImpl::Modern(delegate) => delegate.get_invalidation_paths(),
         }
     }
+
+    pub(crate) fn peek_validity<K>(
+        &self,
+        key: &K,
+    ) -> impl Future<Output = DiceKeyValidity<<K as Key>::Value>> + use<K>
+    where
+        K: Key,
+    {
+        match self {
+            DiceComputationsImpl::Modern(delegate) => delegate.peek_validity(key),
+        }
+    }
 }
 
 pub(crate) enum LinearRecomputeDiceComputationsImpl<'a> {
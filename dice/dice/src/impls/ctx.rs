@@ -38,10 +38,13 @@ use crate::api::invalidation_tracking::DiceKeyTrackedInvalidationPaths;
 use crate::api::key::Key;
 use crate::api::projection::ProjectionKey;
 use crate::api::user_data::UserComputationData;
+use crate::api::validity::DiceKeyValidity;
 use crate::ctx::DiceComputationsImpl;
 use crate::ctx::LinearRecomputeDiceComputationsImpl;
 use crate::impls::cache::DiceTaskRef;
 use crate::impls::cache::SharedCache;
+use crate::impls::core::graph::types::VersionedGraphKey;
+use crate::impls::core::graph::types::VersionedGraphResult;
 use crate::impls::core::state::CoreStateHandle;
 use crate::impls::core::versions::VersionEpoch;
 use crate::impls::deps::RecordedDeps;
@@ -615,6 +618,16 @@ impl ModernComputeCtx<'_> {
     pub(crate) fn cycle_guard<T: UserCycleDetectorGuard>(&self) -> DiceResult<Option<Arc<T>>> {
         self.ctx_data().cycle_guard()
     }
+
+    pub(crate) fn peek_validity<K>(
+        &self,
+        key: &K,
+    ) -> impl Future<Output = DiceKeyValidity<<K as Key>::Value>> + use<K>
+    where
+        K: Key,
+    {
+        self.ctx_data().peek_validity(key)
+    }
 }
 
 impl CoreCtx {
@@ -736,6 +749,52 @@ impl CoreCtx {
     pub(crate) fn cycle_guard<T: UserCycleDetectorGuard>(&self) -> DiceResult<Option<Arc<T>>> {
         self.cycles.cycle_guard()
     }
+
+    /// Look up the key in the versioned graph at this version. Unlike computing the key, this
+    /// never spawns a task or checks the key's deps, so a key whose deps were invalidated is
+    /// reported as dirty even if recomputing it would produce an equal value.
+    pub(crate) fn peek_validity<K>(
+        &self,
+        key: &K,
+    ) -> impl Future<Output = DiceKeyValidity<<K as Key>::Value>> + use<K>
+    where
+        K: Key,
+    {
+        let dice_key = self
+            .async_evaluator
+            .dice
+            .key_index
+            .index(CowDiceKeyHashed::key_ref(key));
+
+        let lookup = self
+            .async_evaluator
+            .dice
+            .state_handle
+            .lookup_key(VersionedGraphKey::new(self.get_version(), dice_key));
+
+        async move {
+            match lookup.await {
+                VersionedGraphResult::Match(entry) => DiceKeyValidity::Valid(
+                    entry
+                        .value()
+                        .downcast_maybe_transient::<K::Value>()
+                        .expect("Type mismatch when peeking key")
+                        .dupe(),
+                ),
+                VersionedGraphResult::CheckDeps(mismatch) => DiceKeyValidity::Dirty(
+                    mismatch
+                        .entry
+                        .downcast_ref::<K::Value>()
+                        .expect("Type mismatch when peeking key")
+                        .dupe(),
+                ),
+                // A graph that is being cleared no longer has a value for anything.
+                VersionedGraphResult::Compute | VersionedGraphResult::Rejected(_) => {
+                    DiceKeyValidity::Missing
+                }
+            }
+        }
+    }
 }
 
 /// Context that is shared for all current live computations of the same version.
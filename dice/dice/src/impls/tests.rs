@@ -15,3 +15,4 @@ mod keys;
 mod spawner;
 mod transients;
 mod user_data;
+mod validity;
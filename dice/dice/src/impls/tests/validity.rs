@@ -0,0 +1,108 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use std::sync::Arc;
+
+use allocative::Allocative;
+use async_trait::async_trait;
+use buck2_futures::cancellation::CancellationContext;
+use derive_more::Display;
+use dupe::Dupe;
+
+use crate::api::computations::DiceComputations;
+use crate::api::cycles::DetectCycles;
+use crate::api::injected::InjectedKey;
+use crate::api::key::Key;
+use crate::api::validity::DiceKeyValidity;
+use crate::impls::dice::DiceModern;
+
+#[derive(Clone, Dupe, Debug, Display, Eq, Hash, PartialEq, Allocative)]
+#[display("{:?}", self)]
+struct File(u32);
+
+impl InjectedKey for File {
+    type Value = Arc<str>;
+
+    fn equality(x: &Self::Value, y: &Self::Value) -> bool {
+        x == y
+    }
+}
+
+#[derive(Clone, Dupe, Debug, Display, Eq, Hash, PartialEq, Allocative)]
+#[display("{:?}", self)]
+struct Len(File);
+
+#[async_trait]
+impl Key for Len {
+    type Value = usize;
+
+    async fn compute(
+        &self,
+        ctx: &mut DiceComputations,
+        _cancellations: &CancellationContext,
+    ) -> Self::Value {
+        ctx.compute(&self.0).await.unwrap().len()
+    }
+
+    fn equality(x: &Self::Value, y: &Self::Value) -> bool {
+        x == y
+    }
+}
+
+#[tokio::test]
+async fn peek_validity_does_not_compute() -> anyhow::Result<()> {
+    let dice = DiceModern::builder().build(DetectCycles::Disabled);
+
+    let mut updater = dice.updater();
+    updater.changed_to([(File(0), Arc::from("a")), (File(1), Arc::from("b"))])?;
+    let mut ctx = updater.commit().await;
+
+    assert_eq!(
+        ctx.peek_validity(&Len(File(0))).await,
+        DiceKeyValidity::Missing
+    );
+    // Peeking doesn't compute the key, so it is still missing.
+    assert_eq!(
+        ctx.peek_validity(&Len(File(0))).await,
+        DiceKeyValidity::Missing
+    );
+
+    assert_eq!(ctx.compute(&Len(File(0))).await?, 1);
+    assert_eq!(ctx.compute(&Len(File(1))).await?, 1);
+    assert_eq!(
+        ctx.peek_validity(&Len(File(0))).await,
+        DiceKeyValidity::Valid(1)
+    );
+
+    let mut updater = ctx.into_updater();
+    updater.changed_to([(File(0), Arc::from("aa"))])?;
+    let mut ctx = updater.commit().await;
+
+    // Only the key that depends on the changed file is dirty, and it still has its old value.
+    assert_eq!(
+        ctx.peek_validity(&Len(File(0))).await,
+        DiceKeyValidity::Dirty(1)
+    );
+    assert_eq!(
+        ctx.peek_validity(&Len(File(1))).await,
+        DiceKeyValidity::Valid(1)
+    );
+    assert_eq!(
+        ctx.peek_validity(&File(0)).await,
+        DiceKeyValidity::Valid(Arc::from("aa"))
+    );
+
+    assert_eq!(ctx.compute(&Len(File(0))).await?, 2);
+    assert_eq!(
+        ctx.peek_validity(&Len(File(0))).await,
+        DiceKeyValidity::Valid(2)
+    );
+
+    Ok(())
+}
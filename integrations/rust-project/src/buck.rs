@@ -23,6 +23,7 @@ use rustc_hash::FxHashSet;
 use serde::Deserialize;
 use tracing::Level;
 use tracing::enabled;
+use tracing::error;
 use tracing::info;
 use tracing::instrument;
 use tracing::trace;
@@ -38,6 +39,7 @@ use crate::json_project::Runnable;
 use crate::json_project::RunnableKind;
 use crate::json_project::Source;
 use crate::json_project::Sysroot;
+use crate::json_project::remove_test_only_crates;
 use crate::target::AliasedTargetInfo;
 use crate::target::ExpandedAndResolved;
 use crate::target::Kind;
@@ -47,22 +49,45 @@ use crate::target::TargetInfo;
 
 const CLIENT_METADATA_RUST_PROJECT: &str = "--client-metadata=id=rust-project";
 
+/// How [`Buck::expand_and_resolve`] resolves the requested targets.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ResolveOptions {
+    pub(crate) exclude_workspaces: bool,
+    /// Fail if any dependency can't be resolved, rather than leaving out the
+    /// crates that need it.
+    pub(crate) strict: bool,
+}
+
+/// How [`to_json_project`] turns the resolved targets into crates.
+#[derive(Debug, Clone)]
+pub(crate) struct JsonProjectOptions {
+    pub(crate) cycle_check: CycleCheck,
+    /// Describe how to build every crate, not only the workspace members.
+    pub(crate) include_all_buildfiles: bool,
+    /// Keep test crates, and the crates only they depend on.
+    pub(crate) include_tests: bool,
+    /// Cfgs set on every crate.
+    pub(crate) extra_cfgs: Vec<String>,
+}
+
 pub(crate) fn to_json_project(
+    project_root: &Path,
     sysroot: Sysroot,
     expanded_and_resolved: ExpandedAndResolved,
     aliases: FxHashMap<Target, AliasedTargetInfo>,
-    check_cycles: bool,
-    include_all_buildfiles: bool,
-    extra_cfgs: &[String],
+    options: &JsonProjectOptions,
 ) -> Result<JsonProject, anyhow::Error> {
-    let mode = select_mode(None);
-    let buck = Buck::new(mode);
-    let project_root = buck.resolve_project_root()?;
-
+    let JsonProjectOptions {
+        cycle_check,
+        include_all_buildfiles,
+        include_tests,
+        extra_cfgs,
+    } = options;
     let ExpandedAndResolved {
         expanded_targets: _,
         queried_proc_macros: proc_macros,
         resolved_deps: target_map,
+        missing_deps: _,
     } = expanded_and_resolved;
 
     let target_index = merge_unit_test_targets(target_map);
@@ -97,8 +122,10 @@ pub(crate) fn to_json_project(
     }
 
     let mut crates: Vec<Crate> = Vec::with_capacity(targets_vec.len());
+    let mut is_test: Vec<bool> = Vec::with_capacity(targets_vec.len());
     for target in &targets_vec {
         let info = target_index.get(&target).unwrap();
+        is_test.push(info.kind == Kind::Test);
 
         let dep_targets = resolve_aliases(&info.deps, &aliases, &proc_macros);
         let deps = as_deps(&dep_targets, info, &targets_to_ids, &target_index);
@@ -124,12 +151,12 @@ pub(crate) fn to_json_project(
 
         // We don't need to push the source folder as rust-analyzer by default will use the root-module parent().
         // info.root_module() will output either the fbcode source file or the symlinked one based on if it's a mapped source or not
-        let root_module = info.root_module(&project_root);
+        let root_module = info.root_module(project_root);
 
         let mut env = FxHashMap::default();
 
-        // Populate the environment variables the target configuration's environment variables,
-        // but ignore OUT_DIR as we handle that later.
+        // Populate the environment variables the target configuration's environment variables.
+        // Paths in them are fixed up below.
         env.extend(info.env.clone().into_iter());
 
         // If $CARGO_MANIFEST_DIR is set, resolve it to an absolute path.
@@ -142,19 +169,23 @@ pub(crate) fn to_json_project(
         }
 
         let mut include_dirs = FxHashSet::default();
-        if let Some(out_dir) = info.env.get("OUT_DIR") {
+        if let Some(rel_out_dir) = info.env.get("OUT_DIR") {
+            // $OUT_DIR is the build script output, which buck reports relative to the project
+            // root. rust-analyzer resolves `env!("OUT_DIR")` on its own, so it must be absolute.
+            let out_dir = project_root.join(rel_out_dir);
             // to ensure that the `OUT_DIR` is included as part of the `PackageRoot` in rust-analyzer,
             // manually insert the parent of the `out_dir` into `include_dirs`.
-            if let Some(parent) = Path::new(out_dir).parent() {
+            if let Some(parent) = out_dir.parent() {
                 include_dirs.insert(parent.to_owned());
             }
+            env.insert("OUT_DIR".to_owned(), out_dir.to_string_lossy().into_owned());
         }
 
         if let Some(parent) = root_module.parent() {
             include_dirs.insert(parent.to_owned());
         }
 
-        let build = if include_all_buildfiles || info.in_workspace {
+        let build = if *include_all_buildfiles || info.in_workspace {
             let build = Build {
                 label: target.clone(),
                 build_file: build_file.to_owned(),
@@ -185,14 +216,17 @@ pub(crate) fn to_json_project(
             is_proc_macro: info.proc_macro.unwrap_or(false),
             proc_macro_dylib_path,
             target: None,
+            label: Some(target.clone()),
         };
         crates.push(crate_info);
     }
 
-    if check_cycles {
-        check_cycles_in_crate_graph(&crates);
+    if !*include_tests {
+        crates = remove_test_only_crates(crates, &is_test);
     }
 
+    check_cycles_in_crate_graph(&mut crates, *cycle_check)?;
+
     let jp = JsonProject {
         sysroot: Box::new(sysroot),
         crates,
@@ -229,78 +263,185 @@ pub(crate) fn to_json_project(
     Ok(jp)
 }
 
+/// What to do about cycles in the crate dependency graph.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CycleCheck {
+    /// Don't look for cycles.
+    Off,
+    /// Fail, showing every cycle found.
+    Fail,
+    /// Log each cycle found and drop one of its edges. Used when rust-analyzer
+    /// invokes us: a single cycle shouldn't prevent it from loading the rest of
+    /// the crate graph.
+    DropEdge,
+}
+
 /// Check that there are no cycles in the crate dependency graph: a
 /// crate should never transitively depend on itself.
-///
-/// If a cycle is found, print the offending crate and terminate.
-fn check_cycles_in_crate_graph(crates: &[Crate]) {
-    // From a start crate ID, each ID we can reach, along with an example route.
-    let mut reachable: FxHashMap<usize, FxHashMap<usize, Vec<usize>>> = FxHashMap::default();
-
-    // Initialize the reachable crates from immediate dependencies.
-    for (idx, krate) in crates.iter().enumerate() {
-        let mut routes: FxHashMap<usize, Vec<usize>> = FxHashMap::default();
-        for dep in &krate.deps {
-            routes.insert(dep.crate_index, vec![idx, dep.crate_index]);
+fn check_cycles_in_crate_graph(
+    crates: &mut [Crate],
+    cycle_check: CycleCheck,
+) -> Result<(), anyhow::Error> {
+    match cycle_check {
+        CycleCheck::Off => Ok(()),
+        CycleCheck::Fail => {
+            if find_cycle(crates).is_none() {
+                return Ok(());
+            }
+            // Break the cycles of a copy of the graph as they are found, so
+            // that all of them are reported rather than just the first one.
+            // Each is logged once, so the error only counts them.
+            let mut crates = crates.to_vec();
+            let mut count = 0;
+            while let Some(cycle) = find_cycle(&crates) {
+                error!(
+                    kind = "error",
+                    cycle = cycle_names_json(&cycle, &crates).as_str(),
+                    "{}",
+                    format_cycle(&cycle, &crates),
+                );
+                count += 1;
+                drop_closing_edge(&mut crates, &cycle);
+            }
+            Err(anyhow::anyhow!(
+                "Found {count} {} in the crate graph",
+                if count == 1 { "cycle" } else { "cycles" }
+            ))
+        }
+        CycleCheck::DropEdge => {
+            while let Some(cycle) = find_cycle(crates) {
+                let (from, to) = (cycle[cycle.len() - 2], cycle[cycle.len() - 1]);
+                warn!(
+                    kind = "progress",
+                    cycle = cycle_names_json(&cycle, crates).as_str(),
+                    "{}\nIgnoring the dependency of {} on {} so that the rest of the crate graph can be loaded.",
+                    format_cycle(&cycle, crates),
+                    crate_label(&crates[from]),
+                    crate_label(&crates[to]),
+                );
+                drop_closing_edge(crates, &cycle);
+            }
+            Ok(())
         }
-
-        reachable.insert(idx, routes);
     }
+}
 
-    let mut changed = true;
-    while changed {
-        changed = false;
-
-        let mut new_reachable = reachable.clone();
-
-        // Iterate all the dependencies, and add any transitive
-        // dependencies that weren't already in `reachable`.
-        for (crate_idx, deps_idxs) in reachable.iter() {
-            for (dep_idx, route) in deps_idxs.iter() {
-                for transitive_dep_idx in reachable[dep_idx].keys() {
-                    if transitive_dep_idx == crate_idx {
-                        let mut cycle_route = route.clone();
-                        cycle_route.push(*transitive_dep_idx);
-
-                        tracing::error!(
-                            crate = crates[*crate_idx].display_name,
-                            route = format_route(&cycle_route, crates),
-                            "Found a cycle",
-                        );
-                        std::process::exit(2);
-                    }
+/// Find a cycle with a depth-first search, visiting crates and their
+/// dependencies in order. Returns the crate indexes along the cycle, starting
+/// and ending with the same crate.
+fn find_cycle(crates: &[Crate]) -> Option<Vec<usize>> {
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    enum State {
+        Unvisited,
+        /// On the current path from the root of the search.
+        InProgress,
+        Done,
+    }
 
-                    if !deps_idxs.contains_key(transitive_dep_idx) {
-                        let mut new_route = route.clone();
-                        new_route.push(*transitive_dep_idx);
+    let mut state = vec![State::Unvisited; crates.len()];
+    for root in 0..crates.len() {
+        if state[root] != State::Unvisited {
+            continue;
+        }
+        state[root] = State::InProgress;
+        // The current path, with the position of the next dependency to visit
+        // for each crate.
+        let mut path = vec![(root, 0)];
+        while let Some((idx, next_dep)) = path.last_mut() {
+            let idx = *idx;
+            let Some(dep) = crates[idx].deps.get(*next_dep) else {
+                state[idx] = State::Done;
+                path.pop();
+                continue;
+            };
+            *next_dep += 1;
 
-                        new_reachable
-                            .get_mut(crate_idx)
-                            .expect("We should always have initialized the dependencies for each crate.")
-                            .insert(*transitive_dep_idx, new_route);
-                        changed = true;
-                    }
+            let dep_idx = dep.crate_index;
+            match state[dep_idx] {
+                State::Unvisited => {
+                    state[dep_idx] = State::InProgress;
+                    path.push((dep_idx, 0));
                 }
+                State::InProgress => {
+                    let start = path
+                        .iter()
+                        .position(|(idx, _)| *idx == dep_idx)
+                        .expect("crates in progress are on the path");
+                    let mut cycle = path[start..]
+                        .iter()
+                        .map(|(idx, _)| *idx)
+                        .collect::<Vec<_>>();
+                    cycle.push(dep_idx);
+                    return Some(cycle);
+                }
+                State::Done => {}
             }
         }
+    }
 
-        reachable = new_reachable;
+    None
+}
+
+/// Remove the dependency that closes `cycle`, from its last crate to its
+/// first. `find_cycle` is deterministic, so this is always the same edge for
+/// the same graph.
+fn drop_closing_edge(crates: &mut [Crate], cycle: &[usize]) {
+    let (from, to) = (cycle[cycle.len() - 2], cycle[cycle.len() - 1]);
+    crates[from].deps.retain(|dep| dep.crate_index != to);
+}
+
+/// The names of the crates along `cycle`.
+fn cycle_names<'a>(cycle: &[usize], crates: &'a [Crate]) -> Vec<&'a str> {
+    cycle.iter().map(|idx| crate_name(&crates[*idx])).collect()
+}
+
+/// `cycle_names` as a JSON array, which `ProgressLayer` outputs as such.
+fn cycle_names_json(cycle: &[usize], crates: &[Crate]) -> String {
+    serde_json::to_string(&cycle_names(cycle, crates)).expect("strings serialize to JSON")
+}
+
+fn crate_name(krate: &Crate) -> &str {
+    krate.display_name.as_deref().unwrap_or("<unnamed>")
+}
+
+fn crate_label(krate: &Crate) -> String {
+    match &krate.label {
+        Some(label) => label.to_string(),
+        None => "<unknown target>".to_owned(),
     }
 }
 
-fn format_route(route: &[usize], crates: &[Crate]) -> String {
-    let mut formatted_crates = vec![];
-    for idx in route {
-        formatted_crates.push(format!(
-            "{} ({idx})",
-            crates[*idx]
-                .display_name
-                .clone()
-                .unwrap_or("<unnamed>".to_owned()),
+/// Render a cycle from `find_cycle`, along with the dependency edges that
+/// form it, one of which has to be removed to break the cycle.
+fn format_cycle(cycle: &[usize], crates: &[Crate]) -> String {
+    let mut out = format!(
+        "Found a cycle in the crate graph, {} depends on itself:\n",
+        crate_name(&crates[cycle[0]])
+    );
+    for (i, idx) in cycle.iter().enumerate() {
+        let arrow = if i == 0 { "   " } else { "-> " };
+        out.push_str(&format!(
+            "  {arrow}{} ({})\n",
+            crate_name(&crates[*idx]),
+            crate_label(&crates[*idx])
         ));
     }
 
-    formatted_crates.join(" -> ")
+    out.push_str("Check the deps of these targets, one of them has to go:");
+    for edge in cycle.windows(2) {
+        let (from, to) = (&crates[edge[0]], edge[1]);
+        let dep_name = from
+            .deps
+            .iter()
+            .find(|dep| dep.crate_index == to)
+            .map_or("<unknown>", |dep| &dep.name);
+        out.push_str(&format!(
+            "\n  {} depends on {} as `{dep_name}`",
+            crate_label(from),
+            crate_label(&crates[to])
+        ));
+    }
+    out
 }
 
 /// If any target in `targets` is an alias, resolve it to the actual target.
@@ -514,12 +655,14 @@ impl Buck {
         Ok(cfg.sysroot_src_path)
     }
 
-    /// Determines the owning target(s) of the saved file and builds them.
+    /// Determines the owning target(s) of the saved files and builds them.
+    ///
+    /// Targets owning more than one of the files are only built once.
     #[instrument]
-    pub(crate) fn check_saved_file(
+    pub(crate) fn check_saved_files(
         &self,
         use_clippy: bool,
-        saved_file: &Path,
+        saved_files: &[PathBuf],
     ) -> Result<CheckOutput, anyhow::Error> {
         let mut command = self.command(["bxl"]);
 
@@ -529,23 +672,25 @@ impl Buck {
 
         command.arg("prelude//rust/rust-analyzer/check.bxl:check");
 
-        let mut file_path = saved_file.to_owned();
-        if !file_path.is_absolute() {
-            if let Ok(cwd) = std::env::current_dir() {
-                file_path = cwd.join(saved_file);
+        // apply BXL scripts-specific arguments:
+        command.arg("--");
+        for saved_file in saved_files {
+            let mut file_path = saved_file.to_owned();
+            if !file_path.is_absolute() {
+                if let Ok(cwd) = std::env::current_dir() {
+                    file_path = cwd.join(saved_file);
+                }
             }
+            command.arg("--file");
+            command.arg(file_path.as_os_str());
         }
 
-        // apply BXL scripts-specific arguments:
-        command.args(["--", "--file"]);
-        command.arg(file_path.as_os_str());
-
         command.args(["--use-clippy", &use_clippy.to_string()]);
 
         // Set working directory to the containing directory of the target file.
         // This fixes cases where the working directory happens to be an
         // unrelated buck project (e.g. www).
-        if let Some(parent_dir) = saved_file.parent() {
+        if let Some(parent_dir) = saved_files.first().and_then(|f| f.parent()) {
             command.current_dir(parent_dir);
         }
 
@@ -555,12 +700,19 @@ impl Buck {
         Ok(files)
     }
 
+    /// Unless [`ResolveOptions::strict`] is set, targets that depend on targets
+    /// that don't exist are left out of the result and reported in
+    /// [`ExpandedAndResolved::missing_deps`] instead of failing the command.
     #[instrument(skip_all)]
     pub(crate) fn expand_and_resolve(
         &self,
         targets: &[Target],
-        exclude_workspaces: bool,
+        options: ResolveOptions,
     ) -> anyhow::Result<ExpandedAndResolved> {
+        let ResolveOptions {
+            exclude_workspaces,
+            strict,
+        } = options;
         if targets.is_empty() {
             return Ok(ExpandedAndResolved::default());
         }
@@ -574,6 +726,8 @@ impl Buck {
             "--",
             "--exclude_workspaces",
             exclude_workspaces.to_string().as_str(),
+            "--strict",
+            strict.to_string().as_str(),
             "--targets",
         ]);
         command.args(targets);
@@ -642,6 +796,19 @@ impl Buck {
                 command.arg("--files");
                 command.args(files);
             }
+            Input::TargetsAndFiles(targets, files) => {
+                // The bxl script only takes one kind of input at a time.
+                let mut owners = self.query_owners(Input::Targets(targets), max_extra_targets)?;
+                for (buildfile, targets) in
+                    self.query_owners(Input::Files(files), max_extra_targets)?
+                {
+                    let owned = owners.entry(buildfile).or_default();
+                    owned.extend(targets);
+                    owned.sort();
+                    owned.dedup();
+                }
+                return Ok(owners);
+            }
             Input::Buildfile(files) => {
                 command.arg("--buildfiles");
                 command.args(files);
@@ -1117,6 +1284,196 @@ fn alias_of_existing_target() {
     );
 }
 
+/// Targets that depend on a missing target are left out of the resolved
+/// targets, and the project is made of the crates that did resolve.
+#[test]
+fn partial_project_with_missing_dep() {
+    let mut resolved_deps = FxHashMap::default();
+    for (name, deps) in [("a", vec!["//foo:b"]), ("b", vec![])] {
+        resolved_deps.insert(
+            Target::new(format!("//foo:{name}")),
+            TargetInfo {
+                name: name.to_owned(),
+                label: name.to_owned(),
+                kind: Kind::Library,
+                edition: None,
+                srcs: vec![],
+                mapped_srcs: FxHashMap::default(),
+                crate_name: None,
+                crate_dynamic: None,
+                crate_root: PathBuf::from("lib.rs"),
+                deps: deps.into_iter().map(Target::new).collect(),
+                test_deps: vec![],
+                named_deps: FxHashMap::default(),
+                proc_macro: None,
+                features: vec![],
+                env: FxHashMap::default(),
+                source_folder: PathBuf::from("/tmp"),
+                project_relative_buildfile: PathBuf::from("foo/BUCK"),
+                in_workspace: true,
+                rustc_flags: vec![],
+            },
+        );
+    }
+
+    let expanded_and_resolved = ExpandedAndResolved {
+        expanded_targets: vec![Target::new("//foo:a"), Target::new("//foo:b")],
+        queried_proc_macros: FxHashMap::default(),
+        resolved_deps,
+        missing_deps: vec![crate::target::MissingDep {
+            target: Target::new("//foo:missing"),
+            wanted_by: Target::new("//foo:c"),
+        }],
+    };
+
+    let project = to_json_project(
+        Path::new("/repo"),
+        Sysroot {
+            sysroot: PathBuf::from("/sysroot"),
+            sysroot_src: None,
+            sysroot_project: None,
+        },
+        expanded_and_resolved,
+        FxHashMap::default(),
+        &JsonProjectOptions {
+            cycle_check: CycleCheck::Fail,
+            include_all_buildfiles: false,
+            include_tests: true,
+            extra_cfgs: vec![],
+        },
+    )
+    .unwrap();
+
+    let index_of = |name: &str| {
+        project
+            .crates
+            .iter()
+            .position(|krate| krate.display_name.as_deref() == Some(name))
+            .unwrap()
+    };
+    assert_eq!(project.crates.len(), 2);
+    assert_eq!(
+        project.crates[index_of("a")].deps,
+        vec![Dep {
+            crate_index: index_of("b"),
+            name: "b".to_owned(),
+        }]
+    );
+    assert_eq!(project.crates[index_of("b")].deps, vec![]);
+}
+
+#[test]
+fn build_script_env_propagated() {
+    // As emitted by resolve_deps.bxl for a crate whose sources are generated by a build script,
+    // and for one that doesn't set any environment.
+    let resolved_deps: FxHashMap<Target, TargetInfo> = serde_json::from_value(serde_json::json!({
+        "//foo:generated": {
+            "name": "generated",
+            "label": "//foo:generated",
+            "kind": "prelude//rules.bzl:rust_library",
+            "edition": null,
+            "srcs": ["buck-out/v2/gen/root/foo/__generated-build-script-run__/out/bindings.rs"],
+            "mapped_srcs": {},
+            "crate": null,
+            "crate_dynamic": null,
+            "crate_root": "lib.rs",
+            "deps": [],
+            "tests": [],
+            "named_deps": {},
+            "proc_macro": null,
+            "features": [],
+            "env": {
+                "CARGO_MANIFEST_DIR": "foo",
+                "CARGO_PKG_NAME": "generated",
+                "OUT_DIR": "buck-out/v2/gen/root/foo/__generated-build-script-run__/out",
+            },
+            "source_folder": "/repo/buck-out/v2/gen/root/foo/__generated__/sources",
+            "project_relative_buildfile": "foo/BUCK",
+            "in_workspace": true,
+            "rustc_flags": [],
+        },
+        "//foo:plain": {
+            "name": "plain",
+            "label": "//foo:plain",
+            "kind": "prelude//rules.bzl:rust_library",
+            "edition": null,
+            "srcs": ["foo/plain.rs"],
+            "mapped_srcs": {},
+            "crate": null,
+            "crate_dynamic": null,
+            "crate_root": "plain.rs",
+            "deps": [],
+            "tests": [],
+            "named_deps": {},
+            "proc_macro": null,
+            "features": [],
+            "source_folder": "/repo/foo",
+            "project_relative_buildfile": "foo/BUCK",
+            "in_workspace": true,
+            "rustc_flags": [],
+        },
+    }))
+    .unwrap();
+
+    let expanded_and_resolved = ExpandedAndResolved {
+        expanded_targets: vec![Target::new("//foo:generated"), Target::new("//foo:plain")],
+        queried_proc_macros: FxHashMap::default(),
+        resolved_deps,
+        missing_deps: vec![],
+    };
+
+    let project = to_json_project(
+        Path::new("/repo"),
+        Sysroot {
+            sysroot: PathBuf::from("/sysroot"),
+            sysroot_src: None,
+            sysroot_project: None,
+        },
+        expanded_and_resolved,
+        FxHashMap::default(),
+        &JsonProjectOptions {
+            cycle_check: CycleCheck::Fail,
+            include_all_buildfiles: false,
+            include_tests: true,
+            extra_cfgs: vec![],
+        },
+    )
+    .unwrap();
+
+    let krate = |name: &str| {
+        project
+            .crates
+            .iter()
+            .find(|krate| krate.display_name.as_deref() == Some(name))
+            .unwrap()
+    };
+
+    let generated = krate("generated");
+    let out_dir = "/repo/buck-out/v2/gen/root/foo/__generated-build-script-run__/out";
+    assert_eq!(
+        generated.env.get("OUT_DIR").map(String::as_str),
+        Some(out_dir)
+    );
+    assert_eq!(
+        generated.env.get("CARGO_PKG_NAME").map(String::as_str),
+        Some("generated")
+    );
+    assert_eq!(
+        generated.env.get("CARGO_MANIFEST_DIR").map(String::as_str),
+        Some("/repo/buck-out/v2/gen/root/foo/__generated__/sources/foo")
+    );
+    assert!(
+        generated
+            .source
+            .as_ref()
+            .unwrap()
+            .include_dirs
+            .contains(Path::new(out_dir).parent().unwrap())
+    );
+
+    assert!(krate("plain").env.is_empty());
+}
+
 #[test]
 fn test_select_mode() {
     // Test default behavior without the fbcode_build cfg
@@ -1156,3 +1513,152 @@ fn test_select_mode() {
         );
     }
 }
+
+#[cfg(test)]
+fn crate_with_deps(name: &str, deps: &[usize]) -> Crate {
+    Crate {
+        display_name: Some(name.to_owned()),
+        deps: deps
+            .iter()
+            .map(|idx| Dep {
+                crate_index: *idx,
+                name: format!("dep{idx}"),
+            })
+            .collect(),
+        label: Some(Target::new(format!("fbcode//{name}:{name}"))),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn cycle_check_reports_cycle_path() {
+    // a -> b -> c -> a, and d -> a outside of the cycle.
+    let mut crates = vec![
+        crate_with_deps("a", &[1]),
+        crate_with_deps("b", &[2]),
+        crate_with_deps("c", &[0]),
+        crate_with_deps("d", &[0]),
+    ];
+
+    let cycle = find_cycle(&crates).unwrap();
+    assert_eq!(cycle, vec![0, 1, 2, 0]);
+    assert_eq!(
+        format_cycle(&cycle, &crates),
+        "Found a cycle in the crate graph, a depends on itself:
+     a (fbcode//a:a)
+  -> b (fbcode//b:b)
+  -> c (fbcode//c:c)
+  -> a (fbcode//a:a)
+Check the deps of these targets, one of them has to go:
+  fbcode//a:a depends on fbcode//b:b as `dep1`
+  fbcode//b:b depends on fbcode//c:c as `dep2`
+  fbcode//c:c depends on fbcode//a:a as `dep0`"
+    );
+    let err = check_cycles_in_crate_graph(&mut crates, CycleCheck::Fail).unwrap_err();
+    assert_eq!(err.to_string(), "Found 1 cycle in the crate graph");
+
+    assert!(check_cycles_in_crate_graph(&mut crates, CycleCheck::Off).is_ok());
+    assert_eq!(crates[2].deps.len(), 1);
+}
+
+/// Collects what `ProgressLayer` writes.
+#[cfg(test)]
+struct CapturedOutput(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+#[cfg(test)]
+impl io::Write for CapturedOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn cycle_check_reports_every_cycle() {
+    // a -> b -> a, and c -> d -> c.
+    let mut crates = vec![
+        crate_with_deps("a", &[1]),
+        crate_with_deps("b", &[0]),
+        crate_with_deps("c", &[3]),
+        crate_with_deps("d", &[2]),
+    ];
+
+    let cycle = find_cycle(&crates).unwrap();
+    assert_eq!(cycle_names(&cycle, &crates), vec!["a", "b", "a"]);
+    assert_eq!(cycle_names_json(&cycle, &crates), r#"["a","b","a"]"#);
+
+    // Each cycle is output once, as an error for tools reading the JSON output.
+    let output = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let subscriber = {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let output = output.clone();
+        tracing_subscriber::registry().with(crate::progress::ProgressLayer::new(move || {
+            CapturedOutput(output.clone())
+        }))
+    };
+    let err = tracing::subscriber::with_default(subscriber, || {
+        check_cycles_in_crate_graph(&mut crates, CycleCheck::Fail).unwrap_err()
+    });
+    assert_eq!(err.to_string(), "Found 2 cycles in the crate graph");
+    let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+    let events = output
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(events.len(), 2, "{output}");
+    for (event, names, path) in [
+        (
+            &events[0],
+            ["a", "b", "a"],
+            "-> b (fbcode//b:b)\n  -> a (fbcode//a:a)",
+        ),
+        (
+            &events[1],
+            ["c", "d", "c"],
+            "-> d (fbcode//d:d)\n  -> c (fbcode//c:c)",
+        ),
+    ] {
+        assert_eq!(event["kind"], "error", "{output}");
+        assert_eq!(event["cycle"], serde_json::json!(names), "{output}");
+        assert!(
+            event["message"].as_str().unwrap().contains(path),
+            "{output}"
+        );
+    }
+
+    // The reported graph is left as it was.
+    assert_eq!(crates[1].deps.len(), 1);
+    assert_eq!(crates[3].deps.len(), 1);
+}
+
+#[test]
+fn cycle_check_drops_closing_edge() {
+    // a -> b -> c -> a, and c -> d -> b.
+    let mut crates = vec![
+        crate_with_deps("a", &[1]),
+        crate_with_deps("b", &[2]),
+        crate_with_deps("c", &[0, 3]),
+        crate_with_deps("d", &[1]),
+    ];
+
+    check_cycles_in_crate_graph(&mut crates, CycleCheck::DropEdge).unwrap();
+
+    // Each cycle lost the edge that closed it, and nothing else.
+    let deps = crates
+        .iter()
+        .map(|krate| {
+            krate
+                .deps
+                .iter()
+                .map(|dep| dep.crate_index)
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(deps, vec![vec![1], vec![2], vec![3], vec![]]);
+    assert_eq!(find_cycle(&crates), None);
+}
@@ -10,20 +10,26 @@
 mod check;
 mod develop;
 mod new;
+mod watch;
 
 #[derive(Debug, Clone)]
 pub(crate) enum Input {
     Targets(Vec<Target>),
     Files(Vec<PathBuf>),
+    /// Targets read from `--targets-from`, along with the files being developed.
+    TargetsAndFiles(Vec<Target>, Vec<PathBuf>),
     Buildfile(Vec<PathBuf>),
 }
 
 use std::path::PathBuf;
 
 pub(crate) use check::Check;
+pub(crate) use check::MessageFormat;
+pub(crate) use check::OutputFormat;
 pub(crate) use develop::Develop;
 pub(crate) use develop::develop_with_sysroot;
 pub(crate) use new::New;
 pub(crate) use new::ProjectKind;
+pub(crate) use watch::Watch;
 
 use crate::target::Target;
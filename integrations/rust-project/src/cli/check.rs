@@ -11,35 +11,68 @@ use std::path::Path;
 use std::path::PathBuf;
 use std::str::FromStr;
 
+use clap::ValueEnum;
+
 use crate::buck;
 use crate::buck::select_mode;
 use crate::diagnostics;
 use crate::path::safe_canonicalize;
 
+/// How `check` prints diagnostics.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub(crate) enum MessageFormat {
+    /// rustc's JSON diagnostics, one per line.
+    Rustc,
+    /// The parameters of LSP `textDocument/publishDiagnostics` notifications, one per line and
+    /// file.
+    Json,
+}
+
+/// What `check` prints after the diagnostics.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub(crate) enum OutputFormat {
+    /// Nothing. Exits successfully unless buck fails, even if rustc reports errors.
+    Text,
+    /// A JSON summary that classifies the errors, and exits with a distinct code for each class.
+    Json,
+}
+
 pub(crate) struct Check {
     pub(crate) buck: buck::Buck,
     pub(crate) use_clippy: bool,
-    pub(crate) saved_file: PathBuf,
+    pub(crate) message_format: MessageFormat,
+    pub(crate) saved_files: Vec<PathBuf>,
 }
 
 impl Check {
-    pub(crate) fn new(mode: Option<String>, use_clippy: bool, saved_file: PathBuf) -> Self {
-        let saved_file = safe_canonicalize(&saved_file);
+    pub(crate) fn new(
+        mode: Option<String>,
+        use_clippy: bool,
+        message_format: MessageFormat,
+        saved_files: Vec<PathBuf>,
+    ) -> Self {
+        let mut saved_files: Vec<PathBuf> = saved_files
+            .iter()
+            .map(|saved_file| safe_canonicalize(saved_file))
+            .collect();
+        saved_files.sort();
+        saved_files.dedup();
 
         let mode = select_mode(mode.as_deref());
         let buck = buck::Buck::new(mode);
         Self {
             buck,
             use_clippy,
-            saved_file,
+            message_format,
+            saved_files,
         }
     }
 
-    pub(crate) fn run(&self) -> Result<(), anyhow::Error> {
+    pub(crate) fn run(&self) -> Result<diagnostics::CheckSummary, anyhow::Error> {
         let start = std::time::Instant::now();
         let buck = &self.buck;
 
-        let check_output = buck.check_saved_file(self.use_clippy, &self.saved_file)?;
+        let check_output = buck.check_saved_files(self.use_clippy, &self.saved_files)?;
 
         let mut diagnostics = vec![];
         for path in check_output.diagnostic_paths {
@@ -72,14 +105,33 @@ impl Check {
             }
         }
 
-        for diagnostic in diagnostics {
-            let out = serde_json::to_string(&diagnostic)?;
-            println!("{}", out);
+        // Lines that aren't rustc diagnostics have no LSP equivalent, and don't count as errors.
+        let messages: Vec<diagnostics::Message> = diagnostics
+            .iter()
+            .filter_map(|diagnostic| serde_json::from_value(diagnostic.clone()).ok())
+            .collect();
+
+        match self.message_format {
+            MessageFormat::Rustc => {
+                for diagnostic in diagnostics {
+                    let out = serde_json::to_string(&diagnostic)?;
+                    println!("{}", out);
+                }
+            }
+            MessageFormat::Json => {
+                for params in diagnostics::to_publish_diagnostics(&messages, &self.saved_files) {
+                    let out = serde_json::to_string(&params)?;
+                    println!("{}", out);
+                }
+            }
         }
 
-        crate::scuba::log_check(start.elapsed(), &self.saved_file, self.use_clippy);
+        crate::scuba::log_check(start.elapsed(), &self.saved_files, self.use_clippy);
 
-        Ok(())
+        Ok(diagnostics::CheckSummary::classify(
+            &messages,
+            &self.saved_files,
+        ))
     }
 }
 
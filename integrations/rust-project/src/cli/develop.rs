@@ -7,37 +7,51 @@
  * of this source tree.
  */
 
-use std::io::BufWriter;
+use std::ffi::OsStr;
+use std::ffi::OsString;
 use std::io::Write;
 use std::path::Path;
 use std::path::PathBuf;
 
+use anyhow::Context;
 use rustc_hash::FxHashMap;
 use serde::Deserialize;
 use serde::Serialize;
 use tracing::info;
+use tracing::warn;
 
 use super::Input;
 use crate::Command;
 use crate::buck;
 use crate::buck::Buck;
+use crate::buck::CycleCheck;
+use crate::buck::JsonProjectOptions;
+use crate::buck::ResolveOptions;
 use crate::buck::select_mode;
 use crate::buck::to_json_project;
 use crate::json_project::JsonProject;
 use crate::json_project::Sysroot;
+use crate::json_project::remove_excluded_crates;
 use crate::path::safe_canonicalize;
 use crate::sysroot::SysrootConfig;
-use crate::sysroot::resolve_buckconfig_sysroot;
-use crate::sysroot::resolve_rustup_sysroot;
+use crate::sysroot::resolve_sysroot;
+use crate::target::MissingDep;
 use crate::target::Target;
 
 #[derive(Debug)]
 pub(crate) struct Develop {
     pub(crate) sysroot: SysrootConfig,
     pub(crate) buck: buck::Buck,
-    pub(crate) check_cycles: bool,
+    pub(crate) cycle_check: CycleCheck,
     pub(crate) invoked_by_ra: bool,
     pub(crate) include_all_buildfiles: bool,
+    /// Fail if any dependency can't be resolved, rather than leaving out the
+    /// crates that need it.
+    pub(crate) strict: bool,
+    /// Keep test crates, and the crates only they depend on.
+    pub(crate) include_tests: bool,
+    /// Glob patterns of the labels of crates to leave out.
+    pub(crate) exclude: Vec<String>,
 }
 
 pub(crate) struct OutputCfg {
@@ -52,10 +66,13 @@ pub(crate) enum Output {
 }
 
 impl Develop {
-    pub(crate) fn from_command(command: Command) -> (Develop, Input, OutputCfg) {
+    pub(crate) fn from_command(
+        command: Command,
+    ) -> Result<(Develop, Input, OutputCfg), anyhow::Error> {
         if let crate::Command::Develop {
             files,
             targets,
+            targets_from,
             out,
             stdout,
             prefer_rustup_managed_toolchain,
@@ -64,6 +81,9 @@ impl Develop {
             mode,
             check_cycles,
             include_all_buildfiles,
+            strict,
+            include_tests,
+            exclude,
             ..
         } = command
         {
@@ -84,27 +104,48 @@ impl Develop {
             let mode = select_mode(mode.as_deref());
             let buck = buck::Buck::new(mode);
 
+            let cycle_check = if check_cycles {
+                CycleCheck::Fail
+            } else {
+                CycleCheck::Off
+            };
+
             let develop = Develop {
                 sysroot,
                 buck,
-                check_cycles,
+                cycle_check,
                 invoked_by_ra: false,
                 include_all_buildfiles,
+                strict,
+                include_tests,
+                exclude,
             };
             let out = OutputCfg { out, pretty };
 
-            let input = if !targets.is_empty() {
-                let targets = targets.into_iter().map(Target::new).collect();
-                Input::Targets(targets)
-            } else {
-                Input::Files(files)
+            let input = match targets_from {
+                Some(path) => {
+                    let targets = read_targets(&path)?.into_iter().map(Target::new).collect();
+                    if files.is_empty() {
+                        Input::Targets(targets)
+                    } else {
+                        Input::TargetsAndFiles(targets, files)
+                    }
+                }
+                None if !targets.is_empty() => {
+                    let targets = targets.into_iter().map(Target::new).collect();
+                    Input::Targets(targets)
+                }
+                None => Input::Files(files),
             };
 
-            return (develop, input, out);
+            return Ok((develop, input, out));
         }
 
         if let crate::Command::DevelopJson {
-            sysroot_mode, args, ..
+            sysroot_mode,
+            check_cycles,
+            args,
+            ..
         } = command
         {
             let out = Output::Stdout;
@@ -125,12 +166,23 @@ impl Develop {
 
             let buck = buck::Buck::new(mode);
 
+            // A cycle shouldn't stop rust-analyzer from loading the rest of the
+            // project, so break cycles rather than failing.
+            let cycle_check = if check_cycles {
+                CycleCheck::DropEdge
+            } else {
+                CycleCheck::Off
+            };
+
             let develop = Develop {
                 sysroot,
                 buck,
-                check_cycles: false,
+                cycle_check,
                 invoked_by_ra: true,
                 include_all_buildfiles: false,
+                strict: false,
+                include_tests: true,
+                exclude: vec![],
             };
             let out = OutputCfg { out, pretty: false };
 
@@ -140,7 +192,7 @@ impl Develop {
                 crate::JsonArguments::Label(target) => Input::Targets(vec![Target::new(target)]),
             };
 
-            return (develop, input, out);
+            return Ok((develop, input, out));
         }
 
         unreachable!("No other subcommand is supported.")
@@ -157,28 +209,9 @@ pub(crate) struct OutputData {
 }
 
 impl Develop {
-    pub(crate) fn run(self, input: Input, cfg: OutputCfg) -> Result<(), anyhow::Error> {
+    pub(crate) fn run(&self, input: Input, cfg: &OutputCfg) -> Result<(), anyhow::Error> {
         let start = std::time::Instant::now();
-        let input = match input {
-            Input::Targets(targets) => Input::Targets(targets),
-            Input::Files(files) => {
-                let canonical_files = files
-                    .into_iter()
-                    .map(|p| safe_canonicalize(&p))
-                    .collect::<Vec<_>>();
-
-                Input::Files(canonical_files)
-            }
-            Input::Buildfile(buildfiles) => Input::Buildfile(buildfiles),
-        };
-        let mut writer: BufWriter<Box<dyn Write>> = match cfg.out {
-            Output::Path(ref p) => {
-                let out = std::fs::File::create(p)?;
-                BufWriter::new(Box::new(out))
-            }
-            Output::Stdout => BufWriter::new(Box::new(std::io::stdout())),
-        };
-
+        let input = canonicalize_input(input);
         let targets = self.related_targets(input.clone())?;
         if targets.is_empty() {
             let err = anyhow::anyhow!("No owning target found")
@@ -209,16 +242,7 @@ impl Develop {
             let project = self.run_inner(targets)?;
             crate::scuba::log_develop(start.elapsed(), input, self.invoked_by_ra);
 
-            if cfg.pretty {
-                serde_json::to_writer_pretty(&mut writer, &project)?;
-            } else {
-                serde_json::to_writer(&mut writer, &project)?;
-            }
-            writeln!(writer)?;
-            match &cfg.out {
-                Output::Path(p) => info!(file = ?p, "wrote rust-project.json"),
-                Output::Stdout => info!("wrote rust-project.json to stdout"),
-            }
+            write_project(&project, cfg)?;
         }
 
         Ok(())
@@ -228,43 +252,43 @@ impl Develop {
         let Develop {
             sysroot,
             buck,
-            check_cycles,
+            cycle_check,
             include_all_buildfiles,
+            strict,
+            include_tests,
+            exclude,
             ..
         } = self;
 
         info!(kind = "progress", "fetching sysroot");
-        let sysroot = match &sysroot {
-            SysrootConfig::Sysroot(path) => Sysroot {
-                sysroot: safe_canonicalize(&expand_tilde(path)?),
-                sysroot_src: None,
-                sysroot_project: None,
-            },
-            SysrootConfig::BuckConfig => {
-                let project_root = buck.resolve_project_root()?;
-                resolve_buckconfig_sysroot(&buck, &project_root)?
-            }
-            SysrootConfig::Rustup => resolve_rustup_sysroot()?,
-        };
+        let sysroot = resolve_sysroot(sysroot, buck)?;
 
         let exclude_workspaces =
             std::env::var("RUST_PROJECT_EXCLUDE_WORKSPACES").is_ok_and(|it| it != "0");
 
         // FIXME(JakobDegen): This should be set via a configuration mechanism of some kind.
         #[cfg(not(fbcode_build))]
-        let extra_cfgs = &["test".to_owned()];
+        let extra_cfgs = vec!["test".to_owned()];
         #[cfg(fbcode_build)]
-        let extra_cfgs = &["test".to_owned(), "fbcode_build".to_owned()];
+        let extra_cfgs = vec!["test".to_owned(), "fbcode_build".to_owned()];
 
-        develop_with_sysroot(
+        let mut project = develop_with_sysroot(
             buck,
             targets,
             sysroot,
-            exclude_workspaces,
-            *check_cycles,
-            *include_all_buildfiles,
-            extra_cfgs,
-        )
+            ResolveOptions {
+                exclude_workspaces,
+                strict: *strict,
+            },
+            &JsonProjectOptions {
+                cycle_check: *cycle_check,
+                include_all_buildfiles: *include_all_buildfiles,
+                include_tests: *include_tests,
+                extra_cfgs,
+            },
+        )?;
+        project.crates = remove_excluded_crates(project.crates, exclude);
+        Ok(project)
     }
 
     /// For every Rust file, return the relevant buck targets that should be used to configure rust-analyzer.
@@ -285,28 +309,94 @@ impl Develop {
     }
 }
 
-fn expand_tilde(path: &Path) -> Result<PathBuf, anyhow::Error> {
-    if path.starts_with("~") {
-        let path = path.strip_prefix("~")?;
-        let home = std::env::var("HOME")?;
-        let home = PathBuf::from(home);
-        Ok(home.join(path))
+pub(crate) fn canonicalize_input(input: Input) -> Input {
+    let canonicalize_files = |files: Vec<PathBuf>| {
+        files
+            .into_iter()
+            .map(|p| safe_canonicalize(&p))
+            .collect::<Vec<_>>()
+    };
+    match input {
+        Input::Targets(targets) => Input::Targets(targets),
+        Input::Files(files) => Input::Files(canonicalize_files(files)),
+        Input::TargetsAndFiles(targets, files) => {
+            Input::TargetsAndFiles(targets, canonicalize_files(files))
+        }
+        Input::Buildfile(buildfiles) => Input::Buildfile(buildfiles),
+    }
+}
+
+/// Read newline-separated targets from `path`, or from stdin when `path` is `-`.
+fn read_targets(path: &Path) -> Result<Vec<String>, anyhow::Error> {
+    let contents = if path == Path::new("-") {
+        std::io::read_to_string(std::io::stdin()).context("failed to read targets from stdin")?
     } else {
-        Ok(path.to_path_buf())
+        std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read targets from {}", path.display()))?
+    };
+    Ok(parse_targets(&contents))
+}
+
+/// One target per line, ignoring blank lines and `#` comments.
+fn parse_targets(contents: &str) -> Vec<String> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let target = line.split('#').next().unwrap_or_default().trim();
+            (!target.is_empty()).then(|| target.to_owned())
+        })
+        .collect()
+}
+
+/// Serialize `project` to the configured output.
+///
+/// Files are written to a temporary sibling first and then renamed into place,
+/// so rust-analyzer never observes a partially-written `rust-project.json`.
+pub(crate) fn write_project(project: &JsonProject, cfg: &OutputCfg) -> Result<(), anyhow::Error> {
+    let mut buf = vec![];
+    if cfg.pretty {
+        serde_json::to_writer_pretty(&mut buf, project)?;
+    } else {
+        serde_json::to_writer(&mut buf, project)?;
+    }
+    writeln!(buf)?;
+
+    match &cfg.out {
+        Output::Path(p) => {
+            let mut tmp_name = OsString::from(".");
+            tmp_name.push(p.file_name().unwrap_or(OsStr::new("rust-project.json")));
+            tmp_name.push(".tmp");
+            let tmp = p.with_file_name(tmp_name);
+
+            std::fs::write(&tmp, &buf).with_context(|| format!("failed to write {:?}", tmp))?;
+            std::fs::rename(&tmp, p)
+                .with_context(|| format!("failed to rename {:?} to {:?}", tmp, p))?;
+            info!(file = ?p, "wrote rust-project.json");
+        }
+        Output::Stdout => {
+            std::io::stdout().write_all(&buf)?;
+            info!("wrote rust-project.json to stdout");
+        }
     }
+
+    Ok(())
 }
 
 pub(crate) fn develop_with_sysroot(
     buck: &Buck,
     targets: Vec<Target>,
     sysroot: Sysroot,
-    exclude_workspaces: bool,
-    check_cycles: bool,
-    include_all_buildfiles: bool,
-    extra_cfgs: &[String],
+    resolve_options: ResolveOptions,
+    project_options: &JsonProjectOptions,
 ) -> Result<JsonProject, anyhow::Error> {
     info!(kind = "progress", "building generated code");
-    let expanded_and_resolved = buck.expand_and_resolve(&targets, exclude_workspaces)?;
+    let expanded_and_resolved = buck.expand_and_resolve(&targets, resolve_options)?;
+    if !expanded_and_resolved.missing_deps.is_empty() {
+        warn!(
+            "{}",
+            format_missing_deps(&expanded_and_resolved.missing_deps)
+        );
+    }
 
     info!(kind = "progress", "resolving aliased libraries");
     let aliased_libraries =
@@ -316,14 +406,80 @@ pub(crate) fn develop_with_sysroot(
         kind = "progress",
         "converting buck info to rust-project.json"
     );
+    let project_root = buck.resolve_project_root()?;
     let rust_project = to_json_project(
+        &project_root,
         sysroot,
         expanded_and_resolved,
         aliased_libraries,
-        check_cycles,
-        include_all_buildfiles,
-        extra_cfgs,
+        project_options,
     )?;
 
     Ok(rust_project)
 }
+
+/// Render `missing` as a table of each missing target and the crate that wanted it.
+fn format_missing_deps(missing: &[MissingDep]) -> String {
+    const MISSING: &str = "MISSING TARGET";
+    const WANTED_BY: &str = "WANTED BY";
+
+    let mut missing = missing.iter().collect::<Vec<_>>();
+    missing.sort_by(|a, b| (&a.target, &a.wanted_by).cmp(&(&b.target, &b.wanted_by)));
+
+    let width = missing
+        .iter()
+        .map(|dep| dep.target.len())
+        .chain([MISSING.len()])
+        .max()
+        .unwrap_or_default();
+
+    let mut out = String::from(
+        "Skipped crates that depend on targets which could not be resolved (use --strict to fail instead):\n",
+    );
+    out.push_str(&format!("  {MISSING:width$}  {WANTED_BY}\n"));
+    for dep in missing {
+        out.push_str(&format!(
+            "  {:width$}  {}\n",
+            dep.target.to_string(),
+            dep.wanted_by
+        ));
+    }
+    out
+}
+
+#[test]
+fn targets_file_contents() {
+    assert_eq!(
+        parse_targets(
+            "# Generated by some tool\n\
+             fbcode//foo:bar\n\
+             \n   \n\
+             \x20 fbcode//baz:baz  # the main library\n\
+             fbcode//qux:qux"
+        ),
+        vec!["fbcode//foo:bar", "fbcode//baz:baz", "fbcode//qux:qux"]
+    );
+}
+
+#[test]
+fn missing_deps_table() {
+    let missing = vec![
+        MissingDep {
+            target: Target::new("fbcode//foo:typo"),
+            wanted_by: Target::new("fbcode//foo:bar"),
+        },
+        MissingDep {
+            target: Target::new("fbcode//baz:generated"),
+            wanted_by: Target::new("fbcode//baz:baz"),
+        },
+    ];
+
+    assert_eq!(
+        format_missing_deps(&missing),
+        "Skipped crates that depend on targets which could not be resolved (use --strict to fail instead):
+  MISSING TARGET         WANTED BY
+  fbcode//baz:generated  fbcode//baz:baz
+  fbcode//foo:typo       fbcode//foo:bar
+"
+    );
+}
@@ -0,0 +1,505 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::Duration;
+use std::time::Instant;
+
+use anyhow::Context;
+use notify::EventKind;
+use notify::RecursiveMode;
+use notify::Watcher;
+use rustc_hash::FxHashMap;
+use rustc_hash::FxHashSet;
+use tracing::info;
+use tracing::warn;
+
+use super::Input;
+use crate::cli::Develop;
+use crate::cli::develop::OutputCfg;
+use crate::cli::develop::canonicalize_input;
+use crate::cli::develop::write_project;
+use crate::target::Target;
+
+/// How long the buildfiles need to be quiet before we regenerate.
+///
+/// Editors and VCS operations tend to touch files in bursts (e.g. write to a
+/// temporary file and rename it over the original), so regenerating on every
+/// event would invoke buck several times for a single logical change.
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+enum WatchEvent {
+    Fs(notify::Result<notify::Event>),
+    Interrupted,
+}
+
+/// Runs [`Develop`] once, then again whenever one of the buildfiles owning the
+/// requested targets, or the project's `.buckconfig`, changes. Only the owners of
+/// the changed buildfiles are queried again, unless `.buckconfig` changed.
+pub(crate) struct Watch {
+    pub(crate) develop: Develop,
+    pub(crate) input: Input,
+    pub(crate) out: OutputCfg,
+}
+
+impl Watch {
+    pub(crate) fn run(self) -> Result<(), anyhow::Error> {
+        let Watch {
+            develop,
+            input,
+            out,
+        } = self;
+        let input = canonicalize_input(input);
+
+        let buildfiles = develop.related_targets(input.clone())?;
+        if buildfiles.is_empty() {
+            return Err(anyhow::anyhow!("No owning target found")
+                .context(format!("Could not find owning target for {:?}", input)));
+        }
+        let mut watched = Watched {
+            buildfiles,
+            buckconfig: develop.buck.resolve_project_root()?.join(".buckconfig"),
+        };
+
+        let (tx, rx) = mpsc::channel();
+
+        let fs_tx = tx.clone();
+        let mut watcher = notify::recommended_watcher(move |res| {
+            let _ = fs_tx.send(WatchEvent::Fs(res));
+        })?;
+        // Watch the package directories rather than the buildfiles themselves:
+        // editors commonly save by renaming over the original file, which
+        // would otherwise drop the watch.
+        let mut dirs = FxHashSet::default();
+        for dir in watched.dirs() {
+            watcher
+                .watch(&dir, RecursiveMode::NonRecursive)
+                .with_context(|| format!("failed to watch {:?}", dir))?;
+            dirs.insert(dir);
+        }
+
+        ctrlc::set_handler(move || {
+            let _ = tx.send(WatchEvent::Interrupted);
+        })?;
+
+        regenerate(&develop, &input, &out);
+        watch_loop(&rx, &mut watched, DEBOUNCE, |watched, changes| {
+            if let Err(e) = reload(&develop, &input, &out, watched, &changes) {
+                // A broken buildfile is a normal state while the user is editing it, so
+                // keep watching and try again on the next change.
+                warn!(error = ?e, "failed to regenerate rust-project.json");
+                return;
+            }
+            // A `.buckconfig` change can bring in new buildfiles.
+            for dir in watched.dirs() {
+                if dirs.contains(&dir) {
+                    continue;
+                }
+                match watcher.watch(&dir, RecursiveMode::NonRecursive) {
+                    Ok(()) => {
+                        dirs.insert(dir);
+                    }
+                    Err(e) => warn!(error = %e, ?dir, "failed to watch"),
+                }
+            }
+        });
+        Ok(())
+    }
+}
+
+/// The files whose changes trigger a regeneration.
+struct Watched {
+    /// The buildfiles owning the requested targets, and the targets they own.
+    buildfiles: FxHashMap<PathBuf, Vec<Target>>,
+    buckconfig: PathBuf,
+}
+
+impl Watched {
+    /// The directories of the watched files.
+    fn dirs(&self) -> FxHashSet<PathBuf> {
+        self.buildfiles
+            .keys()
+            .chain([&self.buckconfig])
+            .filter_map(|path| path.parent())
+            .map(|dir| dir.to_path_buf())
+            .collect()
+    }
+
+    /// What to query the owners of again after `changes`.
+    fn reload_input(&self, changes: &ChangeSet, input: &Input) -> Input {
+        if changes.config {
+            input.clone()
+        } else {
+            Input::Buildfile(changes.buildfiles.iter().cloned().collect())
+        }
+    }
+
+    /// Replaces the targets of the buildfiles that were queried again. A changed
+    /// buildfile that no longer owns any target is still watched.
+    fn update(&mut self, changes: &ChangeSet, mut owners: FxHashMap<PathBuf, Vec<Target>>) {
+        if changes.config {
+            self.buildfiles = owners;
+        } else {
+            for buildfile in &changes.buildfiles {
+                let targets = owners.remove(buildfile).unwrap_or_default();
+                self.buildfiles.insert(buildfile.clone(), targets);
+            }
+        }
+    }
+
+    /// Every target owned by the watched buildfiles, sorted.
+    fn targets(&self) -> Vec<Target> {
+        let mut targets = self
+            .buildfiles
+            .values()
+            .flatten()
+            .cloned()
+            .collect::<Vec<_>>();
+        targets.sort();
+        targets.dedup();
+        targets
+    }
+}
+
+/// The changes to watched files coalesced into a single regeneration.
+#[derive(Debug, Default)]
+struct ChangeSet {
+    /// Changes to `.buckconfig` can affect every target.
+    config: bool,
+    buildfiles: BTreeSet<PathBuf>,
+}
+
+impl ChangeSet {
+    /// Records the watched files changed by `event`, returning whether there were any.
+    fn record(&mut self, event: &notify::Event, watched: &Watched) -> bool {
+        if matches!(event.kind, EventKind::Access(_)) {
+            return false;
+        }
+        let mut relevant = false;
+        for path in &event.paths {
+            if *path == watched.buckconfig {
+                self.config = true;
+                relevant = true;
+            } else if watched.buildfiles.contains_key(path) {
+                self.buildfiles.insert(path.clone());
+                relevant = true;
+            }
+        }
+        relevant
+    }
+
+    /// The requested targets affected by the changes, sorted.
+    fn affected_targets(&self, watched: &Watched) -> Vec<Target> {
+        let mut targets = if self.config {
+            watched.buildfiles.values().flatten().cloned().collect()
+        } else {
+            self.buildfiles
+                .iter()
+                .filter_map(|buildfile| watched.buildfiles.get(buildfile))
+                .flatten()
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+        targets.sort();
+        targets.dedup();
+        targets
+    }
+}
+
+/// Calls `on_change` with the changes coalesced since the last call once `debounce` passed without
+/// new changes to the `watched` files, until interrupted.
+fn watch_loop(
+    rx: &mpsc::Receiver<WatchEvent>,
+    watched: &mut Watched,
+    debounce: Duration,
+    mut on_change: impl FnMut(&mut Watched, ChangeSet),
+) {
+    let mut debouncer = Debouncer::new(debounce);
+    let mut changes = ChangeSet::default();
+    loop {
+        let event = match debouncer.remaining(Instant::now()) {
+            Some(timeout) => rx.recv_timeout(timeout),
+            None => rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+
+        match event {
+            Ok(WatchEvent::Fs(Ok(event))) => {
+                if changes.record(&event, watched) {
+                    debouncer.record(Instant::now());
+                }
+            }
+            Ok(WatchEvent::Fs(Err(e))) => warn!(error = %e, "file watcher error"),
+            Ok(WatchEvent::Interrupted) | Err(RecvTimeoutError::Disconnected) => return,
+            Err(RecvTimeoutError::Timeout) => {}
+        }
+
+        if debouncer.ready(Instant::now()) {
+            on_change(watched, std::mem::take(&mut changes));
+        }
+    }
+}
+
+/// Queries the owners of the changed buildfiles again, or of the whole input if
+/// `.buckconfig` changed, and regenerates the project from the targets of every
+/// watched buildfile, since `rust-project.json` has to describe all of them.
+fn reload(
+    develop: &Develop,
+    input: &Input,
+    out: &OutputCfg,
+    watched: &mut Watched,
+    changes: &ChangeSet,
+) -> Result<(), anyhow::Error> {
+    let start = Instant::now();
+    let affected = changes.affected_targets(watched);
+    let owners = develop.related_targets(watched.reload_input(changes, input))?;
+    watched.update(changes, owners);
+    let project = develop.run_inner(watched.targets())?;
+    write_project(&project, out)?;
+    info!(
+        elapsed_ms = start.elapsed().as_millis(),
+        ?affected,
+        "regenerated rust-project.json"
+    );
+    Ok(())
+}
+
+/// Generates the whole project, querying the owners of the whole input.
+fn regenerate(develop: &Develop, input: &Input, out: &OutputCfg) {
+    let start = Instant::now();
+    match develop.run(input.clone(), out) {
+        Ok(()) => info!(
+            elapsed_ms = start.elapsed().as_millis(),
+            "regenerated rust-project.json"
+        ),
+        Err(e) => warn!(error = ?e, "failed to regenerate rust-project.json"),
+    }
+}
+
+/// Coalesces a burst of events into a single regeneration, which fires once no
+/// new events have arrived for the configured delay.
+#[derive(Debug)]
+struct Debouncer {
+    delay: Duration,
+    last_event: Option<Instant>,
+}
+
+impl Debouncer {
+    fn new(delay: Duration) -> Self {
+        Debouncer {
+            delay,
+            last_event: None,
+        }
+    }
+
+    fn record(&mut self, now: Instant) {
+        self.last_event = Some(now);
+    }
+
+    /// How long until a pending regeneration is due, or `None` if nothing is pending.
+    fn remaining(&self, now: Instant) -> Option<Duration> {
+        self.last_event.map(|last| {
+            self.delay
+                .saturating_sub(now.saturating_duration_since(last))
+        })
+    }
+
+    /// Returns `true` (and clears the pending state) if a regeneration is due.
+    fn ready(&mut self, now: Instant) -> bool {
+        match self.last_event {
+            Some(last) if now.saturating_duration_since(last) >= self.delay => {
+                self.last_event = None;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+#[test]
+fn debouncer_coalesces_bursts() {
+    let start = Instant::now();
+    let ms = Duration::from_millis;
+    let mut debouncer = Debouncer::new(ms(100));
+
+    assert!(!debouncer.ready(start));
+    assert_eq!(debouncer.remaining(start), None);
+
+    // A burst of events keeps pushing the deadline out.
+    debouncer.record(start);
+    debouncer.record(start + ms(50));
+    debouncer.record(start + ms(120));
+    assert!(!debouncer.ready(start + ms(150)));
+    assert_eq!(debouncer.remaining(start + ms(150)), Some(ms(70)));
+
+    // Once things are quiet, we fire exactly once.
+    assert!(debouncer.ready(start + ms(220)));
+    assert!(!debouncer.ready(start + ms(500)));
+    assert_eq!(debouncer.remaining(start + ms(500)), None);
+
+    // A later event starts a new window.
+    debouncer.record(start + ms(600));
+    assert!(!debouncer.ready(start + ms(650)));
+    assert!(debouncer.ready(start + ms(700)));
+}
+
+#[cfg(test)]
+fn test_watched() -> Watched {
+    Watched {
+        buildfiles: FxHashMap::from_iter([
+            (
+                PathBuf::from("/repo/foo/BUCK"),
+                vec![Target::new("//foo:b"), Target::new("//foo:a")],
+            ),
+            (
+                PathBuf::from("/repo/bar/TARGETS"),
+                vec![Target::new("//bar:c"), Target::new("//foo:a")],
+            ),
+        ]),
+        buckconfig: PathBuf::from("/repo/.buckconfig"),
+    }
+}
+
+#[cfg(test)]
+fn modified(paths: &[&str]) -> notify::Event {
+    use notify::event::ModifyKind;
+
+    paths.iter().fold(
+        notify::Event::new(EventKind::Modify(ModifyKind::Any)),
+        |event, path| event.add_path(PathBuf::from(path)),
+    )
+}
+
+#[test]
+fn irrelevant_events_are_ignored() {
+    use notify::event::AccessKind;
+
+    let watched = test_watched();
+    let mut changes = ChangeSet::default();
+
+    assert!(changes.record(&modified(&["/repo/foo/BUCK"]), &watched));
+    assert!(!changes.record(&modified(&["/repo/foo/src/lib.rs"]), &watched));
+    assert!(!changes.record(&modified(&["/repo/baz/BUCK"]), &watched));
+
+    let event = notify::Event::new(EventKind::Access(AccessKind::Any))
+        .add_path(PathBuf::from("/repo/bar/TARGETS"));
+    assert!(!changes.record(&event, &watched));
+
+    assert!(!changes.config);
+    assert_eq!(
+        changes.buildfiles,
+        BTreeSet::from_iter([PathBuf::from("/repo/foo/BUCK")])
+    );
+}
+
+#[test]
+fn changes_map_to_affected_targets() {
+    let watched = test_watched();
+    let labels = |changes: &ChangeSet| {
+        changes
+            .affected_targets(&watched)
+            .into_iter()
+            .map(|t| t.to_string())
+            .collect::<Vec<_>>()
+    };
+
+    let mut changes = ChangeSet::default();
+    assert!(labels(&changes).is_empty());
+
+    changes.record(&modified(&["/repo/foo/BUCK"]), &watched);
+    assert_eq!(labels(&changes), vec!["//foo:a", "//foo:b"]);
+
+    // Targets owned by several changed buildfiles are only reported once.
+    changes.record(
+        &modified(&["/repo/bar/TARGETS", "/repo/foo/BUCK"]),
+        &watched,
+    );
+    assert_eq!(labels(&changes), vec!["//bar:c", "//foo:a", "//foo:b"]);
+
+    // A `.buckconfig` change affects every target.
+    let mut changes = ChangeSet::default();
+    changes.record(&modified(&["/repo/.buckconfig"]), &watched);
+    assert!(changes.config);
+    assert_eq!(labels(&changes), vec!["//bar:c", "//foo:a", "//foo:b"]);
+}
+
+#[test]
+fn changes_reload_owners_of_changed_buildfiles() {
+    let labels = |watched: &Watched| {
+        watched
+            .targets()
+            .into_iter()
+            .map(|t| t.to_string())
+            .collect::<Vec<_>>()
+    };
+    let input = Input::Targets(vec![Target::new("//foo:a")]);
+
+    let mut watched = test_watched();
+    let mut changes = ChangeSet::default();
+    changes.record(&modified(&["/repo/foo/BUCK"]), &watched);
+    // Only the changed buildfile is queried again.
+    assert!(matches!(
+        watched.reload_input(&changes, &input),
+        Input::Buildfile(buildfiles) if buildfiles == [PathBuf::from("/repo/foo/BUCK")]
+    ));
+    watched.update(
+        &changes,
+        FxHashMap::from_iter([(
+            PathBuf::from("/repo/foo/BUCK"),
+            vec![Target::new("//foo:d")],
+        )]),
+    );
+    assert_eq!(labels(&watched), vec!["//bar:c", "//foo:a", "//foo:d"]);
+
+    // A `.buckconfig` change queries the whole input again.
+    let mut changes = ChangeSet::default();
+    changes.record(&modified(&["/repo/.buckconfig"]), &watched);
+    assert!(matches!(
+        watched.reload_input(&changes, &input),
+        Input::Targets(_)
+    ));
+    watched.update(
+        &changes,
+        FxHashMap::from_iter([(
+            PathBuf::from("/repo/baz/BUCK"),
+            vec![Target::new("//baz:e")],
+        )]),
+    );
+    assert_eq!(labels(&watched), vec!["//baz:e"]);
+}
+
+#[test]
+fn change_regenerates_once_after_debounce() {
+    let mut watched = test_watched();
+    let debounce = Duration::from_millis(20);
+
+    let (tx, rx) = mpsc::channel();
+    let sender = std::thread::spawn(move || {
+        // A burst of saves to two buildfiles, and an unrelated change.
+        for path in [
+            "/repo/foo/BUCK",
+            "/repo/foo/BUCK",
+            "/repo/foo/lib.rs",
+            "/repo/bar/TARGETS",
+        ] {
+            tx.send(WatchEvent::Fs(Ok(modified(&[path])))).unwrap();
+        }
+        std::thread::sleep(debounce * 10);
+        tx.send(WatchEvent::Interrupted).unwrap();
+    });
+
+    let mut regenerations = Vec::new();
+    watch_loop(&rx, &mut watched, debounce, |watched, changes| {
+        regenerations.push(changes.affected_targets(watched).len())
+    });
+    sender.join().unwrap();
+    assert_eq!(regenerations, vec![3]);
+}
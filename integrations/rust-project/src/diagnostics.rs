@@ -7,8 +7,17 @@
  * of this source tree.
  */
 
+use std::collections::BTreeMap;
 use std::path::PathBuf;
 
+use lsp_types::DiagnosticRelatedInformation;
+use lsp_types::DiagnosticSeverity;
+use lsp_types::Location;
+use lsp_types::NumberOrString;
+use lsp_types::Position;
+use lsp_types::PublishDiagnosticsParams;
+use lsp_types::Range;
+use lsp_types::Url;
 use serde::Deserialize;
 use serde::Serialize;
 
@@ -67,3 +76,339 @@ pub(crate) struct Text {
     pub(crate) highlight_start: usize,
     pub(crate) highlight_end: usize,
 }
+
+/// Groups rustc diagnostics by file, as the parameters of LSP `textDocument/publishDiagnostics`
+/// notifications. Every file in `saved_files` is included even without diagnostics, so that
+/// clients clear the ones previously shown for it.
+///
+/// Diagnostics without a primary span, such as the summary rustc emits at the end of a build,
+/// are left out.
+pub(crate) fn to_publish_diagnostics(
+    messages: &[Message],
+    saved_files: &[PathBuf],
+) -> Vec<PublishDiagnosticsParams> {
+    let mut by_file: BTreeMap<Url, Vec<lsp_types::Diagnostic>> = saved_files
+        .iter()
+        .filter_map(|path| Some((Url::from_file_path(path).ok()?, Vec::new())))
+        .collect();
+    for message in messages {
+        if let Some((uri, diagnostic)) = to_lsp_diagnostic(message) {
+            by_file.entry(uri).or_default().push(diagnostic);
+        }
+    }
+    by_file
+        .into_iter()
+        .map(|(uri, diagnostics)| PublishDiagnosticsParams::new(uri, diagnostics, None))
+        .collect()
+}
+
+/// Converts a rustc diagnostic to an LSP diagnostic at its primary span, along with the file
+/// the span is in. Clippy lints have `clippy` as their source, to tell them apart from rustc's
+/// own diagnostics.
+pub(crate) fn to_lsp_diagnostic(message: &Message) -> Option<(Url, lsp_types::Diagnostic)> {
+    let primary = message.spans.iter().find(|span| span.is_primary)?;
+    let uri = Url::from_file_path(&primary.file_name).ok()?;
+
+    let mut text = message.message.clone();
+    let mut related_information = Vec::new();
+    for span in &message.spans {
+        if let (false, Some(label)) = (span.is_primary, &span.label) {
+            related_information.extend(related(span, label.clone()));
+        }
+    }
+    for child in &message.children {
+        let child_message = format!("{}: {}", child.level, child.message);
+        match child.spans.iter().find(|span| span.is_primary) {
+            Some(span) => related_information.extend(related(span, child_message)),
+            None => {
+                text.push('\n');
+                text.push_str(&child_message);
+            }
+        }
+    }
+
+    let code = message.code.as_ref().map(|code| code.code.clone());
+    let source = match &code {
+        Some(code) if code.starts_with("clippy::") => "clippy",
+        _ => "rustc",
+    };
+    let diagnostic = lsp_types::Diagnostic {
+        range: range(primary),
+        severity: Some(severity(&message.level)),
+        code: code.map(NumberOrString::String),
+        source: Some(source.to_owned()),
+        message: text,
+        related_information: (!related_information.is_empty()).then_some(related_information),
+        ..Default::default()
+    };
+    Some((uri, diagnostic))
+}
+
+fn severity(level: &str) -> DiagnosticSeverity {
+    match level {
+        "error" | "error: internal compiler error" => DiagnosticSeverity::ERROR,
+        "warning" => DiagnosticSeverity::WARNING,
+        "help" => DiagnosticSeverity::HINT,
+        _ => DiagnosticSeverity::INFORMATION,
+    }
+}
+
+/// rustc lines and columns are 1-based, LSP ones are 0-based.
+fn range(span: &Span) -> Range {
+    Range::new(
+        Position::new(
+            span.line_start.saturating_sub(1) as u32,
+            span.column_start.saturating_sub(1) as u32,
+        ),
+        Position::new(
+            span.line_end.saturating_sub(1) as u32,
+            span.column_end.saturating_sub(1) as u32,
+        ),
+    )
+}
+
+fn related(span: &Span, message: String) -> Option<DiagnosticRelatedInformation> {
+    Some(DiagnosticRelatedInformation {
+        location: Location::new(Url::from_file_path(&span.file_name).ok()?, range(span)),
+        message,
+    })
+}
+
+/// The outcome of a `check`, from the most to the least actionable in the saved files.
+#[derive(Serialize, Clone, Copy, Debug, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum CheckStatus {
+    /// No errors, though there may be warnings.
+    Success,
+    /// rustc reported errors in the saved files, and maybe in other files too.
+    SavedFileError,
+    /// rustc reported errors, but only in other files, such as those of dependencies.
+    OtherFileError,
+    /// buck failed, so there are no diagnostics to show.
+    BuckError,
+}
+
+impl CheckStatus {
+    /// Distinct from 1, which `rust-project` exits with on other errors, and from 2, which is
+    /// what it exits with on invalid arguments.
+    pub(crate) fn exit_code(self) -> i32 {
+        match self {
+            CheckStatus::Success => 0,
+            CheckStatus::SavedFileError => 3,
+            CheckStatus::OtherFileError => 4,
+            CheckStatus::BuckError => 5,
+        }
+    }
+}
+
+/// The machine-readable summary `check --output-format=json` prints after the diagnostics.
+#[derive(Serialize, Debug, Eq, PartialEq)]
+pub(crate) struct CheckSummary {
+    /// Always `check_summary`, so that this can be told apart from rustc's diagnostics, which
+    /// have `diagnostic` here.
+    #[serde(rename = "$message_type")]
+    pub(crate) message_type: &'static str,
+    pub(crate) status: CheckStatus,
+    pub(crate) exit_code: i32,
+    pub(crate) saved_file_errors: usize,
+    pub(crate) other_file_errors: usize,
+    pub(crate) warnings: usize,
+    /// Why buck failed, if the status is `buck_error`.
+    pub(crate) buck_error: Option<String>,
+}
+
+impl CheckSummary {
+    /// Counts the errors in rustc diagnostics by whether their primary span is in one of
+    /// `saved_files`. Errors without a primary span count as errors in other files, except for
+    /// rustc's final "aborting due to" summary.
+    pub(crate) fn classify(messages: &[Message], saved_files: &[PathBuf]) -> Self {
+        let mut saved_file_errors = 0;
+        let mut other_file_errors = 0;
+        let mut warnings = 0;
+        for message in messages {
+            match severity(&message.level) {
+                DiagnosticSeverity::ERROR => {}
+                DiagnosticSeverity::WARNING => {
+                    warnings += 1;
+                    continue;
+                }
+                _ => continue,
+            }
+            match message.spans.iter().find(|span| span.is_primary) {
+                Some(span) if saved_files.contains(&span.file_name) => saved_file_errors += 1,
+                None if message.message.starts_with("aborting due to") => {}
+                _ => other_file_errors += 1,
+            }
+        }
+
+        let status = if saved_file_errors > 0 {
+            CheckStatus::SavedFileError
+        } else if other_file_errors > 0 {
+            CheckStatus::OtherFileError
+        } else {
+            CheckStatus::Success
+        };
+        CheckSummary {
+            message_type: "check_summary",
+            status,
+            exit_code: status.exit_code(),
+            saved_file_errors,
+            other_file_errors,
+            warnings,
+            buck_error: None,
+        }
+    }
+
+    /// The summary of a `check` that failed before rustc reported anything.
+    pub(crate) fn buck_error(error: &anyhow::Error) -> Self {
+        CheckSummary {
+            message_type: "check_summary",
+            status: CheckStatus::BuckError,
+            exit_code: CheckStatus::BuckError.exit_code(),
+            saved_file_errors: 0,
+            other_file_errors: 0,
+            warnings: 0,
+            buck_error: Some(format!("{:#}", error)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UNUSED_VARIABLE: &str = r#"{"$message_type":"diagnostic","message":"unused variable: `x`","code":{"code":"unused_variables","explanation":null},"level":"warning","spans":[{"file_name":"/repo/src/lib.rs","byte_start":24,"byte_end":25,"line_start":2,"line_end":2,"column_start":9,"column_end":10,"is_primary":true,"text":[{"text":"    let x = 1;","highlight_start":9,"highlight_end":10}],"label":null,"suggested_replacement":null,"suggestion_applicability":null,"expansion":null}],"children":[{"message":"`#[warn(unused_variables)]` on by default","code":null,"level":"note","spans":[],"children":[],"rendered":null},{"message":"if this is intentional, prefix it with an underscore","code":null,"level":"help","spans":[{"file_name":"/repo/src/lib.rs","byte_start":24,"byte_end":25,"line_start":2,"line_end":2,"column_start":9,"column_end":10,"is_primary":true,"text":[{"text":"    let x = 1;","highlight_start":9,"highlight_end":10}],"label":null,"suggested_replacement":"_x","suggestion_applicability":"MachineApplicable","expansion":null}],"children":[],"rendered":null}],"rendered":"warning: unused variable: `x`\n"}"#;
+
+    const NEEDLESS_RETURN: &str = r#"{"$message_type":"diagnostic","message":"unneeded `return` statement","code":{"code":"clippy::needless_return","explanation":null},"level":"warning","spans":[{"file_name":"/repo/src/main.rs","byte_start":40,"byte_end":49,"line_start":3,"line_end":3,"column_start":5,"column_end":14,"is_primary":true,"text":[{"text":"    return x;","highlight_start":5,"highlight_end":14}],"label":null,"suggested_replacement":null,"suggestion_applicability":null,"expansion":null}],"children":[],"rendered":"warning: unneeded `return` statement\n"}"#;
+
+    const ABORTING: &str = r#"{"$message_type":"diagnostic","message":"aborting due to 1 previous error","code":null,"level":"error","spans":[],"children":[],"rendered":"error: aborting due to 1 previous error\n"}"#;
+
+    const MISMATCHED_TYPES: &str = r#"{"$message_type":"diagnostic","message":"mismatched types","code":{"code":"E0308","explanation":null},"level":"error","spans":[{"file_name":"/repo/src/lib.rs","byte_start":30,"byte_end":34,"line_start":3,"line_end":3,"column_start":5,"column_end":9,"is_primary":true,"text":[{"text":"    true","highlight_start":5,"highlight_end":9}],"label":"expected `u32`, found `bool`","suggested_replacement":null,"suggestion_applicability":null,"expansion":null}],"children":[],"rendered":"error[E0308]: mismatched types\n"}"#;
+
+    const UNRESOLVED_IMPORT: &str = r#"{"$message_type":"diagnostic","message":"unresolved import `crate::missing`","code":{"code":"E0432","explanation":null},"level":"error","spans":[{"file_name":"/repo/dep/src/lib.rs","byte_start":4,"byte_end":18,"line_start":1,"line_end":1,"column_start":5,"column_end":19,"is_primary":true,"text":[{"text":"use crate::missing;","highlight_start":5,"highlight_end":19}],"label":"no `missing` in the root","suggested_replacement":null,"suggestion_applicability":null,"expansion":null}],"children":[],"rendered":"error[E0432]: unresolved import `crate::missing`\n"}"#;
+
+    fn parse(line: &str) -> Message {
+        serde_json::from_str(line).unwrap()
+    }
+
+    #[test]
+    fn test_rustc_diagnostic() {
+        let (uri, diagnostic) = to_lsp_diagnostic(&parse(UNUSED_VARIABLE)).unwrap();
+        assert_eq!("file:///repo/src/lib.rs", uri.as_str());
+        assert_eq!(
+            Range::new(Position::new(1, 8), Position::new(1, 9)),
+            diagnostic.range
+        );
+        assert_eq!(Some(DiagnosticSeverity::WARNING), diagnostic.severity);
+        assert_eq!(
+            "unused variable: `x`\nnote: `#[warn(unused_variables)]` on by default",
+            diagnostic.message
+        );
+        assert_eq!(
+            Some(NumberOrString::String("unused_variables".to_owned())),
+            diagnostic.code
+        );
+        assert_eq!(Some("rustc"), diagnostic.source.as_deref());
+        assert_eq!(
+            Some(vec![DiagnosticRelatedInformation {
+                location: Location::new(uri, Range::new(Position::new(1, 8), Position::new(1, 9))),
+                message: "help: if this is intentional, prefix it with an underscore".to_owned(),
+            }]),
+            diagnostic.related_information
+        );
+    }
+
+    #[test]
+    fn test_clippy_diagnostic() {
+        let (_, diagnostic) = to_lsp_diagnostic(&parse(NEEDLESS_RETURN)).unwrap();
+        assert_eq!(Some("clippy"), diagnostic.source.as_deref());
+        assert_eq!(
+            Some(NumberOrString::String("clippy::needless_return".to_owned())),
+            diagnostic.code
+        );
+    }
+
+    #[test]
+    fn test_publish_diagnostics() {
+        let messages = [UNUSED_VARIABLE, NEEDLESS_RETURN, ABORTING].map(parse);
+        let saved_files = [
+            PathBuf::from("/repo/src/lib.rs"),
+            PathBuf::from("/repo/src/clean.rs"),
+        ];
+        let published: Vec<(String, usize)> = to_publish_diagnostics(&messages, &saved_files)
+            .into_iter()
+            .map(|params| (params.uri.to_string(), params.diagnostics.len()))
+            .collect();
+        assert_eq!(
+            vec![
+                ("file:///repo/src/clean.rs".to_owned(), 0),
+                ("file:///repo/src/lib.rs".to_owned(), 1),
+                ("file:///repo/src/main.rs".to_owned(), 1),
+            ],
+            published
+        );
+    }
+
+    fn classify(lines: &[&str]) -> CheckSummary {
+        let messages: Vec<Message> = lines.iter().map(|line| parse(line)).collect();
+        CheckSummary::classify(&messages, &[PathBuf::from("/repo/src/lib.rs")])
+    }
+
+    #[test]
+    fn test_classify_success() {
+        let summary = classify(&[UNUSED_VARIABLE, NEEDLESS_RETURN]);
+        assert_eq!(CheckStatus::Success, summary.status);
+        assert_eq!(0, summary.exit_code);
+        assert_eq!(2, summary.warnings);
+    }
+
+    #[test]
+    fn test_classify_saved_file_error() {
+        let summary = classify(&[
+            UNRESOLVED_IMPORT,
+            MISMATCHED_TYPES,
+            UNUSED_VARIABLE,
+            ABORTING,
+        ]);
+        assert_eq!(
+            CheckSummary {
+                message_type: "check_summary",
+                status: CheckStatus::SavedFileError,
+                exit_code: 3,
+                saved_file_errors: 1,
+                other_file_errors: 1,
+                warnings: 1,
+                buck_error: None,
+            },
+            summary
+        );
+    }
+
+    #[test]
+    fn test_classify_other_file_error() {
+        let summary = classify(&[UNRESOLVED_IMPORT, UNUSED_VARIABLE, ABORTING]);
+        assert_eq!(CheckStatus::OtherFileError, summary.status);
+        assert_eq!(4, summary.exit_code);
+        assert_eq!(
+            (0, 1),
+            (summary.saved_file_errors, summary.other_file_errors)
+        );
+    }
+
+    #[test]
+    fn test_buck_error_summary() {
+        let error = anyhow::anyhow!("Unknown target `foo`").context("Failed to run buck2 bxl");
+        assert_eq!(
+            serde_json::json!({
+                "$message_type": "check_summary",
+                "status": "buck_error",
+                "exit_code": 5,
+                "saved_file_errors": 0,
+                "other_file_errors": 0,
+                "warnings": 0,
+                "buck_error": "Failed to run buck2 bxl: Unknown target `foo`",
+            }),
+            serde_json::to_value(CheckSummary::buck_error(&error)).unwrap()
+        );
+    }
+}
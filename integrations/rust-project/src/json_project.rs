@@ -93,6 +93,11 @@ pub(crate) struct Crate {
     /// proc-macro (.so, .dylib, or .dll. depends on the platform.)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub(crate) proc_macro_dylib_path: Option<PathBuf>,
+    /// The buck target this crate was generated from. Unlike `build`, this is
+    /// set for every crate, and it is not written to `rust-project.json`: it
+    /// is only used to report problems in the crate graph.
+    #[serde(skip)]
+    pub(crate) label: Option<Target>,
 }
 
 /// Build system-specific additions the `rust-project.json`.
@@ -216,6 +221,122 @@ pub(crate) struct Dep {
     pub(crate) name: String,
 }
 
+/// Remove test crates, and crates that only test crates depend on, from
+/// `crates`. `is_test[i]` says whether `crates[i]` is a test.
+///
+/// Workspace members and crates that nothing depends on are always kept
+/// (unless they are tests), along with everything they depend on. The
+/// remaining crates keep their relative order, and every [`Dep::crate_index`]
+/// is renumbered to match.
+pub(crate) fn remove_test_only_crates(crates: Vec<Crate>, is_test: &[bool]) -> Vec<Crate> {
+    let mut has_rdeps = vec![false; crates.len()];
+    for krate in &crates {
+        for dep in &krate.deps {
+            has_rdeps[dep.crate_index] = true;
+        }
+    }
+
+    let mut keep = vec![false; crates.len()];
+    let mut stack = (0..crates.len())
+        .filter(|idx| !is_test[*idx] && (crates[*idx].is_workspace_member || !has_rdeps[*idx]))
+        .collect::<Vec<_>>();
+    while let Some(idx) = stack.pop() {
+        if keep[idx] {
+            continue;
+        }
+        keep[idx] = true;
+        stack.extend(crates[idx].deps.iter().map(|dep| dep.crate_index));
+    }
+
+    // Kept crates only depend on kept crates, so no dependency is dropped.
+    retain_crates(crates, &keep)
+}
+
+/// Remove the crates whose label matches one of the `exclude` glob patterns
+/// from `crates`, along with every dependency on them.
+///
+/// Crates depending on an excluded crate are kept, but lose that dependency.
+/// The remaining crates keep their relative order, and every
+/// [`Dep::crate_index`] is renumbered to match.
+pub(crate) fn remove_excluded_crates(crates: Vec<Crate>, exclude: &[String]) -> Vec<Crate> {
+    if exclude.is_empty() {
+        return crates;
+    }
+    let keep = crates
+        .iter()
+        .map(|krate| match &krate.label {
+            Some(label) => !exclude.iter().any(|pattern| glob_matches(pattern, label)),
+            None => true,
+        })
+        .collect::<Vec<_>>();
+    retain_crates(crates, &keep)
+}
+
+/// Keep the crates for which `keep` is true, dropping dependencies on the
+/// others, and renumber every [`Dep::crate_index`] to match.
+fn retain_crates(crates: Vec<Crate>, keep: &[bool]) -> Vec<Crate> {
+    let mut new_indexes = vec![None; crates.len()];
+    let mut next = 0;
+    for (idx, kept) in keep.iter().enumerate() {
+        if *kept {
+            new_indexes[idx] = Some(next);
+            next += 1;
+        }
+    }
+
+    crates
+        .into_iter()
+        .zip(keep)
+        .filter_map(|(mut krate, kept)| {
+            if !kept {
+                return None;
+            }
+            krate
+                .deps
+                .retain_mut(|dep| match new_indexes[dep.crate_index] {
+                    Some(idx) => {
+                        dep.crate_index = idx;
+                        true
+                    }
+                    None => false,
+                });
+            Some(krate)
+        })
+        .collect()
+}
+
+/// Whether `text` matches `pattern`, where `*` matches any sequence of
+/// characters and `?` matches any single character.
+fn glob_matches(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let text = text.chars().collect::<Vec<_>>();
+    let (mut p, mut t) = (0, 0);
+    // Where to resume after the last `*` if the rest fails to match: the
+    // pattern position after it, and the text position it matched up to.
+    let mut backtrack = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p + 1, t));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star_p, star_t)) => {
+                    p = star_p;
+                    t = star_t + 1;
+                    backtrack = Some((star_p, star_t + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
 /// Sysroot paths. These are documented in the rust-analyzer manual:
 ///
 /// <https://rust-analyzer.github.io/book/non_cargo_based_projects.html>
@@ -251,3 +372,104 @@ pub(crate) struct Sysroot {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub(crate) sysroot_project: Option<JsonProject>,
 }
+
+#[test]
+fn remove_test_only_crates_renumbers_deps() {
+    fn krate(name: &str, is_workspace_member: bool, deps: &[(usize, &str)]) -> Crate {
+        Crate {
+            display_name: Some(name.to_owned()),
+            is_workspace_member,
+            deps: deps
+                .iter()
+                .map(|(crate_index, name)| Dep {
+                    crate_index: *crate_index,
+                    name: (*name).to_owned(),
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    // `lib_test` is the only thing using `test_util`, which is the only thing
+    // using `mock`. `lib` is only depended on by its test, but it's in the
+    // workspace.
+    let crates = vec![
+        krate("lib_test", true, &[(1, "test_util"), (2, "lib")]),
+        krate("test_util", false, &[(4, "mock")]),
+        krate("lib", true, &[(3, "shared")]),
+        krate("shared", false, &[]),
+        krate("mock", false, &[(3, "shared")]),
+        krate("bin", false, &[(3, "shared")]),
+    ];
+    let is_test = [true, false, false, false, false, false];
+
+    let pruned = remove_test_only_crates(crates, &is_test);
+    assert_eq!(
+        pruned,
+        vec![
+            krate("lib", true, &[(1, "shared")]),
+            krate("shared", false, &[]),
+            krate("bin", false, &[(1, "shared")]),
+        ]
+    );
+}
+
+#[test]
+fn remove_excluded_crates_prunes_deps() {
+    fn krate(label: &str, deps: &[(usize, &str)]) -> Crate {
+        Crate {
+            display_name: Some(label.rsplit(':').next().unwrap().to_owned()),
+            label: Some(Target::new(label)),
+            deps: deps
+                .iter()
+                .map(|(crate_index, name)| Dep {
+                    crate_index: *crate_index,
+                    name: (*name).to_owned(),
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    let crates = vec![
+        krate(
+            "fbcode//app:app",
+            &[(1, "generated"), (2, "lib"), (3, "serde")],
+        ),
+        krate("fbcode//app:generated", &[]),
+        krate("fbcode//lib:lib", &[(3, "serde"), (4, "serde_derive")]),
+        krate("third-party//serde:serde", &[(4, "serde_derive")]),
+        krate("third-party//serde:serde_derive", &[]),
+    ];
+
+    let pruned = remove_excluded_crates(
+        crates,
+        &["third-party//*".to_owned(), "*:gen?rated".to_owned()],
+    );
+    assert_eq!(
+        pruned,
+        vec![
+            krate("fbcode//app:app", &[(1, "lib")]),
+            krate("fbcode//lib:lib", &[]),
+        ]
+    );
+}
+
+#[test]
+fn glob_matching() {
+    assert!(glob_matches("fbcode//foo:bar", "fbcode//foo:bar"));
+    assert!(!glob_matches("fbcode//foo:bar", "fbcode//foo:baz"));
+    assert!(glob_matches("fbcode//foo:*", "fbcode//foo:bar"));
+    assert!(glob_matches("fbcode//foo:*", "fbcode//foo:"));
+    assert!(glob_matches(
+        "*//third-party/*-sys",
+        "fbsource//third-party/rust:libz-sys"
+    ));
+    assert!(!glob_matches(
+        "*-sys",
+        "fbsource//third-party/rust:libz-sys-tests"
+    ));
+    assert!(glob_matches("fbcode//foo:ba?", "fbcode//foo:baz"));
+    assert!(!glob_matches("fbcode//foo:ba?", "fbcode//foo:ba"));
+    assert!(glob_matches("**", ""));
+}
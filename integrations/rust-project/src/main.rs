@@ -22,6 +22,7 @@ use std::io::IsTerminal as _;
 use std::path::PathBuf;
 use std::str::FromStr;
 
+use buck2_error::interop::ErrorReportModel;
 use clap::ArgAction;
 use clap::Parser;
 use clap::Subcommand;
@@ -31,6 +32,8 @@ use tracing_subscriber::Layer;
 use tracing_subscriber::filter::LevelFilter;
 use tracing_subscriber::layer::SubscriberExt;
 
+use crate::cli::MessageFormat;
+use crate::cli::OutputFormat;
 use crate::cli::ProjectKind;
 use crate::json_project::Crate;
 use crate::json_project::Dep;
@@ -61,13 +64,25 @@ enum Command {
     /// Convert buck's build to a format that rust-analyzer can consume.
     Develop {
         /// Buck targets to include in rust-project.json.
-        #[clap(required = true, conflicts_with = "files", num_args=1..)]
+        #[clap(
+            required_unless_present_any = ["files", "targets_from"],
+            conflicts_with_all = ["files", "targets_from"],
+            num_args=1..
+        )]
         targets: Vec<String>,
 
+        /// Read the Buck targets to include from a file, one per line, or from
+        /// stdin when `-`.
+        ///
+        /// Blank lines and `#` comments are ignored. Useful for target sets too
+        /// large to pass on the command line. Can be combined with `files`.
+        #[clap(long, value_name = "FILE")]
+        targets_from: Option<PathBuf>,
+
         /// Path of the file being developed.
         ///
         /// Used to discover the owning set of targets.
-        #[clap(required = true, last = true, num_args=1..)]
+        #[clap(required_unless_present_any = ["targets", "targets_from"], last = true, num_args=1..)]
         files: Vec<PathBuf>,
 
         /// Where to write the generated `rust-project.json`.
@@ -111,6 +126,34 @@ enum Command {
         /// Include a `build` section for every crate, including dependencies. Otherwise, `build` is only included for crates in the workspace.
         #[clap(long)]
         include_all_buildfiles: bool,
+
+        /// Keep running and regenerate `rust-project.json` whenever a buildfile
+        /// owning the requested targets changes.
+        #[clap(long, conflicts_with = "stdout")]
+        watch: bool,
+
+        /// Fail if a dependency of the requested targets can't be resolved.
+        ///
+        /// By default, crates that depend on missing targets are left out of
+        /// `rust-project.json` and the missing targets are reported.
+        #[clap(long)]
+        strict: bool,
+
+        /// Include test crates, and crates that only test crates depend on.
+        ///
+        /// Pass `--include-tests=false` to leave them out, which can make
+        /// rust-analyzer much faster to index large projects.
+        #[clap(long, default_value = "true", action = ArgAction::Set)]
+        include_tests: bool,
+
+        /// Leave out crates whose target label matches this glob pattern, such
+        /// as `fbsource//third-party/rust:*`. Can be repeated.
+        ///
+        /// `*` matches any sequence of characters and `?` any single
+        /// character. Excluding a crate that other crates depend on drops
+        /// those dependencies from the generated project.
+        #[clap(long, value_name = "PATTERN")]
+        exclude: Vec<String>,
     },
     /// `DevelopJson` is a more limited, stripped down [`Command::Develop`].
     ///
@@ -130,9 +173,16 @@ enum Command {
         #[clap(long)]
         client: Option<String>,
 
+        /// Check for cycles in the generated crate graph.
+        ///
+        /// Rather than failing, each cycle found is logged and broken by
+        /// ignoring one of its dependencies.
+        #[clap(long, default_value = "true", action = ArgAction::Set)]
+        check_cycles: bool,
+
         args: JsonArguments,
     },
-    /// Build the saved file's owning target. This is meant to be used by IDEs to provide diagnostics on save.
+    /// Build the saved files' owning targets. This is meant to be used by IDEs to provide diagnostics on save.
     Check {
         /// Optional argument specifying build mode.
         #[clap(short = 'm', long)]
@@ -141,12 +191,24 @@ enum Command {
         #[clap(short = 'c', long, default_value = "true", action = ArgAction::Set)]
         use_clippy: bool,
 
+        /// How to print diagnostics. `json` prints LSP diagnostics, which tell
+        /// clippy lints apart from rustc's by their `source`.
+        #[clap(long, value_enum, default_value = "rustc")]
+        message_format: MessageFormat,
+
+        /// `json` prints a summary after the diagnostics, and exits with 3 if there are errors in
+        /// the saved files, 4 if there are errors only in other files, and 5 if buck failed.
+        #[clap(long, value_enum, default_value = "text")]
+        output_format: OutputFormat,
+
         /// The name of the client invoking rust-project, such as 'vscode'.
         #[clap(long)]
         client: Option<String>,
 
-        /// The file saved by the user. `rust-project` will infer the owning target(s) of the saved file and build them.
-        saved_file: PathBuf,
+        /// The file(s) saved by the user. `rust-project` will infer the owning target(s) of the saved files and build them
+        /// in a single buck invocation.
+        #[clap(required = true)]
+        saved_files: Vec<PathBuf>,
     },
 }
 
@@ -236,22 +298,33 @@ fn main() -> Result<(), anyhow::Error> {
         .with_writer(io::stderr);
 
     match command {
+        c @ Command::Develop { watch: true, .. } => {
+            // Only report each regeneration rather than the progress of every step.
+            let filter = EnvFilter::builder()
+                .with_default_directive(LevelFilter::WARN.into())
+                .from_env()?
+                .add_directive("rust_project::cli::watch=info".parse()?);
+            let subscriber = tracing_subscriber::registry().with(fmt.with_filter(filter));
+            tracing::subscriber::set_global_default(subscriber)?;
+
+            let (develop, input, out) = cli::Develop::from_command(c)?;
+            cli::Watch {
+                develop,
+                input,
+                out,
+            }
+            .run()
+        }
         c @ Command::Develop { .. } => {
             let subscriber = tracing_subscriber::registry().with(fmt.with_filter(filter));
             tracing::subscriber::set_global_default(subscriber)?;
 
-            let (develop, input, out) = cli::Develop::from_command(c);
-            match develop.run(input.clone(), out) {
+            let (develop, input, out) = cli::Develop::from_command(c)?;
+            match develop.run(input.clone(), &out) {
                 Ok(_) => Ok(()),
                 Err(e) => {
                     crate::scuba::log_develop_error(&e, input, false);
-                    tracing::error!(
-                        error = <anyhow::Error as AsRef<
-                            dyn std::error::Error + Send + Sync + 'static,
-                        >>::as_ref(&e),
-                        source = e.source(),
-                        kind = "error",
-                    );
+                    log_develop_error(e);
                     Ok(())
                 }
             }
@@ -261,18 +334,12 @@ fn main() -> Result<(), anyhow::Error> {
                 .with(progress::ProgressLayer::new(std::io::stdout).with_filter(filter));
             tracing::subscriber::set_global_default(subscriber)?;
 
-            let (develop, input, out) = cli::Develop::from_command(c);
-            match develop.run(input.clone(), out) {
+            let (develop, input, out) = cli::Develop::from_command(c)?;
+            match develop.run(input.clone(), &out) {
                 Ok(_) => Ok(()),
                 Err(e) => {
                     crate::scuba::log_develop_error(&e, input, true);
-                    tracing::error!(
-                        error = <anyhow::Error as AsRef<
-                            dyn std::error::Error + Send + Sync + 'static,
-                        >>::as_ref(&e),
-                        source = e.source(),
-                        kind = "error",
-                    );
+                    log_develop_error(e);
                     Ok(())
                 }
             }
@@ -286,19 +353,46 @@ fn main() -> Result<(), anyhow::Error> {
         Command::Check {
             mode,
             use_clippy,
-            saved_file,
+            message_format,
+            output_format,
+            saved_files,
             ..
         } => {
             let subscriber = tracing_subscriber::registry().with(fmt.with_filter(filter));
             tracing::subscriber::set_global_default(subscriber)?;
 
-            cli::Check::new(mode, use_clippy, saved_file.clone())
+            let result = cli::Check::new(mode, use_clippy, message_format, saved_files.clone())
                 .run()
-                .inspect_err(|e| crate::scuba::log_check_error(&e, &saved_file, use_clippy))
+                .inspect_err(|e| crate::scuba::log_check_error(e, &saved_files, use_clippy));
+            match output_format {
+                OutputFormat::Text => result.map(|_| ()),
+                OutputFormat::Json => {
+                    let summary = result.unwrap_or_else(|e| {
+                        tracing::error!("{:#}", e);
+                        diagnostics::CheckSummary::buck_error(&e)
+                    });
+                    println!("{}", serde_json::to_string(&summary)?);
+                    std::process::exit(summary.exit_code)
+                }
+            }
         }
     }
 }
 
+/// Logs the error that ended a `develop`, with a report of its whole chain for tools reading the
+/// JSON output.
+fn log_develop_error(e: anyhow::Error) {
+    let report = ErrorReportModel::from_anyhow(&e);
+    tracing::error!(
+        error = report.message.as_str(),
+        source = report.source_chain.first().map(String::as_str),
+        report = serde_json::to_string(&report)
+            .expect("reports serialize to JSON")
+            .as_str(),
+        kind = "error",
+    );
+}
+
 #[cfg(not(unix))]
 fn build_info() -> String {
     "No build info available.".to_owned()
@@ -367,6 +461,176 @@ fn test_parse_use_clippy() {
     ));
 }
 
+#[test]
+fn test_parse_message_format() {
+    assert!(matches!(
+        Opt::try_parse_from(["rust-project", "check", "fbcode/foo.rs"]),
+        Ok(Opt {
+            command: Some(Command::Check {
+                message_format: MessageFormat::Rustc,
+                ..
+            }),
+            ..
+        })
+    ));
+
+    assert!(matches!(
+        Opt::try_parse_from([
+            "rust-project",
+            "check",
+            "--message-format",
+            "json",
+            "fbcode/foo.rs",
+        ]),
+        Ok(Opt {
+            command: Some(Command::Check {
+                message_format: MessageFormat::Json,
+                ..
+            }),
+            ..
+        })
+    ));
+}
+
+#[test]
+fn test_parse_output_format() {
+    assert!(matches!(
+        Opt::try_parse_from(["rust-project", "check", "fbcode/foo.rs"]),
+        Ok(Opt {
+            command: Some(Command::Check {
+                output_format: OutputFormat::Text,
+                ..
+            }),
+            ..
+        })
+    ));
+
+    assert!(matches!(
+        Opt::try_parse_from([
+            "rust-project",
+            "check",
+            "--output-format=json",
+            "fbcode/foo.rs",
+        ]),
+        Ok(Opt {
+            command: Some(Command::Check {
+                output_format: OutputFormat::Json,
+                ..
+            }),
+            ..
+        })
+    ));
+}
+
+#[test]
+fn test_parse_check_saved_files() {
+    let opt = Opt::try_parse_from(["rust-project", "check", "fbcode/foo.rs", "fbcode/bar.rs"])
+        .expect("Unable to parse args");
+    match opt.command {
+        Some(Command::Check { saved_files, .. }) => assert_eq!(
+            saved_files,
+            vec![
+                PathBuf::from("fbcode/foo.rs"),
+                PathBuf::from("fbcode/bar.rs")
+            ]
+        ),
+        _ => panic!("expected check command"),
+    }
+
+    let opt = Opt::try_parse_from([
+        "rust-project",
+        "check",
+        "--use-clippy=false",
+        "fbcode/foo.rs",
+    ])
+    .expect("Unable to parse args");
+    assert!(matches!(
+        opt.command,
+        Some(Command::Check {
+            use_clippy: false,
+            saved_files,
+            ..
+        }) if saved_files == vec![PathBuf::from("fbcode/foo.rs")]
+    ));
+
+    assert!(Opt::try_parse_from(["rust-project", "check"]).is_err());
+}
+
+#[test]
+fn test_parse_develop_exclude() {
+    let opt = Opt::try_parse_from([
+        "rust-project",
+        "develop",
+        "--exclude",
+        "fbsource//third-party/rust:*",
+        "--exclude=*:generated",
+        "fbcode//foo:bar",
+    ])
+    .expect("Unable to parse args");
+    match opt.command {
+        Some(Command::Develop { exclude, .. }) => assert_eq!(
+            exclude,
+            vec![
+                "fbsource//third-party/rust:*".to_owned(),
+                "*:generated".to_owned()
+            ]
+        ),
+        _ => panic!("Expected develop command"),
+    }
+}
+
+#[test]
+fn test_parse_develop_targets_from() {
+    let opt = Opt::try_parse_from(["rust-project", "develop", "--targets-from", "targets.txt"])
+        .expect("Unable to parse args");
+    match opt.command {
+        Some(Command::Develop {
+            targets,
+            targets_from,
+            files,
+            ..
+        }) => {
+            assert_eq!(targets_from, Some(PathBuf::from("targets.txt")));
+            assert!(targets.is_empty());
+            assert!(files.is_empty());
+        }
+        _ => panic!("Expected develop command"),
+    }
+
+    let opt = Opt::try_parse_from([
+        "rust-project",
+        "develop",
+        "--targets-from",
+        "-",
+        "--",
+        "fbcode/foo.rs",
+    ])
+    .expect("Unable to parse args");
+    match opt.command {
+        Some(Command::Develop {
+            targets_from,
+            files,
+            ..
+        }) => {
+            assert_eq!(targets_from, Some(PathBuf::from("-")));
+            assert_eq!(files, vec![PathBuf::from("fbcode/foo.rs")]);
+        }
+        _ => panic!("Expected develop command"),
+    }
+
+    assert!(
+        Opt::try_parse_from([
+            "rust-project",
+            "develop",
+            "--targets-from",
+            "targets.txt",
+            "fbcode//foo:bar",
+        ])
+        .is_err()
+    );
+    assert!(Opt::try_parse_from(["rust-project", "develop"]).is_err());
+}
+
 #[test]
 #[ignore]
 fn json_args_pass() {
@@ -376,6 +640,7 @@ fn json_args_pass() {
             args,
             sysroot_mode: SysrootMode::Rustc,
             client: None,
+            check_cycles: true,
         }),
         version: false,
     };
@@ -393,6 +658,7 @@ fn json_args_pass() {
             args,
             sysroot_mode: SysrootMode::Rustc,
             client: None,
+            check_cycles: true,
         }),
         version: false,
     };
@@ -410,6 +676,7 @@ fn json_args_pass() {
             args,
             sysroot_mode: SysrootMode::Rustc,
             client: None,
+            check_cycles: true,
         }),
         version: false,
     };
@@ -63,6 +63,18 @@ pub(crate) fn canonicalize_to_vcs_path(path: &Path, project_root: &Path) -> Path
     canonical_path
 }
 
+/// Expands a leading `~` in `path` to the home directory.
+pub(crate) fn expand_tilde(path: &Path) -> Result<PathBuf, anyhow::Error> {
+    if path.starts_with("~") {
+        let path = path.strip_prefix("~")?;
+        let home = std::env::var("HOME")?;
+        let home = PathBuf::from(home);
+        Ok(home.join(path))
+    } else {
+        Ok(path.to_path_buf())
+    }
+}
+
 fn canonicalize<P: AsRef<Path>>(path: P) -> io::Result<PathBuf> {
     let canonical_path = dunce::canonicalize(&path)?;
 
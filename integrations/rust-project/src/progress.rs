@@ -94,17 +94,19 @@ where
     }
 }
 
+/// Fields whose values are JSON, output as such rather than as strings.
+const JSON_FIELDS: &[&str] = &["project", "cycle", "report"];
+
 struct JsonVisitor<'a>(&'a mut FxHashMap<String, serde_json::Value>);
 
 impl tracing::field::Visit for JsonVisitor<'_> {
     fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
-        let value: String = if field.name() == "project" {
+        let value = if JSON_FIELDS.contains(&field.name()) {
             serde_json::from_str(value).unwrap()
         } else {
-            String::from(value)
+            serde_json::Value::from(value)
         };
-        self.0
-            .insert(field.name().to_owned(), serde_json::Value::from(value));
+        self.0.insert(field.name().to_owned(), value);
     }
 
     fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
@@ -7,7 +7,7 @@
  * of this source tree.
  */
 
-use std::path::Path;
+use std::path::PathBuf;
 use std::time::Duration;
 
 use crate::cli::Input;
@@ -55,32 +55,42 @@ fn get_sl_revision() -> String {
 }
 
 #[cfg(fbcode_build)]
-pub(crate) fn log_check(duration: Duration, saved_file: &Path, use_clippy: bool) {
+pub(crate) fn log_check(duration: Duration, saved_files: &[PathBuf], use_clippy: bool) {
     if !is_ci() {
         let mut sample = new_sample("check");
         sample.add("duration_ms", duration.as_millis() as i64);
-        sample.add("saved_file", saved_file.display().to_string());
+        sample.add("saved_file", display_saved_files(saved_files));
         sample.add("use_clippy", use_clippy.to_string());
         sample.log();
     }
 }
 
 #[cfg(not(fbcode_build))]
-pub(crate) fn log_check(_duration: Duration, _saved_file: &Path, _use_clippy: bool) {}
+pub(crate) fn log_check(_duration: Duration, _saved_files: &[PathBuf], _use_clippy: bool) {}
 
 #[cfg(fbcode_build)]
-pub(crate) fn log_check_error(error: &anyhow::Error, saved_file: &Path, use_clippy: bool) {
+pub(crate) fn log_check_error(error: &anyhow::Error, saved_files: &[PathBuf], use_clippy: bool) {
     if !is_ci() {
         let mut sample = new_sample("check");
         sample.add("error", format!("{:#?}", error));
-        sample.add("saved_file", saved_file.display().to_string());
+        sample.add("saved_file", display_saved_files(saved_files));
         sample.add("use_clippy", use_clippy.to_string());
         sample.log();
     }
 }
 
 #[cfg(not(fbcode_build))]
-pub(crate) fn log_check_error(_error: &anyhow::Error, _saved_file: &Path, _use_clippy: bool) {}
+pub(crate) fn log_check_error(_error: &anyhow::Error, _saved_files: &[PathBuf], _use_clippy: bool) {
+}
+
+#[cfg(fbcode_build)]
+fn display_saved_files(saved_files: &[PathBuf]) -> String {
+    saved_files
+        .iter()
+        .map(|f| f.display().to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+}
 
 #[cfg(fbcode_build)]
 fn new_sample(kind: &str) -> scuba::ScubaSampleBuilder {
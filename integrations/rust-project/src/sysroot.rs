@@ -13,22 +13,144 @@ use std::process::Command;
 use std::process::Stdio;
 
 use anyhow::Context;
+use tracing::info;
 use tracing::instrument;
+use tracing::warn;
 
 use crate::buck::Buck;
+use crate::buck::CycleCheck;
+use crate::buck::JsonProjectOptions;
+use crate::buck::ResolveOptions;
 use crate::buck::truncate_line_ending;
 use crate::buck::utf8_output;
 use crate::cli::develop_with_sysroot;
 use crate::json_project::Sysroot;
+use crate::path::expand_tilde;
+use crate::path::safe_canonicalize;
 use crate::target::Target;
 
-#[derive(Debug)]
+/// Where the source of std crates is, relative to a sysroot that doesn't say otherwise.
+const SYSROOT_SRC: &str = "lib/rustlib/src/rust/library";
+
+/// Sysroots of system-wide toolchain installs, tried when no other sysroot is usable.
+#[cfg(unix)]
+const PLATFORM_DEFAULT_SYSROOTS: &[&str] = &["/usr/local", "/usr"];
+#[cfg(not(unix))]
+const PLATFORM_DEFAULT_SYSROOTS: &[&str] = &[];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub(crate) enum SysrootConfig {
     Sysroot(PathBuf),
     BuckConfig,
     Rustup,
 }
 
+impl SysrootConfig {
+    /// The sysroots to try, in order: this one, then the ones after it in the explicit,
+    /// buckconfig, rustup, platform default order.
+    fn candidates(&self) -> Vec<SysrootConfig> {
+        let mut candidates = match self {
+            SysrootConfig::Sysroot(path) => vec![
+                SysrootConfig::Sysroot(path.clone()),
+                SysrootConfig::BuckConfig,
+                SysrootConfig::Rustup,
+            ],
+            SysrootConfig::BuckConfig => vec![SysrootConfig::BuckConfig, SysrootConfig::Rustup],
+            SysrootConfig::Rustup => vec![SysrootConfig::Rustup],
+        };
+        candidates.extend(
+            PLATFORM_DEFAULT_SYSROOTS
+                .iter()
+                .map(|path| SysrootConfig::Sysroot(PathBuf::from(path))),
+        );
+        candidates
+    }
+
+    fn resolve(&self, buck: &Buck) -> Result<Sysroot, anyhow::Error> {
+        match self {
+            SysrootConfig::Sysroot(path) => Ok(Sysroot {
+                sysroot: safe_canonicalize(&expand_tilde(path)?),
+                sysroot_src: None,
+                sysroot_project: None,
+            }),
+            SysrootConfig::BuckConfig => {
+                let project_root = buck.resolve_project_root()?;
+                resolve_buckconfig_sysroot(buck, &project_root)
+            }
+            SysrootConfig::Rustup => resolve_rustup_sysroot(),
+        }
+    }
+}
+
+/// Resolve the sysroot for `config`.
+///
+/// A sysroot without the source of std crates makes for a project rust-analyzer can't make sense
+/// of, so candidates that fail to resolve or to verify are skipped with a warning, in the order of
+/// [`SysrootConfig::candidates`]. If none verifies, the sysroot of `config` is used anyway.
+pub(crate) fn resolve_sysroot(
+    config: &SysrootConfig,
+    buck: &Buck,
+) -> Result<Sysroot, anyhow::Error> {
+    let mut first = None;
+    for candidate in config.candidates() {
+        let sysroot = match candidate.resolve(buck) {
+            Ok(sysroot) => sysroot,
+            Err(e) => {
+                warn!(candidate = ?candidate, "Skipping sysroot that can't be resolved: {:#}", e);
+                first.get_or_insert(Err(e));
+                continue;
+            }
+        };
+        match verify_sysroot(&sysroot) {
+            Ok(()) => {
+                log_resolved(&sysroot);
+                return Ok(sysroot);
+            }
+            Err(e) => {
+                warn!(candidate = ?candidate, "Skipping sysroot: {:#}", e);
+                first.get_or_insert(Ok(sysroot));
+            }
+        }
+    }
+
+    // There is always at least one candidate: `config` itself.
+    let sysroot = first.expect("no sysroot candidates")?;
+    warn!(
+        "No sysroot contains the source of std crates, using `{}` anyway",
+        sysroot.sysroot.display()
+    );
+    log_resolved(&sysroot);
+    Ok(sysroot)
+}
+
+/// Reports the sysroot in use, which also ends up in the JSON output of `develop-json`.
+fn log_resolved(sysroot: &Sysroot) {
+    info!(
+        kind = "progress",
+        sysroot = %sysroot.sysroot.display(),
+        "using sysroot {}",
+        sysroot.sysroot.display()
+    );
+}
+
+/// Check that `sysroot` contains the source of std crates, either in its `sysroot_src` or where
+/// rust-analyzer looks for it by default.
+pub(crate) fn verify_sysroot(sysroot: &Sysroot) -> Result<(), anyhow::Error> {
+    let sysroot_src = match &sysroot.sysroot_src {
+        Some(sysroot_src) => sysroot_src.clone(),
+        None => sysroot.sysroot.join(SYSROOT_SRC),
+    };
+    let std = sysroot_src.join("std");
+    if !std.is_dir() {
+        anyhow::bail!(
+            "`{}` does not contain the source of std crates, expected `{}` to exist",
+            sysroot.sysroot.display(),
+            std.display()
+        );
+    }
+    Ok(())
+}
+
 /// Choose sysroot and sysroot_src based on platform.
 ///
 /// `sysroot` is the directory that contains std crates:
@@ -81,10 +203,16 @@ pub(crate) fn resolve_buckconfig_sysroot(
             sysroot_src: Some(sysroot_src.clone()),
             sysroot_project: None,
         },
-        true,
-        false,
-        false,
-        &[], // sysroot doesn't get any extra cfgs
+        ResolveOptions {
+            exclude_workspaces: true,
+            strict: true,
+        },
+        &JsonProjectOptions {
+            cycle_check: CycleCheck::Off,
+            include_all_buildfiles: false,
+            include_tests: true,
+            extra_cfgs: vec![], // sysroot doesn't get any extra cfgs
+        },
     )?;
     for krate in &mut sysroot_project.crates {
         if let Some(display_name) = &mut krate.display_name {
@@ -113,12 +241,7 @@ pub(crate) fn resolve_rustup_sysroot() -> Result<Sysroot, anyhow::Error> {
     let mut output = utf8_output(cmd.output(), &cmd)?;
     truncate_line_ending(&mut output);
     let sysroot = PathBuf::from(output);
-    let sysroot_src = sysroot
-        .join("lib")
-        .join("rustlib")
-        .join("src")
-        .join("rust")
-        .join("library");
+    let sysroot_src = sysroot.join(SYSROOT_SRC);
 
     let sysroot = Sysroot {
         sysroot,
@@ -127,3 +250,60 @@ pub(crate) fn resolve_rustup_sysroot() -> Result<Sysroot, anyhow::Error> {
     };
     Ok(sysroot)
 }
+
+#[test]
+fn test_candidates() {
+    let platform_defaults = PLATFORM_DEFAULT_SYSROOTS
+        .iter()
+        .map(|path| SysrootConfig::Sysroot(PathBuf::from(path)));
+
+    let explicit = SysrootConfig::Sysroot(PathBuf::from("/explicit"));
+    let expected: Vec<_> = [
+        explicit.clone(),
+        SysrootConfig::BuckConfig,
+        SysrootConfig::Rustup,
+    ]
+    .into_iter()
+    .chain(platform_defaults.clone())
+    .collect();
+    assert_eq!(expected, explicit.candidates());
+
+    let expected: Vec<_> = [SysrootConfig::Rustup]
+        .into_iter()
+        .chain(platform_defaults)
+        .collect();
+    assert_eq!(expected, SysrootConfig::Rustup.candidates());
+}
+
+#[test]
+fn test_verify_sysroot() {
+    let root = std::env::temp_dir().join(format!(
+        "rust-project-test-verify-sysroot-{}",
+        std::process::id()
+    ));
+    let sysroot = |name: &str, sysroot_src: Option<&str>| Sysroot {
+        sysroot: root.join(name),
+        sysroot_src: sysroot_src.map(|src| root.join(src)),
+        sysroot_project: None,
+    };
+
+    std::fs::create_dir_all(root.join("valid").join(SYSROOT_SRC).join("std")).unwrap();
+    std::fs::create_dir_all(root.join("no_std").join(SYSROOT_SRC).join("core")).unwrap();
+    std::fs::create_dir_all(root.join("no_src/lib/rustlib")).unwrap();
+    std::fs::create_dir_all(root.join("buckified/library/std")).unwrap();
+    // `std` must be a directory.
+    std::fs::create_dir_all(root.join("std_file").join(SYSROOT_SRC)).unwrap();
+    std::fs::write(root.join("std_file").join(SYSROOT_SRC).join("std"), "").unwrap();
+
+    assert!(verify_sysroot(&sysroot("valid", None)).is_ok());
+    assert!(verify_sysroot(&sysroot("no_src", Some("buckified/library"))).is_ok());
+
+    assert!(verify_sysroot(&sysroot("no_std", None)).is_err());
+    assert!(verify_sysroot(&sysroot("no_src", None)).is_err());
+    assert!(verify_sysroot(&sysroot("std_file", None)).is_err());
+    assert!(verify_sysroot(&sysroot("missing", None)).is_err());
+    // An explicit `sysroot_src` is used instead of the default one.
+    assert!(verify_sysroot(&sysroot("valid", Some("buckified"))).is_err());
+
+    std::fs::remove_dir_all(&root).unwrap();
+}
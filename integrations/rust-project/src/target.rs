@@ -126,6 +126,9 @@ pub(crate) struct TargetInfo {
     pub(crate) proc_macro: Option<bool>,
     // Set of features enabled for this crate.
     pub(crate) features: Vec<String>,
+    // Environment the crate is compiled with, including `OUT_DIR` for crates
+    // with a build script. Absent for targets that don't set any.
+    #[serde(default)]
     pub(crate) env: FxHashMap<String, String>,
     // The ensured folder containing symlinks to all sources
     pub(crate) source_folder: PathBuf,
@@ -187,11 +190,22 @@ impl TargetInfo {
     }
 }
 
+/// A dependency of `wanted_by` on a target that buck couldn't resolve.
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+pub(crate) struct MissingDep {
+    pub(crate) target: Target,
+    pub(crate) wanted_by: Target,
+}
+
 #[derive(Debug, Clone, Deserialize, PartialEq, Eq, Default)]
 pub(crate) struct ExpandedAndResolved {
     pub(crate) expanded_targets: Vec<Target>,
     pub(crate) queried_proc_macros: FxHashMap<Target, MacroOutput>,
     pub(crate) resolved_deps: FxHashMap<Target, TargetInfo>,
+    /// Targets that were left out of `resolved_deps` because they depend on
+    /// targets that don't exist. Always empty when resolving in strict mode.
+    #[serde(default)]
+    pub(crate) missing_deps: Vec<MissingDep>,
 }
 
 fn deserialize_named_deps<'de, D>(deserializer: D) -> Result<FxHashMap<String, Target>, D::Error>
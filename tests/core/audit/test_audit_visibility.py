@@ -39,3 +39,18 @@ async def test_audit_visibility(buck: Buck, rule: str, passes: bool) -> None:
             buck.audit_visibility(rule),
             stderr_regex=f"not visible to `{rule}`",
         )
+
+
+@buck_test()
+async def test_audit_visibility_explain(buck: Buck) -> None:
+    out = await buck.audit_visibility(
+        "//subdir:target", "//subdir:badrecursive", "--explain-to", "//:pass2"
+    )
+    assert (
+        "self//subdir:target: visible: matches `self//:pass2` in the `visibility` attribute"
+        in out.stdout
+    )
+    assert (
+        "self//subdir:badrecursive: not visible: no pattern in the `visibility` attribute "
+        'matches ["self//buck2/..."], no close match'
+    ) in out.stdout
@@ -0,0 +1,59 @@
+# Copyright (c) Meta Platforms, Inc. and affiliates.
+#
+# This source code is licensed under both the MIT license found in the
+# LICENSE-MIT file in the root directory of this source tree and the Apache
+# License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+# of this source tree.
+
+# pyre-strict
+
+
+import typing
+
+from buck2.tests.e2e_util.api.buck import Buck
+from buck2.tests.e2e_util.buck_workspace import buck_test
+from buck2.tests.e2e_util.helper.utils import json_get
+
+
+async def get_recompute_stats(buck: Buck) -> typing.Dict[str, int]:
+    log = (await buck.log("show")).stdout.strip().splitlines()
+
+    for line in log:
+        message = json_get(
+            line,
+            "Event",
+            "data",
+            "Instant",
+            "data",
+            "ConfiguredNodeRecomputeStats",
+        )
+        if message is not None:
+            return message
+
+    raise AssertionError("No ConfiguredNodeRecomputeStats event found")
+
+
+@buck_test()
+async def test_unrelated_change_is_equal_recompute(buck: Buck) -> None:
+    await buck.build("//:rule1")
+    stats = await get_recompute_stats(buck)
+    assert stats.get("recomputed", 0) == 0
+
+    # Adding a target invalidates the whole package, and therefore every
+    # configured node in it, without changing any of the existing nodes.
+    with open(buck.cwd / "TARGETS.fixture", "a") as f:
+        f.write('\none(\n    name = "unrelated",\n)\n')
+
+    await buck.build("//:rule1")
+    stats = await get_recompute_stats(buck)
+    assert stats.get("recomputed", 0) >= 2
+    assert stats.get("recomputed_equal", 0) >= 2
+
+
+@buck_test()
+async def test_disabled(buck: Buck) -> None:
+    await buck.build(
+        "//:rule1", "-c", "buck2.configured_node_recompute_stats=false"
+    )
+    log = (await buck.log("show")).stdout
+    assert "ConfiguredNodeRecomputeStats" not in log
@@ -9,12 +9,15 @@
 
 from pathlib import Path
 
+import pytest
+
 from buck2.tests.e2e_util.api.buck import Buck
 from buck2.tests.e2e_util.asserts import expect_failure
 from buck2.tests.e2e_util.buck_workspace import buck_test, env
 from buck2.tests.e2e_util.helper.golden import golden, sanitize_stderr
 
 from buck2.tests.e2e_util.helper.utils import (
+    filter_events,
     is_running_on_linux,
     is_running_on_windows,
     read_invocation_record,
@@ -483,6 +486,30 @@ async def test_client_streaming_error(buck: Buck, tmp_path: Path) -> None:
     )
 
 
+@buck_test()
+@pytest.mark.parametrize(
+    "phase",
+    [
+        "prepare_events",
+        "snapshot",
+        "prepare_command",
+        "file_watcher_sync",
+        "create_context",
+    ],
+)
+async def test_command_setup_error(buck: Buck, phase: str) -> None:
+    res = await expect_failure(
+        buck.targets(":", env={"BUCK2_TEST_FAIL_COMMAND_SETUP": phase})
+    )
+    assert "Injected command setup error" in res.stderr
+    assert f"Command setup failed in phase `{phase}`" in res.stderr
+
+    events = await filter_events(
+        buck, "Event", "data", "Instant", "data", "CommandSetupFailed"
+    )
+    assert events == [{"phase": phase}]
+
+
 @buck_test()
 async def test_action_error_has_categorization(buck: Buck, tmp_path: Path) -> None:
     record_path = tmp_path / "record.json"
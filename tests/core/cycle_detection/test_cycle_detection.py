@@ -15,6 +15,7 @@ from buck2.tests.e2e_util.api.buck import Buck
 from buck2.tests.e2e_util.api.buck_result import BuckException, BuckResult
 from buck2.tests.e2e_util.asserts import expect_failure
 from buck2.tests.e2e_util.buck_workspace import buck_test
+from buck2.tests.e2e_util.helper.utils import filter_events
 
 
 def check_load_cycle_stderr(stderr: str) -> None:
@@ -99,6 +100,42 @@ async def test_detect_configured_graph_cycles_2(buck: Buck) -> None:
     check_cfg_toolchain_graph_cycle_stderr(failure.stderr)
 
 
+@buck_test()
+async def test_configured_graph_cycle_event(buck: Buck) -> None:
+    await expect_cycle(
+        buck.build(
+            "//:top",
+            "-c",
+            "cycles.cfg_pair=yes",
+        ),
+    )
+
+    # Both targets in the cycle may detect it, but it is only reported once.
+    cycles = await filter_events(
+        buck, "Event", "data", "Instant", "data", "ConfiguredGraphCycleDetected"
+    )
+    assert len(cycles) == 1
+    assert cycles[0]["cycle_length"] == 2
+    participants = "\n".join(cycles[0]["participants"])
+    assert "root//:pair_cycle_a" in participants
+    assert "root//:pair_cycle_b" in participants
+    assert cycles[0]["top_level_targets"] == ["root//:top"]
+
+    # The cycle error is served from the cache this time, it is still reported.
+    await expect_cycle(
+        buck.build(
+            "//:top",
+            "-c",
+            "cycles.cfg_pair=yes",
+        ),
+    )
+    cycles = await filter_events(
+        buck, "Event", "data", "Instant", "data", "ConfiguredGraphCycleDetected"
+    )
+    assert len(cycles) == 1
+    assert cycles[0]["cycle_length"] == 2
+
+
 @buck_test()
 async def test_more_recompute_cases(buck: Buck) -> None:
     await buck.cquery("//:top")
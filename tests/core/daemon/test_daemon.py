@@ -19,6 +19,7 @@ import pytest
 
 from buck2.tests.e2e_util.api.buck import Buck
 from buck2.tests.e2e_util.buck_workspace import buck_test, env
+from buck2.tests.e2e_util.helper.utils import filter_events
 
 
 async def get_daemon_dir(buck: Buck) -> Path:
@@ -163,3 +164,38 @@ async def test_prev_daemon_dir(buck: Buck) -> None:
     assert extract_pid(new_daemon_stderr) != extract_pid(killed_daemon_stderr)
 
     assert "triggered shutdown: `buck kill` was invoked" in killed_daemon_stderr
+
+
+@buck_test()
+async def test_command_prologue_timings(buck: Buck) -> None:
+    await buck.targets(
+        "//:rule", env={"BUCK2_TEST_SLOW_COMMAND_SETUP": "file_watcher_sync"}
+    )
+
+    emitted = [
+        phase
+        for phases in await filter_events(
+            buck,
+            "Event",
+            "data",
+            "Instant",
+            "data",
+            "CommandPrologueTimings",
+            "phases",
+        )
+        for phase in phases
+    ]
+    [result] = await filter_events(buck, "Result", "prologue_timings", "phases")
+    assert emitted == result
+
+    durations = {phase["phase"]: phase["duration_us"] for phase in result}
+    assert list(durations) == [
+        "prepare_events",
+        "snapshot",
+        "file_watcher_sync",
+        "prepare_command",
+        "create_context",
+    ]
+    assert durations["file_watcher_sync"] >= 1_000_000
+    # Time spent syncing the file watcher is not counted again in `prepare_command`.
+    assert durations["prepare_command"] < 1_000_000
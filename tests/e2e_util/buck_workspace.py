@@ -441,7 +441,7 @@ def _maybe_setup_prelude_and_ovr_config(path: Path) -> None:
             "", file=f
         )  # append newline because test `.buckconfig` may not end with newline
         print("# Following lines are added by buck_workspace.py", file=f)
-        print("[repositories]", file=f)
+        print("[cells]", file=f)
         print("ovr_config = arvr/tools/build_defs/config", file=f)
         print("fbcode_macros = tools/build_defs/fbcode_macros", file=f)
     with Path(path, "arvr", "tools", "build_defs", "config", ".buckconfig").open(